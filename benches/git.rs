@@ -0,0 +1,155 @@
+//! Benchmarks of the git layer (cloning, fetching a PR head, pushing it)
+//! against local fixture repos of varying sizes. Run with
+//! `cargo bench --features bench`.
+use criterion::{criterion_group, criterion_main, BatchSize, Criterion};
+use git2::{Oid, Repository, Signature};
+use labhub::api::models::github;
+use labhub::bench;
+use std::path::Path;
+use tempfile::TempDir;
+
+/// Number of files in the tree, and of commits, of each fixture repo
+const SIZES: [(&str, usize, usize); 3] = [
+    ("small", 10, 10),
+    ("medium", 1_000, 20),
+    ("large", 10_000, 20),
+];
+
+/// Commits a tree of `files` files, changing `commit % files` on top of
+/// `parent`
+fn commit(repo: &Repository, parent: Option<Oid>, files: usize, commit: usize) -> Oid {
+    let signature = Signature::new("bench", "bench@example.com", &git2::Time::new(0, 0)).unwrap();
+    let parent = parent.map(|id| repo.find_commit(id).unwrap());
+    let mut builder = repo
+        .treebuilder(parent.as_ref().map(|p| p.tree().unwrap()).as_ref())
+        .unwrap();
+    let changed = if parent.is_none() {
+        0..files
+    } else {
+        commit % files..commit % files + 1
+    };
+    for file in changed {
+        let contents = format!("file {} at commit {}\n{}", file, commit, "x".repeat(1024));
+        let blob = repo.blob(contents.as_bytes()).unwrap();
+        builder
+            .insert(format!("file-{}.txt", file), blob, 0o100644)
+            .unwrap();
+    }
+    let tree = repo.find_tree(builder.write().unwrap()).unwrap();
+    let parents: Vec<&git2::Commit> = parent.iter().collect();
+    repo.commit(
+        None,
+        &signature,
+        &signature,
+        &format!("commit {}", commit),
+        &tree,
+        &parents,
+    )
+    .unwrap()
+}
+
+/// A base repo, and a fork of it with a PR branch a few commits ahead
+struct Fixture {
+    dir: TempDir,
+    head_sha: String,
+}
+
+impl Fixture {
+    fn new(files: usize, commits: usize) -> Fixture {
+        let dir = tempfile::tempdir().unwrap();
+        let base = Repository::init_bare(dir.path().join("base.git")).unwrap();
+        let mut head = None;
+        for n in 0..commits {
+            head = Some(commit(&base, head, files, n));
+        }
+        base.reference("refs/heads/master", head.unwrap(), true, "")
+            .unwrap();
+
+        let fork = Repository::init_bare(dir.path().join("fork.git")).unwrap();
+        fork.remote_anonymous(&file_url(&dir.path().join("base.git")))
+            .unwrap()
+            .fetch(&["+refs/heads/*:refs/heads/*"], None, None)
+            .unwrap();
+        for n in commits..commits + 3 {
+            head = Some(commit(&fork, head, files, n));
+        }
+        fork.reference("refs/heads/feature", head.unwrap(), true, "")
+            .unwrap();
+        Fixture {
+            dir,
+            head_sha: head.unwrap().to_string(),
+        }
+    }
+
+    fn base_url(&self) -> String {
+        file_url(&self.dir.path().join("base.git"))
+    }
+
+    /// A PR event from the fork's feature branch
+    fn pr(&self) -> github::PullRequest {
+        let body = std::fs::read_to_string(
+            Path::new(env!("CARGO_MANIFEST_DIR")).join("src/testdata/github_open_pr_forked.json"),
+        )
+        .unwrap();
+        let mut event: serde_json::Value = serde_json::from_str(&body).unwrap();
+        let fork_url = file_url(&self.dir.path().join("fork.git"));
+        event["repository"]["full_name"] = "brndnmtthws/labhub".into();
+        event["repository"]["ssh_url"] = self.base_url().into();
+        event["pull_request"]["head"]["ref"] = "feature".into();
+        event["pull_request"]["head"]["sha"] = self.head_sha.clone().into();
+        event["pull_request"]["head"]["repo"]["ssh_url"] = fork_url.into();
+        serde_json::from_value(event).unwrap()
+    }
+}
+
+fn file_url(path: &Path) -> String {
+    format!("file://{}", path.display())
+}
+
+fn git_benchmarks(c: &mut Criterion) {
+    // The fixtures are cloned from and pushed to local paths, so the SSH
+    // keys and tokens of the config don't matter here
+    if let Err(err) = labhub::config::load_config() {
+        eprintln!("{}", err);
+    }
+    let mut group = c.benchmark_group("git");
+    group.sample_size(10);
+    for (name, files, commits) in SIZES {
+        let fixture = Fixture::new(files, commits);
+        let pr = fixture.pr();
+
+        group.bench_function(format!("clone/{}", name), |b| {
+            b.iter(|| bench::clone(&fixture.base_url()).unwrap())
+        });
+        group.bench_function(format!("fetch/{}", name), |b| {
+            b.iter_batched(
+                || bench::clone(&fixture.base_url()).unwrap(),
+                |mut repo| {
+                    repo.fetch_pr(&pr).unwrap();
+                    repo
+                },
+                BatchSize::PerIteration,
+            )
+        });
+        group.bench_function(format!("push/{}", name), |b| {
+            b.iter_batched(
+                || {
+                    let mut repo = bench::clone(&fixture.base_url()).unwrap();
+                    repo.fetch_pr(&pr).unwrap();
+                    let gitlab = tempfile::tempdir().unwrap();
+                    Repository::init_bare(gitlab.path()).unwrap();
+                    (repo, gitlab)
+                },
+                |(repo, gitlab)| {
+                    repo.push_pr(&pr, &file_url(gitlab.path())).unwrap();
+                    (repo, gitlab)
+                },
+                BatchSize::PerIteration,
+            )
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, git_benchmarks);
+criterion_main!(benches);
@@ -0,0 +1,227 @@
+use crate::audit;
+use crate::config;
+use crate::disk;
+use crate::errors::RequestErrorResult;
+use crate::history;
+use crate::killswitch::{self, KillSwitch};
+use crate::logging;
+use crate::metrics;
+use crate::pause;
+use crate::replay;
+use crate::state;
+use crate::sync;
+use crate::webhook_setup;
+
+use axum::{
+    extract::{Path, Query},
+    http::{header, Request, StatusCode},
+    middleware::{self, Next},
+    response::Response,
+    routing::{delete, get, post, put},
+    Json, Router,
+};
+use log::warn;
+use ring::constant_time;
+use serde_json::json;
+
+/// Compares a provided admin token in constant time
+pub(crate) fn token_matches(token: &str, provided: &str) -> bool {
+    constant_time::verify_slices_are_equal(provided.as_bytes(), token.as_bytes()).is_ok()
+}
+
+/// Rejects requests without the configured admin bearer token
+pub(crate) async fn require_token<B>(
+    request: Request<B>,
+    next: Next<B>,
+) -> Result<Response, StatusCode> {
+    let token = match config::CONFIG.admin.token.as_ref() {
+        Some(token) => token,
+        None => return Err(StatusCode::NOT_FOUND),
+    };
+    let provided = request
+        .headers()
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "));
+    match provided {
+        Some(provided) if token_matches(token, provided) => Ok(next.run(request).await),
+        _ => {
+            warn!("Rejected admin request to {}", request.uri());
+            Err(StatusCode::UNAUTHORIZED)
+        }
+    }
+}
+
+/// Reports compliance with the PR sync latency SLO
+async fn slo() -> Json<metrics::SloStatus> {
+    Json(metrics::slo_status())
+}
+
+/// Reports free disk space and the size of each cached repo
+async fn disk_usage() -> Json<disk::DiskUsage> {
+    Json(disk::usage())
+}
+
+#[derive(Debug, Deserialize)]
+struct CachedRepoQuery {
+    url: String,
+}
+
+/// Evicts a cached repo, freeing its disk space right away, e.g.
+/// `?url=git@github.com:org/repo.git`
+async fn evict_cached_repo(Query(query): Query<CachedRepoQuery>) -> Json<serde_json::Value> {
+    let evicted = sync::evict_repo(&query.url);
+    Json(json!({ "url": query.url, "evicted": evicted }))
+}
+
+/// Lists the repos whose mirroring is paused
+async fn paused() -> Result<Json<serde_json::Value>, RequestErrorResult> {
+    let held = pause::held_counts();
+    let repos: Vec<serde_json::Value> = state::paused_repos()?
+        .into_iter()
+        .map(|paused| {
+            json!({
+                "held_events": held.get(&paused.repo).copied().unwrap_or(0),
+                "repo": paused.repo,
+                "paused_by": paused.paused_by,
+                "paused_at": paused.paused_at,
+            })
+        })
+        .collect();
+    Ok(Json(json!(repos)))
+}
+
+async fn pause_repo(
+    Path((owner, name)): Path<(String, String)>,
+) -> Result<Json<serde_json::Value>, RequestErrorResult> {
+    pause::pause(&format!("{}/{}", owner, name), "admin API")?;
+    Ok(Json(json!({ "paused": true })))
+}
+
+async fn resume_repo(
+    Path((owner, name)): Path<(String, String)>,
+) -> Result<Json<serde_json::Value>, RequestErrorResult> {
+    let queued = pause::resume(&format!("{}/{}", owner, name))?;
+    Ok(Json(json!({ "paused": false, "queued_events": queued })))
+}
+
+/// Lists what LabHub did to a PR, oldest first
+async fn pr_history(
+    Path((owner, name, number)): Path<(String, String, i64)>,
+) -> Result<Json<serde_json::Value>, RequestErrorResult> {
+    let repo = format!("{}/{}", owner, name);
+    let actions = history::for_pr(&repo, number)?;
+    Ok(Json(json!({
+        "repo": repo,
+        "number": number,
+        "actions": actions,
+    })))
+}
+
+/// Audit log entries are returned in pages of at most this many
+const MAX_AUDIT_ENTRIES: i64 = 1000;
+
+/// Queries the audit log, newest first, e.g. with
+/// `?repo=org/repo&pr_number=12&action=ref_push&since=1700000000&limit=50`
+async fn audit_log(
+    Query(mut filter): Query<state::AuditFilter>,
+) -> Result<Json<Vec<state::AuditEntry>>, RequestErrorResult> {
+    filter.limit = Some(filter.limit.unwrap_or(100).clamp(0, MAX_AUDIT_ENTRIES));
+    Ok(Json(audit::query(filter)?))
+}
+
+#[derive(Debug, Deserialize)]
+struct LogFilter {
+    filter: String,
+}
+
+/// Lists the kill switches and whether each is engaged
+async fn kill_switches() -> Result<Json<serde_json::Value>, RequestErrorResult> {
+    let engaged = state::engaged_kill_switches()?;
+    let switches: Vec<serde_json::Value> = KillSwitch::ALL
+        .iter()
+        .map(|switch| {
+            let engaged = engaged.iter().find(|engaged| engaged.name == switch.name());
+            json!({
+                "name": switch.name(),
+                "engaged": engaged.is_some(),
+                "engaged_by": engaged.map(|engaged| &engaged.engaged_by),
+                "engaged_at": engaged.map(|engaged| engaged.engaged_at),
+            })
+        })
+        .collect();
+    Ok(Json(json!(switches)))
+}
+
+async fn engage_kill_switch(
+    Path(name): Path<String>,
+) -> Result<Json<serde_json::Value>, RequestErrorResult> {
+    killswitch::engage(KillSwitch::from_name(&name)?, "admin API")?;
+    Ok(Json(json!({ "name": name, "engaged": true })))
+}
+
+async fn release_kill_switch(
+    Path(name): Path<String>,
+) -> Result<Json<serde_json::Value>, RequestErrorResult> {
+    let was_engaged = killswitch::release(KillSwitch::from_name(&name)?)?;
+    Ok(Json(
+        json!({ "name": name, "engaged": false, "was_engaged": was_engaged }),
+    ))
+}
+
+/// Reports the current log filter
+async fn log_filter() -> Json<serde_json::Value> {
+    Json(json!({ "filter": logging::filter() }))
+}
+
+/// Changes the log filter, e.g. to `info,labhub::github=debug`
+async fn set_log_filter(
+    Json(body): Json<LogFilter>,
+) -> Result<Json<serde_json::Value>, RequestErrorResult> {
+    logging::set_filter(&body.filter)?;
+    Ok(Json(json!({ "filter": body.filter })))
+}
+
+/// Lists the stored webhook payloads which can be replayed, newest first
+async fn payloads() -> Result<Json<Vec<state::WebhookPayload>>, RequestErrorResult> {
+    Ok(Json(state::webhook_payloads()?))
+}
+
+/// Handles a stored webhook payload again
+async fn replay_payload(Path(id): Path<i64>) -> Result<Json<String>, RequestErrorResult> {
+    Ok(Json(replay::replay(id).await?))
+}
+
+/// Creates or updates the GitHub and GitLab webhooks of the mapped repos,
+/// with the tokens in the body, if any, rather than the configured ones
+async fn setup_webhooks(
+    credentials: Option<Json<webhook_setup::Credentials>>,
+) -> Result<Json<Vec<webhook_setup::HookSetup>>, RequestErrorResult> {
+    let credentials = credentials.map(|Json(credentials)| credentials);
+    Ok(Json(
+        webhook_setup::setup_all(&credentials.unwrap_or_default()).await?,
+    ))
+}
+
+/// Builds the `/admin` routes, which all require the admin token
+pub fn router() -> Router {
+    Router::new()
+        .route("/slo", get(slo))
+        .route("/audit", get(audit_log))
+        .route("/disk", get(disk_usage))
+        .route("/disk/repos", delete(evict_cached_repo))
+        .route("/kill-switches", get(kill_switches))
+        .route(
+            "/kill-switches/:name",
+            put(engage_kill_switch).delete(release_kill_switch),
+        )
+        .route("/log-filter", get(log_filter).put(set_log_filter))
+        .route("/paused", get(paused))
+        .route("/payloads", get(payloads))
+        .route("/replay/:id", post(replay_payload))
+        .route("/webhooks", post(setup_webhooks))
+        .route("/repos/:owner/:name/pause", post(pause_repo))
+        .route("/repos/:owner/:name/resume", post(resume_repo))
+        .route("/prs/:owner/:name/:number/history", get(pr_history))
+        .route_layer(middleware::from_fn(require_token))
+}
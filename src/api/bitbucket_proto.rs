@@ -0,0 +1,32 @@
+use headers::{Header, HeaderName, HeaderValue};
+
+pub struct XEventKey(pub String);
+
+impl Header for XEventKey {
+    fn name() -> &'static HeaderName {
+        static N: HeaderName = HeaderName::from_static("x-event-key");
+        &N
+    }
+
+    fn decode<'i, I>(values: &mut I) -> Result<Self, headers::Error>
+    where
+        I: Iterator<Item = &'i HeaderValue>,
+    {
+        let value = values.next().ok_or_else(headers::Error::invalid)?;
+        Ok(XEventKey(
+            value
+                .to_str()
+                .or(Err(headers::Error::invalid()))?
+                .to_owned(),
+        ))
+    }
+
+    fn encode<E>(&self, values: &mut E)
+    where
+        E: Extend<HeaderValue>,
+    {
+        let value = HeaderValue::from_str(self.0.as_str());
+
+        values.extend(value);
+    }
+}
@@ -0,0 +1,68 @@
+use crate::api::{gitea_signature, github_signature::SignatureError};
+
+use log::debug;
+use ring::{digest, hmac};
+
+/// Bitbucket Cloud sends `X-Hub-Signature: sha256=<hex>`, an HMAC-SHA256 of
+/// the body.
+pub fn check_signature(secret: &str, signature: &str, body: &str) -> Result<(), SignatureError> {
+    let v_key = hmac::VerificationKey::new(&digest::SHA256, secret.as_bytes());
+    match signature.strip_prefix("sha256=") {
+        Some(hex_signature) => {
+            hmac::verify(&v_key, body.as_bytes(), &hex::decode(hex_signature)?)?;
+            debug!("Good signature {} for Bitbucket", signature);
+            Ok(())
+        }
+        None => Err(SignatureError::InvalidFormat),
+    }
+}
+
+/// Computes the HMAC-SHA256 of a body as it's read, like Gitea's, with the
+/// `sha256=` prefix of the signature
+pub struct StreamingVerifier(gitea_signature::StreamingVerifier);
+
+impl StreamingVerifier {
+    pub fn new(secret: &str) -> StreamingVerifier {
+        StreamingVerifier(gitea_signature::StreamingVerifier::new(secret))
+    }
+
+    pub fn update(&mut self, chunk: &[u8]) {
+        self.0.update(chunk);
+    }
+
+    pub fn verify(self, signature: &str) -> Result<(), SignatureError> {
+        match signature.strip_prefix("sha256=") {
+            Some(hex_signature) => {
+                self.0.verify(hex_signature)?;
+                debug!("Good signature {} for Bitbucket", signature);
+                Ok(())
+            }
+            None => Err(SignatureError::InvalidFormat),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_check_signature() {
+        let s_key = hmac::SigningKey::new(&digest::SHA256, b"secret");
+        let signature = format!("sha256={}", hex::encode(hmac::sign(&s_key, b"{}").as_ref()));
+        assert!(check_signature("secret", &signature, "{}").is_ok());
+        assert!(check_signature("other", &signature, "{}").is_err());
+        assert!(check_signature("secret", &signature[7..], "{}").is_err());
+
+        let mut verifier = StreamingVerifier::new("secret");
+        verifier.update(b"{");
+        verifier.update(b"}");
+        assert!(verifier.verify(&signature).is_ok());
+        let mut verifier = StreamingVerifier::new("secret");
+        verifier.update(b"{}");
+        assert!(matches!(
+            verifier.verify(&signature[7..]),
+            Err(SignatureError::InvalidFormat)
+        ));
+    }
+}
@@ -0,0 +1,223 @@
+//! Caching GitHub's GET responses by their `ETag`, to send them again as
+//! conditional requests: GitHub answers `304 Not Modified` when nothing
+//! changed, which doesn't count against the rate limit.
+use crate::errors::GitError;
+
+use ring::digest;
+use serde::de::DeserializeOwned;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+
+/// Responses kept at most, the least recently used are evicted first
+const CAPACITY: usize = 1000;
+
+/// A response, fresh or from the cache
+#[derive(Debug)]
+pub struct Response {
+    pub status: reqwest::StatusCode,
+    pub headers: reqwest::header::HeaderMap,
+    pub body: Vec<u8>,
+}
+
+impl Response {
+    async fn read(res: reqwest::Response) -> Result<Response, GitError> {
+        Ok(Response {
+            status: res.status(),
+            headers: res.headers().clone(),
+            body: res.bytes().await?.to_vec(),
+        })
+    }
+
+    pub fn json<T: DeserializeOwned>(&self) -> Result<T, GitError> {
+        Ok(serde_json::from_slice(&self.body)?)
+    }
+
+    pub fn text(&self) -> String {
+        String::from_utf8_lossy(&self.body).into_owned()
+    }
+}
+
+struct Entry {
+    etag: String,
+    response: Arc<Response>,
+    last_used: u64,
+}
+
+pub struct Cache {
+    entries: Mutex<HashMap<String, Entry>>,
+    capacity: usize,
+    /// Increases with each use, to find the least recently used entry
+    clock: AtomicU64,
+    hits: AtomicU64,
+}
+
+lazy_static! {
+    pub static ref GITHUB: Cache = Cache::new(CAPACITY);
+}
+
+/// The cache key of a GET: GitHub's responses vary with the token, which is
+/// only kept hashed
+pub fn key(url: &str, token: &str) -> String {
+    let hash = digest::digest(&digest::SHA256, token.as_bytes());
+    format!("{} {}", hex::encode(&hash.as_ref()[..8]), url)
+}
+
+impl Cache {
+    fn new(capacity: usize) -> Cache {
+        Cache {
+            entries: Mutex::new(HashMap::new()),
+            capacity,
+            clock: AtomicU64::new(0),
+            hits: AtomicU64::new(0),
+        }
+    }
+
+    /// The number of requests answered from the cache
+    pub fn hits(&self) -> u64 {
+        self.hits.load(Ordering::Relaxed)
+    }
+
+    /// The ETag and response cached for a key
+    pub fn get(&self, key: &str) -> Option<(String, Arc<Response>)> {
+        let mut entries = self.entries.lock().unwrap();
+        let entry = entries.get_mut(key)?;
+        entry.last_used = self.clock.fetch_add(1, Ordering::Relaxed);
+        Some((entry.etag.clone(), entry.response.clone()))
+    }
+
+    fn insert(&self, key: String, etag: String, response: Arc<Response>) {
+        let mut entries = self.entries.lock().unwrap();
+        if entries.len() >= self.capacity && !entries.contains_key(&key) {
+            let oldest = entries
+                .iter()
+                .min_by_key(|(_, entry)| entry.last_used)
+                .map(|(key, _)| key.clone());
+            if let Some(oldest) = oldest {
+                entries.remove(&oldest);
+            }
+        }
+        entries.insert(
+            key,
+            Entry {
+                etag,
+                response,
+                last_used: self.clock.fetch_add(1, Ordering::Relaxed),
+            },
+        );
+    }
+
+    /// Reads the response of a GET sent with the ETag of `cached`, if any.
+    /// A `304 Not Modified` is answered with the cached response, and a
+    /// successful response with an ETag is cached.
+    pub async fn response(
+        &self,
+        key: String,
+        cached: Option<Arc<Response>>,
+        res: reqwest::Response,
+    ) -> Result<Arc<Response>, GitError> {
+        if res.status() == reqwest::StatusCode::NOT_MODIFIED {
+            if let Some(cached) = cached {
+                self.hits.fetch_add(1, Ordering::Relaxed);
+                return Ok(cached);
+            }
+        }
+        let etag = res
+            .headers()
+            .get(reqwest::header::ETAG)
+            .and_then(|etag| etag.to_str().ok())
+            .map(str::to_string);
+        let response = Arc::new(Response::read(res).await?);
+        match etag {
+            Some(etag) if response.status.is_success() => self.insert(key, etag, response.clone()),
+            _ => {
+                self.entries.lock().unwrap().remove(&key);
+            }
+        }
+        Ok(response)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_key() {
+        assert_eq!(
+            key("https://api.github.com/repos/o/r", "token"),
+            key("https://api.github.com/repos/o/r", "token")
+        );
+        assert_ne!(
+            key("https://api.github.com/repos/o/r", "token"),
+            key("https://api.github.com/repos/o/r", "other-token")
+        );
+        assert!(!key("https://api.github.com/repos/o/r", "token").contains("token"));
+    }
+
+    #[tokio::test]
+    async fn test_conditional_get() {
+        use wiremock::matchers::{header, method};
+        use wiremock::{Mock, MockServer, ResponseTemplate};
+
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(header("if-none-match", "\"v1\""))
+            .respond_with(ResponseTemplate::new(304))
+            .expect(1)
+            .mount(&server)
+            .await;
+        Mock::given(method("GET"))
+            .respond_with(
+                ResponseTemplate::new(200)
+                    .insert_header("etag", "\"v1\"")
+                    .set_body_json(serde_json::json!({ "number": 5 })),
+            )
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let cache = Cache::new(10);
+        let client = reqwest::Client::new();
+        let key = key(&server.uri(), "token");
+        let send = || async {
+            let cached = cache.get(&key);
+            let mut request = client.get(server.uri());
+            if let Some((etag, _)) = cached.as_ref() {
+                request = request.header(reqwest::header::IF_NONE_MATCH, etag.as_str());
+            }
+            let res = request.send().await.unwrap();
+            cache
+                .response(key.clone(), cached.map(|(_, response)| response), res)
+                .await
+                .unwrap()
+        };
+        let first = send().await;
+        assert_eq!(first.json::<serde_json::Value>().unwrap()["number"], 5);
+        assert_eq!(cache.hits(), 0);
+        let second = send().await;
+        assert_eq!(second.status, reqwest::StatusCode::OK);
+        assert_eq!(second.json::<serde_json::Value>().unwrap()["number"], 5);
+        assert_eq!(cache.hits(), 1);
+    }
+
+    #[test]
+    fn test_eviction() {
+        let cache = Cache::new(2);
+        let response = || {
+            Arc::new(Response {
+                status: reqwest::StatusCode::OK,
+                headers: reqwest::header::HeaderMap::new(),
+                body: b"{}".to_vec(),
+            })
+        };
+        cache.insert("a".to_string(), "\"a\"".to_string(), response());
+        cache.insert("b".to_string(), "\"b\"".to_string(), response());
+        assert_eq!(cache.get("a").unwrap().0, "\"a\"");
+        // b is the least recently used
+        cache.insert("c".to_string(), "\"c\"".to_string(), response());
+        assert!(cache.get("a").is_some());
+        assert!(cache.get("b").is_none());
+        assert!(cache.get("c").is_some());
+    }
+}
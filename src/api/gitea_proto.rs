@@ -0,0 +1,64 @@
+use headers::{Header, HeaderName, HeaderValue};
+
+pub struct XGiteaEvent(pub String);
+
+impl Header for XGiteaEvent {
+    fn name() -> &'static HeaderName {
+        static N: HeaderName = HeaderName::from_static("x-gitea-event");
+        &N
+    }
+
+    fn decode<'i, I>(values: &mut I) -> Result<Self, headers::Error>
+    where
+        I: Iterator<Item = &'i HeaderValue>,
+    {
+        let value = values.next().ok_or_else(headers::Error::invalid)?;
+        Ok(XGiteaEvent(
+            value
+                .to_str()
+                .or(Err(headers::Error::invalid()))?
+                .to_owned(),
+        ))
+    }
+
+    fn encode<E>(&self, values: &mut E)
+    where
+        E: Extend<HeaderValue>,
+    {
+        let value = HeaderValue::from_str(self.0.as_str());
+
+        values.extend(value);
+    }
+}
+
+/// Forgejo sends the same `X-Gitea-*` headers as Gitea, so this covers both
+pub struct XGiteaSignature(pub String);
+
+impl Header for XGiteaSignature {
+    fn name() -> &'static HeaderName {
+        static N: HeaderName = HeaderName::from_static("x-gitea-signature");
+        &N
+    }
+
+    fn decode<'i, I>(values: &mut I) -> Result<Self, headers::Error>
+    where
+        I: Iterator<Item = &'i HeaderValue>,
+    {
+        let value = values.next().ok_or_else(headers::Error::invalid)?;
+        Ok(XGiteaSignature(
+            value
+                .to_str()
+                .or(Err(headers::Error::invalid()))?
+                .to_owned(),
+        ))
+    }
+
+    fn encode<E>(&self, values: &mut E)
+    where
+        E: Extend<HeaderValue>,
+    {
+        let value = HeaderValue::from_str(self.0.as_str());
+
+        values.extend(value);
+    }
+}
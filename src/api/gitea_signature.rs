@@ -0,0 +1,69 @@
+use crate::api::github_signature::SignatureError;
+
+use log::{debug, warn};
+use ring::{constant_time, digest, hmac};
+
+/// Computes the HMAC of a body as it's read, to check it against the
+/// signature once it's complete
+pub struct StreamingVerifier {
+    context: hmac::SigningContext,
+}
+
+impl StreamingVerifier {
+    pub fn new(secret: &str) -> StreamingVerifier {
+        let s_key = hmac::SigningKey::new(&digest::SHA256, secret.as_bytes());
+        StreamingVerifier {
+            context: hmac::SigningContext::with_key(&s_key),
+        }
+    }
+
+    pub fn update(&mut self, chunk: &[u8]) {
+        self.context.update(chunk);
+    }
+
+    /// Checks the hex encoded `signature` against the HMAC of the body
+    pub fn verify(self, signature: &str) -> Result<(), SignatureError> {
+        let expected = hex::decode(signature)?;
+        if constant_time::verify_slices_are_equal(self.context.sign().as_ref(), &expected).is_err()
+        {
+            warn!("Got a bad request signature {}", signature);
+            return Err(SignatureError::BadSignature);
+        }
+        Ok(())
+    }
+}
+
+/// Gitea signs the body with HMAC-SHA256, sent hex encoded without any
+/// `sha256=` prefix.
+pub fn check_signature(secret: &str, signature: &str, body: &str) -> Result<(), SignatureError> {
+    let v_key = hmac::VerificationKey::new(&digest::SHA256, secret.as_bytes());
+    hmac::verify(&v_key, body.as_bytes(), &hex::decode(signature)?)?;
+    debug!("Good signature {} for Gitea", signature);
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_check_signature() {
+        let s_key = hmac::SigningKey::new(&digest::SHA256, b"secret");
+        let signature = hex::encode(hmac::sign(&s_key, b"{}").as_ref());
+        assert!(check_signature("secret", &signature, "{}").is_ok());
+        assert!(check_signature("other", &signature, "{}").is_err());
+        assert!(check_signature("secret", "not hex", "{}").is_err());
+
+        let mut verifier = StreamingVerifier::new("secret");
+        verifier.update(b"{");
+        verifier.update(b"}");
+        assert!(verifier.verify(&signature).is_ok());
+        let mut verifier = StreamingVerifier::new("other");
+        verifier.update(b"{}");
+        assert!(matches!(
+            verifier.verify(&signature),
+            Err(SignatureError::BadSignature)
+        ));
+        assert!(StreamingVerifier::new("secret").verify("not hex").is_err());
+    }
+}
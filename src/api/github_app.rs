@@ -0,0 +1,208 @@
+//! Authenticating as a GitHub App, for the APIs which only apps may use (like
+//! check runs). The app signs a short-lived JWT with its private key, and
+//! trades it for a token of its installation on the repo's owner.
+use crate::api::throttle::{self, ThrottledSend};
+use crate::config;
+use crate::errors::GitError;
+
+use base64::engine::general_purpose::{STANDARD, URL_SAFE_NO_PAD};
+use base64::Engine;
+use log::{error, info};
+use ring::{rand, signature};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+/// Installation tokens are valid for an hour, renew them a bit earlier
+const TOKEN_LIFETIME: Duration = Duration::from_secs(50 * 60);
+
+lazy_static! {
+    /// Installation tokens by API URL and owner
+    static ref TOKENS: Mutex<HashMap<(String, String), (String, Instant)>> =
+        Mutex::new(HashMap::new());
+}
+
+fn pem_to_der(pem: &str) -> Result<Vec<u8>, GitError> {
+    let body: String = pem
+        .lines()
+        .filter(|line| !line.starts_with("-----"))
+        .map(str::trim)
+        .collect();
+    STANDARD
+        .decode(body)
+        .map_err(|err| GitError::Config(format!("Invalid PEM private key: {}", err)))
+}
+
+fn key_pair(pem: &str) -> Result<signature::RSAKeyPair, GitError> {
+    let der = pem_to_der(pem)?;
+    let input = untrusted::Input::from(&der);
+    let key_pair = if pem.contains("BEGIN RSA PRIVATE KEY") {
+        signature::RSAKeyPair::from_der(input)
+    } else {
+        signature::RSAKeyPair::from_pkcs8(input)
+    };
+    key_pair.map_err(|_| {
+        GitError::Config("Unsupported GitHub App private key, expected an RSA key".to_string())
+    })
+}
+
+/// Returns a JWT identifying the app, valid for 10 minutes from `now`
+fn jwt(app_id: i64, pem: &str, now: u64) -> Result<String, GitError> {
+    let header = URL_SAFE_NO_PAD.encode(r#"{"alg":"RS256","typ":"JWT"}"#);
+    // Backdated to allow for clock drift
+    let claims = URL_SAFE_NO_PAD.encode(
+        serde_json::json!({"iat": now - 60, "exp": now + 540, "iss": app_id.to_string()})
+            .to_string(),
+    );
+    let message = format!("{}.{}", header, claims);
+
+    let key_pair = Arc::new(key_pair(pem)?);
+    let mut signature = vec![0; key_pair.public_modulus_len()];
+    signature::RSASigningState::new(key_pair)
+        .and_then(|mut signing_state| {
+            signing_state.sign(
+                &signature::RSA_PKCS1_SHA256,
+                &rand::SystemRandom::new(),
+                message.as_bytes(),
+                &mut signature,
+            )
+        })
+        .map_err(|_| GitError::Other("Unable to sign GitHub App JWT".to_string()))?;
+    Ok(format!("{}.{}", message, URL_SAFE_NO_PAD.encode(signature)))
+}
+
+fn app_headers(jwt: &str) -> reqwest::header::HeaderMap {
+    let mut headers = reqwest::header::HeaderMap::new();
+    headers.insert(
+        reqwest::header::AUTHORIZATION,
+        reqwest::header::HeaderValue::from_str(&format!("Bearer {}", jwt)).unwrap(),
+    );
+    headers.insert(
+        reqwest::header::ACCEPT,
+        reqwest::header::HeaderValue::from_static("application/vnd.github.v3+json"),
+    );
+    headers
+}
+
+async fn create_installation_token(
+    client: &reqwest::Client,
+    api_url: &str,
+    app: &config::GithubApp,
+    org: &str,
+    repo: &str,
+) -> Result<String, GitError> {
+    let pem = std::fs::read_to_string(&app.private_key).map_err(|err| {
+        GitError::Config(format!(
+            "Unable to read GitHub App private key {}: {}",
+            app.private_key, err
+        ))
+    })?;
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs();
+    let jwt = jwt(app.id, &pem, now)?;
+
+    let res = client
+        .get(format!("{}/repos/{}/{}/installation", api_url, org, repo))
+        .headers(app_headers(&jwt))
+        .send_throttled(&throttle::GITHUB)
+        .await?;
+    if res.status() != reqwest::StatusCode::OK {
+        let err = GitError::api_status(
+            format!("GitHub App {} isn't installed on {}/{}", app.id, org, repo),
+            res,
+        )
+        .await;
+        error!("{}", err);
+        return Err(err);
+    }
+    let installation: serde_json::Value = res.json().await?;
+    let installation_id = installation["id"]
+        .as_i64()
+        .ok_or(GitError::UnexpectedResponse(
+            "GitHub App installation has no id".to_string(),
+        ))?;
+
+    info!(
+        "Creating token for installation {} of GitHub App {}",
+        installation_id, app.id
+    );
+    let res = client
+        .post(format!(
+            "{}/app/installations/{}/access_tokens",
+            api_url, installation_id
+        ))
+        .headers(app_headers(&jwt))
+        .send_throttled(&throttle::GITHUB)
+        .await?;
+    match res.status() {
+        reqwest::StatusCode::CREATED => {
+            let token: serde_json::Value = res.json().await?;
+            token["token"]
+                .as_str()
+                .map(str::to_string)
+                .ok_or(GitError::UnexpectedResponse(
+                    "GitHub App installation token is missing".to_string(),
+                ))
+        }
+        _ => {
+            let err = GitError::api_status("Error creating installation token", res).await;
+            error!("{}", err);
+            Err(err)
+        }
+    }
+}
+
+/// Returns a token of the app's installation on the repo's owner, reusing
+/// it until shortly before it expires
+pub async fn installation_token(
+    client: &reqwest::Client,
+    api_url: &str,
+    app: &config::GithubApp,
+    org: &str,
+    repo: &str,
+) -> Result<String, GitError> {
+    let key = (api_url.to_string(), org.to_lowercase());
+    let cached = TOKENS
+        .lock()
+        .unwrap()
+        .get(&key)
+        .filter(|(_, expires)| *expires > Instant::now())
+        .map(|(token, _)| token.clone());
+    if let Some(token) = cached {
+        return Ok(token);
+    }
+    let token = create_installation_token(client, api_url, app, org, repo).await?;
+    TOKENS
+        .lock()
+        .unwrap()
+        .insert(key, (token.clone(), Instant::now() + TOKEN_LIFETIME));
+    Ok(token)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::testing::read_testdata_to_string;
+
+    #[test]
+    fn test_jwt() {
+        let pem = read_testdata_to_string("github_app_key.pem");
+        let jwt = jwt(1234, &pem, 1_700_000_000).unwrap();
+        let parts: Vec<&str> = jwt.split('.').collect();
+        assert_eq!(parts.len(), 3);
+        assert_eq!(
+            URL_SAFE_NO_PAD.decode(parts[0]).unwrap(),
+            br#"{"alg":"RS256","typ":"JWT"}"#
+        );
+        let claims: serde_json::Value =
+            serde_json::from_slice(&URL_SAFE_NO_PAD.decode(parts[1]).unwrap()).unwrap();
+        assert_eq!(
+            claims,
+            serde_json::json!({"iat": 1_699_999_940, "exp": 1_700_000_540, "iss": "1234"})
+        );
+        assert_eq!(URL_SAFE_NO_PAD.decode(parts[2]).unwrap().len(), 256);
+        assert!(key_pair("not a key").is_err());
+    }
+}
@@ -1,9 +1,19 @@
+use crate::api::etag_cache;
 use crate::api::models::github;
+use crate::api::pagination::{self, Page, PER_PAGE};
+use crate::api::throttle::{self, ThrottledSend};
+use crate::api::{self, github_app};
 use crate::config;
 use crate::errors::GitError;
+use crate::killswitch::{self, KillSwitch};
 
-use log::error;
-use reqwest;
+use log::{error, warn};
+use std::sync::Arc;
+
+/// GitHub lists at most 3000 files of a PR
+const MAX_PULL_FILES: usize = 3000;
+/// The pages of files of a PR GitHub lists, 100 per page
+const MAX_FILE_PAGES: i64 = 30;
 
 fn headers(token: &str) -> reqwest::header::HeaderMap {
     let mut headers = reqwest::header::HeaderMap::new();
@@ -22,12 +32,53 @@ fn headers(token: &str) -> reqwest::header::HeaderMap {
     headers
 }
 
+/// The GitHub instance a repo is on
+fn instance(org: &str, repo: &str) -> &'static config::GithubInstance {
+    config::github_for_repo(&format!("{}/{}", org, repo))
+}
+
+fn token(org: &str, repo: &str) -> String {
+    instance(org, repo).site.api_token()
+}
+
+fn make_api_url(instance: &config::GithubInstance) -> String {
+    if let Some(base_url) = api::base_url() {
+        return base_url;
+    }
+    match instance.api_url.as_ref() {
+        Some(api_url) => api_url.trim_end_matches('/').to_string(),
+        None => format!("https://api.{}", instance.hostname()),
+    }
+}
+
 fn make_repo_url(org: &str, repo: &str) -> String {
-    let hostname = match config::CONFIG.github.hostname.as_ref() {
-        Some(hostname) => hostname.clone(),
-        _ => "github.com".to_string(),
-    };
-    format!("https://api.{}/repos/{}/{}", hostname, org, repo)
+    format!(
+        "{}/repos/{}/{}",
+        make_api_url(instance(org, repo)),
+        org,
+        repo
+    )
+}
+
+/// Sends a GET of a repo's API, conditional on the ETag of its last
+/// response, which is returned again if nothing changed
+async fn get_cached(
+    client: &reqwest::Client,
+    org: &str,
+    repo: &str,
+    url: String,
+) -> Result<Arc<etag_cache::Response>, GitError> {
+    let token = token(org, repo);
+    let key = etag_cache::key(&url, &token);
+    let cached = etag_cache::GITHUB.get(&key);
+    let mut request = client.get(url).headers(headers(&token));
+    if let Some((etag, _)) = cached.as_ref() {
+        request = request.header(reqwest::header::IF_NONE_MATCH, etag.as_str());
+    }
+    let res = request.send_throttled(&throttle::GITHUB).await?;
+    etag_cache::GITHUB
+        .response(key, cached.map(|(_, response)| response), res)
+        .await
 }
 
 pub async fn get_pull(
@@ -36,14 +87,371 @@ pub async fn get_pull(
     repo: &str,
     number: i64,
 ) -> Result<github::PullRequestPullRequest, GitError> {
-    let res: github::PullRequestPullRequest = client
-        .get(&format!("{}/pulls/{}", make_repo_url(org, repo), number))
-        .headers(headers(&config::CONFIG.github.api_token))
-        .send()
+    get_cached(
+        client,
+        org,
+        repo,
+        format!("{}/pulls/{}", make_repo_url(org, repo), number),
+    )
+    .await?
+    .json()
+}
+
+/// Returns the scopes of a classic personal access token, from the
+/// `X-OAuth-Scopes` header. Fine-grained tokens don't have scopes, in which
+/// case this returns `None`.
+pub async fn get_token_scopes(
+    client: &reqwest::Client,
+    instance: &config::GithubInstance,
+) -> Result<Option<Vec<String>>, GitError> {
+    let res = client
+        .get(format!("{}/user", make_api_url(instance)))
+        .headers(headers(&instance.site.api_token()))
+        .send_throttled(&throttle::GITHUB)
+        .await?;
+
+    if !res.status().is_success() {
+        let err = GitError::api_status("Error checking token", res).await;
+        error!("{}", err);
+        return Err(err);
+    }
+    Ok(res
+        .headers()
+        .get("x-oauth-scopes")
+        .and_then(|v| v.to_str().ok())
+        .map(|scopes| {
+            scopes
+                .split(',')
+                .map(|s| s.trim().to_string())
+                .filter(|s| !s.is_empty())
+                .collect()
+        }))
+}
+
+/// Returns the HTTP status of listing a repo's PRs, which tells whether the
+/// token can read pull requests there.
+pub async fn probe_pulls(
+    client: &reqwest::Client,
+    org: &str,
+    repo: &str,
+) -> Result<reqwest::StatusCode, GitError> {
+    let res = client
+        .get(format!("{}/pulls?per_page=1", make_repo_url(org, repo)))
+        .headers(headers(&token(org, repo)))
+        .send_throttled(&throttle::GITHUB)
+        .await?;
+    Ok(res.status())
+}
+
+pub async fn get_repo(
+    client: &reqwest::Client,
+    org: &str,
+    repo: &str,
+) -> Result<github::GithubRepository, GitError> {
+    get_cached(client, org, repo, make_repo_url(org, repo))
         .await?
-        .json::<github::PullRequestPullRequest>()
+        .json()
+}
+
+/// The API URL of a repo's webhooks
+pub fn repo_hooks_url(org: &str, repo: &str) -> String {
+    format!("{}/hooks", make_repo_url(org, repo))
+}
+
+/// The API URL of the webhooks of an org on `instance`
+pub fn org_hooks_url(instance: &config::GithubInstance, org: &str) -> String {
+    format!("{}/orgs/{}/hooks", make_api_url(instance), org)
+}
+
+/// Lists the webhooks at `hooks_url`, of a repo or an org, with `token`,
+/// which must be able to administer them
+pub async fn get_hooks(
+    client: &reqwest::Client,
+    hooks_url: &str,
+    token: &str,
+) -> Result<Vec<github::Hook>, GitError> {
+    pagination::paginate(pagination::MAX_PAGES, |page| {
+        get_hooks_page(client, hooks_url, token, page, PER_PAGE)
+    })
+    .await
+}
+
+async fn get_hooks_page(
+    client: &reqwest::Client,
+    hooks_url: &str,
+    token: &str,
+    page: i64,
+    per_page: i64,
+) -> Result<Page<github::Hook>, GitError> {
+    let res = client
+        .get(format!("{}?page={}&per_page={}", hooks_url, page, per_page))
+        .headers(headers(token))
+        .send_throttled(&throttle::GITHUB)
+        .await?;
+
+    match res.status() {
+        reqwest::StatusCode::OK => {
+            let response_headers = res.headers().clone();
+            let hooks: Vec<github::Hook> = res.json().await?;
+            Ok(Page::new(hooks, &response_headers, per_page))
+        }
+        _ => {
+            let err = GitError::api_status("Error listing webhooks", res).await;
+            error!("{}", err);
+            Err(err)
+        }
+    }
+}
+
+/// Creates a webhook at `hooks_url`, or updates the one with `hook_id`
+pub async fn put_hook(
+    client: &reqwest::Client,
+    hooks_url: &str,
+    token: &str,
+    hook_id: Option<i64>,
+    hook: &serde_json::Value,
+) -> Result<github::Hook, GitError> {
+    let request = match hook_id {
+        Some(hook_id) => client.patch(format!("{}/{}", hooks_url, hook_id)),
+        None => client.post(hooks_url),
+    };
+    let res = request
+        .headers(headers(token))
+        .body(hook.to_string())
+        .send_throttled(&throttle::GITHUB)
+        .await?;
+
+    match res.status() {
+        reqwest::StatusCode::OK | reqwest::StatusCode::CREATED => Ok(res.json().await?),
+        _ => {
+            let err = GitError::api_status("Error saving webhook", res).await;
+            error!("{}", err);
+            Err(err)
+        }
+    }
+}
+
+/// Deletes the webhook with `hook_id` at `hooks_url`
+pub async fn delete_hook(
+    client: &reqwest::Client,
+    hooks_url: &str,
+    token: &str,
+    hook_id: i64,
+) -> Result<(), GitError> {
+    let res = client
+        .delete(format!("{}/{}", hooks_url, hook_id))
+        .headers(headers(token))
+        .send_throttled(&throttle::GITHUB)
+        .await?;
+
+    match res.status() {
+        reqwest::StatusCode::NO_CONTENT | reqwest::StatusCode::NOT_FOUND => Ok(()),
+        _ => {
+            let err = GitError::api_status("Error deleting webhook", res).await;
+            error!("{}", err);
+            Err(err)
+        }
+    }
+}
+
+/// Lists one page of PRs in the given state. PRs which can't be parsed (ex:
+/// the head fork was deleted) are skipped.
+pub async fn get_pulls(
+    client: &reqwest::Client,
+    org: &str,
+    repo: &str,
+    state: &str,
+    page: i64,
+    per_page: i64,
+) -> Result<Page<github::PullRequestPullRequest>, GitError> {
+    let res = get_cached(
+        client,
+        org,
+        repo,
+        format!(
+            "{}/pulls?state={}&page={}&per_page={}",
+            make_repo_url(org, repo),
+            state,
+            page,
+            per_page
+        ),
+    )
+    .await?;
+    let pulls: Vec<serde_json::Value> = res.json()?;
+    let pulls = pulls
+        .into_iter()
+        .filter_map(|pr| match serde_json::from_value(pr) {
+            Ok(pr) => Some(pr),
+            Err(err) => {
+                warn!("Skipping PR which couldn't be parsed: {:?}", err);
+                None
+            }
+        })
+        .collect();
+    Ok(Page::new(pulls, &res.headers, per_page))
+}
+
+/// Lists all PRs in the given state
+pub async fn get_all_pulls(
+    client: &reqwest::Client,
+    org: &str,
+    repo: &str,
+    state: &str,
+) -> Result<Vec<github::PullRequestPullRequest>, GitError> {
+    pagination::paginate(pagination::MAX_PAGES, |page| {
+        get_pulls(client, org, repo, state, page, PER_PAGE)
+    })
+    .await
+}
+
+/// Returns the paths of each file a PR changes, one page of them at a time.
+/// Renamed files have their old path as well.
+pub async fn get_pull_files(
+    client: &reqwest::Client,
+    org: &str,
+    repo: &str,
+    number: i64,
+    page: i64,
+    per_page: i64,
+) -> Result<Page<Vec<String>>, GitError> {
+    let res = get_cached(
+        client,
+        org,
+        repo,
+        format!(
+            "{}/pulls/{}/files?page={}&per_page={}",
+            make_repo_url(org, repo),
+            number,
+            page,
+            per_page
+        ),
+    )
+    .await?;
+    if res.status != reqwest::StatusCode::OK {
+        let err = GitError::ApiStatus {
+            context: format!("Error listing files of {}/{}#{}", org, repo, number),
+            code: res.status.as_u16(),
+            body: res.text(),
+        };
+        error!("{}", err);
+        return Err(err);
+    }
+    let files: Vec<serde_json::Value> = res.json()?;
+    let paths = files
+        .iter()
+        .map(|file| {
+            [&file["filename"], &file["previous_filename"]]
+                .iter()
+                .filter_map(|path| path.as_str().map(str::to_string))
+                .collect()
+        })
+        .collect();
+    Ok(Page::new(paths, &res.headers, per_page))
+}
+
+/// The files a PR changes, as far as GitHub lists them
+#[derive(Debug, PartialEq)]
+pub struct PullFiles {
+    /// Paths of the files, renamed files under their old path as well
+    pub paths: Vec<String>,
+    /// Whether the PR changes more files than GitHub lists
+    pub truncated: bool,
+}
+
+/// Returns the paths of all the files a PR changes, see [`PullFiles`]
+pub async fn get_all_pull_files(
+    client: &reqwest::Client,
+    org: &str,
+    repo: &str,
+    number: i64,
+) -> Result<PullFiles, GitError> {
+    let files = pagination::paginate(MAX_FILE_PAGES, |page| {
+        get_pull_files(client, org, repo, number, page, PER_PAGE)
+    })
+    .await?;
+    Ok(PullFiles {
+        truncated: files.len() >= MAX_PULL_FILES,
+        paths: files.into_iter().flatten().collect(),
+    })
+}
+
+/// Returns a user's role on a repo, ex: `write` or `maintain`, which is
+/// `none` for users who aren't collaborators
+pub async fn get_permission_level(
+    client: &reqwest::Client,
+    org: &str,
+    repo: &str,
+    username: &str,
+) -> Result<String, GitError> {
+    let res = get_cached(
+        client,
+        org,
+        repo,
+        format!(
+            "{}/collaborators/{}/permission",
+            make_repo_url(org, repo),
+            username
+        ),
+    )
+    .await?;
+
+    match res.status {
+        reqwest::StatusCode::OK => {
+            let permission: serde_json::Value = res.json()?;
+            // role_name distinguishes triage and maintain, which the
+            // permission field reports as read and write
+            Ok(permission["role_name"]
+                .as_str()
+                .or(permission["permission"].as_str())
+                .unwrap_or("none")
+                .to_string())
+        }
+        reqwest::StatusCode::NOT_FOUND => Ok("none".to_string()),
+        status => {
+            let err = GitError::ApiStatus {
+                context: format!(
+                    "Error getting permission of {} on {}/{}",
+                    username, org, repo
+                ),
+                code: status.as_u16(),
+                body: res.text(),
+            };
+            error!("{}", err);
+            Err(err)
+        }
+    }
+}
+
+/// Reacts to an issue comment, where `content` is the reaction's name, ex:
+/// `+1` or `rocket`
+pub async fn create_reaction(
+    client: &reqwest::Client,
+    org: &str,
+    repo: &str,
+    comment_id: i64,
+    content: &str,
+) -> Result<(), GitError> {
+    killswitch::check(KillSwitch::Reactions)?;
+    let res = client
+        .post(format!(
+            "{}/issues/comments/{}/reactions",
+            make_repo_url(org, repo),
+            comment_id
+        ))
+        .headers(headers(&token(org, repo)))
+        .body(serde_json::json!({ "content": content }).to_string())
+        .send_throttled(&throttle::GITHUB)
         .await?;
-    Ok(res)
+
+    match res.status() {
+        // 200 when the reaction was already there
+        reqwest::StatusCode::OK | reqwest::StatusCode::CREATED => Ok(()),
+        _ => {
+            let err = GitError::api_status("Error creating reaction", res).await;
+            error!("{}", err);
+            Err(err)
+        }
+    }
 }
 
 pub async fn create_issue_comment(
@@ -52,25 +460,290 @@ pub async fn create_issue_comment(
     repo: &str,
     number: i64,
     body: &str,
-) -> Result<(), GitError> {
+) -> Result<i64, GitError> {
+    killswitch::check(KillSwitch::Comments)?;
     let res = client
-        .post(&format!(
+        .post(format!(
             "{}/issues/{}/comments",
             make_repo_url(org, repo),
             number
         ))
-        .headers(headers(&config::CONFIG.github.api_token))
+        .headers(headers(&token(org, repo)))
+        .body(serde_json::json!({"body":body.to_string()}).to_string())
+        .send_throttled(&throttle::GITHUB)
+        .await?;
+
+    match res.status() {
+        reqwest::StatusCode::CREATED => {
+            let comment: github::IssueCommentComment = res.json().await?;
+            comment.id.ok_or(GitError::UnexpectedResponse(
+                "Created issue comment has no id".to_string(),
+            ))
+        }
+        _ => {
+            let err = GitError::api_status("Error creating issue comment", res).await;
+            error!("{}", err);
+            Err(err)
+        }
+    }
+}
+
+pub async fn get_issue_comments(
+    client: &reqwest::Client,
+    org: &str,
+    repo: &str,
+    number: i64,
+    page: i64,
+    per_page: i64,
+) -> Result<Page<github::IssueCommentComment>, GitError> {
+    let res = get_cached(
+        client,
+        org,
+        repo,
+        format!(
+            "{}/issues/{}/comments?page={}&per_page={}",
+            make_repo_url(org, repo),
+            number,
+            page,
+            per_page
+        ),
+    )
+    .await?;
+    let comments: Vec<github::IssueCommentComment> = res.json()?;
+    Ok(Page::new(comments, &res.headers, per_page))
+}
+
+/// Lists all the comments on an issue or PR
+pub async fn get_all_issue_comments(
+    client: &reqwest::Client,
+    org: &str,
+    repo: &str,
+    number: i64,
+) -> Result<Vec<github::IssueCommentComment>, GitError> {
+    pagination::paginate(pagination::MAX_PAGES, |page| {
+        get_issue_comments(client, org, repo, number, page, PER_PAGE)
+    })
+    .await
+}
+
+pub async fn update_issue_comment(
+    client: &reqwest::Client,
+    org: &str,
+    repo: &str,
+    comment_id: i64,
+    body: &str,
+) -> Result<(), GitError> {
+    killswitch::check(KillSwitch::Comments)?;
+    let res = client
+        .patch(format!(
+            "{}/issues/comments/{}",
+            make_repo_url(org, repo),
+            comment_id
+        ))
+        .headers(headers(&token(org, repo)))
         .body(serde_json::json!({"body":body.to_string()}).to_string())
-        .send()
+        .send_throttled(&throttle::GITHUB)
+        .await?;
+
+    match res.status() {
+        reqwest::StatusCode::OK => Ok(()),
+        _ => {
+            let err = GitError::api_status("Error updating issue comment", res).await;
+            error!("{}", err);
+            Err(err)
+        }
+    }
+}
+
+pub async fn delete_issue_comment(
+    client: &reqwest::Client,
+    org: &str,
+    repo: &str,
+    comment_id: i64,
+) -> Result<(), GitError> {
+    killswitch::check(KillSwitch::Comments)?;
+    let res = client
+        .delete(format!(
+            "{}/issues/comments/{}",
+            make_repo_url(org, repo),
+            comment_id
+        ))
+        .headers(headers(&token(org, repo)))
+        .send_throttled(&throttle::GITHUB)
+        .await?;
+
+    match res.status() {
+        reqwest::StatusCode::NO_CONTENT => Ok(()),
+        _ => {
+            let err = GitError::api_status("Error deleting issue comment", res).await;
+            error!("{}", err);
+            Err(err)
+        }
+    }
+}
+
+/// Creates a completed check run on a commit. Only GitHub Apps may create
+/// check runs, so this authenticates as the instance's app.
+#[allow(clippy::too_many_arguments)]
+pub async fn create_check_run(
+    client: &reqwest::Client,
+    org: &str,
+    repo: &str,
+    name: &str,
+    head_sha: &str,
+    conclusion: &str,
+    title: &str,
+    summary: &str,
+    annotations: &[serde_json::Value],
+) -> Result<(), GitError> {
+    killswitch::check(KillSwitch::Checks)?;
+    let instance = instance(org, repo);
+    let app = instance.app.as_ref().ok_or(GitError::Config(format!(
+        "Check runs need a GitHub App, which isn't configured for {}",
+        instance.name
+    )))?;
+    let api_url = make_api_url(instance);
+    let token = github_app::installation_token(client, &api_url, app, org, repo).await?;
+    let res = client
+        .post(format!("{}/check-runs", make_repo_url(org, repo)))
+        .headers(headers(&token))
+        .body(
+            serde_json::json!({
+                "name": name,
+                "head_sha": head_sha,
+                "status": "completed",
+                "conclusion": conclusion,
+                "output": {
+                    "title": title,
+                    "summary": summary,
+                    "annotations": annotations,
+                },
+            })
+            .to_string(),
+        )
+        .send_throttled(&throttle::GITHUB)
+        .await?;
+
+    match res.status() {
+        reqwest::StatusCode::CREATED => Ok(()),
+        _ => {
+            let err = GitError::api_status("Error creating check run", res).await;
+            error!("{}", err);
+            Err(err)
+        }
+    }
+}
+
+/// Creates a deployment of a commit, returning its ID
+pub async fn create_deployment(
+    client: &reqwest::Client,
+    org: &str,
+    repo: &str,
+    sha: &str,
+    environment: &str,
+) -> Result<i64, GitError> {
+    killswitch::check(KillSwitch::Deployments)?;
+    let res = client
+        .post(format!("{}/deployments", make_repo_url(org, repo)))
+        .headers(headers(&token(org, repo)))
+        .body(
+            serde_json::json!({
+                "ref": sha,
+                "environment": environment,
+                "auto_merge": false,
+                "required_contexts": [],
+                "transient_environment": true,
+                "description": "Deployed by GitLab CI",
+            })
+            .to_string(),
+        )
+        .send_throttled(&throttle::GITHUB)
+        .await?;
+
+    match res.status() {
+        reqwest::StatusCode::CREATED => {
+            let deployment: serde_json::Value = res.json().await?;
+            deployment["id"]
+                .as_i64()
+                .ok_or(GitError::UnexpectedResponse(
+                    "Created deployment has no id".to_owned(),
+                ))
+        }
+        _ => {
+            let err = GitError::api_status("Error creating deployment", res).await;
+            error!("{}", err);
+            Err(err)
+        }
+    }
+}
+
+/// Sets the state of a deployment, ex: `in_progress`, `success`, `failure`
+pub async fn create_deployment_status(
+    client: &reqwest::Client,
+    org: &str,
+    repo: &str,
+    deployment_id: i64,
+    state: &str,
+    environment_url: Option<&str>,
+    log_url: Option<&str>,
+) -> Result<(), GitError> {
+    killswitch::check(KillSwitch::Deployments)?;
+    let res = client
+        .post(format!(
+            "{}/deployments/{}/statuses",
+            make_repo_url(org, repo),
+            deployment_id
+        ))
+        .headers(headers(&token(org, repo)))
+        .body(
+            serde_json::json!({
+                "state": state,
+                "environment_url": environment_url.unwrap_or_default(),
+                "log_url": log_url.unwrap_or_default(),
+            })
+            .to_string(),
+        )
+        .send_throttled(&throttle::GITHUB)
         .await?;
 
     match res.status() {
         reqwest::StatusCode::CREATED => Ok(()),
         _ => {
-            let body = res.text().await?;
-            let msg = format!("Error creating issue comment: body={}", body);
-            error!("{}", msg);
-            Err(GitError { message: msg })
+            let err = GitError::api_status("Error creating deployment status", res).await;
+            error!("{}", err);
+            Err(err)
         }
     }
 }
+
+/// Collapses a comment as outdated, which is only exposed by the GraphQL API
+pub async fn minimize_comment(
+    client: &reqwest::Client,
+    org: &str,
+    repo: &str,
+    node_id: &str,
+) -> Result<(), GitError> {
+    killswitch::check(KillSwitch::Comments)?;
+    let query = "mutation($id: ID!) { \
+        minimizeComment(input: {subjectId: $id, classifier: OUTDATED}) { \
+        minimizedComment { isMinimized } } }";
+    let res = client
+        .post(format!("{}/graphql", make_api_url(instance(org, repo))))
+        .headers(headers(&token(org, repo)))
+        .body(serde_json::json!({"query": query, "variables": {"id": node_id}}).to_string())
+        .send_throttled(&throttle::GITHUB)
+        .await?;
+
+    let status = res.status();
+    let body: serde_json::Value = res.json().await?;
+    if status != reqwest::StatusCode::OK || body.get("errors").is_some() {
+        let err = GitError::ApiStatus {
+            context: "Error minimizing comment".to_string(),
+            code: status.as_u16(),
+            body: body.to_string(),
+        };
+        error!("{}", err);
+        return Err(err);
+    }
+    Ok(())
+}
@@ -62,6 +62,69 @@ impl Header for XHubSignature {
     }
 }
 
+pub struct XGitHubDelivery(pub String);
+
+impl Header for XGitHubDelivery {
+    fn name() -> &'static HeaderName {
+        static N: HeaderName = HeaderName::from_static("x-github-delivery");
+        &N
+    }
+
+    fn decode<'i, I>(values: &mut I) -> Result<Self, headers::Error>
+    where
+        I: Iterator<Item = &'i HeaderValue>,
+    {
+        let value = values.next().ok_or_else(headers::Error::invalid)?;
+        Ok(XGitHubDelivery(
+            value
+                .to_str()
+                .or(Err(headers::Error::invalid()))?
+                .to_owned(),
+        ))
+    }
+
+    fn encode<E>(&self, values: &mut E)
+    where
+        E: Extend<HeaderValue>,
+    {
+        let value = HeaderValue::from_str(self.0.as_str());
+
+        values.extend(value);
+    }
+}
+
+/// What a webhook is installed on: `repository`, `organization`, ...
+pub struct XGitHubHookTargetType(pub String);
+
+impl Header for XGitHubHookTargetType {
+    fn name() -> &'static HeaderName {
+        static N: HeaderName = HeaderName::from_static("x-github-hook-installation-target-type");
+        &N
+    }
+
+    fn decode<'i, I>(values: &mut I) -> Result<Self, headers::Error>
+    where
+        I: Iterator<Item = &'i HeaderValue>,
+    {
+        let value = values.next().ok_or_else(headers::Error::invalid)?;
+        Ok(XGitHubHookTargetType(
+            value
+                .to_str()
+                .or(Err(headers::Error::invalid()))?
+                .to_owned(),
+        ))
+    }
+
+    fn encode<E>(&self, values: &mut E)
+    where
+        E: Extend<HeaderValue>,
+    {
+        let value = HeaderValue::from_str(self.0.as_str());
+
+        values.extend(value);
+    }
+}
+
 //#[derive(Debug)]
 //pub enum RequestError {
 //    BadCount,
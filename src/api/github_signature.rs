@@ -1,6 +1,6 @@
 use hex;
 use log::{debug, warn};
-use ring::{digest, hmac};
+use ring::{constant_time, digest, hmac};
 
 impl From<ring::error::Unspecified> for SignatureError {
     fn from(error: ring::error::Unspecified) -> Self {
@@ -18,11 +18,64 @@ impl From<hex::FromHexError> for SignatureError {
 
 #[derive(Debug)]
 pub enum SignatureError {
+    /// The webhook has no signature or token header
+    Missing,
     BadSignature,
     InvalidFormat,
     InvalidEncoding,
 }
 
+/// Verifies `X-Hub-Signature` over a body read in chunks, without holding
+/// on to them. The secret a webhook is signed with depends on its body, so
+/// the HMAC is computed under each of the secrets it may be signed with.
+pub struct StreamingVerifier {
+    contexts: Vec<(String, hmac::SigningContext)>,
+}
+
+impl StreamingVerifier {
+    pub fn new(secrets: Vec<String>) -> StreamingVerifier {
+        let mut contexts: Vec<(String, hmac::SigningContext)> = vec![];
+        for secret in secrets {
+            if contexts.iter().all(|(known, _)| *known != secret) {
+                let s_key = hmac::SigningKey::new(&digest::SHA1, secret.as_bytes());
+                contexts.push((secret, hmac::SigningContext::with_key(&s_key)));
+            }
+        }
+        StreamingVerifier { contexts }
+    }
+
+    pub fn update(&mut self, chunk: &[u8]) {
+        for (_, context) in self.contexts.iter_mut() {
+            context.update(chunk);
+        }
+    }
+
+    /// Checks `signature` against the HMAC of the body under each secret,
+    /// returning those it matches, so the body is only parsed once it's
+    /// known to be signed with one of them
+    pub fn verify(self, signature: &str) -> Result<Vec<String>, SignatureError> {
+        let expected = match signature.split('=').collect::<Vec<&str>>()[..] {
+            [_, hex_signature] => hex::decode(hex_signature)?,
+            _ => return Err(SignatureError::InvalidFormat),
+        };
+        let secrets: Vec<String> = self
+            .contexts
+            .into_iter()
+            .filter_map(|(secret, context)| {
+                constant_time::verify_slices_are_equal(context.sign().as_ref(), &expected)
+                    .ok()
+                    .map(|()| secret)
+            })
+            .collect();
+        if secrets.is_empty() {
+            warn!("Got a bad request signature {} for GitHub", signature);
+            return Err(SignatureError::BadSignature);
+        }
+        debug!("Good signature {} for GitHub", signature);
+        Ok(secrets)
+    }
+}
+
 pub fn check_signature(secret: &str, signature: &str, body: &str) -> Result<(), SignatureError> {
     let v_key = hmac::VerificationKey::new(&digest::SHA1, secret.as_bytes());
     let signature_parts = signature.split('=').collect::<Vec<&str>>();
@@ -35,3 +88,40 @@ pub fn check_signature(secret: &str, signature: &str, body: &str) -> Result<(),
         _ => Err(SignatureError::InvalidFormat),
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_streaming_verifier() {
+        let s_key = hmac::SigningKey::new(&digest::SHA1, b"org");
+        let signature = format!(
+            "sha1={}",
+            hex::encode(hmac::sign(&s_key, b"{\"zen\": 1}").as_ref())
+        );
+        let secrets = || vec!["default".to_string(), "org".to_string()];
+        let mut verifier = StreamingVerifier::new(secrets());
+        verifier.update(b"{\"zen\"");
+        verifier.update(b": 1}");
+        assert!(check_signature("org", &signature, "{\"zen\": 1}").is_ok());
+        assert_eq!(verifier.verify(&signature).unwrap(), ["org"]);
+
+        let mut verifier = StreamingVerifier::new(secrets());
+        verifier.update(b"{\"zen\": 2}");
+        assert!(matches!(
+            verifier.verify(&signature),
+            Err(SignatureError::BadSignature)
+        ));
+        let verifier = StreamingVerifier::new(vec!["other".to_string()]);
+        assert!(matches!(
+            verifier.verify(&signature),
+            Err(SignatureError::BadSignature)
+        ));
+        let verifier = StreamingVerifier::new(secrets());
+        assert!(matches!(
+            verifier.verify("sha1"),
+            Err(SignatureError::InvalidFormat)
+        ));
+    }
+}
@@ -1,10 +1,14 @@
+use crate::api;
 use crate::api::models::gitlab;
+use crate::api::pagination::{self, Page, PER_PAGE};
+use crate::api::throttle::{self, ThrottledSend};
 use crate::config;
 use crate::errors::GitError;
+use crate::killswitch::{self, KillSwitch};
 
 use log::error;
 use percent_encoding::{utf8_percent_encode, AsciiSet, CONTROLS};
-use reqwest;
+use std::collections::HashMap;
 
 fn headers(token: &str) -> reqwest::header::HeaderMap {
     let token_header = reqwest::header::HeaderName::from_static("private-token");
@@ -24,22 +28,227 @@ fn headers(token: &str) -> reqwest::header::HeaderMap {
     headers
 }
 
-fn make_api_url(project: &str) -> String {
-    let hostname = match config::CONFIG.gitlab.hostname.as_ref() {
+const FRAGMENT: &AsciiSet = &CONTROLS.add(b'/').add(b'%');
+
+pub(crate) fn hostname(instance: &config::GitlabInstance) -> String {
+    match instance.site.hostname.as_ref() {
         Some(hostname) => hostname.clone(),
         _ => "gitlab.com".to_string(),
-    };
-    const FRAGMENT: &AsciiSet = &CONTROLS.add(b'/').add(b'%');
+    }
+}
+
+/// API token for the instance a project is on
+fn token(project: &str) -> String {
+    config::gitlab_for_project(project).site.api_token()
+}
+
+/// The API root of an instance
+fn instance_api_url(instance: &config::GitlabInstance) -> String {
+    if let Some(base_url) = api::base_url() {
+        return format!("{}/api/v4", base_url);
+    }
+    match instance.api_url.as_ref() {
+        Some(api_url) => api_url.trim_end_matches('/').to_string(),
+        None => format!("https://{}/api/v4", hostname(instance)),
+    }
+}
+
+fn make_api_url(project: &str) -> String {
+    let api_url = instance_api_url(config::gitlab_for_project(project));
     let project = utf8_percent_encode(project, FRAGMENT).to_string();
-    format!("https://{}/api/v4/projects/{}", hostname, project)
+    format!("{}/projects/{}", api_url, project)
 }
 
 pub fn make_ext_url(project: &str) -> String {
-    let hostname = match config::CONFIG.gitlab.hostname.as_ref() {
-        Some(hostname) => hostname.clone(),
-        _ => "gitlab.com".to_string(),
+    format!(
+        "https://{}/{}",
+        hostname(config::gitlab_for_project(project)),
+        project
+    )
+}
+
+fn make_version_url(instance: &config::GitlabInstance) -> String {
+    format!("{}/version", instance_api_url(instance))
+}
+
+/// Fetches the GitLab version, which is a cheap way to check that the API is
+/// reachable and the token is valid.
+pub async fn get_version(
+    client: &reqwest::Client,
+    instance: &config::GitlabInstance,
+) -> Result<(), GitError> {
+    let res = client
+        .get(make_version_url(instance))
+        .headers(headers(&instance.site.api_token()))
+        .send_throttled(&throttle::GITLAB)
+        .await?;
+
+    match res.status() {
+        reqwest::StatusCode::OK => Ok(()),
+        _ => {
+            let err = GitError::api_status("Error getting GitLab version", res).await;
+            error!("{}", err);
+            Err(err)
+        }
+    }
+}
+
+/// Returns a project, ex: to find its default branch, or `None` if it
+/// doesn't exist
+pub async fn get_project(
+    client: &reqwest::Client,
+    project: &str,
+) -> Result<Option<gitlab::Project>, GitError> {
+    let res = client
+        .get(make_api_url(project))
+        .headers(headers(&token(project)))
+        .send_throttled(&throttle::GITLAB)
+        .await?;
+
+    match res.status() {
+        reqwest::StatusCode::OK => Ok(Some(res.json().await?)),
+        reqwest::StatusCode::NOT_FOUND => Ok(None),
+        _ => {
+            let err = GitError::api_status(format!("Error getting project {}", project), res).await;
+            error!("{}", err);
+            Err(err)
+        }
+    }
+}
+
+/// Fetches the ID of a group or user namespace, by its full path
+pub async fn get_namespace_id(
+    client: &reqwest::Client,
+    instance: &config::GitlabInstance,
+    namespace: &str,
+) -> Result<i64, GitError> {
+    let res = client
+        .get(format!(
+            "{}/namespaces/{}",
+            instance_api_url(instance),
+            utf8_percent_encode(namespace, FRAGMENT)
+        ))
+        .headers(headers(&instance.site.api_token()))
+        .send_throttled(&throttle::GITLAB)
+        .await?;
+
+    match res.status() {
+        reqwest::StatusCode::OK => {
+            let namespace_json: serde_json::Value = res.json().await?;
+            namespace_json["id"]
+                .as_i64()
+                .ok_or(GitError::UnexpectedResponse(format!(
+                    "Namespace {} has no id",
+                    namespace
+                )))
+        }
+        _ => {
+            let err =
+                GitError::api_status(format!("Error getting namespace {}", namespace), res).await;
+            error!("{}", err);
+            Err(err)
+        }
+    }
+}
+
+/// Creates a project with the given attributes, see GitLab's projects API
+pub async fn create_project(
+    client: &reqwest::Client,
+    instance: &config::GitlabInstance,
+    attributes: &serde_json::Value,
+) -> Result<gitlab::Project, GitError> {
+    let res = client
+        .post(format!("{}/projects", instance_api_url(instance)))
+        .headers(headers(&instance.site.api_token()))
+        .header(reqwest::header::CONTENT_TYPE, "application/json")
+        .body(attributes.to_string())
+        .send_throttled(&throttle::GITLAB)
+        .await?;
+
+    match res.status() {
+        reqwest::StatusCode::CREATED => Ok(res.json().await?),
+        _ => {
+            let err = GitError::api_status("Error creating project", res).await;
+            error!("{}", err);
+            Err(err)
+        }
+    }
+}
+
+/// Lists the webhooks of a project, with `token`, which must be able to
+/// administer them
+pub async fn get_hooks(
+    client: &reqwest::Client,
+    project: &str,
+    token: &str,
+) -> Result<Vec<gitlab::ProjectHook>, GitError> {
+    pagination::paginate(pagination::MAX_PAGES, |page| {
+        get_hooks_page(client, project, token, page, PER_PAGE)
+    })
+    .await
+}
+
+async fn get_hooks_page(
+    client: &reqwest::Client,
+    project: &str,
+    token: &str,
+    page: i64,
+    per_page: i64,
+) -> Result<Page<gitlab::ProjectHook>, GitError> {
+    let res = client
+        .get(format!(
+            "{}/hooks?page={}&per_page={}",
+            make_api_url(project),
+            page,
+            per_page
+        ))
+        .headers(headers(token))
+        .send_throttled(&throttle::GITLAB)
+        .await?;
+
+    match res.status() {
+        reqwest::StatusCode::OK => {
+            let response_headers = res.headers().clone();
+            let hooks: Vec<gitlab::ProjectHook> = res.json().await?;
+            Ok(Page::new(hooks, &response_headers, per_page))
+        }
+        _ => {
+            let err =
+                GitError::api_status(format!("Error listing webhooks of {}", project), res).await;
+            error!("{}", err);
+            Err(err)
+        }
+    }
+}
+
+/// Creates a webhook on a project, or updates the one with `hook_id`
+pub async fn put_hook(
+    client: &reqwest::Client,
+    project: &str,
+    token: &str,
+    hook_id: Option<i64>,
+    hook: &serde_json::Value,
+) -> Result<gitlab::ProjectHook, GitError> {
+    let request = match hook_id {
+        Some(hook_id) => client.put(format!("{}/hooks/{}", make_api_url(project), hook_id)),
+        None => client.post(format!("{}/hooks", make_api_url(project))),
     };
-    format!("https://{}/{}", hostname, project)
+    let res = request
+        .headers(headers(token))
+        .header(reqwest::header::CONTENT_TYPE, "application/json")
+        .body(hook.to_string())
+        .send_throttled(&throttle::GITLAB)
+        .await?;
+
+    match res.status() {
+        reqwest::StatusCode::OK | reqwest::StatusCode::CREATED => Ok(res.json().await?),
+        _ => {
+            let err =
+                GitError::api_status(format!("Error saving webhook of {}", project), res).await;
+            error!("{}", err);
+            Err(err)
+        }
+    }
 }
 
 pub async fn get_pipelines(
@@ -47,20 +256,260 @@ pub async fn get_pipelines(
     project: &str,
     page: i64,
     per_page: i64,
-) -> Result<Vec<gitlab::Pipeline>, GitError> {
-    let res: Vec<gitlab::Pipeline> = client
-        .get(&format!(
+) -> Result<Page<gitlab::Pipeline>, GitError> {
+    let res = client
+        .get(format!(
             "{}/pipelines?page={}&per_page={}",
             make_api_url(project),
             page,
             per_page
         ))
-        .headers(headers(&config::CONFIG.gitlab.api_token))
-        .send()
+        .headers(headers(&token(project)))
+        .send_throttled(&throttle::GITLAB)
+        .await?;
+    let response_headers = res.headers().clone();
+    let pipelines: Vec<gitlab::Pipeline> = res.json().await?;
+    Ok(Page::new(pipelines, &response_headers, per_page))
+}
+
+/// Finds the newest pipeline of a commit, paging through the project's
+/// pipelines until it's found
+pub async fn find_pipeline(
+    client: &reqwest::Client,
+    project: &str,
+    sha: &str,
+) -> Result<Option<gitlab::Pipeline>, GitError> {
+    pagination::find(
+        pagination::MAX_PAGES,
+        |page| get_pipelines(client, project, page, PER_PAGE),
+        |pipeline| pipeline.id.is_some() && pipeline.sha.as_deref() == Some(sha),
+    )
+    .await
+}
+
+pub async fn get_pipeline(
+    client: &reqwest::Client,
+    project: &str,
+    pipeline_id: i64,
+) -> Result<gitlab::Pipeline, GitError> {
+    let res = client
+        .get(format!(
+            "{}/pipelines/{}",
+            make_api_url(project),
+            pipeline_id
+        ))
+        .headers(headers(&token(project)))
+        .send_throttled(&throttle::GITLAB)
+        .await?;
+
+    match res.status() {
+        reqwest::StatusCode::OK => Ok(res.json().await?),
+        _ => {
+            let err = GitError::api_status("Error getting pipeline", res).await;
+            error!("{}", err);
+            Err(err)
+        }
+    }
+}
+
+/// Returns the latest successful pipeline of a ref, if any
+pub async fn get_latest_successful_pipeline(
+    client: &reqwest::Client,
+    project: &str,
+    ref_name: &str,
+) -> Result<Option<gitlab::Pipeline>, GitError> {
+    let res: Vec<gitlab::Pipeline> = client
+        .get(format!("{}/pipelines", make_api_url(project)))
+        .query(&[("ref", ref_name), ("status", "success"), ("per_page", "1")])
+        .headers(headers(&token(project)))
+        .send_throttled(&throttle::GITLAB)
         .await?
         .json()
         .await?;
-    Ok(res)
+    Ok(res.into_iter().next())
+}
+
+/// Returns the successful jobs of a pipeline which have an artifacts
+/// archive
+pub async fn get_job_artifacts(
+    client: &reqwest::Client,
+    project: &str,
+    pipeline_id: i64,
+) -> Result<Vec<gitlab::Job>, GitError> {
+    let res = client
+        .get(format!(
+            "{}/pipelines/{}/jobs?scope[]=success&per_page=100",
+            make_api_url(project),
+            pipeline_id
+        ))
+        .headers(headers(&token(project)))
+        .send_throttled(&throttle::GITLAB)
+        .await?;
+
+    match res.status() {
+        reqwest::StatusCode::OK => {
+            let mut jobs: Vec<gitlab::Job> = res.json().await?;
+            jobs.retain(|job| {
+                job.artifacts
+                    .iter()
+                    .flatten()
+                    .any(|artifact| artifact.file_type.as_deref() == Some("archive"))
+            });
+            Ok(jobs)
+        }
+        _ => {
+            let err = GitError::api_status("Error listing pipeline jobs", res).await;
+            error!("{}", err);
+            Err(err)
+        }
+    }
+}
+
+/// Returns the unit test report GitLab collected from a pipeline's JUnit
+/// artifacts
+pub async fn get_test_report(
+    client: &reqwest::Client,
+    project: &str,
+    pipeline_id: i64,
+) -> Result<gitlab::TestReport, GitError> {
+    let res = client
+        .get(format!(
+            "{}/pipelines/{}/test_report",
+            make_api_url(project),
+            pipeline_id
+        ))
+        .headers(headers(&token(project)))
+        .send_throttled(&throttle::GITLAB)
+        .await?;
+
+    match res.status() {
+        reqwest::StatusCode::OK => Ok(res.json().await?),
+        _ => {
+            let err = GitError::api_status("Error getting test report", res).await;
+            error!("{}", err);
+            Err(err)
+        }
+    }
+}
+
+pub async fn get_branches(
+    client: &reqwest::Client,
+    project: &str,
+    search: &str,
+    page: i64,
+    per_page: i64,
+) -> Result<Page<gitlab::Branch>, GitError> {
+    let res = client
+        .get(format!(
+            "{}/repository/branches?search={}&page={}&per_page={}",
+            make_api_url(project),
+            utf8_percent_encode(search, FRAGMENT),
+            page,
+            per_page
+        ))
+        .headers(headers(&token(project)))
+        .send_throttled(&throttle::GITLAB)
+        .await?;
+    let response_headers = res.headers().clone();
+    let branches: Vec<gitlab::Branch> = res.json().await?;
+    Ok(Page::new(branches, &response_headers, per_page))
+}
+
+/// Lists all the branches matching a search, ex: `^pr-`
+pub async fn get_all_branches(
+    client: &reqwest::Client,
+    project: &str,
+    search: &str,
+) -> Result<Vec<gitlab::Branch>, GitError> {
+    pagination::paginate(pagination::MAX_PAGES, |page| {
+        get_branches(client, project, search, page, PER_PAGE)
+    })
+    .await
+}
+
+pub async fn get_branch(
+    client: &reqwest::Client,
+    project: &str,
+    branch: &str,
+) -> Result<Option<gitlab::Branch>, GitError> {
+    let res = client
+        .get(format!(
+            "{}/repository/branches/{}",
+            make_api_url(project),
+            utf8_percent_encode(branch, FRAGMENT)
+        ))
+        .headers(headers(&token(project)))
+        .send_throttled(&throttle::GITLAB)
+        .await?;
+
+    match res.status() {
+        reqwest::StatusCode::OK => Ok(Some(res.json().await?)),
+        reqwest::StatusCode::NOT_FOUND => Ok(None),
+        _ => {
+            let err = GitError::api_status("Error getting branch", res).await;
+            error!("{}", err);
+            Err(err)
+        }
+    }
+}
+
+pub async fn delete_branch(
+    client: &reqwest::Client,
+    project: &str,
+    branch: &str,
+) -> Result<(), GitError> {
+    killswitch::check(KillSwitch::Branches)?;
+    let res = client
+        .delete(format!(
+            "{}/repository/branches/{}",
+            make_api_url(project),
+            utf8_percent_encode(branch, FRAGMENT)
+        ))
+        .headers(headers(&token(project)))
+        .send_throttled(&throttle::GITLAB)
+        .await?;
+
+    match res.status() {
+        reqwest::StatusCode::NO_CONTENT => Ok(()),
+        _ => {
+            let err = GitError::api_status("Error deleting branch", res).await;
+            error!("{}", err);
+            Err(err)
+        }
+    }
+}
+
+pub async fn create_pipeline(
+    client: &reqwest::Client,
+    project: &str,
+    ref_name: &str,
+    variables: &HashMap<String, String>,
+) -> Result<gitlab::Pipeline, GitError> {
+    killswitch::check(KillSwitch::Pipelines)?;
+    let variables: Vec<serde_json::Value> = variables
+        .iter()
+        .map(|(key, value)| serde_json::json!({ "key": key, "value": value }))
+        .collect();
+    let res = client
+        .post(format!(
+            "{}/pipeline?ref={}",
+            make_api_url(project),
+            utf8_percent_encode(ref_name, FRAGMENT)
+        ))
+        .headers(headers(&token(project)))
+        .header(reqwest::header::CONTENT_TYPE, "application/json")
+        .body(serde_json::json!({ "variables": variables }).to_string())
+        .send_throttled(&throttle::GITLAB)
+        .await?;
+
+    match res.status() {
+        reqwest::StatusCode::CREATED => Ok(res.json().await?),
+        _ => {
+            let err = GitError::api_status("Error creating pipeline", res).await;
+            error!("{}", err);
+            Err(err)
+        }
+    }
 }
 
 pub async fn retry_pipeline(
@@ -68,22 +517,286 @@ pub async fn retry_pipeline(
     project: &str,
     pipeline_id: i64,
 ) -> Result<(), GitError> {
+    killswitch::check(KillSwitch::Pipelines)?;
     let res = client
-        .post(&format!(
+        .post(format!(
             "{}/pipelines/{}/retry",
             make_api_url(project),
             pipeline_id
         ))
-        .headers(headers(&config::CONFIG.gitlab.api_token))
-        .send()
+        .headers(headers(&token(project)))
+        .send_throttled(&throttle::GITLAB)
+        .await?;
+
+    match res.status() {
+        reqwest::StatusCode::CREATED => Ok(()),
+        _ => {
+            let err = GitError::api_status("Error retrying pipeline", res).await;
+            error!("{}", err);
+            Err(err)
+        }
+    }
+}
+
+pub async fn cancel_pipeline(
+    client: &reqwest::Client,
+    project: &str,
+    pipeline_id: i64,
+) -> Result<(), GitError> {
+    killswitch::check(KillSwitch::Pipelines)?;
+    let res = client
+        .post(format!(
+            "{}/pipelines/{}/cancel",
+            make_api_url(project),
+            pipeline_id
+        ))
+        .headers(headers(&token(project)))
+        .send_throttled(&throttle::GITLAB)
+        .await?;
+
+    match res.status() {
+        reqwest::StatusCode::OK | reqwest::StatusCode::CREATED => Ok(()),
+        _ => {
+            let err = GitError::api_status("Error canceling pipeline", res).await;
+            error!("{}", err);
+            Err(err)
+        }
+    }
+}
+
+pub async fn retry_job(
+    client: &reqwest::Client,
+    project: &str,
+    job_id: i64,
+) -> Result<(), GitError> {
+    killswitch::check(KillSwitch::Pipelines)?;
+    let res = client
+        .post(format!("{}/jobs/{}/retry", make_api_url(project), job_id))
+        .headers(headers(&token(project)))
+        .send_throttled(&throttle::GITLAB)
         .await?;
 
     match res.status() {
         reqwest::StatusCode::CREATED => Ok(()),
         _ => {
-            let msg = format!("Error retrying pipeline: {:#?}", res);
-            error!("{}", msg);
-            Err(GitError { message: msg })
+            let err = GitError::api_status("Error retrying job", res).await;
+            error!("{}", err);
+            Err(err)
+        }
+    }
+}
+
+/// The open merge requests from a branch
+pub async fn get_merge_requests(
+    client: &reqwest::Client,
+    project: &str,
+    source_branch: &str,
+) -> Result<Vec<gitlab::MergeRequest>, GitError> {
+    let res: Vec<gitlab::MergeRequest> = client
+        .get(format!(
+            "{}/merge_requests?state=opened&source_branch={}",
+            make_api_url(project),
+            utf8_percent_encode(source_branch, FRAGMENT)
+        ))
+        .headers(headers(&token(project)))
+        .send_throttled(&throttle::GITLAB)
+        .await?
+        .json()
+        .await?;
+    Ok(res)
+}
+
+pub async fn create_merge_request(
+    client: &reqwest::Client,
+    project: &str,
+    source_branch: &str,
+    target_branch: &str,
+    title: &str,
+    description: &str,
+    labels: &[&str],
+) -> Result<gitlab::MergeRequest, GitError> {
+    killswitch::check(KillSwitch::MergeRequests)?;
+    let res = client
+        .post(format!("{}/merge_requests", make_api_url(project)))
+        .headers(headers(&token(project)))
+        .header(reqwest::header::CONTENT_TYPE, "application/json")
+        .body(
+            serde_json::json!({
+                "source_branch": source_branch,
+                "target_branch": target_branch,
+                "title": title,
+                "description": description,
+                "labels": labels.join(","),
+            })
+            .to_string(),
+        )
+        .send_throttled(&throttle::GITLAB)
+        .await?;
+
+    match res.status() {
+        reqwest::StatusCode::CREATED => Ok(res.json().await?),
+        _ => {
+            let err = GitError::api_status("Error creating merge request", res).await;
+            error!("{}", err);
+            Err(err)
+        }
+    }
+}
+
+pub async fn update_merge_request(
+    client: &reqwest::Client,
+    project: &str,
+    iid: i64,
+    target_branch: &str,
+    title: &str,
+    description: &str,
+) -> Result<gitlab::MergeRequest, GitError> {
+    killswitch::check(KillSwitch::MergeRequests)?;
+    let res = client
+        .put(format!("{}/merge_requests/{}", make_api_url(project), iid))
+        .headers(headers(&token(project)))
+        .header(reqwest::header::CONTENT_TYPE, "application/json")
+        .body(
+            serde_json::json!({
+                "target_branch": target_branch,
+                "title": title,
+                "description": description,
+            })
+            .to_string(),
+        )
+        .send_throttled(&throttle::GITLAB)
+        .await?;
+
+    match res.status() {
+        reqwest::StatusCode::OK => Ok(res.json().await?),
+        _ => {
+            let err = GitError::api_status("Error updating merge request", res).await;
+            error!("{}", err);
+            Err(err)
+        }
+    }
+}
+
+/// Adds and removes labels of a merge request, leaving its other labels
+/// alone. GitLab creates labels which don't exist yet.
+pub async fn update_merge_request_labels(
+    client: &reqwest::Client,
+    project: &str,
+    iid: i64,
+    add: &[&str],
+    remove: &[&str],
+) -> Result<(), GitError> {
+    killswitch::check(KillSwitch::MergeRequests)?;
+    let res = client
+        .put(format!("{}/merge_requests/{}", make_api_url(project), iid))
+        .headers(headers(&token(project)))
+        .header(reqwest::header::CONTENT_TYPE, "application/json")
+        .body(
+            serde_json::json!({
+                "add_labels": add.join(","),
+                "remove_labels": remove.join(","),
+            })
+            .to_string(),
+        )
+        .send_throttled(&throttle::GITLAB)
+        .await?;
+
+    match res.status() {
+        reqwest::StatusCode::OK => Ok(()),
+        _ => {
+            let err = GitError::api_status("Error updating merge request labels", res).await;
+            error!("{}", err);
+            Err(err)
+        }
+    }
+}
+
+pub async fn close_merge_request(
+    client: &reqwest::Client,
+    project: &str,
+    iid: i64,
+) -> Result<(), GitError> {
+    killswitch::check(KillSwitch::MergeRequests)?;
+    let res = client
+        .put(format!("{}/merge_requests/{}", make_api_url(project), iid))
+        .headers(headers(&token(project)))
+        .header(reqwest::header::CONTENT_TYPE, "application/json")
+        .body(serde_json::json!({ "state_event": "close" }).to_string())
+        .send_throttled(&throttle::GITLAB)
+        .await?;
+
+    match res.status() {
+        reqwest::StatusCode::OK => Ok(()),
+        _ => {
+            let err = GitError::api_status("Error closing merge request", res).await;
+            error!("{}", err);
+            Err(err)
+        }
+    }
+}
+
+/// Comments on a merge request, returning the new note's ID
+pub async fn create_merge_request_note(
+    client: &reqwest::Client,
+    project: &str,
+    iid: i64,
+    body: &str,
+) -> Result<i64, GitError> {
+    killswitch::check(KillSwitch::Comments)?;
+    let res = client
+        .post(format!(
+            "{}/merge_requests/{}/notes",
+            make_api_url(project),
+            iid
+        ))
+        .headers(headers(&token(project)))
+        .header(reqwest::header::CONTENT_TYPE, "application/json")
+        .body(serde_json::json!({ "body": body }).to_string())
+        .send_throttled(&throttle::GITLAB)
+        .await?;
+
+    match res.status() {
+        reqwest::StatusCode::CREATED => {
+            let note: gitlab::Note = res.json().await?;
+            note.id.ok_or(GitError::UnexpectedResponse(
+                "Created merge request note has no id".to_string(),
+            ))
+        }
+        _ => {
+            let err = GitError::api_status("Error creating merge request note", res).await;
+            error!("{}", err);
+            Err(err)
+        }
+    }
+}
+
+pub async fn update_merge_request_note(
+    client: &reqwest::Client,
+    project: &str,
+    iid: i64,
+    note_id: i64,
+    body: &str,
+) -> Result<(), GitError> {
+    killswitch::check(KillSwitch::Comments)?;
+    let res = client
+        .put(format!(
+            "{}/merge_requests/{}/notes/{}",
+            make_api_url(project),
+            iid,
+            note_id
+        ))
+        .headers(headers(&token(project)))
+        .header(reqwest::header::CONTENT_TYPE, "application/json")
+        .body(serde_json::json!({ "body": body }).to_string())
+        .send_throttled(&throttle::GITLAB)
+        .await?;
+
+    match res.status() {
+        reqwest::StatusCode::OK => Ok(()),
+        _ => {
+            let err = GitError::api_status("Error updating merge request note", res).await;
+            error!("{}", err);
+            Err(err)
         }
     }
 }
@@ -91,6 +804,7 @@ pub async fn retry_pipeline(
 #[cfg(test)]
 mod test {
     use super::*;
+    use crate::testing::{read_testdata_to_string, run_test};
 
     #[test]
     fn test_make_ext_url() {
@@ -107,4 +821,97 @@ mod test {
             "https://gitlab.com/api/v4/projects/brndnmtthws-oss%2Fconky"
         );
     }
+
+    #[tokio::test]
+    async fn test_base_url() {
+        let url = api::with_base_url("http://127.0.0.1:8080/".to_string(), async {
+            make_api_url("brndnmtthws-oss/conky")
+        })
+        .await;
+        assert_eq!(
+            url,
+            "http://127.0.0.1:8080/api/v4/projects/brndnmtthws-oss%2Fconky"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_cancel_pipeline() {
+        use wiremock::matchers::{method, path};
+        use wiremock::{Mock, MockServer, ResponseTemplate};
+
+        let server = MockServer::start().await;
+        // GitLab answers cancellations with a 201
+        Mock::given(method("POST"))
+            .and(path(
+                "/api/v4/projects/brndnmtthws-oss%2Fconky/pipelines/46/cancel",
+            ))
+            .respond_with(ResponseTemplate::new(201).set_body_json(serde_json::json!({
+                "id": 46,
+                "status": "canceled",
+            })))
+            .expect(1)
+            .mount(&server)
+            .await;
+        let client = reqwest::Client::new();
+        api::with_base_url(
+            server.uri(),
+            cancel_pipeline(&client, "brndnmtthws-oss/conky", 46),
+        )
+        .await
+        .unwrap();
+    }
+
+    #[test]
+    fn test_models() {
+        run_test(|| {
+            let pipeline: gitlab::Pipeline =
+                serde_json::from_str(&read_testdata_to_string("gitlab_pipeline.json")).unwrap();
+            assert_eq!(pipeline.id, Some(46));
+            assert_eq!(pipeline.source.as_deref(), Some("push"));
+            assert_eq!(pipeline.duration, Some(123));
+            assert_eq!(pipeline.coverage.as_deref(), Some("30.0"));
+            assert_eq!(
+                pipeline.user.and_then(|user| user.username).as_deref(),
+                Some("root")
+            );
+
+            let job: gitlab::Job =
+                serde_json::from_str(&read_testdata_to_string("gitlab_job.json")).unwrap();
+            assert_eq!(job.name.as_deref(), Some("rubocop"));
+            assert_eq!(job.failure_reason.as_deref(), Some("script_failure"));
+            assert_eq!(job.duration, Some(0.465));
+            assert_eq!(job.pipeline.and_then(|pipeline| pipeline.id), Some(46));
+
+            let project: gitlab::Project =
+                serde_json::from_str(&read_testdata_to_string("gitlab_project.json")).unwrap();
+            assert_eq!(
+                project.path_with_namespace.as_deref(),
+                Some("mirrors/hello-world")
+            );
+            assert_eq!(project.default_branch.as_deref(), Some("main"));
+            assert_eq!(project.archived, Some(false));
+
+            let merge_request: gitlab::MergeRequest =
+                serde_json::from_str(&read_testdata_to_string("gitlab_merge_request.json"))
+                    .unwrap();
+            assert_eq!(merge_request.iid, Some(133));
+            assert_eq!(
+                merge_request.labels,
+                Some(vec!["labhub".to_string(), "external".to_string()])
+            );
+            assert_eq!(merge_request.merge_status.as_deref(), Some("can_be_merged"));
+            assert_eq!(
+                merge_request
+                    .author
+                    .and_then(|author| author.username)
+                    .as_deref(),
+                Some("labhub")
+            );
+
+            let jobs: Vec<gitlab::Job> =
+                serde_json::from_str(&read_testdata_to_string("gitlab_pipeline_jobs.json"))
+                    .unwrap();
+            assert_eq!(jobs.len(), 3);
+        });
+    }
 }
@@ -0,0 +1,95 @@
+use headers::{Header, HeaderName, HeaderValue};
+
+pub struct XGitlabEvent(pub String);
+
+impl Header for XGitlabEvent {
+    fn name() -> &'static HeaderName {
+        static N: HeaderName = HeaderName::from_static("x-gitlab-event");
+        &N
+    }
+
+    fn decode<'i, I>(values: &mut I) -> Result<Self, headers::Error>
+    where
+        I: Iterator<Item = &'i HeaderValue>,
+    {
+        let value = values.next().ok_or_else(headers::Error::invalid)?;
+        Ok(XGitlabEvent(
+            value
+                .to_str()
+                .or(Err(headers::Error::invalid()))?
+                .to_owned(),
+        ))
+    }
+
+    fn encode<E>(&self, values: &mut E)
+    where
+        E: Extend<HeaderValue>,
+    {
+        let value = HeaderValue::from_str(self.0.as_str());
+
+        values.extend(value);
+    }
+}
+
+pub struct XGitlabToken(pub String);
+
+impl Header for XGitlabToken {
+    fn name() -> &'static HeaderName {
+        static N: HeaderName = HeaderName::from_static("x-gitlab-token");
+        &N
+    }
+
+    fn decode<'i, I>(values: &mut I) -> Result<Self, headers::Error>
+    where
+        I: Iterator<Item = &'i HeaderValue>,
+    {
+        let value = values.next().ok_or_else(headers::Error::invalid)?;
+        Ok(XGitlabToken(
+            value
+                .to_str()
+                .or(Err(headers::Error::invalid()))?
+                .to_owned(),
+        ))
+    }
+
+    fn encode<E>(&self, values: &mut E)
+    where
+        E: Extend<HeaderValue>,
+    {
+        let value = HeaderValue::from_str(self.0.as_str());
+
+        values.extend(value);
+    }
+}
+
+/// Identifies a webhook delivery, and stays the same when it's resent
+pub struct XGitlabEventUuid(pub String);
+
+impl Header for XGitlabEventUuid {
+    fn name() -> &'static HeaderName {
+        static N: HeaderName = HeaderName::from_static("x-gitlab-event-uuid");
+        &N
+    }
+
+    fn decode<'i, I>(values: &mut I) -> Result<Self, headers::Error>
+    where
+        I: Iterator<Item = &'i HeaderValue>,
+    {
+        let value = values.next().ok_or_else(headers::Error::invalid)?;
+        Ok(XGitlabEventUuid(
+            value
+                .to_str()
+                .or(Err(headers::Error::invalid()))?
+                .to_owned(),
+        ))
+    }
+
+    fn encode<E>(&self, values: &mut E)
+    where
+        E: Extend<HeaderValue>,
+    {
+        let value = HeaderValue::from_str(self.0.as_str());
+
+        values.extend(value);
+    }
+}
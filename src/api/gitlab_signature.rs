@@ -0,0 +1,22 @@
+use crate::api::github_signature::SignatureError;
+
+use log::{debug, warn};
+use ring::constant_time;
+
+/// GitLab sends the webhook secret as-is in `X-Gitlab-Token`, rather than
+/// signing the body. Each GitLab instance has its own secret, so this
+/// returns the index of the one that matched.
+pub fn check_token(secrets: &[&str], token: &str) -> Result<usize, SignatureError> {
+    match secrets.iter().position(|secret| {
+        constant_time::verify_slices_are_equal(secret.as_bytes(), token.as_bytes()).is_ok()
+    }) {
+        Some(index) => {
+            debug!("Good token for GitLab");
+            Ok(index)
+        }
+        None => {
+            warn!("Got a bad GitLab webhook token");
+            Err(SignatureError::BadSignature)
+        }
+    }
+}
@@ -0,0 +1,175 @@
+use crate::errors::GitError;
+
+use log::error;
+use std::collections::HashMap;
+
+const LFS_MEDIA_TYPE: &str = "application/vnd.git-lfs+json";
+
+/// An LFS object, as referenced by a pointer file
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct Object {
+    pub oid: String,
+    pub size: u64,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct Action {
+    pub href: String,
+    #[serde(default)]
+    pub header: HashMap<String, String>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct ObjectError {
+    pub code: i64,
+    pub message: String,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct BatchObject {
+    pub oid: String,
+    pub size: u64,
+    /// Empty when there's nothing to do, e.g. the object is already uploaded
+    #[serde(default)]
+    pub actions: HashMap<String, Action>,
+    pub error: Option<ObjectError>,
+}
+
+#[derive(Deserialize, Debug)]
+struct BatchResponse {
+    objects: Vec<BatchObject>,
+}
+
+/// An LFS server, ex: `https://github.com/org/repo.git/info/lfs`
+pub struct Endpoint {
+    pub url: String,
+    pub username: String,
+    pub password: String,
+}
+
+fn action_headers(action: &Action) -> Result<reqwest::header::HeaderMap, GitError> {
+    let mut headers = reqwest::header::HeaderMap::new();
+    for (name, value) in action.header.iter() {
+        let name = reqwest::header::HeaderName::from_bytes(name.as_bytes()).map_err(|err| {
+            GitError::UnexpectedResponse(format!("Invalid LFS action header {}: {:?}", name, err))
+        })?;
+        let value = reqwest::header::HeaderValue::from_str(value).map_err(|err| {
+            GitError::UnexpectedResponse(format!("Invalid LFS action header value: {:?}", err))
+        })?;
+        headers.insert(name, value);
+    }
+    Ok(headers)
+}
+
+/// Asks the LFS server what to do to `operation` ("download" or "upload")
+/// the objects
+pub async fn batch(
+    client: &reqwest::Client,
+    endpoint: &Endpoint,
+    operation: &str,
+    objects: &[Object],
+) -> Result<Vec<BatchObject>, GitError> {
+    let res = client
+        .post(format!("{}/objects/batch", endpoint.url))
+        .basic_auth(&endpoint.username, Some(&endpoint.password))
+        .header(reqwest::header::ACCEPT, LFS_MEDIA_TYPE)
+        .header(reqwest::header::CONTENT_TYPE, LFS_MEDIA_TYPE)
+        .body(
+            serde_json::json!({
+                "operation": operation,
+                "transfers": ["basic"],
+                "objects": objects,
+            })
+            .to_string(),
+        )
+        .send()
+        .await?;
+
+    match res.status() {
+        reqwest::StatusCode::OK => {
+            let response: BatchResponse = res.json().await?;
+            Ok(response.objects)
+        }
+        _ => {
+            let err = GitError::api_status(
+                format!(
+                    "Error requesting LFS {} batch from {}",
+                    operation, endpoint.url
+                ),
+                res,
+            )
+            .await;
+            error!("{}", err);
+            Err(err)
+        }
+    }
+}
+
+pub async fn download(client: &reqwest::Client, action: &Action) -> Result<Vec<u8>, GitError> {
+    let res = client
+        .get(&action.href)
+        .headers(action_headers(action)?)
+        .send()
+        .await?;
+
+    match res.status() {
+        reqwest::StatusCode::OK => Ok(res.bytes().await?.to_vec()),
+        _ => {
+            let err = GitError::api_status("Error downloading LFS object", res).await;
+            error!("{}", err);
+            Err(err)
+        }
+    }
+}
+
+pub async fn upload(
+    client: &reqwest::Client,
+    action: &Action,
+    data: Vec<u8>,
+) -> Result<(), GitError> {
+    let res = client
+        .put(&action.href)
+        .headers(action_headers(action)?)
+        .header(reqwest::header::CONTENT_TYPE, "application/octet-stream")
+        .body(data)
+        .send()
+        .await?;
+
+    match res.status() {
+        status if status.is_success() => Ok(()),
+        _ => {
+            let err = GitError::api_status("Error uploading LFS object", res).await;
+            error!("{}", err);
+            Err(err)
+        }
+    }
+}
+
+/// Confirms an upload, when the server asked for it with a `verify` action
+pub async fn verify(
+    client: &reqwest::Client,
+    endpoint: &Endpoint,
+    action: &Action,
+    object: &Object,
+) -> Result<(), GitError> {
+    let res = client
+        .post(&action.href)
+        .basic_auth(&endpoint.username, Some(&endpoint.password))
+        .headers(action_headers(action)?)
+        .header(reqwest::header::ACCEPT, LFS_MEDIA_TYPE)
+        .header(reqwest::header::CONTENT_TYPE, LFS_MEDIA_TYPE)
+        .body(serde_json::to_string(object)?)
+        .send()
+        .await?;
+
+    match res.status() {
+        reqwest::StatusCode::OK => Ok(()),
+        _ => {
+            let err =
+                GitError::api_status(format!("Error verifying LFS object {}", object.oid), res)
+                    .await;
+            error!("{}", err);
+            Err(err)
+        }
+    }
+}
@@ -0,0 +1,56 @@
+use crate::config;
+use crate::errors::GitError;
+
+use log::error;
+use percent_encoding::{utf8_percent_encode, NON_ALPHANUMERIC};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Transaction IDs only have to be unique per access token, so that a
+/// retried request isn't sent twice
+fn transaction_id() -> String {
+    static COUNTER: AtomicU64 = AtomicU64::new(0);
+    let millis = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis())
+        .unwrap_or(0);
+    format!(
+        "labhub-{}-{}",
+        millis,
+        COUNTER.fetch_add(1, Ordering::Relaxed)
+    )
+}
+
+/// Sends a notice, which bots use so that other bots don't respond to it
+pub async fn send_notice(
+    client: &reqwest::Client,
+    matrix: &config::Matrix,
+    body: &str,
+) -> Result<(), GitError> {
+    let url = format!(
+        "{}/_matrix/client/v3/rooms/{}/send/m.room.message/{}",
+        matrix.homeserver.trim_end_matches('/'),
+        utf8_percent_encode(&matrix.room_id, NON_ALPHANUMERIC),
+        transaction_id()
+    );
+    let res = client
+        .put(url)
+        .bearer_auth(&matrix.access_token)
+        .json(&serde_json::json!({
+            "msgtype": "m.notice",
+            "body": body,
+        }))
+        .send()
+        .await?;
+
+    if !res.status().is_success() {
+        let err = GitError::api_status(
+            format!("Error sending Matrix message to room={}", matrix.room_id),
+            res,
+        )
+        .await;
+        error!("{}", err);
+        return Err(err);
+    }
+    Ok(())
+}
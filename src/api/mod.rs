@@ -1,5 +1,56 @@
+//! HTTP clients for the GitHub, GitLab, LFS, Matrix and webhook APIs, the
+//! models of their payloads, paging through listings, and webhook signature
+//! checks.
+use crate::errors::GitError;
+
+use std::future::Future;
+
+pub mod bitbucket_proto;
+pub mod bitbucket_signature;
+pub mod etag_cache;
+pub mod gitea_proto;
+pub mod gitea_signature;
+pub mod github_app;
 pub mod github_client;
 pub mod github_proto;
 pub mod github_signature;
 pub mod gitlab_client;
+pub mod gitlab_proto;
+pub mod gitlab_signature;
+pub mod lfs_client;
+pub mod matrix_client;
 pub mod models;
+pub mod pagination;
+pub mod retry;
+pub mod throttle;
+pub mod webhook_client;
+
+tokio::task_local! {
+    static BASE_URL: String;
+}
+
+/// The base URL the GitHub and GitLab clients send requests to instead of
+/// the configured instances, when running under [`with_base_url`]
+pub(crate) fn base_url() -> Option<String> {
+    BASE_URL
+        .try_with(|base_url| base_url.trim_end_matches('/').to_string())
+        .ok()
+}
+
+/// Runs `f` with the GitHub and GitLab clients pointed at `base_url`, ex: a
+/// mock server in tests. GitHub's API is served from its root, GitLab's from
+/// `/api/v4`.
+pub async fn with_base_url<F: Future>(base_url: String, f: F) -> F::Output {
+    BASE_URL.scope(base_url, f).await
+}
+
+static APP_USER_AGENT: &str = concat!(env!("CARGO_PKG_NAME"), "/", env!("CARGO_PKG_VERSION"),);
+
+pub fn new_client() -> Result<reqwest::Client, GitError> {
+    Ok(reqwest::Client::builder()
+        .user_agent(APP_USER_AGENT)
+        .gzip(true)
+        .brotli(true)
+        .deflate(true)
+        .build()?)
+}
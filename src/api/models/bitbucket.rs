@@ -0,0 +1,140 @@
+// This file is auto-generated, do not edit.
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct PullRequestEvent {
+    pub pullrequest: PullRequestEventPullrequest,
+    pub repository: PullRequestEventRepository,
+    pub actor: Option<PullRequestEventActor>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct PullRequestEventPullrequest {
+    pub id: i64,
+    pub title: Option<String>,
+    pub description: Option<String>,
+    pub state: Option<String>,
+    pub author: Option<PullRequestEventPullrequestAuthor>,
+    pub source: PullRequestEventPullrequestSource,
+    pub destination: PullRequestEventPullrequestDestination,
+    pub links: Option<PullRequestEventPullrequestLinks>,
+    pub created_on: Option<String>,
+    pub updated_on: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct PullRequestEventPullrequestAuthor {
+    pub display_name: Option<String>,
+    pub uuid: Option<String>,
+    pub nickname: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct PullRequestEventPullrequestSource {
+    pub branch: PullRequestEventPullrequestSourceBranch,
+    pub commit: PullRequestEventPullrequestSourceCommit,
+    pub repository: PullRequestEventPullrequestSourceRepository,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct PullRequestEventPullrequestSourceBranch {
+    pub name: String,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct PullRequestEventPullrequestSourceCommit {
+    pub hash: String,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct PullRequestEventPullrequestSourceRepository {
+    #[serde(rename = "type")]
+    pub type_key: Option<String>,
+    pub full_name: String,
+    pub name: Option<String>,
+    pub uuid: Option<String>,
+    pub links: Option<PullRequestEventPullrequestSourceRepositoryLinks>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct PullRequestEventPullrequestSourceRepositoryLinks {
+    pub html: Option<PullRequestEventPullrequestSourceRepositoryLinksHtml>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct PullRequestEventPullrequestSourceRepositoryLinksHtml {
+    pub href: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct PullRequestEventPullrequestDestination {
+    pub branch: PullRequestEventPullrequestDestinationBranch,
+    pub commit: PullRequestEventPullrequestDestinationCommit,
+    pub repository: PullRequestEventPullrequestDestinationRepository,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct PullRequestEventPullrequestDestinationBranch {
+    pub name: String,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct PullRequestEventPullrequestDestinationCommit {
+    pub hash: String,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct PullRequestEventPullrequestDestinationRepository {
+    #[serde(rename = "type")]
+    pub type_key: Option<String>,
+    pub full_name: String,
+    pub name: Option<String>,
+    pub uuid: Option<String>,
+    pub links: Option<PullRequestEventPullrequestDestinationRepositoryLinks>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct PullRequestEventPullrequestDestinationRepositoryLinks {
+    pub html: Option<PullRequestEventPullrequestDestinationRepositoryLinksHtml>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct PullRequestEventPullrequestDestinationRepositoryLinksHtml {
+    pub href: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct PullRequestEventPullrequestLinks {
+    pub html: Option<PullRequestEventPullrequestLinksHtml>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct PullRequestEventPullrequestLinksHtml {
+    pub href: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct PullRequestEventRepository {
+    #[serde(rename = "type")]
+    pub type_key: Option<String>,
+    pub full_name: String,
+    pub name: Option<String>,
+    pub uuid: Option<String>,
+    pub links: Option<PullRequestEventRepositoryLinks>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct PullRequestEventRepositoryLinks {
+    pub html: Option<PullRequestEventRepositoryLinksHtml>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct PullRequestEventRepositoryLinksHtml {
+    pub href: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct PullRequestEventActor {
+    pub display_name: Option<String>,
+    pub uuid: Option<String>,
+    pub nickname: Option<String>,
+}
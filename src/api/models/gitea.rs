@@ -0,0 +1,147 @@
+// This file is auto-generated, do not edit.
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct PullRequest {
+    pub action: String,
+    pub number: i64,
+    pub pull_request: PullRequestPullRequest,
+    pub repository: PullRequestRepository,
+    pub sender: Option<PullRequestSender>,
+    pub changes: Option<PullRequestChanges>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct PullRequestChanges {
+    pub title: Option<PullRequestChangesFrom>,
+    pub body: Option<PullRequestChangesFrom>,
+    #[serde(rename = "ref")]
+    pub ref_key: Option<PullRequestChangesFrom>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct PullRequestChangesFrom {
+    pub from: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct PullRequestPullRequest {
+    pub id: Option<i64>,
+    pub url: Option<String>,
+    pub number: i64,
+    pub user: Option<PullRequestPullRequestUser>,
+    pub title: Option<String>,
+    pub body: Option<String>,
+    pub state: Option<String>,
+    pub html_url: Option<String>,
+    pub mergeable: Option<bool>,
+    pub merged: Option<bool>,
+    pub base: PullRequestPullRequestBase,
+    pub head: PullRequestPullRequestHead,
+    pub merge_base: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct PullRequestPullRequestUser {
+    pub id: Option<i64>,
+    pub login: Option<String>,
+    pub username: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct PullRequestPullRequestBase {
+    pub label: Option<String>,
+    #[serde(rename = "ref")]
+    pub ref_key: String,
+    pub sha: String,
+    pub repo_id: Option<i64>,
+    pub repo: PullRequestPullRequestBaseRepo,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct PullRequestPullRequestBaseRepo {
+    pub id: Option<i64>,
+    pub owner: Option<PullRequestPullRequestBaseRepoOwner>,
+    pub name: Option<String>,
+    pub full_name: String,
+    pub description: Option<String>,
+    pub private: Option<bool>,
+    pub fork: Option<bool>,
+    pub html_url: Option<String>,
+    pub ssh_url: String,
+    pub clone_url: Option<String>,
+    pub default_branch: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct PullRequestPullRequestBaseRepoOwner {
+    pub id: Option<i64>,
+    pub login: Option<String>,
+    pub full_name: Option<String>,
+    pub email: Option<String>,
+    pub username: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct PullRequestPullRequestHead {
+    pub label: Option<String>,
+    #[serde(rename = "ref")]
+    pub ref_key: String,
+    pub sha: String,
+    pub repo_id: Option<i64>,
+    pub repo: PullRequestPullRequestHeadRepo,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct PullRequestPullRequestHeadRepo {
+    pub id: Option<i64>,
+    pub owner: Option<PullRequestPullRequestHeadRepoOwner>,
+    pub name: Option<String>,
+    pub full_name: String,
+    pub description: Option<String>,
+    pub private: Option<bool>,
+    pub fork: Option<bool>,
+    pub html_url: Option<String>,
+    pub ssh_url: String,
+    pub clone_url: Option<String>,
+    pub default_branch: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct PullRequestPullRequestHeadRepoOwner {
+    pub id: Option<i64>,
+    pub login: Option<String>,
+    pub full_name: Option<String>,
+    pub email: Option<String>,
+    pub username: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct PullRequestRepository {
+    pub id: Option<i64>,
+    pub owner: Option<PullRequestRepositoryOwner>,
+    pub name: Option<String>,
+    pub full_name: String,
+    pub description: Option<String>,
+    pub private: Option<bool>,
+    pub fork: Option<bool>,
+    pub html_url: Option<String>,
+    pub ssh_url: String,
+    pub clone_url: Option<String>,
+    pub default_branch: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct PullRequestRepositoryOwner {
+    pub id: Option<i64>,
+    pub login: Option<String>,
+    pub full_name: Option<String>,
+    pub email: Option<String>,
+    pub username: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct PullRequestSender {
+    pub id: Option<i64>,
+    pub login: Option<String>,
+    pub username: Option<String>,
+}
@@ -389,15 +389,46 @@ pub struct PushSender {
 pub struct PullRequest {
     pub action: String,
     pub number: i64,
+    pub changes: Option<PullRequestChanges>,
     pub pull_request: PullRequestPullRequest,
+    /// The label added or removed, for `labeled` and `unlabeled` events
+    pub label: Option<PullRequestLabel>,
     pub repository: GithubRepository,
+    #[serde(default)]
     pub sender: GithubSender,
 }
 
+#[derive(Serialize, Deserialize, Debug)]
+pub struct PullRequestLabel {
+    pub id: Option<i64>,
+    pub name: String,
+    pub color: Option<String>,
+    pub description: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct PullRequestChanges {
+    pub title: Option<PullRequestChangesFrom>,
+    pub body: Option<PullRequestChangesFrom>,
+    pub base: Option<PullRequestChangesBase>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct PullRequestChangesBase {
+    #[serde(rename = "ref")]
+    pub ref_key: Option<PullRequestChangesFrom>,
+    pub sha: Option<PullRequestChangesFrom>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct PullRequestChangesFrom {
+    pub from: Option<String>,
+}
+
 #[derive(Serialize, Deserialize, Debug)]
 pub struct PullRequestPullRequest {
-    pub url: String,
-    pub id: i64,
+    pub url: Option<String>,
+    pub id: Option<i64>,
     pub node_id: Option<String>,
     pub html_url: Option<String>,
     pub diff_url: Option<String>,
@@ -409,6 +440,7 @@ pub struct PullRequestPullRequest {
     pub title: Option<String>,
     pub user: PullRequestPullRequestUser,
     pub body: Option<String>,
+    pub labels: Option<Vec<PullRequestLabel>>,
     pub created_at: Option<serde_json::value::Value>,
     pub updated_at: Option<serde_json::value::Value>,
     pub closed_at: Option<serde_json::value::Value>,
@@ -542,9 +574,9 @@ pub struct PullRequestPullRequestHeadUser {
 
 #[derive(Serialize, Deserialize, Debug)]
 pub struct PullRequestPullRequestHeadRepo {
-    pub id: i64,
-    pub node_id: String,
-    pub name: String,
+    pub id: Option<i64>,
+    pub node_id: Option<String>,
+    pub name: Option<String>,
     pub full_name: String,
     pub private: Option<bool>,
     pub owner: Option<PullRequestPullRequestHeadRepoOwner>,
@@ -650,7 +682,7 @@ pub struct PullRequestPullRequestHeadRepoLicense {
 
 #[derive(Serialize, Deserialize, Debug)]
 pub struct PullRequestPullRequestBase {
-    pub label: String,
+    pub label: Option<String>,
     #[serde(rename = "ref")]
     pub ref_key: String,
     pub sha: String,
@@ -683,9 +715,9 @@ pub struct PullRequestPullRequestBaseUser {
 
 #[derive(Serialize, Deserialize, Debug)]
 pub struct PullRequestPullRequestBaseRepo {
-    pub id: i64,
-    pub node_id: String,
-    pub name: String,
+    pub id: Option<i64>,
+    pub node_id: Option<String>,
+    pub name: Option<String>,
     pub full_name: String,
     pub private: Option<bool>,
     pub owner: Option<PullRequestPullRequestBaseRepoOwner>,
@@ -973,7 +1005,7 @@ pub struct PullRequestRepositoryLicense {
     pub node_id: Option<String>,
 }
 
-#[derive(Serialize, Deserialize, Debug, Clone)]
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
 pub struct GithubSender {
     pub login: Option<String>,
     pub id: Option<i64>,
@@ -1002,13 +1034,14 @@ pub struct IssueComment {
     pub issue: IssueCommentIssue,
     pub comment: IssueCommentComment,
     pub repository: GithubRepository,
+    #[serde(default)]
     pub sender: GithubSender,
 }
 
 #[derive(Serialize, Deserialize, Debug)]
 pub struct IssueCommentIssue {
-    pub url: String,
-    pub repository_url: String,
+    pub url: Option<String>,
+    pub repository_url: Option<String>,
     pub labels_url: Option<String>,
     pub comments_url: Option<String>,
     pub events_url: Option<String>,
@@ -1702,3 +1735,18 @@ pub struct RepoPrMergedBy {
     pub type_key: Option<String>,
     pub site_admin: Option<bool>,
 }
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct Hook {
+    pub id: Option<i64>,
+    pub name: Option<String>,
+    pub active: Option<bool>,
+    pub events: Option<Vec<String>>,
+    pub config: Option<HookConfig>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct HookConfig {
+    pub url: Option<String>,
+    pub content_type: Option<String>,
+}
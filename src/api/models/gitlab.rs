@@ -3,9 +3,345 @@
 #[derive(Serialize, Deserialize, Debug)]
 pub struct Pipeline {
     pub id: Option<i64>,
+    pub iid: Option<i64>,
+    pub project_id: Option<i64>,
     pub status: Option<String>,
+    pub source: Option<String>,
     #[serde(rename = "ref")]
     pub ref_key: Option<String>,
     pub sha: Option<String>,
     pub web_url: Option<String>,
+    pub created_at: Option<serde_json::value::Value>,
+    pub updated_at: Option<serde_json::value::Value>,
+    /// Only set when getting a single pipeline
+    pub before_sha: Option<String>,
+    /// Only set when getting a single pipeline
+    pub started_at: Option<String>,
+    /// Only set when getting a single pipeline
+    pub finished_at: Option<String>,
+    /// Only set when getting a single pipeline, in seconds
+    pub duration: Option<i64>,
+    /// Only set when getting a single pipeline
+    pub coverage: Option<String>,
+    /// Only set when getting a single pipeline
+    pub user: Option<User>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct User {
+    pub id: Option<i64>,
+    pub name: Option<String>,
+    pub username: Option<String>,
+    pub web_url: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct Job {
+    pub id: Option<i64>,
+    pub name: Option<String>,
+    pub stage: Option<String>,
+    pub status: Option<String>,
+    #[serde(rename = "ref")]
+    pub ref_key: Option<String>,
+    pub allow_failure: Option<bool>,
+    pub failure_reason: Option<String>,
+    pub created_at: Option<serde_json::value::Value>,
+    pub started_at: Option<String>,
+    pub finished_at: Option<String>,
+    /// In seconds
+    pub duration: Option<f64>,
+    pub web_url: Option<String>,
+    pub pipeline: Option<JobPipeline>,
+    pub artifacts: Option<Vec<JobArtifact>>,
+    pub artifacts_expire_at: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct JobPipeline {
+    pub id: Option<i64>,
+    pub project_id: Option<i64>,
+    #[serde(rename = "ref")]
+    pub ref_key: Option<String>,
+    pub sha: Option<String>,
+    pub status: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct JobArtifact {
+    pub file_type: Option<String>,
+    pub size: Option<i64>,
+    pub filename: Option<String>,
+    pub file_format: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct TestReport {
+    pub total_count: Option<i64>,
+    pub success_count: Option<i64>,
+    pub failed_count: Option<i64>,
+    pub skipped_count: Option<i64>,
+    pub error_count: Option<i64>,
+    pub test_suites: Option<Vec<TestSuite>>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct TestSuite {
+    pub name: Option<String>,
+    pub total_count: Option<i64>,
+    pub failed_count: Option<i64>,
+    pub test_cases: Option<Vec<TestCase>>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct TestCase {
+    pub status: Option<String>,
+    pub name: Option<String>,
+    pub classname: Option<String>,
+    pub file: Option<String>,
+    pub execution_time: Option<f64>,
+    pub system_output: Option<String>,
+    pub stack_trace: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct Branch {
+    pub name: Option<String>,
+    pub merged: Option<bool>,
+    pub protected: Option<bool>,
+    pub default: Option<bool>,
+    pub web_url: Option<String>,
+    pub commit: Option<BranchCommit>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct BranchCommit {
+    pub id: Option<String>,
+    pub short_id: Option<String>,
+    pub title: Option<String>,
+    pub message: Option<String>,
+    pub committed_date: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct MergeRequest {
+    pub id: Option<i64>,
+    pub iid: Option<i64>,
+    pub project_id: Option<i64>,
+    pub title: Option<String>,
+    pub description: Option<String>,
+    pub state: Option<String>,
+    pub source_branch: Option<String>,
+    pub target_branch: Option<String>,
+    pub source_project_id: Option<i64>,
+    pub target_project_id: Option<i64>,
+    pub sha: Option<String>,
+    pub labels: Option<Vec<String>>,
+    pub draft: Option<bool>,
+    /// `can_be_merged`, `cannot_be_merged` or `checking`, among others
+    pub merge_status: Option<String>,
+    pub author: Option<User>,
+    pub created_at: Option<serde_json::value::Value>,
+    pub updated_at: Option<serde_json::value::Value>,
+    pub web_url: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct Project {
+    pub id: Option<i64>,
+    pub name: Option<String>,
+    pub path_with_namespace: Option<String>,
+    pub default_branch: Option<String>,
+    pub visibility: Option<String>,
+    pub archived: Option<bool>,
+    pub web_url: Option<String>,
+    pub http_url_to_repo: Option<String>,
+    pub ssh_url_to_repo: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct PipelineEvent {
+    pub object_kind: Option<String>,
+    pub object_attributes: Option<PipelineEventObjectAttributes>,
+    pub project: Option<PipelineEventProject>,
+    pub commit: Option<PipelineEventCommit>,
+    pub builds: Option<Vec<PipelineEventBuild>>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct PipelineEventObjectAttributes {
+    pub id: Option<i64>,
+    #[serde(rename = "ref")]
+    pub ref_key: Option<String>,
+    pub tag: Option<bool>,
+    pub sha: Option<String>,
+    pub before_sha: Option<String>,
+    pub source: Option<String>,
+    pub status: Option<String>,
+    pub detailed_status: Option<String>,
+    pub stages: Option<Vec<String>>,
+    pub created_at: Option<serde_json::value::Value>,
+    pub finished_at: Option<String>,
+    pub duration: Option<i64>,
+    pub url: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct PipelineEventProject {
+    pub id: Option<i64>,
+    pub name: Option<String>,
+    pub path_with_namespace: Option<String>,
+    pub web_url: Option<String>,
+    pub default_branch: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct PipelineEventCommit {
+    pub id: Option<String>,
+    pub message: Option<String>,
+    pub title: Option<String>,
+    pub url: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct PipelineEventBuild {
+    pub id: Option<i64>,
+    pub stage: Option<String>,
+    pub name: Option<String>,
+    pub status: Option<String>,
+    pub duration: Option<f64>,
+    pub allow_failure: Option<bool>,
+    pub artifacts_file: Option<PipelineEventArtifactsFile>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct PipelineEventArtifactsFile {
+    pub filename: Option<String>,
+    pub size: Option<i64>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct DeploymentEvent {
+    pub object_kind: Option<String>,
+    pub status: Option<String>,
+    pub status_changed_at: Option<String>,
+    pub deployment_id: Option<i64>,
+    pub deployable_id: Option<i64>,
+    pub deployable_url: Option<String>,
+    pub environment: Option<String>,
+    pub environment_slug: Option<String>,
+    pub environment_external_url: Option<String>,
+    pub project: Option<DeploymentEventProject>,
+    #[serde(rename = "ref")]
+    pub ref_key: Option<String>,
+    pub short_sha: Option<String>,
+    pub user_url: Option<String>,
+    pub commit_url: Option<String>,
+    pub commit_title: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct DeploymentEventProject {
+    pub id: Option<i64>,
+    pub name: Option<String>,
+    pub path_with_namespace: Option<String>,
+    pub web_url: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct JobEvent {
+    pub object_kind: Option<String>,
+    #[serde(rename = "ref")]
+    pub ref_key: Option<String>,
+    pub tag: Option<bool>,
+    pub sha: Option<String>,
+    pub build_id: Option<i64>,
+    pub build_name: Option<String>,
+    pub build_stage: Option<String>,
+    pub build_status: Option<String>,
+    pub build_duration: Option<f64>,
+    pub build_allow_failure: Option<bool>,
+    pub build_failure_reason: Option<String>,
+    pub pipeline_id: Option<i64>,
+    pub project_id: Option<i64>,
+    pub project_name: Option<String>,
+    pub project: Option<JobEventProject>,
+    pub commit: Option<JobEventCommit>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct JobEventProject {
+    pub id: Option<i64>,
+    pub name: Option<String>,
+    pub path_with_namespace: Option<String>,
+    pub web_url: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct JobEventCommit {
+    pub id: Option<i64>,
+    pub sha: Option<String>,
+    pub message: Option<String>,
+    pub status: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct NoteEvent {
+    pub object_kind: Option<String>,
+    pub user: Option<NoteEventUser>,
+    pub project: Option<NoteEventProject>,
+    pub object_attributes: Option<NoteEventObjectAttributes>,
+    pub merge_request: Option<NoteEventMergeRequest>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct NoteEventUser {
+    pub id: Option<i64>,
+    pub name: Option<String>,
+    pub username: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct NoteEventProject {
+    pub id: Option<i64>,
+    pub name: Option<String>,
+    pub path_with_namespace: Option<String>,
+    pub web_url: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct NoteEventObjectAttributes {
+    pub id: Option<i64>,
+    pub note: Option<String>,
+    pub noteable_type: Option<String>,
+    pub system: Option<bool>,
+    /// `create` or `update`, only sent by newer GitLab versions
+    pub action: Option<String>,
+    pub url: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct NoteEventMergeRequest {
+    pub id: Option<i64>,
+    pub iid: Option<i64>,
+    pub source_branch: Option<String>,
+    pub target_branch: Option<String>,
+    pub title: Option<String>,
+    pub url: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct Note {
+    pub id: Option<i64>,
+    pub body: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct ProjectHook {
+    pub id: Option<i64>,
+    pub url: Option<String>,
+    pub push_events: Option<bool>,
+    pub pipeline_events: Option<bool>,
+    pub job_events: Option<bool>,
+    pub deployment_events: Option<bool>,
+    pub note_events: Option<bool>,
 }
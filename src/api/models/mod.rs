@@ -1,2 +1,77 @@
+// The generated models mirror the full webhook payloads, so not every
+// struct is used by the handlers.
+#[allow(dead_code)]
+pub mod bitbucket;
+#[allow(dead_code)]
+pub mod gitea;
+#[allow(dead_code)]
 pub mod github;
 pub mod gitlab;
+
+use crate::errors::PayloadError;
+
+use log::warn;
+use serde::de::DeserializeOwned;
+use serde_path_to_error::Segment;
+
+/// The JSON pointer of a path, ex: `/pull_request/labels/0/name`
+fn json_pointer(path: &serde_path_to_error::Path) -> String {
+    path.iter()
+        .filter_map(|segment| match segment {
+            Segment::Seq { index } => Some(index.to_string()),
+            Segment::Map { key } => Some(key.replace('~', "~0").replace('/', "~1")),
+            Segment::Enum { variant } => Some(variant.clone()),
+            Segment::Unknown => None,
+        })
+        .map(|token| format!("/{}", token))
+        .collect()
+}
+
+/// Parses a webhook payload, telling which value failed if it can't be
+pub fn parse<T: DeserializeOwned>(body: &str) -> Result<T, PayloadError> {
+    let deserializer = &mut serde_json::Deserializer::from_str(body);
+    serde_path_to_error::deserialize(deserializer).map_err(|err| {
+        let error = PayloadError {
+            pointer: json_pointer(err.path()),
+            message: err.inner().to_string(),
+        };
+        warn!(
+            "Unable to parse {} payload at {:?}: {}",
+            std::any::type_name::<T>(),
+            error.pointer,
+            error.message
+        );
+        error
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::testing::read_testdata_to_string;
+
+    #[test]
+    fn test_parse() {
+        let body = read_testdata_to_string("github_open_pr_forked.json");
+        let mut event: serde_json::Value = serde_json::from_str(&body).unwrap();
+        assert!(parse::<github::PullRequest>(&body).is_ok());
+
+        event["pull_request"]["head"]["sha"] = serde_json::json!({ "oid": "a91957a8" });
+        let err = parse::<github::PullRequest>(&event.to_string()).unwrap_err();
+        assert_eq!(err.pointer, "/pull_request/head/sha");
+        assert!(
+            err.message.starts_with("invalid type: map"),
+            "{}",
+            err.message
+        );
+
+        // fields the handlers don't need may be missing
+        event["pull_request"]["head"]["sha"] = "a91957a8".into();
+        event["pull_request"].as_object_mut().unwrap().remove("url");
+        event.as_object_mut().unwrap().remove("sender");
+        assert!(parse::<github::PullRequest>(&event.to_string()).is_ok());
+
+        let err = parse::<github::PullRequest>("[]").unwrap_err();
+        assert_eq!(err.pointer, "");
+    }
+}
@@ -0,0 +1,155 @@
+//! Paging through listings of the GitHub and GitLab APIs. Both send a `Link`
+//! header pointing at the next page, and both take `page` and `per_page`
+//! parameters.
+use crate::errors::GitError;
+
+use log::warn;
+use std::future::Future;
+
+/// Items requested per page, the most both APIs allow
+pub const PER_PAGE: i64 = 100;
+/// Pages fetched at most by default, so a runaway listing can't loop forever
+pub const MAX_PAGES: i64 = 100;
+
+/// One page of a listing
+#[derive(Debug)]
+pub struct Page<T> {
+    pub items: Vec<T>,
+    pub has_next: bool,
+}
+
+impl<T> Page<T> {
+    /// A page of `items`, which has a next page if the `Link` header says so.
+    /// Without the header, a full page may have one.
+    pub fn new(items: Vec<T>, headers: &reqwest::header::HeaderMap, per_page: i64) -> Self {
+        let has_next = match headers
+            .get(reqwest::header::LINK)
+            .and_then(|link| link.to_str().ok())
+        {
+            Some(link) => links_next(link),
+            None => items.len() as i64 >= per_page,
+        };
+        Page { items, has_next }
+    }
+}
+
+/// Whether a `Link` header has a `rel="next"` link
+fn links_next(link: &str) -> bool {
+    link.split(',').any(|link| {
+        link.split(';')
+            .skip(1)
+            .any(|param| matches!(param.trim(), "rel=\"next\"" | "rel=next"))
+    })
+}
+
+/// Fetches the pages of a listing, starting from page 1, until the last one
+/// or `max_pages` of them
+pub async fn paginate<T, F, Fut>(max_pages: i64, mut fetch: F) -> Result<Vec<T>, GitError>
+where
+    F: FnMut(i64) -> Fut,
+    Fut: Future<Output = Result<Page<T>, GitError>>,
+{
+    let mut items = vec![];
+    for page in 1..=max_pages {
+        let Page {
+            items: page_items,
+            has_next,
+        } = fetch(page).await?;
+        items.extend(page_items);
+        if !has_next {
+            return Ok(items);
+        }
+    }
+    warn!(
+        "Listing has more than {} pages, ignoring the rest",
+        max_pages
+    );
+    Ok(items)
+}
+
+/// Fetches the pages of a listing until an item matches `predicate`, and
+/// returns that item, without fetching the pages after it
+pub async fn find<T, F, Fut, P>(
+    max_pages: i64,
+    mut fetch: F,
+    mut predicate: P,
+) -> Result<Option<T>, GitError>
+where
+    F: FnMut(i64) -> Fut,
+    Fut: Future<Output = Result<Page<T>, GitError>>,
+    P: FnMut(&T) -> bool,
+{
+    for page in 1..=max_pages {
+        let Page { items, has_next } = fetch(page).await?;
+        if let Some(item) = items.into_iter().find(|item| predicate(item)) {
+            return Ok(Some(item));
+        }
+        if !has_next {
+            return Ok(None);
+        }
+    }
+    warn!(
+        "Listing has more than {} pages, ignoring the rest",
+        max_pages
+    );
+    Ok(None)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use reqwest::header::{HeaderMap, HeaderValue, LINK};
+
+    fn link(value: &'static str) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert(LINK, HeaderValue::from_static(value));
+        headers
+    }
+
+    #[test]
+    fn test_page() {
+        let next = link(
+            "<https://api.github.com/repositories/1/pulls?page=3>; rel=\"next\", \
+             <https://api.github.com/repositories/1/pulls?page=5>; rel=\"last\"",
+        );
+        assert!(Page::new(vec![1], &next, 100).has_next);
+        let last = link("<https://gitlab.com/api/v4/projects/1/pipelines?page=1>; rel=\"first\"");
+        assert!(!Page::new(vec![1; 100], &last, 100).has_next);
+        // without the header, a full page may have a next one
+        assert!(Page::new(vec![1, 2], &HeaderMap::new(), 2).has_next);
+        assert!(!Page::new(vec![1], &HeaderMap::new(), 2).has_next);
+    }
+
+    async fn fetch(page: i64) -> Result<Page<i64>, GitError> {
+        Ok(Page {
+            items: vec![page * 10, page * 10 + 1],
+            has_next: page < 3,
+        })
+    }
+
+    #[tokio::test]
+    async fn test_paginate() {
+        assert_eq!(
+            paginate(MAX_PAGES, fetch).await.unwrap(),
+            vec![10, 11, 20, 21, 30, 31]
+        );
+        assert_eq!(paginate(2, fetch).await.unwrap(), vec![10, 11, 20, 21]);
+        let mut fetched = vec![];
+        let found = find(
+            MAX_PAGES,
+            |page| {
+                fetched.push(page);
+                fetch(page)
+            },
+            |item| *item == 21,
+        )
+        .await
+        .unwrap();
+        assert_eq!(found, Some(21));
+        assert_eq!(fetched, vec![1, 2]);
+        assert_eq!(
+            find(MAX_PAGES, fetch, |item| *item == 40).await.unwrap(),
+            None
+        );
+    }
+}
@@ -0,0 +1,36 @@
+use crate::errors::GitError;
+
+use log::info;
+use std::future::Future;
+use std::time::Duration;
+
+const MAX_ATTEMPTS: u32 = 5;
+const INITIAL_DELAY: Duration = Duration::from_millis(500);
+
+/// Retries a lookup that returns `Ok(None)` while GitLab hasn't caught up
+/// yet (ex: a branch which was pushed a moment ago), backing off between
+/// attempts. Errors are returned right away.
+pub async fn retry_until_found<T, F, Fut>(what: &str, mut f: F) -> Result<T, GitError>
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Result<Option<T>, GitError>>,
+{
+    let mut delay = INITIAL_DELAY;
+    for attempt in 1..=MAX_ATTEMPTS {
+        if let Some(found) = f().await? {
+            return Ok(found);
+        }
+        if attempt < MAX_ATTEMPTS {
+            info!(
+                "{} not found yet (attempt {}/{}), retrying in {:?}",
+                what, attempt, MAX_ATTEMPTS, delay
+            );
+            tokio::time::sleep(delay).await;
+            delay *= 2;
+        }
+    }
+    Err(GitError::Other(format!(
+        "{} not found after {} attempts",
+        what, MAX_ATTEMPTS
+    )))
+}
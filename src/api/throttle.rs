@@ -0,0 +1,462 @@
+use crate::config;
+use crate::secrets;
+
+use log::{debug, warn};
+use reqwest::ResponseBuilderExt;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::sync::Semaphore;
+
+/// Rate limit state reported by an upstream's response headers
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+struct RateLimit {
+    remaining: Option<u64>,
+    /// When the quota resets, as a UNIX timestamp
+    reset: Option<u64>,
+}
+
+/// Caps concurrent requests to an upstream and holds new requests back when
+/// its rate limit is close to exhausted, until the quota resets.
+pub struct Throttle {
+    name: &'static str,
+    semaphore: Semaphore,
+    min_remaining: u64,
+    max_retries: u32,
+    max_retry_wait: Duration,
+    rate_limit: Mutex<RateLimit>,
+    /// Number of responses which were rate limited
+    rate_limited: AtomicU64,
+}
+
+lazy_static! {
+    pub static ref GITHUB: Throttle = Throttle::new("GitHub", &config::CONFIG.limits.github);
+    pub static ref GITLAB: Throttle = Throttle::new("GitLab", &config::CONFIG.limits.gitlab);
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+/// How long to wait before sending, if the remaining quota is at or below
+/// `min_remaining` and the reset time is still ahead.
+fn wait_for(rate_limit: RateLimit, min_remaining: u64, now: u64) -> Option<Duration> {
+    match (rate_limit.remaining, rate_limit.reset) {
+        (Some(remaining), Some(reset)) if remaining <= min_remaining && reset > now => {
+            Some(Duration::from_secs(reset - now))
+        }
+        _ => None,
+    }
+}
+
+/// How long to back off before retrying a response, if it was rate limited:
+/// a 429, or a 403 of GitHub's primary or secondary (abuse) rate limits. Those
+/// tell how long to wait with `Retry-After`, or the quota's reset time.
+/// Secondary limits without either, which only their message tells apart
+/// from permission errors (`secondary`), are waited out for a minute, as
+/// GitHub recommends.
+fn backoff(
+    status: reqwest::StatusCode,
+    headers: &reqwest::header::HeaderMap,
+    secondary: bool,
+    now: u64,
+) -> Option<Duration> {
+    if status != reqwest::StatusCode::TOO_MANY_REQUESTS && status != reqwest::StatusCode::FORBIDDEN
+    {
+        return None;
+    }
+    let retry_after = headers
+        .get(reqwest::header::RETRY_AFTER)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.trim().parse::<u64>().ok());
+    if let Some(retry_after) = retry_after {
+        return Some(Duration::from_secs(retry_after));
+    }
+    match parse_rate_limit(headers) {
+        Some(RateLimit {
+            remaining: Some(0),
+            reset: Some(reset),
+        }) => Some(Duration::from_secs(reset.saturating_sub(now))),
+        // other 403s are permission errors
+        _ if status == reqwest::StatusCode::TOO_MANY_REQUESTS || secondary => {
+            Some(Duration::from_secs(60))
+        }
+        _ => None,
+    }
+}
+
+/// How long to back off before retrying a response, see [`backoff`]. The
+/// body of a 403 whose headers don't tell is read to look for a secondary
+/// rate limit, so the response is rebuilt with it.
+async fn rate_limited_for(
+    res: reqwest::Response,
+) -> Result<(reqwest::Response, Option<Duration>), reqwest::Error> {
+    let wait = backoff(res.status(), res.headers(), false, now());
+    if wait.is_some() || res.status() != reqwest::StatusCode::FORBIDDEN {
+        return Ok((res, wait));
+    }
+    let mut builder = http::Response::builder()
+        .status(res.status())
+        .version(res.version())
+        .url(res.url().clone());
+    for (name, value) in res.headers() {
+        builder = builder.header(name, value);
+    }
+    let body = res.bytes().await?;
+    let secondary = String::from_utf8_lossy(&body)
+        .to_ascii_lowercase()
+        .contains("secondary rate limit");
+    let res = reqwest::Response::from(builder.body(body).unwrap());
+    let wait = backoff(res.status(), res.headers(), secondary, now());
+    Ok((res, wait))
+}
+
+/// Reads GitHub's `X-RateLimit-*` or GitLab's `RateLimit-*` headers
+fn parse_rate_limit(headers: &reqwest::header::HeaderMap) -> Option<RateLimit> {
+    let get = |names: &[&str]| {
+        names
+            .iter()
+            .filter_map(|name| headers.get(*name))
+            .filter_map(|v| v.to_str().ok())
+            .find_map(|v| v.parse::<u64>().ok())
+    };
+    let remaining = get(&["x-ratelimit-remaining", "ratelimit-remaining"]);
+    let reset = get(&["x-ratelimit-reset", "ratelimit-reset"]);
+    remaining.map(|remaining| RateLimit {
+        remaining: Some(remaining),
+        reset,
+    })
+}
+
+impl Throttle {
+    fn new(name: &'static str, limits: &config::UpstreamLimits) -> Throttle {
+        Throttle {
+            name,
+            semaphore: Semaphore::new(limits.max_concurrent),
+            min_remaining: limits.min_remaining,
+            max_retries: limits.max_retries,
+            max_retry_wait: Duration::from_secs(limits.max_retry_wait_secs),
+            rate_limit: Mutex::new(RateLimit::default()),
+            rate_limited: AtomicU64::new(0),
+        }
+    }
+
+    /// Returns the last known remaining quota
+    pub fn remaining(&self) -> Option<u64> {
+        self.rate_limit.lock().unwrap().remaining
+    }
+
+    /// Returns the number of responses which were rate limited
+    pub fn rate_limited(&self) -> u64 {
+        self.rate_limited.load(Ordering::Relaxed)
+    }
+
+    /// Holds all requests back for `wait`, as they'd be rate limited too,
+    /// see [`Throttle::release`]
+    fn hold(&self, wait: Duration) {
+        *self.rate_limit.lock().unwrap() = RateLimit {
+            remaining: Some(0),
+            // rounded up, so the wait is never cut short
+            reset: Some(now() + wait.as_secs() + u64::from(wait.subsec_nanos() > 0)),
+        };
+    }
+
+    /// Stops holding requests back, once a request gave up waiting, so the
+    /// others aren't held for a limit which may lift sooner for them, ex:
+    /// a secondary limit
+    fn release(&self) {
+        *self.rate_limit.lock().unwrap() = RateLimit::default();
+    }
+
+    fn update(&self, headers: &reqwest::header::HeaderMap) {
+        if let Some(rate_limit) = parse_rate_limit(headers) {
+            debug!("{} rate limit: {:?}", self.name, rate_limit);
+            *self.rate_limit.lock().unwrap() = rate_limit;
+        }
+    }
+
+    /// Sends a request. If it's rate limited, all requests are held back
+    /// until the limit lifts and it's retried, up to `max_retries` times.
+    /// If it's rejected as unauthorized, the secrets are read again, and
+    /// it's retried once if the token it was sent with was rotated.
+    pub async fn send(
+        &self,
+        request: reqwest::RequestBuilder,
+    ) -> Result<reqwest::Response, reqwest::Error> {
+        let mut retry = request.try_clone();
+        let mut res = self.send_once(request).await?;
+        let mut retries = 0;
+        loop {
+            let (limited, wait) = rate_limited_for(res).await?;
+            res = limited;
+            let wait = match wait {
+                Some(wait) => wait,
+                None => break,
+            };
+            self.rate_limited.fetch_add(1, Ordering::Relaxed);
+            let request = match retry.take() {
+                Some(request) if retries < self.max_retries && wait <= self.max_retry_wait => {
+                    request
+                }
+                _ => {
+                    warn!(
+                        "{} rate limit exceeded, giving up after {} retries, it lifts in {:?}",
+                        self.name, retries, wait
+                    );
+                    self.release();
+                    return res.error_for_status();
+                }
+            };
+            warn!("{} rate limit exceeded, retrying in {:?}", self.name, wait);
+            self.hold(wait);
+            retries += 1;
+            retry = request.try_clone();
+            res = self.send_once(request).await?;
+        }
+        if res.status() != reqwest::StatusCode::UNAUTHORIZED {
+            return Ok(res);
+        }
+        secrets::refresh_after_unauthorized().await;
+        match retry.and_then(with_rotated_secrets) {
+            Some(retry) => {
+                warn!(
+                    "{} rejected a request with a rotated token, retrying with the new one",
+                    self.name
+                );
+                self.send_once(retry).await
+            }
+            None => Ok(res),
+        }
+    }
+
+    async fn send_once(
+        &self,
+        request: reqwest::RequestBuilder,
+    ) -> Result<reqwest::Response, reqwest::Error> {
+        let _permit = self.semaphore.acquire().await.unwrap();
+        let wait = wait_for(*self.rate_limit.lock().unwrap(), self.min_remaining, now());
+        if let Some(wait) = wait {
+            warn!(
+                "{} rate limit nearly exhausted, holding request for {:?}",
+                self.name, wait
+            );
+            tokio::time::sleep(wait).await;
+        }
+        let res = request.send().await?;
+        self.update(res.headers());
+        Ok(res)
+    }
+}
+
+/// The request with the rotated secrets in its headers replaced by their
+/// current values, if it has any
+fn with_rotated_secrets(request: reqwest::RequestBuilder) -> Option<reqwest::RequestBuilder> {
+    let built = request.try_clone()?.build().ok()?;
+    let mut rotated = reqwest::header::HeaderMap::new();
+    for (name, value) in built.headers() {
+        if let Some(value) = value.to_str().ok().and_then(secrets::replace_rotated) {
+            rotated.insert(name.clone(), value.parse().ok()?);
+        }
+    }
+    if rotated.is_empty() {
+        None
+    } else {
+        Some(request.headers(rotated))
+    }
+}
+
+/// Sends a request through an upstream's [`Throttle`]
+#[allow(async_fn_in_trait)]
+pub trait ThrottledSend {
+    async fn send_throttled(self, throttle: &Throttle)
+        -> Result<reqwest::Response, reqwest::Error>;
+}
+
+impl ThrottledSend for reqwest::RequestBuilder {
+    async fn send_throttled(
+        self,
+        throttle: &Throttle,
+    ) -> Result<reqwest::Response, reqwest::Error> {
+        throttle.send(self).await
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_wait_for() {
+        let rate_limit = RateLimit {
+            remaining: Some(10),
+            reset: Some(1_100),
+        };
+        assert_eq!(wait_for(rate_limit, 5, 1_000), None);
+        assert_eq!(
+            wait_for(rate_limit, 10, 1_000),
+            Some(Duration::from_secs(100))
+        );
+        // the quota was already reset
+        assert_eq!(wait_for(rate_limit, 10, 1_200), None);
+        assert_eq!(wait_for(RateLimit::default(), 10, 1_000), None);
+    }
+
+    #[test]
+    fn test_backoff() {
+        use reqwest::StatusCode;
+
+        let mut headers = reqwest::header::HeaderMap::new();
+        assert_eq!(backoff(StatusCode::OK, &headers, false, 1_000), None);
+        // a permission error
+        assert_eq!(backoff(StatusCode::FORBIDDEN, &headers, false, 1_000), None);
+        assert_eq!(
+            backoff(StatusCode::TOO_MANY_REQUESTS, &headers, false, 1_000),
+            Some(Duration::from_secs(60))
+        );
+
+        // the primary rate limit
+        headers.insert("x-ratelimit-remaining", "0".parse().unwrap());
+        headers.insert("x-ratelimit-reset", "1030".parse().unwrap());
+        assert_eq!(
+            backoff(StatusCode::FORBIDDEN, &headers, false, 1_000),
+            Some(Duration::from_secs(30))
+        );
+        assert_eq!(
+            backoff(StatusCode::FORBIDDEN, &headers, false, 2_000),
+            Some(Duration::ZERO)
+        );
+        assert_eq!(backoff(StatusCode::NOT_FOUND, &headers, false, 1_000), None);
+
+        // a secondary rate limit
+        let mut headers = reqwest::header::HeaderMap::new();
+        headers.insert("x-ratelimit-remaining", "4000".parse().unwrap());
+        headers.insert("retry-after", "45".parse().unwrap());
+        assert_eq!(
+            backoff(StatusCode::FORBIDDEN, &headers, false, 1_000),
+            Some(Duration::from_secs(45))
+        );
+        // without Retry-After, only its message tells
+        headers.remove("retry-after");
+        assert_eq!(backoff(StatusCode::FORBIDDEN, &headers, false, 1_000), None);
+        assert_eq!(
+            backoff(StatusCode::FORBIDDEN, &headers, true, 1_000),
+            Some(Duration::from_secs(60))
+        );
+    }
+
+    #[tokio::test]
+    async fn test_send_retries_rate_limited() {
+        use wiremock::matchers::method;
+        use wiremock::{Mock, MockServer, ResponseTemplate};
+
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .respond_with(ResponseTemplate::new(403).insert_header("retry-after", "0"))
+            .up_to_n_times(1)
+            .mount(&server)
+            .await;
+        Mock::given(method("GET"))
+            .respond_with(ResponseTemplate::new(200).insert_header("x-ratelimit-remaining", "99"))
+            .mount(&server)
+            .await;
+
+        let throttle = Throttle::new("test", &config::UpstreamLimits::default());
+        let client = reqwest::Client::new();
+        let res = throttle.send(client.get(server.uri())).await.unwrap();
+        assert_eq!(res.status(), reqwest::StatusCode::OK);
+        assert_eq!(throttle.rate_limited(), 1);
+        assert_eq!(throttle.remaining(), Some(99));
+
+        // it gives up once the limit lifts too late
+        Mock::given(method("POST"))
+            .respond_with(ResponseTemplate::new(429).insert_header("retry-after", "3600"))
+            .expect(1)
+            .mount(&server)
+            .await;
+        let err = throttle.send(client.post(server.uri())).await.unwrap_err();
+        assert_eq!(err.status(), Some(reqwest::StatusCode::TOO_MANY_REQUESTS));
+        assert_eq!(throttle.rate_limited(), 2);
+        // and doesn't hold the other requests back meanwhile
+        assert_eq!(throttle.remaining(), None);
+    }
+
+    #[tokio::test]
+    async fn test_send_secondary_rate_limited() {
+        use wiremock::matchers::{method, path};
+        use wiremock::{Mock, MockServer, ResponseTemplate};
+
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/limited"))
+            .respond_with(
+                ResponseTemplate::new(403)
+                    .insert_header("x-ratelimit-remaining", "4000")
+                    .set_body_string(r#"{"message": "You have exceeded a secondary rate limit."}"#),
+            )
+            .expect(1)
+            .mount(&server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path("/forbidden"))
+            .respond_with(
+                ResponseTemplate::new(403)
+                    .set_body_string(r#"{"message": "Must have admin rights"}"#),
+            )
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let limits = config::UpstreamLimits {
+            max_retry_wait_secs: 10,
+            ..Default::default()
+        };
+        let throttle = Throttle::new("test", &limits);
+        let client = reqwest::Client::new();
+        let url = format!("{}/limited", server.uri());
+        let err = throttle.send(client.get(&url)).await.unwrap_err();
+        assert_eq!(err.status(), Some(reqwest::StatusCode::FORBIDDEN));
+        assert_eq!(err.url().map(|url| url.as_str()), Some(url.as_str()));
+        assert_eq!(throttle.rate_limited(), 1);
+        assert_eq!(throttle.remaining(), None);
+
+        // a permission error is answered as it is
+        let res = throttle
+            .send(client.get(format!("{}/forbidden", server.uri())))
+            .await
+            .unwrap();
+        assert_eq!(res.status(), reqwest::StatusCode::FORBIDDEN);
+        assert_eq!(
+            res.text().await.unwrap(),
+            r#"{"message": "Must have admin rights"}"#
+        );
+        assert_eq!(throttle.rate_limited(), 1);
+    }
+
+    #[test]
+    fn test_parse_rate_limit() {
+        let mut headers = reqwest::header::HeaderMap::new();
+        assert_eq!(parse_rate_limit(&headers), None);
+        headers.insert("x-ratelimit-remaining", "42".parse().unwrap());
+        headers.insert("x-ratelimit-reset", "1372700873".parse().unwrap());
+        assert_eq!(
+            parse_rate_limit(&headers),
+            Some(RateLimit {
+                remaining: Some(42),
+                reset: Some(1372700873)
+            })
+        );
+
+        let mut headers = reqwest::header::HeaderMap::new();
+        headers.insert("ratelimit-remaining", "7".parse().unwrap());
+        assert_eq!(
+            parse_rate_limit(&headers),
+            Some(RateLimit {
+                remaining: Some(7),
+                reset: None
+            })
+        );
+    }
+}
@@ -0,0 +1,51 @@
+use crate::errors::GitError;
+
+use log::error;
+
+/// Posts a message to a Slack-compatible incoming webhook
+pub async fn post_message(client: &reqwest::Client, url: &str, text: &str) -> Result<(), GitError> {
+    let res = client
+        .post(url)
+        .json(&serde_json::json!({ "text": text }))
+        .send()
+        .await?;
+
+    if !res.status().is_success() {
+        // the URL has the webhook's secret in it, so it isn't logged
+        let err = GitError::api_status("Error posting to notification webhook", res).await;
+        error!("{}", err);
+        return Err(err);
+    }
+    Ok(())
+}
+
+/// Posts a LabHub event to an event webhook, with the body's signature if
+/// the webhook has a secret
+pub async fn post_event(
+    client: &reqwest::Client,
+    url: &str,
+    event: &str,
+    signature: Option<&str>,
+    body: String,
+) -> Result<(), GitError> {
+    let mut request = client
+        .post(url)
+        .header(reqwest::header::CONTENT_TYPE, "application/json")
+        .header("X-LabHub-Event", event)
+        .body(body);
+    if let Some(signature) = signature {
+        request = request.header("X-LabHub-Signature-256", signature);
+    }
+    let res = request.send().await?;
+
+    if !res.status().is_success() {
+        let err = GitError::api_status(
+            format!("Error posting {} to event webhook {}", event, url),
+            res,
+        )
+        .await;
+        error!("{}", err);
+        return Err(err);
+    }
+    Ok(())
+}
@@ -0,0 +1,113 @@
+//! Artifact links: when a pipeline succeeds, its PR gets download links for
+//! the artifacts of each job, so reviewers can grab build outputs without
+//! browsing GitLab.
+use crate::api::models::gitlab;
+use crate::config;
+use crate::messages::{self, Message};
+
+fn format_size(bytes: i64) -> String {
+    const UNITS: [&str; 4] = ["KiB", "MiB", "GiB", "TiB"];
+    if bytes < 1024 {
+        return format!("{} B", bytes);
+    }
+    let mut size = bytes as f64 / 1024.0;
+    let mut unit = 0;
+    while size >= 1024.0 && unit < UNITS.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+    format!("{:.1} {}", size, UNITS[unit])
+}
+
+/// Renders the artifact links of a pipeline's `jobs`, or `None` if none of
+/// them have artifacts. `marker` is appended so the comment is refreshed by
+/// the next pipeline rather than treated as stale.
+pub fn comment_body(
+    pipeline_id: i64,
+    sha: &str,
+    jobs: &[gitlab::Job],
+    marker: &str,
+    language: config::Language,
+) -> Option<String> {
+    let lines: Vec<String> = jobs
+        .iter()
+        .filter_map(|job| {
+            let web_url = job.web_url.as_deref()?;
+            let archive = job
+                .artifacts
+                .iter()
+                .flatten()
+                .find(|artifact| artifact.file_type.as_deref() == Some("archive"))?;
+            let mut line = format!(
+                "- `{}`: [{}]({}/artifacts/download)",
+                job.name.as_deref().unwrap_or_default(),
+                archive.filename.as_deref().unwrap_or("artifacts.zip"),
+                web_url
+            );
+            let mut details = vec![];
+            if let Some(size) = archive.size {
+                details.push(format_size(size));
+            }
+            if let Some(expire_at) = job.artifacts_expire_at.as_deref() {
+                details.push(messages::text(
+                    language,
+                    Message::ArtifactsExpire { date: expire_at },
+                ));
+            }
+            if !details.is_empty() {
+                line.push_str(&format!(" ({})", details.join(", ")));
+            }
+            Some(line)
+        })
+        .collect();
+    if lines.is_empty() {
+        return None;
+    }
+    Some(format!(
+        "{}\n\n{}\n\n{}",
+        messages::text(language, Message::Artifacts { pipeline_id, sha }),
+        lines.join("\n"),
+        marker
+    ))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::testing::read_testdata_to_string;
+
+    #[test]
+    fn test_comment_body() {
+        let jobs: Vec<gitlab::Job> =
+            serde_json::from_str(&read_testdata_to_string("gitlab_pipeline_jobs.json")).unwrap();
+        let sha = "a91957a858320c0e17f3a0eca7cfacbff50ea29a";
+        assert_eq!(
+            comment_body(
+                2366,
+                sha,
+                &jobs,
+                "<!-- labhub:artifacts -->",
+                config::Language::En
+            )
+            .unwrap(),
+            "📦 Artifacts of pipeline 2366 for `a91957a8`:\n\
+             \n\
+             - `compile`: [artifacts.zip](https://gitlab.example.com/gitlab-org/gitlab-test/-/jobs/1975/artifacts/download) \
+             (1.5 MiB, expires 2023-03-15T10:01:02.000Z)\n\
+             - `docs`: [docs.zip](https://gitlab.example.com/gitlab-org/gitlab-test/-/jobs/1979/artifacts/download) (512 B)\n\
+             \n\
+             <!-- labhub:artifacts -->"
+        );
+        assert_eq!(
+            comment_body(2366, sha, &jobs[2..], "", config::Language::En),
+            None
+        );
+    }
+
+    #[test]
+    fn test_format_size() {
+        assert_eq!(format_size(1000), "1000 B");
+        assert_eq!(format_size(2048), "2.0 KiB");
+        assert_eq!(format_size(5 * 1024 * 1024 * 1024), "5.0 GiB");
+    }
+}
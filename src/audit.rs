@@ -0,0 +1,87 @@
+//! An append-only audit log of every change LabHub makes on the forges (ref
+//! pushes and deletions, pipeline retries, comments, merge requests), with
+//! who caused it and whether it succeeded. Unlike [`crate::history`],
+//! failures are recorded too, and entries can't be changed or removed.
+use crate::errors::GitError;
+use crate::repo_name;
+use crate::state;
+
+use log::error;
+
+/// The actor of changes LabHub makes on its own, rather than on behalf of
+/// some user, like cleaning up stale branches
+pub const LABHUB: &str = "labhub";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Action {
+    /// A PR head was pushed to its GitLab branch
+    RefPush,
+    /// A GitLab branch was deleted
+    RefDeletion,
+    /// A pipeline or job was retried
+    PipelineRetry,
+    /// A pipeline was canceled
+    PipelineCancel,
+    /// A comment was posted or refreshed
+    Comment,
+    /// A GitLab merge request was opened or updated
+    MergeRequest,
+}
+
+impl Action {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Action::RefPush => "ref_push",
+            Action::RefDeletion => "ref_deletion",
+            Action::PipelineRetry => "pipeline_retry",
+            Action::PipelineCancel => "pipeline_cancel",
+            Action::Comment => "comment",
+            Action::MergeRequest => "merge_request",
+        }
+    }
+}
+
+/// Records an action and its outcome. `repo` is the GitHub repo when the
+/// action is on behalf of a PR, the GitLab project otherwise. Failing to
+/// record it doesn't fail the action.
+pub fn record<T>(
+    actor: &str,
+    repo: &str,
+    pr_number: Option<i64>,
+    action: Action,
+    target: &str,
+    outcome: &Result<T, GitError>,
+) {
+    let repo = repo_name::lookup_key(repo);
+    let entry = state::AuditEntry {
+        id: 0,
+        at: 0,
+        actor: actor.to_string(),
+        repo: repo.clone(),
+        pr_number,
+        action: action.name().to_string(),
+        target: target.to_string(),
+        outcome: if outcome.is_ok() {
+            "success"
+        } else {
+            "failure"
+        }
+        .to_string(),
+        error: outcome.as_ref().err().map(|err| err.to_string()),
+    };
+    if let Err(err) = state::record_audit_entry(entry) {
+        error!(
+            "Error recording {} of {} on {} in the audit log: {:?}",
+            action.name(),
+            target,
+            repo,
+            err
+        );
+    }
+}
+
+/// Audit log entries matching `filter`, newest first
+pub fn query(mut filter: state::AuditFilter) -> Result<Vec<state::AuditEntry>, GitError> {
+    filter.repo = filter.repo.map(|repo| repo_name::lookup_key(&repo));
+    state::audit_entries(&filter)
+}
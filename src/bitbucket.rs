@@ -0,0 +1,38 @@
+//! Bitbucket Cloud webhook handling.
+use crate::api::models::{self, bitbucket};
+use crate::config;
+use crate::errors::RequestErrorResult;
+use crate::forge::{BitbucketPullRequest, ForgePullRequest};
+use crate::sync;
+
+use log::info;
+
+/// Handles the body of a Bitbucket Cloud webhook whose signature has already
+/// been checked, where `event_key` is the `X-Event-Key` header. PRs are
+/// synced by the same engine as GitHub PRs.
+pub async fn handle_event_body(event_key: &str, body: &str) -> Result<String, RequestErrorResult> {
+    match event_key {
+        "pullrequest:created"
+        | "pullrequest:updated"
+        | "pullrequest:fulfilled"
+        | "pullrequest:rejected" => {
+            if config::feature_enabled(&config::Feature::ExternalPr) {
+                let event: bitbucket::PullRequestEvent = models::parse(body)?;
+                let pr = BitbucketPullRequest::new(event_key, event);
+                if config::action_enabled(pr.action()) {
+                    info!("Bitbucket PullRequest action={}", pr.action());
+                    sync::handle_pr(Box::new(pr))?;
+                } else {
+                    info!("Event trigger action not enabled. Skipping event.");
+                }
+            } else {
+                info!("ExternalPr feature not enabled. Skipping event.");
+            }
+            Ok(String::from("Thanks buddy bro 😍"))
+        }
+        _ => Ok(format!(
+            "Unhandled event_key={}, doing nothing 😀",
+            event_key,
+        )),
+    }
+}
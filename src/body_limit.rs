@@ -0,0 +1,133 @@
+//! Limits on the size of request bodies, per route, from `[server.body_limits]`.
+//! Requests announcing a larger body with `Content-Length` are turned away
+//! before any of it is read, and webhook bodies are read in a single pass,
+//! accounted for in the metrics by route.
+use crate::errors::{GitError, RequestErrorResult};
+use crate::metrics;
+
+use axum::{
+    body::{Body, Bytes},
+    http::{header, Request},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use futures::{Stream, StreamExt};
+use log::{debug, info};
+use std::fmt::Display;
+
+/// The `Content-Length` of a request, if it has a valid one
+fn content_length<B>(request: &Request<B>) -> Option<u64> {
+    request
+        .headers()
+        .get(header::CONTENT_LENGTH)?
+        .to_str()
+        .ok()?
+        .parse()
+        .ok()
+}
+
+/// Middleware answering requests whose `Content-Length` is over `limit`
+/// with a 413, without reading their body. Bodies without one, or which
+/// lie about it, are still limited as they're read.
+pub async fn reject_oversized(
+    route: &'static str,
+    limit: usize,
+    request: Request<Body>,
+    next: Next<Body>,
+) -> Response {
+    match content_length(&request) {
+        Some(length) if length > limit as u64 => {
+            info!(
+                "Rejecting a {} byte body to {}, over the limit of {}",
+                length,
+                request.uri().path(),
+                limit
+            );
+            metrics::record_oversized_body(route);
+            RequestErrorResult::from(GitError::PayloadTooLarge { limit }).into_response()
+        }
+        _ => next.run(request).await,
+    }
+}
+
+/// Reads a webhook body of at most `limit` bytes, passing each chunk to
+/// `on_chunk` as it arrives, ex: to verify its signature. The body is
+/// only buffered once, in a string of its `content_length`.
+pub async fn read<S, E>(
+    route: &'static str,
+    mut body: S,
+    content_length: Option<u64>,
+    limit: usize,
+    mut on_chunk: impl FnMut(&[u8]),
+) -> Result<String, GitError>
+where
+    S: Stream<Item = Result<Bytes, E>> + Unpin,
+    E: Display,
+{
+    let capacity = content_length.map_or(0, |length| length.min(limit as u64) as usize);
+    let mut bytes = Vec::with_capacity(capacity);
+    while let Some(chunk) = body.next().await {
+        let chunk = chunk.map_err(|err| {
+            debug!("Unable to read body: {}", err);
+            GitError::BadRequest(format!("Unable to read the body: {}", err))
+        })?;
+        if bytes.len() + chunk.len() > limit {
+            metrics::record_oversized_body(route);
+            return Err(GitError::PayloadTooLarge { limit });
+        }
+        on_chunk(&chunk);
+        bytes.extend_from_slice(&chunk);
+    }
+    metrics::record_body(route, bytes.len());
+    String::from_utf8(bytes)
+        .map_err(|err| GitError::BadRequest(format!("The body isn't UTF-8: {}", err)))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use futures::stream;
+
+    fn chunks(chunks: &[&'static str]) -> impl Stream<Item = Result<Bytes, String>> + Unpin {
+        stream::iter(
+            chunks
+                .iter()
+                .map(|chunk| Ok(Bytes::from_static(chunk.as_bytes())))
+                .collect::<Vec<_>>(),
+        )
+    }
+
+    #[tokio::test]
+    async fn test_read() {
+        let mut seen = vec![];
+        let body = read("test", chunks(&["{\"a\"", ": 1}"]), Some(8), 8, |chunk| {
+            seen.extend_from_slice(chunk)
+        })
+        .await
+        .unwrap();
+        assert_eq!(body, "{\"a\": 1}");
+        assert_eq!(seen, b"{\"a\": 1}");
+
+        let err = read("test", chunks(&["{\"a\"", ": 1}"]), None, 7, |_| {})
+            .await
+            .unwrap_err();
+        assert!(matches!(err, GitError::PayloadTooLarge { limit: 7 }));
+
+        let broken = stream::iter(vec![Err::<Bytes, _>("reset".to_string())]);
+        assert!(read("test", broken, None, 7, |_| {}).await.is_err());
+    }
+
+    #[test]
+    fn test_content_length() {
+        let request = Request::builder()
+            .header(header::CONTENT_LENGTH, "42")
+            .body(())
+            .unwrap();
+        assert_eq!(content_length(&request), Some(42));
+        let request = Request::builder()
+            .header(header::CONTENT_LENGTH, "lots")
+            .body(())
+            .unwrap();
+        assert_eq!(content_length(&request), None);
+    }
+}
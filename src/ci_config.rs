@@ -0,0 +1,70 @@
+//! Pinned CI config: fork PRs pushed with a pipeline profile that has a
+//! `ci_config` get their `.gitlab-ci.yml` replaced by that trusted version,
+//! in a generated commit on top of the PR's head, so contributors can't
+//! change the pipeline definition which runs with the project's secrets.
+//! Files the pinned config runs from the repo still come from the PR.
+use crate::config;
+use crate::errors::GitError;
+
+/// Path of the CI config GitLab runs, relative to the repo's root
+pub const CI_CONFIG_PATH: &str = ".gitlab-ci.yml";
+
+const COMMIT_TITLE_PREFIX: &str = "Pin CI config for ";
+
+/// Title of the commit pinning the CI config of `source_sha`
+pub fn commit_title(source_sha: &str) -> String {
+    format!("{}{}", COMMIT_TITLE_PREFIX, source_sha)
+}
+
+pub fn commit_message(source_sha: &str) -> String {
+    format!(
+        "{}\n\nGenerated by LabHub so CI runs the trusted {} rather than the PR's.\n",
+        commit_title(source_sha),
+        CI_CONFIG_PATH
+    )
+}
+
+/// Returns the PR head a generated commit was made for, from its title
+pub fn source_sha(commit_title: &str) -> Option<&str> {
+    commit_title.strip_prefix(COMMIT_TITLE_PREFIX)
+}
+
+/// Reads the pinned CI config of a pipeline profile, if fork PRs pushed
+/// with it get one
+pub fn pinned(
+    profile: &config::PipelineProfile,
+    is_fork: bool,
+) -> Result<Option<Vec<u8>>, GitError> {
+    match profile.ci_config.as_deref() {
+        Some(path) if is_fork => std::fs::read(path).map(Some).map_err(|err| {
+            GitError::Other(format!(
+                "Error reading the pinned CI config {}: {}",
+                path, err
+            ))
+        }),
+        _ => Ok(None),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_pinned() {
+        let sha = "a91957a858320c0e17f3a0eca7cfacbff50ea29a";
+        assert_eq!(source_sha(&commit_title(sha)), Some(sha));
+        assert_eq!(source_sha("Fix typo"), None);
+
+        let mut profile = config::PipelineProfile::default();
+        assert_eq!(pinned(&profile, true).unwrap(), None);
+        profile.ci_config = Some("src/testdata/pinned-gitlab-ci.yml".to_string());
+        assert_eq!(pinned(&profile, false).unwrap(), None);
+        assert_eq!(
+            pinned(&profile, true).unwrap().as_deref(),
+            Some("test:\n  script: make test\n".as_bytes())
+        );
+        profile.ci_config = Some("src/testdata/missing.yml".to_string());
+        assert!(pinned(&profile, true).is_err());
+    }
+}
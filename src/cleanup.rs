@@ -0,0 +1,250 @@
+//! Deleting the GitLab branches of PRs which are no longer open.
+use crate::api;
+use crate::api::{github_client, gitlab_client};
+use crate::audit;
+use crate::config;
+use crate::errors::GitError;
+use crate::state;
+
+use log::{debug, error, info};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Extracts the PR number from a branch pushed by LabHub, which are named
+/// after the `branch_template` of the pipeline profiles,
+/// `{prefix}-{number}/{head_full_name}/{ref}` by default.
+pub(crate) fn pr_number_from_branch(branch: &str) -> Option<i64> {
+    config::branch_profiles()
+        .iter()
+        .find_map(|profile| profile.pr_number_from_branch(branch))
+}
+
+/// Whether the branch of a PR which is no longer open is due for deletion,
+/// given when it was first seen closed
+fn retention_expired(
+    retention: config::BranchRetention,
+    merged: bool,
+    closed_at: i64,
+    now: i64,
+) -> bool {
+    match retention {
+        config::BranchRetention::Delete => true,
+        config::BranchRetention::KeepMerged => !merged,
+        config::BranchRetention::KeepDays { days } => now - closed_at >= days as i64 * 24 * 60 * 60,
+    }
+}
+
+/// Whether a PR's branch should be deleted: its PR is no longer open, and
+/// the mapping's `branch_retention` doesn't keep it (any longer)
+async fn is_branch_stale(
+    client: &reqwest::Client,
+    github_repo: &str,
+    project: &str,
+    branch: &str,
+    number: i64,
+    retention: config::BranchRetention,
+) -> Result<bool, GitError> {
+    let repo_full_name_parts: Vec<&str> = github_repo.split('/').collect();
+    if repo_full_name_parts.len() != 2 {
+        return Err(GitError::Other(format!(
+            "Invalid repo name {}",
+            github_repo
+        )));
+    }
+    let pr = github_client::get_pull(
+        client,
+        repo_full_name_parts[0],
+        repo_full_name_parts[1],
+        number,
+    )
+    .await?;
+    if pr.state.as_deref() == Some("open") {
+        return Ok(false);
+    }
+    // Branches whose close wasn't seen are kept from now on
+    let closed_at = match state::retained_branch(project, branch)? {
+        Some(retained) => retained.closed_at,
+        None if matches!(retention, config::BranchRetention::KeepDays { .. }) => {
+            state::retain_branch(project, branch)?;
+            return Ok(false);
+        }
+        None => 0,
+    };
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs() as i64)
+        .unwrap_or(0);
+    Ok(retention_expired(
+        retention,
+        pr.merged == Some(true),
+        closed_at,
+        now,
+    ))
+}
+
+async fn cleanup_project(
+    client: &reqwest::Client,
+    github_repo: &str,
+    project: &str,
+    retention: config::BranchRetention,
+) -> Result<usize, GitError> {
+    let mut deleted = 0;
+    let mut stale_branches = vec![];
+    for profile in config::branch_profiles() {
+        let search = format!("^{}", profile.branch_name_start());
+        let branches = gitlab_client::get_all_branches(client, project, &search).await?;
+        for branch in branches.iter().filter_map(|b| b.name.as_ref()) {
+            if let Some(number) = pr_number_from_branch(branch) {
+                match is_branch_stale(client, github_repo, project, branch, number, retention).await
+                {
+                    Ok(false) => debug!("Keeping {} of PR {}", branch, number),
+                    Ok(true) => stale_branches.push(branch.clone()),
+                    Err(err) => error!(
+                        "Unable to check state of PR {} for {}: {:?}",
+                        number, branch, err
+                    ),
+                }
+            }
+        }
+    }
+
+    for branch in stale_branches.iter() {
+        info!("Deleting stale branch {} from project={}", branch, project);
+        let result = gitlab_client::delete_branch(client, project, branch).await;
+        audit::record(
+            audit::LABHUB,
+            github_repo,
+            pr_number_from_branch(branch),
+            audit::Action::RefDeletion,
+            &format!("{} on {}", branch, project),
+            &result,
+        );
+        result?;
+        state::forget_retained_branch(project, branch)?;
+        deleted += 1;
+    }
+    Ok(deleted)
+}
+
+async fn cleanup_mapping(
+    client: &reqwest::Client,
+    mapping: &config::Mapping,
+) -> Result<usize, GitError> {
+    let retention = mapping.branch_retention;
+    let mut deleted = cleanup_project(
+        client,
+        &mapping.github_repo,
+        &mapping.gitlab_repo,
+        retention,
+    )
+    .await?;
+    if let Some(untrusted) = mapping.untrusted_gitlab_repo.as_ref() {
+        deleted += cleanup_project(client, &mapping.github_repo, untrusted, retention).await?;
+    }
+    Ok(deleted)
+}
+
+/// Deletes the stale `pr-*` branches of one mapped GitLab project, returning
+/// how many were deleted
+pub async fn cleanup_project_branches(project: &str) -> Result<usize, GitError> {
+    let not_found = || GitError::NotFound(format!("No mapping for GitLab project {}", project));
+    let github_repo = config::github_repo_for(project).ok_or_else(not_found)?;
+    let mapping = config::mapping_for_repo(&github_repo).ok_or_else(not_found)?;
+    let client = api::new_client()?;
+    cleanup_project(&client, &github_repo, project, mapping.branch_retention).await
+}
+
+pub async fn cleanup_stale_branches() -> Result<(), GitError> {
+    let client = api::new_client()?;
+    // The projects of wildcard mappings are only known once their PRs sync
+    let mappings = config::CONFIG.mappings.iter().filter(|m| !m.is_wildcard());
+    for mapping in mappings {
+        match cleanup_mapping(&client, mapping).await {
+            Ok(deleted) => info!(
+                "Cleaned up {} stale branches for project={}",
+                deleted, mapping.gitlab_repo
+            ),
+            Err(err) => error!(
+                "Error cleaning up stale branches for project={}: {:?}",
+                mapping.gitlab_repo, err
+            ),
+        }
+    }
+    Ok(())
+}
+
+/// Periodically deletes `pr-*` branches on GitLab whose PR is no longer open,
+/// which catches PRs whose `closed` webhook was missed.
+pub async fn run_periodic_cleanup() {
+    let mut interval =
+        tokio::time::interval(Duration::from_secs(config::CONFIG.cleanup.interval_secs));
+    loop {
+        interval.tick().await;
+        info!("Looking for stale branches on GitLab");
+        if let Err(err) = cleanup_stale_branches().await {
+            error!("Error cleaning up stale branches: {:?}", err);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::testing::assert_within_budget;
+
+    #[test]
+    fn branch_parsing_budget() {
+        assert_within_budget("PR branch parsing", Duration::from_micros(50), 1000, || {
+            pr_number_from_branch("pr-12/octocat/hello-world/fix-typo")
+        });
+    }
+
+    #[test]
+    fn test_retention_expired() {
+        use config::BranchRetention::*;
+        const DAY: i64 = 24 * 60 * 60;
+        assert!(retention_expired(Delete, true, 0, 0));
+        assert!(retention_expired(KeepMerged, false, 0, 0));
+        assert!(!retention_expired(KeepMerged, true, 0, 100 * DAY));
+        let week = KeepDays { days: 7 };
+        assert!(!retention_expired(week, false, DAY, 7 * DAY));
+        assert!(retention_expired(week, false, DAY, 8 * DAY));
+        assert!(retention_expired(week, true, DAY, 8 * DAY));
+    }
+
+    #[test]
+    fn test_pr_number_from_branch() {
+        assert_eq!(
+            pr_number_from_branch("pr-12/octocat/hello-world/fix-typo"),
+            Some(12)
+        );
+        assert_eq!(pr_number_from_branch("pr-12"), None);
+        assert_eq!(pr_number_from_branch("master"), None);
+        assert_eq!(pr_number_from_branch("pr-abc/octocat/hello-world/x"), None);
+
+        let fork = config::PipelineProfile {
+            branch_prefix: "fork-pr".to_string(),
+            ..Default::default()
+        };
+        assert_eq!(
+            fork.pr_number_from_branch("fork-pr-3/octocat/hello-world/x"),
+            Some(3)
+        );
+        assert_eq!(
+            pr_number_from_branch("fork-pr-3/octocat/hello-world/x"),
+            None
+        );
+        let short = config::PipelineProfile {
+            branch_template: "gh-{number}".to_string(),
+            ..Default::default()
+        };
+        assert_eq!(short.pr_number_from_branch("gh-42"), Some(42));
+        assert_eq!(short.pr_number_from_branch("gh-42/x"), None);
+        assert_eq!(short.pr_number_from_branch("pr-42"), None);
+        let nested = config::PipelineProfile {
+            branch_template: "pr/{number}/{ref}".to_string(),
+            ..Default::default()
+        };
+        assert_eq!(nested.pr_number_from_branch("pr/7/fix-typo"), Some(7));
+        assert_eq!(nested.pr_number_from_branch("pr/7"), None);
+    }
+}
@@ -0,0 +1,739 @@
+//! Running several LabHub replicas behind one load balancer, coordinated
+//! through the `[redis]` Redis: PR events go to a queue shared by all
+//! replicas, each of which takes the next event whenever its own worker is
+//! idle; webhook deliveries are deduplicated across replicas; a replica
+//! holds a lock on a repo while syncing its PRs, so replicas never push to
+//! the same repo at once; and paused repos, kill switches and approvals are
+//! kept in Redis, so all replicas honor them.
+use crate::config;
+use crate::errors::GitError;
+use crate::forge::{self, ForgePullRequest};
+use crate::queue;
+
+use log::{error, info, warn};
+use redis::aio::ConnectionManager;
+use redis::{AsyncCommands, Direction};
+use ring::rand::{SecureRandom, SystemRandom};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+use std::time::Duration;
+use tokio::sync::mpsc::{unbounded_channel, UnboundedReceiver, UnboundedSender};
+use tokio::sync::OnceCell;
+use tokio::task::JoinHandle;
+use tokio::time::sleep;
+
+/// How long to wait for the shared queue to have an event, before checking
+/// whether to stop
+const POP_TIMEOUT_SECS: f64 = 1.0;
+/// How often to check whether this replica's worker is idle, or a locked
+/// repo was unlocked
+const POLL_INTERVAL: Duration = Duration::from_millis(200);
+/// How long to wait before reconnecting to Redis after an error
+const RECONNECT_DELAY: Duration = Duration::from_secs(5);
+/// How long reading or writing shared state may take, see [`shared_put`]
+const SHARED_STATE_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Deletes a lock only if it's still held with the same token, as it may
+/// have expired and been taken by another replica
+const UNLOCK_SCRIPT: &str = r#"
+if redis.call("GET", KEYS[1]) == ARGV[1] then
+    return redis.call("DEL", KEYS[1])
+end
+return 0
+"#;
+
+/// Extends a lock only if it's still held with the same token
+const RENEW_SCRIPT: &str = r#"
+if redis.call("GET", KEYS[1]) == ARGV[1] then
+    return redis.call("PEXPIRE", KEYS[1], ARGV[2])
+end
+return 0
+"#;
+
+/// A PR event on its way to the shared queue
+struct Outgoing {
+    stored: String,
+    pr: Box<dyn ForgePullRequest>,
+}
+
+lazy_static! {
+    static ref CLIENT: Option<redis::Client> = config::CONFIG
+        .redis
+        .as_ref()
+        .map(|redis| { redis::Client::open(redis.url.as_str()).expect("Invalid Redis URL") });
+    static ref CONNECTION: OnceCell<ConnectionManager> = OnceCell::new();
+    /// A blocking connection for shared state, whose callers aren't async
+    static ref SYNC_CONNECTION: Mutex<Option<redis::Connection>> = Mutex::new(None);
+    static ref OUTBOX: (
+        UnboundedSender<Outgoing>,
+        Mutex<Option<UnboundedReceiver<Outgoing>>>
+    ) = {
+        let (sender, receiver) = unbounded_channel();
+        (sender, Mutex::new(Some(receiver)))
+    };
+}
+
+/// Set once this replica stops taking events from the shared queue
+static STOPPING: AtomicBool = AtomicBool::new(false);
+
+/// Whether replicas are coordinated through Redis
+pub fn enabled() -> bool {
+    config::CONFIG.redis.is_some()
+}
+
+fn client() -> Result<&'static redis::Client, GitError> {
+    CLIENT
+        .as_ref()
+        .ok_or(GitError::Config("Redis isn't configured".to_string()))
+}
+
+fn key(prefix: &str, name: &str) -> String {
+    format!("{}:{}", prefix, name)
+}
+
+fn prefixed(name: &str) -> String {
+    let prefix = config::CONFIG
+        .redis
+        .as_ref()
+        .map(|redis| redis.key_prefix.as_str())
+        .unwrap_or("labhub");
+    key(prefix, name)
+}
+
+/// The list of events this replica took from the shared queue and hasn't
+/// handled yet. It's named after the replica, so that a replica which died
+/// while syncing puts them back on the queue once it's restarted.
+fn processing_key() -> String {
+    let replica_id = config::CONFIG
+        .redis
+        .as_ref()
+        .map(|redis| redis.replica_id.as_str())
+        .unwrap_or("labhub");
+    prefixed(&format!("processing:{}", replica_id))
+}
+
+/// The shared connection, which reconnects by itself
+async fn connection() -> Result<ConnectionManager, GitError> {
+    let client = client()?;
+    Ok(CONNECTION
+        .get_or_try_init(|| ConnectionManager::new(client.clone()))
+        .await?
+        .clone())
+}
+
+/// Hands a PR event to the shared queue, in the order events are received.
+/// Events which can't be serialized are queued on this replica instead.
+pub fn share(pr: Box<dyn ForgePullRequest>) {
+    let stored = match forge::store(pr.as_ref()) {
+        Some(stored) => stored,
+        None => {
+            warn!(
+                "Can't share PR {} of {}, syncing it on this replica",
+                pr.number(),
+                pr.base_full_name()
+            );
+            queue::enqueue_local(pr);
+            return;
+        }
+    };
+    if let Err(err) = OUTBOX.0.send(Outgoing { stored, pr }) {
+        queue::enqueue_local(err.0.pr);
+    }
+}
+
+async fn push(stored: &str) -> Result<(), GitError> {
+    let mut connection = connection().await?;
+    let _: i64 = connection.rpush(prefixed("queue"), stored).await?;
+    Ok(())
+}
+
+/// Pushes shared events to Redis one at a time, so they stay in order.
+/// Events which can't be pushed are synced on this replica.
+pub async fn run_forwarder() {
+    let receiver = OUTBOX.1.lock().unwrap().take();
+    let mut receiver = match receiver {
+        Some(receiver) => receiver,
+        None => {
+            error!("Shared queue forwarder is already running");
+            return;
+        }
+    };
+    while let Some(outgoing) = receiver.recv().await {
+        if let Err(err) = push(&outgoing.stored).await {
+            error!(
+                "Error sharing PR event, syncing it on this replica: {:?}",
+                err
+            );
+            queue::enqueue_local(outgoing.pr);
+        }
+    }
+}
+
+/// Moves the next event of the shared queue to a processing list, waiting
+/// up to [`POP_TIMEOUT_SECS`] for one
+async fn take<C: redis::aio::ConnectionLike + Send>(
+    connection: &mut C,
+    queue_key: &str,
+    processing_key: &str,
+) -> Result<Option<String>, GitError> {
+    Ok(connection
+        .blmove(
+            queue_key,
+            processing_key,
+            Direction::Left,
+            Direction::Right,
+            POP_TIMEOUT_SECS,
+        )
+        .await?)
+}
+
+/// Puts the events left in a processing list back at the head of the
+/// shared queue, in order, returning how many there were
+async fn recover<C: redis::aio::ConnectionLike + Send>(
+    connection: &mut C,
+    processing_key: &str,
+    queue_key: &str,
+) -> Result<usize, GitError> {
+    let mut recovered = 0;
+    loop {
+        let moved: Option<String> = connection
+            .lmove(processing_key, queue_key, Direction::Right, Direction::Left)
+            .await?;
+        if moved.is_none() {
+            return Ok(recovered);
+        }
+        recovered += 1;
+    }
+}
+
+/// Removes an event from a processing list
+async fn remove<C: redis::aio::ConnectionLike + Send>(
+    connection: &mut C,
+    processing_key: &str,
+    stored: &str,
+) -> Result<(), GitError> {
+    let _: i64 = connection.lrem(processing_key, 1, stored).await?;
+    Ok(())
+}
+
+/// Takes events from the shared queue whenever this replica's worker is
+/// idle, until [`stop`] is called. Each event stays in this replica's
+/// processing list until it's [acknowledged](acknowledge), so that events
+/// are put back on the queue on startup if the replica died before
+/// handling them.
+pub async fn run_feeder() {
+    let queue_key = prefixed("queue");
+    let processing_key = processing_key();
+    let mut recovered = false;
+    while !STOPPING.load(Ordering::Relaxed) {
+        // a dedicated connection, as BLMOVE blocks it
+        let mut connection = match client() {
+            Ok(client) => match client.get_async_connection().await {
+                Ok(connection) => connection,
+                Err(err) => {
+                    error!("Error connecting to Redis: {:?}", err);
+                    sleep(RECONNECT_DELAY).await;
+                    continue;
+                }
+            },
+            Err(err) => {
+                error!("Not taking shared PR events: {}", err);
+                return;
+            }
+        };
+        if !recovered {
+            match recover(&mut connection, &processing_key, &queue_key).await {
+                Ok(0) => {}
+                Ok(count) => info!(
+                    "Put {} PR events left over from a previous run back on the shared queue",
+                    count
+                ),
+                Err(err) => {
+                    error!("Error recovering left over PR events: {:?}", err);
+                    sleep(RECONNECT_DELAY).await;
+                    continue;
+                }
+            }
+            recovered = true;
+        }
+        info!("Taking PR events from the shared queue");
+        while !STOPPING.load(Ordering::Relaxed) {
+            if queue::depth() > 0 {
+                sleep(POLL_INTERVAL).await;
+                continue;
+            }
+            let stored = match take(&mut connection, &queue_key, &processing_key).await {
+                Ok(Some(stored)) => stored,
+                Ok(None) => continue,
+                Err(err) => {
+                    error!("Error taking a shared PR event: {:?}", err);
+                    sleep(RECONNECT_DELAY).await;
+                    break;
+                }
+            };
+            if STOPPING.load(Ordering::Relaxed) {
+                // leave it to the other replicas, or to this one's next run
+                let returned: Result<Option<String>, redis::RedisError> = connection
+                    .lmove(
+                        &processing_key,
+                        &queue_key,
+                        Direction::Right,
+                        Direction::Left,
+                    )
+                    .await;
+                if let Err(err) = returned {
+                    error!("Error returning a shared PR event: {:?}", err);
+                }
+                break;
+            }
+            match forge::restore(&stored) {
+                Ok(pr) => queue::enqueue_shared(pr, stored),
+                Err(err) => {
+                    error!("Error restoring shared PR event: {:?}", err);
+                    if let Err(err) = remove(&mut connection, &processing_key, &stored).await {
+                        error!("Error dropping shared PR event: {:?}", err);
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Removes an event taken from the shared queue from this replica's
+/// processing list, once it's handled or handed back
+pub async fn acknowledge(stored: &str) {
+    let removed = match connection().await {
+        Ok(mut connection) => remove(&mut connection, &processing_key(), stored).await,
+        Err(err) => Err(err),
+    };
+    if let Err(err) = removed {
+        error!("Error acknowledging shared PR event: {:?}", err);
+    }
+}
+
+/// Stops taking events from the shared queue, on shutdown
+pub fn stop() {
+    STOPPING.store(true, Ordering::Relaxed);
+}
+
+/// Puts events this replica didn't handle back on the shared queue, for the
+/// other replicas. Returns those which couldn't be.
+pub async fn requeue(prs: Vec<Box<dyn ForgePullRequest>>) -> Vec<Box<dyn ForgePullRequest>> {
+    let mut failed = vec![];
+    for pr in prs {
+        let pushed = match forge::store(pr.as_ref()) {
+            Some(stored) => push(&stored).await,
+            None => Err(GitError::Other("Unable to serialize PR event".to_string())),
+        };
+        if let Err(err) = pushed {
+            error!("Error returning PR event to the shared queue: {:?}", err);
+            failed.push(pr);
+        }
+    }
+    failed
+}
+
+/// Records a webhook delivery ID for all replicas. Returns true if no
+/// replica had recorded it yet.
+pub async fn record_delivery(delivery_id: &str) -> Result<bool, GitError> {
+    let mut connection = connection().await?;
+    let set: Option<String> = redis::cmd("SET")
+        .arg(prefixed(&format!("delivery:{}", delivery_id)))
+        .arg(1)
+        .arg("NX")
+        .arg("EX")
+        .arg(config::CONFIG.dedupe.delivery_retention_secs)
+        .query_async(&mut connection)
+        .await?;
+    Ok(set.is_some())
+}
+
+/// Returns true if a replica recorded a webhook delivery ID
+pub async fn delivery_recorded(delivery_id: &str) -> Result<bool, GitError> {
+    let mut connection = connection().await?;
+    let exists: bool = redis::cmd("EXISTS")
+        .arg(prefixed(&format!("delivery:{}", delivery_id)))
+        .query_async(&mut connection)
+        .await?;
+    Ok(exists)
+}
+
+/// A repo locked by this replica, see [`lock_repos`]
+#[derive(Debug)]
+pub struct RepoLock {
+    key: String,
+    token: String,
+    /// Extends the lock while it's held, see [`keep_renewed`]
+    renewal: JoinHandle<()>,
+}
+
+/// A random token, so a replica only ever releases its own locks
+fn lock_token() -> Result<String, GitError> {
+    let mut bytes = [0u8; 16];
+    SystemRandom::new()
+        .fill(&mut bytes)
+        .map_err(|_| GitError::Other("Unable to generate a lock token".to_string()))?;
+    Ok(hex::encode(bytes))
+}
+
+async fn lock(
+    connection: &mut ConnectionManager,
+    key: String,
+    ttl_ms: u64,
+) -> Result<RepoLock, GitError> {
+    let token = lock_token()?;
+    loop {
+        let set: Option<String> = redis::cmd("SET")
+            .arg(&key)
+            .arg(&token)
+            .arg("NX")
+            .arg("PX")
+            .arg(ttl_ms)
+            .query_async(connection)
+            .await?;
+        if set.is_some() {
+            let renewal = tokio::spawn(keep_renewed(
+                connection.clone(),
+                key.clone(),
+                token.clone(),
+                ttl_ms,
+            ));
+            return Ok(RepoLock {
+                key,
+                token,
+                renewal,
+            });
+        }
+        sleep(POLL_INTERVAL).await;
+    }
+}
+
+/// How often a held lock is extended: often enough that a couple of failed
+/// attempts don't let it expire
+fn renewal_interval(ttl_ms: u64) -> Duration {
+    Duration::from_millis((ttl_ms / 3).max(1))
+}
+
+/// Extends a lock to its full TTL periodically until it's released, so it
+/// doesn't expire while its repo is still being synced, e.g. while waiting
+/// for GitLab to come back. The TTL then only bounds how long a lock
+/// outlives a replica which died holding it.
+async fn keep_renewed(mut connection: ConnectionManager, key: String, token: String, ttl_ms: u64) {
+    let script = redis::Script::new(RENEW_SCRIPT);
+    loop {
+        sleep(renewal_interval(ttl_ms)).await;
+        let renewed: Result<i64, redis::RedisError> = script
+            .key(&key)
+            .arg(&token)
+            .arg(ttl_ms)
+            .invoke_async(&mut connection)
+            .await;
+        match renewed {
+            Ok(0) => {
+                error!("Lock {} expired while it was held", key);
+                return;
+            }
+            Ok(_) => {}
+            Err(err) => error!("Error renewing lock {}: {:?}", key, err),
+        }
+    }
+}
+
+/// Locks repos (by their lookup keys) for this replica, waiting while
+/// another replica holds them. Locks are taken in order, so replicas
+/// locking overlapping repos can't deadlock. Without Redis, there's nothing
+/// to lock.
+pub async fn lock_repos(mut repos: Vec<String>) -> Result<Vec<RepoLock>, GitError> {
+    let ttl_ms = match config::CONFIG.redis.as_ref() {
+        Some(redis) => redis.lock_ttl_secs * 1000,
+        None => return Ok(vec![]),
+    };
+    repos.sort();
+    repos.dedup();
+    let mut connection = connection().await?;
+    let mut locks = vec![];
+    for repo in repos {
+        match lock(&mut connection, prefixed(&format!("lock:{}", repo)), ttl_ms).await {
+            Ok(lock) => locks.push(lock),
+            Err(err) => {
+                unlock_repos(locks).await;
+                return Err(err);
+            }
+        }
+    }
+    Ok(locks)
+}
+
+/// Releases repo locks, logging failures as they expire anyway
+pub async fn unlock_repos(locks: Vec<RepoLock>) {
+    if locks.is_empty() {
+        return;
+    }
+    for lock in &locks {
+        lock.renewal.abort();
+    }
+    let mut connection = match connection().await {
+        Ok(connection) => connection,
+        Err(err) => {
+            error!("Error releasing repo locks: {:?}", err);
+            return;
+        }
+    };
+    for lock in locks {
+        match unlock(&mut connection, &lock).await {
+            Ok(false) => warn!("Lock {} had expired before it was released", lock.key),
+            Ok(true) => {}
+            Err(err) => error!("Error releasing lock {}: {:?}", lock.key, err),
+        }
+    }
+}
+
+/// Releases a lock, returning false if it had expired
+async fn unlock(connection: &mut ConnectionManager, lock: &RepoLock) -> Result<bool, GitError> {
+    lock.renewal.abort();
+    let released: i64 = redis::Script::new(UNLOCK_SCRIPT)
+        .key(&lock.key)
+        .arg(&lock.token)
+        .invoke_async(connection)
+        .await?;
+    Ok(released > 0)
+}
+
+/// Runs commands for shared state on a blocking connection, as their
+/// callers aren't async. Like the state store's, these are quick; the
+/// connection is reopened after an error.
+fn with_connection<T>(
+    query: impl FnOnce(&mut redis::Connection) -> redis::RedisResult<T>,
+) -> Result<T, GitError> {
+    let mut guard = SYNC_CONNECTION.lock().unwrap();
+    if guard.is_none() {
+        let connection = client()?.get_connection_with_timeout(SHARED_STATE_TIMEOUT)?;
+        connection.set_read_timeout(Some(SHARED_STATE_TIMEOUT))?;
+        connection.set_write_timeout(Some(SHARED_STATE_TIMEOUT))?;
+        *guard = Some(connection);
+    }
+    let result = query(guard.as_mut().unwrap());
+    if result.is_err() {
+        *guard = None;
+    }
+    Ok(result?)
+}
+
+/// Stores a value for all replicas, under a field of a Redis hash
+pub fn shared_put<T: Serialize>(hash: &str, field: &str, value: &T) -> Result<(), GitError> {
+    let value = serde_json::to_string(value)?;
+    with_connection(|connection| {
+        redis::cmd("HSET")
+            .arg(prefixed(hash))
+            .arg(field)
+            .arg(value)
+            .query(connection)
+    })
+}
+
+/// Removes a value stored with [`shared_put`], returning false if there
+/// wasn't one
+pub fn shared_remove(hash: &str, field: &str) -> Result<bool, GitError> {
+    let removed: i64 = with_connection(|connection| {
+        redis::cmd("HDEL")
+            .arg(prefixed(hash))
+            .arg(field)
+            .query(connection)
+    })?;
+    Ok(removed > 0)
+}
+
+pub fn shared_get<T: DeserializeOwned>(hash: &str, field: &str) -> Result<Option<T>, GitError> {
+    let value: Option<String> = with_connection(|connection| {
+        redis::cmd("HGET")
+            .arg(prefixed(hash))
+            .arg(field)
+            .query(connection)
+    })?;
+    Ok(value
+        .map(|value| serde_json::from_str(&value))
+        .transpose()?)
+}
+
+/// All values stored in a hash, ordered by field
+pub fn shared_values<T: DeserializeOwned>(hash: &str) -> Result<Vec<T>, GitError> {
+    let mut values: Vec<(String, String)> =
+        with_connection(|connection| redis::cmd("HGETALL").arg(prefixed(hash)).query(connection))?;
+    values.sort();
+    values
+        .iter()
+        .map(|(_, value)| Ok(serde_json::from_str(value)?))
+        .collect()
+}
+
+fn held_key(repo: &str) -> String {
+    prefixed(&format!("held:{}", repo))
+}
+
+/// Holds a PR event of a paused repo for all replicas, so that it's queued
+/// whichever replica resumes the repo, see [`crate::pause`]
+pub fn hold(repo: &str, pr: &dyn ForgePullRequest) -> Result<(), GitError> {
+    let stored = forge::store(pr)
+        .ok_or_else(|| GitError::Other("Unable to serialize PR event".to_string()))?;
+    let _: i64 = with_connection(|connection| {
+        redis::cmd("RPUSH")
+            .arg(held_key(repo))
+            .arg(stored)
+            .query(connection)
+    })?;
+    Ok(())
+}
+
+/// Takes the events held for a repo with [`hold`]
+pub fn take_held(repo: &str) -> Result<Vec<Box<dyn ForgePullRequest>>, GitError> {
+    let key = held_key(repo);
+    let (held,): (Vec<String>,) = with_connection(|connection| {
+        redis::pipe()
+            .atomic()
+            .cmd("LRANGE")
+            .arg(&key)
+            .arg(0)
+            .arg(-1)
+            .cmd("DEL")
+            .arg(&key)
+            .ignore()
+            .query(connection)
+    })?;
+    Ok(held
+        .iter()
+        .filter_map(|stored| match forge::restore(stored) {
+            Ok(pr) => Some(pr),
+            Err(err) => {
+                error!("Error restoring held PR event: {:?}", err);
+                None
+            }
+        })
+        .collect())
+}
+
+/// Number of events held with [`hold`] for each paused repo
+pub fn held_counts() -> Result<HashMap<String, usize>, GitError> {
+    let prefix = held_key("");
+    with_connection(|connection| {
+        let keys: Vec<String> =
+            redis::Commands::scan_match(connection, format!("{}*", prefix))?.collect();
+        let mut counts = HashMap::new();
+        for key in keys {
+            let count: usize = redis::cmd("LLEN").arg(&key).query(connection)?;
+            counts.insert(key[prefix.len()..].to_string(), count);
+        }
+        Ok(counts)
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_keys() {
+        assert_eq!(key("labhub", "queue"), "labhub:queue");
+        assert_eq!(
+            key("staging", "lock:octocat/hello-world"),
+            "staging:lock:octocat/hello-world"
+        );
+        let token = lock_token().unwrap();
+        assert_eq!(token.len(), 32);
+        assert_ne!(token, lock_token().unwrap());
+    }
+
+    #[test]
+    fn test_renewal_interval() {
+        assert_eq!(renewal_interval(900_000), Duration::from_secs(300));
+        assert_eq!(renewal_interval(1), Duration::from_millis(1));
+    }
+
+    /// A connection to the Redis at `LABHUB_TEST_REDIS_URL`, for the tests
+    /// of lock and queue behaviour, which are skipped without one
+    async fn test_connection() -> Option<ConnectionManager> {
+        let url = match std::env::var("LABHUB_TEST_REDIS_URL") {
+            Ok(url) => url,
+            Err(_) => {
+                eprintln!("LABHUB_TEST_REDIS_URL isn't set, skipping");
+                return None;
+            }
+        };
+        let client = redis::Client::open(url).unwrap();
+        Some(ConnectionManager::new(client).await.unwrap())
+    }
+
+    fn test_key(name: &str) -> String {
+        key(&format!("labhub-test-{}", lock_token().unwrap()), name)
+    }
+
+    #[tokio::test]
+    async fn test_lock() {
+        let mut connection = match test_connection().await {
+            Some(connection) => connection,
+            None => return,
+        };
+        let key = test_key("lock:octocat/hello-world");
+        let held = lock(&mut connection, key.clone(), 300).await.unwrap();
+
+        // another replica waits, even past the TTL, as the lock is renewed
+        let waiting = tokio::time::timeout(
+            Duration::from_millis(1000),
+            lock(&mut connection.clone(), key.clone(), 300),
+        )
+        .await;
+        assert!(waiting.is_err());
+
+        assert!(unlock(&mut connection, &held).await.unwrap());
+        let taken = tokio::time::timeout(
+            Duration::from_millis(1000),
+            lock(&mut connection.clone(), key.clone(), 300),
+        )
+        .await
+        .unwrap()
+        .unwrap();
+        assert_ne!(taken.token, held.token);
+        // releasing a lock which was since taken by another replica leaves it
+        assert!(!unlock(&mut connection, &held).await.unwrap());
+
+        // without renewals, it expires
+        taken.renewal.abort();
+        sleep(Duration::from_millis(500)).await;
+        let exists: bool = connection.exists(&key).await.unwrap();
+        assert!(!exists);
+        assert!(!unlock(&mut connection, &taken).await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_queue() {
+        let mut connection = match test_connection().await {
+            Some(connection) => connection,
+            None => return,
+        };
+        let queue_key = test_key("queue");
+        let processing_key = test_key("processing:replica-0");
+        for event in ["a", "b", "c"] {
+            let _: i64 = connection.rpush(&queue_key, event).await.unwrap();
+        }
+
+        let taken = take(&mut connection, &queue_key, &processing_key).await;
+        assert_eq!(taken.unwrap().as_deref(), Some("a"));
+        let taken = take(&mut connection, &queue_key, &processing_key).await;
+        assert_eq!(taken.unwrap().as_deref(), Some("b"));
+        let processing: Vec<String> = connection.lrange(&processing_key, 0, -1).await.unwrap();
+        assert_eq!(processing, ["a", "b"]);
+
+        // "b" was handled, then the replica died while handling "a"
+        remove(&mut connection, &processing_key, "b").await.unwrap();
+        let recovered = recover(&mut connection, &processing_key, &queue_key).await;
+        assert_eq!(recovered.unwrap(), 1);
+        let queue: Vec<String> = connection.lrange(&queue_key, 0, -1).await.unwrap();
+        assert_eq!(queue, ["a", "c"]);
+        let processing: Vec<String> = connection.lrange(&processing_key, 0, -1).await.unwrap();
+        assert!(processing.is_empty());
+
+        let _: i64 = connection.del(&queue_key).await.unwrap();
+    }
+}
@@ -1,3 +1,6 @@
+//! Parsing of PR comment commands, and who may use them.
+use crate::config;
+
 use regex::Regex;
 use std::convert::TryFrom;
 
@@ -5,11 +8,80 @@ fn tokenize_comment(body: &str) -> Vec<&str> {
     body.split_whitespace().collect()
 }
 
-#[derive(Debug, PartialEq, Deserialize)]
+#[derive(Debug, PartialEq, Eq, Hash, Deserialize, Serialize)]
 #[serde(rename_all = "kebab-case")]
 pub enum CommandAction {
     Retry,
     NewPipeline,
+    Status,
+    PauseMirroring,
+    ResumeMirroring,
+    Approve,
+}
+
+/// A user's permission level on a GitHub repo, from lowest to highest
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PermissionLevel {
+    None,
+    Read,
+    Triage,
+    Write,
+    Maintain,
+    Admin,
+}
+
+impl PermissionLevel {
+    /// Parses a GitHub role name, treating unknown (e.g. custom) roles as
+    /// read access
+    pub fn from_role(role: &str) -> PermissionLevel {
+        match role {
+            "none" => PermissionLevel::None,
+            "triage" => PermissionLevel::Triage,
+            "write" | "push" => PermissionLevel::Write,
+            "maintain" => PermissionLevel::Maintain,
+            "admin" => PermissionLevel::Admin,
+            _ => PermissionLevel::Read,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            PermissionLevel::None => "none",
+            PermissionLevel::Read => "read",
+            PermissionLevel::Triage => "triage",
+            PermissionLevel::Write => "write",
+            PermissionLevel::Maintain => "maintain",
+            PermissionLevel::Admin => "admin",
+        }
+    }
+}
+
+/// The permission level needed to use a command
+pub fn required_permission(
+    commands: &config::Commands,
+    command: &CommandAction,
+) -> PermissionLevel {
+    commands
+        .permissions
+        .get(command)
+        .copied()
+        .unwrap_or(commands.default_permission)
+}
+
+/// Whether `username` may use `command`, given their permission level on the
+/// repo. The deny list wins over the allow list, which wins over permissions.
+pub fn is_allowed(
+    commands: &config::Commands,
+    command: &CommandAction,
+    username: &str,
+    level: PermissionLevel,
+) -> bool {
+    let listed = |users: &[String]| users.iter().any(|u| u.eq_ignore_ascii_case(username));
+    if listed(&commands.denied_users) {
+        return false;
+    }
+    listed(&commands.allowed_users) || level >= required_permission(commands, command)
 }
 
 #[derive(Debug, PartialEq)]
@@ -37,6 +109,10 @@ impl TryFrom<&str> for CommandAction {
         match body.to_lowercase().as_ref() {
             "retry" => Ok(CommandAction::Retry),
             "new-pipeline" => Ok(CommandAction::NewPipeline),
+            "status" => Ok(CommandAction::Status),
+            "pause-mirroring" => Ok(CommandAction::PauseMirroring),
+            "resume-mirroring" => Ok(CommandAction::ResumeMirroring),
+            "approve" => Ok(CommandAction::Approve),
             _ => Err(CommandError::UnknownCommand),
         }
     }
@@ -74,6 +150,7 @@ impl Command {
 }
 
 #[cfg(test)]
+#[allow(clippy::bool_assert_comparison)]
 mod test {
     use super::*;
     use crate::testing::run_test;
@@ -116,6 +193,16 @@ mod test {
                 Command::parse_from("@bot retry nerp", "bot").unwrap().args,
                 vec!["nerp"]
             );
+            assert_eq!(
+                Command::parse_from("@bot pause-mirroring please", "bot")
+                    .unwrap()
+                    .command,
+                CommandAction::PauseMirroring
+            );
+            assert_eq!(
+                Command::parse_from("@bot approve", "bot").unwrap().command,
+                CommandAction::Approve
+            );
         });
     }
 
@@ -144,6 +231,55 @@ mod test {
         });
     }
 
+    #[test]
+    fn test_is_allowed() {
+        run_test(|| {
+            let commands = config::Commands {
+                enabled_commands: vec![CommandAction::Retry, CommandAction::Status],
+                allowed_users: vec!["Friend".to_string()],
+                denied_users: vec!["troll".to_string()],
+                default_permission: PermissionLevel::Write,
+                permissions: [(CommandAction::Status, PermissionLevel::None)].into(),
+                acknowledgement: config::Acknowledgement::Comment,
+            };
+            let retry = CommandAction::Retry;
+            assert!(is_allowed(&commands, &retry, "dev", PermissionLevel::Write));
+            assert!(is_allowed(&commands, &retry, "dev", PermissionLevel::Admin));
+            assert!(!is_allowed(
+                &commands,
+                &retry,
+                "dev",
+                PermissionLevel::Triage
+            ));
+            assert!(is_allowed(
+                &commands,
+                &retry,
+                "friend",
+                PermissionLevel::None
+            ));
+            assert!(!is_allowed(
+                &commands,
+                &retry,
+                "troll",
+                PermissionLevel::Admin
+            ));
+            assert!(is_allowed(
+                &commands,
+                &CommandAction::Status,
+                "anyone",
+                PermissionLevel::None
+            ));
+            assert_eq!(
+                PermissionLevel::from_role("maintain"),
+                PermissionLevel::Maintain
+            );
+            assert_eq!(
+                PermissionLevel::from_role("custom-role"),
+                PermissionLevel::Read
+            );
+        });
+    }
+
     #[test]
     fn test_parse_body() {
         run_test(|| {
@@ -0,0 +1,292 @@
+//! Comment mirroring: with the `comment_mirroring` feature, conversation
+//! comments on a PR are copied to the GitLab merge request opened for it and
+//! notes on the merge request are copied back to the PR. Mirrored copies end
+//! with a marker, so they're never mirrored back.
+use crate::api;
+use crate::api::models::{github, gitlab};
+use crate::api::{github_client, gitlab_client};
+use crate::audit;
+use crate::config;
+use crate::errors::GitError;
+use crate::messages::{self, Message};
+use crate::repo_name;
+use crate::state;
+
+use log::info;
+
+/// Ends every mirrored comment. Being a LabHub marker, it also keeps them
+/// from being removed as stale bot comments.
+const MIRRORED_MARKER: &str = "<!-- labhub:mirrored -->";
+
+fn is_mirrored(body: &str) -> bool {
+    body.contains(MIRRORED_MARKER)
+}
+
+/// Escapes the lines of a comment GitLab would run as quick actions, ex:
+/// `/merge`, which would run as LabHub's user. The escaped slash renders the
+/// same. Code blocks are left alone, GitLab ignores them.
+fn neutralize_quick_actions(body: &str) -> String {
+    let mut in_code_block = false;
+    body.lines()
+        .map(|line| {
+            let trimmed = line.trim_start();
+            if trimmed.starts_with("```") || trimmed.starts_with("~~~") {
+                in_code_block = !in_code_block;
+            }
+            if !in_code_block && trimmed.starts_with('/') {
+                let indent = &line[..line.len() - trimmed.len()];
+                format!("{}\\{}", indent, trimmed)
+            } else {
+                line.to_string()
+            }
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// The body of the copy of a comment, in the language of the GitHub repo
+fn mirrored_body(
+    github_repo: &str,
+    author: &str,
+    forge: &str,
+    url: Option<&str>,
+    body: &str,
+) -> String {
+    format!(
+        "{}\n\n{}\n\n{}",
+        messages::for_repo(github_repo, Message::MirroredComment { author, forge, url }),
+        body.trim_end(),
+        MIRRORED_MARKER
+    )
+}
+
+/// Mirrors a created or edited PR comment onto the PR's merge request
+pub async fn mirror_github_comment(ic: &github::IssueComment) -> Result<(), GitError> {
+    let repo = &ic.repository.full_name;
+    if !matches!(ic.action.as_str(), "created" | "edited")
+        || ic.issue.pull_request.is_none()
+        || is_mirrored(&ic.comment.body)
+    {
+        return Ok(());
+    }
+    let author = ic
+        .comment
+        .user
+        .as_ref()
+        .and_then(|user| user.login.as_deref())
+        .unwrap_or("ghost");
+    if author == config::github_for_repo(repo).site.username {
+        return Ok(());
+    }
+    let comment_id = ic.comment.id.ok_or(GitError::BadRequest(format!(
+        "Comment on PR {} of {} has no id",
+        ic.issue.number, repo
+    )))?;
+    let key = repo_name::lookup_key(repo);
+    let merge_request = match state::merge_request(&key, ic.issue.number)? {
+        Some(merge_request) => merge_request,
+        None => {
+            info!(
+                "PR {} of {} has no merge request, not mirroring comment {}",
+                ic.issue.number, repo, comment_id
+            );
+            return Ok(());
+        }
+    };
+    let body = mirrored_body(
+        repo,
+        author,
+        "GitHub",
+        ic.comment.html_url.as_deref(),
+        &neutralize_quick_actions(&ic.comment.body),
+    );
+    let client = api::new_client()?;
+    let project = &merge_request.gitlab_project;
+    let result = match state::mirrored_comment_by_github(&key, comment_id)? {
+        Some(mirrored) => gitlab_client::update_merge_request_note(
+            &client,
+            project,
+            merge_request.iid,
+            mirrored.gitlab_note_id,
+            &body,
+        )
+        .await
+        .map(|()| None),
+        None => {
+            gitlab_client::create_merge_request_note(&client, project, merge_request.iid, &body)
+                .await
+                .map(Some)
+        }
+    };
+    audit::record(
+        author,
+        repo,
+        Some(ic.issue.number),
+        audit::Action::Comment,
+        &format!("{}!{}", project, merge_request.iid),
+        &result,
+    );
+    if let Some(note_id) = result? {
+        info!(
+            "Mirrored comment {} of PR {} as note {} on {}",
+            comment_id, ic.issue.number, note_id, project
+        );
+        state::record_mirrored_comment(&state::MirroredComment {
+            github_repo: key,
+            pr_number: ic.issue.number,
+            github_comment_id: comment_id,
+            gitlab_project: project.clone(),
+            gitlab_note_id: note_id,
+        })?;
+    }
+    Ok(())
+}
+
+/// Mirrors a created or edited merge request note onto the PR it was opened
+/// for
+pub async fn mirror_gitlab_note(event: &gitlab::NoteEvent) -> Result<(), GitError> {
+    let (attributes, project, iid) = match (
+        event.object_attributes.as_ref(),
+        event
+            .project
+            .as_ref()
+            .and_then(|project| project.path_with_namespace.as_deref()),
+        event.merge_request.as_ref().and_then(|mr| mr.iid),
+    ) {
+        (Some(attributes), Some(project), Some(iid)) => (attributes, project, iid),
+        _ => return Ok(()),
+    };
+    let (note_id, note) = match (attributes.id, attributes.note.as_deref()) {
+        (Some(note_id), Some(note)) => (note_id, note),
+        _ => return Ok(()),
+    };
+    if attributes.noteable_type.as_deref() != Some("MergeRequest")
+        || attributes.system == Some(true)
+        || !matches!(
+            attributes.action.as_deref(),
+            None | Some("create" | "update")
+        )
+        || is_mirrored(note)
+    {
+        return Ok(());
+    }
+    let author = event
+        .user
+        .as_ref()
+        .and_then(|user| user.username.as_deref())
+        .unwrap_or("ghost");
+    if author == config::gitlab_for_project(project).site.username {
+        return Ok(());
+    }
+    let merge_request = match state::merge_request_by_iid(project, iid)? {
+        Some(merge_request) => merge_request,
+        None => {
+            info!(
+                "Merge request {} on {} wasn't opened for a PR, not mirroring note {}",
+                iid, project, note_id
+            );
+            return Ok(());
+        }
+    };
+    let repo = &merge_request.github_repo;
+    let (org, name) = repo
+        .split_once('/')
+        .ok_or(GitError::Other(format!("Invalid repo name {}", repo)))?;
+    let body = mirrored_body(repo, author, "GitLab", attributes.url.as_deref(), note);
+    let client = api::new_client()?;
+    let result = match state::mirrored_comment_by_gitlab(project, note_id)? {
+        Some(mirrored) => github_client::update_issue_comment(
+            &client,
+            org,
+            name,
+            mirrored.github_comment_id,
+            &body,
+        )
+        .await
+        .map(|()| None),
+        None => {
+            github_client::create_issue_comment(&client, org, name, merge_request.pr_number, &body)
+                .await
+                .map(Some)
+        }
+    };
+    audit::record(
+        author,
+        repo,
+        Some(merge_request.pr_number),
+        audit::Action::Comment,
+        &format!("{}#{}", repo, merge_request.pr_number),
+        &result,
+    );
+    if let Some(comment_id) = result? {
+        info!(
+            "Mirrored note {} on {} as comment {} of PR {}",
+            note_id, project, comment_id, merge_request.pr_number
+        );
+        state::record_mirrored_comment(&state::MirroredComment {
+            github_repo: repo.clone(),
+            pr_number: merge_request.pr_number,
+            github_comment_id: comment_id,
+            gitlab_project: project.to_string(),
+            gitlab_note_id: note_id,
+        })?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::testing::{read_testdata_to_string, run_test};
+
+    #[test]
+    fn test_mirrored_body() {
+        run_test(|| {
+            let body = mirrored_body(
+                "octocat/hello-world",
+                "octocat",
+                "GitHub",
+                Some("https://github.com/octocat/hello-world/pull/12#issuecomment-1"),
+                "Looks good\n",
+            );
+            assert_eq!(
+                body,
+                "**octocat** [commented](https://github.com/octocat/hello-world/pull/12#issuecomment-1) \
+                 on GitHub:\n\nLooks good\n\n<!-- labhub:mirrored -->"
+            );
+            assert!(is_mirrored(&body));
+            assert!(!is_mirrored("Looks good"));
+        });
+    }
+
+    #[test]
+    fn test_neutralize_quick_actions() {
+        assert_eq!(
+            neutralize_quick_actions("LGTM\n/merge\n  /approve now\nsee /docs"),
+            "LGTM\n\\/merge\n  \\/approve now\nsee /docs"
+        );
+        assert_eq!(
+            neutralize_quick_actions("```\n/usr/bin/env\n```\n/close"),
+            "```\n/usr/bin/env\n```\n\\/close"
+        );
+    }
+
+    #[test]
+    fn note_event() {
+        run_test(|| {
+            let event: gitlab::NoteEvent =
+                serde_json::from_str(&read_testdata_to_string("gitlab_note_merge_request.json"))
+                    .unwrap();
+            let attributes = event.object_attributes.unwrap();
+            assert_eq!(attributes.id, Some(1244));
+            assert_eq!(attributes.noteable_type.as_deref(), Some("MergeRequest"));
+            assert_eq!(attributes.system, Some(false));
+            assert_eq!(event.user.unwrap().username.as_deref(), Some("root"));
+            assert_eq!(
+                event.project.unwrap().path_with_namespace.as_deref(),
+                Some("mirrors/hello-world")
+            );
+            assert_eq!(event.merge_request.and_then(|mr| mr.iid), Some(1));
+        });
+    }
+}
@@ -1,12 +1,22 @@
+//! The `LabHub.toml` configuration, and the repo mappings derived from it.
 use crate::commands;
+use crate::errors::GitError;
+use crate::flaky;
+use crate::host_keys;
+use crate::mapping_rules;
+use crate::ref_name;
+use crate::repo_name;
+use crate::secrets;
+use crate::sentry;
 
 use log::info;
+use serde::{Deserialize, Deserializer};
 use std::collections::HashMap;
 use std::env;
 use std::fs::File;
 use std::io::prelude::*;
+use std::path::Path;
 use std::sync::Mutex;
-use toml;
 use yansi::Paint;
 
 #[derive(Debug, Deserialize, PartialEq)]
@@ -14,25 +24,118 @@ use yansi::Paint;
 pub enum Feature {
     ExternalPr,
     Commands,
+    StaleBranchCleanup,
+    StartupReconciliation,
+    Deployments,
+    ReviewAppComments,
+    Lfs,
+    PrVariables,
+    FlakyRetry,
+    PipelineSummary,
+    ArtifactLinks,
+    TestAnnotations,
+    CoverageComments,
+    ProtectedPaths,
+    CommentMirroring,
+    SyncInternalPrs,
 }
 
 #[derive(Debug, Deserialize)]
 pub struct Config {
     pub server: Server,
-    pub github: Site,
-    pub gitlab: Site,
+    /// Either a single `[github]` table, or several `[[github]]` instances,
+    /// e.g. github.com and GitHub Enterprise
+    #[serde(deserialize_with = "one_or_many")]
+    pub github: Vec<GithubInstance>,
+    /// Either a single `[gitlab]` table, or several `[[gitlab]]` instances
+    #[serde(deserialize_with = "one_or_many")]
+    pub gitlab: Vec<GitlabInstance>,
     pub mappings: Vec<Mapping>,
     pub features: Vec<Feature>,
     pub commands: Commands,
     pub actions: Actions,
+    #[serde(default)]
+    pub cleanup: Cleanup,
+    #[serde(default)]
+    pub comments: Comments,
+    #[serde(default)]
+    pub state: State,
+    #[serde(default)]
+    pub dedupe: Dedupe,
+    #[serde(default)]
+    pub limits: Limits,
+    pub gitea: Option<Gitea>,
+    pub bitbucket: Option<Bitbucket>,
+    #[serde(default)]
+    pub admin: Admin,
+    #[serde(default)]
+    pub slo: Slo,
+    #[serde(default)]
+    pub batching: Batching,
+    #[serde(default)]
+    pub clone: CloneOptions,
+    #[serde(default)]
+    pub submodules: Submodules,
+    /// Signing of the commits LabHub generates, if set
+    pub signing: Option<Signing>,
+    #[serde(default)]
+    pub pause: Pause,
+    #[serde(default)]
+    pub trust: Trust,
+    #[serde(default)]
+    pub proxy: Proxy,
+    #[serde(default)]
+    pub ssh: Ssh,
+    #[serde(default)]
+    pub durations: Durations,
+    #[serde(default)]
+    pub flaky: Flaky,
+    #[serde(default)]
+    pub coverage: Coverage,
+    /// Language of the messages posted on PRs, unless the repo's mapping
+    /// sets one
+    #[serde(default)]
+    pub language: Language,
+    #[serde(default)]
+    pub disk: Disk,
+    #[serde(default)]
+    pub notifications: Notifications,
+    /// Where to post LabHub's events for other tools to react to
+    #[serde(default)]
+    pub event_webhooks: Vec<EventWebhook>,
+    /// Redis shared by several LabHub replicas, if set
+    pub redis: Option<Redis>,
+    /// Secrets manager the `secret:` values of the config are fetched from,
+    /// if set
+    pub secrets: Option<Secrets>,
+    /// Where panics and errors are reported, if set
+    pub sentry: Option<Sentry>,
+    /// Create the GitLab projects of mappings which don't exist yet, on
+    /// their first sync, if set
+    pub provisioning: Option<Provisioning>,
+    /// Which GitHub repos' webhooks are handled
+    #[serde(default)]
+    pub repos: Repos,
+}
+
+impl Config {
+    /// The sites of all configured forges and GitLab instances
+    pub fn sites(&self) -> impl Iterator<Item = &Site> {
+        self.github
+            .iter()
+            .map(|instance| &instance.site)
+            .chain(self.gitlab.iter().map(|instance| &instance.site))
+            .chain(self.gitea.iter().map(|gitea| &gitea.site))
+            .chain(self.bitbucket.iter().map(|bitbucket| &bitbucket.site))
+    }
 }
 
 pub fn feature_enabled(feature: &Feature) -> bool {
-    CONFIG.features.contains(&feature)
+    CONFIG.features.contains(feature)
 }
 
 pub fn command_enabled(command: &commands::CommandAction) -> bool {
-    feature_enabled(&Feature::Commands) && CONFIG.commands.enabled_commands.contains(&command)
+    feature_enabled(&Feature::Commands) && CONFIG.commands.enabled_commands.contains(command)
 }
 
 pub fn action_enabled(action: &str) -> bool {
@@ -42,32 +145,1082 @@ pub fn action_enabled(action: &str) -> bool {
 #[derive(Debug, Deserialize)]
 pub struct Actions {
     pub enabled_actions: Vec<String>,
+    /// Whether draft PRs are only synced once they're ready for review
+    #[serde(default)]
+    pub skip_drafts: bool,
+    /// What to do when a synced PR is converted to a draft
+    #[serde(default)]
+    pub converted_to_draft: DraftPolicy,
+}
+
+/// What to do when a synced PR is converted to a draft, with the
+/// `converted_to_draft` action enabled
+#[derive(Debug, Deserialize, PartialEq, Eq, Clone, Copy, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum DraftPolicy {
+    #[default]
+    Ignore,
+    /// Cancel the pipeline still running for the PR's head
+    CancelPipeline,
+    /// Close the merge request and delete the branch, as if the PR was
+    /// closed, until it's ready for review again
+    Close,
 }
 
 #[derive(Debug, Deserialize)]
 pub struct Commands {
     pub enabled_commands: Vec<commands::CommandAction>,
+    /// Users who may use any command, whatever their permission level
+    #[serde(default)]
+    pub allowed_users: Vec<String>,
+    /// Users who may not use any command
+    #[serde(default)]
+    pub denied_users: Vec<String>,
+    /// Permission level on the repo needed for commands not in `permissions`
+    #[serde(default = "default_command_permission")]
+    pub default_permission: commands::PermissionLevel,
+    /// Permission level on the repo needed for each command
+    #[serde(default = "default_command_permissions")]
+    pub permissions: HashMap<commands::CommandAction, commands::PermissionLevel>,
+    /// How commands which succeeded without a result to show are
+    /// acknowledged
+    #[serde(default)]
+    pub acknowledgement: Acknowledgement,
+}
+
+#[derive(Debug, Deserialize, PartialEq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum Acknowledgement {
+    /// Reply with a comment
+    #[default]
+    Comment,
+    /// React to the command's comment, keeping comments for results and
+    /// errors
+    Reaction,
+}
+
+fn default_command_permission() -> commands::PermissionLevel {
+    commands::PermissionLevel::Write
+}
+
+fn default_command_permissions() -> HashMap<commands::CommandAction, commands::PermissionLevel> {
+    HashMap::from([(
+        commands::CommandAction::Status,
+        commands::PermissionLevel::None,
+    )])
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(default)]
+pub struct Cleanup {
+    /// How often to look for orphaned `pr-*` branches on GitLab, in seconds
+    pub interval_secs: u64,
+}
+
+impl Default for Cleanup {
+    fn default() -> Self {
+        Cleanup {
+            interval_secs: 60 * 60,
+        }
+    }
+}
+
+/// Language of the messages posted on PRs, see [`crate::messages`]
+#[derive(Debug, Deserialize, PartialEq, Eq, Clone, Copy, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum Language {
+    #[default]
+    En,
+    De,
+}
+
+/// What to do with the bot's older comments on a PR once a new one is posted
+#[derive(Debug, Deserialize, PartialEq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum StaleCommentPolicy {
+    #[default]
+    Keep,
+    Delete,
+    Minimize,
+}
+
+#[derive(Debug, Deserialize, Default)]
+#[serde(default)]
+pub struct Comments {
+    pub stale_comment_policy: StaleCommentPolicy,
+}
+
+#[derive(Debug, Deserialize, Default)]
+#[serde(default)]
+pub struct State {
+    /// Path to the SQLite database. When unset, state is kept in memory and
+    /// lost on restart.
+    pub database: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(default)]
+pub struct Dedupe {
+    /// Number of recent webhook delivery IDs remembered in memory
+    pub delivery_cache_size: usize,
+    /// How long delivery IDs are kept in the state store, in seconds. Only
+    /// used when `state.database` is set.
+    pub delivery_retention_secs: i64,
+}
+
+impl Default for Dedupe {
+    fn default() -> Self {
+        Dedupe {
+            delivery_cache_size: 10_000,
+            delivery_retention_secs: 7 * 24 * 60 * 60,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(default)]
+pub struct Admin {
+    /// Bearer token required by the `/admin` routes, which are disabled
+    /// when this isn't set
+    pub token: Option<String>,
+    /// Number of recent verified webhook payloads kept for replaying them
+    /// through the admin API, 0 to keep none
+    pub replay_payloads: usize,
+}
+
+impl Default for Admin {
+    fn default() -> Self {
+        Admin {
+            token: None,
+            replay_payloads: 100,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(default)]
+pub struct Slo {
+    /// PR events should be mirrored to GitLab within this many seconds of
+    /// being received...
+    pub latency_target_secs: u64,
+    /// ...for at least this fraction of events
+    pub objective: f64,
+    /// Number of recent PR events the SLO is measured over
+    pub window_size: usize,
+    /// Don't alert until at least this many events have been measured
+    pub min_samples: usize,
+}
+
+impl Default for Slo {
+    fn default() -> Self {
+        Slo {
+            latency_target_secs: 60,
+            objective: 0.95,
+            window_size: 1000,
+            min_samples: 20,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(default)]
+pub struct Batching {
+    /// How long to wait for more PR closes before deleting their GitLab
+    /// branches together, in milliseconds
+    pub close_window_ms: u64,
+    /// Most PR branches deleted by a single push
+    pub max_refs_per_push: usize,
+    /// Pause after each batched push, in milliseconds
+    pub push_interval_ms: u64,
+    /// How long to wait for another push to a PR before syncing it, in
+    /// milliseconds. Pushes to a PR arriving within this window of each
+    /// other are synced once, at the latest head. 0, the default, syncs
+    /// every push.
+    pub synchronize_window_ms: u64,
+}
+
+impl Default for Batching {
+    fn default() -> Self {
+        Batching {
+            close_window_ms: 2000,
+            max_refs_per_push: 50,
+            push_interval_ms: 1000,
+            synchronize_window_ms: 0,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum CloneMode {
+    /// Clone the whole source repo
+    Full,
+    /// Start from an empty repo, and only fetch each PR's head
+    Narrow,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(default)]
+pub struct CloneOptions {
+    pub mode: CloneMode,
+}
+
+impl Default for CloneOptions {
+    fn default() -> Self {
+        CloneOptions {
+            mode: CloneMode::Full,
+        }
+    }
+}
+
+/// Pipeline duration tracking, to flag PRs whose pipelines got slower
+#[derive(Debug, Deserialize)]
+#[serde(default)]
+pub struct Durations {
+    /// How many of the base branch's latest pipelines the median is taken of
+    pub window: usize,
+    /// Fewest base branch pipelines needed to compare against
+    pub min_samples: usize,
+    /// How many times slower than the median a PR pipeline has to be to be
+    /// flagged
+    pub slowdown_factor: f64,
+}
+
+impl Default for Durations {
+    fn default() -> Self {
+        Durations {
+            window: 20,
+            min_samples: 5,
+            slowdown_factor: 1.5,
+        }
+    }
+}
+
+/// Coverage comparison, used when the `coverage_comments` feature is enabled
+#[derive(Debug, Deserialize)]
+#[serde(default)]
+pub struct Coverage {
+    /// How many percentage points lower than the base branch's coverage a
+    /// PR's has to be to be flagged as a regression
+    pub max_drop: f64,
+}
+
+impl Default for Coverage {
+    fn default() -> Self {
+        Coverage { max_drop: 0.5 }
+    }
+}
+
+/// Flaky job detection, used when the `flaky_retry` feature is enabled
+#[derive(Debug, Deserialize)]
+#[serde(default)]
+pub struct Flaky {
+    /// Regexes of the names of jobs known to be flaky
+    pub known_jobs: Vec<String>,
+    /// Also treat jobs as flaky once they failed then passed on a retry in
+    /// this many pipelines, 0 to only retry known flaky jobs
+    pub min_passes_on_retry: i64,
+}
+
+impl Default for Flaky {
+    fn default() -> Self {
+        Flaky {
+            known_jobs: vec![],
+            min_passes_on_retry: 3,
+        }
+    }
+}
+
+/// Disk space guard for the cache of cloned repos
+#[derive(Debug, Deserialize)]
+#[serde(default)]
+pub struct Disk {
+    /// Free space to keep on the temporary directory's volume, in MiB.
+    /// Cached repos are evicted to make room before a clone, and the clone
+    /// is refused if that's not enough.
+    pub min_free_mb: u64,
+    /// Cached repos unused for this long are evicted, e.g. those of repos
+    /// no longer mapped. 0 keeps them until disk space runs low.
+    pub repo_ttl_secs: u64,
+}
+
+impl Default for Disk {
+    fn default() -> Self {
+        Disk {
+            min_free_mb: 512,
+            repo_ttl_secs: 7 * 24 * 60 * 60,
+        }
+    }
+}
+
+/// Where to notify maintainers of PR syncs
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct Notifications {
+    pub matrix: Option<Matrix>,
+    /// Slack-compatible incoming webhooks
+    pub webhooks: Vec<NotificationWebhook>,
+}
+
+/// What a notification backend is sent
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum NotificationEvent {
+    /// A PR sync failed for good
+    SyncFailed,
+    /// A PR was mirrored to GitLab
+    SyncSucceeded,
+}
+
+fn default_notification_events() -> Vec<NotificationEvent> {
+    vec![NotificationEvent::SyncFailed]
+}
+
+#[derive(Debug, Deserialize)]
+pub struct Matrix {
+    /// Homeserver base URL, ex: `https://matrix.org`
+    pub homeserver: String,
+    /// Room ID, ex: `!abcdef:matrix.org`, which the bot user has joined
+    pub room_id: String,
+    pub access_token: String,
+    #[serde(default = "default_notification_events")]
+    pub events: Vec<NotificationEvent>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct NotificationWebhook {
+    /// Slack incoming webhook URL, or a Discord webhook URL ending in
+    /// `/slack`
+    pub url: String,
+    #[serde(default = "default_notification_events")]
+    pub events: Vec<NotificationEvent>,
+}
+
+/// Coordination of several LabHub replicas through Redis
+#[derive(Debug, Deserialize)]
+pub struct Redis {
+    /// ex: `redis://redis.example.com:6379/0`, or `rediss://` for TLS
+    pub url: String,
+    /// Prefix of LabHub's keys, for sharing a Redis between deployments
+    #[serde(default = "default_redis_key_prefix")]
+    pub key_prefix: String,
+    /// How long a replica may hold a repo's lock, in seconds, after which
+    /// it's released even if the replica died while syncing
+    #[serde(default = "default_redis_lock_ttl_secs")]
+    pub lock_ttl_secs: u64,
+    /// Name of this replica, which must stay the same across its restarts:
+    /// the events it was syncing when it died are recovered under it.
+    /// Defaults to `$HOSTNAME`.
+    #[serde(default = "default_redis_replica_id")]
+    pub replica_id: String,
+}
+
+fn default_redis_key_prefix() -> String {
+    "labhub".to_string()
+}
+
+fn default_redis_lock_ttl_secs() -> u64 {
+    15 * 60
+}
+
+fn default_redis_replica_id() -> String {
+    env::var("HOSTNAME").unwrap_or_else(|_| "labhub".to_string())
+}
+
+#[derive(Debug, Deserialize, PartialEq, Eq, Clone, Copy)]
+#[serde(rename_all = "snake_case")]
+pub enum SecretsProvider {
+    Vault,
+    AwsSecretsManager,
+}
+
+/// The Sentry project panics and errors are reported to, see
+/// [`crate::sentry`]
+#[derive(Debug, Deserialize)]
+pub struct Sentry {
+    /// The project's DSN, ex: `https://key@o0.ingest.sentry.io/0`, which
+    /// may be a secret reference
+    pub dsn: String,
+    /// Sentry's environment of the events, ex: `production`
+    pub environment: Option<String>,
+}
+
+/// The settings of the GitLab projects LabHub creates, see
+/// [`crate::provisioning`]. They're created in the group of the mapping's
+/// project, which must exist.
+#[derive(Debug, Deserialize)]
+#[serde(default)]
+pub struct Provisioning {
+    /// `private`, `internal` or `public`
+    pub visibility: String,
+    /// Path of the CI config in the repo, defaults to GitLab's
+    /// `.gitlab-ci.yml`
+    pub ci_config_path: Option<String>,
+    pub shared_runners_enabled: bool,
+    /// Maximum duration of the projects' jobs, in seconds
+    pub build_timeout: Option<u64>,
+}
+
+impl Default for Provisioning {
+    fn default() -> Self {
+        Provisioning {
+            visibility: "private".to_string(),
+            ci_config_path: None,
+            shared_runners_enabled: true,
+            build_timeout: None,
+        }
+    }
+}
+
+/// Which GitHub repos' webhooks are handled, so those of other repos
+/// sharing the webhook secret, ex: through an org webhook, are dropped
+/// before anything is cloned. Repos are `owner/name` or `owner/*`, matched
+/// case-insensitively.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct Repos {
+    /// Only these repos are handled, unless it's empty
+    pub allow: Vec<String>,
+    /// These repos are never handled, even if they're allowed
+    pub deny: Vec<String>,
+    /// Only the repos in the mappings or in `allow` are handled, as they
+    /// always are for org webhooks
+    pub mapped_only: bool,
+}
+
+impl Repos {
+    /// Why the webhooks of a GitHub repo are dropped, if they are.
+    /// `org_hook` is whether they're from an org webhook.
+    pub fn rejection(&self, full_name: &str, org_hook: bool) -> Option<&'static str> {
+        let listed = |patterns: &[String]| {
+            patterns
+                .iter()
+                .any(|pattern| mapping_rules::matches(pattern, full_name))
+        };
+        let allowed = listed(&self.allow);
+        if listed(&self.deny) {
+            Some("it's in the deny list")
+        } else if !self.allow.is_empty() && !allowed {
+            Some("it isn't in the allow list")
+        } else if (self.mapped_only || org_hook)
+            && !allowed
+            && mapping_for_repo(full_name).is_none()
+        {
+            Some("it isn't in the mappings")
+        } else {
+            None
+        }
+    }
+}
+
+/// Where credentials written as `secret:path#field` are fetched from, see
+/// [`crate::secrets`]
+#[derive(Debug, Deserialize)]
+pub struct Secrets {
+    pub provider: SecretsProvider,
+    /// How often secrets are fetched again, in seconds, 0 to only fetch them
+    /// on startup
+    #[serde(default = "default_secrets_refresh_secs")]
+    pub refresh_secs: u64,
+    #[serde(default)]
+    pub vault: Vault,
+    #[serde(default)]
+    pub aws: AwsSecretsManager,
+}
+
+fn default_secrets_refresh_secs() -> u64 {
+    60 * 60
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(default)]
+pub struct Vault {
+    /// ex: `https://vault.example.com:8200`, defaults to `VAULT_ADDR`
+    pub address: Option<String>,
+    /// Environment variable holding the Vault token
+    pub token_env: String,
+    /// Vault Enterprise namespace
+    pub namespace: Option<String>,
+}
+
+impl Default for Vault {
+    fn default() -> Self {
+        Vault {
+            address: None,
+            token_env: "VAULT_TOKEN".to_string(),
+            namespace: None,
+        }
+    }
+}
+
+/// AWS Secrets Manager, authenticated with the usual `AWS_ACCESS_KEY_ID`,
+/// `AWS_SECRET_ACCESS_KEY` and `AWS_SESSION_TOKEN` environment variables
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct AwsSecretsManager {
+    /// Defaults to `AWS_REGION` or `AWS_DEFAULT_REGION`
+    pub region: Option<String>,
+    /// Endpoint URL, ex: of a VPC endpoint. Defaults to the region's.
+    pub endpoint: Option<String>,
+}
+
+/// An event posted to event webhooks
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum WebhookEvent {
+    /// A PR was mirrored to GitLab
+    PrSynced,
+    /// A PR's pipeline finished
+    PipelineFinished,
+    /// A PR comment command was run
+    CommandExecuted,
+}
+
+fn default_webhook_events() -> Vec<WebhookEvent> {
+    vec![
+        WebhookEvent::PrSynced,
+        WebhookEvent::PipelineFinished,
+        WebhookEvent::CommandExecuted,
+    ]
+}
+
+#[derive(Debug, Deserialize)]
+pub struct EventWebhook {
+    pub url: String,
+    /// Key of the HMAC-SHA256 signature of each body, sent in the
+    /// `X-LabHub-Signature-256` header
+    pub secret: Option<String>,
+    #[serde(default = "default_webhook_events")]
+    pub events: Vec<WebhookEvent>,
+}
+
+/// Verification of the host keys of SSH git connections. Without either
+/// setting, host keys aren't verified.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct Ssh {
+    /// known_hosts file host keys are checked against, ex: the output of
+    /// `ssh-keyscan github.com gitlab.com`
+    pub known_hosts: Option<String>,
+    /// Pinned SHA256 fingerprints of host keys by hostname, as printed by
+    /// `ssh-keygen -l`. The git CLI only checks `known_hosts`.
+    pub host_key_fingerprints: HashMap<String, Vec<String>>,
+}
+
+/// Egress proxy settings for git connections
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct Proxy {
+    /// Proxy for git over HTTP(S), ex: `http://proxy.example.com:3128`
+    pub url: Option<String>,
+    /// SSH `ProxyCommand`, ex: `nc -X 5 -x proxy.example.com:1080 %h %p`.
+    /// libgit2 can't proxy SSH, so git then runs through the git CLI.
+    pub ssh_command: Option<String>,
+}
+
+/// Which PR authors are trusted, and how their pipelines differ from those
+/// of untrusted authors
+#[derive(Debug, Deserialize)]
+#[serde(default)]
+pub struct Trust {
+    /// Logins of authors whose PRs are always trusted
+    pub trusted_users: Vec<String>,
+    /// GitHub author associations which are trusted, ex: `MEMBER`
+    pub trusted_associations: Vec<String>,
+    /// Paths which PRs from untrusted authors may only change with a
+    /// maintainer's approval, with the `protected_paths` feature. A trailing
+    /// `/` matches a whole directory, `*` any part of a file name and `**`
+    /// any number of directories.
+    pub protected_paths: Vec<String>,
+    pub trusted: PipelineProfile,
+    pub untrusted: PipelineProfile,
+}
+
+impl Default for Trust {
+    fn default() -> Self {
+        Trust {
+            trusted_users: vec![],
+            trusted_associations: vec![
+                "OWNER".to_string(),
+                "MEMBER".to_string(),
+                "COLLABORATOR".to_string(),
+            ],
+            protected_paths: vec![".gitlab-ci.yml".to_string()],
+            trusted: PipelineProfile::default(),
+            untrusted: PipelineProfile::default(),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(default)]
+pub struct PipelineProfile {
+    /// Prefix of the GitLab branch names, ex: `pr` for
+    /// `pr-12/owner/repo/branch`
+    pub branch_prefix: String,
+    /// Template of the GitLab branch names, with the `{prefix}`, `{number}`,
+    /// `{head_full_name}` and `{ref}` placeholders, ex: `gh-{number}`
+    pub branch_template: String,
+    /// CI variables for the pipelines LabHub creates itself
+    pub variables: HashMap<String, String>,
+    /// Git push options sent with PR branches, ex: `ci.skip` or
+    /// `ci.variable=FOO=bar`
+    pub push_options: Vec<String>,
+    /// Path of a trusted `.gitlab-ci.yml` replacing the one of fork PRs
+    /// pushed with this profile, in a generated commit on top of their head
+    pub ci_config: Option<String>,
+}
+
+pub const DEFAULT_BRANCH_TEMPLATE: &str = "{prefix}-{number}/{head_full_name}/{ref}";
+
+lazy_static! {
+    static ref PLACEHOLDER: regex::Regex = regex::Regex::new(r"\{([^{}]*)\}").unwrap();
+}
+
+impl Default for PipelineProfile {
+    fn default() -> Self {
+        PipelineProfile {
+            branch_prefix: "pr".to_string(),
+            branch_template: DEFAULT_BRANCH_TEMPLATE.to_string(),
+            variables: HashMap::new(),
+            push_options: vec![],
+            ci_config: None,
+        }
+    }
+}
+
+impl PipelineProfile {
+    /// Name of the GitLab branch of a PR pushed with this profile
+    pub fn branch_name(&self, number: i64, head_full_name: &str, gitref: &str) -> String {
+        ref_name::branch(&self.expand_branch_template(number, head_full_name, gitref))
+    }
+
+    /// The branch template with its placeholders filled in, before it's made
+    /// a valid branch name
+    fn expand_branch_template(&self, number: i64, head_full_name: &str, gitref: &str) -> String {
+        PLACEHOLDER
+            .replace_all(&self.branch_template, |cap: &regex::Captures| {
+                match &cap[1] {
+                    "prefix" => self.branch_prefix.clone(),
+                    "number" => number.to_string(),
+                    "head_full_name" => head_full_name.to_string(),
+                    "ref" => gitref.to_string(),
+                    _ => cap[0].to_string(),
+                }
+            })
+            .into_owned()
+    }
+
+    /// What the names of the branches pushed with this profile start with,
+    /// up to the PR number, ex: `pr-`
+    pub fn branch_name_start(&self) -> String {
+        let start = match self.branch_template.split_once("{number}") {
+            Some((start, _)) => start,
+            None => &self.branch_template,
+        };
+        start.replace("{prefix}", &self.branch_prefix)
+    }
+
+    /// The PR number of a branch pushed with this profile, if it is one
+    pub fn pr_number_from_branch(&self, branch: &str) -> Option<i64> {
+        let (_, after) = self.branch_template.split_once("{number}")?;
+        let rest = branch.strip_prefix(&self.branch_name_start())?;
+        let digits = rest.len() - rest.trim_start_matches(|c: char| c.is_ascii_digit()).len();
+        let (number, rest) = rest.split_at(digits);
+        // the literal text following the number delimits it
+        let delimiter = after.split('{').next().unwrap_or("");
+        let delimited = if after.is_empty() {
+            rest.is_empty()
+        } else {
+            rest.starts_with(delimiter)
+        };
+        if !delimited {
+            return None;
+        }
+        number.parse().ok()
+    }
+}
+
+/// What to do with PR events for repos whose mirroring is paused
+#[derive(Debug, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum PausedEvents {
+    /// Hold them in memory, and sync them once mirroring resumes
+    Queue,
+    Drop,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(default)]
+pub struct Pause {
+    pub paused_events: PausedEvents,
+}
+
+impl Default for Pause {
+    fn default() -> Self {
+        Pause {
+            paused_events: PausedEvents::Queue,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Default)]
+#[serde(default)]
+pub struct Submodules {
+    /// Submodule URL rewrites applied before pushing, in order
+    pub rewrites: Vec<SubmoduleRewrite>,
+}
+
+#[derive(Debug, Deserialize, PartialEq, Eq, Clone, Copy)]
+#[serde(rename_all = "snake_case")]
+pub enum SigningFormat {
+    Gpg,
+    Ssh,
+}
+
+/// Key signing the commits LabHub generates on top of PR heads, like
+/// submodule rewrites and pinned CI configs
+#[derive(Debug, Deserialize)]
+pub struct Signing {
+    pub format: SigningFormat,
+    /// GPG key ID, or path of the SSH private key
+    pub key: String,
+    /// Committer of the generated commits, which GitLab matches against
+    /// the key's identity to verify them
+    pub name: String,
+    pub email: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct SubmoduleRewrite {
+    /// URL prefix to replace, ex: `https://github.com/org/`
+    pub from: String,
+    /// What to replace it with, ex: `https://gitlab.com/org-mirrors/`
+    pub to: String,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(default)]
+pub struct UpstreamLimits {
+    /// Maximum number of API requests in flight at once
+    pub max_concurrent: usize,
+    /// Hold requests back until the rate limit resets once the remaining
+    /// quota drops to this
+    pub min_remaining: u64,
+    /// How many times a rate limited request is retried
+    pub max_retries: u32,
+    /// Longest wait for a rate limit to lift before retrying, in seconds.
+    /// Requests which would have to wait longer fail right away.
+    pub max_retry_wait_secs: u64,
+}
+
+impl Default for UpstreamLimits {
+    fn default() -> Self {
+        UpstreamLimits {
+            max_concurrent: 8,
+            min_remaining: 50,
+            max_retries: 3,
+            max_retry_wait_secs: 5 * 60,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Default)]
+#[serde(default)]
+pub struct Limits {
+    pub github: UpstreamLimits,
+    pub gitlab: UpstreamLimits,
 }
 
 #[derive(Debug, Deserialize)]
 pub struct Server {
     pub bindto: String,
+    /// Log the headers and bodies of requests at debug level, redacted
+    #[serde(default)]
+    pub log_bodies: bool,
+    /// Answer webhooks with a 503 while this many PR syncs are queued, 0
+    /// for no limit. Forges don't redeliver them, so their events are lost
+    /// unless they're replayed.
+    #[serde(default)]
+    pub max_queued_events: usize,
+    #[serde(default)]
+    pub body_limits: BodyLimits,
+    /// URL GitHub and GitLab reach LabHub at, ex:
+    /// `https://labhub.example.com`, used to set up their webhooks
+    pub public_url: Option<String>,
+}
+
+/// Largest request bodies accepted, in bytes, by route
+#[derive(Debug, Deserialize)]
+#[serde(default)]
+pub struct BodyLimits {
+    /// Limit of the routes without one of their own
+    pub default: usize,
+    /// Limits of the webhook routes, ex: `github` for `/github/events`
+    pub github: Option<usize>,
+    pub gitlab: Option<usize>,
+    pub gitea: Option<usize>,
+    pub bitbucket: Option<usize>,
+}
+
+impl Default for BodyLimits {
+    fn default() -> Self {
+        BodyLimits {
+            default: crate::MAX_BODY_LENGTH,
+            github: None,
+            gitlab: None,
+            gitea: None,
+            bitbucket: None,
+        }
+    }
+}
+
+impl BodyLimits {
+    /// The limit of the `/{forge}/events` route
+    pub fn webhook(&self, forge: &str) -> usize {
+        match forge {
+            "github" => self.github,
+            "gitlab" => self.gitlab,
+            "gitea" => self.gitea,
+            "bitbucket" => self.bitbucket,
+            _ => None,
+        }
+        .unwrap_or(self.default)
+    }
+
+    /// The largest of the limits
+    pub fn max(&self) -> usize {
+        [self.github, self.gitlab, self.gitea, self.bitbucket]
+            .into_iter()
+            .flatten()
+            .fold(self.default, usize::max)
+    }
 }
 
 #[derive(Debug, Deserialize)]
 pub struct Site {
     pub webhook_secret: String,
     pub username: String,
+    /// Path to the SSH private key, optional with `ssh_agent`
+    #[serde(default)]
     pub ssh_key: String,
+    /// Passphrase of an encrypted `ssh_key`
+    pub ssh_key_passphrase: Option<String>,
+    /// Environment variable overriding `ssh_key_passphrase`, to keep it out
+    /// of the config file
+    pub ssh_key_passphrase_env: Option<String>,
+    /// Authenticate with the keys of the ssh-agent at `SSH_AUTH_SOCK`,
+    /// before trying `ssh_key`
+    #[serde(default)]
+    pub ssh_agent: bool,
     pub api_token: String,
     pub hostname: Option<String>,
     pub ssh_url: Option<String>,
 }
 
+impl Site {
+    /// The API token, fetched from the secrets manager if it's a reference
+    pub fn api_token(&self) -> String {
+        secrets::resolve(&self.api_token)
+    }
+
+    /// The webhook secret, fetched from the secrets manager if it's a
+    /// reference
+    pub fn webhook_secret(&self) -> String {
+        secrets::resolve(&self.webhook_secret)
+    }
+
+    /// Path of the SSH key, written out from the secrets manager if
+    /// `ssh_key` is a reference
+    pub fn ssh_key_path(&self) -> String {
+        secrets::ssh_key_path(&self.ssh_key)
+    }
+
+    /// The passphrase of the SSH key, from `ssh_key_passphrase_env` if it's
+    /// set
+    pub fn ssh_key_passphrase(&self) -> Option<String> {
+        self.ssh_key_passphrase_env
+            .as_deref()
+            .and_then(|name| env::var(name).ok())
+            .or_else(|| self.ssh_key_passphrase.as_deref().map(secrets::resolve))
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct GithubInstance {
+    /// Name mappings use to pick this instance with `github_instance`
+    #[serde(default = "default_instance_name")]
+    pub name: String,
+    /// API base URL, defaults to `https://api.{hostname}`. GitHub Enterprise
+    /// Server uses `https://{hostname}/api/v3`.
+    pub api_url: Option<String>,
+    /// Webhook secrets of orgs whose hooks don't use `webhook_secret`, by
+    /// org name
+    #[serde(default)]
+    pub org_webhook_secrets: HashMap<String, String>,
+    /// Orgs sending their events through one org webhook, which
+    /// `setup-webhooks` sets up instead of a webhook per repo
+    #[serde(default)]
+    pub org_webhooks: Vec<String>,
+    /// GitHub App to authenticate as for the APIs which tokens of users
+    /// can't use, like check runs
+    pub app: Option<GithubApp>,
+    #[serde(flatten)]
+    pub site: Site,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct GithubApp {
+    pub id: i64,
+    /// Path to the app's private key, in PEM format
+    pub private_key: String,
+}
+
+impl GithubInstance {
+    pub fn hostname(&self) -> &str {
+        self.site.hostname.as_deref().unwrap_or("github.com")
+    }
+
+    /// Every secret webhooks from this instance may be signed with: the
+    /// default one, and those of orgs
+    pub fn webhook_secrets(&self) -> Vec<String> {
+        std::iter::once(&self.site.webhook_secret)
+            .chain(self.org_webhook_secrets.values())
+            .map(|secret| secrets::resolve(secret))
+            .collect()
+    }
+
+    /// Whether the repos of `org` send their events through an org webhook
+    pub fn has_org_webhook(&self, org: &str) -> bool {
+        self.org_webhooks
+            .iter()
+            .any(|name| name.eq_ignore_ascii_case(org))
+    }
+
+    /// Returns the webhook secret for hooks from `org`, matched
+    /// case-insensitively
+    pub fn webhook_secret(&self, org: Option<&str>) -> String {
+        secrets::resolve(
+            org.and_then(|org| {
+                self.org_webhook_secrets
+                    .iter()
+                    .find(|(name, _)| name.eq_ignore_ascii_case(org))
+            })
+            .map(|(_, secret)| secret.as_str())
+            .unwrap_or(&self.site.webhook_secret),
+        )
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct GitlabInstance {
+    /// Name mappings use to pick this instance with `gitlab_instance`
+    #[serde(default = "default_instance_name")]
+    pub name: String,
+    /// API base URL, defaults to `https://{hostname}/api/v4`
+    pub api_url: Option<String>,
+    #[serde(flatten)]
+    pub site: Site,
+}
+
+fn default_instance_name() -> String {
+    "default".to_string()
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum OneOrMany<T> {
+    One(T),
+    Many(Vec<T>),
+}
+
+fn one_or_many<'de, D, T>(deserializer: D) -> Result<Vec<T>, D::Error>
+where
+    D: Deserializer<'de>,
+    T: Deserialize<'de>,
+{
+    Ok(match OneOrMany::deserialize(deserializer)? {
+        OneOrMany::One(value) => vec![value],
+        OneOrMany::Many(values) => values,
+    })
+}
+
 #[derive(Debug, Deserialize)]
 pub struct Mapping {
     pub github_repo: String,
     pub gitlab_repo: String,
+    /// Name of the GitHub instance the repo is on, defaults to the first one
+    pub github_instance: Option<String>,
+    /// GitLab project for PRs from untrusted authors, defaults to
+    /// `gitlab_repo`. It must be on the same GitLab instance.
+    pub untrusted_gitlab_repo: Option<String>,
+    /// Name of the GitLab instance to push to, defaults to the first one
+    pub gitlab_instance: Option<String>,
+    /// Language of the messages posted on the repo's PRs, defaults to the
+    /// global `language`
+    pub language: Option<Language>,
+    /// Cancel a PR's running pipeline when a new head is pushed
+    #[serde(default)]
+    pub auto_cancel: bool,
+    /// What happens to the GitLab branches of the repo's closed PRs
+    #[serde(default)]
+    pub branch_retention: BranchRetention,
+    /// Open a GitLab merge request for each PR's branch, targeting its base
+    /// branch, and keep it updated
+    #[serde(default)]
+    pub merge_requests: bool,
+}
+
+impl Mapping {
+    /// Whether this maps every repo of an org, see [`crate::mapping_rules`]
+    pub fn is_wildcard(&self) -> bool {
+        mapping_rules::is_wildcard(&self.github_repo)
+    }
+}
+
+/// What happens to the GitLab branch of a closed PR
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(tag = "policy", rename_all = "snake_case")]
+pub enum BranchRetention {
+    /// Delete it as soon as the PR is closed
+    #[default]
+    Delete,
+    /// Keep it for some days after the PR is closed, then have the periodic
+    /// cleanup delete it
+    KeepDays { days: u64 },
+    /// Keep the branches of merged PRs forever, e.g. for bisecting, and
+    /// delete those of PRs closed without merging
+    KeepMerged,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct Gitea {
+    #[serde(flatten)]
+    pub site: Site,
+    #[serde(default)]
+    pub mappings: Vec<GiteaMapping>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct GiteaMapping {
+    pub gitea_repo: String,
+    pub gitlab_repo: String,
+    pub gitlab_instance: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct Bitbucket {
+    #[serde(flatten)]
+    pub site: Site,
+    #[serde(default)]
+    pub mappings: Vec<BitbucketMapping>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct BitbucketMapping {
+    pub bitbucket_repo: String,
+    pub gitlab_repo: String,
+    pub gitlab_instance: Option<String>,
 }
 
 lazy_static! {
@@ -84,41 +1237,897 @@ lazy_static! {
     };
 }
 
+lazy_static! {
+    pub static ref GITEA_TO_LAB: Mutex<HashMap<String, String>> = {
+        let m: HashMap<String, String> = HashMap::new();
+        Mutex::new(m)
+    };
+}
+
+lazy_static! {
+    /// GitLab project → name of the GitLab instance it's on
+    static ref GITLAB_PROJECT_INSTANCES: Mutex<HashMap<String, String>> = Mutex::new(HashMap::new());
+}
+
+lazy_static! {
+    /// GitLab project → GitLab project for PRs from untrusted authors
+    static ref UNTRUSTED_PROJECTS: Mutex<HashMap<String, String>> = Mutex::new(HashMap::new());
+}
+
+/// Returns the GitLab project PRs for `project` are pushed to, depending on
+/// whether their author is trusted
+pub fn gitlab_project_for(project: &str, trusted: bool) -> String {
+    if trusted {
+        return project.to_string();
+    }
+    let untrusted = UNTRUSTED_PROJECTS.lock().unwrap().get(project).cloned();
+    untrusted
+        .or_else(|| {
+            wildcard_mappings().find_map(|mapping| {
+                let untrusted = mapping.untrusted_gitlab_repo.as_ref()?;
+                mapping_rules::apply(&mapping.gitlab_repo, untrusted, project)
+            })
+        })
+        .unwrap_or_else(|| project.to_string())
+}
+
+fn wildcard_mappings() -> impl Iterator<Item = &'static Mapping> {
+    CONFIG
+        .mappings
+        .iter()
+        .filter(|mapping| mapping.is_wildcard())
+}
+
+/// The wildcard mapping a GitHub repo matches, with the GitLab project it
+/// maps the repo to
+fn wildcard_mapping_for_repo(github_repo: &str) -> Option<(&'static Mapping, String)> {
+    wildcard_mappings().find_map(|mapping| {
+        mapping_rules::apply(&mapping.github_repo, &mapping.gitlab_repo, github_repo)
+            .map(|project| (mapping, project))
+    })
+}
+
+/// The wildcard mapping a GitLab project, or the project for untrusted PRs,
+/// matches, with the GitHub repo it's for
+fn wildcard_mapping_for_project(project: &str) -> Option<(&'static Mapping, String)> {
+    wildcard_mappings().find_map(|mapping| {
+        [
+            Some(&mapping.gitlab_repo),
+            mapping.untrusted_gitlab_repo.as_ref(),
+        ]
+        .into_iter()
+        .flatten()
+        .find_map(|pattern| mapping_rules::apply(pattern, &mapping.github_repo, project))
+        .map(|github_repo| (mapping, repo_name::lookup_key(&github_repo)))
+    })
+}
+
+/// Returns the mapping of a GitHub repo: its own, or else the first
+/// wildcard mapping it matches
+pub fn mapping_for_repo(github_repo: &str) -> Option<&'static Mapping> {
+    let key = repo_name::lookup_key(github_repo);
+    CONFIG
+        .mappings
+        .iter()
+        .filter(|mapping| !mapping.is_wildcard())
+        .find(|mapping| repo_name::lookup_key(&mapping.github_repo) == key)
+        .or_else(|| wildcard_mapping_for_repo(github_repo).map(|(mapping, _)| mapping))
+}
+
+/// Returns the GitLab project a GitHub repo is mapped to, if it's mapped
+pub fn gitlab_repo_for(github_repo: &str) -> Option<String> {
+    let mapping = mapping_for_repo(github_repo)?;
+    if mapping.is_wildcard() {
+        wildcard_mapping_for_repo(github_repo).map(|(_, project)| project)
+    } else {
+        Some(mapping.gitlab_repo.clone())
+    }
+}
+
+/// Returns the GitHub repo a GitLab project, or the project for untrusted
+/// PRs, is mapped from, if it's mapped
+pub fn github_repo_for(project: &str) -> Option<String> {
+    CONFIG
+        .mappings
+        .iter()
+        .filter(|mapping| !mapping.is_wildcard())
+        .find(|mapping| {
+            mapping.gitlab_repo == project
+                || mapping.untrusted_gitlab_repo.as_deref() == Some(project)
+        })
+        .map(|mapping| mapping.github_repo.clone())
+        .or_else(|| wildcard_mapping_for_project(project).map(|(_, github_repo)| github_repo))
+}
+
+/// Returns the GitHub repos of `org` which are mapped
+pub fn mapped_repos_of_org(org: &str) -> Vec<&'static str> {
+    CONFIG
+        .mappings
+        .iter()
+        .map(|mapping| mapping.github_repo.as_str())
+        .filter(|repo| {
+            repo.split_once('/')
+                .is_some_and(|(owner, _)| owner.eq_ignore_ascii_case(org))
+        })
+        .collect()
+}
+
+/// Returns the language of the messages posted on a GitHub repo's PRs
+pub fn language_for_repo(github_repo: &str) -> Language {
+    mapping_for_repo(github_repo)
+        .and_then(|mapping| mapping.language)
+        .unwrap_or(CONFIG.language)
+}
+
+/// What happens to the GitLab branches of a GitHub repo's closed PRs
+pub fn branch_retention_for_repo(github_repo: &str) -> BranchRetention {
+    mapping_for_repo(github_repo)
+        .map(|mapping| mapping.branch_retention)
+        .unwrap_or_default()
+}
+
+/// Whether pushes to a GitHub repo's PRs cancel their running pipeline
+pub fn auto_cancel_for_repo(github_repo: &str) -> bool {
+    mapping_for_repo(github_repo).is_some_and(|mapping| mapping.auto_cancel)
+}
+
+/// Whether a GitHub repo's PRs get a GitLab merge request
+pub fn merge_requests_for_repo(github_repo: &str) -> bool {
+    mapping_for_repo(github_repo).is_some_and(|mapping| mapping.merge_requests)
+}
+
+pub fn pipeline_profile(trusted: bool) -> &'static PipelineProfile {
+    if trusted {
+        &CONFIG.trust.trusted
+    } else {
+        &CONFIG.trust.untrusted
+    }
+}
+
+/// Profiles of the GitLab branches LabHub pushes, without two naming them
+/// alike
+pub fn branch_profiles() -> Vec<&'static PipelineProfile> {
+    let mut profiles = vec![&CONFIG.trust.trusted];
+    let untrusted = &CONFIG.trust.untrusted;
+    if (&untrusted.branch_prefix, &untrusted.branch_template)
+        != (
+            &CONFIG.trust.trusted.branch_prefix,
+            &CONFIG.trust.trusted.branch_template,
+        )
+    {
+        profiles.push(untrusted);
+    }
+    profiles
+}
+
+lazy_static! {
+    /// Canonical GitHub repo name → name of the GitHub instance it's on
+    static ref GITHUB_REPO_INSTANCES: Mutex<HashMap<String, String>> = Mutex::new(HashMap::new());
+}
+
+lazy_static! {
+    pub static ref BITBUCKET_TO_LAB: Mutex<HashMap<String, String>> = {
+        let m: HashMap<String, String> = HashMap::new();
+        Mutex::new(m)
+    };
+}
+
 fn get_labhub_toml_path() -> String {
     env::var("LABHUB_TOML").unwrap_or_else(|_| "LabHub.toml".to_string())
 }
 
-lazy_static! {
-    pub static ref CONFIG: Config = {
-        let labhub_toml_path = get_labhub_toml_path();
-        let config: Config = toml::from_str(&read_file_to_string(&labhub_toml_path)).unwrap();
-        config
+/// The active deployment environment (ex: `staging`, `production`), taken
+/// from `LABHUB_ENV`. When set, the matching overlay file is merged on top
+/// of the base config.
+pub fn get_labhub_env() -> Option<String> {
+    env::var("LABHUB_ENV").ok().filter(|e| !e.is_empty())
+}
+
+/// Returns the overlay path for an environment, ex: `LabHub.toml` with
+/// `staging` becomes `LabHub.staging.toml`.
+fn get_overlay_path(base_path: &str, environment: &str) -> String {
+    let path = Path::new(base_path);
+    let stem = path
+        .file_stem()
+        .and_then(|s| s.to_str())
+        .unwrap_or("LabHub");
+    let file_name = match path.extension().and_then(|e| e.to_str()) {
+        Some(ext) => format!("{}.{}.{}", stem, environment, ext),
+        None => format!("{}.{}", stem, environment),
     };
+    path.with_file_name(file_name)
+        .to_string_lossy()
+        .into_owned()
 }
 
-fn read_file_to_string(filename: &str) -> String {
-    let mut file = File::open(filename).expect("Unable to open the file");
+/// Deep merges `overlay` into `base`. Tables are merged key by key, any
+/// other value (including arrays) in the overlay replaces the base value.
+fn merge_toml(base: &mut toml::Value, overlay: toml::Value) {
+    match (base, overlay) {
+        (toml::Value::Table(base_table), toml::Value::Table(overlay_table)) => {
+            for (key, value) in overlay_table {
+                match base_table.get_mut(&key) {
+                    Some(base_value) => merge_toml(base_value, value),
+                    None => {
+                        base_table.insert(key, value);
+                    }
+                }
+            }
+        }
+        (base, overlay) => *base = overlay,
+    }
+}
+
+lazy_static! {
+    pub static ref CONFIG: Config = read_config().unwrap_or_else(|message| panic!("{}", message));
+}
+
+/// Reads and parses the config, with the environment's overlay merged in
+fn read_config() -> Result<Config, String> {
+    let labhub_toml_path = get_labhub_toml_path();
+    let mut value = read_toml(&labhub_toml_path)?;
+    if let Some(environment) = get_labhub_env() {
+        let overlay_path = get_overlay_path(&labhub_toml_path, &environment);
+        merge_toml(&mut value, read_toml(&overlay_path)?);
+    }
+    value
+        .try_into()
+        .map_err(|err| format!("Invalid config in {}: {}", labhub_toml_path, err))
+}
+
+fn read_toml(filename: &str) -> Result<toml::Value, String> {
     let mut contents = String::new();
-    file.read_to_string(&mut contents)
-        .expect("Unable to read the file");
-    contents
+    File::open(filename)
+        .and_then(|mut file| file.read_to_string(&mut contents))
+        .map_err(|err| format!("Unable to read {}: {}", filename, err))?;
+    toml::from_str(&contents).map_err(|err| format!("Invalid TOML in {}: {}", filename, err))
+}
+
+/// Pull request actions GitHub sends, which other forges' actions are
+/// normalized to
+const KNOWN_ACTIONS: &[&str] = &[
+    "assigned",
+    "auto_merge_disabled",
+    "auto_merge_enabled",
+    "closed",
+    "converted_to_draft",
+    "demilestoned",
+    "dequeued",
+    "edited",
+    "enqueued",
+    "labeled",
+    "locked",
+    "milestoned",
+    "opened",
+    "ready_for_review",
+    "reopened",
+    "review_request_removed",
+    "review_requested",
+    "synchronize",
+    "unassigned",
+    "unlabeled",
+    "unlocked",
+];
+
+/// Checks that `host` is a bare hostname, optionally with a port
+fn is_hostname(host: &str) -> bool {
+    match url::Url::parse(&format!("ssh://{}/", host)) {
+        Ok(url) => url.host_str().is_some() && url.username().is_empty() && url.path() == "/",
+        Err(_) => false,
+    }
+}
+
+fn validate_file(problems: &mut Vec<String>, what: &str, path: &str) {
+    if let Err(err) = File::open(path) {
+        problems.push(format!("{} {} isn't readable: {}", what, path, err));
+    }
+}
+
+fn validate_site(problems: &mut Vec<String>, name: &str, site: &Site) {
+    for (key, value) in [
+        ("webhook_secret", &site.webhook_secret),
+        ("username", &site.username),
+        ("api_token", &site.api_token),
+    ] {
+        if value.trim().is_empty() {
+            problems.push(format!("{}: {} is empty", name, key));
+        }
+    }
+    if secrets::is_reference(&site.ssh_key) {
+        // fetched from the secrets manager on startup
+    } else if !site.ssh_key.is_empty() {
+        validate_file(problems, &format!("{}: ssh_key", name), &site.ssh_key);
+    } else if !site.ssh_agent {
+        problems.push(format!("{}: ssh_key is empty, and ssh_agent is off", name));
+    }
+    if let Some(var) = site.ssh_key_passphrase_env.as_deref() {
+        if env::var(var).is_err() && site.ssh_key_passphrase.is_none() {
+            problems.push(format!(
+                "{}: ssh_key_passphrase_env {} isn't set",
+                name, var
+            ));
+        }
+    }
+    if site.ssh_agent && env::var_os("SSH_AUTH_SOCK").is_none() {
+        problems.push(format!(
+            "{}: ssh_agent is on, but SSH_AUTH_SOCK isn't set",
+            name
+        ));
+    }
+    for (key, value) in [("hostname", &site.hostname), ("ssh_url", &site.ssh_url)] {
+        if let Some(host) = value.as_deref().filter(|host| !is_hostname(host)) {
+            problems.push(format!(
+                "{}: {} {:?} isn't a hostname, like gitlab.example.com",
+                name, key, host
+            ));
+        }
+    }
+}
+
+fn validate_repo(problems: &mut Vec<String>, mapping: &str, key: &str, repo: &str) {
+    if let Err(err) = repo_name::canonicalize(repo) {
+        problems.push(format!("Mapping {}: {}: {}", mapping, key, err));
+    }
+}
+
+fn validate_project(problems: &mut Vec<String>, mapping: &str, key: &str, project: &str) {
+    let segments: Vec<&str> = project.split('/').collect();
+    if segments.len() < 2 || segments.iter().any(|segment| segment.trim().is_empty()) {
+        problems.push(format!(
+            "Mapping {}: {} {:?} isn't a GitLab project path, like group/project",
+            mapping, key, project
+        ));
+    }
+}
+
+/// Checks a GitLab instance name of a mapping, and records the project as
+/// being on it to catch projects mapped on several instances
+fn validate_gitlab_instance(
+    problems: &mut Vec<String>,
+    config: &Config,
+    projects: &mut HashMap<String, String>,
+    project: &str,
+    instance: Option<&String>,
+) {
+    let name = match instance {
+        Some(name) => name.clone(),
+        None => match config.gitlab.first() {
+            Some(instance) => instance.name.clone(),
+            None => return,
+        },
+    };
+    if !config.gitlab.iter().any(|instance| instance.name == name) {
+        problems.push(format!(
+            "Mapping {}: gitlab_instance {} isn't configured",
+            project, name
+        ));
+        return;
+    }
+    match projects.get(project) {
+        Some(existing) if *existing != name => problems.push(format!(
+            "GitLab project {} is mapped on both the {} and {} instances",
+            project, existing, name
+        )),
+        _ => {
+            projects.insert(project.to_string(), name);
+        }
+    }
+}
+
+fn validate_branch_template(problems: &mut Vec<String>, name: &str, profile: &PipelineProfile) {
+    let template = &profile.branch_template;
+    let mut problem = |message: &str| {
+        problems.push(format!(
+            "trust.{}: branch_template {:?} {}",
+            name, template, message
+        ))
+    };
+    for cap in PLACEHOLDER.captures_iter(template) {
+        if !["prefix", "number", "head_full_name", "ref"].contains(&&cap[1]) {
+            problem(&format!("has an unknown placeholder {}", &cap[0]));
+            return;
+        }
+    }
+    let Some((start, after)) = template.split_once("{number}") else {
+        problem("has no {number}");
+        return;
+    };
+    if after.contains("{number}") {
+        problem("has more than one {number}");
+    } else if start.contains("{head_full_name}") || start.contains("{ref}") {
+        problem("has {head_full_name} or {ref} before {number}");
+    } else if after.starts_with('{') {
+        problem("has a placeholder right after {number}");
+    } else {
+        let example = profile.expand_branch_template(12, "owner/repo", "branch");
+        if profile.branch_name(12, "owner/repo", "branch") != example {
+            problem(&format!(
+                "with branch_prefix {:?} isn't a valid branch name",
+                profile.branch_prefix
+            ));
+        }
+    }
+}
+
+fn validate_api_url(problems: &mut Vec<String>, name: &str, api_url: Option<&str>) {
+    if let Some(api_url) = api_url {
+        if let Err(err) = url::Url::parse(api_url) {
+            problems.push(format!(
+                "{}: api_url {:?} is invalid: {}",
+                name, api_url, err
+            ));
+        }
+    }
+}
+
+/// Checks the config for problems which would otherwise only surface when
+/// handling some event, and returns all of them
+pub fn validate(config: &Config) -> Vec<String> {
+    let mut problems = vec![];
+    if config.github.is_empty() {
+        problems.push("At least one GitHub instance must be configured".to_string());
+    }
+    if config.gitlab.is_empty() {
+        problems.push("At least one GitLab instance must be configured".to_string());
+    }
+    if let Some(public_url) = config.server.public_url.as_deref() {
+        match url::Url::parse(public_url) {
+            Ok(url) if ["http", "https"].contains(&url.scheme()) => {}
+            _ => problems.push(format!(
+                "server.public_url {:?} isn't an HTTP(S) URL",
+                public_url
+            )),
+        }
+    }
+    for forge in ["github", "gitlab", "gitea", "bitbucket"] {
+        if config.server.body_limits.webhook(forge) == 0 {
+            problems.push(format!("server.body_limits: the {} limit is 0", forge));
+        }
+    }
+    // A semaphore without permits never lets a request through
+    for (forge, limits) in [
+        ("github", &config.limits.github),
+        ("gitlab", &config.limits.gitlab),
+    ] {
+        if limits.max_concurrent == 0 {
+            problems.push(format!("limits.{}: max_concurrent is 0", forge));
+        }
+    }
+    // tokio intervals can't tick every 0 seconds
+    if config.cleanup.interval_secs == 0 {
+        problems.push("cleanup: interval_secs is 0".to_string());
+    }
+    for pattern in config.flaky.known_jobs.iter() {
+        if let Err(err) = flaky::known_job_regex(pattern) {
+            problems.push(format!(
+                "flaky: known_jobs pattern {:?} isn't a valid regex: {}",
+                pattern, err
+            ));
+        }
+    }
+
+    for instance in config.github.iter() {
+        let name = format!("GitHub instance {}", instance.name);
+        validate_site(&mut problems, &name, &instance.site);
+        validate_api_url(&mut problems, &name, instance.api_url.as_deref());
+        for (org, secret) in instance.org_webhook_secrets.iter() {
+            if secret.trim().is_empty() {
+                problems.push(format!("{}: webhook secret of {} is empty", name, org));
+            }
+        }
+        if let Some(app) = instance.app.as_ref() {
+            validate_file(
+                &mut problems,
+                &format!("{}: app private_key", name),
+                &app.private_key,
+            );
+        }
+    }
+    for instance in config.gitlab.iter() {
+        let name = format!("GitLab instance {}", instance.name);
+        validate_site(&mut problems, &name, &instance.site);
+        validate_api_url(&mut problems, &name, instance.api_url.as_deref());
+    }
+    if let Some(gitea) = config.gitea.as_ref() {
+        validate_site(&mut problems, "Gitea", &gitea.site);
+    }
+    if let Some(bitbucket) = config.bitbucket.as_ref() {
+        validate_site(&mut problems, "Bitbucket", &bitbucket.site);
+    }
+    for reference in secrets::references(config) {
+        match secrets::Reference::parse(reference) {
+            Ok(parsed) if parsed.is_managed() && config.secrets.is_none() => {
+                problems.push(format!(
+                    "{} is a secret reference, but no [secrets] provider is configured",
+                    reference
+                ))
+            }
+            Ok(_) => {}
+            Err(err) => problems.push(format!("secrets: {}", err)),
+        }
+    }
+    if let Some(sentry) = config.sentry.as_ref() {
+        if !secrets::is_reference(&sentry.dsn) {
+            if let Err(err) = sentry::Dsn::parse(&sentry.dsn) {
+                problems.push(format!("sentry: {}", err));
+            }
+        }
+    }
+    if let Some(provisioning) = config.provisioning.as_ref() {
+        if !["private", "internal", "public"].contains(&provisioning.visibility.as_str()) {
+            problems.push(format!(
+                "provisioning: visibility {:?} isn't private, internal or public",
+                provisioning.visibility
+            ));
+        }
+    }
+    for (field, patterns) in [("allow", &config.repos.allow), ("deny", &config.repos.deny)] {
+        for pattern in patterns {
+            let repo = mapping_rules::prefix(pattern)
+                .map(|owner| format!("{}/repo", owner))
+                .unwrap_or_else(|| pattern.clone());
+            if repo_name::canonicalize(&repo).is_err() {
+                problems.push(format!(
+                    "repos: {} entry {:?} isn't a repo, like owner/name or owner/*",
+                    field, pattern
+                ));
+            }
+        }
+    }
+    if let Err(err) = host_keys::TrustedKeys::load(&config.ssh) {
+        problems.push(format!("ssh: {}", err));
+    }
+    if let Some(signing) = config.signing.as_ref() {
+        for (key, value) in [
+            ("key", &signing.key),
+            ("name", &signing.name),
+            ("email", &signing.email),
+        ] {
+            if value.trim().is_empty() {
+                problems.push(format!("signing: {} is empty", key));
+            }
+        }
+        if signing.format == SigningFormat::Ssh {
+            validate_file(&mut problems, "signing: key", &signing.key);
+        }
+    }
+    if let Some(redis) = config.redis.as_ref() {
+        if let Err(err) = redis::Client::open(redis.url.as_str()) {
+            problems.push(format!("redis: url is invalid: {}", err));
+        }
+        if redis.lock_ttl_secs == 0 {
+            problems.push("redis: lock_ttl_secs must be positive".to_string());
+        }
+    }
+    for webhook in config.event_webhooks.iter() {
+        match url::Url::parse(&webhook.url) {
+            Ok(url) if ["http", "https"].contains(&url.scheme()) => {}
+            Ok(_) => problems.push(format!(
+                "event_webhooks: url {:?} isn't an HTTP(S) URL",
+                webhook.url
+            )),
+            Err(err) => problems.push(format!(
+                "event_webhooks: url {:?} is invalid: {}",
+                webhook.url, err
+            )),
+        }
+        if webhook.secret.as_deref().map(str::trim) == Some("") {
+            problems.push(format!(
+                "event_webhooks: secret of {} is empty",
+                webhook.url
+            ));
+        }
+    }
+    for (name, profile) in [
+        ("trusted", &config.trust.trusted),
+        ("untrusted", &config.trust.untrusted),
+    ] {
+        if let Some(path) = profile.ci_config.as_deref() {
+            validate_file(&mut problems, &format!("trust.{}: ci_config", name), path);
+        }
+        validate_branch_template(&mut problems, name, profile);
+    }
+
+    let mut repos: HashMap<String, String> = HashMap::new();
+    let mut projects: HashMap<String, String> = HashMap::new();
+    for mapping in config.mappings.iter() {
+        let name = &mapping.github_repo;
+        let projects_of_mapping = [
+            Some(&mapping.gitlab_repo),
+            mapping.untrusted_gitlab_repo.as_ref(),
+        ];
+        if projects_of_mapping
+            .iter()
+            .flatten()
+            .any(|project| mapping_rules::is_wildcard(project))
+            || mapping.is_wildcard()
+        {
+            for project in projects_of_mapping.into_iter().flatten() {
+                if let Some(problem) = mapping_rules::check(&mapping.github_repo, project) {
+                    problems.push(format!("Mapping {}: {}", name, problem));
+                }
+            }
+        } else {
+            validate_repo(&mut problems, name, "github_repo", &mapping.github_repo);
+            validate_project(&mut problems, name, "gitlab_repo", &mapping.gitlab_repo);
+            if let Some(untrusted) = mapping.untrusted_gitlab_repo.as_deref() {
+                validate_project(&mut problems, name, "untrusted_gitlab_repo", untrusted);
+            }
+        }
+
+        let instance = mapping
+            .github_instance
+            .clone()
+            .or_else(|| config.github.first().map(|instance| instance.name.clone()));
+        if let Some(instance) = instance {
+            if !config.github.iter().any(|github| github.name == instance) {
+                problems.push(format!(
+                    "Mapping {}: github_instance {} isn't configured",
+                    name, instance
+                ));
+            } else {
+                let key = repo_name::lookup_key(&mapping.github_repo);
+                match repos.get(&key) {
+                    Some(existing) if *existing != instance => problems.push(format!(
+                        "GitHub repo {} is mapped on both the {} and {} instances",
+                        name, existing, instance
+                    )),
+                    _ => {
+                        repos.insert(key, instance);
+                    }
+                }
+            }
+        }
+        for project in [
+            Some(&mapping.gitlab_repo),
+            mapping.untrusted_gitlab_repo.as_ref(),
+        ]
+        .into_iter()
+        .flatten()
+        {
+            validate_gitlab_instance(
+                &mut problems,
+                config,
+                &mut projects,
+                project,
+                mapping.gitlab_instance.as_ref(),
+            );
+        }
+    }
+    for (forge, repo, project, instance) in config
+        .gitea
+        .iter()
+        .flat_map(|gitea| gitea.mappings.iter())
+        .map(|m| {
+            (
+                "gitea_repo",
+                &m.gitea_repo,
+                &m.gitlab_repo,
+                &m.gitlab_instance,
+            )
+        })
+        .chain(
+            config
+                .bitbucket
+                .iter()
+                .flat_map(|bitbucket| bitbucket.mappings.iter())
+                .map(|m| {
+                    (
+                        "bitbucket_repo",
+                        &m.bitbucket_repo,
+                        &m.gitlab_repo,
+                        &m.gitlab_instance,
+                    )
+                }),
+        )
+    {
+        validate_repo(&mut problems, repo, forge, repo);
+        validate_project(&mut problems, repo, "gitlab_repo", project);
+        validate_gitlab_instance(
+            &mut problems,
+            config,
+            &mut projects,
+            project,
+            instance.as_ref(),
+        );
+    }
+
+    if config.features.contains(&Feature::Commands) && config.commands.enabled_commands.is_empty() {
+        problems.push("The commands feature is enabled, but no enabled_commands are".to_string());
+    }
+    for action in config.actions.enabled_actions.iter() {
+        if !KNOWN_ACTIONS.contains(&action.as_str()) {
+            problems.push(format!(
+                "Unknown pull request action {:?} in enabled_actions",
+                action
+            ));
+        }
+    }
+    problems
+}
+
+fn find_github_instance(name: &str) -> Option<&'static GithubInstance> {
+    CONFIG.github.iter().find(|instance| instance.name == name)
+}
+
+/// Returns the GitHub instance with the given hostname, used to pick the
+/// instance a webhook came from.
+pub fn github_for_hostname(hostname: &str) -> Option<&'static GithubInstance> {
+    CONFIG
+        .github
+        .iter()
+        .find(|instance| instance.hostname().eq_ignore_ascii_case(hostname))
+}
+
+/// Returns the GitHub instance a repo is mapped on, if it's mapped at all
+pub fn github_instance_of_repo(full_name: &str) -> Option<&'static GithubInstance> {
+    let name = GITHUB_REPO_INSTANCES
+        .lock()
+        .unwrap()
+        .get(&repo_name::lookup_key(full_name))
+        .cloned();
+    let name = name.or_else(|| {
+        let (mapping, _) = wildcard_mapping_for_repo(full_name)?;
+        Some(
+            mapping
+                .github_instance
+                .clone()
+                .unwrap_or_else(|| CONFIG.github[0].name.clone()),
+        )
+    });
+    name.and_then(|name| find_github_instance(&name))
+}
+
+/// Returns the GitHub instance a repo is on, as set by the `github_instance`
+/// of its mapping. Repos without a mapping are assumed to be on the first
+/// instance.
+pub fn github_for_repo(full_name: &str) -> &'static GithubInstance {
+    github_instance_of_repo(full_name).unwrap_or(&CONFIG.github[0])
+}
+
+fn register_github_repo(full_name: &str, instance: Option<&String>) {
+    let name = instance
+        .cloned()
+        .unwrap_or_else(|| CONFIG.github[0].name.clone());
+    if find_github_instance(&name).is_none() {
+        panic!(
+            "Mapping for {} uses unknown GitHub instance {}",
+            full_name, name
+        );
+    }
+    let mut instances = GITHUB_REPO_INSTANCES.lock().unwrap();
+    if let Some(existing) = instances.get(full_name) {
+        if *existing != name {
+            panic!(
+                "GitHub repo {} is mapped on both the {} and {} instances",
+                full_name, existing, name
+            );
+        }
+    }
+    instances.insert(full_name.to_string(), name);
+}
+
+fn find_gitlab_instance(name: &str) -> Option<&'static GitlabInstance> {
+    CONFIG.gitlab.iter().find(|instance| instance.name == name)
+}
+
+/// Returns the GitLab instance a project is on, as set by the
+/// `gitlab_instance` of the mapping it's in. Projects without a mapping are
+/// assumed to be on the first instance.
+pub fn gitlab_for_project(project: &str) -> &'static GitlabInstance {
+    let name = GITLAB_PROJECT_INSTANCES
+        .lock()
+        .unwrap()
+        .get(project)
+        .cloned();
+    let name = name.or_else(|| {
+        let (mapping, _) = wildcard_mapping_for_project(project)?;
+        mapping.gitlab_instance.clone()
+    });
+    name.and_then(|name| find_gitlab_instance(&name))
+        .unwrap_or(&CONFIG.gitlab[0])
+}
+
+fn register_gitlab_project(project: &str, instance: Option<&String>) {
+    let name = instance
+        .cloned()
+        .unwrap_or_else(|| CONFIG.gitlab[0].name.clone());
+    if find_gitlab_instance(&name).is_none() {
+        panic!(
+            "Mapping for {} uses unknown GitLab instance {}",
+            project, name
+        );
+    }
+    let mut instances = GITLAB_PROJECT_INSTANCES.lock().unwrap();
+    if let Some(existing) = instances.get(project) {
+        if *existing != name {
+            panic!(
+                "GitLab project {} is mapped on both the {} and {} instances",
+                project, existing, name
+            );
+        }
+    }
+    instances.insert(project.to_string(), name);
 }
 
-pub fn load_config() {
+/// Loads and validates the config, and builds the repo mappings. Call this
+/// once at startup, before handling any events. All the problems found are
+/// reported at once.
+pub fn load_config() -> Result<(), GitError> {
+    let problems = match read_config() {
+        Ok(config) => validate(&config),
+        Err(message) => vec![message],
+    };
+    if !problems.is_empty() {
+        return Err(GitError::Config(format!(
+            "Invalid config, found {} problem(s):\n  - {}",
+            problems.len(),
+            problems.join("\n  - ")
+        )));
+    }
     info!(
         "Loaded LabHub configuration values from {}",
         get_labhub_toml_path()
     );
+    if let Some(environment) = get_labhub_env() {
+        info!(
+            "Applied {} overlay from {}",
+            environment,
+            get_overlay_path(&get_labhub_toml_path(), &environment)
+        );
+    }
     info!("CONFIG => {:#?}", Paint::red(&*CONFIG));
 
-    for mapping in CONFIG.mappings.iter() {
+    // Wildcard mappings are matched when looking repos up. Malformed repo
+    // names were reported by validate, so the keys are well formed.
+    for mapping in CONFIG
+        .mappings
+        .iter()
+        .filter(|mapping| !mapping.is_wildcard())
+    {
         let mut hub_to_lab_lock = HUB_TO_LAB.lock();
         let hub_to_lab = hub_to_lab_lock.as_mut().unwrap();
-        hub_to_lab.insert(mapping.github_repo.clone(), mapping.gitlab_repo.clone());
+        hub_to_lab.insert(
+            repo_name::lookup_key(&mapping.github_repo),
+            mapping.gitlab_repo.clone(),
+        );
+        register_github_repo(
+            &repo_name::lookup_key(&mapping.github_repo),
+            mapping.github_instance.as_ref(),
+        );
 
         let mut lab_to_hub_lock = LAB_TO_HUB.lock();
         let lab_to_hub = lab_to_hub_lock.as_mut().unwrap();
         lab_to_hub.insert(mapping.gitlab_repo.clone(), mapping.github_repo.clone());
+        register_gitlab_project(&mapping.gitlab_repo, mapping.gitlab_instance.as_ref());
+        if let Some(untrusted) = mapping.untrusted_gitlab_repo.as_ref() {
+            lab_to_hub.insert(untrusted.clone(), mapping.github_repo.clone());
+            register_gitlab_project(untrusted, mapping.gitlab_instance.as_ref());
+            UNTRUSTED_PROJECTS
+                .lock()
+                .unwrap()
+                .insert(mapping.gitlab_repo.clone(), untrusted.clone());
+        }
+    }
+    if let Some(gitea) = CONFIG.gitea.as_ref() {
+        let mut gitea_to_lab = GITEA_TO_LAB.lock().unwrap();
+        for mapping in gitea.mappings.iter() {
+            gitea_to_lab.insert(
+                repo_name::lookup_key(&mapping.gitea_repo),
+                mapping.gitlab_repo.clone(),
+            );
+            register_gitlab_project(&mapping.gitlab_repo, mapping.gitlab_instance.as_ref());
+        }
+    }
+    if let Some(bitbucket) = CONFIG.bitbucket.as_ref() {
+        let mut bitbucket_to_lab = BITBUCKET_TO_LAB.lock().unwrap();
+        for mapping in bitbucket.mappings.iter() {
+            bitbucket_to_lab.insert(
+                repo_name::lookup_key(&mapping.bitbucket_repo),
+                mapping.gitlab_repo.clone(),
+            );
+            register_gitlab_project(&mapping.gitlab_repo, mapping.gitlab_instance.as_ref());
+        }
     }
     info!(
         "HUB_TO_LAB => {:#?}",
@@ -128,4 +2137,466 @@ pub fn load_config() {
         "LAB_TO_HUB => {:#?}",
         Paint::red(LAB_TO_HUB.lock().unwrap())
     );
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_ssh_key_passphrase() {
+        let mut site: Site = toml::from_str(
+            r#"
+webhook_secret = "secret"
+username = "ci-user"
+ssh_agent = true
+api_token = "token"
+"#,
+        )
+        .unwrap();
+        assert_eq!(site.ssh_key, "");
+        assert_eq!(site.ssh_key_passphrase(), None);
+        site.ssh_key_passphrase = Some("from config".to_string());
+        site.ssh_key_passphrase_env = Some("LABHUB_TEST_SSH_KEY_PASSPHRASE".to_string());
+        assert_eq!(site.ssh_key_passphrase().as_deref(), Some("from config"));
+        env::set_var("LABHUB_TEST_SSH_KEY_PASSPHRASE", "from env");
+        assert_eq!(site.ssh_key_passphrase().as_deref(), Some("from env"));
+    }
+
+    #[test]
+    fn test_get_overlay_path() {
+        assert_eq!(
+            get_overlay_path("LabHub.toml", "staging"),
+            "LabHub.staging.toml"
+        );
+        assert_eq!(
+            get_overlay_path("/etc/labhub/LabHub.toml", "prod"),
+            "/etc/labhub/LabHub.prod.toml"
+        );
+        assert_eq!(get_overlay_path("labhub", "prod"), "labhub.prod");
+    }
+
+    #[test]
+    fn test_merge_toml() {
+        let mut base: toml::Value = toml::from_str(
+            r#"
+features = ["external_pr", "commands"]
+[server]
+bindto = "127.0.0.1:12345"
+[github]
+username = "ci-user"
+api_token = "token"
+"#,
+        )
+        .unwrap();
+        let overlay: toml::Value = toml::from_str(
+            r#"
+features = ["external_pr"]
+[github]
+api_token = "staging-token"
+"#,
+        )
+        .unwrap();
+        merge_toml(&mut base, overlay);
+
+        assert_eq!(base["features"].as_array().unwrap().len(), 1);
+        assert_eq!(base["server"]["bindto"].as_str(), Some("127.0.0.1:12345"));
+        assert_eq!(base["github"]["username"].as_str(), Some("ci-user"));
+        assert_eq!(base["github"]["api_token"].as_str(), Some("staging-token"));
+    }
+
+    #[derive(Deserialize)]
+    struct GithubOnly {
+        #[serde(deserialize_with = "one_or_many")]
+        github: Vec<GithubInstance>,
+    }
+
+    #[test]
+    fn test_github_instances() {
+        let many: GithubOnly = toml::from_str(
+            r#"
+[[github]]
+webhook_secret = "secret"
+username = "ci-user"
+ssh_key = "/etc/labhub/ssh/github"
+api_token = "token"
+
+[[github]]
+name = "enterprise"
+webhook_secret = "other-secret"
+username = "ci-user"
+ssh_key = "/etc/labhub/ssh/ghe"
+api_token = "other-token"
+hostname = "ghe.example.com"
+api_url = "https://ghe.example.com/api/v3"
+
+[github.org_webhook_secrets]
+Platform = "platform-secret"
+"#,
+        )
+        .unwrap();
+        assert_eq!(many.github.len(), 2);
+        assert_eq!(many.github[0].name, "default");
+        assert_eq!(many.github[0].hostname(), "github.com");
+        assert_eq!(many.github[1].hostname(), "ghe.example.com");
+        assert_eq!(
+            many.github[1].api_url.as_deref(),
+            Some("https://ghe.example.com/api/v3")
+        );
+        assert_eq!(many.github[0].webhook_secret(Some("platform")), "secret");
+        assert_eq!(
+            many.github[1].webhook_secret(Some("platform")),
+            "platform-secret"
+        );
+        assert_eq!(many.github[1].webhook_secret(Some("other")), "other-secret");
+        assert_eq!(many.github[1].webhook_secret(None), "other-secret");
+    }
+
+    #[derive(Deserialize)]
+    struct GitlabOnly {
+        #[serde(deserialize_with = "one_or_many")]
+        gitlab: Vec<GitlabInstance>,
+    }
+
+    #[test]
+    fn test_gitlab_instances() {
+        let single: GitlabOnly = toml::from_str(
+            r#"
+[gitlab]
+webhook_secret = "secret"
+username = "ci-user"
+ssh_key = "/etc/labhub/ssh/gitlab"
+api_token = "token"
+"#,
+        )
+        .unwrap();
+        assert_eq!(single.gitlab.len(), 1);
+        assert_eq!(single.gitlab[0].name, "default");
+        assert_eq!(single.gitlab[0].site.api_token, "token");
+
+        let many: GitlabOnly = toml::from_str(
+            r#"
+[[gitlab]]
+webhook_secret = "secret"
+username = "ci-user"
+ssh_key = "/etc/labhub/ssh/gitlab"
+api_token = "token"
+
+[[gitlab]]
+name = "internal"
+webhook_secret = "other-secret"
+username = "ci-user"
+ssh_key = "/etc/labhub/ssh/internal"
+api_token = "other-token"
+hostname = "gitlab.example.com"
+"#,
+        )
+        .unwrap();
+        assert_eq!(many.gitlab.len(), 2);
+        assert_eq!(many.gitlab[1].name, "internal");
+        assert_eq!(
+            many.gitlab[1].site.hostname.as_deref(),
+            Some("gitlab.example.com")
+        );
+    }
+
+    #[test]
+    fn test_validate() {
+        let key = concat!(
+            env!("CARGO_MANIFEST_DIR"),
+            "/src/testdata/github_app_key.pem"
+        );
+        let config: Config = toml::from_str(&format!(
+            r#"
+mappings = [
+    {{ github_repo = "org/repo", gitlab_repo = "group/repo" }},
+    {{ github_repo = "org/other", gitlab_repo = "repo", github_instance = "enterprise" }},
+    {{ github_repo = "not a repo", gitlab_repo = "group/repo", gitlab_instance = "internal" }},
+]
+features = ["commands"]
+
+[server]
+bindto = "127.0.0.1:12345"
+
+[github]
+webhook_secret = "secret"
+username = "ci-user"
+ssh_key = "{key}"
+api_token = " "
+
+[gitlab]
+webhook_secret = "secret"
+username = "ci-user"
+ssh_key = "/nonexistent/labhub/key"
+api_token = "token"
+hostname = "https://gitlab.example.com"
+
+[commands]
+enabled_commands = []
+
+[actions]
+enabled_actions = ["opened", "synchronised"]
+
+[[event_webhooks]]
+url = "ftp://hooks.example.com/labhub"
+"#
+        ))
+        .unwrap();
+        let problems = validate(&config);
+        assert_eq!(problems.len(), 10, "{:#?}", problems);
+        assert_eq!(problems[0], "GitHub instance default: api_token is empty");
+        assert!(problems[1].starts_with(
+            "GitLab instance default: ssh_key /nonexistent/labhub/key isn't readable"
+        ));
+        assert_eq!(
+            problems[2..],
+            [
+                "GitLab instance default: hostname \"https://gitlab.example.com\" isn't a \
+                 hostname, like gitlab.example.com",
+                "event_webhooks: url \"ftp://hooks.example.com/labhub\" isn't an HTTP(S) URL",
+                "Mapping org/other: gitlab_repo \"repo\" isn't a GitLab project path, like \
+                 group/project",
+                "Mapping org/other: github_instance enterprise isn't configured",
+                "Mapping not a repo: github_repo: Malformed repo full name \"not a repo\", \
+                 expected owner/name",
+                "Mapping group/repo: gitlab_instance internal isn't configured",
+                "The commands feature is enabled, but no enabled_commands are",
+                "Unknown pull request action \"synchronised\" in enabled_actions",
+            ]
+        );
+
+        assert!(is_hostname("gitlab.example.com"));
+        assert!(is_hostname("gitlab.example.com:2222"));
+        assert!(!is_hostname("gitlab.example.com/group"));
+        assert!(!is_hostname("git@gitlab.example.com"));
+        assert!(!is_hostname(""));
+    }
+
+    #[test]
+    fn test_validate_zero_limits() {
+        let config: Config = toml::from_str(
+            r#"
+mappings = []
+features = []
+
+[server]
+bindto = "127.0.0.1:12345"
+
+[github]
+webhook_secret = "secret"
+username = "ci-user"
+api_token = "token"
+
+[gitlab]
+webhook_secret = "secret"
+username = "ci-user"
+api_token = "token"
+
+[commands]
+enabled_commands = []
+
+[actions]
+enabled_actions = ["opened"]
+
+[limits.gitlab]
+max_concurrent = 0
+
+[cleanup]
+interval_secs = 0
+
+[flaky]
+known_jobs = ["e2e("]
+"#,
+        )
+        .unwrap();
+        let problems = validate(&config);
+        assert!(problems.contains(&"limits.gitlab: max_concurrent is 0".to_string()));
+        assert!(!problems.iter().any(|p| p.starts_with("limits.github")));
+        assert!(problems.contains(&"cleanup: interval_secs is 0".to_string()));
+        assert!(problems
+            .iter()
+            .any(|p| p.starts_with("flaky: known_jobs pattern \"e2e(\" isn't a valid regex")));
+    }
+
+    #[test]
+    fn test_wildcard_mappings() {
+        assert_eq!(
+            gitlab_repo_for("Brndnmtthws-Labs/Widget").as_deref(),
+            Some("brndnmtthws-oss/labs/Widget")
+        );
+        assert_eq!(
+            github_repo_for("brndnmtthws-oss/labs/widget").as_deref(),
+            Some("brndnmtthws-labs/widget")
+        );
+        assert_eq!(
+            mapping_for_repo("brndnmtthws-labs/widget").map(|m| m.github_repo.as_str()),
+            Some("brndnmtthws-labs/*")
+        );
+        // single repo mappings still take precedence
+        assert_eq!(
+            gitlab_repo_for("brndnmtthws/labhub").as_deref(),
+            Some("brndnmtthws-oss/labhub")
+        );
+        assert_eq!(gitlab_repo_for("brndnmtthws-labs/team/widget"), None);
+        assert_eq!(github_repo_for("brndnmtthws-oss/labs/sub/widget"), None);
+        assert!(github_instance_of_repo("brndnmtthws-labs/widget").is_some());
+    }
+
+    #[test]
+    fn test_repos() {
+        let repos: Repos = toml::from_str(
+            r#"
+allow = ["brndnmtthws/*", "other/labhub"]
+deny = ["brndnmtthws/secret"]
+"#,
+        )
+        .unwrap();
+        assert_eq!(repos.rejection("BrndnMtthws/Conky", false), None);
+        assert_eq!(repos.rejection("other/labhub", false), None);
+        assert_eq!(
+            repos.rejection("brndnmtthws/secret", false),
+            Some("it's in the deny list")
+        );
+        assert_eq!(
+            repos.rejection("someone/else", false),
+            Some("it isn't in the allow list")
+        );
+        // allowed repos are handled from org webhooks even if unmapped
+        assert_eq!(repos.rejection("brndnmtthws/unmapped", true), None);
+
+        let mapped_only = Repos {
+            mapped_only: true,
+            ..Repos::default()
+        };
+        assert_eq!(mapped_only.rejection("brndnmtthws/labhub", false), None);
+        assert_eq!(
+            mapped_only.rejection("brndnmtthws-labs/widget", false),
+            None
+        );
+        assert_eq!(
+            mapped_only.rejection("brndnmtthws/unmapped", false),
+            Some("it isn't in the mappings")
+        );
+        let default = Repos::default();
+        assert_eq!(default.rejection("brndnmtthws/unmapped", false), None);
+        assert_eq!(
+            default.rejection("brndnmtthws/unmapped", true),
+            Some("it isn't in the mappings")
+        );
+    }
+
+    #[test]
+    fn test_branch_name() {
+        let profile = PipelineProfile::default();
+        assert_eq!(
+            profile.branch_name(12, "octocat/hello-world", "fix-typo"),
+            "pr-12/octocat/hello-world/fix-typo"
+        );
+        assert_eq!(profile.branch_name_start(), "pr-");
+        assert_eq!(
+            profile.branch_name(12, "octocat/.github", "feat/x..y.lock"),
+            "pr-12/octocat/github/feat/x.y"
+        );
+        let profile = PipelineProfile {
+            branch_prefix: "fork".to_string(),
+            branch_template: "{prefix}/{number}".to_string(),
+            ..Default::default()
+        };
+        assert_eq!(
+            profile.branch_name(12, "octocat/hello-world", "x"),
+            "fork/12"
+        );
+        assert_eq!(profile.branch_name_start(), "fork/");
+    }
+
+    #[test]
+    fn test_validate_branch_template() {
+        let problems_of = |branch_prefix: &str, branch_template: &str| {
+            let mut problems = vec![];
+            let profile = PipelineProfile {
+                branch_prefix: branch_prefix.to_string(),
+                branch_template: branch_template.to_string(),
+                ..Default::default()
+            };
+            validate_branch_template(&mut problems, "trusted", &profile);
+            problems
+        };
+        assert!(problems_of("pr", DEFAULT_BRANCH_TEMPLATE).is_empty());
+        assert!(problems_of("pr", "gh-{number}").is_empty());
+        assert!(problems_of("pr", "pr/{number}/{ref}").is_empty());
+        assert_eq!(
+            problems_of("pr", "{prefix}-{id}"),
+            ["trust.trusted: branch_template \"{prefix}-{id}\" has an unknown placeholder {id}"]
+        );
+        assert_eq!(
+            problems_of("pr", "{prefix}/{ref}"),
+            ["trust.trusted: branch_template \"{prefix}/{ref}\" has no {number}"]
+        );
+        assert_eq!(problems_of("pr", "{ref}-{number}").len(), 1);
+        assert_eq!(problems_of("pr", "pr-{number}{ref}").len(), 1);
+        assert_eq!(problems_of("pr", "{number}-{number}").len(), 1);
+        assert_eq!(
+            problems_of("p r", "{prefix}-{number}"),
+            [
+                "trust.trusted: branch_template \"{prefix}-{number}\" with branch_prefix \"p r\" \
+              isn't a valid branch name"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_actions() {
+        let actions: Actions = toml::from_str(r#"enabled_actions = ["opened"]"#).unwrap();
+        assert!(!actions.skip_drafts);
+        assert_eq!(actions.converted_to_draft, DraftPolicy::Ignore);
+        let actions: Actions = toml::from_str(
+            r#"
+enabled_actions = ["opened", "ready_for_review", "converted_to_draft"]
+skip_drafts = true
+converted_to_draft = "cancel_pipeline"
+"#,
+        )
+        .unwrap();
+        assert!(actions.skip_drafts);
+        assert_eq!(actions.converted_to_draft, DraftPolicy::CancelPipeline);
+        assert!(toml::from_str::<Actions>(
+            r#"
+enabled_actions = []
+converted_to_draft = "delete"
+"#
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn test_branch_retention() {
+        #[derive(Deserialize)]
+        struct Mappings {
+            mappings: Vec<Mapping>,
+        }
+        let config: Mappings = toml::from_str(
+            r#"
+mappings = [
+    { github_repo = "org/a", gitlab_repo = "group/a" },
+    { github_repo = "org/b", gitlab_repo = "group/b", branch_retention = { policy = "keep_days", days = 14 } },
+    { github_repo = "org/c", gitlab_repo = "group/c", branch_retention = { policy = "keep_merged" } },
+]
+"#,
+        )
+        .unwrap();
+        let retentions: Vec<BranchRetention> = config
+            .mappings
+            .iter()
+            .map(|mapping| mapping.branch_retention)
+            .collect();
+        assert_eq!(
+            retentions,
+            [
+                BranchRetention::Delete,
+                BranchRetention::KeepDays { days: 14 },
+                BranchRetention::KeepMerged
+            ]
+        );
+    }
 }
@@ -0,0 +1,97 @@
+//! Coverage comments: the test coverage GitLab extracts from a PR pipeline's
+//! job logs is compared with the base branch's latest successful pipeline,
+//! flagging drops beyond `[coverage] max_drop`.
+use crate::config;
+use crate::messages::{self, Message};
+
+/// Parses GitLab's coverage, a percentage like "83.52"
+pub fn parse(coverage: Option<&str>) -> Option<f64> {
+    coverage.and_then(|coverage| coverage.trim().parse().ok())
+}
+
+/// Renders the comparison of a PR pipeline's coverage with its base
+/// branch's, if known. `marker` is appended so the comment is refreshed by
+/// the next pipeline rather than treated as stale.
+pub fn comment_body(
+    coverage: &config::Coverage,
+    pipeline_id: i64,
+    pr_coverage: f64,
+    base_coverage: Option<f64>,
+    base_ref: &str,
+    marker: &str,
+    language: config::Language,
+) -> String {
+    let comparison = match base_coverage {
+        Some(base) => {
+            let delta = pr_coverage - base;
+            let emoji = if delta < -coverage.max_drop {
+                "⚠️"
+            } else if delta >= 0.0 {
+                "📈"
+            } else {
+                "📉"
+            };
+            let mut comparison = messages::text(
+                language,
+                Message::CoverageCompared {
+                    emoji,
+                    coverage: pr_coverage,
+                    pipeline_id,
+                    delta,
+                    base_coverage: base,
+                    base_ref,
+                },
+            );
+            if delta < -coverage.max_drop {
+                comparison.push_str(&messages::text(
+                    language,
+                    Message::CoverageDropTooLarge {
+                        max_drop: coverage.max_drop,
+                    },
+                ));
+            }
+            comparison
+        }
+        None => messages::text(
+            language,
+            Message::CoverageWithoutBase {
+                coverage: pr_coverage,
+                pipeline_id,
+                base_ref,
+            },
+        ),
+    };
+    format!("{}\n\n{}", comparison, marker)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_comment_body() {
+        const EN: config::Language = config::Language::En;
+        let coverage = config::Coverage::default();
+        assert_eq!(parse(Some("83.52")), Some(83.52));
+        assert_eq!(parse(Some("")), None);
+        assert_eq!(parse(None), None);
+        assert_eq!(
+            comment_body(&coverage, 12, 80.0, Some(81.25), "master", "<!-- m -->", EN),
+            "⚠️ Coverage is **80.00%** in pipeline 12, -1.25 points from 81.25% on `master`. \
+             That's more than the 0.50 point drop allowed.\n\n<!-- m -->"
+        );
+        assert_eq!(
+            comment_body(&coverage, 12, 81.0, Some(81.25), "master", "", EN),
+            "📉 Coverage is **81.00%** in pipeline 12, -0.25 points from 81.25% on `master`.\n\n"
+        );
+        assert_eq!(
+            comment_body(&coverage, 12, 82.0, Some(81.25), "master", "", EN),
+            "📈 Coverage is **82.00%** in pipeline 12, +0.75 points from 81.25% on `master`.\n\n"
+        );
+        assert_eq!(
+            comment_body(&coverage, 12, 82.0, None, "main", "", EN),
+            "📊 Coverage is **82.00%** in pipeline 12. There's no successful pipeline with \
+             coverage on `main` to compare it with.\n\n"
+        );
+    }
+}
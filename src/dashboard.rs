@@ -0,0 +1,364 @@
+//! A read-only HTML dashboard of the bridge's status at `/dashboard`: the
+//! repo mappings, recent syncs and pipelines, the queue, and recent
+//! failures, from the state store. Browsers log in with HTTP basic auth,
+//! using the admin token as the password.
+use crate::admin;
+use crate::audit;
+use crate::config;
+use crate::errors::{GitError, RequestErrorResult};
+use crate::health;
+use crate::queue;
+use crate::state;
+
+use axum::{
+    http::{header, HeaderValue, Request, StatusCode},
+    middleware::{self, Next},
+    response::{Html, IntoResponse, Response},
+    routing::get,
+    Router,
+};
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use log::warn;
+use maud::{html, Markup, DOCTYPE};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// How many syncs, pipelines and failures are shown
+const RECENT: i64 = 20;
+
+/// Asks for the admin token as the basic auth password, with any username
+async fn require_login<B>(request: Request<B>, next: Next<B>) -> Response {
+    let token = match config::CONFIG.admin.token.as_ref() {
+        Some(token) => token,
+        None => return StatusCode::NOT_FOUND.into_response(),
+    };
+    let password = request
+        .headers()
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Basic "))
+        .and_then(|encoded| STANDARD.decode(encoded).ok())
+        .and_then(|decoded| String::from_utf8(decoded).ok())
+        .and_then(|credentials| {
+            credentials
+                .split_once(':')
+                .map(|(_, password)| password.to_string())
+        });
+    match password {
+        Some(password) if admin::token_matches(token, &password) => next.run(request).await,
+        provided => {
+            if provided.is_some() {
+                warn!("Rejected dashboard login");
+            }
+            let mut response = StatusCode::UNAUTHORIZED.into_response();
+            response.headers_mut().insert(
+                header::WWW_AUTHENTICATE,
+                HeaderValue::from_static("Basic realm=\"LabHub\""),
+            );
+            response
+        }
+    }
+}
+
+/// A repo mapped to a GitLab project
+struct MappingRow {
+    forge: &'static str,
+    repo: String,
+    gitlab_project: String,
+    untrusted_gitlab_project: Option<String>,
+    merge_requests: bool,
+}
+
+/// A PR sync, with the status of its head's latest pipeline
+struct SyncRow {
+    sync: state::PrSync,
+    pipeline: Option<state::Pipeline>,
+}
+
+/// Everything the dashboard shows
+struct Status {
+    now: i64,
+    queue_depth: usize,
+    gitlab_error: Option<String>,
+    paused: Vec<state::PausedRepo>,
+    kill_switches: Vec<state::EngagedSwitch>,
+    mappings: Vec<MappingRow>,
+    syncs: Vec<SyncRow>,
+    pipelines: Vec<state::Pipeline>,
+    failures: Vec<state::AuditEntry>,
+}
+
+fn mappings() -> Vec<MappingRow> {
+    let github = config::CONFIG.mappings.iter().map(|m| MappingRow {
+        forge: "GitHub",
+        repo: m.github_repo.clone(),
+        gitlab_project: m.gitlab_repo.clone(),
+        untrusted_gitlab_project: m.untrusted_gitlab_repo.clone(),
+        merge_requests: m.merge_requests,
+    });
+    let gitea = config::CONFIG
+        .gitea
+        .iter()
+        .flat_map(|gitea| gitea.mappings.iter())
+        .map(|m| MappingRow {
+            forge: "Gitea",
+            repo: m.gitea_repo.clone(),
+            gitlab_project: m.gitlab_repo.clone(),
+            untrusted_gitlab_project: None,
+            merge_requests: false,
+        });
+    let bitbucket = config::CONFIG
+        .bitbucket
+        .iter()
+        .flat_map(|bitbucket| bitbucket.mappings.iter())
+        .map(|m| MappingRow {
+            forge: "Bitbucket",
+            repo: m.bitbucket_repo.clone(),
+            gitlab_project: m.gitlab_repo.clone(),
+            untrusted_gitlab_project: None,
+            merge_requests: false,
+        });
+    github.chain(gitea).chain(bitbucket).collect()
+}
+
+fn status() -> Result<Status, GitError> {
+    let syncs = state::recent_pr_syncs(None, None, RECENT)?
+        .into_iter()
+        .map(|sync| {
+            let pipeline = state::latest_pipeline(&sync.gitlab_project, &sync.head_sha)?;
+            Ok(SyncRow { sync, pipeline })
+        })
+        .collect::<Result<Vec<_>, GitError>>()?;
+    Ok(Status {
+        now: SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs() as i64)
+            .unwrap_or(0),
+        queue_depth: queue::depth(),
+        gitlab_error: health::gitlab_last_error(),
+        paused: state::paused_repos()?,
+        kill_switches: state::engaged_kill_switches()?,
+        mappings: mappings(),
+        syncs,
+        pipelines: state::recent_pipelines(None, None, RECENT)?,
+        failures: audit::query(state::AuditFilter {
+            outcome: Some("failure".to_string()),
+            limit: Some(RECENT),
+            ..Default::default()
+        })?,
+    })
+}
+
+/// How long ago a timestamp was, roughly
+fn ago(now: i64, at: i64) -> String {
+    let secs = (now - at).max(0);
+    match secs {
+        0..=59 => format!("{}s ago", secs),
+        60..=3599 => format!("{}m ago", secs / 60),
+        3600..=86399 => format!("{}h ago", secs / 3600),
+        _ => format!("{}d ago", secs / 86400),
+    }
+}
+
+fn short_sha(sha: &str) -> &str {
+    &sha[..sha.len().min(8)]
+}
+
+fn pipeline_status(status: &str) -> Markup {
+    html! { span class={ "status " (status) } { (status) } }
+}
+
+fn render(status: &Status) -> Markup {
+    html! {
+        (DOCTYPE)
+        html {
+            head {
+                meta charset="utf-8";
+                meta http-equiv="refresh" content="30";
+                title { "LabHub" }
+                style {
+                    "body { font-family: sans-serif; margin: 2em; }
+                     table { border-collapse: collapse; margin-bottom: 2em; }
+                     th, td { border-bottom: 1px solid #ddd; padding: 0.3em 0.8em; text-align: left; }
+                     code { font-size: 0.9em; }
+                     .status.success { color: #1a7f37; }
+                     .status.failed, .failure { color: #cf222e; }
+                     .status.running, .status.pending { color: #9a6700; }"
+                }
+            }
+            body {
+                h1 { "LabHub" }
+                p {
+                    "Queued PR events: " strong { (status.queue_depth) }
+                    " · GitLab: "
+                    @match &status.gitlab_error {
+                        Some(error) => span class="failure" { "unreachable (" (error) ")" },
+                        None => "reachable",
+                    }
+                }
+                @if !status.kill_switches.is_empty() {
+                    p class="failure" {
+                        "Engaged kill switches: "
+                        @for (i, switch) in status.kill_switches.iter().enumerate() {
+                            @if i > 0 { ", " }
+                            code { (switch.name) } " by " (switch.engaged_by)
+                        }
+                    }
+                }
+                @if !status.paused.is_empty() {
+                    p {
+                        "Paused repos: "
+                        @for (i, paused) in status.paused.iter().enumerate() {
+                            @if i > 0 { ", " }
+                            (paused.repo) " by " (paused.paused_by)
+                        }
+                    }
+                }
+
+                h2 { "Mappings" }
+                table {
+                    tr { th { "Forge" } th { "Repo" } th { "GitLab project" } th { "Untrusted PRs" } th { "Merge requests" } }
+                    @for mapping in &status.mappings {
+                        tr {
+                            td { (mapping.forge) }
+                            td { (mapping.repo) }
+                            td { (mapping.gitlab_project) }
+                            td { (mapping.untrusted_gitlab_project.as_deref().unwrap_or(&mapping.gitlab_project)) }
+                            td { @if mapping.merge_requests { "yes" } @else { "no" } }
+                        }
+                    }
+                }
+
+                h2 { "Recent syncs" }
+                table {
+                    tr { th { "PR" } th { "Head" } th { "GitLab branch" } th { "Pipeline" } th { "Synced" } }
+                    @for row in &status.syncs {
+                        tr {
+                            td { (row.sync.github_repo) "#" (row.sync.pr_number) }
+                            td { code { (short_sha(&row.sync.head_sha)) } }
+                            td { (row.sync.gitlab_project) " " code { (row.sync.gitlab_branch) } }
+                            td {
+                                @match &row.pipeline {
+                                    Some(pipeline) => { (pipeline.pipeline_id) " " (pipeline_status(&pipeline.status)) },
+                                    None => "none yet",
+                                }
+                            }
+                            td { (ago(status.now, row.sync.synced_at)) }
+                        }
+                    }
+                }
+
+                h2 { "Recent pipelines" }
+                table {
+                    tr { th { "Project" } th { "Pipeline" } th { "Commit" } th { "Status" } th { "Updated" } }
+                    @for pipeline in &status.pipelines {
+                        tr {
+                            td { (pipeline.gitlab_project) }
+                            td { (pipeline.pipeline_id) }
+                            td { code { (short_sha(&pipeline.sha)) } }
+                            td { (pipeline_status(&pipeline.status)) }
+                            td { (ago(status.now, pipeline.updated_at)) }
+                        }
+                    }
+                }
+
+                h2 { "Recent failures" }
+                table {
+                    tr { th { "When" } th { "Repo" } th { "Action" } th { "Target" } th { "Error" } }
+                    @for entry in &status.failures {
+                        tr {
+                            td { (ago(status.now, entry.at)) }
+                            td {
+                                (entry.repo)
+                                @if let Some(number) = entry.pr_number { "#" (number) }
+                            }
+                            td { (entry.action) }
+                            td { code { (entry.target) } }
+                            td class="failure" { (entry.error.as_deref().unwrap_or("")) }
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+async fn dashboard() -> Result<Html<String>, RequestErrorResult> {
+    Ok(Html(render(&status()?).into_string()))
+}
+
+/// Builds the `/dashboard` route, which requires the admin token
+pub fn router() -> Router {
+    Router::new()
+        .route("/", get(dashboard))
+        .route_layer(middleware::from_fn(require_login))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_ago() {
+        assert_eq!(ago(100, 100), "0s ago");
+        assert_eq!(ago(100, 200), "0s ago");
+        assert_eq!(ago(200, 100), "1m ago");
+        assert_eq!(ago(7300, 100), "2h ago");
+        assert_eq!(ago(3 * 86400, 0), "3d ago");
+    }
+
+    #[test]
+    fn test_render() {
+        let status = Status {
+            now: 1000,
+            queue_depth: 2,
+            gitlab_error: None,
+            paused: vec![],
+            kill_switches: vec![],
+            mappings: vec![MappingRow {
+                forge: "GitHub",
+                repo: "octocat/hello-world".to_string(),
+                gitlab_project: "mirrors/hello-world".to_string(),
+                untrusted_gitlab_project: None,
+                merge_requests: true,
+            }],
+            syncs: vec![SyncRow {
+                sync: state::PrSync {
+                    github_repo: "octocat/hello-world".to_string(),
+                    pr_number: 12,
+                    head_sha: "a91957a858320c0e17f3a0eca7cfacbff50ea29a".to_string(),
+                    gitlab_project: "mirrors/hello-world".to_string(),
+                    gitlab_branch: "pr-12/octocat/hello-world/fix-typo".to_string(),
+                    synced_at: 940,
+                },
+                pipeline: Some(state::Pipeline {
+                    gitlab_project: "mirrors/hello-world".to_string(),
+                    pipeline_id: 31,
+                    sha: "a91957a858320c0e17f3a0eca7cfacbff50ea29a".to_string(),
+                    status: "failed".to_string(),
+                    updated_at: 990,
+                }),
+            }],
+            pipelines: vec![],
+            failures: vec![state::AuditEntry {
+                id: 1,
+                at: 995,
+                actor: "labhub".to_string(),
+                repo: "octocat/hello-world".to_string(),
+                pr_number: Some(12),
+                action: "comment".to_string(),
+                target: "octocat/hello-world#12".to_string(),
+                outcome: "failure".to_string(),
+                error: Some("<status=403>".to_string()),
+            }],
+        };
+        let page = render(&status).into_string();
+        assert!(page.contains("Queued PR events: <strong>2</strong>"));
+        assert!(page.contains("<td>octocat/hello-world#12</td>"));
+        assert!(page.contains("<code>a91957a8</code>"));
+        assert!(page.contains("31 <span class=\"status failed\">failed</span>"));
+        assert!(page.contains("<td>1m ago</td>"));
+        // errors are escaped
+        assert!(page.contains("&lt;status=403&gt;"));
+    }
+}
@@ -0,0 +1,122 @@
+use crate::cluster;
+use crate::config;
+use crate::state;
+
+use log::error;
+use std::collections::{HashSet, VecDeque};
+use std::sync::Mutex;
+
+/// Bounded set of recently seen webhook delivery IDs, evicting the oldest
+/// once full.
+struct DeliveryCache {
+    capacity: usize,
+    order: VecDeque<String>,
+    seen: HashSet<String>,
+}
+
+impl DeliveryCache {
+    fn new(capacity: usize) -> DeliveryCache {
+        DeliveryCache {
+            capacity,
+            order: VecDeque::new(),
+            seen: HashSet::new(),
+        }
+    }
+
+    fn contains(&self, delivery_id: &str) -> bool {
+        self.seen.contains(delivery_id)
+    }
+
+    /// Returns true if the ID hasn't been seen before
+    fn insert(&mut self, delivery_id: &str) -> bool {
+        if self.seen.contains(delivery_id) {
+            return false;
+        }
+        if self.order.len() >= self.capacity {
+            if let Some(oldest) = self.order.pop_front() {
+                self.seen.remove(&oldest);
+            }
+        }
+        self.order.push_back(delivery_id.to_string());
+        self.seen.insert(delivery_id.to_string());
+        true
+    }
+}
+
+lazy_static! {
+    static ref DELIVERIES: Mutex<DeliveryCache> = Mutex::new(DeliveryCache::new(
+        config::CONFIG.dedupe.delivery_cache_size
+    ));
+}
+
+/// Returns true if a webhook delivery was already handled, either recently
+/// by this process, by another replica (when they share a Redis), or (when
+/// a state database is configured) before a restart. Deliveries are only
+/// recorded once handled, with [`record_delivery`], so redeliveries of
+/// failed ones are handled again.
+pub async fn is_duplicate_delivery(delivery_id: &str) -> bool {
+    if DELIVERIES.lock().unwrap().contains(delivery_id) {
+        return true;
+    }
+    if cluster::enabled() {
+        match cluster::delivery_recorded(delivery_id).await {
+            Ok(true) => return true,
+            Ok(false) => {}
+            Err(err) => error!("Error looking up delivery {}: {:?}", delivery_id, err),
+        }
+    }
+    // recorded here too, for when Redis is unreachable after a restart
+    if config::CONFIG.state.database.is_some() {
+        match state::delivery_recorded(delivery_id) {
+            Ok(recorded) => return recorded,
+            Err(err) => error!("Error looking up delivery {}: {:?}", delivery_id, err),
+        }
+    }
+    false
+}
+
+/// Records a webhook delivery which was handled, so redeliveries of it are
+/// skipped
+pub async fn record_delivery(delivery_id: &str) {
+    DELIVERIES.lock().unwrap().insert(delivery_id);
+    if cluster::enabled() {
+        if let Err(err) = cluster::record_delivery(delivery_id).await {
+            error!("Error sharing delivery {}: {:?}", delivery_id, err);
+        }
+    }
+    if config::CONFIG.state.database.is_some() {
+        if let Err(err) = state::record_delivery(delivery_id) {
+            error!("Error recording delivery {}: {:?}", delivery_id, err);
+        }
+    }
+}
+
+/// Returns true if the given head was the last one pushed for the PR
+pub fn is_synced_head(github_repo: &str, pr_number: i64, head_sha: &str) -> bool {
+    match state::latest_pr_sync(github_repo, pr_number) {
+        Ok(Some(sync)) => sync.head_sha == head_sha,
+        Ok(None) => false,
+        Err(err) => {
+            error!("Error looking up PR sync: {:?}", err);
+            false
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_delivery_cache() {
+        let mut cache = DeliveryCache::new(2);
+        assert!(cache.insert("a"));
+        assert!(!cache.insert("a"));
+        assert!(cache.contains("a"));
+        assert!(cache.insert("b"));
+        assert!(cache.insert("c"));
+        // "a" was evicted
+        assert!(cache.insert("a"));
+        assert!(!cache.insert("c"));
+    }
+}
@@ -0,0 +1,177 @@
+//! Guarding the disk the cloned repos are cached on. Clones go in the
+//! temporary directory, and a volume filling up would otherwise only show
+//! as failing clones and fetches.
+use crate::config;
+use crate::sync;
+
+use log::{info, warn};
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::time::Duration;
+use tempfile::TempDir;
+
+/// Prefix of the directories repos are cloned into, so that those left
+/// behind by a crashed process can be found
+const CLONE_DIR_PREFIX: &str = "labhub-clone-";
+
+/// Number of cached repos, as of the last time they were unlocked
+static CACHED_REPOS: AtomicUsize = AtomicUsize::new(0);
+/// Total size of the cached repos, as of the last time they were unlocked
+static CACHE_BYTES: AtomicU64 = AtomicU64::new(0);
+
+/// Disk usage of the clone cache
+#[derive(Debug, Serialize)]
+pub struct DiskUsage {
+    /// Free space on the temporary directory's volume, if it can be read
+    pub free_bytes: Option<u64>,
+    pub min_free_bytes: u64,
+    pub cache_bytes: u64,
+    pub cached_repos: Vec<CachedRepo>,
+}
+
+/// A cloned repo in the cache
+#[derive(Debug, Serialize)]
+pub struct CachedRepo {
+    pub url: String,
+    pub bytes: u64,
+    /// Seconds since the repo was last used for a sync
+    pub idle_secs: u64,
+}
+
+/// Creates a directory to clone a repo into
+pub fn clone_dir() -> io::Result<TempDir> {
+    tempfile::Builder::new().prefix(CLONE_DIR_PREFIX).tempdir()
+}
+
+pub fn free_bytes() -> io::Result<u64> {
+    fs2::available_space(std::env::temp_dir())
+}
+
+pub fn min_free_bytes() -> u64 {
+    config::CONFIG.disk.min_free_mb * 1024 * 1024
+}
+
+/// Total size of the files in a directory, skipping what can't be read
+pub fn dir_size(path: &Path) -> u64 {
+    let entries = match fs::read_dir(path) {
+        Ok(entries) => entries,
+        Err(_) => return 0,
+    };
+    entries
+        .filter_map(Result::ok)
+        .map(|entry| match entry.file_type() {
+            Ok(file_type) if file_type.is_dir() => dir_size(&entry.path()),
+            Ok(file_type) if file_type.is_file() => entry.metadata().map(|m| m.len()).unwrap_or(0),
+            _ => 0,
+        })
+        .sum()
+}
+
+/// Records the size of the clone cache, which the sync code does whenever
+/// it unlocks the cached repos
+pub fn record_cache(repos: usize, bytes: u64) {
+    CACHED_REPOS.store(repos, Ordering::Relaxed);
+    CACHE_BYTES.store(bytes, Ordering::Relaxed);
+}
+
+/// The number and total size of the cached repos, without locking them
+pub fn cache_gauges() -> (usize, u64) {
+    (
+        CACHED_REPOS.load(Ordering::Relaxed),
+        CACHE_BYTES.load(Ordering::Relaxed),
+    )
+}
+
+/// Current free space and clone cache usage
+pub fn usage() -> DiskUsage {
+    let cached_repos: Vec<CachedRepo> = sync::cached_repo_sizes()
+        .into_iter()
+        .map(|(url, bytes, idle)| CachedRepo {
+            url,
+            bytes,
+            idle_secs: idle.as_secs(),
+        })
+        .collect();
+    DiskUsage {
+        free_bytes: free_bytes().ok(),
+        min_free_bytes: min_free_bytes(),
+        cache_bytes: cached_repos.iter().map(|repo| repo.bytes).sum(),
+        cached_repos,
+    }
+}
+
+fn remove_leftover_clones_in(dir: &Path) -> usize {
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(err) => {
+            warn!("Unable to list {}: {}", dir.display(), err);
+            return 0;
+        }
+    };
+    let mut removed = 0;
+    for entry in entries.filter_map(Result::ok) {
+        if !entry
+            .file_name()
+            .to_string_lossy()
+            .starts_with(CLONE_DIR_PREFIX)
+        {
+            continue;
+        }
+        match fs::remove_dir_all(entry.path()) {
+            Ok(()) => removed += 1,
+            Err(err) => warn!("Unable to remove {}: {}", entry.path().display(), err),
+        }
+    }
+    removed
+}
+
+/// Removes the clones left behind by a previous process which didn't get
+/// to clean up, e.g. because it was killed. Only called on startup, as
+/// clones in use can't be told apart.
+pub fn remove_leftover_clones() {
+    let removed = remove_leftover_clones_in(&std::env::temp_dir());
+    if removed > 0 {
+        info!("Removed {} leftover repo clones", removed);
+    }
+}
+
+/// How often idle cached repos are looked for, at most
+const EVICTION_INTERVAL: Duration = Duration::from_secs(10 * 60);
+
+/// Periodically evicts the cached repos unused for `repo_ttl_secs`, so
+/// clones of repos which stopped getting PRs, or were unmapped, don't stay
+/// on disk until it runs low
+pub async fn run_periodic_eviction() {
+    let ttl = Duration::from_secs(config::CONFIG.disk.repo_ttl_secs);
+    let mut interval = tokio::time::interval(ttl.min(EVICTION_INTERVAL));
+    loop {
+        interval.tick().await;
+        let evicted = sync::evict_idle_repos(ttl);
+        if !evicted.is_empty() {
+            info!("Evicted {} idle cached repos", evicted.len());
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_dir_size_and_leftovers() {
+        let dir = tempfile::tempdir().unwrap();
+        let clone = dir.path().join(format!("{}abc", CLONE_DIR_PREFIX));
+        fs::create_dir_all(clone.join("objects")).unwrap();
+        fs::write(clone.join("HEAD"), "0123456789").unwrap();
+        fs::write(clone.join("objects").join("pack"), "01234").unwrap();
+        fs::write(dir.path().join("other"), "0123").unwrap();
+        assert_eq!(dir_size(&clone), 15);
+        assert_eq!(dir_size(dir.path()), 19);
+
+        assert_eq!(remove_leftover_clones_in(dir.path()), 1);
+        assert!(!clone.exists());
+        assert!(dir.path().join("other").exists());
+    }
+}
@@ -0,0 +1,82 @@
+//! Pipeline duration trends: a PR pipeline much slower than the recent
+//! pipelines of its base branch is flagged as a possible regression.
+use crate::config;
+use crate::messages::{self, Message};
+
+fn median(durations: &[i64]) -> Option<f64> {
+    if durations.is_empty() {
+        return None;
+    }
+    let mut sorted = durations.to_vec();
+    sorted.sort_unstable();
+    let mid = sorted.len() / 2;
+    if sorted.len().is_multiple_of(2) {
+        Some((sorted[mid - 1] + sorted[mid]) as f64 / 2.0)
+    } else {
+        Some(sorted[mid] as f64)
+    }
+}
+
+pub(crate) fn format_duration(secs: f64) -> String {
+    let secs = secs.round() as i64;
+    if secs < 60 {
+        format!("{}s", secs)
+    } else {
+        format!("{}m {}s", secs / 60, secs % 60)
+    }
+}
+
+/// Returns a note for the PR if its pipeline took `duration` seconds, which
+/// is significantly more than the median of `base_durations`
+pub fn slowdown_note(
+    durations: &config::Durations,
+    duration: i64,
+    base_durations: &[i64],
+    base_ref: &str,
+    language: config::Language,
+) -> Option<String> {
+    if base_durations.len() < durations.min_samples {
+        return None;
+    }
+    let median = median(base_durations)?;
+    if median <= 0.0 || (duration as f64) < median * durations.slowdown_factor {
+        return None;
+    }
+    Some(messages::text(
+        language,
+        Message::Slowdown {
+            took: &format_duration(duration as f64),
+            factor: duration as f64 / median,
+            median: &format_duration(median),
+            samples: base_durations.len(),
+            base_ref,
+        },
+    ))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_slowdown_note() {
+        let durations = config::Durations::default();
+        let base = [100, 120, 110, 90, 130];
+        assert_eq!(median(&base), Some(110.0));
+        assert_eq!(median(&[1, 2, 3, 4]), Some(2.5));
+        assert_eq!(
+            slowdown_note(&durations, 330, &base, "master", config::Language::En).unwrap(),
+            "⚠️ This pipeline took 5m 30s, 3.0x the median of 1m 50s for the last 5 \
+             pipelines on `master`."
+        );
+        assert_eq!(
+            slowdown_note(&durations, 150, &base, "master", config::Language::En),
+            None
+        );
+        // too few samples to tell
+        assert_eq!(
+            slowdown_note(&durations, 330, &base[..4], "master", config::Language::En),
+            None
+        );
+    }
+}
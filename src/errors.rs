@@ -1,16 +1,24 @@
-use crate::api::github_signature;
+//! Error types, and their conversion to HTTP responses. Error responses
+//! are JSON objects with the `error` message and a machine-readable `code`,
+//! ex: `invalid_signature`, `invalid_payload`, `api_status` or
+//! `overloaded`.
+use crate::api::github_signature::SignatureError;
 use crate::commands;
 
 use axum::{
-    http::StatusCode,
+    http::{header, StatusCode},
     response::{IntoResponse, Response},
     Json,
 };
+use std::any::Any;
 use std::io;
 
 #[derive(Debug)]
 pub struct ResponseError {
+    status: StatusCode,
     response: serde_json::Value,
+    /// Seconds to wait before retrying, sent as `Retry-After`
+    retry_after: Option<u64>,
 }
 
 #[derive(Debug)]
@@ -18,15 +26,31 @@ pub struct BadRequest {
     response: serde_json::Value,
 }
 
+/// A webhook payload which couldn't be parsed, with where it failed
+#[derive(Debug)]
+pub struct PayloadError {
+    /// JSON pointer to the value which failed, ex: `/pull_request/head/sha`,
+    /// empty for the whole payload
+    pub pointer: String,
+    pub message: String,
+}
+
 #[derive(Debug)]
 pub enum RequestErrorResult {
     BadRequest(BadRequest),
     ResponseError(ResponseError),
+    InvalidPayload(PayloadError),
 }
 
 impl IntoResponse for ResponseError {
     fn into_response(self) -> Response {
-        (StatusCode::INTERNAL_SERVER_ERROR, Json(self.response)).into_response()
+        let mut response = (self.status, Json(self.response)).into_response();
+        if let Some(retry_after) = self.retry_after {
+            response
+                .headers_mut()
+                .insert(header::RETRY_AFTER, retry_after.into());
+        }
+        response
     }
 }
 
@@ -36,96 +60,407 @@ impl IntoResponse for BadRequest {
     }
 }
 
+impl IntoResponse for PayloadError {
+    fn into_response(self) -> Response {
+        (
+            StatusCode::BAD_REQUEST,
+            Json(serde_json::json!({
+                "error": self.message,
+                "code": "invalid_payload",
+                "pointer": self.pointer,
+            })),
+        )
+            .into_response()
+    }
+}
+
 impl IntoResponse for RequestErrorResult {
     fn into_response(self) -> Response {
         match self {
             RequestErrorResult::BadRequest(br) => br.into_response(),
             RequestErrorResult::ResponseError(re) => re.into_response(),
+            RequestErrorResult::InvalidPayload(pe) => pe.into_response(),
         }
     }
 }
 
-#[derive(Debug)]
-pub struct GitError {
-    pub message: String,
+/// The JSON body of an error response
+fn error_body(message: String, code: &str) -> serde_json::Value {
+    serde_json::json!({ "error": message, "code": code })
 }
 
-impl From<io::Error> for RequestErrorResult {
-    fn from(error: io::Error) -> Self {
-        RequestErrorResult::ResponseError {
-            0: ResponseError {
-                response: serde_json::json!({ "error": format!("{:?}", error) }),
-            },
+impl RequestErrorResult {
+    /// A `500 Internal Server Error` with `message`
+    pub fn internal(message: String) -> RequestErrorResult {
+        RequestErrorResult::ResponseError(ResponseError {
+            status: StatusCode::INTERNAL_SERVER_ERROR,
+            response: error_body(message, "internal"),
+            retry_after: None,
+        })
+    }
+
+    /// The HTTP status of the response
+    pub fn status(&self) -> StatusCode {
+        match self {
+            RequestErrorResult::BadRequest(_) | RequestErrorResult::InvalidPayload(_) => {
+                StatusCode::BAD_REQUEST
+            }
+            RequestErrorResult::ResponseError(re) => re.status,
         }
     }
-}
 
-impl From<github_signature::SignatureError> for RequestErrorResult {
-    fn from(error: github_signature::SignatureError) -> Self {
-        RequestErrorResult::BadRequest {
-            0: BadRequest {
-                response: serde_json::json!({ "error": format!("{:?}", error) }),
-            },
+    /// The machine-readable code of the error
+    pub fn code(&self) -> String {
+        let response = match self {
+            RequestErrorResult::BadRequest(br) => &br.response,
+            RequestErrorResult::ResponseError(re) => &re.response,
+            RequestErrorResult::InvalidPayload(_) => return "invalid_payload".to_string(),
+        };
+        response["code"].as_str().unwrap_or_default().to_string()
+    }
+
+    /// The error sent back in the response
+    pub fn message(&self) -> String {
+        let response = match self {
+            RequestErrorResult::BadRequest(br) => &br.response,
+            RequestErrorResult::ResponseError(re) => &re.response,
+            RequestErrorResult::InvalidPayload(pe) => return pe.message.clone(),
+        };
+        match &response["error"] {
+            serde_json::Value::String(error) => error.clone(),
+            _ => response.to_string(),
         }
     }
 }
 
-impl From<serde_json::error::Error> for RequestErrorResult {
-    fn from(error: serde_json::error::Error) -> Self {
-        RequestErrorResult::BadRequest {
-            0: BadRequest {
-                response: serde_json::json!({ "error": format!("{:?}", error) }),
-            },
+/// The message a panic was raised with
+pub fn panic_message(payload: &(dyn Any + Send)) -> String {
+    payload
+        .downcast_ref::<&str>()
+        .map(|message| message.to_string())
+        .or_else(|| payload.downcast_ref::<String>().cloned())
+        .unwrap_or_else(|| "Box<dyn Any>".to_string())
+}
+
+/// LabHub's errors, by what failed
+#[derive(Debug, thiserror::Error)]
+pub enum GitError {
+    /// Cloning a repo, or fetching from one of its remotes, failed
+    #[error("Unable to clone or fetch {remote}: {message}")]
+    GitClone { remote: String, message: String },
+    /// Pushing to a remote failed
+    #[error("Unable to push {refspec} to {remote}: {message}")]
+    GitPush {
+        remote: String,
+        refspec: String,
+        message: String,
+    },
+    /// Another git operation failed
+    #[error("Git error: {}", .0.message())]
+    Git(#[from] git2::Error),
+    /// A git command failed, ex: fetching from a remote
+    #[error("{0}")]
+    GitCommand(String),
+    /// An API answered with an unexpected status
+    #[error("{context}: status={code} body={body}")]
+    ApiStatus {
+        context: String,
+        code: u16,
+        body: String,
+    },
+    /// An API couldn't be reached
+    #[error("Request error: {0}")]
+    Request(#[from] reqwest::Error),
+    /// An API answered without something it always sends, ex: the id of
+    /// what was created
+    #[error("{0}")]
+    UnexpectedResponse(String),
+    /// A webhook's signature or token is missing or didn't match
+    #[error("Invalid webhook signature: {0:?}")]
+    Signature(SignatureError),
+    /// A request body is over the limit of its route
+    #[error("The body is over the limit of {limit} bytes")]
+    PayloadTooLarge { limit: usize },
+    /// The head fetched for a PR isn't the one its event, or approval, was
+    /// for, ex: when it was pushed to since
+    #[error("The fetched head {fetched} of the PR isn't {expected}")]
+    HeadMoved { expected: String, fetched: String },
+    /// Too much work is pending to take more
+    #[error("{message}")]
+    Overloaded {
+        message: String,
+        /// Seconds to wait before trying again
+        retry_after_secs: u64,
+    },
+    /// LabHub's configuration is invalid or incomplete for what was asked
+    #[error("{0}")]
+    Config(String),
+    /// What was asked for doesn't exist
+    #[error("{0}")]
+    NotFound(String),
+    /// The request is malformed or asks for something which can't be done
+    #[error("{0}")]
+    BadRequest(String),
+    /// An action was skipped as its kill switch is engaged, see
+    /// [`crate::killswitch`]
+    #[error("Skipped, the {0} kill switch is engaged")]
+    KillSwitch(&'static str),
+    #[error("Invalid payload at {pointer:?}: {message}")]
+    InvalidPayload { pointer: String, message: String },
+    #[error("JSON error: {0}")]
+    Json(#[from] serde_json::Error),
+    #[error("I/O error: {0}")]
+    Io(#[from] io::Error),
+    #[error("State store error: {0}")]
+    State(#[from] rusqlite::Error),
+    #[error("Redis error: {0}")]
+    Redis(#[from] redis::RedisError),
+    #[error("Command error: {0:?}")]
+    Command(commands::CommandError),
+    /// Any other failure of LabHub's own
+    #[error("{0}")]
+    Other(String),
+}
+
+impl GitError {
+    /// An API's unexpected response, with its body
+    pub async fn api_status(context: impl Into<String>, res: reqwest::Response) -> GitError {
+        let code = res.status().as_u16();
+        GitError::ApiStatus {
+            context: context.into(),
+            code,
+            body: res.text().await.unwrap_or_default(),
         }
     }
-}
 
-impl From<GitError> for RequestErrorResult {
-    fn from(error: GitError) -> Self {
-        RequestErrorResult::BadRequest {
-            0: BadRequest {
-                response: serde_json::json!({ "error": format!("{:?}", error) }),
-            },
+    /// The variant's name, ex: for metrics
+    pub fn kind(&self) -> &'static str {
+        match self {
+            GitError::GitClone { .. } => "git_clone",
+            GitError::GitPush { .. } => "git_push",
+            GitError::Git(_) => "git",
+            GitError::GitCommand(_) => "git_command",
+            GitError::ApiStatus { code: 429, .. } => "rate_limited",
+            GitError::Request(err) if err.status() == Some(StatusCode::TOO_MANY_REQUESTS) => {
+                "rate_limited"
+            }
+            GitError::ApiStatus { .. } => "api_status",
+            GitError::Request(_) => "request",
+            GitError::UnexpectedResponse(_) => "unexpected_response",
+            GitError::Signature(SignatureError::Missing) => "missing_signature",
+            GitError::Signature(SignatureError::BadSignature) => "invalid_signature",
+            GitError::Signature(_) => "malformed_signature",
+            GitError::PayloadTooLarge { .. } => "payload_too_large",
+            GitError::HeadMoved { .. } => "head_moved",
+            GitError::Overloaded { .. } => "overloaded",
+            GitError::Config(_) => "config",
+            GitError::NotFound(_) => "not_found",
+            GitError::BadRequest(_) => "bad_request",
+            GitError::KillSwitch(_) => "kill_switch",
+            GitError::InvalidPayload { .. } => "invalid_payload",
+            GitError::Json(_) => "json",
+            GitError::Io(_) => "io",
+            GitError::State(_) => "state",
+            GitError::Redis(_) => "redis",
+            GitError::Command(_) => "command",
+            GitError::Other(_) => "other",
         }
     }
-}
 
-impl From<git2::Error> for GitError {
-    fn from(error: git2::Error) -> Self {
-        GitError {
-            message: format!("Git error: {:?}", error.message()),
+    /// The HTTP status of a request which failed with this error: failures
+    /// of GitHub, GitLab or the remotes are a 502, or a 429 when they're
+    /// rate limited, errors of the request a 400, and LabHub's own failures,
+    /// including any not known to be one of those, a 500. A missing or
+    /// malformed signature is a 401, and a wrong one a 403.
+    pub fn status(&self) -> StatusCode {
+        if self.kind() == "rate_limited" {
+            return StatusCode::TOO_MANY_REQUESTS;
+        }
+        match self {
+            GitError::GitClone { .. }
+            | GitError::GitPush { .. }
+            | GitError::GitCommand(_)
+            | GitError::ApiStatus { .. }
+            | GitError::Request(_)
+            | GitError::UnexpectedResponse(_) => StatusCode::BAD_GATEWAY,
+            GitError::Signature(SignatureError::BadSignature) => StatusCode::FORBIDDEN,
+            GitError::Signature(_) => StatusCode::UNAUTHORIZED,
+            GitError::PayloadTooLarge { .. } => StatusCode::PAYLOAD_TOO_LARGE,
+            GitError::HeadMoved { .. } => StatusCode::CONFLICT,
+            GitError::Overloaded { .. } | GitError::KillSwitch(_) => {
+                StatusCode::SERVICE_UNAVAILABLE
+            }
+            GitError::NotFound(_) => StatusCode::NOT_FOUND,
+            GitError::InvalidPayload { .. }
+            | GitError::Json(_)
+            | GitError::Command(_)
+            | GitError::BadRequest(_) => StatusCode::BAD_REQUEST,
+            GitError::Config(_)
+            | GitError::Git(_)
+            | GitError::Io(_)
+            | GitError::State(_)
+            | GitError::Redis(_)
+            | GitError::Other(_) => StatusCode::INTERNAL_SERVER_ERROR,
         }
     }
 }
 
-impl From<io::Error> for GitError {
+impl From<io::Error> for RequestErrorResult {
     fn from(error: io::Error) -> Self {
-        GitError {
-            message: format!("Git error: {:?}", error),
-        }
+        GitError::from(error).into()
     }
 }
 
-impl From<serde_json::error::Error> for GitError {
+impl From<SignatureError> for RequestErrorResult {
+    fn from(error: SignatureError) -> Self {
+        GitError::Signature(error).into()
+    }
+}
+
+impl From<serde_json::error::Error> for RequestErrorResult {
     fn from(error: serde_json::error::Error) -> Self {
-        GitError {
-            message: format!("Github serde error: {:?}", error),
+        GitError::from(error).into()
+    }
+}
+
+impl From<GitError> for RequestErrorResult {
+    fn from(error: GitError) -> Self {
+        let retry_after = match &error {
+            GitError::Overloaded {
+                retry_after_secs, ..
+            } => Some(*retry_after_secs),
+            _ => None,
+        };
+        let response = error_body(error.to_string(), error.kind());
+        match error.status() {
+            StatusCode::BAD_REQUEST => RequestErrorResult::BadRequest(BadRequest { response }),
+            status => RequestErrorResult::ResponseError(ResponseError {
+                status,
+                response,
+                retry_after,
+            }),
         }
     }
 }
 
-impl From<reqwest::Error> for GitError {
-    fn from(error: reqwest::Error) -> Self {
-        GitError {
-            message: format!("Git request error: {:?}", error),
+impl From<PayloadError> for RequestErrorResult {
+    fn from(error: PayloadError) -> Self {
+        RequestErrorResult::InvalidPayload(error)
+    }
+}
+
+impl From<PayloadError> for GitError {
+    fn from(error: PayloadError) -> Self {
+        GitError::InvalidPayload {
+            pointer: error.pointer,
+            message: error.message,
         }
     }
 }
 
+impl From<SignatureError> for GitError {
+    fn from(error: SignatureError) -> Self {
+        GitError::Signature(error)
+    }
+}
+
 impl From<commands::CommandError> for GitError {
     fn from(error: commands::CommandError) -> Self {
-        GitError {
-            message: format!("Git command error: {:?}", error),
-        }
+        GitError::Command(error)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_status() {
+        let err = GitError::ApiStatus {
+            context: "Error getting pipeline".to_string(),
+            code: 500,
+            body: "oops".to_string(),
+        };
+        assert_eq!(
+            err.to_string(),
+            "Error getting pipeline: status=500 body=oops"
+        );
+        assert_eq!(err.kind(), "api_status");
+        assert_eq!(err.status(), StatusCode::BAD_GATEWAY);
+        let push = GitError::GitPush {
+            remote: "gitlab".to_string(),
+            refspec: "+refs/heads/pr-1:refs/heads/pr-1".to_string(),
+            message: "rejected".to_string(),
+        };
+        assert_eq!(push.status(), StatusCode::BAD_GATEWAY);
+        assert_eq!(
+            GitError::NotFound("No such payload".to_string()).status(),
+            StatusCode::NOT_FOUND
+        );
+        assert_eq!(
+            GitError::Config("Redis isn't configured".to_string()).status(),
+            StatusCode::INTERNAL_SERVER_ERROR
+        );
+        assert_eq!(
+            GitError::BadRequest("Invalid repo name x".to_string()).status(),
+            StatusCode::BAD_REQUEST
+        );
+        assert_eq!(
+            GitError::UnexpectedResponse("Created deployment has no id".to_string()).status(),
+            StatusCode::BAD_GATEWAY
+        );
+        assert_eq!(
+            GitError::KillSwitch("comments").to_string(),
+            "Skipped, the comments kill switch is engaged"
+        );
+        assert_eq!(
+            GitError::KillSwitch("comments").status(),
+            StatusCode::SERVICE_UNAVAILABLE
+        );
+        // failures not known to be the request's are LabHub's
+        assert_eq!(
+            GitError::Other("Unable to generate a lock token".to_string()).status(),
+            StatusCode::INTERNAL_SERVER_ERROR
+        );
+    }
+
+    #[test]
+    fn test_into_response() {
+        let response = RequestErrorResult::from(GitError::NotFound("No such payload".to_string()))
+            .into_response();
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+        let err = RequestErrorResult::from(GitError::BadRequest("Invalid repo name x".to_string()));
+        assert!(matches!(err, RequestErrorResult::BadRequest(_)));
+        assert_eq!(err.message(), "Invalid repo name x");
+        assert_eq!(err.code(), "bad_request");
+    }
+
+    #[test]
+    fn test_webhook_statuses() {
+        let missing = RequestErrorResult::from(SignatureError::Missing);
+        assert_eq!(missing.status(), StatusCode::UNAUTHORIZED);
+        assert_eq!(missing.code(), "missing_signature");
+        let bad = RequestErrorResult::from(SignatureError::BadSignature);
+        assert_eq!(bad.status(), StatusCode::FORBIDDEN);
+        assert_eq!(bad.code(), "invalid_signature");
+        let json = serde_json::from_str::<serde_json::Value>("{").unwrap_err();
+        let json = RequestErrorResult::from(json);
+        assert_eq!(json.status(), StatusCode::BAD_REQUEST);
+        assert_eq!(json.code(), "json");
+        let rate_limited = GitError::ApiStatus {
+            context: "Error creating check run".to_string(),
+            code: 429,
+            body: String::new(),
+        };
+        assert_eq!(rate_limited.status(), StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(rate_limited.kind(), "rate_limited");
+
+        let response = RequestErrorResult::from(GitError::Overloaded {
+            message: "Busy".to_string(),
+            retry_after_secs: 30,
+        })
+        .into_response();
+        assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(response.headers()[header::RETRY_AFTER], "30");
     }
 }
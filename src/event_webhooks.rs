@@ -0,0 +1,162 @@
+//! Event webhooks: LabHub's own events, like PR syncs and finished
+//! pipelines, posted as JSON to the `[[event_webhooks]]` which asked for
+//! them, so other tools can react to what the bridge does.
+use crate::api;
+use crate::api::webhook_client;
+use crate::commands::CommandAction;
+use crate::config::{self, WebhookEvent};
+use crate::forge::ForgePullRequest;
+
+use log::error;
+use ring::{digest, hmac};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+#[derive(Debug, Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum Event {
+    PrSynced {
+        repo: String,
+        number: i64,
+        forge: &'static str,
+        action: String,
+        head_sha: String,
+    },
+    PipelineFinished {
+        repo: String,
+        number: i64,
+        gitlab_project: String,
+        pipeline_id: i64,
+        sha: String,
+        status: String,
+    },
+    CommandExecuted {
+        repo: String,
+        number: i64,
+        command: CommandAction,
+        user: String,
+        succeeded: bool,
+    },
+}
+
+impl Event {
+    /// Event for a PR which was mirrored to GitLab
+    pub fn pr_synced(pr: &dyn ForgePullRequest) -> Event {
+        Event::PrSynced {
+            repo: pr.base_full_name().to_string(),
+            number: pr.number(),
+            forge: pr.forge().name(),
+            action: pr.action().to_string(),
+            head_sha: pr.head_sha().to_string(),
+        }
+    }
+
+    fn kind(&self) -> WebhookEvent {
+        match self {
+            Event::PrSynced { .. } => WebhookEvent::PrSynced,
+            Event::PipelineFinished { .. } => WebhookEvent::PipelineFinished,
+            Event::CommandExecuted { .. } => WebhookEvent::CommandExecuted,
+        }
+    }
+
+    fn name(&self) -> &'static str {
+        match self.kind() {
+            WebhookEvent::PrSynced => "pr_synced",
+            WebhookEvent::PipelineFinished => "pipeline_finished",
+            WebhookEvent::CommandExecuted => "command_executed",
+        }
+    }
+}
+
+/// The JSON body posted for an event, with when it happened
+fn body(event: &Event, at: u64) -> String {
+    let mut body = serde_json::to_value(event).unwrap_or_default();
+    body["at"] = at.into();
+    body.to_string()
+}
+
+/// The `X-LabHub-Signature-256` header of a body, like GitHub's
+fn signature(secret: &str, body: &str) -> String {
+    let key = hmac::SigningKey::new(&digest::SHA256, secret.as_bytes());
+    format!(
+        "sha256={}",
+        hex::encode(hmac::sign(&key, body.as_bytes()).as_ref())
+    )
+}
+
+/// Posts an event to every event webhook which asked for it, in the
+/// background, logging failures
+pub fn emit(event: Event) {
+    let webhooks: Vec<&'static config::EventWebhook> = config::CONFIG
+        .event_webhooks
+        .iter()
+        .filter(|webhook| webhook.events.contains(&event.kind()))
+        .collect();
+    if webhooks.is_empty() {
+        return;
+    }
+    let at = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0);
+    let body = body(&event, at);
+    tokio::spawn(async move {
+        let client = match api::new_client() {
+            Ok(client) => client,
+            Err(err) => {
+                error!("Error posting {} event: {:?}", event.name(), err);
+                return;
+            }
+        };
+        for webhook in webhooks {
+            let signature = webhook
+                .secret
+                .as_deref()
+                .map(|secret| signature(secret, &body));
+            if let Err(err) = webhook_client::post_event(
+                &client,
+                &webhook.url,
+                event.name(),
+                signature.as_deref(),
+                body.clone(),
+            )
+            .await
+            {
+                error!("Error posting {} event: {:?}", event.name(), err);
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_body() {
+        let event = Event::CommandExecuted {
+            repo: "octocat/hello-world".to_string(),
+            number: 12,
+            command: CommandAction::NewPipeline,
+            user: "octocat".to_string(),
+            succeeded: true,
+        };
+        assert_eq!(event.name(), "command_executed");
+        let body = body(&event, 1700000000);
+        let value: serde_json::Value = serde_json::from_str(&body).unwrap();
+        assert_eq!(
+            value,
+            serde_json::json!({
+                "event": "command_executed",
+                "repo": "octocat/hello-world",
+                "number": 12,
+                "command": "new-pipeline",
+                "user": "octocat",
+                "succeeded": true,
+                "at": 1700000000,
+            })
+        );
+        let signature = signature("secret", &body);
+        let hex = signature.strip_prefix("sha256=").unwrap();
+        assert!(crate::api::gitea_signature::check_signature("secret", hex, &body).is_ok());
+    }
+}
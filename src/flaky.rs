@@ -0,0 +1,65 @@
+//! Flaky job detection: a failed job that's known to be flaky, or that often
+//! passes when retried, is retried once automatically before its failure
+//! stands.
+use crate::config;
+
+use regex::{Regex, RegexSet};
+
+lazy_static! {
+    /// The configured known flaky job patterns, checked by
+    /// [`config::validate`] on startup
+    pub static ref KNOWN_JOBS: RegexSet =
+        known_jobs(&config::CONFIG.flaky.known_jobs).unwrap_or_else(|_| RegexSet::empty());
+}
+
+fn anchored(pattern: &str) -> String {
+    format!("^(?:{})$", pattern)
+}
+
+/// Compiles a known flaky job pattern, which has to match the whole job name
+pub(crate) fn known_job_regex(pattern: &str) -> Result<Regex, regex::Error> {
+    Regex::new(&anchored(pattern))
+}
+
+/// Compiles the known flaky job patterns into one set
+pub fn known_jobs(patterns: &[String]) -> Result<RegexSet, regex::Error> {
+    RegexSet::new(patterns.iter().map(|p| anchored(p)))
+}
+
+/// Whether a failed job should be retried, given the known flaky job
+/// patterns and how many pipelines it previously passed on retry in
+pub fn is_flaky(
+    flaky: &config::Flaky,
+    known_jobs: &RegexSet,
+    job_name: &str,
+    passes_on_retry: i64,
+) -> bool {
+    known_jobs.is_match(job_name)
+        || (flaky.min_passes_on_retry > 0 && passes_on_retry >= flaky.min_passes_on_retry)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_is_flaky() {
+        let flaky = config::Flaky {
+            known_jobs: vec!["integration.*".to_string(), "e2e".to_string()],
+            min_passes_on_retry: 3,
+        };
+        let known = known_jobs(&flaky.known_jobs).unwrap();
+        assert!(is_flaky(&flaky, &known, "integration-postgres", 0));
+        assert!(is_flaky(&flaky, &known, "e2e", 0));
+        assert!(!is_flaky(&flaky, &known, "e2e-firefox", 0));
+        assert!(!is_flaky(&flaky, &known, "unit", 2));
+        assert!(is_flaky(&flaky, &known, "unit", 3));
+        let flaky = config::Flaky {
+            min_passes_on_retry: 0,
+            ..Default::default()
+        };
+        let known = known_jobs(&flaky.known_jobs).unwrap();
+        assert!(!is_flaky(&flaky, &known, "unit", 10));
+        assert!(known_job_regex("e2e(").is_err());
+    }
+}
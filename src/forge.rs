@@ -0,0 +1,611 @@
+//! The forge-agnostic view of a PR which the sync engine works with.
+use crate::api::models::{bitbucket, gitea, github};
+use crate::config;
+use crate::errors::GitError;
+use crate::repo_name;
+
+use std::fmt::Debug;
+
+/// A source forge which sends PR webhooks
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Forge {
+    GitHub,
+    Gitea,
+    Bitbucket,
+}
+
+impl Forge {
+    /// Short name, used for git remote names
+    pub fn name(&self) -> &'static str {
+        match self {
+            Forge::GitHub => "github",
+            Forge::Gitea => "gitea",
+            Forge::Bitbucket => "bitbucket",
+        }
+    }
+
+    /// Ref on the base repo which points to a PR's head, if this forge has one
+    pub fn pull_ref(&self, number: i64) -> Option<String> {
+        match self {
+            Forge::GitHub | Forge::Gitea => Some(format!("refs/pull/{}/head", number)),
+            Forge::Bitbucket => None,
+        }
+    }
+
+    /// Credentials for fetching `repo_full_name` from this forge. Events
+    /// restored from the queue or replayed may be from a forge which isn't
+    /// configured anymore.
+    pub fn site(&self, repo_full_name: &str) -> Result<&'static config::Site, GitError> {
+        match self {
+            Forge::GitHub => Ok(&config::github_for_repo(repo_full_name).site),
+            Forge::Gitea => config::CONFIG
+                .gitea
+                .as_ref()
+                .map(|gitea| &gitea.site)
+                .ok_or_else(|| GitError::Config("Gitea is not configured".to_string())),
+            Forge::Bitbucket => config::CONFIG
+                .bitbucket
+                .as_ref()
+                .map(|bitbucket| &bitbucket.site)
+                .ok_or_else(|| GitError::Config("Bitbucket is not configured".to_string())),
+        }
+    }
+}
+
+/// A PR event from any forge, as needed by the sync engine. Each forge's
+/// payload model implements this.
+pub trait ForgePullRequest: Debug + Send + Sync {
+    fn forge(&self) -> Forge;
+    /// The event action, normalized to GitHub's names (`opened`,
+    /// `synchronize`, `closed`, `edited`, ...)
+    fn action(&self) -> &str;
+    fn number(&self) -> i64;
+    fn is_fork(&self) -> bool;
+    fn base_full_name(&self) -> &str;
+    fn base_clone_url(&self) -> &str;
+    fn base_ref(&self) -> &str;
+    /// The previous base branch, if this event changed it
+    fn previous_base_ref(&self) -> Option<&str>;
+    fn head_full_name(&self) -> &str;
+    fn head_clone_url(&self) -> &str;
+    fn head_ref(&self) -> &str;
+    fn head_sha(&self) -> &str;
+    /// The GitLab project the base repo is mapped to
+    fn gitlab_project(&self) -> String;
+    /// Login of the PR's author
+    fn author(&self) -> Option<&str> {
+        None
+    }
+    fn title(&self) -> Option<&str> {
+        None
+    }
+    /// Web page of the PR
+    fn html_url(&self) -> Option<&str> {
+        None
+    }
+    /// Names of the PR's labels. Only GitHub reports them.
+    fn labels(&self) -> Vec<&str> {
+        vec![]
+    }
+    /// The label added or removed by a `labeled` or `unlabeled` event
+    fn changed_label(&self) -> Option<&str> {
+        None
+    }
+    /// The author's association with the base repo, ex: `MEMBER`. Only
+    /// GitHub reports it.
+    fn author_association(&self) -> Option<&str> {
+        None
+    }
+    /// Whether the PR was merged, rather than closed without merging
+    fn is_merged(&self) -> bool {
+        false
+    }
+    /// Whether the PR is a draft. Only GitHub reports it.
+    fn is_draft(&self) -> bool {
+        false
+    }
+    /// The event's payload, for storing it across restarts
+    fn payload(&self) -> Option<serde_json::Value> {
+        None
+    }
+}
+
+/// A PR event stored across restarts, see [`store`]
+#[derive(Serialize, Deserialize)]
+struct StoredEvent {
+    forge: String,
+    action: String,
+    payload: serde_json::Value,
+}
+
+/// Serializes a PR event, returning `None` if it can't be
+pub fn store(pr: &dyn ForgePullRequest) -> Option<String> {
+    let stored = StoredEvent {
+        forge: pr.forge().name().to_string(),
+        action: pr.action().to_string(),
+        payload: pr.payload()?,
+    };
+    serde_json::to_string(&stored).ok()
+}
+
+/// Deserializes a PR event serialized with [`store`]
+pub fn restore(stored: &str) -> Result<Box<dyn ForgePullRequest>, GitError> {
+    let stored: StoredEvent = serde_json::from_str(stored)?;
+    match stored.forge.as_str() {
+        "github" => Ok(Box::new(serde_json::from_value::<github::PullRequest>(
+            stored.payload,
+        )?)),
+        "gitea" => Ok(Box::new(serde_json::from_value::<gitea::PullRequest>(
+            stored.payload,
+        )?)),
+        "bitbucket" => Ok(Box::new(BitbucketPullRequest::new(
+            &stored.action,
+            serde_json::from_value(stored.payload)?,
+        ))),
+        forge => Err(GitError::Other(format!(
+            "Unknown forge {} in stored event",
+            forge
+        ))),
+    }
+}
+
+/// Checks that the repo names in a PR event are well formed, so that a
+/// malformed payload is rejected up front rather than producing odd mapping
+/// misses or branch names.
+pub fn validate(pr: &dyn ForgePullRequest) -> Result<(), GitError> {
+    repo_name::canonicalize(pr.base_full_name())?;
+    repo_name::canonicalize(pr.head_full_name())?;
+    Ok(())
+}
+
+fn lookup_mapping(
+    map: &std::sync::Mutex<std::collections::HashMap<String, String>>,
+    name: &str,
+) -> String {
+    map.lock()
+        .unwrap()
+        .get(&repo_name::lookup_key(name))
+        .cloned()
+        .unwrap_or_else(|| name.to_string())
+}
+
+impl ForgePullRequest for github::PullRequest {
+    fn forge(&self) -> Forge {
+        Forge::GitHub
+    }
+
+    fn action(&self) -> &str {
+        &self.action
+    }
+
+    fn number(&self) -> i64 {
+        self.pull_request.number
+    }
+
+    fn is_fork(&self) -> bool {
+        self.pull_request.head.repo.fork
+    }
+
+    fn base_full_name(&self) -> &str {
+        &self.pull_request.base.repo.full_name
+    }
+
+    fn base_clone_url(&self) -> &str {
+        &self.repository.ssh_url
+    }
+
+    fn base_ref(&self) -> &str {
+        &self.pull_request.base.ref_key
+    }
+
+    fn previous_base_ref(&self) -> Option<&str> {
+        self.changes
+            .as_ref()
+            .and_then(|c| c.base.as_ref())
+            .and_then(|b| b.ref_key.as_ref())
+            .and_then(|r| r.from.as_deref())
+    }
+
+    fn head_full_name(&self) -> &str {
+        &self.pull_request.head.repo.full_name
+    }
+
+    fn head_clone_url(&self) -> &str {
+        &self.pull_request.head.repo.ssh_url
+    }
+
+    fn head_ref(&self) -> &str {
+        &self.pull_request.head.ref_key
+    }
+
+    fn head_sha(&self) -> &str {
+        &self.pull_request.head.sha
+    }
+
+    fn gitlab_project(&self) -> String {
+        let name = self.base_full_name();
+        config::gitlab_repo_for(name).unwrap_or_else(|| name.to_string())
+    }
+
+    fn author(&self) -> Option<&str> {
+        self.pull_request.user.login.as_deref()
+    }
+
+    fn title(&self) -> Option<&str> {
+        self.pull_request.title.as_deref()
+    }
+
+    fn html_url(&self) -> Option<&str> {
+        self.pull_request.html_url.as_deref()
+    }
+
+    fn labels(&self) -> Vec<&str> {
+        self.pull_request
+            .labels
+            .iter()
+            .flatten()
+            .map(|label| label.name.as_str())
+            .collect()
+    }
+
+    fn changed_label(&self) -> Option<&str> {
+        self.label.as_ref().map(|label| label.name.as_str())
+    }
+
+    fn author_association(&self) -> Option<&str> {
+        self.pull_request.author_association.as_deref()
+    }
+
+    fn is_merged(&self) -> bool {
+        self.pull_request.merged == Some(true)
+    }
+
+    fn is_draft(&self) -> bool {
+        self.pull_request.draft == Some(true)
+    }
+
+    fn payload(&self) -> Option<serde_json::Value> {
+        serde_json::to_value(self).ok()
+    }
+}
+
+impl ForgePullRequest for gitea::PullRequest {
+    fn forge(&self) -> Forge {
+        Forge::Gitea
+    }
+
+    fn action(&self) -> &str {
+        match self.action.as_str() {
+            "synchronized" => "synchronize",
+            action => action,
+        }
+    }
+
+    fn number(&self) -> i64 {
+        self.pull_request.number
+    }
+
+    fn is_fork(&self) -> bool {
+        self.pull_request.head.repo.full_name != self.pull_request.base.repo.full_name
+    }
+
+    fn base_full_name(&self) -> &str {
+        &self.pull_request.base.repo.full_name
+    }
+
+    fn base_clone_url(&self) -> &str {
+        &self.repository.ssh_url
+    }
+
+    fn base_ref(&self) -> &str {
+        &self.pull_request.base.ref_key
+    }
+
+    /// Gitea reports a changed target branch as a changed `ref`
+    fn previous_base_ref(&self) -> Option<&str> {
+        self.changes
+            .as_ref()
+            .and_then(|c| c.ref_key.as_ref())
+            .and_then(|r| r.from.as_deref())
+    }
+
+    fn head_full_name(&self) -> &str {
+        &self.pull_request.head.repo.full_name
+    }
+
+    fn head_clone_url(&self) -> &str {
+        &self.pull_request.head.repo.ssh_url
+    }
+
+    fn head_ref(&self) -> &str {
+        &self.pull_request.head.ref_key
+    }
+
+    fn head_sha(&self) -> &str {
+        &self.pull_request.head.sha
+    }
+
+    fn gitlab_project(&self) -> String {
+        lookup_mapping(&config::GITEA_TO_LAB, self.base_full_name())
+    }
+
+    fn author(&self) -> Option<&str> {
+        let user = self.pull_request.user.as_ref()?;
+        user.login.as_deref().or(user.username.as_deref())
+    }
+
+    fn title(&self) -> Option<&str> {
+        self.pull_request.title.as_deref()
+    }
+
+    fn html_url(&self) -> Option<&str> {
+        self.pull_request.html_url.as_deref()
+    }
+
+    fn is_merged(&self) -> bool {
+        self.pull_request.merged == Some(true)
+    }
+
+    fn payload(&self) -> Option<serde_json::Value> {
+        serde_json::to_value(self).ok()
+    }
+}
+
+/// A Bitbucket Cloud PR event. Bitbucket sends the action in the
+/// `X-Event-Key` header rather than the payload, and doesn't include clone
+/// URLs, so both are filled in here.
+///
+/// Note that Bitbucket only sends abbreviated commit hashes, which syncs
+/// complete from the fetched head.
+#[derive(Debug)]
+pub struct BitbucketPullRequest {
+    action: String,
+    base_clone_url: String,
+    head_clone_url: String,
+    pub event: bitbucket::PullRequestEvent,
+}
+
+impl BitbucketPullRequest {
+    pub fn new(event_key: &str, event: bitbucket::PullRequestEvent) -> BitbucketPullRequest {
+        let action = match event_key {
+            "pullrequest:created" => "opened",
+            "pullrequest:updated" => "synchronize",
+            "pullrequest:fulfilled" | "pullrequest:rejected" => "closed",
+            other => other,
+        };
+        let hostname = config::CONFIG
+            .bitbucket
+            .as_ref()
+            .and_then(|b| b.site.hostname.clone())
+            .unwrap_or_else(|| "bitbucket.org".to_string());
+        let pull = &event.pullrequest;
+        BitbucketPullRequest {
+            action: action.to_owned(),
+            base_clone_url: format!(
+                "git@{}:{}.git",
+                hostname, pull.destination.repository.full_name
+            ),
+            head_clone_url: format!("git@{}:{}.git", hostname, pull.source.repository.full_name),
+            event,
+        }
+    }
+}
+
+impl ForgePullRequest for BitbucketPullRequest {
+    fn forge(&self) -> Forge {
+        Forge::Bitbucket
+    }
+
+    fn action(&self) -> &str {
+        &self.action
+    }
+
+    fn number(&self) -> i64 {
+        self.event.pullrequest.id
+    }
+
+    fn is_fork(&self) -> bool {
+        self.head_full_name() != self.base_full_name()
+    }
+
+    fn base_full_name(&self) -> &str {
+        &self.event.pullrequest.destination.repository.full_name
+    }
+
+    fn base_clone_url(&self) -> &str {
+        &self.base_clone_url
+    }
+
+    fn base_ref(&self) -> &str {
+        &self.event.pullrequest.destination.branch.name
+    }
+
+    fn previous_base_ref(&self) -> Option<&str> {
+        None
+    }
+
+    fn head_full_name(&self) -> &str {
+        &self.event.pullrequest.source.repository.full_name
+    }
+
+    fn head_clone_url(&self) -> &str {
+        &self.head_clone_url
+    }
+
+    fn head_ref(&self) -> &str {
+        &self.event.pullrequest.source.branch.name
+    }
+
+    fn head_sha(&self) -> &str {
+        &self.event.pullrequest.source.commit.hash
+    }
+
+    fn gitlab_project(&self) -> String {
+        lookup_mapping(&config::BITBUCKET_TO_LAB, self.base_full_name())
+    }
+
+    fn author(&self) -> Option<&str> {
+        self.event
+            .pullrequest
+            .author
+            .as_ref()
+            .and_then(|author| author.nickname.as_deref())
+    }
+
+    fn title(&self) -> Option<&str> {
+        self.event.pullrequest.title.as_deref()
+    }
+
+    fn html_url(&self) -> Option<&str> {
+        self.event
+            .pullrequest
+            .links
+            .as_ref()
+            .and_then(|links| links.html.as_ref())
+            .and_then(|html| html.href.as_deref())
+    }
+
+    fn is_merged(&self) -> bool {
+        self.event.pullrequest.state.as_deref() == Some("MERGED")
+    }
+
+    /// The normalized action is stored alongside, as Bitbucket sends it in a
+    /// header
+    fn payload(&self) -> Option<serde_json::Value> {
+        serde_json::to_value(&self.event).ok()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::testing::{assert_within_budget, read_testdata_to_string};
+    use std::time::Duration;
+
+    #[test]
+    fn payload_parsing_budget() {
+        let body = read_testdata_to_string("github_open_pr_forked.json");
+        assert_within_budget(
+            "GitHub PR payload parsing",
+            Duration::from_millis(5),
+            100,
+            || serde_json::from_str::<github::PullRequest>(&body).unwrap(),
+        );
+    }
+
+    #[test]
+    fn unconfigured_forge_site() {
+        assert!(matches!(
+            Forge::Gitea.site("brndnmtthws/labhub"),
+            Err(GitError::Config(_))
+        ));
+        assert!(matches!(
+            Forge::Bitbucket.site("brndnmtthws/labhub"),
+            Err(GitError::Config(_))
+        ));
+    }
+
+    #[test]
+    fn github_draft_pr() {
+        let mut event: serde_json::Value =
+            serde_json::from_str(&read_testdata_to_string("github_open_pr_forked.json")).unwrap();
+        let pr: github::PullRequest = serde_json::from_value(event.clone()).unwrap();
+        assert!(!pr.is_draft());
+        event["action"] = "converted_to_draft".into();
+        event["pull_request"]["draft"] = true.into();
+        let pr: github::PullRequest = serde_json::from_value(event).unwrap();
+        assert_eq!(pr.action(), "converted_to_draft");
+        assert!(pr.is_draft());
+    }
+
+    #[test]
+    fn gitea_open_pr_fork() {
+        let pr: gitea::PullRequest =
+            serde_json::from_str(&read_testdata_to_string("gitea_open_pr_forked.json")).unwrap();
+        assert_eq!(pr.forge(), Forge::Gitea);
+        assert!(pr.is_fork());
+        assert_eq!(pr.number(), 1);
+        assert_eq!(pr.base_full_name(), "octocat/hello-world");
+        assert_eq!(pr.head_full_name(), "contributor/hello-world");
+        assert_eq!(pr.head_ref(), "fix-typo");
+        assert_eq!(
+            pr.head_clone_url(),
+            "git@gitea.example.com:contributor/hello-world.git"
+        );
+        assert_eq!(pr.previous_base_ref(), None);
+    }
+
+    #[test]
+    fn gitea_edited_pr_base() {
+        let mut event: serde_json::Value =
+            serde_json::from_str(&read_testdata_to_string("gitea_open_pr_forked.json")).unwrap();
+        event["action"] = "edited".into();
+        event["changes"] = serde_json::json!({ "ref": { "from": "develop" } });
+        let pr: gitea::PullRequest = serde_json::from_value(event.clone()).unwrap();
+        assert_eq!(pr.action(), "edited");
+        assert_eq!(pr.previous_base_ref(), Some("develop"));
+
+        event["changes"] = serde_json::json!({ "title": { "from": "Fix typo" } });
+        let pr: gitea::PullRequest = serde_json::from_value(event).unwrap();
+        assert_eq!(pr.previous_base_ref(), None);
+    }
+
+    #[test]
+    fn bitbucket_pr_created_fork() {
+        let event: bitbucket::PullRequestEvent =
+            serde_json::from_str(&read_testdata_to_string("bitbucket_pr_created_forked.json"))
+                .unwrap();
+        let pr = BitbucketPullRequest::new("pullrequest:created", event);
+        assert_eq!(pr.forge(), Forge::Bitbucket);
+        assert_eq!(pr.action(), "opened");
+        assert!(pr.is_fork());
+        assert_eq!(pr.number(), 1);
+        assert_eq!(pr.base_ref(), "main");
+        assert_eq!(pr.head_ref(), "fix-typo");
+        assert_eq!(
+            pr.head_clone_url(),
+            "git@bitbucket.org:contributor/hello-world.git"
+        );
+    }
+
+    #[test]
+    fn github_open_pr_fork() {
+        let pr: github::PullRequest =
+            serde_json::from_str(&read_testdata_to_string("github_open_pr_forked.json")).unwrap();
+        assert_eq!(pr.forge(), Forge::GitHub);
+        assert!(pr.is_fork());
+        assert_eq!(pr.action(), "opened");
+        assert_eq!(pr.previous_base_ref(), None);
+    }
+
+    #[test]
+    fn github_labeled_pr() {
+        let pr: github::PullRequest =
+            serde_json::from_str(&read_testdata_to_string("github_pr_labeled_forked.json"))
+                .unwrap();
+        assert_eq!(pr.action(), "labeled");
+        assert_eq!(pr.labels(), ["bug", "run-e2e"]);
+        assert_eq!(pr.changed_label(), Some("run-e2e"));
+    }
+
+    #[test]
+    fn store_and_restore() {
+        let event: bitbucket::PullRequestEvent =
+            serde_json::from_str(&read_testdata_to_string("bitbucket_pr_created_forked.json"))
+                .unwrap();
+        let pr = BitbucketPullRequest::new("pullrequest:fulfilled", event);
+        let restored = restore(&store(&pr).unwrap()).unwrap();
+        assert_eq!(restored.forge(), Forge::Bitbucket);
+        assert_eq!(restored.action(), "closed");
+        assert_eq!(restored.head_sha(), pr.head_sha());
+
+        let pr: github::PullRequest =
+            serde_json::from_str(&read_testdata_to_string("github_open_pr_forked.json")).unwrap();
+        let restored = restore(&store(&pr).unwrap()).unwrap();
+        assert_eq!(restored.forge(), Forge::GitHub);
+        assert_eq!(restored.head_ref(), pr.head_ref());
+        assert_eq!(restored.number(), pr.number());
+
+        assert!(restore(r#"{"forge":"svn","action":"opened","payload":{}}"#).is_err());
+    }
+}
@@ -0,0 +1,138 @@
+//! Running the git CLI, for what libgit2 can't do: sending push options, and
+//! SSH connections through an egress proxy.
+use crate::config;
+use crate::errors::GitError;
+
+use log::{error, info};
+use std::io;
+use std::path::Path;
+use std::process::Command;
+
+/// Whether SSH connections have to go through the git CLI, because a proxy
+/// command is configured
+pub fn ssh_proxied() -> bool {
+    config::CONFIG.proxy.ssh_command.is_some()
+}
+
+/// Quotes a value for the shell which runs `GIT_SSH_COMMAND`
+fn shell_quote(value: &str) -> String {
+    format!("'{}'", value.replace('\'', r"'\''"))
+}
+
+/// The SSH command git runs. Without a key, ssh authenticates with the
+/// ssh-agent at `SSH_AUTH_SOCK`. ssh can't be given the key's passphrase, so
+/// encrypted keys have to be added to the agent.
+fn ssh_command(ssh_key: &str, known_hosts: Option<&str>, proxy_command: Option<&str>) -> String {
+    let mut command = "ssh".to_string();
+    if !ssh_key.is_empty() {
+        command.push_str(" -i ");
+        command.push_str(&shell_quote(ssh_key));
+        command.push_str(" -o IdentitiesOnly=yes");
+    }
+    match known_hosts {
+        Some(known_hosts) => {
+            command.push_str(" -o ");
+            command.push_str(&shell_quote(&format!("UserKnownHostsFile={}", known_hosts)));
+            command.push_str(" -o StrictHostKeyChecking=yes");
+        }
+        None => command.push_str(" -o StrictHostKeyChecking=accept-new"),
+    }
+    if let Some(proxy_command) = proxy_command {
+        command.push_str(" -o ");
+        command.push_str(&shell_quote(&format!("ProxyCommand={}", proxy_command)));
+    }
+    command
+}
+
+pub fn push_args(remote: &str, refspecs: &[String], options: &[String]) -> Vec<String> {
+    let mut args = vec!["push".to_string()];
+    for option in options {
+        args.push("--push-option".to_string());
+        args.push(option.clone());
+    }
+    args.push(remote.to_string());
+    args.extend(refspecs.iter().cloned());
+    args
+}
+
+/// Runs git with `args`, authenticating with the site's SSH key. `git_dir`
+/// is the repo's git directory, if any.
+pub fn run(git_dir: Option<&Path>, site: &config::Site, args: &[String]) -> Result<(), GitError> {
+    info!("Running git {}", args.join(" "));
+    let mut command = Command::new("git");
+    if let Some(git_dir) = git_dir {
+        command.arg("--git-dir").arg(git_dir);
+    }
+    let output = command
+        .args(args)
+        .env(
+            "GIT_SSH_COMMAND",
+            ssh_command(
+                &site.ssh_key_path(),
+                config::CONFIG.ssh.known_hosts.as_deref(),
+                config::CONFIG.proxy.ssh_command.as_deref(),
+            ),
+        )
+        .output()
+        .map_err(|err| io::Error::new(err.kind(), format!("Unable to run git: {}", err)))?;
+    if !output.status.success() {
+        let msg = format!(
+            "git {} failed: status={} stderr={}",
+            args.first().map(String::as_str).unwrap_or_default(),
+            output.status,
+            String::from_utf8_lossy(&output.stderr)
+        );
+        error!("{}", msg);
+        return Err(GitError::GitCommand(msg));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_push_args() {
+        assert_eq!(
+            push_args(
+                "gitlab",
+                &["+refs/heads/a:refs/heads/a".to_string()],
+                &["ci.skip".to_string(), "ci.variable=FOO=bar".to_string()]
+            ),
+            [
+                "push",
+                "--push-option",
+                "ci.skip",
+                "--push-option",
+                "ci.variable=FOO=bar",
+                "gitlab",
+                "+refs/heads/a:refs/heads/a"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_ssh_command() {
+        assert_eq!(
+            ssh_command("/etc/labhub/ssh/gitlab", None, None),
+            "ssh -i '/etc/labhub/ssh/gitlab' -o IdentitiesOnly=yes \
+             -o StrictHostKeyChecking=accept-new"
+        );
+        assert_eq!(
+            ssh_command("key", None, Some("nc -X 5 -x proxy:1080 %h %p")),
+            "ssh -i 'key' -o IdentitiesOnly=yes -o StrictHostKeyChecking=accept-new \
+             -o 'ProxyCommand=nc -X 5 -x proxy:1080 %h %p'"
+        );
+        assert_eq!(
+            ssh_command("key", Some("/etc/labhub/known_hosts"), None),
+            "ssh -i 'key' -o IdentitiesOnly=yes -o 'UserKnownHostsFile=/etc/labhub/known_hosts' \
+             -o StrictHostKeyChecking=yes"
+        );
+        assert_eq!(
+            ssh_command("", None, None),
+            "ssh -o StrictHostKeyChecking=accept-new"
+        );
+        assert_eq!(shell_quote("it's"), r"'it'\''s'");
+    }
+}
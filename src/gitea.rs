@@ -0,0 +1,35 @@
+//! Gitea and Forgejo webhook handling.
+use crate::api::models::{self, gitea};
+use crate::config;
+use crate::errors::RequestErrorResult;
+use crate::forge::ForgePullRequest;
+use crate::sync;
+
+use log::info;
+
+/// Handles the body of a Gitea (or Forgejo) webhook whose signature has
+/// already been checked, where `event_type` is the `X-Gitea-Event` header.
+/// PRs are synced by the same engine as GitHub PRs.
+pub async fn handle_event_body(event_type: &str, body: &str) -> Result<String, RequestErrorResult> {
+    match event_type {
+        "pull_request" => {
+            if config::feature_enabled(&config::Feature::ExternalPr) {
+                let pr: gitea::PullRequest = models::parse(body)?;
+                let action = pr.action().to_owned();
+                if config::action_enabled(&action) {
+                    info!("Gitea PullRequest action={}", action);
+                    sync::handle_pr(Box::new(pr))?;
+                } else {
+                    info!("Event trigger action not enabled. Skipping event.");
+                }
+            } else {
+                info!("ExternalPr feature not enabled. Skipping event.");
+            }
+            Ok(String::from("Thanks buddy bro 😍"))
+        }
+        _ => Ok(format!(
+            "Unhandled event_type={}, doing nothing 😀",
+            event_type,
+        )),
+    }
+}
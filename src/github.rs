@@ -1,320 +1,457 @@
-use crate::api::models::github;
+//! GitHub webhook handling: PR events, comment commands, and the comments
+//! LabHub posts on PRs.
+use crate::api;
+use crate::api::models::{self, github, gitlab};
 use crate::api::{github_client, gitlab_client};
+use crate::audit;
 use crate::commands;
+use crate::comment_mirror;
 use crate::config;
+use crate::coverage;
+use crate::dedupe;
+use crate::durations;
 use crate::errors::{GitError, RequestErrorResult};
+use crate::event_webhooks::{self, Event};
+use crate::forge::{self, ForgePullRequest};
+use crate::health;
+use crate::history;
+use crate::messages::{self, Message};
+use crate::pause;
+use crate::repo_name;
+use crate::state;
+use crate::sync;
+
+use log::{error, info, warn};
+use regex::Regex;
 
-use git2::build::RepoBuilder;
-use git2::{FetchOptions, PushOptions, RemoteCallbacks, Repository};
-use log::{debug, error, info, warn};
-use std::collections::HashMap;
-use std::path::Path;
-use std::sync::Mutex;
-use tempfile::{tempdir, TempDir};
-
-#[cfg(test)]
-use mockers_derive::mocked;
-
-struct RepoData {
-    repo: Repository,
-    #[allow(dead_code)]
-    dir: TempDir,
+fn get_gitlab_repo_name(github_repo_full_name: &str) -> String {
+    config::gitlab_repo_for(github_repo_full_name)
+        .unwrap_or_else(|| github_repo_full_name.to_string())
 }
 
-lazy_static! {
-    static ref REPOS: Mutex<HashMap<String, RepoData>> = {
-        #[allow(unused_mut)]
-        let mut m: HashMap<String, RepoData> = HashMap::new();
-        Mutex::new(m)
-    };
+/// Hidden markers identify comments which are refreshed in place rather than
+/// treated as stale
+const MARKER_PREFIX: &str = "<!-- labhub:";
+const REVIEW_APP_MARKER: &str = "<!-- labhub:review-app -->";
+const LFS_MARKER: &str = "<!-- labhub:lfs -->";
+const FLAKY_MARKER: &str = "<!-- labhub:flaky -->";
+pub(crate) const PIPELINE_MARKER: &str = "<!-- labhub:pipeline -->";
+pub(crate) const ARTIFACTS_MARKER: &str = "<!-- labhub:artifacts -->";
+const COVERAGE_MARKER: &str = "<!-- labhub:coverage -->";
+const PROTECTED_MARKER: &str = "<!-- labhub:protected -->";
+
+/// Returns the bot's comments on a PR
+async fn get_bot_comments(
+    client: &reqwest::Client,
+    org: &str,
+    repo: &str,
+    number: i64,
+) -> Result<Vec<github::IssueCommentComment>, GitError> {
+    let mut comments = github_client::get_all_issue_comments(client, org, repo, number).await?;
+
+    let github = config::github_for_repo(&format!("{}/{}", org, repo));
+    comments
+        .retain(|c| c.user.as_ref().and_then(|u| u.login.as_ref()) == Some(&github.site.username));
+    Ok(comments)
 }
 
-static APP_USER_AGENT: &str = concat!(env!("CARGO_PKG_NAME"), "/", env!("CARGO_PKG_VERSION"),);
-
-fn get_gitlab_repo_name(github_repo_full_name: &str) -> String {
-    let hub_to_lab_lock = config::HUB_TO_LAB.lock().unwrap();
-    let hub_to_lab = &*hub_to_lab_lock;
-    if hub_to_lab.contains_key(github_repo_full_name) {
-        hub_to_lab.get(github_repo_full_name).unwrap().to_string()
-    } else {
-        github_repo_full_name.to_string()
+/// Deletes or minimizes the bot's earlier comments on a PR, according to the
+/// configured `stale_comment_policy`, so that only the latest one stays
+/// visible.
+async fn remove_stale_comments(
+    client: &reqwest::Client,
+    org: &str,
+    repo: &str,
+    number: i64,
+) -> Result<(), GitError> {
+    let policy = &config::CONFIG.comments.stale_comment_policy;
+    if *policy == config::StaleCommentPolicy::Keep {
+        return Ok(());
     }
-}
 
-fn get_remote_callbacks(site: &config::Site) -> RemoteCallbacks {
-    let mut remote_callbacks = RemoteCallbacks::new();
-    let ssh_key = site.ssh_key.clone();
-    remote_callbacks.credentials(move |_url, _user_from_url, cred| {
-        debug!("Entered Git credential callback, cred={:?}", cred);
-        if cred.contains(git2::CredentialType::USERNAME) {
-            git2::Cred::username(&"git".to_string())
-        } else {
-            let path = Path::new(&ssh_key);
-            git2::Cred::ssh_key(&"git".to_string(), None, &path, None)
-        }
-    });
-    remote_callbacks.push_update_reference(|reference, status_option| {
-        match status_option {
-            Some(status) => error!(
-                "Failed to update remote ref {} message={:?}",
-                reference, status
-            ),
-            _ => info!("Updated remote ref {}", reference),
-        };
-        Ok(())
-    });
-    remote_callbacks.update_tips(|reference, oid1, oid2| {
-        debug!(
-            "Updated tips, ref={} oid1={} oid2={}",
-            reference, oid1, oid2
-        );
-        true
-    });
-    remote_callbacks
-}
-
-#[cfg_attr(test, mocked)]
-trait RepositoryExt {
-    fn add_remotes(&mut self, pr_handle: &PrHandle) -> Result<(), GitError>;
-    fn fetch_github_remote(&self, pr_handle: &PrHandle) -> Result<(), GitError>;
-    fn create_ref_for_pr(&self, pr_handle: &PrHandle) -> Result<(), GitError>;
-    fn push_pr_ref(&self, pr_handle: &PrHandle) -> Result<(), GitError>;
-    fn delete_pr_ref(&self, pr_handle: &PrHandle) -> Result<(), GitError>;
-}
-
-#[derive(Debug, Eq, PartialEq)]
-pub struct PrHandle {
-    base_full_name: String,
-    head_full_name: String,
-    github_remote: String,
-    gitlab_remote: String,
-    gitref: String,
-    github_clone_url: String,
-    pr_number: i64,
-}
-
-impl PrHandle {
-    fn new(pr: &github::PullRequest) -> PrHandle {
-        PrHandle {
-            gitref: pr.pull_request.head.ref_key.clone(),
-            pr_number: pr.pull_request.number,
-            github_clone_url: pr.pull_request.head.repo.ssh_url.clone(),
-            github_remote: format!("github-{}", pr.pull_request.number,),
-            gitlab_remote: "gitlab".to_string(),
-            base_full_name: pr.pull_request.base.repo.full_name.clone(),
-            head_full_name: pr.pull_request.head.repo.full_name.clone(),
+    let comments = get_bot_comments(client, org, repo, number).await?;
+    let bot_comments = comments.iter().filter(|c| !c.body.contains(MARKER_PREFIX));
+    for comment in bot_comments {
+        match policy {
+            config::StaleCommentPolicy::Delete => {
+                if let Some(id) = comment.id {
+                    info!(
+                        "Deleting stale comment id={} on {}/{}#{}",
+                        id, org, repo, number
+                    );
+                    github_client::delete_issue_comment(client, org, repo, id).await?;
+                }
+            }
+            config::StaleCommentPolicy::Minimize => {
+                if let Some(node_id) = comment.node_id.as_ref() {
+                    info!(
+                        "Minimizing stale comment node_id={} on {}/{}#{}",
+                        node_id, org, repo, number
+                    );
+                    github_client::minimize_comment(client, org, repo, node_id).await?;
+                }
+            }
+            config::StaleCommentPolicy::Keep => {}
         }
     }
+    Ok(())
 }
 
-impl RepositoryExt for Repository {
-    fn add_remotes(&mut self, pr_handle: &PrHandle) -> Result<(), GitError> {
-        let github_refspec = format!("+refs/heads/*:refs/remotes/{}/*", pr_handle.github_remote);
-        self.remote_add_fetch(&pr_handle.github_remote, &github_refspec)?;
-        self.remote_set_url(&pr_handle.github_remote, &pr_handle.github_clone_url)?;
-        let hostname = config::CONFIG
-            .gitlab
-            .ssh_url
-            .clone()
-            .or(config::CONFIG.gitlab.hostname.clone())
-            .unwrap_or("gitlab.com".to_string());
-        let gitlab_url = format!(
-            "ssh://git@{}/{}.git",
-            hostname,
-            get_gitlab_repo_name(&pr_handle.base_full_name)
-        );
-        let gitlab_refspec = "refs/heads/master:refs/heads/master".to_string();
-        self.remote_add_push(&pr_handle.gitlab_remote, &gitlab_refspec)?;
-        self.remote_set_url(&pr_handle.gitlab_remote, &gitlab_url)?;
-        Ok(())
+async fn reconcile_repo(client: &reqwest::Client, github_repo: &str) -> Result<(), GitError> {
+    let repo_full_name_parts: Vec<&str> = github_repo.split('/').collect();
+    if repo_full_name_parts.len() != 2 {
+        return Err(GitError::BadRequest(format!(
+            "Invalid repo name {}",
+            github_repo
+        )));
     }
+    let (org, repo) = (repo_full_name_parts[0], repo_full_name_parts[1]);
+    let repository = github_client::get_repo(client, org, repo).await?;
 
-    fn fetch_github_remote(&self, pr_handle: &PrHandle) -> Result<(), GitError> {
+    let pulls = github_client::get_all_pulls(client, org, repo, "open").await?;
+    for pr in pulls {
+        let pullrequest = github::PullRequest {
+            action: "synchronize".to_owned(),
+            number: pr.number,
+            changes: None,
+            pull_request: pr,
+            label: None,
+            repository: repository.clone(),
+            sender: github::GithubSender {
+                login: Some(config::github_for_repo(github_repo).site.username.clone()),
+                ..Default::default()
+            },
+        };
+        if !sync::is_bridged(&pullrequest) || sync::is_pr_synced(client, &pullrequest).await? {
+            continue;
+        }
         info!(
-            "Fetching remote={} ref={}",
-            pr_handle.github_remote, pr_handle.gitref
+            "PR {}#{} head sha={} is missing from GitLab, syncing",
+            github_repo, pullrequest.number, pullrequest.pull_request.head.sha
         );
-        let mut remote = self.find_remote(&pr_handle.github_remote)?;
-
-        let mut fetch_options = FetchOptions::new();
-        fetch_options.remote_callbacks(get_remote_callbacks(&config::CONFIG.github));
-
-        remote.fetch(&[&pr_handle.gitref], Some(&mut fetch_options), None)?;
-
-        info!("Successfully fetched remote");
-        Ok(())
+        sync::handle_pr(Box::new(pullrequest))?;
     }
+    Ok(())
+}
 
-    fn create_ref_for_pr(&self, pr_handle: &PrHandle) -> Result<(), GitError> {
-        let github_ref = format!(
-            "refs/remotes/{}/{}",
-            pr_handle.github_remote, pr_handle.gitref
-        );
-        let gitlab_ref = format!(
-            "refs/heads/pr-{}/{}/{}",
-            pr_handle.pr_number, pr_handle.head_full_name, pr_handle.gitref
-        );
-        let id = self.refname_to_id(&github_ref)?;
-        debug!("Creating ref {} from {}, id={}", gitlab_ref, github_ref, id);
-        self.reference(&gitlab_ref, id, true, "new ref")?;
-        Ok(())
+/// Syncs any open bridged PRs whose head isn't on GitLab yet, which recovers
+/// from webhooks missed while LabHub was down.
+pub async fn reconcile_open_prs() {
+    if !config::feature_enabled(&config::Feature::ExternalPr) {
+        info!("ExternalPr feature not enabled. Skipping reconciliation.");
+        return;
     }
-
-    fn push_pr_ref(&self, pr_handle: &PrHandle) -> Result<(), GitError> {
-        info!(
-            "Pushing PR remote={} ref={} number={} base_full_name={}",
-            pr_handle.gitlab_remote,
-            pr_handle.gitref,
-            pr_handle.pr_number,
-            pr_handle.base_full_name
-        );
-        let mut gitremote = self.find_remote(&pr_handle.gitlab_remote)?;
-        let mut push_options = PushOptions::new();
-        push_options.remote_callbacks(get_remote_callbacks(&config::CONFIG.gitlab));
-
-        let refspec = format!(
-            "+refs/heads/pr-{}/{}/{}:refs/heads/pr-{}/{}/{}",
-            pr_handle.pr_number,
-            pr_handle.head_full_name,
-            pr_handle.gitref,
-            pr_handle.pr_number,
-            pr_handle.head_full_name,
-            pr_handle.gitref
-        );
-        gitremote.push(&[&refspec], Some(&mut push_options))?;
-
-        info!("Successfully pushed");
-        Ok(())
+    health::wait_for_gitlab().await;
+    let client = match api::new_client() {
+        Ok(client) => client,
+        Err(err) => {
+            error!("Unable to create client for reconciliation: {:?}", err);
+            return;
+        }
+    };
+    // Repos mapped by wildcard are reconciled as their events come in
+    let mappings = config::CONFIG.mappings.iter().filter(|m| !m.is_wildcard());
+    for mapping in mappings {
+        info!("Reconciling open PRs for {}", mapping.github_repo);
+        if let Err(err) = reconcile_repo(&client, &mapping.github_repo).await {
+            error!(
+                "Error reconciling open PRs for {}: {:?}",
+                mapping.github_repo, err
+            );
+        }
     }
+}
 
-    fn delete_pr_ref(&self, pr_handle: &PrHandle) -> Result<(), GitError> {
-        info!(
-            "Deleting PR remote={} ref={} number={} base_full_name={}",
-            pr_handle.gitlab_remote,
-            pr_handle.gitref,
-            pr_handle.pr_number,
-            pr_handle.base_full_name
-        );
-        let mut gitremote = self.find_remote(&pr_handle.gitlab_remote)?;
-        let mut push_options = PushOptions::new();
-        push_options.remote_callbacks(get_remote_callbacks(&config::CONFIG.gitlab));
-
-        let refspec = format!(
-            ":refs/heads/pr-{}/{}/{}",
-            pr_handle.pr_number, pr_handle.head_full_name, pr_handle.gitref,
-        );
-        gitremote.push(&[&refspec], Some(&mut push_options))?;
+/// Fetches a PR from GitHub, as a `synchronize` event
+async fn fetch_pr_event(
+    client: &reqwest::Client,
+    repo_full_name: &str,
+    number: i64,
+) -> Result<github::PullRequest, GitError> {
+    let (org, repo) = repo_full_name
+        .split_once('/')
+        .ok_or(GitError::BadRequest(format!(
+            "Invalid repo name {}",
+            repo_full_name
+        )))?;
+    let pull = serde_json::to_value(github_client::get_pull(client, org, repo, number).await?)?;
+    Ok(serde_json::from_value(serde_json::json!({
+        "action": "synchronize",
+        "number": number,
+        "repository": pull["base"]["repo"],
+        "sender": pull["user"],
+        "pull_request": pull,
+    }))?)
+}
 
-        info!("Successfully pushed");
-        Ok(())
+/// Fetches a PR and syncs it to GitLab right away, bypassing the queue, for
+/// one-off manual syncs
+pub async fn sync_pr_now(repo_full_name: &str, number: i64) -> Result<String, GitError> {
+    let client = api::new_client()?;
+    let pr = fetch_pr_event(&client, repo_full_name, number).await?;
+    forge::validate(&pr)?;
+    if !sync::is_bridged(&pr) {
+        return Ok(format!(
+            "{}#{} isn't from a fork and the sync_internal_prs feature is off, nothing to sync",
+            repo_full_name, number
+        ));
     }
+    // Held events would be lost when the command exits, so it refuses
+    if pause::is_paused(&pr) {
+        return Err(GitError::BadRequest(format!(
+            "Mirroring of {} is paused, resume it to sync #{}",
+            repo_full_name, number
+        )));
+    }
+    sync::sync_pr(&pr).await
 }
 
-fn clone_repo(url: &str) -> Result<RepoData, GitError> {
-    // Setup fetch options
-    let mut fetch_options = FetchOptions::new();
-    fetch_options.remote_callbacks(get_remote_callbacks(&config::CONFIG.github));
+fn review_app_comment_body(language: config::Language, sha: &str, url: &str) -> String {
+    format!(
+        "{}\n\n{}",
+        messages::text(language, Message::ReviewApp { sha, url }),
+        REVIEW_APP_MARKER
+    )
+}
 
-    // Initialize & clone repo
-    let mut builder = RepoBuilder::new();
-    builder.fetch_options(fetch_options);
-    let dir = tempdir()?;
-    match builder.clone(url, dir.as_ref()) {
-        Ok(repo) => {
+/// Posts a comment identified by `marker`, or refreshes the one previously
+/// posted with it
+async fn upsert_marked_comment(
+    client: &reqwest::Client,
+    github_repo: &str,
+    number: i64,
+    marker: &str,
+    body: &str,
+) -> Result<(), GitError> {
+    let (org, repo) = github_repo
+        .split_once('/')
+        .ok_or(GitError::BadRequest(format!(
+            "Invalid repo name {}",
+            github_repo
+        )))?;
+    let existing = get_bot_comments(client, org, repo, number)
+        .await?
+        .into_iter()
+        .find(|c| c.body.contains(marker));
+    let result = match existing {
+        Some(comment) if comment.body == body => return Ok(()),
+        Some(github::IssueCommentComment { id: Some(id), .. }) => {
             info!(
-                "Cloned new repo {} into {}",
-                url,
-                dir.as_ref().to_str().unwrap()
+                "Refreshing comment id={} marker={} on {}#{}",
+                id, marker, github_repo, number
             );
-
-            Ok(RepoData { repo, dir })
+            github_client::update_issue_comment(client, org, repo, id, body).await
         }
-        Err(err) => {
-            let msg = format!("Error cloning repo: {:?}", err);
-            error!("{}", &msg);
-            Err(GitError { message: msg })
+        _ => {
+            info!(
+                "Posting comment marker={} on {}#{}",
+                marker, github_repo, number
+            );
+            github_client::create_issue_comment(client, org, repo, number, body)
+                .await
+                .map(|_| ())
         }
-    }
+    };
+    audit::record(
+        audit::LABHUB,
+        github_repo,
+        Some(number),
+        audit::Action::Comment,
+        &history::summarize(body),
+        &result,
+    );
+    result?;
+    history::record(
+        github_repo,
+        number,
+        history::Action::Comment,
+        &history::summarize(body),
+    );
+    Ok(())
 }
 
-fn handle_pr_closed_with_repo(
-    repo: &mut dyn RepositoryExt,
-    pr: &github::PullRequest,
-) -> Result<String, GitError> {
-    let pr_handle = PrHandle::new(pr);
-
-    info!("pr_handle={:#?}", pr_handle);
-
-    repo.add_remotes(&pr_handle)?;
-    repo.delete_pr_ref(&pr_handle)?;
-
-    Ok(String::from("deleted :D"))
+/// Posts the review app URL of a PR, or refreshes the comment it was
+/// previously posted in, so reviewers can open the preview without a GitLab
+/// account.
+pub(crate) async fn post_review_app_url(
+    client: &reqwest::Client,
+    github_repo: &str,
+    number: i64,
+    sha: &str,
+    url: &str,
+) -> Result<(), GitError> {
+    let body = review_app_comment_body(config::language_for_repo(github_repo), sha, url);
+    upsert_marked_comment(client, github_repo, number, REVIEW_APP_MARKER, &body).await
 }
 
-fn handle_pr_closed(pr: &github::PullRequest) -> Result<String, GitError> {
-    info!("Handling closed PR");
-    let url = &pr.repository.ssh_url;
-    let mut repos = REPOS.lock();
-    let repo_data = repos
-        .as_mut()
-        .unwrap()
-        .entry(url.clone())
-        .or_insert(clone_repo(url.as_str())?);
-
-    handle_pr_closed_with_repo(&mut repo_data.repo, pr)
+/// Warns on a PR that its LFS objects couldn't be copied to GitLab, so CI
+/// will only see the pointer files
+pub(crate) async fn post_lfs_warning(
+    client: &reqwest::Client,
+    github_repo: &str,
+    number: i64,
+    sha: &str,
+    error: &str,
+) -> Result<(), GitError> {
+    let body = format!(
+        "{}\n\n{}",
+        messages::for_repo(github_repo, Message::LfsCopyFailed { sha, error }),
+        LFS_MARKER
+    );
+    upsert_marked_comment(client, github_repo, number, LFS_MARKER, &body).await
 }
 
-fn handle_pr_updated(pr: &github::PullRequest) -> Result<String, GitError> {
-    info!("Handling open PR");
-    let url = &pr.repository.ssh_url;
-    info!("Handling open PR ssh: {}", url);
-    let mut repos = REPOS.lock();
-    let repo_data = repos
-        .as_mut()
-        .unwrap()
-        .entry(url.clone())
-        .or_insert(clone_repo(url.as_str())?);
-
-    handle_pr_updated_with_repo(&mut repo_data.repo, pr)
+/// Notes on a PR that a failed job was retried as flaky, so its pipeline
+/// result reflects the retry
+pub(crate) async fn post_flaky_retry_note(
+    client: &reqwest::Client,
+    github_repo: &str,
+    number: i64,
+    job_name: &str,
+    pipeline_id: i64,
+) -> Result<(), GitError> {
+    let body = format!(
+        "{}\n\n{}",
+        messages::for_repo(
+            github_repo,
+            Message::FlakyRetry {
+                job: job_name,
+                pipeline_id
+            }
+        ),
+        FLAKY_MARKER
+    );
+    upsert_marked_comment(client, github_repo, number, FLAKY_MARKER, &body).await
 }
 
-fn handle_pr_updated_with_repo(
-    repo: &mut dyn RepositoryExt,
-    pr: &github::PullRequest,
-) -> Result<String, GitError> {
-    info!("handle_pr_updated_with_repo");
-    let pr_handle = PrHandle::new(pr);
-
-    info!("pr_handle={:#?}", pr_handle);
-
-    repo.add_remotes(&pr_handle)?;
-    repo.fetch_github_remote(&pr_handle)?;
-    repo.create_ref_for_pr(&pr_handle)?;
-    repo.push_pr_ref(&pr_handle)?;
-
-    Ok(String::from(":)"))
+/// Explains on a PR that its head wasn't synced as it changes protected
+/// paths, refreshing the explanation for each new head
+pub(crate) async fn post_protected_paths_notice(
+    github_repo: &str,
+    number: i64,
+    sha: &str,
+    files: &[String],
+) -> Result<(), GitError> {
+    let client = api::new_client()?;
+    let body = format!(
+        "{}\n\n{}",
+        messages::for_repo(github_repo, Message::ProtectedPathsChanged { sha, files }),
+        PROTECTED_MARKER
+    );
+    upsert_marked_comment(&client, github_repo, number, PROTECTED_MARKER, &body).await
 }
 
-impl github::PullRequest {
-    fn is_fork(&self) -> bool {
-        self.pull_request.head.repo.fork
-    }
+/// Posts the artifact links of a PR's latest successful pipeline, or
+/// refreshes the comment they were previously posted in
+pub(crate) async fn post_artifact_links(
+    client: &reqwest::Client,
+    github_repo: &str,
+    number: i64,
+    body: &str,
+) -> Result<(), GitError> {
+    upsert_marked_comment(client, github_repo, number, ARTIFACTS_MARKER, body).await
 }
 
-fn handle_pr(pr: github::PullRequest) -> Result<(), GitError> {
-    if pr.is_fork() {
-        info!("PR is a fork");
-        let result = match pr.action.as_ref() {
-            "closed" => handle_pr_closed(&pr),
-            _ => handle_pr_updated(&pr),
+/// Compares a PR pipeline's coverage with the base branch's latest
+/// successful pipeline, refreshing the comment of the previous comparison
+pub(crate) async fn post_coverage_comparison(
+    client: &reqwest::Client,
+    github_repo: &str,
+    number: i64,
+    pipeline_id: i64,
+    pr_coverage: f64,
+) -> Result<(), GitError> {
+    let (org, repo) = github_repo
+        .split_once('/')
+        .ok_or(GitError::BadRequest(format!(
+            "Invalid repo name {}",
+            github_repo
+        )))?;
+    let base_ref = github_client::get_pull(client, org, repo, number)
+        .await?
+        .base
+        .ref_key;
+    // The base branch is built in the main project, even for untrusted PRs
+    let gitlab_repo = get_gitlab_repo_name(github_repo);
+    let base_coverage =
+        match gitlab_client::get_latest_successful_pipeline(client, &gitlab_repo, &base_ref).await?
+        {
+            Some(gitlab::Pipeline { id: Some(id), .. }) => coverage::parse(
+                gitlab_client::get_pipeline(client, &gitlab_repo, id)
+                    .await?
+                    .coverage
+                    .as_deref(),
+            ),
+            _ => None,
         };
-        match result {
-            Ok(ok) => info!("Handled PR: {}", ok),
-            Err(err) => error!("Caught error handling PR: {:?}", err),
+    let body = coverage::comment_body(
+        &config::CONFIG.coverage,
+        pipeline_id,
+        pr_coverage,
+        base_coverage,
+        &base_ref,
+        COVERAGE_MARKER,
+        config::language_for_repo(github_repo),
+    );
+    upsert_marked_comment(client, github_repo, number, COVERAGE_MARKER, &body).await
+}
+
+/// Posts the summary of a finished pipeline, or updates the comment it was
+/// previously posted in when the pipeline was re-run
+pub(crate) async fn post_pipeline_summary(
+    client: &reqwest::Client,
+    github_repo: &str,
+    number: i64,
+    gitlab_project: &str,
+    pipeline_id: i64,
+    body: &str,
+) -> Result<(), GitError> {
+    let (org, repo) = github_repo
+        .split_once('/')
+        .ok_or(GitError::BadRequest(format!(
+            "Invalid repo name {}",
+            github_repo
+        )))?;
+    let updated = match state::pipeline_comment(gitlab_project, pipeline_id)? {
+        Some(id) => {
+            info!(
+                "Updating summary id={} of pipeline {} on {}#{}",
+                id, pipeline_id, github_repo, number
+            );
+            // The comment may have been deleted, in which case a new one is
+            // posted
+            github_client::update_issue_comment(client, org, repo, id, body)
+                .await
+                .is_ok()
         }
+        None => false,
+    };
+    let result = if updated {
+        Ok(())
     } else {
-        info!("Skipping PR because it's not a fork, cya 👋");
-    }
+        info!(
+            "Posting summary of pipeline {} on {}#{}",
+            pipeline_id, github_repo, number
+        );
+        github_client::create_issue_comment(client, org, repo, number, body)
+            .await
+            .and_then(|id| state::record_pipeline_comment(gitlab_project, pipeline_id, id))
+    };
+    audit::record(
+        audit::LABHUB,
+        github_repo,
+        Some(number),
+        audit::Action::Comment,
+        &history::summarize(body),
+        &result,
+    );
+    result?;
+    history::record(
+        github_repo,
+        number,
+        history::Action::Comment,
+        &history::summarize(body),
+    );
     Ok(())
 }
 
@@ -329,18 +466,49 @@ async fn write_issue_comment(
         .map(std::string::ToString::to_string)
         .collect();
     if repo_full_name_parts.len() != 2 {
-        return Err(GitError {
-            message: format!("Invalid repo name {}", repo_full_name),
-        });
+        return Err(GitError::BadRequest(format!(
+            "Invalid repo name {}",
+            repo_full_name
+        )));
     }
-    github_client::create_issue_comment(
+    if let Err(err) = remove_stale_comments(
         client,
         &repo_full_name_parts[0],
         &repo_full_name_parts[1],
         ic.issue.number,
-        body,
     )
     .await
+    {
+        error!("Error removing stale comments: {:?}", err);
+    }
+    let result = github_client::create_issue_comment(
+        client,
+        &repo_full_name_parts[0],
+        &repo_full_name_parts[1],
+        ic.issue.number,
+        body,
+    )
+    .await;
+    audit::record(
+        ic.comment
+            .user
+            .as_ref()
+            .and_then(|u| u.login.as_deref())
+            .unwrap_or(audit::LABHUB),
+        &repo_full_name,
+        Some(ic.issue.number),
+        audit::Action::Comment,
+        &history::summarize(body),
+        &result,
+    );
+    result?;
+    history::record(
+        &repo_full_name,
+        ic.issue.number,
+        history::Action::Comment,
+        &history::summarize(body),
+    );
+    Ok(())
 }
 
 async fn get_sha(client: &reqwest::Client, ic: &github::IssueComment) -> Result<String, GitError> {
@@ -350,9 +518,10 @@ async fn get_sha(client: &reqwest::Client, ic: &github::IssueComment) -> Result<
         .map(std::string::ToString::to_string)
         .collect();
     if repo_full_name_parts.len() != 2 {
-        return Err(GitError {
-            message: format!("Invalid repo name {}", repo_full_name),
-        });
+        return Err(GitError::BadRequest(format!(
+            "Invalid repo name {}",
+            repo_full_name
+        )));
     }
     let pr = github_client::get_pull(
         client,
@@ -375,26 +544,55 @@ async fn find_pipeline_id(
     project: &str,
     sha: &str,
 ) -> Result<i64, GitError> {
-    let mut result_len = 100;
-    let mut page = 1;
-    while result_len == 100 {
-        let pipelines = gitlab_client::get_pipelines(client, project, page, 100).await?;
-        let pipeline = pipelines
-            .iter()
-            .filter(|p| p.sha.is_some() && p.id.is_some())
-            .find(|p| p.sha.as_ref().unwrap() == sha);
-        if let Some(pipeline) = pipeline {
-            return Ok(pipeline.id.unwrap());
+    if let Some(pipeline) = state::latest_pipeline(project, sha)? {
+        return Ok(pipeline.pipeline_id);
+    }
+    if let Some(pipeline) = gitlab_client::find_pipeline(client, project, sha).await? {
+        let pipeline_id = pipeline.id.unwrap();
+        state::record_pipeline(
+            project,
+            pipeline_id,
+            sha,
+            pipeline.status.as_deref().unwrap_or("unknown"),
+        )?;
+        return Ok(pipeline_id);
+    }
+    Err(GitError::NotFound(format!(
+        "Unable to find pipeline for project={} sha={}",
+        project, sha
+    )))
+}
+
+/// Acknowledges a command which succeeded, with a reaction to its comment or
+/// with `body` as a reply, per the `acknowledgement` setting. Without a
+/// body, there's nothing to reply with.
+async fn acknowledge_command(
+    client: &reqwest::Client,
+    ic: &github::IssueComment,
+    reaction: &str,
+    body: Option<&str>,
+) -> Result<(), GitError> {
+    match (&config::CONFIG.commands.acknowledgement, body) {
+        (config::Acknowledgement::Reaction, _) => {
+            let (org, repo) =
+                ic.repository
+                    .full_name
+                    .split_once('/')
+                    .ok_or(GitError::BadRequest(format!(
+                        "Invalid repo name {}",
+                        ic.repository.full_name
+                    )))?;
+            let comment_id = ic.comment.id.ok_or(GitError::BadRequest(
+                "Comment has no id to react to".to_string(),
+            ))?;
+            github_client::create_reaction(client, org, repo, comment_id, reaction).await
+        }
+        (config::Acknowledgement::Comment, Some(body)) => {
+            info!("Commenting on github");
+            write_issue_comment(client, ic, body).await
         }
-        result_len = pipelines.len();
-        page += 1;
+        (config::Acknowledgement::Comment, None) => Ok(()),
     }
-    Err(GitError {
-        message: format!(
-            "Unable to find pipeline for project={} sha={}",
-            project, sha
-        ),
-    })
 }
 
 async fn handle_retry_command(
@@ -402,25 +600,236 @@ async fn handle_retry_command(
     ic: &github::IssueComment,
 ) -> Result<(), GitError> {
     let repo_full_name = ic.repository.full_name.clone();
-    let sha = get_sha(&client, ic).await?;
-    let project = get_gitlab_repo_name(&repo_full_name);
+    let sha = get_sha(client, ic).await?;
+    // PRs from untrusted authors may be pushed to a separate project
+    let project =
+        match state::latest_pr_sync(&repo_name::lookup_key(&repo_full_name), ic.issue.number)? {
+            Some(sync) => sync.gitlab_project,
+            None => get_gitlab_repo_name(&repo_full_name),
+        };
     info!("Got retry command for project={} sha={}", project, sha);
-    let pipeline_id = find_pipeline_id(&client, &get_gitlab_repo_name(&project), &sha).await?;
+    let pipeline_id = find_pipeline_id(client, &project, &sha).await?;
     info!("Retrying pipeline id: {}", pipeline_id);
-    gitlab_client::retry_pipeline(&client, &project, pipeline_id).await?;
+    let result = gitlab_client::retry_pipeline(client, &project, pipeline_id).await;
+    audit::record(
+        ic.comment
+            .user
+            .as_ref()
+            .and_then(|u| u.login.as_deref())
+            .unwrap_or(audit::LABHUB),
+        &repo_full_name,
+        Some(ic.issue.number),
+        audit::Action::PipelineRetry,
+        &format!("pipeline {} on {}", pipeline_id, project),
+        &result,
+    );
+    result?;
+
+    let comment_body = messages::for_repo(
+        &repo_full_name,
+        Message::RetrySent {
+            pipeline_id,
+            project_url: &gitlab_client::make_ext_url(&project),
+        },
+    );
 
-    let comment_body = format!(
-        "Sent **retry** command for pipeline [**{}**]({}/pipelines/{}) on [**GitLab**]({})
+    acknowledge_command(client, ic, "rocket", Some(&comment_body)).await
+}
 
-Have a great day! 😄",
-        pipeline_id,
-        gitlab_client::make_ext_url(&project),
-        pipeline_id,
-        gitlab_client::make_ext_url(&project),
+/// Whether `approved`, the SHA given to the `approve` command, is `head`,
+/// in full or abbreviated to at least 7 characters
+fn approves_head(approved: Option<&str>, head: &str) -> bool {
+    match approved {
+        Some(approved) => {
+            approved.len() >= 7 && head.to_lowercase().starts_with(&approved.to_lowercase())
+        }
+        None => false,
+    }
+}
+
+/// Approves syncing the PR's head, even if it changes protected paths, and
+/// syncs it. The approver names the head they reviewed, so a push after
+/// their review isn't approved with it.
+async fn handle_approve_command(
+    client: &reqwest::Client,
+    ic: &github::IssueComment,
+    args: &[String],
+) -> Result<(), GitError> {
+    let repo_full_name = &ic.repository.full_name;
+    let approver = ic.comment.user.as_ref().and_then(|u| u.login.as_deref());
+    let pr = fetch_pr_event(client, repo_full_name, ic.issue.number).await?;
+    let sha = pr.head_sha().to_string();
+    if !approves_head(args.first().map(String::as_str), &sha) {
+        info!(
+            "Not approving {}#{}, whose head {} isn't the one given by {:?}",
+            repo_full_name, ic.issue.number, sha, approver
+        );
+        let comment_body =
+            messages::for_repo(repo_full_name, Message::ApprovalNeedsHead { head: &sha });
+        return write_issue_comment(client, ic, &comment_body).await;
+    }
+    info!(
+        "Head {} of {}#{} approved by {:?}",
+        sha, repo_full_name, ic.issue.number, approver
     );
+    state::record_approval(
+        &repo_name::canonicalize(repo_full_name)?,
+        ic.issue.number,
+        &sha,
+        approver.unwrap_or("unknown"),
+    )?;
+    sync::handle_pr(Box::new(pr))?;
+
+    let comment_body = messages::for_repo(repo_full_name, Message::HeadApproved { sha: &sha });
+    acknowledge_command(client, ic, "+1", Some(&comment_body)).await
+}
+
+async fn handle_pause_command(
+    client: &reqwest::Client,
+    ic: &github::IssueComment,
+    paused: bool,
+) -> Result<(), GitError> {
+    let repo_full_name = &ic.repository.full_name;
+    info!("Got pause command, paused={}", paused);
+    let commenter = ic.comment.user.as_ref().and_then(|u| u.login.clone());
+
+    let comment_body = if paused {
+        pause::pause(repo_full_name, &commenter.unwrap_or_default())?;
+        messages::for_repo(
+            repo_full_name,
+            Message::MirroringPaused {
+                repo: repo_full_name,
+            },
+        )
+    } else {
+        match pause::resume(repo_full_name)? {
+            Some(queued) => messages::for_repo(
+                repo_full_name,
+                Message::MirroringResumed {
+                    repo: repo_full_name,
+                    queued,
+                },
+            ),
+            None => {
+                let body = messages::for_repo(
+                    repo_full_name,
+                    Message::MirroringNotPaused {
+                        repo: repo_full_name,
+                    },
+                );
+                return write_issue_comment(client, ic, &body).await;
+            }
+        }
+    };
 
-    info!("Commenting on github");
-    write_issue_comment(&client, ic, &comment_body).await
+    acknowledge_command(client, ic, "+1", Some(&comment_body)).await
+}
+
+/// Flags a PR pipeline that was much slower than its base branch's recent
+/// pipelines
+async fn duration_note(
+    client: &reqwest::Client,
+    ic: &github::IssueComment,
+    pipeline: &state::Pipeline,
+) -> Result<String, GitError> {
+    let duration = match state::pipeline_duration(&pipeline.gitlab_project, pipeline.pipeline_id)? {
+        Some(duration) => duration,
+        None => return Ok(String::new()),
+    };
+    let repo_full_name = &ic.repository.full_name;
+    let (org, repo) = repo_full_name
+        .split_once('/')
+        .ok_or(GitError::BadRequest(format!(
+            "Invalid repo name {}",
+            repo_full_name
+        )))?;
+    let base_ref = github_client::get_pull(client, org, repo, ic.issue.number)
+        .await?
+        .base
+        .ref_key;
+    // The base branch is built in the main project, even for untrusted PRs
+    let durations = &config::CONFIG.durations;
+    let base_durations = state::recent_durations(
+        &get_gitlab_repo_name(repo_full_name),
+        &base_ref,
+        durations.window,
+    )?;
+    Ok(durations::slowdown_note(
+        durations,
+        duration,
+        &base_durations,
+        &base_ref,
+        config::language_for_repo(repo_full_name),
+    )
+    .map(|note| format!("\n\n{}", note))
+    .unwrap_or_default())
+}
+
+async fn handle_status_command(
+    client: &reqwest::Client,
+    ic: &github::IssueComment,
+) -> Result<(), GitError> {
+    let repo_full_name = ic.repository.full_name.clone();
+    info!("Got status command");
+
+    let language = config::language_for_repo(&repo_full_name);
+    let comment_body =
+        match state::latest_pr_sync(&repo_name::lookup_key(&repo_full_name), ic.issue.number)? {
+            Some(sync) => {
+                let project_url = gitlab_client::make_ext_url(&sync.gitlab_project);
+                let latest = state::latest_pipeline(&sync.gitlab_project, &sync.head_sha)?;
+                let pipeline = match latest.as_ref() {
+                    Some(pipeline) => messages::text(
+                        language,
+                        Message::PipelineIs {
+                            pipeline_id: pipeline.pipeline_id,
+                            project_url: &project_url,
+                            status: &pipeline.status,
+                        },
+                    ),
+                    None => messages::text(language, Message::NoPipelineYet),
+                };
+                let slowdown = match latest.as_ref() {
+                    Some(pipeline) => match duration_note(client, ic, pipeline).await {
+                        Ok(note) => note,
+                        Err(err) => {
+                            error!("Error comparing pipeline durations: {:?}", err);
+                            String::new()
+                        }
+                    },
+                    None => String::new(),
+                };
+                let deployment =
+                    match state::latest_deployment(&sync.gitlab_project, &sync.gitlab_branch)? {
+                        Some(state::Deployment {
+                            status,
+                            url: Some(url),
+                            ..
+                        }) if status == "success" => format!(
+                            "\n\n{}",
+                            messages::text(language, Message::DeployedToReviewApp { url: &url })
+                        ),
+                        _ => String::new(),
+                    };
+                format!(
+                    "{}{}{}",
+                    messages::text(
+                        language,
+                        Message::PushedToGitlab {
+                            sha: &sync.head_sha,
+                            branch: &sync.gitlab_branch,
+                            project_url: &project_url,
+                            pipeline: &pipeline,
+                        }
+                    ),
+                    slowdown,
+                    deployment
+                )
+            }
+            None => messages::text(language, Message::NotPushedYet),
+        };
+
+    write_issue_comment(client, ic, &comment_body).await
 }
 
 async fn handle_new_pipeline_command(
@@ -447,11 +856,14 @@ async fn handle_new_pipeline_command(
         let pullrequest = github::PullRequest {
             action: "opened".to_owned(),
             number: ic.issue.number,
+            changes: None,
             pull_request: pr,
+            label: None,
             repository: ic.repository.clone(),
             sender: ic.sender.clone(),
         };
-        handle_pr(pullrequest)?;
+        sync::handle_pr(Box::new(pullrequest))?;
+        acknowledge_command(client, ic, "rocket", None).await?;
     } else {
         info!("Event trigger action not enabled. Skipping event.");
     }
@@ -469,16 +881,58 @@ async fn handle_new_pipeline_command(
     //        pipeline_id,
     //        gitlab_client::make_ext_url(&project),
     //    );
-    //    write_issue_comment(&client, ic, &comment_body).await
+    //    write_issue_comment(client, ic, &comment_body).await
+}
+
+/// Checks the commenter's permission level on the repo against the command
+/// policy, returning why they aren't allowed to use the command if so
+async fn check_command_permission(
+    client: &reqwest::Client,
+    ic: &github::IssueComment,
+    command: &commands::CommandAction,
+) -> Result<Option<String>, GitError> {
+    let policy = &config::CONFIG.commands;
+    let required = commands::required_permission(policy, command);
+    let denial = messages::for_repo(
+        &ic.repository.full_name,
+        Message::PermissionDenied {
+            permission: required.name(),
+        },
+    );
+    let username = match ic.comment.user.as_ref().and_then(|u| u.login.as_deref()) {
+        Some(username) => username,
+        None => return Ok(Some(denial)),
+    };
+    let (org, repo) = ic
+        .repository
+        .full_name
+        .split_once('/')
+        .ok_or(GitError::BadRequest(format!(
+            "Invalid repo name {}",
+            ic.repository.full_name
+        )))?;
+    // Skip the API call when the lists or policy already decide
+    let level = if required == commands::PermissionLevel::None
+        || commands::is_allowed(policy, command, username, commands::PermissionLevel::None)
+    {
+        commands::PermissionLevel::None
+    } else {
+        let role = github_client::get_permission_level(client, org, repo, username).await?;
+        commands::PermissionLevel::from_role(&role)
+    };
+    if commands::is_allowed(policy, command, username, level) {
+        Ok(None)
+    } else {
+        info!(
+            "Denying command {:?} to {} with permission {:?}",
+            command, username, level
+        );
+        Ok(Some(denial))
+    }
 }
 
 async fn handle_pr_ic(ic: github::IssueComment) -> Result<(), GitError> {
-    let client = reqwest::Client::builder()
-        .user_agent(APP_USER_AGENT)
-        .gzip(true)
-        .brotli(true)
-        .deflate(true)
-        .build()?;
+    let client = api::new_client()?;
     info!(
         "Issue comment received for issue number={} action={}",
         ic.issue.number, ic.action,
@@ -489,16 +943,18 @@ async fn handle_pr_ic(ic: github::IssueComment) -> Result<(), GitError> {
     //    return Ok(());
     //}
 
-    let command_res =
-        commands::parse_body(ic.comment.body.as_ref(), &*config::CONFIG.github.username);
+    let command_res = commands::parse_body(
+        ic.comment.body.as_ref(),
+        &config::github_for_repo(&ic.repository.full_name)
+            .site
+            .username,
+    );
 
     match command_res {
         Err(commands::CommandError::UnknownCommand) => {
             // Write a comment on the PR
-            let comment_body = "Sorry, but I don't know what that command means.
-
-Thanks for asking 🥰"
-                .to_string();
+            let comment_body =
+                messages::for_repo(&ic.repository.full_name, Message::UnknownCommand);
 
             write_issue_comment(&client, &ic, &comment_body).await?;
             Ok(())
@@ -509,24 +965,59 @@ Thanks for asking 🥰"
             if !config::command_enabled(&command.command) {
                 warn!("Command {:#?} is not enabled.", command.command);
                 Ok(())
+            } else if let Some(denial) =
+                check_command_permission(&client, &ic, &command.command).await?
+            {
+                write_issue_comment(&client, &ic, &denial).await
             } else {
-                match command.command {
+                let result = match command.command {
                     commands::CommandAction::Retry => handle_retry_command(&client, &ic).await,
                     commands::CommandAction::NewPipeline => {
                         handle_new_pipeline_command(&client, &ic).await
                     }
+                    commands::CommandAction::Status => handle_status_command(&client, &ic).await,
+                    commands::CommandAction::PauseMirroring => {
+                        handle_pause_command(&client, &ic, true).await
+                    }
+                    commands::CommandAction::ResumeMirroring => {
+                        handle_pause_command(&client, &ic, false).await
+                    }
+                    commands::CommandAction::Approve => {
+                        handle_approve_command(&client, &ic, &command.args).await
+                    }
+                };
+                let commenter = ic.comment.user.as_ref().and_then(|u| u.login.as_deref());
+                event_webhooks::emit(Event::CommandExecuted {
+                    repo: ic.repository.full_name.clone(),
+                    number: ic.issue.number,
+                    command: command.command,
+                    user: commenter.unwrap_or("unknown").to_string(),
+                    succeeded: result.is_ok(),
+                });
+                if result.is_ok() {
+                    history::record(
+                        &ic.repository.full_name,
+                        ic.issue.number,
+                        history::Action::Command,
+                        &format!(
+                            "{} by {}",
+                            history::summarize(&ic.comment.body),
+                            commenter.unwrap_or("unknown")
+                        ),
+                    );
                 }
+                result
             }
         }
-        Err(commands::CommandError::BadUsername) => Err(GitError {
-            message: "Bad username for command".to_owned(),
-        }),
-        Err(commands::CommandError::InvalidLength) => Err(GitError {
-            message: "Too many parameters for command".to_owned(),
-        }),
-        Err(commands::CommandError::InvalidFormat) => Err(GitError {
-            message: "Invalid format for command".to_owned(),
-        }),
+        Err(commands::CommandError::BadUsername) => {
+            Err(GitError::BadRequest("Bad username for command".to_owned()))
+        }
+        Err(commands::CommandError::InvalidLength) => Err(GitError::BadRequest(
+            "Too many parameters for command".to_owned(),
+        )),
+        Err(commands::CommandError::InvalidFormat) => Err(GitError::BadRequest(
+            "Invalid format for command".to_owned(),
+        )),
     }
 }
 
@@ -534,27 +1025,347 @@ async fn handle_ic(ic: github::IssueComment) {
     if ic.is_from_pr() {
         match handle_pr_ic(ic).await {
             Ok(()) => info!("Finished handling issue comment"),
-            Err(_err) => info!("Error acting on issue comment: {}", _err.message),
+            Err(_err) => info!("Error acting on issue comment: {}", _err),
         }
     } else {
         info!("Ignoring non-PR comment");
     }
 }
 
+/// The JSON payload of a webhook sent with the
+/// `application/x-www-form-urlencoded` content type, in its `payload` field
+pub fn form_payload(body: &str) -> Result<String, GitError> {
+    url::form_urlencoded::parse(body.as_bytes())
+        .find(|(name, _)| name == "payload")
+        .map(|(_, payload)| payload.into_owned())
+        .ok_or_else(|| GitError::InvalidPayload {
+            pointer: "payload".to_string(),
+            message: "The form-encoded webhook has no payload field".to_string(),
+        })
+}
+
+/// Returns the secret a webhook should be signed with, from the GitHub
+/// instance and org it came from. The body is signed with one of the
+/// configured secrets, which must be this one.
+pub fn secret_for_event(body: &str) -> Result<String, GitError> {
+    let event: serde_json::Value = serde_json::from_str(body)?;
+    let instance = instance_for_event(&event)?;
+    let org = event["repository"]["owner"]["login"]
+        .as_str()
+        .or(event["organization"]["login"].as_str());
+    Ok(instance.webhook_secret(org))
+}
+
+/// Returns the answer to a webhook which is dropped because of its repo,
+/// see [`config::Repos`], if it is. Whether the repo's events come from an
+/// org webhook, which only handles mapped or allowed repos, is told by the
+/// verified payload and `org_webhooks`. `target_type`, the unsigned
+/// `X-GitHub-Hook-Installation-Target-Type` header, is only a hint, to drop
+/// the deliveries of repo webhooks left over in such an org, as its org
+/// webhook sends the same events.
+pub fn dropped_event(body: &str, target_type: Option<&str>) -> Option<String> {
+    let event: serde_json::Value = serde_json::from_str(body).ok()?;
+    let full_name = event["repository"]["full_name"].as_str()?;
+    let org_hook = match (
+        instance_for_event(&event),
+        event["repository"]["owner"]["login"].as_str(),
+    ) {
+        (Ok(instance), Some(owner)) => instance.has_org_webhook(owner),
+        _ => false,
+    };
+    let reason = dropped_reason(full_name, org_hook, target_type)?;
+    info!("Dropping webhook of repo={}, as {}", full_name, reason);
+    Some(format!(
+        "Not handling events of {}, as {}",
+        full_name, reason
+    ))
+}
+
+fn dropped_reason(
+    full_name: &str,
+    org_hook: bool,
+    target_type: Option<&str>,
+) -> Option<&'static str> {
+    if org_hook && target_type == Some("repository") {
+        return Some("its org webhook sends them");
+    }
+    config::CONFIG.repos.rejection(full_name, org_hook)
+}
+
+/// Picks the GitHub instance a webhook came from, by the hostname of its
+/// repository. Events without a repository are assumed to be from the first
+/// instance.
+fn instance_for_event(
+    event: &serde_json::Value,
+) -> Result<&'static config::GithubInstance, GitError> {
+    let repository = &event["repository"];
+    let html_url = match repository["html_url"].as_str() {
+        Some(html_url) => html_url,
+        None => return Ok(&config::CONFIG.github[0]),
+    };
+    let hostname = reqwest::Url::parse(html_url)
+        .ok()
+        .and_then(|url| url.host_str().map(str::to_string))
+        .ok_or(GitError::BadRequest(format!(
+            "Malformed repository URL {}",
+            html_url
+        )))?;
+    let instance = config::github_for_hostname(&hostname).ok_or(GitError::Config(format!(
+        "No GitHub instance is configured for {}",
+        hostname
+    )))?;
+    if let Some(full_name) = repository["full_name"].as_str() {
+        if let Some(mapped) = config::github_instance_of_repo(full_name) {
+            if mapped.name != instance.name {
+                return Err(GitError::BadRequest(format!(
+                    "Repo {} is mapped on the {} GitHub instance, not {}",
+                    full_name, mapped.name, instance.name
+                )));
+            }
+        }
+    }
+    Ok(instance)
+}
+
+/// The action, repo full name and number of a PR event, which is all that's
+/// needed to fetch the rest of it
+fn minimal_pr_fields(event: &serde_json::Value) -> Option<(String, String, i64)> {
+    lazy_static! {
+        static ref PULL_URL: Regex = Regex::new(r"/repos/([^/]+/[^/]+)/pulls/(\d+)$").unwrap();
+    }
+    let action = event["action"].as_str()?.to_string();
+    let pull_url = event["pull_request"]["url"]
+        .as_str()
+        .and_then(|url| PULL_URL.captures(url));
+    let full_name = event["repository"]["full_name"]
+        .as_str()
+        .or(event["pull_request"]["base"]["repo"]["full_name"].as_str())
+        .map(str::to_string)
+        .or_else(|| pull_url.as_ref().map(|captures| captures[1].to_string()))?;
+    let number = event["number"]
+        .as_i64()
+        .or(event["pull_request"]["number"].as_i64())
+        .or_else(|| {
+            pull_url
+                .as_ref()
+                .and_then(|captures| captures[2].parse().ok())
+        })?;
+    Some((action, full_name, number))
+}
+
+/// Parses a PR event, fetching the PR from the API if the payload is
+/// missing fields. GitHub trims the payloads of giant PRs to stay under its
+/// 25MB webhook cap, and those should still be mirrored.
+async fn parse_pr_event(body: &str) -> Result<github::PullRequest, RequestErrorResult> {
+    let parse_err = match models::parse(body) {
+        Ok(pr) => return Ok(pr),
+        Err(err) => err,
+    };
+    let mut event: serde_json::Value = serde_json::from_str(body)?;
+    let (action, full_name, number) = match minimal_pr_fields(&event) {
+        Some(fields) => fields,
+        None => return Err(parse_err.into()),
+    };
+    warn!(
+        "PR event action={} for {}#{} is incomplete ({:?}: {}), fetching the PR instead",
+        action, full_name, number, parse_err.pointer, parse_err.message
+    );
+    let (org, repo) = full_name
+        .split_once('/')
+        .ok_or(GitError::BadRequest(format!(
+            "Invalid repo name {}",
+            full_name
+        )))?;
+    let client = api::new_client()?;
+    let pull = serde_json::to_value(github_client::get_pull(&client, org, repo, number).await?)?;
+    if event["repository"]["full_name"].is_null() {
+        event["repository"] = pull["base"]["repo"].clone();
+    }
+    if !event["sender"].is_object() {
+        event["sender"] = pull["user"].clone();
+    }
+    event["number"] = number.into();
+    event["pull_request"] = pull;
+    Ok(serde_json::from_value(event)?)
+}
+
+/// The parts of a `ping` event LabHub checks
+#[derive(Deserialize, Debug)]
+struct Ping {
+    hook_id: Option<i64>,
+    hook: Option<PingHook>,
+    repository: Option<PingAccount>,
+    organization: Option<PingAccount>,
+}
+
+#[derive(Deserialize, Debug)]
+struct PingHook {
+    #[serde(rename = "type")]
+    hook_type: Option<String>,
+    #[serde(default)]
+    events: Vec<String>,
+    active: Option<bool>,
+}
+
+#[derive(Deserialize, Debug)]
+struct PingAccount {
+    full_name: Option<String>,
+    login: Option<String>,
+}
+
+/// What GitHub is told about a new webhook in response to its `ping`
+#[derive(Serialize, Debug, PartialEq)]
+pub struct PingReport {
+    /// Whether the webhook is set up for LabHub to work
+    pub ok: bool,
+    pub hook_id: Option<i64>,
+    pub repository: Option<String>,
+    pub organization: Option<String>,
+    /// The GitLab project the repo is mapped to
+    pub gitlab_repo: Option<String>,
+    /// The mapped repos of the org, for org webhooks
+    pub mapped_repos: Vec<String>,
+    /// The events LabHub needs, by the features enabled
+    pub required_events: Vec<&'static str>,
+    /// The required events the webhook doesn't send
+    pub missing_events: Vec<&'static str>,
+    pub problems: Vec<String>,
+}
+
+/// The events a webhook must send for the enabled features
+pub(crate) fn required_events() -> Vec<&'static str> {
+    let mut events = vec![];
+    if config::feature_enabled(&config::Feature::ExternalPr) {
+        events.push("pull_request");
+    }
+    if config::feature_enabled(&config::Feature::Commands)
+        || config::feature_enabled(&config::Feature::CommentMirroring)
+    {
+        events.push("issue_comment");
+    }
+    events
+}
+
+/// Checks a new webhook from its `ping`: that it sends the events LabHub
+/// needs, and that its repo is mapped to a GitLab project
+fn check_ping(ping: &Ping, required_events: Vec<&'static str>) -> PingReport {
+    let mut problems = vec![];
+    let events = ping
+        .hook
+        .as_ref()
+        .map(|hook| hook.events.as_slice())
+        .unwrap_or_default();
+    let missing_events: Vec<&'static str> = required_events
+        .iter()
+        .copied()
+        .filter(|event| !events.iter().any(|sent| sent == "*" || sent == event))
+        .collect();
+    if !missing_events.is_empty() {
+        problems.push(format!(
+            "The webhook doesn't send the {} events",
+            missing_events.join(", ")
+        ));
+    }
+    if ping.hook.as_ref().and_then(|hook| hook.active) == Some(false) {
+        problems.push("The webhook isn't active".to_string());
+    }
+
+    let repository = ping
+        .repository
+        .as_ref()
+        .and_then(|repo| repo.full_name.clone());
+    let organization = ping.organization.as_ref().and_then(|org| org.login.clone());
+    let gitlab_repo = repository.as_deref().and_then(config::gitlab_repo_for);
+    let mut mapped_repos = vec![];
+    match (&repository, &organization) {
+        (Some(repository), _) if gitlab_repo.is_none() => problems.push(format!(
+            "{} isn't in the mappings, so its PRs won't be synced",
+            repository
+        )),
+        (Some(_), _) => {}
+        (None, Some(organization)) => {
+            mapped_repos = config::mapped_repos_of_org(organization)
+                .into_iter()
+                .map(str::to_string)
+                .collect();
+            if mapped_repos.is_empty() {
+                problems.push(format!(
+                    "None of {}'s repos are in the mappings",
+                    organization
+                ));
+            }
+        }
+        (None, None) => {
+            let hook_type = ping
+                .hook
+                .as_ref()
+                .and_then(|hook| hook.hook_type.as_deref())
+                .unwrap_or("unknown");
+            problems.push(format!(
+                "The {} webhook isn't on a repo or org, so no PRs will be synced",
+                hook_type
+            ));
+        }
+    }
+
+    PingReport {
+        ok: problems.is_empty(),
+        hook_id: ping.hook_id,
+        repository,
+        organization,
+        gitlab_repo,
+        mapped_repos,
+        required_events,
+        missing_events,
+        problems,
+    }
+}
+
+/// Answers the `ping` GitHub sends when a webhook is created with a report
+/// of whether it's set up for LabHub to work, which GitHub shows with the
+/// delivery
+pub fn handle_ping(body: &str) -> Result<serde_json::Value, RequestErrorResult> {
+    let ping: Ping = models::parse(body)?;
+    let report = check_ping(&ping, required_events());
+    if report.ok {
+        info!("Ping from hook_id={:?}, it's set up right", report.hook_id);
+    } else {
+        warn!(
+            "Ping from hook_id={:?}: {}",
+            report.hook_id,
+            report.problems.join("; ")
+        );
+    }
+    Ok(serde_json::to_value(report)?)
+}
+
+/// Handles the body of a GitHub webhook whose signature has already been
+/// checked, where `event_type` is the `X-GitHub-Event` header.
 pub async fn handle_event_body(event_type: &str, body: &str) -> Result<String, RequestErrorResult> {
     match event_type {
         "push" => {
-            let push: github::Push = serde_json::from_str(body)?;
+            let push: github::Push = models::parse(body)?;
             info!("Push ref={}", push.ref_key);
             Ok(String::from("Push received 😘"))
         }
         "pull_request" => {
             if config::feature_enabled(&config::Feature::ExternalPr) {
-                let pr: github::PullRequest = serde_json::from_str(body)?;
+                let pr = parse_pr_event(body).await?;
                 // check if pull request event trigger action is enabled in config file
-                if config::action_enabled(pr.action.as_ref()) {
+                if pr.action == "synchronize"
+                    && dedupe::is_synced_head(
+                        &repo_name::lookup_key(&pr.pull_request.base.repo.full_name),
+                        pr.pull_request.number,
+                        &pr.pull_request.head.sha,
+                    )
+                {
+                    info!(
+                        "Head sha={} was already synced, skipping",
+                        pr.pull_request.head.sha
+                    );
+                } else if config::action_enabled(pr.action.as_ref()) {
                     info!("PullRequest action={}", pr.action);
-                    handle_pr(pr)?;
+                    sync::handle_pr(Box::new(pr))?;
                 } else {
                     info!("Event trigger action not enabled. Skipping event.");
                 }
@@ -564,8 +1375,10 @@ pub async fn handle_event_body(event_type: &str, body: &str) -> Result<String, R
             Ok(String::from("Thanks buddy bro 😍"))
         }
         "issue_comment" => {
-            if config::feature_enabled(&config::Feature::Commands) {
-                let ic: github::IssueComment = serde_json::from_str(body)?;
+            let commands = config::feature_enabled(&config::Feature::Commands);
+            let mirroring = config::feature_enabled(&config::Feature::CommentMirroring);
+            if commands || mirroring {
+                let ic: github::IssueComment = models::parse(body)?;
                 info!(
                     "Issue comment action={} user={}",
                     ic.action,
@@ -575,9 +1388,16 @@ pub async fn handle_event_body(event_type: &str, body: &str) -> Result<String, R
                         .map(|u| u.login.clone())
                         .unwrap_or("Unknown user".to_owned())
                 );
-                handle_ic(ic).await;
+                if mirroring {
+                    if let Err(err) = comment_mirror::mirror_github_comment(&ic).await {
+                        error!("Error mirroring issue comment: {}", err);
+                    }
+                }
+                if commands {
+                    handle_ic(ic).await;
+                }
             } else {
-                info!("Commands feature not enabled. Skipping event.");
+                info!("Neither Commands nor CommentMirroring feature enabled. Skipping event.");
             }
             Ok(String::from("Issue comment received 🥳"))
         }
@@ -594,61 +1414,156 @@ mod test {
     use crate::testing::{read_testdata_to_string, run_test};
     // use mockers::Scenario;
     #[test]
-    fn open_pr() {
+    fn get_pr() {
         run_test(|| {
-            info!("open_pr test");
-            let pr: github::PullRequest =
-                serde_json::from_str(&read_testdata_to_string("github_open_pull_request.json"))
-                    .unwrap();
-            assert_eq!(pr.is_fork(), false);
-            let _pr_handle = PrHandle::new(&pr);
+            info!("get_pr test");
+            let _pr: github::RepoPr =
+                serde_json::from_str(&read_testdata_to_string("github_get_pr.json")).unwrap();
         });
     }
 
     #[test]
-    fn reopen_pr() {
-        run_test(|| {
-            info!("reopen_pr test");
-            let pr: github::PullRequest =
-                serde_json::from_str(&read_testdata_to_string("github_reopen_pull_request.json"))
-                    .unwrap();
-            assert_eq!(pr.is_fork(), false);
-            let _pr_handle = PrHandle::new(&pr);
+    fn incomplete_pr_event() {
+        let event: serde_json::Value =
+            serde_json::from_str(&read_testdata_to_string("github_open_pr_forked.json")).unwrap();
+        let expected = Some((
+            "opened".to_string(),
+            "brndnmtthws/labhub-test".to_string(),
+            5,
+        ));
+        assert_eq!(minimal_pr_fields(&event), expected);
+        // trimmed down to the PR's API URL
+        let trimmed = serde_json::json!({
+            "action": "opened",
+            "pull_request": {
+                "url": "https://api.github.com/repos/brndnmtthws/labhub-test/pulls/5"
+            }
         });
+        assert_eq!(minimal_pr_fields(&trimmed), expected);
+        assert_eq!(
+            minimal_pr_fields(&serde_json::json!({ "action": "opened" })),
+            None
+        );
     }
 
     #[test]
-    fn open_pr_fork() {
-        run_test(|| {
-            info!("open_pr_fork test");
-            let pr: github::PullRequest =
-                serde_json::from_str(&read_testdata_to_string("github_open_pr_forked.json"))
-                    .unwrap();
-            assert_eq!(pr.is_fork(), true);
-            let _pr_handle = PrHandle::new(&pr);
-        });
+    fn review_app_comment() {
+        let body = review_app_comment_body(
+            config::Language::En,
+            "a91957a858320c0e17f3a0eca7cfacbff50ea29a",
+            "https://pr-12.review.example.com",
+        );
+        assert!(body.starts_with("🔍 The review app for `a91957a8` is deployed"));
+        assert!(body.ends_with(REVIEW_APP_MARKER));
+        let body = review_app_comment_body(
+            config::Language::De,
+            "a91957a858320c0e17f3a0eca7cfacbff50ea29a",
+            "https://pr-12.review.example.com",
+        );
+        assert!(body.starts_with("🔍 Die Review-App für `a91957a8`"));
     }
 
     #[test]
-    fn close_pr_fork() {
+    fn event_instance() {
         run_test(|| {
-            info!("close_pr_fork test");
-            let pr: github::PullRequest =
-                serde_json::from_str(&read_testdata_to_string("github_close_pr_forked.json"))
-                    .unwrap();
-            let _pr_handle = PrHandle::new(&pr);
+            let body = read_testdata_to_string("github_open_pr_forked.json");
+            let event = serde_json::from_str(&body).unwrap();
+            assert_eq!(instance_for_event(&event).unwrap().name, "default");
+            assert_eq!(secret_for_event(&body).unwrap(), "secret");
+            let body = body.replace("https://github.com/", "https://ghe.example.com/");
+            assert!(secret_for_event(&body).is_err());
+            assert_eq!(secret_for_event("{}").unwrap(), "secret");
         });
     }
 
     #[test]
-    fn get_pr() {
+    fn ping() {
         run_test(|| {
-            info!("get_pr test");
-            let _pr: github::RepoPr =
-                serde_json::from_str(&read_testdata_to_string("github_get_pr.json")).unwrap();
+            let ping: Ping = serde_json::from_value(serde_json::json!({
+                "hook_id": 7,
+                "hook": {"type": "Repository", "events": ["push", "pull_request"], "active": true},
+                "repository": {"full_name": "brndnmtthws/labhub"},
+            }))
+            .unwrap();
+            let report = check_ping(&ping, vec!["pull_request"]);
+            assert!(report.ok, "{:?}", report.problems);
+            assert_eq!(report.hook_id, Some(7));
+
+            let report = check_ping(&ping, vec!["pull_request", "issue_comment"]);
+            assert!(!report.ok);
+            assert_eq!(report.missing_events, vec!["issue_comment"]);
+
+            let ping: Ping = serde_json::from_value(serde_json::json!({
+                "hook": {"type": "Repository", "events": ["*"]},
+                "repository": {"full_name": "someone/unmapped"},
+            }))
+            .unwrap();
+            let report = check_ping(&ping, vec!["pull_request", "issue_comment"]);
+            assert!(report.missing_events.is_empty());
+            assert_eq!(
+                report.problems,
+                vec!["someone/unmapped isn't in the mappings, so its PRs won't be synced"]
+            );
+
+            let ping: Ping = serde_json::from_value(serde_json::json!({
+                "hook": {"type": "Organization", "events": ["*"]},
+                "organization": {"login": "brndnmtthws"},
+            }))
+            .unwrap();
+            let report = check_ping(&ping, vec!["pull_request"]);
+            assert!(report.ok, "{:?}", report.problems);
+            assert!(!report.mapped_repos.is_empty());
         });
     }
 
+    #[test]
+    fn approves_head() {
+        let head = "0123456789abcdef0123456789abcdef01234567";
+        assert!(super::approves_head(Some("0123456"), head));
+        assert!(super::approves_head(Some("0123456789ABCDEF"), head));
+        assert!(super::approves_head(Some(head), head));
+        assert!(!super::approves_head(None, head));
+        assert!(!super::approves_head(Some("012345"), head));
+        assert!(!super::approves_head(Some("fedcba9"), head));
+    }
+
+    #[test]
+    fn dropped_reason() {
+        // the events of an org with an org webhook come from it, not from
+        // repo webhooks left over
+        assert_eq!(
+            super::dropped_reason("brndnmtthws/labhub", true, Some("repository")),
+            Some("its org webhook sends them")
+        );
+        assert_eq!(
+            super::dropped_reason("brndnmtthws/labhub", true, Some("organization")),
+            None
+        );
+        assert_eq!(
+            super::dropped_reason("brndnmtthws/unmapped", true, None),
+            Some("it isn't in the mappings")
+        );
+        // the header alone doesn't make an org webhook
+        assert_eq!(
+            super::dropped_reason("brndnmtthws/unmapped", false, Some("organization")),
+            None
+        );
+        assert_eq!(
+            super::dropped_reason("brndnmtthws/labhub", false, Some("repository")),
+            None
+        );
+    }
+
+    #[test]
+    fn form_encoded_payload() {
+        let body = "payload=%7B%22zen%22%3A+%22Keep+it+simple.%22%7D&other=1";
+        assert_eq!(form_payload(body).unwrap(), r#"{"zen": "Keep it simple."}"#);
+        assert!(matches!(
+            form_payload("other=1"),
+            Err(GitError::InvalidPayload { .. })
+        ));
+    }
+
     #[test]
     fn created_issue_comment() {
         run_test(|| {
@@ -659,4 +1574,83 @@ mod test {
             .unwrap();
         });
     }
+
+    #[tokio::test]
+    async fn retry_command() {
+        use wiremock::matchers::{body_string_contains, method, path};
+        use wiremock::{Mock, MockServer, ResponseTemplate};
+
+        let sha = "5ca1ab1e5ca1ab1e5ca1ab1e5ca1ab1e5ca1ab1e";
+        let mut ic: github::IssueComment = serde_json::from_str(&read_testdata_to_string(
+            "github_created_issue_comment.json",
+        ))
+        .unwrap();
+        ic.comment.body = "@ci-user retry".to_string();
+        let event: serde_json::Value =
+            serde_json::from_str(&read_testdata_to_string("github_open_pr_forked.json")).unwrap();
+        let mut pr = event["pull_request"].clone();
+        pr["head"]["sha"] = sha.into();
+
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path(
+                "/repos/brndnmtthws/labhub/collaborators/brndnmtthws/permission",
+            ))
+            .respond_with(
+                ResponseTemplate::new(200)
+                    .set_body_json(serde_json::json!({ "permission": "admin" })),
+            )
+            .mount(&server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path("/repos/brndnmtthws/labhub/pulls/8"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(pr))
+            .mount(&server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path("/api/v4/projects/brndnmtthws-oss%2Flabhub/pipelines"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!([
+                { "id": 48, "status": "success", "sha": "a91957a858320c0e17f3a0eca7cfacbff50ea29a" },
+                { "id": 47, "status": "failed", "sha": sha },
+            ])))
+            .mount(&server)
+            .await;
+        Mock::given(method("POST"))
+            .and(path(
+                "/api/v4/projects/brndnmtthws-oss%2Flabhub/pipelines/47/retry",
+            ))
+            .respond_with(ResponseTemplate::new(201))
+            .expect(1)
+            .mount(&server)
+            .await;
+        Mock::given(method("POST"))
+            .and(path("/repos/brndnmtthws/labhub/issues/8/comments"))
+            .and(body_string_contains("47"))
+            .respond_with(
+                ResponseTemplate::new(201)
+                    .set_body_json(serde_json::json!({ "id": 1, "body": "" })),
+            )
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        // the PR was synced before
+        state::record_pr_sync(
+            "brndnmtthws/labhub",
+            8,
+            sha,
+            "brndnmtthws-oss/labhub",
+            "pr-8/brndnmtthws/labhub/master",
+        )
+        .unwrap();
+        api::with_base_url(server.uri(), handle_pr_ic(ic))
+            .await
+            .unwrap();
+        assert_eq!(
+            state::latest_pipeline("brndnmtthws-oss/labhub", sha)
+                .unwrap()
+                .map(|pipeline| pipeline.pipeline_id),
+            Some(47)
+        );
+    }
 }
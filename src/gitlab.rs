@@ -0,0 +1,540 @@
+//! GitLab webhook handling: pipeline, job and deployment events.
+use crate::api;
+use crate::api::models::{self, gitlab};
+use crate::api::{github_client, gitlab_client};
+use crate::artifacts;
+use crate::audit;
+use crate::cleanup;
+use crate::comment_mirror;
+use crate::config;
+use crate::coverage;
+use crate::errors::{GitError, RequestErrorResult};
+use crate::event_webhooks::{self, Event};
+use crate::flaky;
+use crate::github;
+use crate::history;
+use crate::pipeline_summary;
+use crate::state;
+use crate::sync;
+use crate::test_report;
+
+use log::{error, info};
+
+async fn handle_pipeline(event: gitlab::PipelineEvent) {
+    let project = event
+        .project
+        .as_ref()
+        .and_then(|p| p.path_with_namespace.clone());
+    let attributes = event.object_attributes.as_ref();
+    let id = attributes.and_then(|a| a.id);
+    // Pipelines of generated submodule rewrite and pinned CI config commits
+    // are attributed to the PR head they were made for
+    let source_sha = event
+        .commit
+        .as_ref()
+        .and_then(|c| c.message.as_deref())
+        .and_then(|message| sync::generated_commit_source(message).map(str::to_string));
+    let sha = source_sha.or(attributes.and_then(|a| a.sha.clone()));
+    let status = attributes.and_then(|a| a.status.clone());
+    let duration = attributes.and_then(|a| a.duration);
+    let ref_name = attributes.and_then(|a| a.ref_key.clone());
+    match (project, id, sha, status) {
+        (Some(project), Some(id), Some(sha), Some(status)) => {
+            info!(
+                "Pipeline project={} id={} sha={} status={}",
+                project, id, sha, status
+            );
+            if let Err(err) = state::record_pipeline(&project, id, &sha, &status) {
+                error!("Error recording pipeline: {:?}", err);
+            }
+            let finished = ["success", "failed", "canceled", "skipped"].contains(&status.as_str());
+            if let (true, Some((github_repo, number))) = (
+                finished,
+                ref_name.as_deref().and_then(|r| github_pr(&project, r)),
+            ) {
+                history::record(
+                    &github_repo,
+                    number,
+                    history::Action::Pipeline,
+                    &format!(
+                        "pipeline {} for {} {}",
+                        id,
+                        &sha[..sha.len().min(8)],
+                        status
+                    ),
+                );
+                event_webhooks::emit(Event::PipelineFinished {
+                    repo: github_repo.clone(),
+                    number,
+                    gitlab_project: project.clone(),
+                    pipeline_id: id,
+                    sha: sha.clone(),
+                    status: status.clone(),
+                });
+                // Reporting takes several GitHub and GitLab API calls, which
+                // mustn't hold up the webhook response
+                tokio::spawn(report_pipeline(
+                    event,
+                    project.clone(),
+                    id,
+                    sha,
+                    status.clone(),
+                    github_repo,
+                    number,
+                ));
+            }
+            // Failed or canceled pipelines stop early, so only successful
+            // ones say how long CI takes
+            if let (true, Some(duration), Some(ref_name)) =
+                (status == "success", duration, ref_name)
+            {
+                if let Err(err) = state::record_pipeline_duration(&project, id, &ref_name, duration)
+                {
+                    error!("Error recording pipeline duration: {:?}", err);
+                }
+            }
+        }
+        _ => info!("Ignoring incomplete pipeline event"),
+    }
+}
+
+/// Posts a finished pipeline's results on its GitHub PR, with the features
+/// which are enabled
+async fn report_pipeline(
+    event: gitlab::PipelineEvent,
+    project: String,
+    id: i64,
+    sha: String,
+    status: String,
+    github_repo: String,
+    number: i64,
+) {
+    if config::feature_enabled(&config::Feature::PipelineSummary) {
+        if let Err(err) =
+            post_pipeline_summary(&event, &project, id, &sha, &github_repo, number).await
+        {
+            error!("Error posting pipeline summary: {:?}", err);
+        }
+    }
+    // Canceled and skipped pipelines have no complete test report
+    if ["success", "failed"].contains(&status.as_str())
+        && config::feature_enabled(&config::Feature::TestAnnotations)
+    {
+        if let Err(err) = post_test_annotations(&project, id, &sha, &github_repo).await {
+            error!("Error posting test annotations: {:?}", err);
+        }
+    }
+    // The coverage of a failed pipeline leaves out the jobs which didn't
+    // run, so comparing it would flag a drop which isn't there
+    if status == "success" && config::feature_enabled(&config::Feature::CoverageComments) {
+        if let Err(err) = post_coverage_comparison(&project, id, &github_repo, number).await {
+            error!("Error posting coverage comparison: {:?}", err);
+        }
+    }
+    if status == "success" && config::feature_enabled(&config::Feature::ArtifactLinks) {
+        if let Err(err) = post_artifact_links(&project, id, &sha, &github_repo, number).await {
+            error!("Error posting artifact links: {:?}", err);
+        }
+    }
+}
+
+async fn post_pipeline_summary(
+    event: &gitlab::PipelineEvent,
+    project: &str,
+    pipeline_id: i64,
+    sha: &str,
+    github_repo: &str,
+    number: i64,
+) -> Result<(), GitError> {
+    let language = config::language_for_repo(github_repo);
+    let body = match pipeline_summary::comment_body(event, sha, github::PIPELINE_MARKER, language) {
+        Some(body) => body,
+        None => {
+            info!("Not enough details to summarize pipeline {}", pipeline_id);
+            return Ok(());
+        }
+    };
+    let client = api::new_client()?;
+    github::post_pipeline_summary(&client, github_repo, number, project, pipeline_id, &body).await
+}
+
+async fn post_artifact_links(
+    project: &str,
+    pipeline_id: i64,
+    sha: &str,
+    github_repo: &str,
+    number: i64,
+) -> Result<(), GitError> {
+    let client = api::new_client()?;
+    let jobs = gitlab_client::get_job_artifacts(&client, project, pipeline_id).await?;
+    let language = config::language_for_repo(github_repo);
+    match artifacts::comment_body(pipeline_id, sha, &jobs, github::ARTIFACTS_MARKER, language) {
+        Some(body) => github::post_artifact_links(&client, github_repo, number, &body).await,
+        None => {
+            info!("Pipeline {} has no artifacts to link", pipeline_id);
+            Ok(())
+        }
+    }
+}
+
+async fn post_coverage_comparison(
+    project: &str,
+    pipeline_id: i64,
+    github_repo: &str,
+    number: i64,
+) -> Result<(), GitError> {
+    let client = api::new_client()?;
+    let pipeline = gitlab_client::get_pipeline(&client, project, pipeline_id).await?;
+    match coverage::parse(pipeline.coverage.as_deref()) {
+        Some(pr_coverage) => {
+            github::post_coverage_comparison(&client, github_repo, number, pipeline_id, pr_coverage)
+                .await
+        }
+        None => {
+            info!("Pipeline {} has no coverage", pipeline_id);
+            Ok(())
+        }
+    }
+}
+
+/// Turns the pipeline's test failures into a check run on the PR head
+async fn post_test_annotations(
+    project: &str,
+    pipeline_id: i64,
+    sha: &str,
+    github_repo: &str,
+) -> Result<(), GitError> {
+    let client = api::new_client()?;
+    let report = gitlab_client::get_test_report(&client, project, pipeline_id).await?;
+    let check_run = match test_report::check_run(&report, config::language_for_repo(github_repo)) {
+        Some(check_run) => check_run,
+        None => {
+            info!("Pipeline {} has no test report", pipeline_id);
+            return Ok(());
+        }
+    };
+    let (org, repo) = github_repo.split_once('/').ok_or(GitError::Config(format!(
+        "Invalid repo name {}",
+        github_repo
+    )))?;
+    info!(
+        "Creating check run for pipeline {} on {} with {} annotations",
+        pipeline_id,
+        github_repo,
+        check_run.annotations.len()
+    );
+    github_client::create_check_run(
+        &client,
+        org,
+        repo,
+        "GitLab tests",
+        sha,
+        check_run.conclusion,
+        &check_run.title,
+        &check_run.summary,
+        &check_run.annotations,
+    )
+    .await
+}
+
+/// Retries a failed job once if it looks flaky, noting it on the PR
+async fn retry_flaky_job(
+    project: &str,
+    job_id: i64,
+    pipeline_id: i64,
+    job_name: &str,
+    ref_name: Option<&str>,
+) -> Result<(), GitError> {
+    if state::was_auto_retried(project, pipeline_id, job_name)? {
+        info!("Job {} was already retried once, letting it fail", job_name);
+        return Ok(());
+    }
+    let passes_on_retry = state::passes_on_retry(project, job_name)?;
+    if !flaky::is_flaky(
+        &config::CONFIG.flaky,
+        &flaky::KNOWN_JOBS,
+        job_name,
+        passes_on_retry,
+    ) {
+        return Ok(());
+    }
+    info!(
+        "Retrying flaky job {} id={} in project={} pipeline={}",
+        job_name, job_id, project, pipeline_id
+    );
+    let client = api::new_client()?;
+    let pr = ref_name.and_then(|r| github_pr(project, r));
+    let result = gitlab_client::retry_job(&client, project, job_id).await;
+    audit::record(
+        audit::LABHUB,
+        pr.as_ref().map_or(project, |(github_repo, _)| github_repo),
+        pr.as_ref().map(|(_, number)| *number),
+        audit::Action::PipelineRetry,
+        &format!("job {} of pipeline {} on {}", job_id, pipeline_id, project),
+        &result,
+    );
+    result?;
+    state::record_job(project, job_id, pipeline_id, job_name, "failed", true)?;
+    if let Some((github_repo, number)) = pr {
+        github::post_flaky_retry_note(&client, &github_repo, number, job_name, pipeline_id).await?;
+    }
+    Ok(())
+}
+
+async fn handle_job(event: gitlab::JobEvent) {
+    let project = event.project.and_then(|p| p.path_with_namespace);
+    match (
+        project,
+        event.build_id,
+        event.pipeline_id,
+        event.build_name,
+        event.build_status,
+    ) {
+        (Some(project), Some(job_id), Some(pipeline_id), Some(job_name), Some(status)) => {
+            info!(
+                "Job project={} id={} name={} status={}",
+                project, job_id, job_name, status
+            );
+            if let Err(err) =
+                state::record_job(&project, job_id, pipeline_id, &job_name, &status, false)
+            {
+                error!("Error recording job: {:?}", err);
+            }
+            if status == "failed"
+                && event.build_allow_failure != Some(true)
+                && config::feature_enabled(&config::Feature::FlakyRetry)
+            {
+                if let Err(err) = retry_flaky_job(
+                    &project,
+                    job_id,
+                    pipeline_id,
+                    &job_name,
+                    event.ref_key.as_deref(),
+                )
+                .await
+                {
+                    error!("Error retrying flaky job: {:?}", err);
+                }
+            }
+        }
+        _ => info!("Ignoring incomplete job event"),
+    }
+}
+
+/// Maps a GitLab deployment status onto a GitHub deployment state
+fn github_deployment_state(status: &str) -> Option<&'static str> {
+    match status {
+        "running" => Some("in_progress"),
+        "success" => Some("success"),
+        "failed" => Some("failure"),
+        "canceled" => Some("error"),
+        _ => None,
+    }
+}
+
+/// Returns the GitHub repo and PR number a GitLab branch was pushed for, if
+/// it's a LabHub PR branch
+fn github_pr(gitlab_project: &str, gitlab_branch: &str) -> Option<(String, i64)> {
+    let number = cleanup::pr_number_from_branch(gitlab_branch)?;
+    let github_repo = config::github_repo_for(gitlab_project)?;
+    Some((github_repo, number))
+}
+
+/// Posts the URL of a successful review app deployment on its GitHub PR
+async fn post_review_app_url(deployment: &state::Deployment, sha: &str) -> Result<(), GitError> {
+    let url = match (deployment.status.as_str(), deployment.url.as_ref()) {
+        ("success", Some(url)) => url,
+        _ => return Ok(()),
+    };
+    match github_pr(&deployment.gitlab_project, &deployment.gitlab_branch) {
+        Some((github_repo, number)) => {
+            let client = api::new_client()?;
+            github::post_review_app_url(&client, &github_repo, number, sha, url).await
+        }
+        None => Ok(()),
+    }
+}
+
+/// Mirrors a recorded GitLab deployment of a PR branch onto the GitHub PR
+/// as a deployment, creating it the first time the deployment is seen.
+async fn bridge_deployment(
+    deployment: &state::Deployment,
+    sha: &str,
+    log_url: Option<&str>,
+) -> Result<(), GitError> {
+    let state = match github_deployment_state(&deployment.status) {
+        Some(state) => state,
+        None => return Ok(()),
+    };
+    let github_repo = match github_pr(&deployment.gitlab_project, &deployment.gitlab_branch) {
+        Some((github_repo, _)) => github_repo,
+        None => return Ok(()),
+    };
+    let (org, repo) = github_repo.split_once('/').ok_or(GitError::Config(format!(
+        "Malformed GitHub repo {}",
+        github_repo
+    )))?;
+
+    let client = api::new_client()?;
+    let github_deployment_id = match deployment.github_deployment_id {
+        Some(id) => id,
+        None => {
+            let id =
+                github_client::create_deployment(&client, org, repo, sha, &deployment.environment)
+                    .await?;
+            state::set_github_deployment_id(
+                &deployment.gitlab_project,
+                deployment.deployment_id,
+                id,
+            )?;
+            id
+        }
+    };
+    github_client::create_deployment_status(
+        &client,
+        org,
+        repo,
+        github_deployment_id,
+        state,
+        deployment.url.as_deref(),
+        log_url,
+    )
+    .await
+}
+
+async fn handle_deployment(event: gitlab::DeploymentEvent) {
+    let project = event.project.and_then(|p| p.path_with_namespace);
+    // The full SHA is only exposed through the commit URL
+    let sha = event
+        .commit_url
+        .as_ref()
+        .and_then(|url| url.rsplit('/').next().map(str::to_string));
+    match (
+        project,
+        event.deployment_id,
+        event.ref_key,
+        event.environment,
+        event.status,
+        sha,
+    ) {
+        (Some(project), Some(id), Some(branch), Some(environment), Some(status), Some(sha)) => {
+            info!(
+                "Deployment project={} id={} branch={} environment={} status={}",
+                project, id, branch, environment, status
+            );
+            let url = event.environment_external_url.filter(|url| !url.is_empty());
+            let recorded = state::record_deployment(
+                &project,
+                id,
+                &branch,
+                &environment,
+                url.as_deref(),
+                &status,
+            )
+            .and_then(|_| state::deployment(&project, id));
+            let deployment = match recorded {
+                Ok(Some(deployment)) => deployment,
+                Ok(None) => return,
+                Err(err) => {
+                    error!("Error recording deployment: {:?}", err);
+                    return;
+                }
+            };
+            if config::feature_enabled(&config::Feature::Deployments) {
+                if let Err(err) =
+                    bridge_deployment(&deployment, &sha, event.deployable_url.as_deref()).await
+                {
+                    error!("Error bridging deployment to GitHub: {:?}", err);
+                }
+            }
+            if config::feature_enabled(&config::Feature::ReviewAppComments) {
+                if let Err(err) = post_review_app_url(&deployment, &sha).await {
+                    error!("Error posting review app URL: {:?}", err);
+                }
+            }
+        }
+        _ => info!("Ignoring incomplete deployment event"),
+    }
+}
+
+/// Handles the body of a GitLab webhook whose token has already been
+/// checked, where `event_type` is the `X-Gitlab-Event` header.
+pub async fn handle_event_body(event_type: &str, body: &str) -> Result<String, RequestErrorResult> {
+    match event_type {
+        "Pipeline Hook" => {
+            let event: gitlab::PipelineEvent = models::parse(body)?;
+            handle_pipeline(event).await;
+            Ok(String::from("Pipeline received 🚀"))
+        }
+        "Job Hook" => {
+            let event: gitlab::JobEvent = models::parse(body)?;
+            handle_job(event).await;
+            Ok(String::from("Job received 🚀"))
+        }
+        "Deployment Hook" => {
+            let event: gitlab::DeploymentEvent = models::parse(body)?;
+            handle_deployment(event).await;
+            Ok(String::from("Deployment received 🚀"))
+        }
+        "Note Hook" => {
+            if config::feature_enabled(&config::Feature::CommentMirroring) {
+                let event: gitlab::NoteEvent = models::parse(body)?;
+                if let Err(err) = comment_mirror::mirror_gitlab_note(&event).await {
+                    error!("Error mirroring note: {}", err);
+                }
+            } else {
+                info!("CommentMirroring feature not enabled. Skipping event.");
+            }
+            Ok(String::from("Note received 🚀"))
+        }
+        _ => Ok(format!(
+            "Unhandled event_type={}, doing nothing 😀",
+            event_type,
+        )),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::testing::{read_testdata_to_string, run_test};
+
+    #[test]
+    fn job_event() {
+        run_test(|| {
+            let event: gitlab::JobEvent =
+                serde_json::from_str(&read_testdata_to_string("gitlab_job_failed.json")).unwrap();
+            assert_eq!(event.build_id, Some(1977));
+            assert_eq!(event.build_status.as_deref(), Some("failed"));
+            assert_eq!(event.build_allow_failure, Some(false));
+            assert_eq!(
+                event.project.unwrap().path_with_namespace.as_deref(),
+                Some("gitlab-org/gitlab-test")
+            );
+        });
+    }
+
+    #[test]
+    fn deployment_event() {
+        run_test(|| {
+            let event: gitlab::DeploymentEvent =
+                serde_json::from_str(&read_testdata_to_string("gitlab_deployment_success.json"))
+                    .unwrap();
+            assert_eq!(event.deployment_id, Some(15));
+            assert_eq!(
+                event.ref_key.as_deref(),
+                Some("pr-12/octocat/hello-world/fix-typo")
+            );
+            assert_eq!(
+                event.environment_external_url.as_deref(),
+                Some("https://pr-12.review.example.com")
+            );
+            assert_eq!(
+                event.commit_url.unwrap().rsplit('/').next(),
+                Some("a91957a858320c0e17f3a0eca7cfacbff50ea29a")
+            );
+            assert_eq!(github_deployment_state("canceled"), Some("error"));
+            assert_eq!(github_deployment_state("created"), None);
+        });
+    }
+}
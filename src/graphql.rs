@@ -0,0 +1,243 @@
+//! A read-only GraphQL API at `/graphql` over the state store, for querying
+//! PR syncs, pipelines and the audit log to build dashboards on. Like the
+//! admin API, it requires the admin token.
+use crate::admin;
+use crate::audit;
+use crate::errors::GitError;
+use crate::repo_name;
+use crate::state;
+
+use async_graphql::{
+    ComplexObject, EmptyMutation, EmptySubscription, Object, Schema, SimpleObject,
+};
+use axum::{middleware, routing::post, Json, Router};
+
+/// Default and largest number of items a query returns
+const DEFAULT_LIMIT: i64 = 100;
+const MAX_LIMIT: i64 = 1000;
+
+/// Deepest query accepted, more than the schema needs
+const MAX_DEPTH: usize = 5;
+
+type StatusSchema = Schema<Query, EmptyMutation, EmptySubscription>;
+
+lazy_static! {
+    static ref SCHEMA: StatusSchema = Schema::build(Query, EmptyMutation, EmptySubscription)
+        .limit_depth(MAX_DEPTH)
+        .finish();
+}
+
+fn limit(limit: Option<i64>) -> i64 {
+    limit.unwrap_or(DEFAULT_LIMIT).clamp(0, MAX_LIMIT)
+}
+
+fn graphql_error(err: GitError) -> async_graphql::Error {
+    async_graphql::Error::new(err.to_string())
+}
+
+/// A PR head which was pushed to GitLab
+#[derive(SimpleObject)]
+#[graphql(complex)]
+struct PrSync {
+    repo: String,
+    number: i64,
+    head_sha: String,
+    gitlab_project: String,
+    gitlab_branch: String,
+    /// In seconds since the epoch
+    synced_at: i64,
+}
+
+#[ComplexObject]
+impl PrSync {
+    /// The latest pipeline of the synced head
+    async fn pipeline(&self) -> async_graphql::Result<Option<Pipeline>> {
+        Ok(state::latest_pipeline(&self.gitlab_project, &self.head_sha)
+            .map_err(graphql_error)?
+            .map(Pipeline::from))
+    }
+}
+
+impl From<state::PrSync> for PrSync {
+    fn from(sync: state::PrSync) -> Self {
+        PrSync {
+            repo: sync.github_repo,
+            number: sync.pr_number,
+            head_sha: sync.head_sha,
+            gitlab_project: sync.gitlab_project,
+            gitlab_branch: sync.gitlab_branch,
+            synced_at: sync.synced_at,
+        }
+    }
+}
+
+/// A GitLab pipeline, as last seen by LabHub
+#[derive(SimpleObject)]
+struct Pipeline {
+    gitlab_project: String,
+    id: i64,
+    sha: String,
+    status: String,
+    /// In seconds since the epoch
+    updated_at: i64,
+}
+
+impl From<state::Pipeline> for Pipeline {
+    fn from(pipeline: state::Pipeline) -> Self {
+        Pipeline {
+            gitlab_project: pipeline.gitlab_project,
+            id: pipeline.pipeline_id,
+            sha: pipeline.sha,
+            status: pipeline.status,
+            updated_at: pipeline.updated_at,
+        }
+    }
+}
+
+/// An entry of the audit log
+#[derive(SimpleObject)]
+struct AuditEvent {
+    id: i64,
+    /// In seconds since the epoch
+    at: i64,
+    actor: String,
+    repo: String,
+    pr_number: Option<i64>,
+    action: String,
+    target: String,
+    outcome: String,
+    error: Option<String>,
+}
+
+impl From<state::AuditEntry> for AuditEvent {
+    fn from(entry: state::AuditEntry) -> Self {
+        AuditEvent {
+            id: entry.id,
+            at: entry.at,
+            actor: entry.actor,
+            repo: entry.repo,
+            pr_number: entry.pr_number,
+            action: entry.action,
+            target: entry.target,
+            outcome: entry.outcome,
+            error: entry.error,
+        }
+    }
+}
+
+struct Query;
+
+#[Object]
+impl Query {
+    /// PR syncs of a repo or PR, or of all repos, newest first
+    async fn pr_syncs(
+        &self,
+        repo: Option<String>,
+        number: Option<i64>,
+        limit: Option<i64>,
+    ) -> async_graphql::Result<Vec<PrSync>> {
+        let repo = repo.map(|repo| repo_name::lookup_key(&repo));
+        Ok(
+            state::recent_pr_syncs(repo.as_deref(), number, self::limit(limit))
+                .map_err(graphql_error)?
+                .into_iter()
+                .map(PrSync::from)
+                .collect(),
+        )
+    }
+
+    /// Pipelines of a GitLab project or commit, or of all projects, most
+    /// recently updated first
+    async fn pipelines(
+        &self,
+        gitlab_project: Option<String>,
+        sha: Option<String>,
+        limit: Option<i64>,
+    ) -> async_graphql::Result<Vec<Pipeline>> {
+        Ok(state::recent_pipelines(
+            gitlab_project.as_deref(),
+            sha.as_deref(),
+            self::limit(limit),
+        )
+        .map_err(graphql_error)?
+        .into_iter()
+        .map(Pipeline::from)
+        .collect())
+    }
+
+    /// Audit log entries, newest first
+    #[allow(clippy::too_many_arguments)]
+    async fn audit_events(
+        &self,
+        repo: Option<String>,
+        pr_number: Option<i64>,
+        action: Option<String>,
+        actor: Option<String>,
+        since: Option<i64>,
+        outcome: Option<String>,
+        limit: Option<i64>,
+    ) -> async_graphql::Result<Vec<AuditEvent>> {
+        let filter = state::AuditFilter {
+            repo,
+            pr_number,
+            action,
+            actor,
+            since,
+            outcome,
+            limit: Some(self::limit(limit)),
+        };
+        Ok(audit::query(filter)
+            .map_err(graphql_error)?
+            .into_iter()
+            .map(AuditEvent::from)
+            .collect())
+    }
+}
+
+async fn graphql(Json(request): Json<async_graphql::Request>) -> Json<async_graphql::Response> {
+    Json(SCHEMA.execute(request).await)
+}
+
+/// Builds the `/graphql` route, which requires the admin token
+pub fn router() -> Router {
+    Router::new()
+        .route("/", post(graphql))
+        .route_layer(middleware::from_fn(admin::require_token))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_query() {
+        state::record_pr_sync(
+            "graphql/test",
+            3,
+            "c0ffee",
+            "group/graphql-test",
+            "pr-3/fork/test/branch",
+        )
+        .unwrap();
+        state::record_pipeline("group/graphql-test", 41, "c0ffee", "success").unwrap();
+
+        let response = SCHEMA
+            .execute(
+                r#"{ prSyncs(repo: "GraphQL/Test") { number headSha pipeline { id status } } }"#,
+            )
+            .await;
+        assert!(response.errors.is_empty(), "{:?}", response.errors);
+        assert_eq!(
+            response.data.into_json().unwrap(),
+            serde_json::json!({
+                "prSyncs": [
+                    { "number": 3, "headSha": "c0ffee", "pipeline": { "id": 41, "status": "success" } }
+                ]
+            })
+        );
+
+        // read-only
+        let response = SCHEMA.execute(r#"mutation { prSyncs { number } }"#).await;
+        assert!(!response.errors.is_empty());
+    }
+}
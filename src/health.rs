@@ -0,0 +1,207 @@
+use crate::api;
+use crate::api::{github_client, gitlab_client};
+use crate::config;
+use crate::errors::GitError;
+
+use log::{info, warn};
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+use std::time::Duration;
+
+const PROBE_INTERVAL: Duration = Duration::from_secs(30);
+const MAX_WAIT_DELAY: Duration = Duration::from_secs(60);
+
+/// The last probe result of an upstream API. Upstreams are assumed to be
+/// unhealthy until the first probe succeeds.
+struct Upstream {
+    name: &'static str,
+    healthy: AtomicBool,
+    last_error: Mutex<Option<String>>,
+}
+
+impl Upstream {
+    const fn new(name: &'static str) -> Upstream {
+        Upstream {
+            name,
+            healthy: AtomicBool::new(false),
+            last_error: Mutex::new(None),
+        }
+    }
+
+    fn record(&self, result: Result<(), GitError>) -> bool {
+        let healthy = result.is_ok();
+        if self.healthy.swap(healthy, Ordering::Relaxed) != healthy {
+            if healthy {
+                info!("{} is reachable", self.name);
+            } else {
+                warn!("{} is unreachable", self.name);
+            }
+        }
+        *self.last_error.lock().unwrap() = result.err().map(|err| err.to_string());
+        healthy
+    }
+
+    fn status(&self) -> DependencyStatus {
+        DependencyStatus {
+            healthy: self.healthy.load(Ordering::Relaxed),
+            error: self.last_error.lock().unwrap().clone(),
+        }
+    }
+}
+
+static GITHUB: Upstream = Upstream::new("GitHub");
+static GITLAB: Upstream = Upstream::new("GitLab");
+
+/// Whether a dependency checked by the readiness check is usable
+#[derive(Debug, PartialEq, Serialize)]
+pub struct DependencyStatus {
+    pub healthy: bool,
+    pub error: Option<String>,
+}
+
+impl From<Result<(), String>> for DependencyStatus {
+    fn from(result: Result<(), String>) -> DependencyStatus {
+        DependencyStatus {
+            healthy: result.is_ok(),
+            error: result.err(),
+        }
+    }
+}
+
+pub fn is_gitlab_healthy() -> bool {
+    GITLAB.healthy.load(Ordering::Relaxed)
+}
+
+pub fn gitlab_last_error() -> Option<String> {
+    GITLAB.last_error.lock().unwrap().clone()
+}
+
+/// Checks whether the GitHub API is reachable and accepts the tokens, and
+/// records the result
+pub async fn probe_github() -> bool {
+    let result = async {
+        let client = api::new_client()?;
+        for instance in config::CONFIG.github.iter() {
+            github_client::get_token_scopes(&client, instance)
+                .await
+                .map_err(|err| {
+                    GitError::Other(format!("GitHub instance {}: {}", instance.name, err))
+                })?;
+        }
+        Ok::<(), GitError>(())
+    }
+    .await;
+    GITHUB.record(result)
+}
+
+/// Checks whether the GitLab API is reachable, and records the result
+pub async fn probe_gitlab() -> bool {
+    let result = async {
+        let client = api::new_client()?;
+        for instance in config::CONFIG.gitlab.iter() {
+            gitlab_client::get_version(&client, instance)
+                .await
+                .map_err(|err| {
+                    GitError::Other(format!("GitLab instance {}: {}", instance.name, err))
+                })?;
+        }
+        Ok::<(), GitError>(())
+    }
+    .await;
+    GITLAB.record(result)
+}
+
+/// Checks that the SSH keys of all configured sites can be read
+fn check_ssh_keys<'a>(sites: impl Iterator<Item = &'a config::Site>) -> Result<(), String> {
+    let unreadable: Vec<String> = sites
+        .filter(|site| !site.ssh_key.is_empty())
+        .filter_map(|site| {
+            let path = site.ssh_key_path();
+            File::open(&path)
+                .err()
+                .map(|err| format!("{}: {}", path, err))
+        })
+        .collect();
+    if unreadable.is_empty() {
+        Ok(())
+    } else {
+        Err(format!("Unreadable SSH keys: {}", unreadable.join(", ")))
+    }
+}
+
+/// Checks that repos can be cloned into the temporary directory
+fn check_repo_cache() -> Result<(), String> {
+    tempfile::tempdir().map(|_| ()).map_err(|err| {
+        format!(
+            "Unable to create a repo directory in {}: {}",
+            std::env::temp_dir().display(),
+            err
+        )
+    })
+}
+
+/// The status of each dependency LabHub needs to sync PRs. The API tokens
+/// are checked by the periodic probe, the rest on each call.
+pub fn dependencies() -> BTreeMap<&'static str, DependencyStatus> {
+    let sites = config::CONFIG.sites();
+    BTreeMap::from([
+        ("github", GITHUB.status()),
+        ("gitlab", GITLAB.status()),
+        ("ssh_keys", check_ssh_keys(sites).into()),
+        ("repo_cache", check_repo_cache().into()),
+    ])
+}
+
+/// Waits, with backoff, until GitLab is reachable
+pub async fn wait_for_gitlab() {
+    let mut delay = Duration::from_secs(1);
+    while !is_gitlab_healthy() && !probe_gitlab().await {
+        info!("Waiting {:?} for GitLab to come back", delay);
+        tokio::time::sleep(delay).await;
+        delay = std::cmp::min(delay * 2, MAX_WAIT_DELAY);
+    }
+}
+
+/// Keeps the GitHub and GitLab health status fresh for the readiness checks
+pub async fn run_periodic_probe() {
+    let mut interval = tokio::time::interval(PROBE_INTERVAL);
+    loop {
+        interval.tick().await;
+        probe_gitlab().await;
+        probe_github().await;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn site(ssh_key: &str) -> config::Site {
+        config::Site {
+            webhook_secret: String::new(),
+            username: String::new(),
+            ssh_key: ssh_key.to_string(),
+            ssh_key_passphrase: None,
+            ssh_key_passphrase_env: None,
+            ssh_agent: false,
+            api_token: String::new(),
+            hostname: None,
+            ssh_url: None,
+        }
+    }
+
+    #[test]
+    fn test_check_ssh_keys() {
+        let dir = tempfile::tempdir().unwrap();
+        let key = dir.path().join("key");
+        std::fs::write(&key, "key").unwrap();
+        let readable = site(key.to_str().unwrap());
+        assert_eq!(check_ssh_keys([&readable].into_iter()), Ok(()));
+        let missing = site("/nonexistent/key");
+        let err = check_ssh_keys([&readable, &missing].into_iter()).unwrap_err();
+        assert!(err.starts_with("Unreadable SSH keys: /nonexistent/key: "));
+        assert!(check_repo_cache().is_ok());
+    }
+}
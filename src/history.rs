@@ -0,0 +1,80 @@
+//! A changelog of what LabHub did to each PR (mirrored heads, pipelines,
+//! commands, comments), for answering "what did the bot do to my PR?".
+use crate::errors::GitError;
+use crate::repo_name;
+use crate::state;
+
+use log::error;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Action {
+    /// The PR head was pushed to its GitLab branch
+    Mirrored,
+    /// The GitLab branch of a closed PR was deleted
+    BranchDeleted,
+    /// A pipeline was started or finished
+    Pipeline,
+    /// A command comment was handled
+    Command,
+    /// A comment was posted or refreshed
+    Comment,
+    /// The PR's GitLab merge request was opened or closed
+    MergeRequest,
+}
+
+impl Action {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Action::Mirrored => "mirrored",
+            Action::BranchDeleted => "branch_deleted",
+            Action::Pipeline => "pipeline",
+            Action::Command => "command",
+            Action::Comment => "comment",
+            Action::MergeRequest => "merge_request",
+        }
+    }
+}
+
+/// Records an action on a PR. Failing to do so doesn't fail the action.
+pub fn record(github_repo: &str, pr_number: i64, action: Action, detail: &str) {
+    let repo = repo_name::lookup_key(github_repo);
+    if let Err(err) = state::record_pr_action(&repo, pr_number, action.name(), detail) {
+        error!(
+            "Error recording {} on {}#{}: {:?}",
+            action.name(),
+            repo,
+            pr_number,
+            err
+        );
+    }
+}
+
+/// Shortens a comment to its first line, for the detail of a
+/// [`Action::Comment`]
+pub fn summarize(body: &str) -> String {
+    const MAX_CHARS: usize = 80;
+    let line = body.lines().next().unwrap_or_default().trim();
+    if line.chars().count() > MAX_CHARS {
+        format!("{}…", line.chars().take(MAX_CHARS).collect::<String>())
+    } else {
+        line.to_string()
+    }
+}
+
+/// The actions taken on a PR, oldest first
+pub fn for_pr(github_repo: &str, pr_number: i64) -> Result<Vec<state::PrAction>, GitError> {
+    state::pr_actions(&repo_name::canonicalize(github_repo)?, pr_number)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_summarize() {
+        assert_eq!(summarize("  First line\nsecond"), "First line");
+        assert_eq!(summarize(""), "");
+        let long = "x".repeat(100);
+        assert_eq!(summarize(&long), format!("{}…", "x".repeat(80)));
+    }
+}
@@ -0,0 +1,340 @@
+//! Verifying the SSH host keys of the servers LabHub clones from and pushes
+//! to, against the `known_hosts` file and the pinned fingerprints of the
+//! `[ssh]` section. libgit2 doesn't check them on its own.
+use crate::config;
+
+use base64::engine::general_purpose::{STANDARD, STANDARD_NO_PAD};
+use base64::Engine;
+use log::{info, warn};
+use ring::{digest, hmac};
+
+/// A host pattern of a known_hosts line
+#[derive(Debug, PartialEq)]
+enum HostPattern {
+    Plain(String),
+    /// `|1|salt|hash`, as written by `ssh-keygen -H` or `ssh-keyscan -H`
+    Hashed {
+        salt: Vec<u8>,
+        hash: Vec<u8>,
+    },
+}
+
+impl HostPattern {
+    fn parse(pattern: &str) -> Result<HostPattern, String> {
+        let hashed = match pattern.strip_prefix("|1|") {
+            Some(hashed) => hashed,
+            None => return Ok(HostPattern::Plain(pattern.to_lowercase())),
+        };
+        let (salt, hash) = hashed
+            .split_once('|')
+            .ok_or(format!("invalid hashed host {}", pattern))?;
+        let decode = |value: &str| {
+            STANDARD
+                .decode(value)
+                .map_err(|err| format!("invalid hashed host {}: {}", pattern, err))
+        };
+        Ok(HostPattern::Hashed {
+            salt: decode(salt)?,
+            hash: decode(hash)?,
+        })
+    }
+
+    /// Whether the pattern is for `host`, on any port
+    fn matches(&self, host: &str) -> bool {
+        match self {
+            HostPattern::Plain(pattern) => {
+                pattern == host
+                    || pattern
+                        .strip_prefix('[')
+                        .and_then(|pattern| pattern.split_once("]:"))
+                        .is_some_and(|(pattern, _)| pattern == host)
+            }
+            HostPattern::Hashed { salt, hash } => {
+                let key = hmac::SigningKey::new(&digest::SHA1, salt);
+                hmac::sign(&key, host.as_bytes()).as_ref() == hash.as_slice()
+            }
+        }
+    }
+}
+
+/// A host key LabHub knows of
+#[derive(Debug)]
+struct KnownKey {
+    hosts: Vec<HostPattern>,
+    fingerprint: String,
+    /// Marked `@revoked`, so connections presenting it are refused
+    revoked: bool,
+}
+
+/// The SHA256 fingerprint of a host key, as `ssh-keygen -l` prints it, ex:
+/// `SHA256:uNiVztksCsDhcc0u9e8BujQXVUpKZIDTMczCvj3tD2s`
+pub fn fingerprint(key: &[u8]) -> String {
+    fingerprint_of_hash(digest::digest(&digest::SHA256, key).as_ref())
+}
+
+/// The fingerprint of a host key from its SHA256 hash
+pub fn fingerprint_of_hash(hash: &[u8]) -> String {
+    format!("SHA256:{}", STANDARD_NO_PAD.encode(hash))
+}
+
+fn parse_fingerprint(fingerprint: &str) -> Result<String, String> {
+    let hash = fingerprint
+        .strip_prefix("SHA256:")
+        .and_then(|hash| STANDARD_NO_PAD.decode(hash.trim_end_matches('=')).ok())
+        .filter(|hash| hash.len() == 32)
+        .ok_or(format!(
+            "{:?} isn't a SHA256 fingerprint, like ssh-keygen -l prints",
+            fingerprint
+        ))?;
+    Ok(fingerprint_of_hash(&hash))
+}
+
+/// Parses the keys of a known_hosts file. Wildcard and negated host
+/// patterns, and `@cert-authority` lines, aren't supported and are skipped.
+fn parse_known_hosts(contents: &str) -> Result<Vec<KnownKey>, String> {
+    let mut keys = vec![];
+    for (n, line) in contents.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let mut fields = line.split_whitespace();
+        let mut hosts = fields.next();
+        let revoked = match hosts {
+            Some("@revoked") => {
+                hosts = fields.next();
+                true
+            }
+            Some(marker) if marker.starts_with('@') => continue,
+            _ => false,
+        };
+        let (hosts, key) = match (hosts, fields.next(), fields.next()) {
+            (Some(hosts), Some(_key_type), Some(key)) => (hosts, key),
+            _ => return Err(format!("line {}: expected hosts, key type and key", n + 1)),
+        };
+        let key = STANDARD
+            .decode(key)
+            .map_err(|err| format!("line {}: invalid key: {}", n + 1, err))?;
+        let hosts = hosts
+            .split(',')
+            .filter(|pattern| !pattern.contains(['*', '?', '!']))
+            .map(HostPattern::parse)
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|err| format!("line {}: {}", n + 1, err))?;
+        keys.push(KnownKey {
+            hosts,
+            fingerprint: fingerprint(&key),
+            revoked,
+        });
+    }
+    Ok(keys)
+}
+
+/// The host keys LabHub trusts
+#[derive(Debug)]
+pub struct TrustedKeys {
+    keys: Vec<KnownKey>,
+}
+
+impl TrustedKeys {
+    /// Loads the keys of the `[ssh]` section, if it configures any
+    pub fn load(ssh: &config::Ssh) -> Result<Option<TrustedKeys>, String> {
+        if ssh.known_hosts.is_none() && ssh.host_key_fingerprints.is_empty() {
+            return Ok(None);
+        }
+        let mut keys = vec![];
+        if let Some(path) = ssh.known_hosts.as_deref() {
+            let contents = std::fs::read_to_string(path)
+                .map_err(|err| format!("known_hosts {} isn't readable: {}", path, err))?;
+            keys = parse_known_hosts(&contents)
+                .map_err(|err| format!("known_hosts {}: {}", path, err))?;
+        }
+        for (host, fingerprints) in ssh.host_key_fingerprints.iter() {
+            for fingerprint in fingerprints {
+                keys.push(KnownKey {
+                    hosts: vec![HostPattern::Plain(host.to_lowercase())],
+                    fingerprint: parse_fingerprint(fingerprint)
+                        .map_err(|err| format!("host_key_fingerprints of {}: {}", host, err))?,
+                    revoked: false,
+                });
+            }
+        }
+        Ok(Some(TrustedKeys { keys }))
+    }
+
+    /// Checks the fingerprint of the key `host` presented
+    pub fn verify(&self, host: &str, fingerprint: &str) -> Result<(), String> {
+        let host = host.to_lowercase();
+        let mut host_keys = self
+            .keys
+            .iter()
+            .filter(|key| key.hosts.iter().any(|pattern| pattern.matches(&host)))
+            .peekable();
+        if host_keys.peek().is_none() {
+            return Err(format!(
+                "No host key of {} is known, refusing to connect. Add it to the \
+                 known_hosts file or host_key_fingerprints of the [ssh] section.",
+                host
+            ));
+        }
+        let matching: Vec<&KnownKey> = host_keys
+            .filter(|key| key.fingerprint == fingerprint)
+            .collect();
+        if matching.iter().any(|key| key.revoked) {
+            Err(format!(
+                "Host key {} of {} is revoked, refusing to connect",
+                fingerprint, host
+            ))
+        } else if matching.is_empty() {
+            Err(format!(
+                "Host key {} of {} doesn't match its known keys, refusing to connect. \
+                 It may have been rotated, or the connection intercepted.",
+                fingerprint, host
+            ))
+        } else {
+            Ok(())
+        }
+    }
+}
+
+lazy_static! {
+    static ref TRUSTED_KEYS: Result<Option<TrustedKeys>, String> =
+        TrustedKeys::load(&config::CONFIG.ssh);
+}
+
+/// Checks the SHA256 fingerprint of the key `host` presented against the
+/// trusted keys, failing if it has none. Returns `None` if no keys are
+/// configured, so host keys aren't verified, and an error if the configured
+/// keys can't be loaded.
+pub fn check(host: &str, fingerprint: Option<&str>) -> Option<Result<(), String>> {
+    match &*TRUSTED_KEYS {
+        Ok(None) => None,
+        Ok(Some(keys)) => Some(verify_fingerprint(keys, host, fingerprint)),
+        Err(err) => Some(Err(format!(
+            "SSH host keys can't be verified, refusing to connect: {}",
+            err
+        ))),
+    }
+}
+
+fn verify_fingerprint(
+    keys: &TrustedKeys,
+    host: &str,
+    fingerprint: Option<&str>,
+) -> Result<(), String> {
+    match fingerprint {
+        Some(fingerprint) => keys.verify(host, fingerprint),
+        None => Err(format!(
+            "The host key of {} has no SHA256 hash to verify, refusing to connect",
+            host
+        )),
+    }
+}
+
+/// Logs whether SSH host keys are verified, on startup
+pub fn log_status() {
+    match &*TRUSTED_KEYS {
+        Ok(None) => warn!(
+            "SSH host keys aren't verified, set known_hosts or host_key_fingerprints in the \
+             [ssh] section"
+        ),
+        Ok(Some(keys)) => info!("Verifying SSH host keys against {} keys", keys.keys.len()),
+        Err(err) => warn!("SSH connections will be refused: {}", err),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::collections::HashMap;
+
+    const GITHUB_KEY: &str = "AAAAC3NzaC1lZDI1NTE5AAAAIOMqqnkVzrm0SdG6UOoqKLsabgH5C9okWi0dh2l9GKJl";
+    const GITHUB_FINGERPRINT: &str = "SHA256:+DiY3wvvV6TuJJhbpZisF/zLDA0zPMSvHdkr4UvCOqU";
+
+    #[test]
+    fn test_fingerprint() {
+        assert_eq!(
+            fingerprint(&STANDARD.decode(GITHUB_KEY).unwrap()),
+            GITHUB_FINGERPRINT
+        );
+        assert_eq!(
+            parse_fingerprint(&format!("{}=", GITHUB_FINGERPRINT)).unwrap(),
+            GITHUB_FINGERPRINT
+        );
+        assert!(parse_fingerprint("MD5:16:27:ac:a5:76:28:2d:36:63:1b:56:4d:eb:df:a6:48").is_err());
+        assert!(parse_fingerprint("SHA256:abc").is_err());
+    }
+
+    #[test]
+    fn test_known_hosts() {
+        let contents = format!(
+            "# github.com:22 SSH-2.0-babeld\n\
+             github.com,140.82.121.4 ssh-ed25519 {key}\n\
+             [gitlab.example.com]:2222 ssh-ed25519 {key} comment\n\
+             |1|JfKTdBh7rNbXkVAQCRp4OQoPfmI=|fhROika3wTFZT5uXNwOG1UeyFB4= ssh-ed25519 {key}\n\
+             *.example.org ssh-ed25519 {key}\n\
+             @cert-authority *.example.com ssh-ed25519 {key}\n\
+             @revoked old.example.com ssh-ed25519 {key}\n",
+            key = GITHUB_KEY
+        );
+        let keys = TrustedKeys {
+            keys: parse_known_hosts(&contents).unwrap(),
+        };
+        assert_eq!(keys.keys.len(), 5);
+        assert!(keys.verify("github.com", GITHUB_FINGERPRINT).is_ok());
+        assert!(keys.verify("GitHub.com", GITHUB_FINGERPRINT).is_ok());
+        assert!(keys
+            .verify("gitlab.example.com", GITHUB_FINGERPRINT)
+            .is_ok());
+        // the hashed entry is for "hashed.example.com"
+        assert!(keys
+            .verify("hashed.example.com", GITHUB_FINGERPRINT)
+            .is_ok());
+        let other = fingerprint(b"another key");
+        assert!(keys
+            .verify("github.com", &other)
+            .unwrap_err()
+            .starts_with("Host key SHA256:"));
+        assert!(keys
+            .verify("git.example.org", GITHUB_FINGERPRINT)
+            .unwrap_err()
+            .starts_with("No host key of git.example.org is known"));
+        assert!(keys
+            .verify("old.example.com", GITHUB_FINGERPRINT)
+            .unwrap_err()
+            .ends_with("is revoked, refusing to connect"));
+
+        assert_eq!(
+            parse_known_hosts("github.com ssh-ed25519").unwrap_err(),
+            "line 1: expected hosts, key type and key"
+        );
+        assert!(parse_known_hosts("github.com ssh-ed25519 !!!").is_err());
+    }
+
+    #[test]
+    fn test_load() {
+        assert!(TrustedKeys::load(&config::Ssh::default())
+            .unwrap()
+            .is_none());
+        let ssh = config::Ssh {
+            known_hosts: None,
+            host_key_fingerprints: HashMap::from([(
+                "GitHub.com".to_string(),
+                vec![GITHUB_FINGERPRINT.to_string()],
+            )]),
+        };
+        let keys = TrustedKeys::load(&ssh).unwrap().unwrap();
+        assert!(keys.verify("github.com", GITHUB_FINGERPRINT).is_ok());
+        assert!(verify_fingerprint(&keys, "github.com", Some(GITHUB_FINGERPRINT)).is_ok());
+        assert!(verify_fingerprint(&keys, "github.com", None)
+            .unwrap_err()
+            .ends_with("has no SHA256 hash to verify, refusing to connect"));
+        let ssh = config::Ssh {
+            known_hosts: Some("/nonexistent/known_hosts".to_string()),
+            host_key_fingerprints: HashMap::new(),
+        };
+        assert!(TrustedKeys::load(&ssh)
+            .unwrap_err()
+            .starts_with("known_hosts /nonexistent/known_hosts isn't readable"));
+    }
+}
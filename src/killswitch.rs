@@ -0,0 +1,105 @@
+//! Kill switches, which stop LabHub from doing one kind of thing at all
+//! while engaged, e.g. to stop a misbehaving feature from spamming PRs
+//! while a fix is prepared. Unlike the `features` config, they're flipped
+//! at runtime through the admin API, and checked right before each action
+//! so that syncs already in progress honor them too.
+use crate::errors::GitError;
+use crate::state;
+
+use log::{error, info, warn};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum KillSwitch {
+    /// Posting, updating, deleting and minimizing PR comments
+    Comments,
+    /// Reacting to command comments
+    Reactions,
+    /// Pushing and deleting GitLab branches
+    Branches,
+    /// Creating and retrying GitLab pipelines and jobs
+    Pipelines,
+    /// Creating GitHub deployments and their statuses
+    Deployments,
+    /// Creating GitHub check runs
+    Checks,
+    /// Opening and updating GitLab merge requests
+    MergeRequests,
+}
+
+impl KillSwitch {
+    pub const ALL: [KillSwitch; 7] = [
+        KillSwitch::Comments,
+        KillSwitch::Reactions,
+        KillSwitch::Branches,
+        KillSwitch::Pipelines,
+        KillSwitch::Deployments,
+        KillSwitch::Checks,
+        KillSwitch::MergeRequests,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            KillSwitch::Comments => "comments",
+            KillSwitch::Reactions => "reactions",
+            KillSwitch::Branches => "branches",
+            KillSwitch::Pipelines => "pipelines",
+            KillSwitch::Deployments => "deployments",
+            KillSwitch::Checks => "checks",
+            KillSwitch::MergeRequests => "merge_requests",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Result<KillSwitch, GitError> {
+        KillSwitch::ALL
+            .into_iter()
+            .find(|switch| switch.name() == name)
+            .ok_or(GitError::NotFound(format!("Unknown kill switch {}", name)))
+    }
+}
+
+pub fn engage(switch: KillSwitch, engaged_by: &str) -> Result<(), GitError> {
+    warn!("Kill switch {} engaged by {}", switch.name(), engaged_by);
+    state::engage_kill_switch(switch.name(), engaged_by)
+}
+
+/// Releases a kill switch, returning false if it wasn't engaged
+pub fn release(switch: KillSwitch) -> Result<bool, GitError> {
+    info!("Kill switch {} released", switch.name());
+    state::release_kill_switch(switch.name())
+}
+
+/// Whether a kill switch is engaged. If that can't be told, the action is
+/// let through, as a kill switch is an emergency measure.
+pub fn is_engaged(switch: KillSwitch) -> bool {
+    match state::engaged_kill_switches() {
+        Ok(engaged) => engaged.iter().any(|engaged| engaged.name == switch.name()),
+        Err(err) => {
+            error!("Error checking kill switch {}: {:?}", switch.name(), err);
+            false
+        }
+    }
+}
+
+/// Fails if a kill switch is engaged, for actions whose callers need to
+/// know they didn't happen
+pub fn check(switch: KillSwitch) -> Result<(), GitError> {
+    if is_engaged(switch) {
+        let err = GitError::KillSwitch(switch.name());
+        warn!("{}", err);
+        return Err(err);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_from_name() {
+        for switch in KillSwitch::ALL {
+            assert_eq!(KillSwitch::from_name(switch.name()).unwrap(), switch);
+        }
+        assert!(KillSwitch::from_name("everything").is_err());
+    }
+}
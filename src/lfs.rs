@@ -0,0 +1,169 @@
+//! Git LFS forwarding: pushing a branch only pushes the LFS pointer files,
+//! so the objects they point to are copied from the source forge's LFS
+//! server to GitLab's before the push.
+use crate::api;
+use crate::api::gitlab_client;
+use crate::api::lfs_client::{self, Endpoint, Object};
+use crate::config;
+use crate::errors::GitError;
+use crate::forge::Forge;
+
+use log::info;
+
+const POINTER_VERSION: &str = "version https://git-lfs.github.com/spec/v1";
+
+/// Pointer files are tiny, any bigger blob is real content
+pub const MAX_POINTER_SIZE: usize = 1024;
+
+/// Parses an LFS pointer file, returning `None` for any other blob
+pub fn parse_pointer(data: &[u8]) -> Option<Object> {
+    if data.len() > MAX_POINTER_SIZE {
+        return None;
+    }
+    let text = std::str::from_utf8(data).ok()?;
+    let mut lines = text.lines();
+    if lines.next()? != POINTER_VERSION {
+        return None;
+    }
+    let mut oid = None;
+    let mut size = None;
+    for line in lines {
+        match line.split_once(' ') {
+            Some(("oid", value)) => oid = value.strip_prefix("sha256:"),
+            Some(("size", value)) => size = value.parse().ok(),
+            _ => {}
+        }
+    }
+    Some(Object {
+        oid: oid?.to_string(),
+        size: size?,
+    })
+}
+
+/// LFS server of the repo a PR's head was fetched from
+fn source_endpoint(
+    forge: Forge,
+    base_full_name: &str,
+    head_full_name: &str,
+) -> Result<Endpoint, GitError> {
+    let site = forge.site(base_full_name)?;
+    let hostname = match forge {
+        Forge::GitHub => config::github_for_repo(base_full_name)
+            .hostname()
+            .to_string(),
+        Forge::Gitea => site.hostname.clone().unwrap_or_default(),
+        Forge::Bitbucket => site
+            .hostname
+            .clone()
+            .unwrap_or_else(|| "bitbucket.org".to_string()),
+    };
+    Ok(Endpoint {
+        url: format!("https://{}/{}.git/info/lfs", hostname, head_full_name),
+        username: site.username.clone(),
+        password: site.api_token(),
+    })
+}
+
+fn gitlab_endpoint(project: &str) -> Endpoint {
+    let instance = config::gitlab_for_project(project);
+    Endpoint {
+        url: format!(
+            "https://{}/{}.git/info/lfs",
+            gitlab_client::hostname(instance),
+            project
+        ),
+        username: instance.site.username.clone(),
+        password: instance.site.api_token(),
+    }
+}
+
+/// Copies the LFS objects GitLab is missing from the source forge, returning
+/// how many were uploaded
+pub async fn forward_objects(
+    forge: Forge,
+    base_full_name: &str,
+    head_full_name: &str,
+    gitlab_project: &str,
+    objects: &[Object],
+) -> Result<usize, GitError> {
+    if objects.is_empty() {
+        return Ok(0);
+    }
+    let client = api::new_client()?;
+    let gitlab = gitlab_endpoint(gitlab_project);
+    let uploads: Vec<lfs_client::BatchObject> =
+        lfs_client::batch(&client, &gitlab, "upload", objects)
+            .await?
+            .into_iter()
+            .filter(|object| object.actions.contains_key("upload"))
+            .collect();
+    if uploads.is_empty() {
+        return Ok(0);
+    }
+    info!(
+        "Forwarding {} LFS objects to GitLab project={}",
+        uploads.len(),
+        gitlab_project
+    );
+
+    let missing: Vec<Object> = uploads
+        .iter()
+        .map(|object| Object {
+            oid: object.oid.clone(),
+            size: object.size,
+        })
+        .collect();
+    let source = source_endpoint(forge, base_full_name, head_full_name)?;
+    let downloads = lfs_client::batch(&client, &source, "download", &missing).await?;
+
+    for upload in uploads.iter() {
+        let download = downloads.iter().find(|download| download.oid == upload.oid);
+        let download = match download.and_then(|download| download.actions.get("download")) {
+            Some(action) => action,
+            None => {
+                return Err(GitError::UnexpectedResponse(format!(
+                    "LFS object {} can't be downloaded from {}: {:?}",
+                    upload.oid,
+                    source.url,
+                    download.and_then(|download| download.error.as_ref())
+                )))
+            }
+        };
+        let data = lfs_client::download(&client, download).await?;
+        lfs_client::upload(&client, &upload.actions["upload"], data).await?;
+        if let Some(verify) = upload.actions.get("verify") {
+            let object = Object {
+                oid: upload.oid.clone(),
+                size: upload.size,
+            };
+            lfs_client::verify(&client, &gitlab, verify, &object).await?;
+        }
+    }
+    Ok(uploads.len())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse_pointer() {
+        let pointer = parse_pointer(
+            b"version https://git-lfs.github.com/spec/v1\n\
+              oid sha256:4d7a214614ab2935c943f9e0ff69d22eadbb8f32b1258daaa5e2ca24d17e2393\n\
+              size 12345\n",
+        )
+        .unwrap();
+        assert_eq!(
+            pointer.oid,
+            "4d7a214614ab2935c943f9e0ff69d22eadbb8f32b1258daaa5e2ca24d17e2393"
+        );
+        assert_eq!(pointer.size, 12345);
+
+        assert_eq!(parse_pointer(b"fn main() {}\n"), None);
+        assert_eq!(
+            parse_pointer(b"version https://git-lfs.github.com/spec/v1\nsize 12\n"),
+            None
+        );
+    }
+}
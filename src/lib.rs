@@ -0,0 +1,243 @@
+//! LabHub bridges GitHub PRs (as well as Gitea and Bitbucket ones) to GitLab
+//! CI: it listens for webhooks, pushes branches from external (forked) PRs to
+//! GitLab, and accepts commands by way of PR comments.
+//!
+//! The `labhub` binary is a thin wrapper around this crate. To embed the
+//! bridge in another service, load the config and its [`secrets`], start the
+//! background tasks, and merge [`app`] into your own router:
+//!
+//! ```no_run
+//! # async fn run() {
+//! use axum::{routing::get, Router};
+//!
+//! labhub::config::load_config().expect("invalid config");
+//! labhub::secrets::load().await.expect("unable to fetch secrets");
+//! labhub::start_background_tasks();
+//!
+//! let app = Router::new()
+//!     .route("/hello", get(|| async { "hi" }))
+//!     .merge(labhub::app());
+//! axum::Server::bind(&"127.0.0.1:8080".parse().unwrap())
+//!     .serve(app.into_make_service())
+//!     .await
+//!     .unwrap();
+//! # }
+//! ```
+//!
+//! The sync engine can also be driven without the server, as the
+//! subcommands of the `labhub` binary do, e.g. with
+//! [`github::sync_pr_now`] or [`cleanup::cleanup_project_branches`]. Webhook
+//! bodies which were already verified can be handled with each forge's
+//! `handle_event_body`, e.g. [`github::handle_event_body`].
+#[macro_use]
+extern crate serde_derive;
+#[macro_use]
+extern crate lazy_static;
+extern crate futures;
+extern crate regex;
+extern crate reqwest;
+extern crate toml;
+extern crate url;
+use axum::{
+    body::Body,
+    extract::DefaultBodyLimit,
+    http::Request,
+    middleware::{self, Next},
+    routing::{get, post, MethodRouter},
+    Router,
+};
+use log::{error, info};
+use std::convert::Infallible;
+
+mod admin;
+pub mod api;
+mod artifacts;
+mod audit;
+pub mod bitbucket;
+mod body_limit;
+mod ci_config;
+pub mod cleanup;
+mod cluster;
+pub mod commands;
+mod comment_mirror;
+pub mod config;
+mod coverage;
+mod dashboard;
+mod dedupe;
+mod disk;
+mod durations;
+pub mod errors;
+mod event_webhooks;
+mod flaky;
+pub mod forge;
+mod git_cli;
+pub mod gitea;
+pub mod github;
+pub mod gitlab;
+mod graphql;
+mod health;
+mod history;
+mod host_keys;
+mod killswitch;
+mod lfs;
+pub mod logging;
+mod mapping_rules;
+mod messages;
+mod metrics;
+mod notifications;
+mod pause;
+mod persist;
+mod pipeline_summary;
+mod protected;
+mod provisioning;
+mod queue;
+pub mod ref_name;
+mod replay;
+pub mod repo_name;
+mod request_log;
+pub mod secrets;
+mod sentry;
+pub mod service;
+mod signing;
+pub mod state;
+mod submodules;
+mod sync;
+#[cfg(feature = "bench")]
+#[doc(hidden)]
+pub use sync::bench;
+mod test_report;
+mod token_check;
+mod trust;
+pub mod webhook_setup;
+
+#[cfg(test)]
+mod testing;
+
+/// Largest request body accepted by [`app`], unless `[server.body_limits]`
+/// says otherwise
+pub const MAX_BODY_LENGTH: usize = 10 * 1024 * 1024;
+
+/// Limits the bodies of `route` to `limit` bytes, turning away those whose
+/// `Content-Length` is larger before reading them
+fn limit_body(route: MethodRouter, name: &'static str, limit: usize) -> MethodRouter {
+    route
+        .layer::<_, _, Infallible>(middleware::from_fn(
+            move |request: Request<Body>, next: Next<Body>| {
+                body_limit::reject_oversized(name, limit, request, next)
+            },
+        ))
+        .layer(DefaultBodyLimit::max(limit))
+}
+
+/// The route of a forge's webhooks, with the body limit configured for it
+fn webhook(forge: &'static str, route: MethodRouter) -> MethodRouter {
+    limit_body(
+        route,
+        forge,
+        config::CONFIG.server.body_limits.webhook(forge),
+    )
+}
+
+/// Builds the router with all of LabHub's routes
+pub fn app() -> Router {
+    Router::new()
+        .route("/check", get(service::check))
+        .route("/check/ready", get(service::ready))
+        .route("/healthz", get(service::check))
+        .route("/readyz", get(service::readyz))
+        .route("/version", get(service::version))
+        .route("/metrics", get(service::metrics))
+        .route(
+            "/github/events",
+            webhook("github", post(service::github_event)),
+        )
+        .route(
+            "/gitlab/events",
+            webhook("gitlab", post(service::gitlab_event)),
+        )
+        .route(
+            "/gitea/events",
+            webhook("gitea", post(service::gitea_event)),
+        )
+        .route(
+            "/bitbucket/events",
+            webhook("bitbucket", post(service::bitbucket_event)),
+        )
+        .nest("/admin", admin::router())
+        .nest("/dashboard", dashboard::router())
+        .nest("/graphql", graphql::router())
+        .layer(middleware::from_fn(request_log::log_request))
+        .layer(DefaultBodyLimit::max(
+            config::CONFIG.server.body_limits.default,
+        ))
+}
+
+/// Spawns the PR sync queue worker, the upstream health probe, and the
+/// background tasks for the enabled features (token checks, startup
+/// reconciliation, stale branch cleanup), after removing the repo clones
+/// left behind by a previous process and queueing the events saved by
+/// [`shutdown`]. Panics are reported to Sentry from then on, if it's
+/// configured. Must be called from within a Tokio runtime, otherwise PR
+/// events are queued but never processed.
+pub fn start_background_tasks() {
+    sentry::install_panic_hook();
+    host_keys::log_status();
+    disk::remove_leftover_clones();
+    persist::restore_pending_events();
+    tokio::spawn(queue::run_worker());
+    if cluster::enabled() {
+        tokio::spawn(cluster::run_forwarder());
+        tokio::spawn(cluster::run_feeder());
+    }
+    tokio::spawn(health::run_periodic_probe());
+    tokio::spawn(token_check::check_github_token());
+    if config::CONFIG.disk.repo_ttl_secs > 0 {
+        tokio::spawn(disk::run_periodic_eviction());
+    }
+    if !secrets::references(&config::CONFIG).is_empty() {
+        tokio::spawn(secrets::run_periodic_refresh());
+    }
+    if config::feature_enabled(&config::Feature::StartupReconciliation) {
+        tokio::spawn(github::reconcile_open_prs());
+    }
+    if config::feature_enabled(&config::Feature::StaleBranchCleanup) {
+        tokio::spawn(cleanup::run_periodic_cleanup());
+    }
+}
+
+/// Resolves once the process is asked to stop, by Ctrl-C or SIGTERM
+pub async fn shutdown_signal() {
+    let ctrl_c = async {
+        if let Err(err) = tokio::signal::ctrl_c().await {
+            error!("Unable to listen for Ctrl-C: {}", err);
+            futures::future::pending::<()>().await;
+        }
+    };
+    #[cfg(unix)]
+    let terminate = async {
+        match tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate()) {
+            Ok(mut signal) => {
+                signal.recv().await;
+            }
+            Err(err) => {
+                error!("Unable to listen for SIGTERM: {}", err);
+                futures::future::pending::<()>().await;
+            }
+        }
+    };
+    #[cfg(not(unix))]
+    let terminate = futures::future::pending::<()>();
+    tokio::select! {
+        _ = ctrl_c => {}
+        _ = terminate => {}
+    }
+    info!("Shutting down");
+}
+
+/// Stops the PR sync queue worker and saves the events which weren't
+/// handled yet to the state store, so that the next
+/// [`start_background_tasks`] picks them up. Call this after the server has
+/// stopped accepting webhooks.
+pub async fn shutdown() {
+    persist::save_pending_events().await;
+}
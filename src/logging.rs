@@ -0,0 +1,78 @@
+//! Logging setup, with a filter which can be changed at runtime through the
+//! admin API, e.g. to debug a production issue without restarting and
+//! losing the failing state. The `log` records are forwarded to `tracing`.
+use crate::errors::GitError;
+
+use log::warn;
+use std::sync::Mutex;
+use tracing_subscriber::{fmt, prelude::*, reload, util::SubscriberInitExt, EnvFilter, Registry};
+
+/// Used when `RUST_LOG` is unset
+const DEFAULT_FILTER: &str = "error";
+
+lazy_static! {
+    static ref FILTER: Mutex<Option<(reload::Handle<EnvFilter, Registry>, String)>> =
+        Mutex::new(None);
+}
+
+/// Initializes logging with the filter from `RUST_LOG`, which takes the
+/// same directives as `env_logger`, e.g. `info,labhub::github=debug`
+pub fn init() {
+    let directives = std::env::var("RUST_LOG").unwrap_or_else(|_| DEFAULT_FILTER.to_string());
+    let filter = EnvFilter::try_new(&directives).unwrap_or_else(|err| {
+        eprintln!("Invalid RUST_LOG {:?}: {}", directives, err);
+        EnvFilter::new(DEFAULT_FILTER)
+    });
+    let (filter, handle) = reload::Layer::new(filter);
+    tracing_subscriber::registry()
+        .with(filter)
+        .with(fmt::layer())
+        .init();
+    // The filter does the filtering, including after it's been changed
+    log::set_max_level(log::LevelFilter::Trace);
+    *FILTER.lock().unwrap() = Some((handle, directives));
+}
+
+/// The current filter directives, or `None` if logging wasn't set up with
+/// [`init`]
+pub fn filter() -> Option<String> {
+    FILTER
+        .lock()
+        .unwrap()
+        .as_ref()
+        .map(|(_, directives)| directives.clone())
+}
+
+/// Replaces the filter with `directives`
+pub fn set_filter(directives: &str) -> Result<(), GitError> {
+    let filter = EnvFilter::try_new(directives).map_err(|err| {
+        GitError::BadRequest(format!("Invalid log filter {:?}: {}", directives, err))
+    })?;
+    let mut current = FILTER.lock().unwrap();
+    let (handle, current_directives) = current.as_mut().ok_or(GitError::Other(
+        "Logging isn't managed by LabHub".to_string(),
+    ))?;
+    handle
+        .reload(filter)
+        .map_err(|err| GitError::Other(format!("Unable to change the log filter: {}", err)))?;
+    warn!(
+        "Log filter changed from {:?} to {:?}",
+        current_directives, directives
+    );
+    *current_directives = directives.to_string();
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_set_filter() {
+        let err = set_filter("labhub=loud").unwrap_err();
+        assert!(err.to_string().starts_with("Invalid log filter"));
+        // the tests log through env_logger
+        let err = set_filter("labhub::github=debug").unwrap_err();
+        assert_eq!(err.to_string(), "Logging isn't managed by LabHub");
+    }
+}
@@ -1,48 +1,160 @@
-#[macro_use]
-extern crate serde_derive;
-#[macro_use]
-extern crate lazy_static;
-extern crate futures;
-extern crate regex;
-extern crate reqwest;
-extern crate toml;
-extern crate url;
-use axum::{extract::DefaultBodyLimit, routing::get, routing::post, Router};
-
-mod api;
-mod commands;
-mod config;
-mod errors;
-mod github;
-mod service;
-
-#[cfg(test)]
-mod testing;
-
+use clap::{value_parser, Arg, ArgAction, Command};
+use labhub::config;
 use log::info;
 
-const MAX_BODY_LENGTH: usize = 10 * 1024 * 1024;
-
-#[tokio::main]
-async fn main() {
-    // initialize tracing
-    //tracing_subscriber::fmt::init();
+fn cli() -> Command {
+    Command::new("labhub")
+        .version(env!("CARGO_PKG_VERSION"))
+        .about("Bridges GitHub PRs to GitLab CI")
+        .subcommand(Command::new("serve").about("Run the webhook server (the default)"))
+        .subcommand(
+            Command::new("sync")
+                .about("Sync one PR to GitLab right away, without the server")
+                .arg(
+                    Arg::new("repo")
+                        .required(true)
+                        .help("GitHub repo of the PR, as org/repo"),
+                )
+                .arg(
+                    Arg::new("number")
+                        .required(true)
+                        .value_parser(value_parser!(i64))
+                        .help("PR number"),
+                ),
+        )
+        .subcommand(Command::new("validate-config").about("Load the config and report problems"))
+        .subcommand(
+            Command::new("setup-webhooks")
+                .about("Create or update the GitHub and GitLab webhooks of the mapped repos")
+                .long_about(
+                    "Create or update the GitHub and GitLab webhooks of the mapped repos.\n\n\
+                     Tokens able to administer them, if the configured API tokens can't, are \
+                     read from the LABHUB_GITHUB_TOKEN and LABHUB_GITLAB_TOKEN environment \
+                     variables, or with --credentials-stdin, from a JSON object with \
+                     github_token and gitlab_token on stdin. Exits non-zero if any webhook \
+                     couldn't be set up.",
+                )
+                .arg(
+                    Arg::new("credentials-stdin")
+                        .long("credentials-stdin")
+                        .action(ArgAction::SetTrue)
+                        .help("Read the tokens from a JSON object on stdin"),
+                ),
+        )
+        .subcommand(
+            Command::new("cleanup-branches")
+                .about("Delete the pr-* branches of closed PRs from a mapped GitLab project")
+                .arg(
+                    Arg::new("project")
+                        .required(true)
+                        .help("GitLab project, as group/project"),
+                ),
+        )
+}
 
-    env_logger::init();
+/// Tokens for `setup-webhooks`, kept out of the command line where other
+/// users could see them in the process list
+fn setup_credentials(
+    from_stdin: bool,
+) -> Result<labhub::webhook_setup::Credentials, labhub::errors::GitError> {
+    if from_stdin {
+        return Ok(serde_json::from_reader(std::io::stdin().lock())?);
+    }
+    let token = |name| std::env::var(name).ok().filter(|token| !token.is_empty());
+    Ok(labhub::webhook_setup::Credentials {
+        github_token: token("LABHUB_GITHUB_TOKEN"),
+        gitlab_token: token("LABHUB_GITLAB_TOKEN"),
+    })
+}
 
-    info!("✨ May your hopes and dreams become reality ✨");
-    config::load_config();
+/// Sets the webhooks up, printing what was done, and fails if any of them
+/// couldn't be
+async fn setup_webhooks(
+    credentials: &labhub::webhook_setup::Credentials,
+) -> Result<String, labhub::errors::GitError> {
+    let setups = labhub::webhook_setup::setup_all(credentials).await?;
+    let report = serde_json::to_string_pretty(&setups).unwrap_or_default();
+    let failed = setups
+        .iter()
+        .filter(|setup| {
+            matches!(
+                setup.result,
+                labhub::webhook_setup::HookResult::Failed { .. }
+            )
+        })
+        .count();
+    if failed > 0 {
+        println!("{}", report);
+        return Err(labhub::errors::GitError::Other(format!(
+            "Unable to set up {} of {} webhooks",
+            failed,
+            setups.len()
+        )));
+    }
+    Ok(report)
+}
 
-    // build our application with a single route
-    let app = Router::new()
-        .route("/check", get(service::check))
-        .route("/github/events", post(service::github_event))
-        .route("/gitlab/events", post(service::gitlab_event))
-        .layer(DefaultBodyLimit::max(MAX_BODY_LENGTH));
+async fn serve() {
+    labhub::start_background_tasks();
 
     // run it with hyper on localhost:12345
     axum::Server::bind(&config::CONFIG.server.bindto.parse().unwrap())
-        .serve(app.into_make_service())
+        .serve(labhub::app().into_make_service())
+        .with_graceful_shutdown(labhub::shutdown_signal())
         .await
         .unwrap();
+
+    labhub::shutdown().await;
+}
+
+#[tokio::main]
+async fn main() {
+    let matches = cli().get_matches();
+    labhub::logging::init();
+
+    info!("✨ May your hopes and dreams become reality ✨");
+    if let Err(err) = config::load_config() {
+        eprintln!("{}", err);
+        std::process::exit(1);
+    }
+    match labhub::secrets::load().await {
+        Ok(0) => {}
+        Ok(count) => info!("Fetched {} secrets", count),
+        Err(err) => {
+            eprintln!("Unable to fetch secrets: {}", err);
+            std::process::exit(1);
+        }
+    }
+
+    let result = match matches.subcommand() {
+        Some(("sync", args)) => {
+            let repo = args.get_one::<String>("repo").unwrap();
+            let number = *args.get_one::<i64>("number").unwrap();
+            labhub::github::sync_pr_now(repo, number).await
+        }
+        Some(("validate-config", _)) => Ok("Config is valid".to_string()),
+        Some(("cleanup-branches", args)) => {
+            let project = args.get_one::<String>("project").unwrap();
+            labhub::cleanup::cleanup_project_branches(project)
+                .await
+                .map(|deleted| format!("Deleted {} stale branches from {}", deleted, project))
+        }
+        Some(("setup-webhooks", args)) => {
+            match setup_credentials(args.get_flag("credentials-stdin")) {
+                Ok(credentials) => setup_webhooks(&credentials).await,
+                Err(err) => Err(err),
+            }
+        }
+        _ => {
+            serve().await;
+            return;
+        }
+    };
+    match result {
+        Ok(message) => println!("{}", message),
+        Err(err) => {
+            eprintln!("{}", err);
+            std::process::exit(1);
+        }
+    }
 }
@@ -0,0 +1,107 @@
+//! Wildcard repo mappings, like `github-org/*` → `gitlab-group/*`, mapping
+//! every repo of an org to the project of the same name in a group, so
+//! orgs with many repos don't need a mapping for each. The `*` stands for
+//! the repo's name, the last segment of its path, and works both ways.
+//! Mappings of a single repo take precedence over the wildcard ones, so
+//! they're the exceptions to them.
+
+/// Whether a mapping's repo or project is a wildcard pattern, `prefix/*`
+pub fn is_wildcard(pattern: &str) -> bool {
+    pattern.ends_with("/*")
+}
+
+/// The path a wildcard pattern matches the repos in, ex: `github-org` for
+/// `github-org/*`
+pub fn prefix(pattern: &str) -> Option<&str> {
+    pattern
+        .strip_suffix("/*")
+        .filter(|prefix| !prefix.is_empty() && !prefix.contains('*'))
+}
+
+/// Maps `name` through the rule `from` → `to`, ex: `github-org/repo` through
+/// `github-org/*` → `gitlab-group/*` is `gitlab-group/repo`. Paths are
+/// matched case-insensitively, and only directly within the prefix.
+pub fn apply(from: &str, to: &str, name: &str) -> Option<String> {
+    let from_prefix = prefix(from)?;
+    let to_prefix = prefix(to)?;
+    let (name_prefix, repo) = name.rsplit_once('/')?;
+    if repo.is_empty() || !name_prefix.eq_ignore_ascii_case(from_prefix) {
+        return None;
+    }
+    Some(format!("{}/{}", to_prefix, repo))
+}
+
+/// Whether the repo `name` is `pattern`, or in its org for a wildcard
+/// pattern, ignoring case
+pub fn matches(pattern: &str, name: &str) -> bool {
+    match prefix(pattern) {
+        Some(prefix) => name
+            .rsplit_once('/')
+            .is_some_and(|(owner, repo)| !repo.is_empty() && owner.eq_ignore_ascii_case(prefix)),
+        None => pattern.eq_ignore_ascii_case(name),
+    }
+}
+
+/// A problem with a mapping from `from` to `to`, if they aren't both exact
+/// names or both wildcard patterns
+pub fn check(from: &str, to: &str) -> Option<String> {
+    match (is_wildcard(from), is_wildcard(to)) {
+        (false, false) => None,
+        (true, true) if prefix(from).is_some() && prefix(to).is_some() => None,
+        (true, true) => Some(format!(
+            "{} → {} isn't a valid wildcard mapping, like org/* → group/*",
+            from, to
+        )),
+        _ => Some(format!(
+            "{} → {} mixes a wildcard with a single repo, both need to be like org/*",
+            from, to
+        )),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_apply() {
+        assert_eq!(
+            apply("github-org/*", "gitlab-group/*", "github-org/repo").as_deref(),
+            Some("gitlab-group/repo")
+        );
+        assert_eq!(
+            apply("Github-Org/*", "group/sub/*", "github-org/repo").as_deref(),
+            Some("group/sub/repo")
+        );
+        assert_eq!(
+            apply("group/sub/*", "github-org/*", "group/sub/repo").as_deref(),
+            Some("github-org/repo")
+        );
+        assert_eq!(apply("github-org/*", "group/*", "other-org/repo"), None);
+        assert_eq!(apply("group/*", "github-org/*", "group/sub/repo"), None);
+        assert_eq!(apply("github-org/*", "group/*", "github-org/"), None);
+        assert_eq!(
+            apply("github-org/repo", "group/repo", "github-org/repo"),
+            None
+        );
+    }
+
+    #[test]
+    fn test_matches() {
+        assert!(matches("github-org/*", "GitHub-Org/repo"));
+        assert!(matches("github-org/repo", "github-org/Repo"));
+        assert!(!matches("github-org/*", "other-org/repo"));
+        assert!(!matches("github-org/*", "github-org/"));
+        assert!(!matches("github-org/repo", "github-org/other"));
+    }
+
+    #[test]
+    fn test_check() {
+        assert_eq!(check("org/repo", "group/repo"), None);
+        assert_eq!(check("org/*", "group/sub/*"), None);
+        assert!(check("org/*", "group/repo").is_some());
+        assert!(check("org/repo", "group/*").is_some());
+        assert!(check("/*", "group/*").is_some());
+        assert!(check("org/*/*", "group/*").is_some());
+    }
+}
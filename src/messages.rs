@@ -0,0 +1,645 @@
+//! The text of everything LabHub posts on PRs, in each supported language.
+//! The language is picked with `language` in the config, or per repo with
+//! the mapping's `language`.
+use crate::config::{self, Language};
+
+/// A message, with what it says about
+#[derive(Debug, Clone, Copy)]
+pub enum Message<'a> {
+    UnknownCommand,
+    PermissionDenied {
+        permission: &'a str,
+    },
+    RetrySent {
+        pipeline_id: i64,
+        project_url: &'a str,
+    },
+    MirroringPaused {
+        repo: &'a str,
+    },
+    MirroringResumed {
+        repo: &'a str,
+        queued: usize,
+    },
+    MirroringNotPaused {
+        repo: &'a str,
+    },
+    /// Reply to the `status` command, `pipeline` is a [`Message::PipelineIs`]
+    /// or [`Message::NoPipelineYet`]
+    PushedToGitlab {
+        sha: &'a str,
+        branch: &'a str,
+        project_url: &'a str,
+        pipeline: &'a str,
+    },
+    PipelineIs {
+        pipeline_id: i64,
+        project_url: &'a str,
+        status: &'a str,
+    },
+    NoPipelineYet,
+    DeployedToReviewApp {
+        url: &'a str,
+    },
+    NotPushedYet,
+    ReviewApp {
+        sha: &'a str,
+        url: &'a str,
+    },
+    LfsCopyFailed {
+        sha: &'a str,
+        error: &'a str,
+    },
+    /// A PR head from an untrusted author changes protected paths
+    ProtectedPathsChanged {
+        sha: &'a str,
+        files: &'a [String],
+    },
+    /// Reply to the `approve` command
+    HeadApproved {
+        sha: &'a str,
+    },
+    /// Reply to an `approve` command without the PR's current head
+    ApprovalNeedsHead {
+        head: &'a str,
+    },
+    /// Description of the GitLab merge request opened for a PR
+    MergeRequestDescription {
+        repo: &'a str,
+        number: i64,
+        url: Option<&'a str>,
+        author: Option<&'a str>,
+    },
+    /// Heading of a comment mirrored from the other forge
+    MirroredComment {
+        author: &'a str,
+        forge: &'a str,
+        url: Option<&'a str>,
+    },
+    FlakyRetry {
+        job: &'a str,
+        pipeline_id: i64,
+    },
+    Slowdown {
+        took: &'a str,
+        factor: f64,
+        median: &'a str,
+        samples: usize,
+        base_ref: &'a str,
+    },
+    /// Heading of a pipeline summary, `outcome` is the pipeline's status
+    PipelineSummary {
+        emoji: &'a str,
+        pipeline_id: i64,
+        pipeline_url: &'a str,
+        outcome: &'a str,
+        sha: &'a str,
+    },
+    TookDuration {
+        duration: &'a str,
+    },
+    /// Header row of the pipeline summary's table of jobs
+    JobColumns,
+    AllowedToFail,
+    Download,
+    Artifacts {
+        pipeline_id: i64,
+        sha: &'a str,
+    },
+    ArtifactsExpire {
+        date: &'a str,
+    },
+    CoverageCompared {
+        emoji: &'a str,
+        coverage: f64,
+        pipeline_id: i64,
+        delta: f64,
+        base_coverage: f64,
+        base_ref: &'a str,
+    },
+    CoverageDropTooLarge {
+        max_drop: f64,
+    },
+    CoverageWithoutBase {
+        coverage: f64,
+        pipeline_id: i64,
+        base_ref: &'a str,
+    },
+    TestsSummary {
+        total: i64,
+        passed: i64,
+        failed: i64,
+        skipped: i64,
+    },
+    TestsFailed {
+        failed: i64,
+        total: i64,
+    },
+    TestsPassed {
+        total: i64,
+    },
+    UnannotatedFailures,
+}
+
+/// Formats a decimal number, with a decimal comma in German
+fn decimal(language: Language, value: f64, precision: usize, signed: bool) -> String {
+    let formatted = if signed {
+        format!("{:+.*}", precision, value)
+    } else {
+        format!("{:.*}", precision, value)
+    };
+    match language {
+        Language::En => formatted,
+        Language::De => formatted.replace('.', ","),
+    }
+}
+
+fn short_sha(sha: &str) -> &str {
+    &sha[..sha.len().min(8)]
+}
+
+/// A Markdown link to a PR, as `org/repo#12`
+fn pr_link(repo: &str, number: i64, url: Option<&str>) -> String {
+    match url {
+        Some(url) => format!("[{}#{}]({})", repo, number, url),
+        None => format!("{}#{}", repo, number),
+    }
+}
+
+/// A Markdown list of file paths
+fn file_list(files: &[String]) -> String {
+    files
+        .iter()
+        .map(|file| format!("- `{}`", file))
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+fn outcome_en(status: &str) -> &str {
+    match status {
+        "success" => "passed",
+        "canceled" => "was canceled",
+        "skipped" => "was skipped",
+        other => other,
+    }
+}
+
+fn outcome_de(status: &str) -> &str {
+    match status {
+        "success" => "war erfolgreich",
+        "failed" => "ist fehlgeschlagen",
+        "canceled" => "wurde abgebrochen",
+        "skipped" => "wurde übersprungen",
+        other => other,
+    }
+}
+
+fn english(message: Message) -> String {
+    let language = Language::En;
+    match message {
+        Message::UnknownCommand => {
+            "Sorry, but I don't know what that command means.\n\nThanks for asking 🥰".to_string()
+        }
+        Message::PermissionDenied { permission } => format!(
+            "Sorry, this command needs {} permission on the repo.",
+            permission
+        ),
+        Message::RetrySent {
+            pipeline_id,
+            project_url,
+        } => format!(
+            "Sent **retry** command for pipeline [**{}**]({}/pipelines/{}) on [**GitLab**]({})\n\n\
+             Have a great day! 😄",
+            pipeline_id, project_url, pipeline_id, project_url
+        ),
+        Message::MirroringPaused { repo } => format!(
+            "Mirroring of {} to GitLab is paused, until someone comments `resume-mirroring`.",
+            repo
+        ),
+        Message::MirroringResumed { repo, queued } => format!(
+            "Mirroring of {} to GitLab is resumed, syncing {} PR events received meanwhile.",
+            repo, queued
+        ),
+        Message::MirroringNotPaused { repo } => format!("Mirroring of {} wasn't paused.", repo),
+        Message::PushedToGitlab {
+            sha,
+            branch,
+            project_url,
+            pipeline,
+        } => format!(
+            "Commit `{}` was pushed to branch `{}` on [**GitLab**]({}), {}.",
+            sha, branch, project_url, pipeline
+        ),
+        Message::PipelineIs {
+            pipeline_id,
+            project_url,
+            status,
+        } => format!(
+            "pipeline [**{}**]({}/pipelines/{}) is **{}**",
+            pipeline_id, project_url, pipeline_id, status
+        ),
+        Message::NoPipelineYet => "no pipeline has been seen yet".to_string(),
+        Message::DeployedToReviewApp { url } => format!("Deployed to review app: {}", url),
+        Message::NotPushedYet => "I haven't pushed this PR to GitLab yet.".to_string(),
+        Message::ReviewApp { sha, url } => format!(
+            "🔍 The review app for `{}` is deployed to {}",
+            short_sha(sha),
+            url
+        ),
+        Message::LfsCopyFailed { sha, error } => format!(
+            "⚠️ The Git LFS objects of `{}` couldn't be copied to GitLab, so CI will only see \
+             the LFS pointer files: {}",
+            short_sha(sha),
+            error
+        ),
+        Message::ProtectedPathsChanged { sha, files } => format!(
+            "🔒 `{}` wasn't pushed to GitLab, as it changes protected paths which could expose \
+             CI secrets:\n\n{}\n\nA maintainer can review it and comment `approve {}` to \
+             sync it anyway. Later pushes need approving again.",
+            short_sha(sha),
+            file_list(files),
+            short_sha(sha)
+        ),
+        Message::HeadApproved { sha } => {
+            format!("Approved `{}`, syncing it to GitLab.", short_sha(sha))
+        }
+        Message::ApprovalNeedsHead { head } => format!(
+            "Nothing was approved: the PR's head is `{}`. Review it, then comment `approve {}`.",
+            short_sha(head),
+            short_sha(head)
+        ),
+        Message::MergeRequestDescription {
+            repo,
+            number,
+            url,
+            author,
+        } => {
+            format!(
+            "Mirrored by LabHub from {}{}. This merge request's branch follows the PR's head, so \
+             push to the PR to update it.",
+            pr_link(repo, number, url),
+            author.map(|author| format!(", opened by {}", author)).unwrap_or_default()
+        )
+        }
+        Message::MirroredComment { author, forge, url } => match url {
+            Some(url) => format!("**{}** [commented]({}) on {}:", author, url, forge),
+            None => format!("**{}** commented on {}:", author, forge),
+        },
+        Message::FlakyRetry { job, pipeline_id } => format!(
+            "🔁 Job `{}` failed in pipeline {} and looks flaky, so it was retried once \
+             automatically. The pipeline's result is the retry's.",
+            job, pipeline_id
+        ),
+        Message::Slowdown {
+            took,
+            factor,
+            median,
+            samples,
+            base_ref,
+        } => format!(
+            "⚠️ This pipeline took {}, {}x the median of {} for the last {} pipelines on `{}`.",
+            took,
+            decimal(language, factor, 1, false),
+            median,
+            samples,
+            base_ref
+        ),
+        Message::PipelineSummary {
+            emoji,
+            pipeline_id,
+            pipeline_url,
+            outcome,
+            sha,
+        } => format!(
+            "### {} Pipeline [#{}]({}) {} for `{}`",
+            emoji,
+            pipeline_id,
+            pipeline_url,
+            outcome_en(outcome),
+            short_sha(sha)
+        ),
+        Message::TookDuration { duration } => format!(" in {}", duration),
+        Message::JobColumns => "| Stage | Job | Status | Duration | Artifacts |".to_string(),
+        Message::AllowedToFail => "allowed".to_string(),
+        Message::Download => "download".to_string(),
+        Message::Artifacts { pipeline_id, sha } => format!(
+            "📦 Artifacts of pipeline {} for `{}`:",
+            pipeline_id,
+            short_sha(sha)
+        ),
+        Message::ArtifactsExpire { date } => format!("expires {}", date),
+        Message::CoverageCompared {
+            emoji,
+            coverage,
+            pipeline_id,
+            delta,
+            base_coverage,
+            base_ref,
+        } => format!(
+            "{} Coverage is **{}%** in pipeline {}, {} points from {}% on `{}`.",
+            emoji,
+            decimal(language, coverage, 2, false),
+            pipeline_id,
+            decimal(language, delta, 2, true),
+            decimal(language, base_coverage, 2, false),
+            base_ref
+        ),
+        Message::CoverageDropTooLarge { max_drop } => format!(
+            " That's more than the {} point drop allowed.",
+            decimal(language, max_drop, 2, false)
+        ),
+        Message::CoverageWithoutBase {
+            coverage,
+            pipeline_id,
+            base_ref,
+        } => format!(
+            "📊 Coverage is **{}%** in pipeline {}. There's no successful pipeline with \
+             coverage on `{}` to compare it with.",
+            decimal(language, coverage, 2, false),
+            pipeline_id,
+            base_ref
+        ),
+        Message::TestsSummary {
+            total,
+            passed,
+            failed,
+            skipped,
+        } => format!(
+            "{} tests: {} passed, {} failed, {} skipped.",
+            total, passed, failed, skipped
+        ),
+        Message::TestsFailed { failed, total } => {
+            format!("{} of {} tests failed", failed, total)
+        }
+        Message::TestsPassed { total } => format!("All {} tests passed", total),
+        Message::UnannotatedFailures => "Failures which couldn't be annotated:".to_string(),
+    }
+}
+
+fn german(message: Message) -> String {
+    let language = Language::De;
+    match message {
+        Message::UnknownCommand => {
+            "Diesen Befehl kenne ich leider nicht.\n\nDanke für die Nachfrage 🥰".to_string()
+        }
+        Message::PermissionDenied { permission } => format!(
+            "Für diesen Befehl ist leider die Berechtigung {} für das Repository nötig.",
+            permission
+        ),
+        Message::RetrySent {
+            pipeline_id,
+            project_url,
+        } => format!(
+            "Pipeline [**{}**]({}/pipelines/{}) auf [**GitLab**]({}) wird **erneut gestartet**\n\n\
+             Einen schönen Tag noch! 😄",
+            pipeline_id, project_url, pipeline_id, project_url
+        ),
+        Message::MirroringPaused { repo } => format!(
+            "Die Spiegelung von {} nach GitLab ist pausiert, bis jemand `resume-mirroring` \
+             kommentiert.",
+            repo
+        ),
+        Message::MirroringResumed { repo, queued } => format!(
+            "Die Spiegelung von {} nach GitLab läuft wieder, {} zwischenzeitlich \
+             eingegangene PR-Ereignisse werden synchronisiert.",
+            repo, queued
+        ),
+        Message::MirroringNotPaused { repo } => {
+            format!("Die Spiegelung von {} war nicht pausiert.", repo)
+        }
+        Message::PushedToGitlab {
+            sha,
+            branch,
+            project_url,
+            pipeline,
+        } => format!(
+            "Commit `{}` wurde in den Branch `{}` auf [**GitLab**]({}) gepusht, {}.",
+            sha, branch, project_url, pipeline
+        ),
+        Message::PipelineIs {
+            pipeline_id,
+            project_url,
+            status,
+        } => format!(
+            "Pipeline [**{}**]({}/pipelines/{}) ist **{}**",
+            pipeline_id, project_url, pipeline_id, status
+        ),
+        Message::NoPipelineYet => "bisher wurde keine Pipeline gesehen".to_string(),
+        Message::DeployedToReviewApp { url } => {
+            format!("In der Review-App bereitgestellt: {}", url)
+        }
+        Message::NotPushedYet => "Ich habe diesen PR noch nicht nach GitLab gepusht.".to_string(),
+        Message::ReviewApp { sha, url } => format!(
+            "🔍 Die Review-App für `{}` ist unter {} bereitgestellt",
+            short_sha(sha),
+            url
+        ),
+        Message::LfsCopyFailed { sha, error } => format!(
+            "⚠️ Die Git-LFS-Objekte von `{}` konnten nicht nach GitLab kopiert werden, daher \
+             sieht die CI nur die LFS-Pointer-Dateien: {}",
+            short_sha(sha),
+            error
+        ),
+        Message::ProtectedPathsChanged { sha, files } => format!(
+            "🔒 `{}` wurde nicht nach GitLab gepusht, da es geschützte Pfade ändert, über die \
+             CI-Geheimnisse offengelegt werden könnten:\n\n{}\n\nEin Maintainer kann es \
+             prüfen und mit `approve {}` kommentieren, um es trotzdem zu synchronisieren. \
+             Spätere Pushes müssen erneut freigegeben werden.",
+            short_sha(sha),
+            file_list(files),
+            short_sha(sha)
+        ),
+        Message::HeadApproved { sha } => format!(
+            "`{}` ist freigegeben und wird nach GitLab synchronisiert.",
+            short_sha(sha)
+        ),
+        Message::ApprovalNeedsHead { head } => format!(
+            "Nichts wurde freigegeben: Der Head des PRs ist `{}`. Prüfe ihn und kommentiere \
+             dann `approve {}`.",
+            short_sha(head),
+            short_sha(head)
+        ),
+        Message::MergeRequestDescription {
+            repo,
+            number,
+            url,
+            author,
+        } => {
+            format!(
+            "Von LabHub gespiegelt aus {}{}. Der Branch dieses Merge Requests folgt dem Head des \
+             PRs, Änderungen bitte in den PR pushen.",
+            pr_link(repo, number, url),
+            author.map(|author| format!(", eröffnet von {}", author)).unwrap_or_default()
+        )
+        }
+        Message::MirroredComment { author, forge, url } => match url {
+            Some(url) => format!("**{}** hat auf {} [kommentiert]({}):", author, forge, url),
+            None => format!("**{}** hat auf {} kommentiert:", author, forge),
+        },
+        Message::FlakyRetry { job, pipeline_id } => format!(
+            "🔁 Job `{}` ist in Pipeline {} fehlgeschlagen und scheint instabil zu sein, daher \
+             wurde er einmal automatisch wiederholt. Das Ergebnis der Pipeline ist das der \
+             Wiederholung.",
+            job, pipeline_id
+        ),
+        Message::Slowdown {
+            took,
+            factor,
+            median,
+            samples,
+            base_ref,
+        } => format!(
+            "⚠️ Diese Pipeline hat {} gedauert, das {}-Fache des Medians von {} der letzten {} \
+             Pipelines auf `{}`.",
+            took,
+            decimal(language, factor, 1, false),
+            median,
+            samples,
+            base_ref
+        ),
+        Message::PipelineSummary {
+            emoji,
+            pipeline_id,
+            pipeline_url,
+            outcome,
+            sha,
+        } => format!(
+            "### {} Pipeline [#{}]({}) für `{}` {}",
+            emoji,
+            pipeline_id,
+            pipeline_url,
+            short_sha(sha),
+            outcome_de(outcome)
+        ),
+        Message::TookDuration { duration } => format!(" nach {}", duration),
+        Message::JobColumns => "| Stage | Job | Status | Dauer | Artefakte |".to_string(),
+        Message::AllowedToFail => "erlaubt".to_string(),
+        Message::Download => "herunterladen".to_string(),
+        Message::Artifacts { pipeline_id, sha } => format!(
+            "📦 Artefakte der Pipeline {} für `{}`:",
+            pipeline_id,
+            short_sha(sha)
+        ),
+        Message::ArtifactsExpire { date } => format!("läuft ab am {}", date),
+        Message::CoverageCompared {
+            emoji,
+            coverage,
+            pipeline_id,
+            delta,
+            base_coverage,
+            base_ref,
+        } => format!(
+            "{} Die Testabdeckung liegt in Pipeline {} bei **{} %**, {} Punkte gegenüber {} % \
+             auf `{}`.",
+            emoji,
+            pipeline_id,
+            decimal(language, coverage, 2, false),
+            decimal(language, delta, 2, true),
+            decimal(language, base_coverage, 2, false),
+            base_ref
+        ),
+        Message::CoverageDropTooLarge { max_drop } => format!(
+            " Das ist mehr als der erlaubte Rückgang von {} Punkten.",
+            decimal(language, max_drop, 2, false)
+        ),
+        Message::CoverageWithoutBase {
+            coverage,
+            pipeline_id,
+            base_ref,
+        } => format!(
+            "📊 Die Testabdeckung liegt in Pipeline {} bei **{} %**. Auf `{}` gibt es keine \
+             erfolgreiche Pipeline mit Testabdeckung zum Vergleich.",
+            pipeline_id,
+            decimal(language, coverage, 2, false),
+            base_ref
+        ),
+        Message::TestsSummary {
+            total,
+            passed,
+            failed,
+            skipped,
+        } => format!(
+            "{} Tests: {} bestanden, {} fehlgeschlagen, {} übersprungen.",
+            total, passed, failed, skipped
+        ),
+        Message::TestsFailed { failed, total } => {
+            format!("{} von {} Tests fehlgeschlagen", failed, total)
+        }
+        Message::TestsPassed { total } => format!("Alle {} Tests bestanden", total),
+        Message::UnannotatedFailures => {
+            "Fehlschläge, die nicht annotiert werden konnten:".to_string()
+        }
+    }
+}
+
+/// Returns the text of a message in a language
+pub fn text(language: Language, message: Message) -> String {
+    match language {
+        Language::En => english(message),
+        Language::De => german(message),
+    }
+}
+
+/// Returns the text of a message in the language of a GitHub repo
+pub fn for_repo(github_repo: &str, message: Message) -> String {
+    text(config::language_for_repo(github_repo), message)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_text() {
+        let message = Message::CoverageCompared {
+            emoji: "📉",
+            coverage: 81.0,
+            pipeline_id: 12,
+            delta: -0.25,
+            base_coverage: 81.25,
+            base_ref: "master",
+        };
+        assert_eq!(
+            text(Language::En, message),
+            "📉 Coverage is **81.00%** in pipeline 12, -0.25 points from 81.25% on `master`."
+        );
+        assert_eq!(
+            text(Language::De, message),
+            "📉 Die Testabdeckung liegt in Pipeline 12 bei **81,00 %**, -0,25 Punkte gegenüber \
+             81,25 % auf `master`."
+        );
+        let message = Message::PipelineSummary {
+            emoji: "✅",
+            pipeline_id: 7,
+            pipeline_url: "https://gitlab.com/a/b/-/pipelines/7",
+            outcome: "success",
+            sha: "a91957a858320c0e",
+        };
+        assert_eq!(
+            text(Language::De, message),
+            "### ✅ Pipeline [#7](https://gitlab.com/a/b/-/pipelines/7) für `a91957a8` war \
+             erfolgreich"
+        );
+        let message = Message::MergeRequestDescription {
+            repo: "octocat/hello-world",
+            number: 12,
+            url: Some("https://github.com/octocat/hello-world/pull/12"),
+            author: Some("alice"),
+        };
+        assert_eq!(
+            text(Language::En, message),
+            "Mirrored by LabHub from \
+             [octocat/hello-world#12](https://github.com/octocat/hello-world/pull/12), opened by \
+             alice. This merge request's branch follows the PR's head, so push to the PR to \
+             update it."
+        );
+        let message = Message::MergeRequestDescription {
+            repo: "octocat/hello-world",
+            number: 12,
+            url: None,
+            author: None,
+        };
+        assert!(text(Language::De, message)
+            .starts_with("Von LabHub gespiegelt aus octocat/hello-world#12. Der Branch"));
+    }
+}
@@ -0,0 +1,309 @@
+use crate::api::{etag_cache, throttle};
+use crate::config;
+use crate::disk;
+
+use log::{info, warn};
+use std::collections::{BTreeMap, VecDeque};
+use std::fmt::Write;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::Duration;
+
+static PR_SYNCS_SUCCEEDED: AtomicU64 = AtomicU64::new(0);
+static PR_SYNCS_FAILED: AtomicU64 = AtomicU64::new(0);
+static PR_SYNCS_SKIPPED: AtomicU64 = AtomicU64::new(0);
+static SLO_BREACHED: AtomicBool = AtomicBool::new(false);
+static GITHUB_DUPLICATES: AtomicU64 = AtomicU64::new(0);
+static GITLAB_DUPLICATES: AtomicU64 = AtomicU64::new(0);
+static DROPPED_WEBHOOKS: AtomicU64 = AtomicU64::new(0);
+static PANICS: AtomicU64 = AtomicU64::new(0);
+
+lazy_static! {
+    /// Latencies of the most recent PR syncs, with `None` for syncs that
+    /// failed, which count against the SLO
+    static ref SYNC_LATENCIES: Mutex<VecDeque<Option<Duration>>> = Mutex::new(VecDeque::new());
+    /// Failed PR syncs by the kind of error, see [`crate::errors::GitError::kind`]
+    static ref PR_SYNC_FAILURE_KINDS: Mutex<BTreeMap<&'static str, u64>> =
+        Mutex::new(BTreeMap::new());
+    /// Bytes of webhook bodies read, by route
+    static ref BODY_BYTES: Mutex<BTreeMap<&'static str, u64>> = Mutex::new(BTreeMap::new());
+    /// Requests turned away for their body size, by route
+    static ref OVERSIZED_BODIES: Mutex<BTreeMap<&'static str, u64>> =
+        Mutex::new(BTreeMap::new());
+}
+
+/// Current compliance with the configured PR sync latency SLO
+#[derive(Debug, Serialize, PartialEq)]
+pub struct SloStatus {
+    pub latency_target_secs: u64,
+    pub objective: f64,
+    /// Number of PR events measured
+    pub samples: usize,
+    /// Fraction of measured events mirrored within the target, if any were
+    /// measured
+    pub compliance: Option<f64>,
+    pub breached: bool,
+}
+
+fn slo_status_for(samples: &VecDeque<Option<Duration>>, slo: &config::Slo) -> SloStatus {
+    let target = Duration::from_secs(slo.latency_target_secs);
+    let within_target = samples
+        .iter()
+        .filter(|latency| matches!(latency, Some(latency) if *latency <= target))
+        .count();
+    let compliance = if samples.is_empty() {
+        None
+    } else {
+        Some(within_target as f64 / samples.len() as f64)
+    };
+    SloStatus {
+        latency_target_secs: slo.latency_target_secs,
+        objective: slo.objective,
+        samples: samples.len(),
+        compliance,
+        breached: samples.len() >= slo.min_samples && compliance.is_some_and(|c| c < slo.objective),
+    }
+}
+
+pub fn slo_status() -> SloStatus {
+    slo_status_for(&SYNC_LATENCIES.lock().unwrap(), &config::CONFIG.slo)
+}
+
+fn record_sync_latency(latency: Option<Duration>) {
+    let status = {
+        let mut samples = SYNC_LATENCIES.lock().unwrap();
+        samples.push_back(latency);
+        while samples.len() > config::CONFIG.slo.window_size {
+            samples.pop_front();
+        }
+        slo_status_for(&samples, &config::CONFIG.slo)
+    };
+    if SLO_BREACHED.swap(status.breached, Ordering::Relaxed) != status.breached {
+        let compliance = status.compliance.unwrap_or_default() * 100.0;
+        if status.breached {
+            warn!(
+                "PR sync latency SLO breached: {:.1}% of the last {} PR events were mirrored within {}s, objective is {:.1}%",
+                compliance,
+                status.samples,
+                status.latency_target_secs,
+                status.objective * 100.0
+            );
+        } else {
+            info!(
+                "PR sync latency SLO recovered: {:.1}% of the last {} PR events were mirrored within {}s",
+                compliance, status.samples, status.latency_target_secs
+            );
+        }
+    }
+}
+
+/// Records a PR event which was mirrored to GitLab `latency` after it was
+/// received
+pub fn record_pr_sync(latency: Duration) {
+    PR_SYNCS_SUCCEEDED.fetch_add(1, Ordering::Relaxed);
+    record_sync_latency(Some(latency));
+}
+
+/// Records a PR event which couldn't be mirrored, because of an error of
+/// `kind`
+pub fn record_pr_sync_failure(kind: &'static str) {
+    PR_SYNCS_FAILED.fetch_add(1, Ordering::Relaxed);
+    *PR_SYNC_FAILURE_KINDS
+        .lock()
+        .unwrap()
+        .entry(kind)
+        .or_default() += 1;
+    record_sync_latency(None);
+}
+
+/// Records a PR event which wasn't mirrored because a kill switch is
+/// engaged. It doesn't count against the SLO.
+pub fn record_pr_sync_skipped() {
+    PR_SYNCS_SKIPPED.fetch_add(1, Ordering::Relaxed);
+}
+
+/// Records a webhook or PR sync which panicked
+pub fn record_panic() {
+    PANICS.fetch_add(1, Ordering::Relaxed);
+}
+
+/// Records a webhook which was skipped as already handled, by `source`
+/// ("github" or "gitlab")
+pub fn record_duplicate_delivery(source: &str) {
+    match source {
+        "github" => GITHUB_DUPLICATES.fetch_add(1, Ordering::Relaxed),
+        _ => GITLAB_DUPLICATES.fetch_add(1, Ordering::Relaxed),
+    };
+}
+
+/// Records a GitHub webhook dropped because of its repo, see
+/// [`config::Repos`]
+pub fn record_dropped_webhook() {
+    DROPPED_WEBHOOKS.fetch_add(1, Ordering::Relaxed);
+}
+
+/// Records a webhook body of `bytes` read for `route`
+pub fn record_body(route: &'static str, bytes: usize) {
+    *BODY_BYTES.lock().unwrap().entry(route).or_default() += bytes as u64;
+}
+
+/// Records a request to `route` turned away for the size of its body
+pub fn record_oversized_body(route: &'static str) {
+    *OVERSIZED_BODIES.lock().unwrap().entry(route).or_default() += 1;
+}
+
+/// Renders the metrics in the Prometheus text format
+pub fn render() -> String {
+    let status = slo_status();
+    let mut out = String::new();
+    let _ = writeln!(out, "# TYPE labhub_pr_syncs_total counter");
+    let _ = writeln!(
+        out,
+        "labhub_pr_syncs_total{{result=\"success\"}} {}",
+        PR_SYNCS_SUCCEEDED.load(Ordering::Relaxed)
+    );
+    let _ = writeln!(
+        out,
+        "labhub_pr_syncs_total{{result=\"failure\"}} {}",
+        PR_SYNCS_FAILED.load(Ordering::Relaxed)
+    );
+    let _ = writeln!(
+        out,
+        "labhub_pr_syncs_total{{result=\"skipped\"}} {}",
+        PR_SYNCS_SKIPPED.load(Ordering::Relaxed)
+    );
+    let _ = writeln!(out, "# TYPE labhub_pr_sync_failures_total counter");
+    for (kind, count) in PR_SYNC_FAILURE_KINDS.lock().unwrap().iter() {
+        let _ = writeln!(
+            out,
+            "labhub_pr_sync_failures_total{{kind=\"{}\"}} {}",
+            kind, count
+        );
+    }
+    let _ = writeln!(out, "# TYPE labhub_duplicate_webhooks_total counter");
+    let _ = writeln!(
+        out,
+        "labhub_duplicate_webhooks_total{{source=\"github\"}} {}",
+        GITHUB_DUPLICATES.load(Ordering::Relaxed)
+    );
+    let _ = writeln!(
+        out,
+        "labhub_duplicate_webhooks_total{{source=\"gitlab\"}} {}",
+        GITLAB_DUPLICATES.load(Ordering::Relaxed)
+    );
+    let _ = writeln!(out, "# TYPE labhub_dropped_webhooks_total counter");
+    let _ = writeln!(
+        out,
+        "labhub_dropped_webhooks_total {}",
+        DROPPED_WEBHOOKS.load(Ordering::Relaxed)
+    );
+    let _ = writeln!(out, "# TYPE labhub_request_body_bytes_total counter");
+    for (route, bytes) in BODY_BYTES.lock().unwrap().iter() {
+        let _ = writeln!(
+            out,
+            "labhub_request_body_bytes_total{{route=\"{}\"}} {}",
+            route, bytes
+        );
+    }
+    let _ = writeln!(out, "# TYPE labhub_oversized_requests_total counter");
+    for (route, count) in OVERSIZED_BODIES.lock().unwrap().iter() {
+        let _ = writeln!(
+            out,
+            "labhub_oversized_requests_total{{route=\"{}\"}} {}",
+            route, count
+        );
+    }
+    let _ = writeln!(out, "# TYPE labhub_panics_total counter");
+    let _ = writeln!(
+        out,
+        "labhub_panics_total {}",
+        PANICS.load(Ordering::Relaxed)
+    );
+    if let Some(compliance) = status.compliance {
+        let _ = writeln!(out, "# TYPE labhub_pr_sync_slo_compliance gauge");
+        let _ = writeln!(out, "labhub_pr_sync_slo_compliance {}", compliance);
+    }
+    let _ = writeln!(out, "# TYPE labhub_pr_sync_slo_breached gauge");
+    let _ = writeln!(
+        out,
+        "labhub_pr_sync_slo_breached {}",
+        u8::from(status.breached)
+    );
+    let upstreams = [
+        ("github", &*throttle::GITHUB),
+        ("gitlab", &*throttle::GITLAB),
+    ];
+    let _ = writeln!(out, "# TYPE labhub_api_rate_limit_remaining gauge");
+    for (upstream, throttle) in upstreams {
+        if let Some(remaining) = throttle.remaining() {
+            let _ = writeln!(
+                out,
+                "labhub_api_rate_limit_remaining{{upstream=\"{}\"}} {}",
+                upstream, remaining
+            );
+        }
+    }
+    let _ = writeln!(out, "# TYPE labhub_api_rate_limited_total counter");
+    for (upstream, throttle) in upstreams {
+        let _ = writeln!(
+            out,
+            "labhub_api_rate_limited_total{{upstream=\"{}\"}} {}",
+            upstream,
+            throttle.rate_limited()
+        );
+    }
+    let _ = writeln!(out, "# TYPE labhub_github_etag_hits_total counter");
+    let _ = writeln!(
+        out,
+        "labhub_github_etag_hits_total {}",
+        etag_cache::GITHUB.hits()
+    );
+    if let Ok(free_bytes) = disk::free_bytes() {
+        let _ = writeln!(out, "# TYPE labhub_disk_free_bytes gauge");
+        let _ = writeln!(out, "labhub_disk_free_bytes {}", free_bytes);
+    }
+    let (cached_repos, cache_bytes) = disk::cache_gauges();
+    let _ = writeln!(out, "# TYPE labhub_repo_cache_bytes gauge");
+    let _ = writeln!(out, "labhub_repo_cache_bytes {}", cache_bytes);
+    let _ = writeln!(out, "# TYPE labhub_repo_cache_repos gauge");
+    let _ = writeln!(out, "labhub_repo_cache_repos {}", cached_repos);
+    out
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_slo_status() {
+        let slo = config::Slo {
+            latency_target_secs: 60,
+            objective: 0.9,
+            window_size: 10,
+            min_samples: 4,
+        };
+        let mut samples = VecDeque::new();
+        assert_eq!(slo_status_for(&samples, &slo).compliance, None);
+
+        samples.extend([
+            Some(Duration::from_secs(5)),
+            None,
+            Some(Duration::from_secs(61)),
+        ]);
+        let status = slo_status_for(&samples, &slo);
+        assert_eq!(status.samples, 3);
+        assert_eq!(status.compliance, Some(1.0 / 3.0));
+        // Not enough samples to alert yet
+        assert!(!status.breached);
+
+        samples.push_back(Some(Duration::from_secs(60)));
+        let status = slo_status_for(&samples, &slo);
+        assert_eq!(status.compliance, Some(0.5));
+        assert!(status.breached);
+
+        let mut samples: VecDeque<_> = (0..10).map(|_| Some(Duration::from_secs(1))).collect();
+        samples.push_back(None);
+        let status = slo_status_for(&samples, &slo);
+        assert!(!status.breached);
+    }
+}
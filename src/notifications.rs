@@ -0,0 +1,120 @@
+//! Notifying maintainers of PR syncs, in particular those which failed for
+//! good, so they find out without going through the logs. Each backend is
+//! configured with the events it's sent.
+use crate::api;
+use crate::api::{matrix_client, webhook_client};
+use crate::config::{self, NotificationEvent};
+use crate::forge::ForgePullRequest;
+
+use log::error;
+use std::time::Duration;
+use tokio::time::timeout;
+
+/// How long sending to each backend may take, so one which doesn't answer
+/// doesn't pile up notifications
+const SEND_TIMEOUT: Duration = Duration::from_secs(10);
+
+fn sync_failure_message(pr: &dyn ForgePullRequest, error: &str) -> String {
+    format!(
+        "⚠️ LabHub couldn't mirror {}#{} ({} PR, action={}): {}",
+        pr.base_full_name(),
+        pr.number(),
+        pr.forge().name(),
+        pr.action(),
+        error
+    )
+}
+
+fn sync_success_message(pr: &dyn ForgePullRequest) -> String {
+    format!(
+        "✅ LabHub mirrored {}#{} ({} PR, action={}) at {}",
+        pr.base_full_name(),
+        pr.number(),
+        pr.forge().name(),
+        pr.action(),
+        &pr.head_sha()[..pr.head_sha().len().min(8)]
+    )
+}
+
+/// Sends a message to every backend configured for the event, logging
+/// failures
+async fn notify(event: NotificationEvent, message: &str) {
+    let notifications = &config::CONFIG.notifications;
+    let matrix = notifications
+        .matrix
+        .as_ref()
+        .filter(|matrix| matrix.events.contains(&event));
+    let webhooks: Vec<&config::NotificationWebhook> = notifications
+        .webhooks
+        .iter()
+        .filter(|webhook| webhook.events.contains(&event))
+        .collect();
+    if matrix.is_none() && webhooks.is_empty() {
+        return;
+    }
+    let client = match api::new_client() {
+        Ok(client) => client,
+        Err(err) => {
+            error!("Error sending notifications: {:?}", err);
+            return;
+        }
+    };
+    if let Some(matrix) = matrix {
+        match timeout(
+            SEND_TIMEOUT,
+            matrix_client::send_notice(&client, matrix, message),
+        )
+        .await
+        {
+            Ok(Ok(_)) => {}
+            Ok(Err(err)) => error!("Error sending Matrix notification: {:?}", err),
+            Err(_) => error!("Timed out sending Matrix notification"),
+        }
+    }
+    for webhook in webhooks {
+        match timeout(
+            SEND_TIMEOUT,
+            webhook_client::post_message(&client, &webhook.url, message),
+        )
+        .await
+        {
+            Ok(Ok(_)) => {}
+            Ok(Err(err)) => error!("Error sending webhook notification: {:?}", err),
+            Err(_) => error!("Timed out sending webhook notification"),
+        }
+    }
+}
+
+/// Notifies of a PR sync, including its push to GitLab, which failed and
+/// won't be retried, in the background
+pub fn sync_failed(pr: &dyn ForgePullRequest, error: &str) {
+    let message = sync_failure_message(pr, error);
+    tokio::spawn(async move { notify(NotificationEvent::SyncFailed, &message).await });
+}
+
+/// Notifies of a PR which was mirrored to GitLab, in the background
+pub fn sync_succeeded(pr: &dyn ForgePullRequest) {
+    let message = sync_success_message(pr);
+    tokio::spawn(async move { notify(NotificationEvent::SyncSucceeded, &message).await });
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::api::models::github;
+    use crate::testing::read_testdata_to_string;
+
+    #[test]
+    fn test_sync_messages() {
+        let pr: github::PullRequest =
+            serde_json::from_str(&read_testdata_to_string("github_open_pr_forked.json")).unwrap();
+        assert_eq!(
+            sync_failure_message(&pr, "Error cloning repo"),
+            "⚠️ LabHub couldn't mirror brndnmtthws/labhub-test#5 (github PR, action=opened): \
+             Error cloning repo"
+        );
+        assert!(sync_success_message(&pr).starts_with(
+            "✅ LabHub mirrored brndnmtthws/labhub-test#5 (github PR, action=opened) at "
+        ));
+    }
+}
@@ -0,0 +1,112 @@
+//! Pausing mirroring per repo, e.g. while its GitLab project is being
+//! migrated. Events for paused repos are held until mirroring resumes, or
+//! dropped, per the `[pause]` config. With replicas sharing a Redis, held
+//! events are kept there, so whichever replica resumes the repo queues them.
+use crate::cluster;
+use crate::config;
+use crate::errors::GitError;
+use crate::forge::ForgePullRequest;
+use crate::queue;
+use crate::repo_name;
+use crate::state;
+
+use log::{error, info};
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+lazy_static! {
+    /// Events held for paused repos, by repo lookup key
+    static ref HELD: Mutex<HashMap<String, Vec<Box<dyn ForgePullRequest>>>> =
+        Mutex::new(HashMap::new());
+}
+
+pub fn pause(repo: &str, paused_by: &str) -> Result<(), GitError> {
+    let repo = repo_name::canonicalize(repo)?;
+    info!("Pausing mirroring of {} for {}", repo, paused_by);
+    state::pause_repo(&repo, paused_by)
+}
+
+/// Resumes mirroring of a repo and queues the events held meanwhile,
+/// returning how many there were, or `None` if the repo wasn't paused
+pub fn resume(repo: &str) -> Result<Option<usize>, GitError> {
+    let repo = repo_name::canonicalize(repo)?;
+    if !state::resume_repo(&repo)? {
+        return Ok(None);
+    }
+    let mut held = HELD.lock().unwrap().remove(&repo).unwrap_or_default();
+    if cluster::enabled() {
+        held.extend(cluster::take_held(&repo)?);
+    }
+    info!(
+        "Resuming mirroring of {}, queueing {} held events",
+        repo,
+        held.len()
+    );
+    let count = held.len();
+    for pr in held {
+        queue::enqueue(pr);
+    }
+    Ok(Some(count))
+}
+
+/// Whether mirroring of a PR's repo is paused. Errors checking are logged,
+/// and the repo taken to be mirrored.
+pub(crate) fn is_paused(pr: &dyn ForgePullRequest) -> bool {
+    let repo = repo_name::lookup_key(pr.base_full_name());
+    state::is_repo_paused(&repo).unwrap_or_else(|err| {
+        error!("Error checking whether {} is paused: {:?}", repo, err);
+        false
+    })
+}
+
+/// Holds or drops the event if its repo is paused, otherwise hands it back
+pub(crate) fn intercept(pr: Box<dyn ForgePullRequest>) -> Option<Box<dyn ForgePullRequest>> {
+    if !is_paused(pr.as_ref()) {
+        return Some(pr);
+    }
+    let repo = repo_name::lookup_key(pr.base_full_name());
+    match config::CONFIG.pause.paused_events {
+        config::PausedEvents::Queue => {
+            info!("Mirroring of {} is paused, holding PR event", repo);
+            if cluster::enabled() {
+                match cluster::hold(&repo, pr.as_ref()) {
+                    Ok(()) => return None,
+                    Err(err) => error!(
+                        "Error holding PR event for all replicas, holding it here: {:?}",
+                        err
+                    ),
+                }
+            }
+            HELD.lock().unwrap().entry(repo).or_default().push(pr);
+        }
+        config::PausedEvents::Drop => {
+            info!("Mirroring of {} is paused, dropping PR event", repo);
+        }
+    }
+    None
+}
+
+/// Takes all held events, e.g. to store them on shutdown
+pub(crate) fn take_held() -> Vec<Box<dyn ForgePullRequest>> {
+    HELD.lock()
+        .unwrap()
+        .drain()
+        .flat_map(|(_, held)| held)
+        .collect()
+}
+
+/// Number of events held for each paused repo
+pub fn held_counts() -> HashMap<String, usize> {
+    let mut counts = if cluster::enabled() {
+        cluster::held_counts().unwrap_or_else(|err| {
+            error!("Error counting held PR events: {:?}", err);
+            HashMap::new()
+        })
+    } else {
+        HashMap::new()
+    };
+    for (repo, held) in HELD.lock().unwrap().iter() {
+        *counts.entry(repo.clone()).or_default() += held.len();
+    }
+    counts
+}
@@ -0,0 +1,92 @@
+//! Keeping the events which are still waiting to be handled across
+//! restarts. Webhook deliveries, PR syncs and pipelines are recorded in the
+//! state store as they happen, so the queue and the events held for paused
+//! repos are all that would otherwise be lost.
+use crate::cluster;
+use crate::config;
+use crate::forge::{self, ForgePullRequest};
+use crate::pause;
+use crate::queue;
+use crate::state;
+
+use log::{error, info, warn};
+use std::time::Duration;
+
+/// Events which were queued for syncing
+const QUEUED: &str = "queued";
+/// Events which were held for a paused repo
+const HELD: &str = "held";
+
+/// How long to wait for a running sync to finish on shutdown, within the 30
+/// seconds Kubernetes gives a pod to stop by default
+const SHUTDOWN_GRACE: Duration = Duration::from_secs(25);
+
+fn save(kind: &str, prs: Vec<Box<dyn ForgePullRequest>>) {
+    if prs.is_empty() {
+        return;
+    }
+    if config::CONFIG.state.database.is_none() {
+        warn!(
+            "Losing {} {} PR events, as no state database is configured",
+            prs.len(),
+            kind
+        );
+        return;
+    }
+    let events: Vec<String> = prs
+        .iter()
+        .filter_map(|pr| forge::store(pr.as_ref()))
+        .collect();
+    match state::save_pending_events(kind, &events) {
+        Ok(()) => info!("Saved {} {} PR events", events.len(), kind),
+        Err(err) => error!("Error saving {} PR events: {:?}", kind, err),
+    }
+}
+
+/// Stops the queue worker and saves the events which weren't handled yet,
+/// or hands them back to the other replicas
+pub async fn save_pending_events() {
+    cluster::stop();
+    let mut unhandled = queue::shutdown(SHUTDOWN_GRACE).await;
+    if cluster::enabled() {
+        unhandled = cluster::requeue(unhandled).await;
+    }
+    save(QUEUED, unhandled);
+    save(HELD, pause::take_held());
+}
+
+fn restore(kind: &str) -> Vec<Box<dyn ForgePullRequest>> {
+    let events = match state::take_saved_events(kind) {
+        Ok(events) => events,
+        Err(err) => {
+            error!("Error loading saved {} PR events: {:?}", kind, err);
+            return Vec::new();
+        }
+    };
+    if !events.is_empty() {
+        info!("Restoring {} {} PR events", events.len(), kind);
+    }
+    events
+        .iter()
+        .filter_map(|event| match forge::restore(event) {
+            Ok(pr) => Some(pr),
+            Err(err) => {
+                error!("Error restoring saved PR event: {:?}", err);
+                None
+            }
+        })
+        .collect()
+}
+
+/// Queues the events saved on the last shutdown again. Held events are held
+/// again if their repo is still paused.
+pub fn restore_pending_events() {
+    for pr in restore(QUEUED) {
+        queue::enqueue(pr);
+    }
+    for pr in restore(HELD) {
+        if let Some(pr) = pause::intercept(pr) {
+            queue::enqueue(pr);
+        }
+    }
+}
@@ -0,0 +1,150 @@
+//! Pipeline summary comments: when a pipeline finishes, its PR gets a single
+//! comment with the result of each job, grouped by stage, which is updated
+//! in place when the pipeline is re-run.
+use crate::api::models::gitlab;
+use crate::config;
+use crate::durations::format_duration;
+use crate::messages::{self, Message};
+
+use std::collections::BTreeMap;
+
+fn status_emoji(status: &str, allow_failure: bool) -> &'static str {
+    match status {
+        "success" => "✅",
+        "failed" if allow_failure => "⚠️",
+        "failed" => "❌",
+        "canceled" => "⏹️",
+        "skipped" => "⏭️",
+        "manual" => "✋",
+        _ => "⏳",
+    }
+}
+
+/// The latest run of each job, ordered by stage then name. Pipeline hooks
+/// list every run of a retried job.
+fn latest_jobs<'a>(
+    builds: &'a [gitlab::PipelineEventBuild],
+    stages: &[String],
+) -> Vec<&'a gitlab::PipelineEventBuild> {
+    let mut latest: BTreeMap<(usize, &str, &str), &gitlab::PipelineEventBuild> = BTreeMap::new();
+    for build in builds {
+        let stage = build.stage.as_deref().unwrap_or_default();
+        let name = build.name.as_deref().unwrap_or_default();
+        let position = stages
+            .iter()
+            .position(|s| s == stage)
+            .unwrap_or(stages.len());
+        let entry = latest.entry((position, stage, name)).or_insert(build);
+        if build.id > entry.id {
+            *entry = build;
+        }
+    }
+    latest.into_values().collect()
+}
+
+/// Renders the summary of a finished pipeline. `marker` is appended so the
+/// comment isn't treated as stale.
+pub fn comment_body(
+    event: &gitlab::PipelineEvent,
+    sha: &str,
+    marker: &str,
+    language: config::Language,
+) -> Option<String> {
+    let attributes = event.object_attributes.as_ref()?;
+    let project_url = event.project.as_ref()?.web_url.as_deref()?;
+    let id = attributes.id?;
+    let status = attributes.status.as_deref()?;
+    let mut body = messages::text(
+        language,
+        Message::PipelineSummary {
+            emoji: status_emoji(status, false),
+            pipeline_id: id,
+            pipeline_url: &format!("{}/-/pipelines/{}", project_url, id),
+            outcome: status,
+            sha,
+        },
+    );
+    if let Some(duration) = attributes.duration {
+        body.push_str(&messages::text(
+            language,
+            Message::TookDuration {
+                duration: &format_duration(duration as f64),
+            },
+        ));
+    }
+    body.push_str("\n\n");
+
+    let stages = attributes.stages.clone().unwrap_or_default();
+    let jobs = latest_jobs(event.builds.as_deref().unwrap_or_default(), &stages);
+    if !jobs.is_empty() {
+        body.push_str(&messages::text(language, Message::JobColumns));
+        body.push('\n');
+        body.push_str("| --- | --- | --- | --- | --- |\n");
+        for job in jobs {
+            let job_status = job.status.as_deref().unwrap_or("unknown");
+            let allow_failure = job.allow_failure.unwrap_or(false);
+            let duration = job.duration.map(format_duration).unwrap_or_default();
+            let artifacts = match (job.id, &job.artifacts_file) {
+                (
+                    Some(job_id),
+                    Some(gitlab::PipelineEventArtifactsFile {
+                        filename: Some(_), ..
+                    }),
+                ) => {
+                    format!(
+                        "[{}]({}/-/jobs/{}/artifacts/download)",
+                        messages::text(language, Message::Download),
+                        project_url,
+                        job_id
+                    )
+                }
+                _ => String::new(),
+            };
+            body.push_str(&format!(
+                "| {} | {} | {} {}{} | {} | {} |\n",
+                job.stage.as_deref().unwrap_or_default(),
+                job.name.as_deref().unwrap_or_default(),
+                status_emoji(job_status, allow_failure),
+                job_status,
+                if allow_failure && job_status == "failed" {
+                    format!(" ({})", messages::text(language, Message::AllowedToFail))
+                } else {
+                    String::new()
+                },
+                duration,
+                artifacts
+            ));
+        }
+        body.push('\n');
+    }
+    body.push_str(marker);
+    Some(body)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::testing::read_testdata_to_string;
+
+    #[test]
+    fn test_comment_body() {
+        let event: gitlab::PipelineEvent =
+            serde_json::from_str(&read_testdata_to_string("gitlab_pipeline_failed.json")).unwrap();
+        let sha = "a91957a858320c0e17f3a0eca7cfacbff50ea29a";
+        assert_eq!(
+            comment_body(&event, sha, "<!-- labhub:pipeline -->", config::Language::En).unwrap(),
+            "### ❌ Pipeline [#2366](https://gitlab.example.com/gitlab-org/gitlab-test/-/pipelines/2366) \
+             failed for `a91957a8` in 5m 30s\n\
+             \n\
+             | Stage | Job | Status | Duration | Artifacts |\n\
+             | --- | --- | --- | --- | --- |\n\
+             | build | compile | ✅ success | 1m 2s | \
+             [download](https://gitlab.example.com/gitlab-org/gitlab-test/-/jobs/1975/artifacts/download) |\n\
+             | test | lint | ⚠️ failed (allowed) | 5s |  |\n\
+             | test | rspec | ❌ failed | 23s |  |\n\
+             | deploy | review | ⏭️ skipped |  |  |\n\
+             \n\
+             <!-- labhub:pipeline -->"
+        );
+    }
+}
@@ -0,0 +1,179 @@
+//! Protected paths: a PR from an untrusted author which changes the CI
+//! config (or other configured paths) could use it to exfiltrate CI secrets,
+//! so with the `protected_paths` feature such heads are only pushed once a
+//! maintainer approves them with the `approve` command.
+use crate::api;
+use crate::api::github_client;
+use crate::config;
+use crate::errors::GitError;
+use crate::forge::{Forge, ForgePullRequest};
+use crate::repo_name;
+use crate::state;
+use crate::trust;
+
+use log::{info, warn};
+use regex::Regex;
+
+/// Listed in place of the files GitHub doesn't list, for PRs changing more
+/// than it lists, which are held as if they changed protected paths
+const TRUNCATED_FILES: &str = "(more files than GitHub lists, which may be protected)";
+
+fn pattern_regex(pattern: &str) -> Option<Regex> {
+    let pattern = pattern.trim_start_matches('/');
+    let mut regex = String::from("^");
+    let mut rest = pattern;
+    while let Some(i) = rest.find('*') {
+        regex.push_str(&regex::escape(&rest[..i]));
+        rest = &rest[i..];
+        if let Some(after) = rest.strip_prefix("**/") {
+            regex.push_str("(.*/)?");
+            rest = after;
+        } else if let Some(after) = rest.strip_prefix("**") {
+            regex.push_str(".*");
+            rest = after;
+        } else {
+            regex.push_str("[^/]*");
+            rest = &rest[1..];
+        }
+    }
+    regex.push_str(&regex::escape(rest));
+    // a directory matches everything in it
+    if !pattern.ends_with('/') {
+        regex.push('$');
+    }
+    Regex::new(&regex).ok()
+}
+
+/// Returns the files matching any of the protected path patterns
+pub fn protected_files(patterns: &[String], files: &[String]) -> Vec<String> {
+    let regexes: Vec<Regex> = patterns.iter().filter_map(|p| pattern_regex(p)).collect();
+    files
+        .iter()
+        .filter(|file| regexes.iter().any(|re| re.is_match(file)))
+        .cloned()
+        .collect()
+}
+
+async fn changed_files(pr: &dyn ForgePullRequest) -> Result<github_client::PullFiles, GitError> {
+    let (org, repo) = pr
+        .base_full_name()
+        .split_once('/')
+        .ok_or(GitError::BadRequest(format!(
+            "Invalid repo name {}",
+            pr.base_full_name()
+        )))?;
+    let client = api::new_client()?;
+    github_client::get_all_pull_files(&client, org, repo, pr.number()).await
+}
+
+/// Returns the protected files changed by a PR from an untrusted author, if
+/// its head needs approving before it's synced
+pub async fn needs_approval(pr: &dyn ForgePullRequest) -> Result<Option<Vec<String>>, GitError> {
+    let patterns = &config::CONFIG.trust.protected_paths;
+    if !config::feature_enabled(&config::Feature::ProtectedPaths)
+        || patterns.is_empty()
+        || trust::is_trusted(pr)
+    {
+        return Ok(None);
+    }
+    let repo = repo_name::lookup_key(pr.base_full_name());
+    if let Some(approval) = state::approval(&repo, pr.number(), pr.head_sha())? {
+        info!(
+            "Head {} of PR {} was approved by {}",
+            pr.head_sha(),
+            pr.number(),
+            approval.approved_by
+        );
+        return Ok(None);
+    }
+    if pr.forge() != Forge::GitHub {
+        // Only GitHub PRs can be inspected and approved
+        return Err(GitError::Config(format!(
+            "Not syncing untrusted {} PR {} of {}, as protected paths can only be checked \
+                 on GitHub",
+            pr.forge().name(),
+            pr.number(),
+            pr.base_full_name()
+        )));
+    }
+    let files = changed_files(pr).await?;
+    let mut protected = protected_files(patterns, &files.paths);
+    if files.truncated {
+        // The files GitHub doesn't list may be protected
+        warn!(
+            "PR {} of {} changes more files than GitHub lists, holding it as protected",
+            pr.number(),
+            pr.base_full_name()
+        );
+        protected.push(TRUNCATED_FILES.to_string());
+    }
+    Ok(Some(protected).filter(|protected| !protected.is_empty()))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_protected_files() {
+        let patterns: Vec<String> = [".gitlab-ci.yml", "ci/", "scripts/*.sh", "**/Dockerfile"]
+            .iter()
+            .map(|p| p.to_string())
+            .collect();
+        let files: Vec<String> = [
+            ".gitlab-ci.yml",
+            "docs/.gitlab-ci.yml",
+            "ci/deploy/template.yml",
+            "scripts/build.sh",
+            "scripts/nested/build.sh",
+            "Dockerfile",
+            "images/base/Dockerfile",
+            "src/main.rs",
+        ]
+        .iter()
+        .map(|f| f.to_string())
+        .collect();
+        assert_eq!(
+            protected_files(&patterns, &files),
+            [
+                ".gitlab-ci.yml",
+                "ci/deploy/template.yml",
+                "scripts/build.sh",
+                "Dockerfile",
+                "images/base/Dockerfile",
+            ]
+        );
+        assert!(protected_files(&[], &files).is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_truncated_files() {
+        use serde_json::json;
+        use wiremock::matchers::{method, path};
+        use wiremock::{Mock, MockServer, ResponseTemplate};
+
+        let server = MockServer::start().await;
+        let page: Vec<_> = (0..100)
+            .map(|i| json!({ "filename": format!("src/{}.rs", i) }))
+            .collect();
+        Mock::given(method("GET"))
+            .and(path("/repos/brndnmtthws/labhub/pulls/7/files"))
+            .respond_with(
+                ResponseTemplate::new(200)
+                    .insert_header("Link", "<https://api.github.com/next>; rel=\"next\"")
+                    .set_body_json(page),
+            )
+            .mount(&server)
+            .await;
+
+        let client = api::new_client().unwrap();
+        let files = api::with_base_url(
+            server.uri(),
+            github_client::get_all_pull_files(&client, "brndnmtthws", "labhub", 7),
+        )
+        .await
+        .unwrap();
+        assert_eq!(files.paths.len(), 3000);
+        assert!(files.truncated);
+    }
+}
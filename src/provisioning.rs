@@ -0,0 +1,153 @@
+//! Creates the GitLab project a PR is pushed to when it doesn't exist yet,
+//! with the settings of the `[provisioning]` section, so onboarding a repo
+//! only takes adding its mapping. Projects are created in the group of
+//! their path, which must exist, and checked once per process.
+use crate::api::{self, gitlab_client};
+use crate::config;
+use crate::errors::GitError;
+
+use log::info;
+use serde_json::json;
+use std::collections::HashSet;
+use std::sync::Mutex;
+
+lazy_static! {
+    /// GitLab projects known to exist
+    static ref EXISTING: Mutex<HashSet<String>> = Mutex::new(HashSet::new());
+}
+
+/// The attributes of a new project at `path`, in the namespace `namespace_id`
+fn project_attributes(
+    settings: &config::Provisioning,
+    namespace_id: i64,
+    path: &str,
+) -> serde_json::Value {
+    let mut attributes = json!({
+        "name": path,
+        "path": path,
+        "namespace_id": namespace_id,
+        "visibility": settings.visibility,
+        "shared_runners_enabled": settings.shared_runners_enabled,
+    });
+    if let Some(ci_config_path) = settings.ci_config_path.as_ref() {
+        attributes["ci_config_path"] = json!(ci_config_path);
+    }
+    if let Some(build_timeout) = settings.build_timeout {
+        attributes["build_timeout"] = json!(build_timeout);
+    }
+    attributes
+}
+
+async fn ensure_project_with(
+    client: &reqwest::Client,
+    project: &str,
+    settings: &config::Provisioning,
+) -> Result<(), GitError> {
+    if gitlab_client::get_project(client, project).await?.is_some() {
+        return Ok(());
+    }
+    let (namespace, path) = project.rsplit_once('/').ok_or(GitError::Config(format!(
+        "Invalid GitLab project {}",
+        project
+    )))?;
+    let instance = config::gitlab_for_project(project);
+    let namespace_id = gitlab_client::get_namespace_id(client, instance, namespace).await?;
+    let attributes = project_attributes(settings, namespace_id, path);
+    match gitlab_client::create_project(client, instance, &attributes).await {
+        Ok(created) => {
+            info!(
+                "Created GitLab project {} with id={:?}",
+                project, created.id
+            );
+            Ok(())
+        }
+        // Another sync may have created it in the meantime
+        Err(err) => match gitlab_client::get_project(client, project).await {
+            Ok(Some(_)) => Ok(()),
+            _ => Err(err),
+        },
+    }
+}
+
+/// Creates `project` if it doesn't exist and `[provisioning]` is configured
+pub async fn ensure_project(project: &str) -> Result<(), GitError> {
+    let settings = match config::CONFIG.provisioning.as_ref() {
+        Some(settings) => settings,
+        None => return Ok(()),
+    };
+    if EXISTING.lock().unwrap().contains(project) {
+        return Ok(());
+    }
+    let client = api::new_client()?;
+    ensure_project_with(&client, project, settings).await?;
+    EXISTING.lock().unwrap().insert(project.to_string());
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use wiremock::matchers::{body_partial_json, method, path};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    #[test]
+    fn test_project_attributes() {
+        let settings = config::Provisioning {
+            ci_config_path: Some("ci/gitlab.yml".to_string()),
+            ..config::Provisioning::default()
+        };
+        assert_eq!(
+            project_attributes(&settings, 5, "labhub"),
+            json!({
+                "name": "labhub",
+                "path": "labhub",
+                "namespace_id": 5,
+                "visibility": "private",
+                "shared_runners_enabled": true,
+                "ci_config_path": "ci/gitlab.yml",
+            })
+        );
+    }
+
+    #[tokio::test]
+    async fn test_ensure_project() {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/api/v4/projects/brndnmtthws-oss%2Fnew"))
+            .respond_with(ResponseTemplate::new(404))
+            .mount(&server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path("/api/v4/namespaces/brndnmtthws-oss"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({ "id": 5 })))
+            .mount(&server)
+            .await;
+        Mock::given(method("POST"))
+            .and(path("/api/v4/projects"))
+            .and(body_partial_json(
+                json!({ "path": "new", "namespace_id": 5 }),
+            ))
+            .respond_with(ResponseTemplate::new(201).set_body_json(json!({ "id": 12 })))
+            .expect(1)
+            .mount(&server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path("/api/v4/projects/brndnmtthws-oss%2Flabhub"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({ "id": 1 })))
+            .mount(&server)
+            .await;
+
+        let client = api::new_client().unwrap();
+        let settings = config::Provisioning::default();
+        api::with_base_url(server.uri(), async {
+            ensure_project_with(&client, "brndnmtthws-oss/new", &settings)
+                .await
+                .unwrap();
+            // existing projects are left alone
+            ensure_project_with(&client, "brndnmtthws-oss/labhub", &settings)
+                .await
+                .unwrap();
+        })
+        .await;
+    }
+}
@@ -0,0 +1,539 @@
+use crate::cluster;
+use crate::config;
+use crate::errors;
+use crate::forge::ForgePullRequest;
+use crate::health;
+use crate::metrics;
+use crate::notifications;
+use crate::repo_name;
+use crate::sentry;
+use crate::sync;
+
+use futures::FutureExt;
+use log::{error, info, warn};
+use std::panic::AssertUnwindSafe;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use tokio::sync::mpsc::{unbounded_channel, UnboundedReceiver, UnboundedSender};
+use tokio::sync::Notify;
+use tokio::time::{sleep, timeout, timeout_at};
+
+/// How long after the shutdown deadline to wait for the worker to stop
+const STOP_MARGIN: Duration = Duration::from_secs(1);
+
+/// PR syncs waiting to be processed. Events are queued by the webhook
+/// handlers and processed one at a time by [`run_worker`], which holds
+/// them while GitLab is unreachable instead of dropping them.
+struct Queue {
+    sender: UnboundedSender<Job>,
+    receiver: Mutex<Option<UnboundedReceiver<Job>>>,
+    depth: AtomicUsize,
+}
+
+#[derive(Debug)]
+struct Job {
+    pr: Box<dyn ForgePullRequest>,
+    /// When the event was received, for measuring sync latency
+    received: Instant,
+    /// The event as taken from the shared queue, to acknowledge it once
+    /// it's handled, see [`cluster::acknowledge`]
+    shared: Option<String>,
+}
+
+lazy_static! {
+    static ref QUEUE: Queue = {
+        let (sender, receiver) = unbounded_channel();
+        Queue {
+            sender,
+            receiver: Mutex::new(Some(receiver)),
+            depth: AtomicUsize::new(0),
+        }
+    };
+    /// Tells the worker to stop, see [`shutdown`]
+    static ref SHUTDOWN: Notify = Notify::new();
+    /// Until when a sync running at shutdown may take to finish
+    static ref SHUTDOWN_DEADLINE: Mutex<Option<Instant>> = Mutex::new(None);
+    /// Set by the worker once it has stopped
+    static ref STOPPED: Notify = Notify::new();
+    /// Jobs the worker hadn't handled when it stopped
+    static ref LEFTOVER: Mutex<Vec<Job>> = Mutex::new(Vec::new());
+}
+
+/// Queues a PR event for syncing, on the queue shared by all replicas if
+/// there's one
+pub fn enqueue(pr: Box<dyn ForgePullRequest>) {
+    if cluster::enabled() {
+        cluster::share(pr);
+    } else {
+        enqueue_local(pr);
+    }
+}
+
+/// Queues a PR event for this replica's worker
+pub(crate) fn enqueue_local(pr: Box<dyn ForgePullRequest>) {
+    enqueue_job(pr, None);
+}
+
+/// Queues a PR event taken from the shared queue for this replica's
+/// worker, which acknowledges it once it's handled
+pub(crate) fn enqueue_shared(pr: Box<dyn ForgePullRequest>, stored: String) {
+    enqueue_job(pr, Some(stored));
+}
+
+fn enqueue_job(pr: Box<dyn ForgePullRequest>, shared: Option<String>) {
+    QUEUE.depth.fetch_add(1, Ordering::Relaxed);
+    let job = Job {
+        pr,
+        received: Instant::now(),
+        shared,
+    };
+    if QUEUE.sender.send(job).is_err() {
+        QUEUE.depth.fetch_sub(1, Ordering::Relaxed);
+        error!("PR queue is closed, dropping event");
+    }
+}
+
+/// Acknowledges the jobs which were taken from the shared queue
+async fn acknowledge(jobs: &[Job]) {
+    for job in jobs {
+        if let Some(stored) = &job.shared {
+            cluster::acknowledge(stored).await;
+        }
+    }
+}
+
+/// Number of PR syncs queued or in progress on this replica
+pub fn depth() -> usize {
+    QUEUE.depth.load(Ordering::Relaxed)
+}
+
+/// Collects PR closes arriving within `window` of each other into one
+/// batch of at most `max` jobs, so that a stale bot closing dozens of PRs
+/// results in a few pushes rather than one per PR. Returns the batch, and
+/// the job which ended it if that isn't a close.
+async fn collect_closes(
+    receiver: &mut UnboundedReceiver<Job>,
+    first: Job,
+    window: Duration,
+    max: usize,
+) -> (Vec<Job>, Option<Job>) {
+    let mut batch = vec![first];
+    while batch.len() < max {
+        match timeout(window, receiver.recv()).await {
+            Ok(Some(job)) if job.pr.action() == "closed" => batch.push(job),
+            Ok(Some(job)) => return (batch, Some(job)),
+            Ok(None) | Err(_) => break,
+        }
+    }
+    (batch, None)
+}
+
+fn is_same_pr(a: &dyn ForgePullRequest, b: &dyn ForgePullRequest) -> bool {
+    a.forge() == b.forge()
+        && a.number() == b.number()
+        && a.base_full_name().eq_ignore_ascii_case(b.base_full_name())
+}
+
+/// Pushes to PRs waiting out their debounce window. While more
+/// `synchronize` events for the same PR arrive within `window` of the
+/// previous one, only the latest is kept, so a burst of force-pushes results
+/// in a single sync of the final head. Other PRs' events are handled
+/// meanwhile.
+#[derive(Default)]
+struct PendingPushes {
+    pushes: Vec<(Job, Instant)>,
+}
+
+impl PendingPushes {
+    /// Holds a push until `deadline`, replacing the one pending for the same
+    /// PR, which is returned
+    fn add(&mut self, job: Job, deadline: Instant) -> Option<Job> {
+        let pending = self
+            .pushes
+            .iter_mut()
+            .find(|(pending, _)| is_same_pr(&*pending.pr, &*job.pr));
+        match pending {
+            Some((pending, pending_deadline)) => {
+                *pending_deadline = deadline;
+                // measure latency from the first push of the burst
+                let received = pending.received;
+                Some(std::mem::replace(
+                    pending,
+                    Job {
+                        pr: job.pr,
+                        received,
+                        shared: job.shared,
+                    },
+                ))
+            }
+            None => {
+                self.pushes.push((job, deadline));
+                None
+            }
+        }
+    }
+
+    /// Takes the push whose window ended first, if one has by `now`
+    fn take_due(&mut self, now: Instant) -> Option<Job> {
+        let due = self
+            .pushes
+            .iter()
+            .enumerate()
+            .filter(|(_, (_, deadline))| *deadline <= now)
+            .min_by_key(|(_, (_, deadline))| *deadline)
+            .map(|(index, _)| index)?;
+        Some(self.pushes.remove(due).0)
+    }
+
+    /// Takes the push pending for a PR, so its other events don't overtake it
+    fn take_pr(&mut self, pr: &dyn ForgePullRequest) -> Option<Job> {
+        let index = self
+            .pushes
+            .iter()
+            .position(|(pending, _)| is_same_pr(&*pending.pr, pr))?;
+        Some(self.pushes.remove(index).0)
+    }
+
+    fn next_deadline(&self) -> Option<Instant> {
+        self.pushes.iter().map(|(_, deadline)| *deadline).min()
+    }
+
+    fn drain(&mut self) -> Vec<Job> {
+        self.pushes.drain(..).map(|(job, _)| job).collect()
+    }
+}
+
+/// Drops a push superseded by a later one to the same PR
+async fn skip_superseded(superseded: Job) {
+    info!(
+        "Skipping push of {} to PR {}, superseded by a later push",
+        superseded.pr.head_sha(),
+        superseded.pr.number()
+    );
+    QUEUE.depth.fetch_sub(1, Ordering::Relaxed);
+    acknowledge(std::slice::from_ref(&superseded)).await;
+}
+
+/// Handles either a batch of PR closes, or a single other job, retrying
+/// while GitLab is unreachable. The jobs' repos are locked meanwhile, when
+/// replicas share the queue.
+async fn process(jobs: &[Job]) {
+    sentry::with_context(sentry::Context::of_pr(jobs[0].pr.as_ref()), sync_jobs(jobs)).await;
+    acknowledge(jobs).await;
+    QUEUE.depth.fetch_sub(jobs.len(), Ordering::Relaxed);
+}
+
+async fn sync_jobs(jobs: &[Job]) {
+    let repos = jobs
+        .iter()
+        .map(|job| repo_name::lookup_key(job.pr.base_full_name()))
+        .collect();
+    let locks = match cluster::lock_repos(repos).await {
+        Ok(locks) => locks,
+        Err(err) => {
+            warn!("Syncing without locking the repos: {:?}", err);
+            vec![]
+        }
+    };
+    loop {
+        health::wait_for_gitlab().await;
+        let sync = async {
+            if jobs[0].pr.action() == "closed" {
+                let prs: Vec<&dyn ForgePullRequest> =
+                    jobs.iter().map(|job| job.pr.as_ref()).collect();
+                sync::close_prs(&prs).await
+            } else {
+                sync::sync_pr(jobs[0].pr.as_ref()).await
+            }
+        };
+        // A panic fails the jobs, instead of taking the worker down
+        let result = match AssertUnwindSafe(sync).catch_unwind().await {
+            Ok(result) => result,
+            Err(panic) => {
+                let message = format!("Panicked syncing PR: {}", errors::panic_message(&*panic));
+                error!("{}", message);
+                metrics::record_panic();
+                for job in jobs {
+                    metrics::record_pr_sync_failure("panic");
+                    notifications::sync_failed(job.pr.as_ref(), &message);
+                }
+                break;
+            }
+        };
+        match result {
+            Ok(ok) => {
+                info!("Handled PR: {}", ok);
+                for job in jobs {
+                    metrics::record_pr_sync(job.received.elapsed());
+                    notifications::sync_succeeded(job.pr.as_ref());
+                }
+                break;
+            }
+            // Kill switches stop syncs on purpose, they didn't fail
+            Err(errors::GitError::KillSwitch(switch)) => {
+                info!("Skipped PR, the {} kill switch is engaged", switch);
+                for _ in jobs {
+                    metrics::record_pr_sync_skipped();
+                }
+                break;
+            }
+            Err(err) => {
+                error!("Caught error handling PR: {:?}", err);
+                if health::probe_gitlab().await {
+                    sentry::capture_error(&err);
+                    for job in jobs {
+                        metrics::record_pr_sync_failure(err.kind());
+                        notifications::sync_failed(job.pr.as_ref(), &err.to_string());
+                    }
+                    break;
+                }
+                warn!("GitLab is unreachable, retrying PR once it's back");
+            }
+        }
+    }
+    cluster::unlock_repos(locks).await;
+}
+
+/// Closes the queue and returns the jobs still in it
+fn drain(receiver: &mut UnboundedReceiver<Job>, mut jobs: Vec<Job>) -> Vec<Job> {
+    receiver.close();
+    while let Ok(job) = receiver.try_recv() {
+        jobs.push(job);
+    }
+    jobs
+}
+
+/// Sleeps until `deadline`, which the worker only does when there is one
+async fn sleep_until(deadline: Option<Instant>) {
+    if let Some(deadline) = deadline {
+        tokio::time::sleep_until(deadline.into()).await;
+    }
+}
+
+/// Stops the worker and returns the events which weren't handled yet. A
+/// sync in progress is given until `grace` runs out to finish, so a push
+/// isn't cut off halfway; one that takes longer is interrupted and returned
+/// too, as syncing a PR again is harmless. Events taken from the shared
+/// queue are acknowledged, as the caller puts them back on it.
+pub async fn shutdown(grace: Duration) -> Vec<Box<dyn ForgePullRequest>> {
+    let receiver = QUEUE.receiver.lock().unwrap().take();
+    let jobs = match receiver {
+        // the worker was never started
+        Some(mut receiver) => drain(&mut receiver, Vec::new()),
+        None => {
+            let deadline = Instant::now() + grace;
+            *SHUTDOWN_DEADLINE.lock().unwrap() = Some(deadline);
+            SHUTDOWN.notify_one();
+            // The worker gives up on a running sync at the deadline, leave it
+            // a moment to hand over what it hadn't handled
+            let stopped = deadline + STOP_MARGIN;
+            if timeout_at(stopped.into(), STOPPED.notified())
+                .await
+                .is_err()
+            {
+                warn!("PR queue worker didn't stop in time, losing queued events");
+            }
+            std::mem::take(&mut *LEFTOVER.lock().unwrap())
+        }
+    };
+    QUEUE.depth.fetch_sub(jobs.len(), Ordering::Relaxed);
+    acknowledge(&jobs).await;
+    jobs.into_iter().map(|job| job.pr).collect()
+}
+
+pub async fn run_worker() {
+    let receiver = QUEUE.receiver.lock().unwrap().take();
+    let mut receiver = match receiver {
+        Some(receiver) => receiver,
+        None => {
+            error!("PR queue worker is already running");
+            return;
+        }
+    };
+    let batching = &config::CONFIG.batching;
+    let window = Duration::from_millis(batching.synchronize_window_ms);
+    let mut pushes = PendingPushes::default();
+    let mut next = None;
+    let unhandled = loop {
+        let job = match pushes.take_due(Instant::now()) {
+            Some(job) => job,
+            None => {
+                let job = match next.take() {
+                    Some(job) => job,
+                    None => {
+                        let deadline = pushes.next_deadline();
+                        tokio::select! {
+                            job = receiver.recv() => match job {
+                                Some(job) => job,
+                                None => break pushes.drain(),
+                            },
+                            _ = sleep_until(deadline), if deadline.is_some() => continue,
+                            _ = SHUTDOWN.notified() => break pushes.drain(),
+                        }
+                    }
+                };
+                if job.pr.action() == "synchronize" && !window.is_zero() {
+                    if let Some(superseded) = pushes.add(job, Instant::now() + window) {
+                        skip_superseded(superseded).await;
+                    }
+                    continue;
+                }
+                // The PR's other events go after its pending push
+                match pushes.take_pr(job.pr.as_ref()) {
+                    Some(push) => {
+                        next = Some(job);
+                        push
+                    }
+                    None => job,
+                }
+            }
+        };
+        let batch = if job.pr.action() == "closed" {
+            let (batch, leftover) = collect_closes(
+                &mut receiver,
+                job,
+                Duration::from_millis(batching.close_window_ms),
+                batching.max_refs_per_push,
+            )
+            .await;
+            next = leftover;
+            batch
+        } else {
+            vec![job]
+        };
+        let mut sync = Box::pin(process(&batch));
+        let stopping = tokio::select! {
+            _ = &mut sync => false,
+            _ = SHUTDOWN.notified() => true,
+        };
+        if stopping {
+            // Let the running sync finish rather than abort a push midway
+            let deadline = SHUTDOWN_DEADLINE
+                .lock()
+                .unwrap()
+                .unwrap_or_else(Instant::now);
+            let finished = timeout_at(deadline.into(), &mut sync).await.is_ok();
+            drop(sync);
+            let mut unhandled = if finished {
+                Vec::new()
+            } else {
+                warn!("Interrupting a PR sync which didn't finish before shutdown");
+                batch
+            };
+            unhandled.extend(next.take());
+            unhandled.extend(pushes.drain());
+            break unhandled;
+        }
+        drop(sync);
+        if batch.len() > 1 {
+            // Space out batched pushes, as each one sets off a burst of
+            // GitLab webhooks and API calls
+            sleep(Duration::from_millis(batching.push_interval_ms)).await;
+        }
+    };
+    let unhandled = drain(&mut receiver, unhandled);
+    if !unhandled.is_empty() {
+        info!(
+            "PR queue worker stopped with {} unhandled events",
+            unhandled.len()
+        );
+    }
+    LEFTOVER.lock().unwrap().extend(unhandled);
+    STOPPED.notify_one();
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::api::models::github;
+    use crate::testing::read_testdata_to_string;
+
+    fn job(testdata: &str) -> Job {
+        let pr: github::PullRequest =
+            serde_json::from_str(&read_testdata_to_string(testdata)).unwrap();
+        Job {
+            pr: Box::new(pr),
+            received: Instant::now(),
+            shared: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_collect_closes() {
+        // The windows run out on the paused clock, without waiting
+        tokio::time::pause();
+        let (sender, mut receiver) = unbounded_channel();
+        for _ in 0..3 {
+            sender.send(job("github_close_pr_forked.json")).unwrap();
+        }
+        sender.send(job("github_open_pr_forked.json")).unwrap();
+        sender.send(job("github_close_pr_forked.json")).unwrap();
+
+        let window = Duration::from_secs(5);
+        let first = receiver.recv().await.unwrap();
+        let (batch, leftover) = collect_closes(&mut receiver, first, window, 2).await;
+        assert_eq!(batch.len(), 2);
+        assert!(leftover.is_none());
+
+        let first = receiver.recv().await.unwrap();
+        let (batch, leftover) = collect_closes(&mut receiver, first, window, 10).await;
+        assert_eq!(batch.len(), 1);
+        assert_eq!(leftover.unwrap().pr.action(), "opened");
+
+        let first = receiver.recv().await.unwrap();
+        let (batch, leftover) = collect_closes(&mut receiver, first, window, 10).await;
+        assert_eq!(batch.len(), 1);
+        assert!(leftover.is_none());
+    }
+
+    fn push(number: i64, sha: &str) -> Job {
+        let mut event: serde_json::Value =
+            serde_json::from_str(&read_testdata_to_string("github_open_pr_forked.json")).unwrap();
+        event["action"] = "synchronize".into();
+        event["number"] = number.into();
+        event["pull_request"]["number"] = number.into();
+        event["pull_request"]["head"]["sha"] = sha.into();
+        Job {
+            pr: Box::new(serde_json::from_value::<github::PullRequest>(event).unwrap()),
+            received: Instant::now(),
+            shared: None,
+        }
+    }
+
+    #[test]
+    fn test_pending_pushes() {
+        let mut pushes = PendingPushes::default();
+        let now = Instant::now();
+        let later = now + Duration::from_millis(10);
+        let first = push(5, "a");
+        let received = first.received;
+        assert!(pushes.add(first, later).is_none());
+        assert!(pushes.add(push(6, "d"), now).is_none());
+        assert_eq!(pushes.add(push(5, "b"), later).unwrap().pr.head_sha(), "a");
+        assert_eq!(pushes.add(push(5, "c"), later).unwrap().pr.head_sha(), "b");
+        assert_eq!(pushes.next_deadline(), Some(now));
+
+        // PR 6's window ended, PR 5's didn't
+        assert_eq!(pushes.take_due(now).unwrap().pr.head_sha(), "d");
+        assert!(pushes.take_due(now).is_none());
+        assert_eq!(pushes.next_deadline(), Some(later));
+
+        let job = pushes.take_due(later).unwrap();
+        assert_eq!(job.pr.head_sha(), "c");
+        assert_eq!(job.received, received);
+        assert!(pushes.drain().is_empty());
+
+        pushes.add(push(5, "e"), later);
+        assert!(pushes.take_pr(push(6, "f").pr.as_ref()).is_none());
+        assert_eq!(
+            pushes
+                .take_pr(push(5, "f").pr.as_ref())
+                .unwrap()
+                .pr
+                .head_sha(),
+            "e"
+        );
+        assert!(pushes.next_deadline().is_none());
+    }
+}
@@ -0,0 +1,116 @@
+//! Valid git ref names for the GitLab branches LabHub pushes. Head repo and
+//! branch names come from the forges, which allow names git or GitLab don't,
+//! like `owner/.github` or refs longer than GitLab's limit.
+use ring::digest;
+
+/// Longest branch name LabHub pushes, GitLab rejects longer ones
+pub const MAX_LEN: usize = 255;
+/// Hex digits of the hash suffix of truncated names
+const HASH_LEN: usize = 8;
+
+/// Makes a name a valid git ref name, replacing the characters git doesn't
+/// allow with `-`, and dropping what can't start or end a ref component
+pub fn sanitize(name: &str) -> String {
+    let mut name: String = name
+        .chars()
+        .map(|c| {
+            if c.is_control() || " ~^:?*[\\".contains(c) {
+                '-'
+            } else {
+                c
+            }
+        })
+        .collect::<String>()
+        .replace("@{", "-{");
+    while name.contains("..") {
+        name = name.replace("..", ".");
+    }
+    // Components can't be empty, start with a dot, or end with `.lock` or a
+    // dot
+    let name = name
+        .split('/')
+        .map(|component| {
+            let mut component = component.trim_start_matches('.');
+            loop {
+                component = component.trim_end_matches('.');
+                match component.strip_suffix(".lock") {
+                    Some(stripped) => component = stripped,
+                    None => break,
+                }
+            }
+            component
+        })
+        .filter(|component| !component.is_empty())
+        .collect::<Vec<_>>()
+        .join("/");
+    if name == "@" {
+        "-".to_string()
+    } else {
+        name
+    }
+}
+
+/// Shortens a name to `max_len` bytes, ending it with a hash of the whole
+/// name so distinct long names stay distinct
+pub fn truncate(name: &str, max_len: usize) -> String {
+    if name.len() <= max_len {
+        return name.to_string();
+    }
+    let hash = hex::encode(digest::digest(&digest::SHA256, name.as_bytes()));
+    let mut end = max_len.saturating_sub(HASH_LEN + 1);
+    while !name.is_char_boundary(end) {
+        end -= 1;
+    }
+    format!("{}-{}", &name[..end], &hash[..HASH_LEN])
+}
+
+/// A valid GitLab branch name for a name
+pub fn branch(name: &str) -> String {
+    truncate(&sanitize(name), MAX_LEN)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_sanitize() {
+        assert_eq!(
+            sanitize("pr-12/octocat/hello-world/fix-typo"),
+            "pr-12/octocat/hello-world/fix-typo"
+        );
+        assert_eq!(
+            sanitize("pr-1/o/r/fix: a~b^c?d*e[f\\g"),
+            "pr-1/o/r/fix--a-b-c-d-e-f-g"
+        );
+        assert_eq!(sanitize("pr-1/o/r/a\tb\u{7f}c"), "pr-1/o/r/a-b-c");
+        assert_eq!(sanitize("pr-1/o/r/a..b@{1}"), "pr-1/o/r/a.b-{1}");
+        assert_eq!(sanitize("pr-1/o/r/a....b"), "pr-1/o/r/a.b");
+        assert_eq!(
+            sanitize("pr-1/octocat/.github/main"),
+            "pr-1/octocat/github/main"
+        );
+        assert_eq!(sanitize("pr-1/o/.r//x.lock"), "pr-1/o/r/x");
+        assert_eq!(sanitize("pr-1/o/r/x.lock.lock."), "pr-1/o/r/x");
+        assert_eq!(sanitize("/pr-1/o/r/x/"), "pr-1/o/r/x");
+        assert_eq!(sanitize("pr-1/o/r/..."), "pr-1/o/r");
+        assert_eq!(sanitize("pr-1/o/r/ünïcødé"), "pr-1/o/r/ünïcødé");
+        assert_eq!(sanitize("@"), "-");
+    }
+
+    #[test]
+    fn test_truncate() {
+        assert_eq!(truncate("pr-1/o/r/x", 20), "pr-1/o/r/x");
+        let long = format!("pr-1/o/r/{}", "x".repeat(300));
+        let truncated = truncate(&long, MAX_LEN);
+        assert_eq!(truncated.len(), MAX_LEN);
+        assert!(truncated.starts_with("pr-1/o/r/xxx"));
+        assert_ne!(truncated, truncate(&format!("{}y", long), MAX_LEN));
+        assert_eq!(truncated, truncate(&long, MAX_LEN));
+
+        let wide = branch(&format!("pr-1/o/r/{}", "é".repeat(200)));
+        assert!(wide.len() <= MAX_LEN);
+        assert!(wide.starts_with("pr-1/o/r/é"));
+        assert_eq!(sanitize(&wide), wide);
+    }
+}
@@ -0,0 +1,69 @@
+//! Keeping the recent verified webhook payloads, so that one which was
+//! handled badly can be replayed through the admin API while debugging.
+use crate::bitbucket;
+use crate::config;
+use crate::errors::{GitError, RequestErrorResult};
+use crate::gitea;
+use crate::github;
+use crate::gitlab;
+use crate::state;
+
+use log::{error, info};
+
+/// Stores a payload, if the admin API is enabled to replay it, returning its
+/// ID. `source` is "github", "gitlab", "gitea" or "bitbucket".
+pub fn record(source: &str, event_type: &str, body: &str) -> Option<i64> {
+    let admin = &config::CONFIG.admin;
+    if admin.token.is_none() || admin.replay_payloads == 0 {
+        return None;
+    }
+    match state::record_webhook_payload(source, event_type, body, admin.replay_payloads) {
+        Ok(id) => {
+            info!("Stored {} webhook payload id={}", source, id);
+            Some(id)
+        }
+        Err(err) => {
+            error!("Error storing {} webhook payload: {:?}", source, err);
+            None
+        }
+    }
+}
+
+/// Records where a stored payload couldn't be parsed, if handling it failed
+/// for that, so it's kept to be replayed once LabHub can parse it
+pub fn record_result<T>(
+    payload_id: Option<i64>,
+    result: Result<T, RequestErrorResult>,
+) -> Result<T, RequestErrorResult> {
+    if let (Some(id), Err(RequestErrorResult::InvalidPayload(err))) = (payload_id, &result) {
+        if let Err(err) = state::record_payload_error(id, &err.pointer, &err.message) {
+            error!(
+                "Error recording parse error of payload id={}: {:?}",
+                id, err
+            );
+        }
+    }
+    result
+}
+
+/// Handles a stored payload again, skipping the signature check and
+/// deduplication
+pub async fn replay(id: i64) -> Result<String, RequestErrorResult> {
+    let payload = state::webhook_payload(id)?.ok_or(GitError::NotFound(format!(
+        "No stored webhook payload with id={}",
+        id
+    )))?;
+    info!(
+        "Replaying {} webhook payload id={} type={}",
+        payload.source, payload.id, payload.event_type
+    );
+    let event_type = payload.event_type.as_str();
+    let body = payload.body.as_str();
+    match payload.source.as_str() {
+        "github" => github::handle_event_body(event_type, body).await,
+        "gitlab" => gitlab::handle_event_body(event_type, body).await,
+        "gitea" => gitea::handle_event_body(event_type, body).await,
+        "bitbucket" => bitbucket::handle_event_body(event_type, body).await,
+        source => Err(GitError::BadRequest(format!("Unknown webhook source {}", source)).into()),
+    }
+}
@@ -0,0 +1,51 @@
+//! Canonical repo names, for matching them against the mappings.
+use crate::errors::GitError;
+
+use regex::Regex;
+
+/// Returns the canonical form of an `owner/name` repo full name from a
+/// webhook payload or the config. Forges treat repo names case-insensitively
+/// and may send them in any case, so mapping lookups and state records use
+/// the lowercased name. GitLab branch names keep the original case.
+pub fn canonicalize(full_name: &str) -> Result<String, GitError> {
+    lazy_static! {
+        static ref RE: Regex = Regex::new(r"^[A-Za-z0-9_.-]+/[A-Za-z0-9_.-]+$").unwrap();
+    }
+    if RE.is_match(full_name) {
+        Ok(full_name.to_lowercase())
+    } else {
+        Err(GitError::BadRequest(format!(
+            "Malformed repo full name {:?}, expected owner/name",
+            full_name
+        )))
+    }
+}
+
+/// Canonicalizes a name for a mapping lookup, falling back to plain
+/// lowercasing so a malformed name simply won't match
+pub fn lookup_key(full_name: &str) -> String {
+    canonicalize(full_name).unwrap_or_else(|_| full_name.to_lowercase())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_canonicalize() {
+        assert_eq!(
+            canonicalize("BrndnMtthws/LabHub").unwrap(),
+            "brndnmtthws/labhub"
+        );
+        assert_eq!(
+            canonicalize("some-org/repo.rs_2").unwrap(),
+            "some-org/repo.rs_2"
+        );
+        assert!(canonicalize("labhub").is_err());
+        assert!(canonicalize("a/b/c").is_err());
+        assert!(canonicalize("/labhub").is_err());
+        assert!(canonicalize("owner/").is_err());
+        assert!(canonicalize("owner/la hub").is_err());
+        assert!(canonicalize("").is_err());
+    }
+}
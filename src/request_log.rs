@@ -0,0 +1,221 @@
+//! Logs the requests to LabHub's HTTP server at debug level: the method,
+//! path, status and latency of each, and with `log_bodies` in `[server]`
+//! their headers and bodies too. Tokens, signatures and passwords are
+//! redacted from the headers and JSON bodies before they're logged.
+use crate::config;
+
+use axum::{
+    body::{self, Body, Bytes, Full, HttpBody},
+    http::{HeaderMap, Request, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use log::{debug, log_enabled, Level};
+use serde_json::Value;
+use std::fmt::Display;
+use std::time::Instant;
+
+const REDACTED: &str = "[redacted]";
+
+/// Headers and JSON fields whose name contains any of these hold
+/// credentials
+const SECRET_NAMES: &[&str] = &[
+    "authorization",
+    "cookie",
+    "password",
+    "passphrase",
+    "private_key",
+    "secret",
+    "signature",
+    "token",
+];
+
+/// Whether a header or JSON field named `name` holds credentials
+fn is_secret(name: &str) -> bool {
+    let name = name.to_ascii_lowercase();
+    SECRET_NAMES.iter().any(|secret| name.contains(secret))
+}
+
+/// The headers as `name: value` pairs, with credentials redacted
+pub fn redact_headers(headers: &HeaderMap) -> String {
+    headers
+        .iter()
+        .map(|(name, value)| {
+            let value = if is_secret(name.as_str()) {
+                REDACTED
+            } else {
+                value.to_str().unwrap_or("<binary>")
+            };
+            format!("{}: {}", name, value)
+        })
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+fn redact_value(value: &mut Value) {
+    match value {
+        Value::Object(fields) => {
+            for (name, field) in fields.iter_mut() {
+                if is_secret(name) && !field.is_null() {
+                    *field = Value::String(REDACTED.to_string());
+                } else {
+                    redact_value(field);
+                }
+            }
+        }
+        Value::Array(values) => values.iter_mut().for_each(redact_value),
+        _ => {}
+    }
+}
+
+/// The body with its credentials redacted. Only JSON can be redacted, so
+/// other bodies are left out.
+pub fn redact_body(body: &[u8]) -> String {
+    if body.is_empty() {
+        return String::new();
+    }
+    match serde_json::from_slice::<Value>(body) {
+        Ok(mut value) => {
+            redact_value(&mut value);
+            value.to_string()
+        }
+        Err(_) => format!("<{} bytes, not JSON>", body.len()),
+    }
+}
+
+/// Reads a body of at most `limit` bytes
+async fn collect<B>(mut body: B, limit: usize) -> Result<Bytes, StatusCode>
+where
+    B: HttpBody<Data = Bytes> + Unpin,
+    B::Error: Display,
+{
+    let mut bytes = Vec::new();
+    while let Some(chunk) = body.data().await {
+        let chunk = chunk.map_err(|err| {
+            debug!("Unable to read body: {}", err);
+            StatusCode::BAD_REQUEST
+        })?;
+        if bytes.len() + chunk.len() > limit {
+            return Err(StatusCode::PAYLOAD_TOO_LARGE);
+        }
+        bytes.extend_from_slice(&chunk);
+    }
+    Ok(bytes.into())
+}
+
+/// Middleware logging each request, see the module docs
+pub async fn log_request(request: Request<Body>, next: Next<Body>) -> Response {
+    if !log_enabled!(Level::Debug) {
+        return next.run(request).await;
+    }
+    let method = request.method().clone();
+    let path = request.uri().path().to_string();
+    let start = Instant::now();
+
+    if !config::CONFIG.server.log_bodies {
+        let response = next.run(request).await;
+        debug!(
+            "{} {} -> {} in {}ms",
+            method,
+            path,
+            response.status().as_u16(),
+            start.elapsed().as_millis()
+        );
+        return response;
+    }
+
+    let (parts, request_body) = request.into_parts();
+    let request_body = match collect(request_body, config::CONFIG.server.body_limits.max()).await {
+        Ok(request_body) => request_body,
+        Err(status) => return status.into_response(),
+    };
+    debug!(
+        "{} {} headers={{{}}} body={}",
+        method,
+        path,
+        redact_headers(&parts.headers),
+        redact_body(&request_body)
+    );
+    let response = next
+        .run(Request::from_parts(parts, Body::from(request_body)))
+        .await;
+
+    let (parts, response_body) = response.into_parts();
+    let response_body = match collect(response_body, usize::MAX).await {
+        Ok(response_body) => response_body,
+        Err(_) => return StatusCode::INTERNAL_SERVER_ERROR.into_response(),
+    };
+    debug!(
+        "{} {} -> {} in {}ms headers={{{}}} body={}",
+        method,
+        path,
+        parts.status.as_u16(),
+        start.elapsed().as_millis(),
+        redact_headers(&parts.headers),
+        redact_body(&response_body)
+    );
+    Response::from_parts(parts, body::boxed(Full::from(response_body)))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use axum::http::HeaderValue;
+    use serde_json::json;
+
+    #[test]
+    fn test_redact_headers() {
+        let mut headers = HeaderMap::new();
+        headers.insert("x-github-event", HeaderValue::from_static("push"));
+        headers.insert(
+            "x-hub-signature-256",
+            HeaderValue::from_static("sha256=abc"),
+        );
+        headers.insert("x-gitlab-token", HeaderValue::from_static("secret"));
+        headers.insert("authorization", HeaderValue::from_static("Bearer token"));
+        let redacted = redact_headers(&headers);
+        assert!(redacted.contains("x-github-event: push"));
+        assert!(redacted.contains("x-hub-signature-256: [redacted]"));
+        assert!(redacted.contains("x-gitlab-token: [redacted]"));
+        assert!(redacted.contains("authorization: [redacted]"));
+        assert!(!redacted.contains("abc"));
+        assert!(!redacted.contains("Bearer"));
+    }
+
+    #[test]
+    fn test_redact_body() {
+        let body = json!({
+            "action": "opened",
+            "hook": {"config": {"secret": "hunter2", "url": "https://labhub"}},
+            "installation": {"access_token": "ghs_abc", "expires_at": null},
+            "users": [{"login": "me", "password": "pw"}],
+            "token": null,
+        });
+        let redacted: Value =
+            serde_json::from_str(&redact_body(body.to_string().as_bytes())).unwrap();
+        assert_eq!(
+            redacted,
+            json!({
+                "action": "opened",
+                "hook": {"config": {"secret": "[redacted]", "url": "https://labhub"}},
+                "installation": {"access_token": "[redacted]", "expires_at": null},
+                "users": [{"login": "me", "password": "[redacted]"}],
+                "token": null,
+            })
+        );
+        assert_eq!(redact_body(b""), "");
+        assert_eq!(redact_body(b"token=abc"), "<9 bytes, not JSON>");
+    }
+
+    #[tokio::test]
+    async fn test_collect() {
+        assert_eq!(
+            collect(Body::from("hello"), 5).await.unwrap(),
+            Bytes::from("hello")
+        );
+        assert_eq!(
+            collect(Body::from("hello"), 4).await.unwrap_err(),
+            StatusCode::PAYLOAD_TOO_LARGE
+        );
+    }
+}
@@ -1,39 +1,413 @@
-use crate::api::{github_proto, github_signature};
+//! The HTTP handlers behind [`crate::app`].
+use crate::api::{
+    bitbucket_proto, bitbucket_signature, gitea_proto, gitea_signature, github_proto,
+    github_signature::{self, SignatureError},
+    gitlab_proto, gitlab_signature,
+};
+use crate::bitbucket;
+use crate::body_limit;
 use crate::config;
-use crate::errors;
+use crate::dedupe;
+use crate::errors::{self, GitError};
+use crate::gitea;
 use crate::github;
+use crate::gitlab;
+use crate::health;
+use crate::metrics;
+use crate::queue;
+use crate::replay;
+use crate::sentry;
 
-use axum::{extract::TypedHeader, Json};
-use log::{debug, info};
+use axum::{
+    extract::{BodyStream, TypedHeader},
+    headers::{ContentLength, ContentType},
+    http::StatusCode,
+    Json,
+};
+use futures::FutureExt;
+use log::{debug, error, info, warn};
 use serde_json::json;
+use std::future::Future;
+use std::panic::AssertUnwindSafe;
 
+/// Liveness check
 pub async fn check() -> &'static str {
     "ok"
 }
 
+/// Readiness check, which fails while GitLab is unreachable. Events are
+/// still accepted and queued in the meantime.
+pub async fn ready() -> (StatusCode, Json<serde_json::Value>) {
+    let gitlab_healthy = health::is_gitlab_healthy();
+    let status = if gitlab_healthy {
+        StatusCode::OK
+    } else {
+        StatusCode::SERVICE_UNAVAILABLE
+    };
+    (
+        status,
+        Json(json!({
+            "gitlab": {
+                "healthy": gitlab_healthy,
+                "error": health::gitlab_last_error(),
+            },
+            "queue_depth": queue::depth(),
+        })),
+    )
+}
+
+/// Readiness check with a status per dependency: the GitHub and GitLab API
+/// tokens, the SSH keys, and the repo cache directory. Fails if any of them
+/// is unusable.
+pub async fn readyz() -> (StatusCode, Json<serde_json::Value>) {
+    let dependencies = health::dependencies();
+    let ready = dependencies.values().all(|dependency| dependency.healthy);
+    let status = if ready {
+        StatusCode::OK
+    } else {
+        StatusCode::SERVICE_UNAVAILABLE
+    };
+    (
+        status,
+        Json(json!({
+            "ready": ready,
+            "dependencies": dependencies,
+            "queue_depth": queue::depth(),
+        })),
+    )
+}
+
+/// Reports the LabHub version and the active config environment
+pub async fn version() -> Json<serde_json::Value> {
+    Json(json!({
+        "name": env!("CARGO_PKG_NAME"),
+        "version": env!("CARGO_PKG_VERSION"),
+        "environment": config::get_labhub_env(),
+    }))
+}
+
+/// Prometheus metrics
+pub async fn metrics() -> String {
+    metrics::render()
+}
+
+/// How long an overloaded LabHub asks for webhooks to be retried after
+const OVERLOADED_RETRY_AFTER_SECS: u64 = 30;
+
+/// Turns webhooks away with a 503 while `max_queued_events` PR syncs are
+/// already queued, rather than letting them pile up. Forges don't redeliver
+/// them by themselves, so the event is lost unless it's redelivered by hand,
+/// or replayed through the admin API, which keeps its payload.
+fn reject_when_overloaded(source: &str, event_type: &str, body: &str) -> Result<(), GitError> {
+    let max = config::CONFIG.server.max_queued_events;
+    let result = check_queue_depth(queue::depth(), max);
+    if result.is_err() {
+        warn!(
+            "Turning away {} {} webhook, too many PR syncs are queued",
+            source, event_type
+        );
+        replay::record(source, event_type, body);
+    }
+    result
+}
+
+fn check_queue_depth(depth: usize, max: usize) -> Result<(), GitError> {
+    if max > 0 && depth >= max {
+        return Err(GitError::Overloaded {
+            message: format!("{} PR syncs are queued already, try again later", depth),
+            retry_after_secs: OVERLOADED_RETRY_AFTER_SECS,
+        });
+    }
+    Ok(())
+}
+
+/// Handles a verified webhook with `handler`, reporting its errors, but not
+/// its invalid payloads, and its panics with the repo and PR it's about. A
+/// panic is answered with a 500, leaving the other webhooks unaffected.
+async fn handle<'a, F, Fut>(
+    forge: &str,
+    event: &'a str,
+    body: &'a str,
+    handler: F,
+) -> Result<String, errors::RequestErrorResult>
+where
+    F: FnOnce(&'a str, &'a str) -> Fut,
+    Fut: Future<Output = Result<String, errors::RequestErrorResult>>,
+{
+    let context = sentry::Context::of_webhook(forge, event, body);
+    sentry::with_context(context, async {
+        let result = match AssertUnwindSafe(handler(event, body)).catch_unwind().await {
+            Ok(result) => result,
+            Err(panic) => {
+                let message = format!(
+                    "Panicked handling the webhook: {}",
+                    errors::panic_message(&*panic)
+                );
+                error!("{}", message);
+                metrics::record_panic();
+                return Err(errors::RequestErrorResult::internal(message));
+            }
+        };
+        match &result {
+            Ok(_) | Err(errors::RequestErrorResult::InvalidPayload(_)) => {}
+            Err(err) => sentry::capture_message(&err.message()),
+        }
+        result
+    })
+    .await
+}
+
+/// Verifies and handles a GitHub webhook
 pub async fn github_event(
     TypedHeader(event_type): TypedHeader<github_proto::XGitHubEvent>,
-    TypedHeader(signature): TypedHeader<github_proto::XHubSignature>,
-    body: String,
-) -> Result<Json<String>, errors::RequestErrorResult> {
+    signature: Option<TypedHeader<github_proto::XHubSignature>>,
+    delivery: Option<TypedHeader<github_proto::XGitHubDelivery>>,
+    target_type: Option<TypedHeader<github_proto::XGitHubHookTargetType>>,
+    content_length: Option<TypedHeader<ContentLength>>,
+    content_type: Option<TypedHeader<ContentType>>,
+    body: BodyStream,
+) -> Result<Json<serde_json::Value>, errors::RequestErrorResult> {
     info!("Received GitHub webhook, type={}", event_type.0);
 
-    // Check X-Hub-Signature
-    github_signature::check_signature(
-        &config::CONFIG.github.webhook_secret.clone(),
-        &signature.0,
-        &body,
-    )?;
+    // Check X-Hub-Signature with the secret of the instance and org it's
+    // from, hashing the body as it's read
+    let TypedHeader(signature) = signature.ok_or(SignatureError::Missing)?;
+    let mut verifier = github_signature::StreamingVerifier::new(
+        config::CONFIG
+            .github
+            .iter()
+            .flat_map(|instance| instance.webhook_secrets())
+            .collect(),
+    );
+    let body = body_limit::read(
+        "github",
+        body,
+        content_length.map(|TypedHeader(length)| length.0),
+        config::CONFIG.server.body_limits.webhook("github"),
+        |chunk| verifier.update(chunk),
+    )
+    .await?;
+    // Nothing is parsed before the raw body is known to be signed with one of
+    // the secrets
+    let signed_with = verifier.verify(&signature.0)?;
+    // The form-encoded body is signed, and the JSON is in its payload field
+    let body = match content_type {
+        Some(TypedHeader(content_type))
+            if content_type
+                .to_string()
+                .starts_with("application/x-www-form-urlencoded") =>
+        {
+            github::form_payload(&body)?
+        }
+        _ => body,
+    };
+    // It must be signed with the secret of the instance and org it's from,
+    // not just any of them
+    let secret = github::secret_for_event(&body)?;
+    if !signed_with.contains(&secret) {
+        warn!("GitHub webhook isn't signed with the secret of its org");
+        return Err(SignatureError::BadSignature.into());
+    }
+    // Events which would be dropped anyway aren't rejected when overloaded,
+    // so GitHub doesn't redeliver them
+    let target_type = target_type.map(|TypedHeader(target_type)| target_type.0);
+    if let Some(message) = github::dropped_event(&body, target_type.as_deref()) {
+        metrics::record_dropped_webhook();
+        return Ok(Json(json!(message)));
+    }
+    reject_when_overloaded("github", event_type.0.as_ref(), &body)?;
+
+    let delivery = delivery.map(|TypedHeader(delivery)| delivery.0);
+    if let Some(delivery) = delivery.as_ref() {
+        if dedupe::is_duplicate_delivery(delivery).await {
+            info!("Skipping duplicate delivery={}", delivery);
+            metrics::record_duplicate_delivery("github");
+            return Ok(Json(json!("Already handled this one 😉")));
+        }
+    }
+    if event_type.0 == "ping" {
+        return Ok(Json(github::handle_ping(&body)?));
+    }
+    let payload_id = replay::record("github", event_type.0.as_ref(), &body);
+
+    // Handle the event
+    let result = handle("github", event_type.0.as_ref(), &body, |event, body| {
+        github::handle_event_body(event, body)
+    })
+    .await;
+    if let (Ok(_), Some(delivery)) = (&result, &delivery) {
+        dedupe::record_delivery(delivery).await;
+    }
+    Ok(Json(json!(replay::record_result(payload_id, result)?)))
+}
+
+/// Verifies and handles a GitLab webhook
+pub async fn gitlab_event(
+    TypedHeader(event_type): TypedHeader<gitlab_proto::XGitlabEvent>,
+    token: Option<TypedHeader<gitlab_proto::XGitlabToken>>,
+    event_uuid: Option<TypedHeader<gitlab_proto::XGitlabEventUuid>>,
+    content_length: Option<TypedHeader<ContentLength>>,
+    body: BodyStream,
+) -> Result<Json<String>, errors::RequestErrorResult> {
+    let event_uuid = event_uuid.map(|TypedHeader(uuid)| uuid.0);
+    info!(
+        "Received GitLab webhook, type={} uuid={}",
+        event_type.0,
+        event_uuid.as_deref().unwrap_or("none")
+    );
+
+    // Check X-Gitlab-Token, which may be from any of the GitLab instances
+    let TypedHeader(token) = token.ok_or(SignatureError::Missing)?;
+    let secrets: Vec<String> = config::CONFIG
+        .gitlab
+        .iter()
+        .map(|instance| instance.site.webhook_secret())
+        .collect();
+    let secrets: Vec<&str> = secrets.iter().map(String::as_str).collect();
+    let instance = &config::CONFIG.gitlab[gitlab_signature::check_token(&secrets, &token.0)?];
+    debug!("Webhook is from GitLab instance {}", instance.name);
+    // The token isn't a signature of the body, which is only counted as it's
+    // read
+    let body = body_limit::read(
+        "gitlab",
+        body,
+        content_length.map(|TypedHeader(length)| length.0),
+        config::CONFIG.server.body_limits.webhook("gitlab"),
+        |_| {},
+    )
+    .await?;
+    reject_when_overloaded("gitlab", event_type.0.as_ref(), &body)?;
 
-    debug!("body={}", body);
+    // Resent webhooks keep their UUID, and it's kept apart from GitHub's
+    // delivery IDs
+    if let Some(uuid) = event_uuid.as_ref() {
+        if dedupe::is_duplicate_delivery(&format!("gitlab:{}", uuid)).await {
+            info!("Skipping duplicate GitLab event uuid={}", uuid);
+            metrics::record_duplicate_delivery("gitlab");
+            return Ok(Json(String::from("Already handled this one 😉")));
+        }
+    }
+    let payload_id = replay::record("gitlab", event_type.0.as_ref(), &body);
 
     // Handle the event
-    Ok(Json(
-        github::handle_event_body(&event_type.0.as_ref(), &body).await?,
-    ))
+    let result = handle("gitlab", event_type.0.as_ref(), &body, |event, body| {
+        gitlab::handle_event_body(event, body)
+    })
+    .await;
+    if let (Ok(_), Some(uuid)) = (&result, &event_uuid) {
+        dedupe::record_delivery(&format!("gitlab:{}", uuid)).await;
+    }
+    Ok(Json(replay::record_result(payload_id, result)?))
 }
 
-pub async fn gitlab_event(Json(event): Json<serde_json::Value>) -> Json<serde_json::Value> {
-    info!("{:?}", event);
-    Json(json!({"hello":"hi"}))
+/// Verifies and handles a Gitea or Forgejo webhook
+pub async fn gitea_event(
+    TypedHeader(event_type): TypedHeader<gitea_proto::XGiteaEvent>,
+    signature: Option<TypedHeader<gitea_proto::XGiteaSignature>>,
+    content_length: Option<TypedHeader<ContentLength>>,
+    body: BodyStream,
+) -> Result<Json<String>, errors::RequestErrorResult> {
+    info!("Received Gitea webhook, type={}", event_type.0);
+
+    let gitea_config = config::CONFIG
+        .gitea
+        .as_ref()
+        .ok_or(GitError::NotFound("Gitea is not configured".to_owned()))?;
+
+    // Check X-Gitea-Signature, hashing the body as it's read
+    let TypedHeader(signature) = signature.ok_or(SignatureError::Missing)?;
+    let mut verifier = gitea_signature::StreamingVerifier::new(&gitea_config.site.webhook_secret());
+    let body = body_limit::read(
+        "gitea",
+        body,
+        content_length.map(|TypedHeader(length)| length.0),
+        config::CONFIG.server.body_limits.webhook("gitea"),
+        |chunk| verifier.update(chunk),
+    )
+    .await?;
+    verifier.verify(&signature.0)?;
+    debug!("Good signature {} for Gitea", signature.0);
+    reject_when_overloaded("gitea", event_type.0.as_ref(), &body)?;
+
+    let payload_id = replay::record("gitea", event_type.0.as_ref(), &body);
+
+    // Handle the event
+    let result = handle("gitea", event_type.0.as_ref(), &body, |event, body| {
+        gitea::handle_event_body(event, body)
+    })
+    .await;
+    Ok(Json(replay::record_result(payload_id, result)?))
+}
+
+/// Verifies and handles a Bitbucket Cloud webhook
+pub async fn bitbucket_event(
+    TypedHeader(event_key): TypedHeader<bitbucket_proto::XEventKey>,
+    signature: Option<TypedHeader<github_proto::XHubSignature>>,
+    content_length: Option<TypedHeader<ContentLength>>,
+    body: BodyStream,
+) -> Result<Json<String>, errors::RequestErrorResult> {
+    info!("Received Bitbucket webhook, key={}", event_key.0);
+
+    let bitbucket_config = config::CONFIG
+        .bitbucket
+        .as_ref()
+        .ok_or(GitError::NotFound("Bitbucket is not configured".to_owned()))?;
+
+    // Check X-Hub-Signature, hashing the body as it's read
+    let TypedHeader(signature) = signature.ok_or(SignatureError::Missing)?;
+    let mut verifier =
+        bitbucket_signature::StreamingVerifier::new(&bitbucket_config.site.webhook_secret());
+    let body = body_limit::read(
+        "bitbucket",
+        body,
+        content_length.map(|TypedHeader(length)| length.0),
+        config::CONFIG.server.body_limits.webhook("bitbucket"),
+        |chunk| verifier.update(chunk),
+    )
+    .await?;
+    verifier.verify(&signature.0)?;
+    reject_when_overloaded("bitbucket", event_key.0.as_ref(), &body)?;
+
+    let payload_id = replay::record("bitbucket", event_key.0.as_ref(), &body);
+
+    // Handle the event
+    let result = handle("bitbucket", event_key.0.as_ref(), &body, |event, body| {
+        bitbucket::handle_event_body(event, body)
+    })
+    .await;
+    Ok(Json(replay::record_result(payload_id, result)?))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_handle_panic() {
+        let ok = handle("github", "push", "{}", |_, _| async {
+            Ok("done".to_string())
+        })
+        .await;
+        assert_eq!(ok.unwrap(), "done");
+
+        let err = handle("github", "push", "{}", |_, _| async {
+            panic!("unexpected payload");
+        })
+        .await
+        .unwrap_err();
+        assert!(matches!(err, errors::RequestErrorResult::ResponseError(_)));
+        assert_eq!(
+            err.message(),
+            "Panicked handling the webhook: unexpected payload"
+        );
+    }
+
+    #[test]
+    fn test_check_queue_depth() {
+        assert!(check_queue_depth(1000, 0).is_ok());
+        assert!(check_queue_depth(9, 10).is_ok());
+        let err = check_queue_depth(10, 10).unwrap_err();
+        assert_eq!(err.status(), StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(err.kind(), "overloaded");
+    }
 }
@@ -0,0 +1,89 @@
+//! Signing the commits LabHub generates on top of PR heads with the
+//! `[signing]` key, by running gpg or ssh-keygen like git itself does.
+use crate::config::{Signing, SigningFormat};
+use crate::errors::GitError;
+
+use log::error;
+use std::io::Write;
+use std::process::{Command, Stdio};
+
+/// The program and arguments which sign standard input, writing an armored
+/// signature to standard output
+fn sign_command(signing: &Signing) -> (&'static str, Vec<String>) {
+    match signing.format {
+        SigningFormat::Gpg => (
+            "gpg",
+            vec![
+                "--batch".to_string(),
+                "--status-fd=2".to_string(),
+                "-bsau".to_string(),
+                signing.key.clone(),
+            ],
+        ),
+        SigningFormat::Ssh => (
+            "ssh-keygen",
+            vec![
+                "-Y".to_string(),
+                "sign".to_string(),
+                "-n".to_string(),
+                "git".to_string(),
+                "-f".to_string(),
+                signing.key.clone(),
+            ],
+        ),
+    }
+}
+
+/// Signs a commit's content, returning the signature for its `gpgsig` header
+pub fn sign(signing: &Signing, content: &str) -> Result<String, GitError> {
+    let (program, args) = sign_command(signing);
+    let mut child = Command::new(program)
+        .args(&args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|err| GitError::Other(format!("Unable to run {}: {}", program, err)))?;
+    if let Some(mut stdin) = child.stdin.take() {
+        stdin.write_all(content.as_bytes())?;
+    }
+    let output = child.wait_with_output()?;
+    if !output.status.success() {
+        let msg = format!(
+            "Signing a commit with {} failed: status={} stderr={}",
+            program,
+            output.status,
+            String::from_utf8_lossy(&output.stderr)
+        );
+        error!("{}", msg);
+        return Err(GitError::Other(msg));
+    }
+    String::from_utf8(output.stdout)
+        .map_err(|err| GitError::Other(format!("Invalid signature from {}: {}", program, err)))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_sign_command() {
+        let mut signing = Signing {
+            format: SigningFormat::Gpg,
+            key: "0xA1B2C3D4".to_string(),
+            name: "LabHub".to_string(),
+            email: "labhub@example.com".to_string(),
+        };
+        let (program, args) = sign_command(&signing);
+        assert_eq!(program, "gpg");
+        assert_eq!(args, ["--batch", "--status-fd=2", "-bsau", "0xA1B2C3D4"]);
+        signing.format = SigningFormat::Ssh;
+        signing.key = "/etc/labhub/ssh/signing".to_string();
+        let (program, args) = sign_command(&signing);
+        assert_eq!(program, "ssh-keygen");
+        assert_eq!(
+            args,
+            ["-Y", "sign", "-n", "git", "-f", "/etc/labhub/ssh/signing"]
+        );
+    }
+}
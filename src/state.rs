@@ -0,0 +1,2185 @@
+//! The SQLite state store: PR syncs, pipelines, jobs, deployments and the
+//! admin API's records.
+use crate::cluster;
+use crate::config;
+use crate::errors::GitError;
+
+use log::info;
+use rusqlite::{params, Connection, OptionalExtension};
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// A PR head which was pushed to GitLab
+#[derive(Debug, PartialEq)]
+pub struct PrSync {
+    pub github_repo: String,
+    pub pr_number: i64,
+    pub head_sha: String,
+    pub gitlab_project: String,
+    pub gitlab_branch: String,
+    pub synced_at: i64,
+}
+
+/// A GitLab pipeline, as last seen by LabHub
+#[derive(Debug, PartialEq)]
+pub struct Pipeline {
+    pub gitlab_project: String,
+    pub pipeline_id: i64,
+    pub sha: String,
+    pub status: String,
+    pub updated_at: i64,
+}
+
+/// How long a successful GitLab pipeline took
+#[derive(Debug, PartialEq)]
+pub struct PipelineDuration {
+    pub gitlab_project: String,
+    pub pipeline_id: i64,
+    pub ref_name: String,
+    pub duration_secs: i64,
+    pub finished_at: i64,
+}
+
+/// A GitLab CI job, as last seen by LabHub
+#[derive(Debug, PartialEq)]
+pub struct Job {
+    pub gitlab_project: String,
+    pub job_id: i64,
+    pub pipeline_id: i64,
+    pub job_name: String,
+    pub status: String,
+    /// Whether LabHub retried it as flaky
+    pub auto_retried: bool,
+    pub updated_at: i64,
+}
+
+/// A GitLab deployment of a PR branch, e.g. to a review app
+#[derive(Debug, PartialEq)]
+pub struct Deployment {
+    pub gitlab_project: String,
+    pub deployment_id: i64,
+    pub gitlab_branch: String,
+    pub environment: String,
+    pub url: Option<String>,
+    pub status: String,
+    /// The GitHub deployment it's bridged to, if any
+    pub github_deployment_id: Option<i64>,
+    pub updated_at: i64,
+}
+
+/// A repo whose mirroring is paused
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+pub struct PausedRepo {
+    pub repo: String,
+    pub paused_by: String,
+    pub paused_at: i64,
+}
+
+/// A kill switch which is engaged, see [`crate::killswitch`]
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+pub struct EngagedSwitch {
+    pub name: String,
+    pub engaged_by: String,
+    pub engaged_at: i64,
+}
+
+/// Something LabHub did to a PR, see [`crate::history`]
+#[derive(Debug, PartialEq, Serialize)]
+pub struct PrAction {
+    pub action: String,
+    pub detail: String,
+    pub at: i64,
+}
+
+/// The GitLab branch of a closed PR, kept for a while rather than deleted
+/// right away
+#[derive(Debug, PartialEq)]
+pub struct RetainedBranch {
+    pub gitlab_project: String,
+    pub branch: String,
+    pub closed_at: i64,
+}
+
+/// A PR head which a maintainer approved syncing, see [`crate::protected`]
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+pub struct Approval {
+    pub github_repo: String,
+    pub pr_number: i64,
+    pub head_sha: String,
+    pub approved_by: String,
+    pub approved_at: i64,
+}
+
+/// The GitLab merge request opened for a PR, with the repo's
+/// `merge_requests` set
+#[derive(Debug, PartialEq)]
+pub struct MergeRequest {
+    pub github_repo: String,
+    pub pr_number: i64,
+    pub gitlab_project: String,
+    pub iid: i64,
+}
+
+/// A comment mirrored between a PR and its merge request, with the
+/// `comment_mirroring` feature
+#[derive(Debug, PartialEq)]
+pub struct MirroredComment {
+    pub github_repo: String,
+    pub pr_number: i64,
+    pub github_comment_id: i64,
+    pub gitlab_project: String,
+    pub gitlab_note_id: i64,
+}
+
+/// An entry of the audit log, see [`crate::audit`]
+#[derive(Debug, PartialEq, Serialize)]
+pub struct AuditEntry {
+    pub id: i64,
+    pub at: i64,
+    pub actor: String,
+    pub repo: String,
+    pub pr_number: Option<i64>,
+    pub action: String,
+    /// What was acted on, e.g. a branch or a pipeline
+    pub target: String,
+    /// "success" or "failure"
+    pub outcome: String,
+    /// Why it failed, for failures
+    pub error: Option<String>,
+}
+
+/// Which audit log entries to return, all of them by default
+#[derive(Debug, Default, Deserialize)]
+pub struct AuditFilter {
+    pub repo: Option<String>,
+    pub pr_number: Option<i64>,
+    pub action: Option<String>,
+    pub actor: Option<String>,
+    /// Only entries from this time on, in seconds since the epoch
+    pub since: Option<i64>,
+    /// "success" or "failure"
+    pub outcome: Option<String>,
+    pub limit: Option<i64>,
+}
+
+/// A verified webhook payload, kept for replaying it
+#[derive(Debug, PartialEq, Serialize)]
+pub struct WebhookPayload {
+    pub id: i64,
+    /// "github", "gitlab", "gitea" or "bitbucket"
+    pub source: String,
+    pub event_type: String,
+    #[serde(skip)]
+    pub body: String,
+    pub received_at: i64,
+    /// JSON pointer to where the payload couldn't be parsed, if it couldn't
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error_pointer: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+const SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS pr_syncs (
+    github_repo TEXT NOT NULL,
+    pr_number INTEGER NOT NULL,
+    head_sha TEXT NOT NULL,
+    gitlab_project TEXT NOT NULL,
+    gitlab_branch TEXT NOT NULL,
+    synced_at INTEGER NOT NULL,
+    PRIMARY KEY (github_repo, pr_number, head_sha)
+);
+CREATE TABLE IF NOT EXISTS pipelines (
+    gitlab_project TEXT NOT NULL,
+    pipeline_id INTEGER NOT NULL,
+    sha TEXT NOT NULL,
+    status TEXT NOT NULL,
+    updated_at INTEGER NOT NULL,
+    PRIMARY KEY (gitlab_project, pipeline_id)
+);
+CREATE INDEX IF NOT EXISTS pipelines_sha ON pipelines (gitlab_project, sha);
+CREATE TABLE IF NOT EXISTS pipeline_durations (
+    gitlab_project TEXT NOT NULL,
+    pipeline_id INTEGER NOT NULL,
+    ref_name TEXT NOT NULL,
+    duration_secs INTEGER NOT NULL,
+    finished_at INTEGER NOT NULL,
+    PRIMARY KEY (gitlab_project, pipeline_id)
+);
+CREATE INDEX IF NOT EXISTS pipeline_durations_ref ON pipeline_durations (gitlab_project, ref_name);
+CREATE TABLE IF NOT EXISTS pipeline_comments (
+    gitlab_project TEXT NOT NULL,
+    pipeline_id INTEGER NOT NULL,
+    comment_id INTEGER NOT NULL,
+    PRIMARY KEY (gitlab_project, pipeline_id)
+);
+CREATE TABLE IF NOT EXISTS jobs (
+    gitlab_project TEXT NOT NULL,
+    job_id INTEGER NOT NULL,
+    pipeline_id INTEGER NOT NULL,
+    job_name TEXT NOT NULL,
+    status TEXT NOT NULL,
+    auto_retried INTEGER NOT NULL,
+    updated_at INTEGER NOT NULL,
+    PRIMARY KEY (gitlab_project, job_id)
+);
+CREATE INDEX IF NOT EXISTS jobs_name ON jobs (gitlab_project, job_name);
+CREATE TABLE IF NOT EXISTS deployments (
+    gitlab_project TEXT NOT NULL,
+    deployment_id INTEGER NOT NULL,
+    gitlab_branch TEXT NOT NULL,
+    environment TEXT NOT NULL,
+    url TEXT,
+    status TEXT NOT NULL,
+    github_deployment_id INTEGER,
+    updated_at INTEGER NOT NULL,
+    PRIMARY KEY (gitlab_project, deployment_id)
+);
+CREATE INDEX IF NOT EXISTS deployments_branch ON deployments (gitlab_project, gitlab_branch);
+CREATE TABLE IF NOT EXISTS paused_repos (
+    repo TEXT PRIMARY KEY,
+    paused_by TEXT NOT NULL,
+    paused_at INTEGER NOT NULL
+);
+CREATE TABLE IF NOT EXISTS kill_switches (
+    name TEXT PRIMARY KEY,
+    engaged_by TEXT NOT NULL,
+    engaged_at INTEGER NOT NULL
+);
+CREATE TABLE IF NOT EXISTS pr_actions (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    github_repo TEXT NOT NULL,
+    pr_number INTEGER NOT NULL,
+    action TEXT NOT NULL,
+    detail TEXT NOT NULL,
+    at INTEGER NOT NULL
+);
+CREATE INDEX IF NOT EXISTS pr_actions_pr ON pr_actions (github_repo, pr_number);
+CREATE TABLE IF NOT EXISTS approvals (
+    github_repo TEXT NOT NULL,
+    pr_number INTEGER NOT NULL,
+    head_sha TEXT NOT NULL,
+    approved_by TEXT NOT NULL,
+    approved_at INTEGER NOT NULL,
+    PRIMARY KEY (github_repo, pr_number, head_sha)
+);
+CREATE TABLE IF NOT EXISTS merge_requests (
+    github_repo TEXT NOT NULL,
+    pr_number INTEGER NOT NULL,
+    gitlab_project TEXT NOT NULL,
+    iid INTEGER NOT NULL,
+    PRIMARY KEY (github_repo, pr_number)
+);
+CREATE TABLE IF NOT EXISTS mirrored_comments (
+    github_repo TEXT NOT NULL,
+    pr_number INTEGER NOT NULL,
+    github_comment_id INTEGER NOT NULL,
+    gitlab_project TEXT NOT NULL,
+    gitlab_note_id INTEGER NOT NULL,
+    PRIMARY KEY (github_repo, github_comment_id),
+    UNIQUE (gitlab_project, gitlab_note_id)
+);
+CREATE TABLE IF NOT EXISTS retained_branches (
+    gitlab_project TEXT NOT NULL,
+    branch TEXT NOT NULL,
+    closed_at INTEGER NOT NULL,
+    PRIMARY KEY (gitlab_project, branch)
+);
+CREATE TABLE IF NOT EXISTS audit_log (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    at INTEGER NOT NULL,
+    actor TEXT NOT NULL,
+    repo TEXT NOT NULL,
+    pr_number INTEGER,
+    action TEXT NOT NULL,
+    target TEXT NOT NULL,
+    outcome TEXT NOT NULL,
+    error TEXT
+);
+CREATE INDEX IF NOT EXISTS audit_log_repo ON audit_log (repo, pr_number);
+CREATE TRIGGER IF NOT EXISTS audit_log_no_update BEFORE UPDATE ON audit_log
+BEGIN
+    SELECT RAISE(ABORT, 'the audit log is append-only');
+END;
+CREATE TRIGGER IF NOT EXISTS audit_log_no_delete BEFORE DELETE ON audit_log
+BEGIN
+    SELECT RAISE(ABORT, 'the audit log is append-only');
+END;
+CREATE TABLE IF NOT EXISTS webhook_payloads (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    source TEXT NOT NULL,
+    event_type TEXT NOT NULL,
+    body TEXT NOT NULL,
+    received_at INTEGER NOT NULL
+);
+CREATE TABLE IF NOT EXISTS payload_errors (
+    payload_id INTEGER PRIMARY KEY NOT NULL,
+    pointer TEXT NOT NULL,
+    error TEXT NOT NULL
+);
+CREATE TABLE IF NOT EXISTS pending_events (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    kind TEXT NOT NULL,
+    event TEXT NOT NULL
+);
+CREATE TABLE IF NOT EXISTS deliveries (
+    delivery_id TEXT PRIMARY KEY NOT NULL,
+    received_at INTEGER NOT NULL
+);
+";
+
+fn open(path: Option<&str>) -> Result<Connection, GitError> {
+    let conn = match path {
+        Some(path) => Connection::open(path)?,
+        None => Connection::open_in_memory()?,
+    };
+    conn.execute_batch(SCHEMA)?;
+    Ok(conn)
+}
+
+/// The Redis hashes keeping what all replicas must agree on, when they
+/// share one, instead of the state store
+const SHARED_PAUSED_REPOS: &str = "paused_repos";
+const SHARED_KILL_SWITCHES: &str = "kill_switches";
+const SHARED_APPROVALS: &str = "approvals";
+
+lazy_static! {
+    static ref DB: Mutex<Connection> = {
+        let path = config::CONFIG.state.database.as_deref();
+        info!("Opening state store {}", path.unwrap_or(":memory:"));
+        Mutex::new(open(path).expect("Unable to open the state store"))
+    };
+}
+
+fn now() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs() as i64)
+        .unwrap_or(0)
+}
+
+fn insert_pr_sync(conn: &Connection, sync: &PrSync) -> Result<(), GitError> {
+    conn.execute(
+        "INSERT OR REPLACE INTO pr_syncs
+         (github_repo, pr_number, head_sha, gitlab_project, gitlab_branch, synced_at)
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+        params![
+            sync.github_repo,
+            sync.pr_number,
+            sync.head_sha,
+            sync.gitlab_project,
+            sync.gitlab_branch,
+            sync.synced_at
+        ],
+    )?;
+    Ok(())
+}
+
+fn select_latest_pr_sync(
+    conn: &Connection,
+    github_repo: &str,
+    pr_number: i64,
+) -> Result<Option<PrSync>, GitError> {
+    Ok(conn
+        .query_row(
+            "SELECT github_repo, pr_number, head_sha, gitlab_project, gitlab_branch, synced_at
+             FROM pr_syncs WHERE github_repo = ?1 AND pr_number = ?2
+             ORDER BY synced_at DESC, rowid DESC LIMIT 1",
+            params![github_repo, pr_number],
+            |row| {
+                Ok(PrSync {
+                    github_repo: row.get(0)?,
+                    pr_number: row.get(1)?,
+                    head_sha: row.get(2)?,
+                    gitlab_project: row.get(3)?,
+                    gitlab_branch: row.get(4)?,
+                    synced_at: row.get(5)?,
+                })
+            },
+        )
+        .optional()?)
+}
+
+fn select_recent_pr_syncs(
+    conn: &Connection,
+    github_repo: Option<&str>,
+    pr_number: Option<i64>,
+    limit: i64,
+) -> Result<Vec<PrSync>, GitError> {
+    let mut stmt = conn.prepare(
+        "SELECT github_repo, pr_number, head_sha, gitlab_project, gitlab_branch, synced_at
+         FROM pr_syncs WHERE (?1 IS NULL OR github_repo = ?1) AND (?2 IS NULL OR pr_number = ?2)
+         ORDER BY synced_at DESC, rowid DESC LIMIT ?3",
+    )?;
+    let rows = stmt.query_map(params![github_repo, pr_number, limit], |row| {
+        Ok(PrSync {
+            github_repo: row.get(0)?,
+            pr_number: row.get(1)?,
+            head_sha: row.get(2)?,
+            gitlab_project: row.get(3)?,
+            gitlab_branch: row.get(4)?,
+            synced_at: row.get(5)?,
+        })
+    })?;
+    Ok(rows.collect::<Result<Vec<_>, _>>()?)
+}
+
+fn upsert_pipeline(conn: &Connection, pipeline: &Pipeline) -> Result<(), GitError> {
+    conn.execute(
+        "INSERT OR REPLACE INTO pipelines
+         (gitlab_project, pipeline_id, sha, status, updated_at)
+         VALUES (?1, ?2, ?3, ?4, ?5)",
+        params![
+            pipeline.gitlab_project,
+            pipeline.pipeline_id,
+            pipeline.sha,
+            pipeline.status,
+            pipeline.updated_at
+        ],
+    )?;
+    Ok(())
+}
+
+fn select_latest_pipeline(
+    conn: &Connection,
+    gitlab_project: &str,
+    sha: &str,
+) -> Result<Option<Pipeline>, GitError> {
+    Ok(conn
+        .query_row(
+            "SELECT gitlab_project, pipeline_id, sha, status, updated_at
+             FROM pipelines WHERE gitlab_project = ?1 AND sha = ?2
+             ORDER BY pipeline_id DESC LIMIT 1",
+            params![gitlab_project, sha],
+            |row| {
+                Ok(Pipeline {
+                    gitlab_project: row.get(0)?,
+                    pipeline_id: row.get(1)?,
+                    sha: row.get(2)?,
+                    status: row.get(3)?,
+                    updated_at: row.get(4)?,
+                })
+            },
+        )
+        .optional()?)
+}
+
+fn select_recent_pipelines(
+    conn: &Connection,
+    gitlab_project: Option<&str>,
+    sha: Option<&str>,
+    limit: i64,
+) -> Result<Vec<Pipeline>, GitError> {
+    let mut stmt = conn.prepare(
+        "SELECT gitlab_project, pipeline_id, sha, status, updated_at
+         FROM pipelines WHERE (?1 IS NULL OR gitlab_project = ?1) AND (?2 IS NULL OR sha = ?2)
+         ORDER BY updated_at DESC, pipeline_id DESC LIMIT ?3",
+    )?;
+    let rows = stmt.query_map(params![gitlab_project, sha, limit], |row| {
+        Ok(Pipeline {
+            gitlab_project: row.get(0)?,
+            pipeline_id: row.get(1)?,
+            sha: row.get(2)?,
+            status: row.get(3)?,
+            updated_at: row.get(4)?,
+        })
+    })?;
+    Ok(rows.collect::<Result<Vec<_>, _>>()?)
+}
+
+fn insert_pipeline_duration(
+    conn: &Connection,
+    duration: &PipelineDuration,
+) -> Result<(), GitError> {
+    conn.execute(
+        "INSERT OR REPLACE INTO pipeline_durations
+         (gitlab_project, pipeline_id, ref_name, duration_secs, finished_at)
+         VALUES (?1, ?2, ?3, ?4, ?5)",
+        params![
+            duration.gitlab_project,
+            duration.pipeline_id,
+            duration.ref_name,
+            duration.duration_secs,
+            duration.finished_at
+        ],
+    )?;
+    Ok(())
+}
+
+fn select_pipeline_duration(
+    conn: &Connection,
+    gitlab_project: &str,
+    pipeline_id: i64,
+) -> Result<Option<i64>, GitError> {
+    Ok(conn
+        .query_row(
+            "SELECT duration_secs FROM pipeline_durations
+             WHERE gitlab_project = ?1 AND pipeline_id = ?2",
+            params![gitlab_project, pipeline_id],
+            |row| row.get(0),
+        )
+        .optional()?)
+}
+
+fn select_recent_durations(
+    conn: &Connection,
+    gitlab_project: &str,
+    ref_name: &str,
+    limit: usize,
+) -> Result<Vec<i64>, GitError> {
+    let mut stmt = conn.prepare(
+        "SELECT duration_secs FROM pipeline_durations
+         WHERE gitlab_project = ?1 AND ref_name = ?2
+         ORDER BY finished_at DESC, pipeline_id DESC LIMIT ?3",
+    )?;
+    let rows = stmt.query_map(params![gitlab_project, ref_name, limit as i64], |row| {
+        row.get(0)
+    })?;
+    Ok(rows.collect::<Result<Vec<_>, _>>()?)
+}
+
+fn upsert_pipeline_comment(
+    conn: &Connection,
+    gitlab_project: &str,
+    pipeline_id: i64,
+    comment_id: i64,
+) -> Result<(), GitError> {
+    conn.execute(
+        "INSERT OR REPLACE INTO pipeline_comments (gitlab_project, pipeline_id, comment_id)
+         VALUES (?1, ?2, ?3)",
+        params![gitlab_project, pipeline_id, comment_id],
+    )?;
+    Ok(())
+}
+
+fn select_pipeline_comment(
+    conn: &Connection,
+    gitlab_project: &str,
+    pipeline_id: i64,
+) -> Result<Option<i64>, GitError> {
+    Ok(conn
+        .query_row(
+            "SELECT comment_id FROM pipeline_comments
+             WHERE gitlab_project = ?1 AND pipeline_id = ?2",
+            params![gitlab_project, pipeline_id],
+            |row| row.get(0),
+        )
+        .optional()?)
+}
+
+/// Inserts or updates a job, keeping whether it was auto-retried
+fn upsert_job(conn: &Connection, job: &Job) -> Result<(), GitError> {
+    conn.execute(
+        "INSERT INTO jobs
+         (gitlab_project, job_id, pipeline_id, job_name, status, auto_retried, updated_at)
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)
+         ON CONFLICT (gitlab_project, job_id) DO UPDATE SET
+         status = excluded.status,
+         auto_retried = auto_retried OR excluded.auto_retried,
+         updated_at = excluded.updated_at",
+        params![
+            job.gitlab_project,
+            job.job_id,
+            job.pipeline_id,
+            job.job_name,
+            job.status,
+            job.auto_retried,
+            job.updated_at
+        ],
+    )?;
+    Ok(())
+}
+
+/// Counts the pipelines in which a job failed, then passed on a retry
+fn select_passes_on_retry(
+    conn: &Connection,
+    gitlab_project: &str,
+    job_name: &str,
+) -> Result<i64, GitError> {
+    Ok(conn.query_row(
+        "SELECT COUNT(DISTINCT failed.pipeline_id) FROM jobs failed
+         JOIN jobs passed ON passed.gitlab_project = failed.gitlab_project
+         AND passed.pipeline_id = failed.pipeline_id
+         AND passed.job_name = failed.job_name
+         AND passed.job_id > failed.job_id
+         WHERE failed.gitlab_project = ?1 AND failed.job_name = ?2
+         AND failed.status = 'failed' AND passed.status = 'success'",
+        params![gitlab_project, job_name],
+        |row| row.get(0),
+    )?)
+}
+
+fn select_auto_retried(
+    conn: &Connection,
+    gitlab_project: &str,
+    pipeline_id: i64,
+    job_name: &str,
+) -> Result<bool, GitError> {
+    Ok(conn
+        .query_row(
+            "SELECT 1 FROM jobs WHERE gitlab_project = ?1 AND pipeline_id = ?2
+             AND job_name = ?3 AND auto_retried LIMIT 1",
+            params![gitlab_project, pipeline_id, job_name],
+            |_| Ok(()),
+        )
+        .optional()?
+        .is_some())
+}
+
+/// Inserts or updates a deployment, keeping its GitHub deployment ID unless
+/// a new one is given
+fn upsert_deployment(conn: &Connection, deployment: &Deployment) -> Result<(), GitError> {
+    conn.execute(
+        "INSERT INTO deployments
+         (gitlab_project, deployment_id, gitlab_branch, environment, url, status,
+          github_deployment_id, updated_at)
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)
+         ON CONFLICT (gitlab_project, deployment_id) DO UPDATE SET
+         url = excluded.url, status = excluded.status, updated_at = excluded.updated_at,
+         github_deployment_id = COALESCE(excluded.github_deployment_id, github_deployment_id)",
+        params![
+            deployment.gitlab_project,
+            deployment.deployment_id,
+            deployment.gitlab_branch,
+            deployment.environment,
+            deployment.url,
+            deployment.status,
+            deployment.github_deployment_id,
+            deployment.updated_at
+        ],
+    )?;
+    Ok(())
+}
+
+fn deployment_from_row(row: &rusqlite::Row) -> rusqlite::Result<Deployment> {
+    Ok(Deployment {
+        gitlab_project: row.get(0)?,
+        deployment_id: row.get(1)?,
+        gitlab_branch: row.get(2)?,
+        environment: row.get(3)?,
+        url: row.get(4)?,
+        status: row.get(5)?,
+        github_deployment_id: row.get(6)?,
+        updated_at: row.get(7)?,
+    })
+}
+
+const DEPLOYMENT_COLUMNS: &str = "gitlab_project, deployment_id, gitlab_branch, environment, \
+    url, status, github_deployment_id, updated_at";
+
+fn select_deployment(
+    conn: &Connection,
+    gitlab_project: &str,
+    deployment_id: i64,
+) -> Result<Option<Deployment>, GitError> {
+    Ok(conn
+        .query_row(
+            &format!(
+                "SELECT {} FROM deployments WHERE gitlab_project = ?1 AND deployment_id = ?2",
+                DEPLOYMENT_COLUMNS
+            ),
+            params![gitlab_project, deployment_id],
+            deployment_from_row,
+        )
+        .optional()?)
+}
+
+fn select_latest_deployment(
+    conn: &Connection,
+    gitlab_project: &str,
+    gitlab_branch: &str,
+) -> Result<Option<Deployment>, GitError> {
+    Ok(conn
+        .query_row(
+            &format!(
+                "SELECT {} FROM deployments WHERE gitlab_project = ?1 AND gitlab_branch = ?2
+                 ORDER BY deployment_id DESC LIMIT 1",
+                DEPLOYMENT_COLUMNS
+            ),
+            params![gitlab_project, gitlab_branch],
+            deployment_from_row,
+        )
+        .optional()?)
+}
+
+fn insert_paused_repo(conn: &Connection, paused: &PausedRepo) -> Result<(), GitError> {
+    conn.execute(
+        "INSERT OR REPLACE INTO paused_repos (repo, paused_by, paused_at) VALUES (?1, ?2, ?3)",
+        params![paused.repo, paused.paused_by, paused.paused_at],
+    )?;
+    Ok(())
+}
+
+/// Returns true if the repo was paused
+fn delete_paused_repo(conn: &Connection, repo: &str) -> Result<bool, GitError> {
+    Ok(conn.execute("DELETE FROM paused_repos WHERE repo = ?1", params![repo])? > 0)
+}
+
+fn select_paused_repos(conn: &Connection) -> Result<Vec<PausedRepo>, GitError> {
+    let mut stmt =
+        conn.prepare("SELECT repo, paused_by, paused_at FROM paused_repos ORDER BY repo")?;
+    let rows = stmt.query_map([], |row| {
+        Ok(PausedRepo {
+            repo: row.get(0)?,
+            paused_by: row.get(1)?,
+            paused_at: row.get(2)?,
+        })
+    })?;
+    Ok(rows.collect::<Result<Vec<_>, _>>()?)
+}
+
+fn insert_kill_switch(conn: &Connection, switch: &EngagedSwitch) -> Result<(), GitError> {
+    conn.execute(
+        "INSERT OR REPLACE INTO kill_switches (name, engaged_by, engaged_at) VALUES (?1, ?2, ?3)",
+        params![switch.name, switch.engaged_by, switch.engaged_at],
+    )?;
+    Ok(())
+}
+
+/// Returns true if the switch was engaged
+fn delete_kill_switch(conn: &Connection, name: &str) -> Result<bool, GitError> {
+    Ok(conn.execute("DELETE FROM kill_switches WHERE name = ?1", params![name])? > 0)
+}
+
+fn select_kill_switches(conn: &Connection) -> Result<Vec<EngagedSwitch>, GitError> {
+    let mut stmt =
+        conn.prepare("SELECT name, engaged_by, engaged_at FROM kill_switches ORDER BY name")?;
+    let rows = stmt.query_map([], |row| {
+        Ok(EngagedSwitch {
+            name: row.get(0)?,
+            engaged_by: row.get(1)?,
+            engaged_at: row.get(2)?,
+        })
+    })?;
+    Ok(rows.collect::<Result<Vec<_>, _>>()?)
+}
+
+fn insert_pr_action(
+    conn: &Connection,
+    github_repo: &str,
+    pr_number: i64,
+    action: &PrAction,
+) -> Result<(), GitError> {
+    conn.execute(
+        "INSERT INTO pr_actions (github_repo, pr_number, action, detail, at)
+         VALUES (?1, ?2, ?3, ?4, ?5)",
+        params![
+            github_repo,
+            pr_number,
+            action.action,
+            action.detail,
+            action.at
+        ],
+    )?;
+    Ok(())
+}
+
+fn select_pr_actions(
+    conn: &Connection,
+    github_repo: &str,
+    pr_number: i64,
+) -> Result<Vec<PrAction>, GitError> {
+    let mut stmt = conn.prepare(
+        "SELECT action, detail, at FROM pr_actions
+         WHERE github_repo = ?1 AND pr_number = ?2 ORDER BY id",
+    )?;
+    let rows = stmt.query_map(params![github_repo, pr_number], |row| {
+        Ok(PrAction {
+            action: row.get(0)?,
+            detail: row.get(1)?,
+            at: row.get(2)?,
+        })
+    })?;
+    Ok(rows.collect::<Result<Vec<_>, _>>()?)
+}
+
+fn upsert_approval(conn: &Connection, approval: &Approval) -> Result<(), GitError> {
+    conn.execute(
+        "INSERT OR REPLACE INTO approvals
+         (github_repo, pr_number, head_sha, approved_by, approved_at)
+         VALUES (?1, ?2, ?3, ?4, ?5)",
+        params![
+            approval.github_repo,
+            approval.pr_number,
+            approval.head_sha,
+            approval.approved_by,
+            approval.approved_at
+        ],
+    )?;
+    Ok(())
+}
+
+fn select_approval(
+    conn: &Connection,
+    github_repo: &str,
+    pr_number: i64,
+    head_sha: &str,
+) -> Result<Option<Approval>, GitError> {
+    Ok(conn
+        .query_row(
+            "SELECT github_repo, pr_number, head_sha, approved_by, approved_at FROM approvals
+             WHERE github_repo = ?1 AND pr_number = ?2 AND head_sha = ?3",
+            params![github_repo, pr_number, head_sha],
+            |row| {
+                Ok(Approval {
+                    github_repo: row.get(0)?,
+                    pr_number: row.get(1)?,
+                    head_sha: row.get(2)?,
+                    approved_by: row.get(3)?,
+                    approved_at: row.get(4)?,
+                })
+            },
+        )
+        .optional()?)
+}
+
+fn upsert_merge_request(conn: &Connection, merge_request: &MergeRequest) -> Result<(), GitError> {
+    conn.execute(
+        "INSERT OR REPLACE INTO merge_requests (github_repo, pr_number, gitlab_project, iid)
+         VALUES (?1, ?2, ?3, ?4)",
+        params![
+            merge_request.github_repo,
+            merge_request.pr_number,
+            merge_request.gitlab_project,
+            merge_request.iid
+        ],
+    )?;
+    Ok(())
+}
+
+fn select_merge_request(
+    conn: &Connection,
+    github_repo: &str,
+    pr_number: i64,
+) -> Result<Option<MergeRequest>, GitError> {
+    Ok(conn
+        .query_row(
+            "SELECT github_repo, pr_number, gitlab_project, iid FROM merge_requests
+             WHERE github_repo = ?1 AND pr_number = ?2",
+            params![github_repo, pr_number],
+            |row| {
+                Ok(MergeRequest {
+                    github_repo: row.get(0)?,
+                    pr_number: row.get(1)?,
+                    gitlab_project: row.get(2)?,
+                    iid: row.get(3)?,
+                })
+            },
+        )
+        .optional()?)
+}
+
+fn select_merge_request_by_iid(
+    conn: &Connection,
+    gitlab_project: &str,
+    iid: i64,
+) -> Result<Option<MergeRequest>, GitError> {
+    Ok(conn
+        .query_row(
+            "SELECT github_repo, pr_number, gitlab_project, iid FROM merge_requests
+             WHERE gitlab_project = ?1 AND iid = ?2",
+            params![gitlab_project, iid],
+            |row| {
+                Ok(MergeRequest {
+                    github_repo: row.get(0)?,
+                    pr_number: row.get(1)?,
+                    gitlab_project: row.get(2)?,
+                    iid: row.get(3)?,
+                })
+            },
+        )
+        .optional()?)
+}
+
+fn delete_merge_request(
+    conn: &Connection,
+    github_repo: &str,
+    pr_number: i64,
+) -> Result<(), GitError> {
+    conn.execute(
+        "DELETE FROM merge_requests WHERE github_repo = ?1 AND pr_number = ?2",
+        params![github_repo, pr_number],
+    )?;
+    Ok(())
+}
+
+fn insert_mirrored_comment(conn: &Connection, comment: &MirroredComment) -> Result<(), GitError> {
+    conn.execute(
+        "INSERT OR REPLACE INTO mirrored_comments
+         (github_repo, pr_number, github_comment_id, gitlab_project, gitlab_note_id)
+         VALUES (?1, ?2, ?3, ?4, ?5)",
+        params![
+            comment.github_repo,
+            comment.pr_number,
+            comment.github_comment_id,
+            comment.gitlab_project,
+            comment.gitlab_note_id
+        ],
+    )?;
+    Ok(())
+}
+
+fn select_mirrored_comment(
+    conn: &Connection,
+    condition: &str,
+    params: &[&dyn rusqlite::ToSql],
+) -> Result<Option<MirroredComment>, GitError> {
+    Ok(conn
+        .query_row(
+            &format!(
+                "SELECT github_repo, pr_number, github_comment_id, gitlab_project, gitlab_note_id
+                 FROM mirrored_comments WHERE {}",
+                condition
+            ),
+            params,
+            |row| {
+                Ok(MirroredComment {
+                    github_repo: row.get(0)?,
+                    pr_number: row.get(1)?,
+                    github_comment_id: row.get(2)?,
+                    gitlab_project: row.get(3)?,
+                    gitlab_note_id: row.get(4)?,
+                })
+            },
+        )
+        .optional()?)
+}
+
+fn upsert_retained_branch(conn: &Connection, branch: &RetainedBranch) -> Result<(), GitError> {
+    conn.execute(
+        "INSERT OR REPLACE INTO retained_branches (gitlab_project, branch, closed_at)
+         VALUES (?1, ?2, ?3)",
+        params![branch.gitlab_project, branch.branch, branch.closed_at],
+    )?;
+    Ok(())
+}
+
+fn select_retained_branch(
+    conn: &Connection,
+    gitlab_project: &str,
+    branch: &str,
+) -> Result<Option<RetainedBranch>, GitError> {
+    Ok(conn
+        .query_row(
+            "SELECT gitlab_project, branch, closed_at FROM retained_branches
+             WHERE gitlab_project = ?1 AND branch = ?2",
+            params![gitlab_project, branch],
+            |row| {
+                Ok(RetainedBranch {
+                    gitlab_project: row.get(0)?,
+                    branch: row.get(1)?,
+                    closed_at: row.get(2)?,
+                })
+            },
+        )
+        .optional()?)
+}
+
+fn delete_retained_branch(
+    conn: &Connection,
+    gitlab_project: &str,
+    branch: &str,
+) -> Result<(), GitError> {
+    conn.execute(
+        "DELETE FROM retained_branches WHERE gitlab_project = ?1 AND branch = ?2",
+        params![gitlab_project, branch],
+    )?;
+    Ok(())
+}
+
+fn insert_audit_entry(conn: &Connection, entry: &AuditEntry) -> Result<(), GitError> {
+    conn.execute(
+        "INSERT INTO audit_log (at, actor, repo, pr_number, action, target, outcome, error)
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)",
+        params![
+            entry.at,
+            entry.actor,
+            entry.repo,
+            entry.pr_number,
+            entry.action,
+            entry.target,
+            entry.outcome,
+            entry.error
+        ],
+    )?;
+    Ok(())
+}
+
+/// Audit log entries matching `filter`, newest first
+fn select_audit_entries(
+    conn: &Connection,
+    filter: &AuditFilter,
+) -> Result<Vec<AuditEntry>, GitError> {
+    let mut stmt = conn.prepare(
+        "SELECT id, at, actor, repo, pr_number, action, target, outcome, error FROM audit_log
+         WHERE (?1 IS NULL OR repo = ?1) AND (?2 IS NULL OR pr_number = ?2)
+         AND (?3 IS NULL OR action = ?3) AND (?4 IS NULL OR actor = ?4)
+         AND (?5 IS NULL OR at >= ?5) AND (?6 IS NULL OR outcome = ?6)
+         ORDER BY id DESC LIMIT ?7",
+    )?;
+    let rows = stmt.query_map(
+        params![
+            filter.repo,
+            filter.pr_number,
+            filter.action,
+            filter.actor,
+            filter.since,
+            filter.outcome,
+            filter.limit.unwrap_or(-1)
+        ],
+        |row| {
+            Ok(AuditEntry {
+                id: row.get(0)?,
+                at: row.get(1)?,
+                actor: row.get(2)?,
+                repo: row.get(3)?,
+                pr_number: row.get(4)?,
+                action: row.get(5)?,
+                target: row.get(6)?,
+                outcome: row.get(7)?,
+                error: row.get(8)?,
+            })
+        },
+    )?;
+    Ok(rows.collect::<Result<Vec<_>, _>>()?)
+}
+
+/// Inserts a payload, keeping only the `keep` most recent ones, and the
+/// `keep` most recent ones which couldn't be parsed
+fn insert_webhook_payload(
+    conn: &Connection,
+    payload: &WebhookPayload,
+    keep: usize,
+) -> Result<i64, GitError> {
+    conn.execute(
+        "INSERT INTO webhook_payloads (source, event_type, body, received_at)
+         VALUES (?1, ?2, ?3, ?4)",
+        params![
+            payload.source,
+            payload.event_type,
+            payload.body,
+            payload.received_at
+        ],
+    )?;
+    let id = conn.last_insert_rowid();
+    conn.execute(
+        "DELETE FROM webhook_payloads WHERE id <= ?1 AND id NOT IN (
+             SELECT payload_id FROM payload_errors ORDER BY payload_id DESC LIMIT ?2
+         )",
+        params![id - keep as i64, keep as i64],
+    )?;
+    conn.execute(
+        "DELETE FROM payload_errors WHERE payload_id NOT IN (SELECT id FROM webhook_payloads)",
+        [],
+    )?;
+    Ok(id)
+}
+
+fn insert_payload_error(
+    conn: &Connection,
+    payload_id: i64,
+    pointer: &str,
+    error: &str,
+) -> Result<(), GitError> {
+    conn.execute(
+        "INSERT OR REPLACE INTO payload_errors (payload_id, pointer, error) VALUES (?1, ?2, ?3)",
+        params![payload_id, pointer, error],
+    )?;
+    Ok(())
+}
+
+fn select_webhook_payload(conn: &Connection, id: i64) -> Result<Option<WebhookPayload>, GitError> {
+    Ok(conn
+        .query_row(
+            "SELECT id, source, event_type, body, received_at, pointer, error
+             FROM webhook_payloads LEFT JOIN payload_errors ON payload_id = id
+             WHERE id = ?1",
+            params![id],
+            |row| {
+                Ok(WebhookPayload {
+                    id: row.get(0)?,
+                    source: row.get(1)?,
+                    event_type: row.get(2)?,
+                    body: row.get(3)?,
+                    received_at: row.get(4)?,
+                    error_pointer: row.get(5)?,
+                    error: row.get(6)?,
+                })
+            },
+        )
+        .optional()?)
+}
+
+/// The stored payloads, newest first, without their bodies
+fn select_webhook_payloads(conn: &Connection) -> Result<Vec<WebhookPayload>, GitError> {
+    let mut stmt = conn.prepare(
+        "SELECT id, source, event_type, received_at, pointer, error
+         FROM webhook_payloads LEFT JOIN payload_errors ON payload_id = id
+         ORDER BY id DESC",
+    )?;
+    let rows = stmt.query_map([], |row| {
+        Ok(WebhookPayload {
+            id: row.get(0)?,
+            source: row.get(1)?,
+            event_type: row.get(2)?,
+            body: String::new(),
+            received_at: row.get(3)?,
+            error_pointer: row.get(4)?,
+            error: row.get(5)?,
+        })
+    })?;
+    Ok(rows.collect::<Result<Vec<_>, _>>()?)
+}
+
+fn insert_pending_events(conn: &Connection, kind: &str, events: &[String]) -> Result<(), GitError> {
+    for event in events {
+        conn.execute(
+            "INSERT INTO pending_events (kind, event) VALUES (?1, ?2)",
+            params![kind, event],
+        )?;
+    }
+    Ok(())
+}
+
+/// Returns and forgets the stored events of a kind, oldest first
+fn take_pending_events(conn: &Connection, kind: &str) -> Result<Vec<String>, GitError> {
+    let events = {
+        let mut stmt =
+            conn.prepare("SELECT event FROM pending_events WHERE kind = ?1 ORDER BY id")?;
+        let rows = stmt.query_map(params![kind], |row| row.get(0))?;
+        rows.collect::<Result<Vec<String>, _>>()?
+    };
+    conn.execute("DELETE FROM pending_events WHERE kind = ?1", params![kind])?;
+    Ok(events)
+}
+
+fn insert_delivery(
+    conn: &Connection,
+    delivery_id: &str,
+    received_at: i64,
+    retention_secs: i64,
+) -> Result<bool, GitError> {
+    conn.execute(
+        "DELETE FROM deliveries WHERE received_at < ?1",
+        params![received_at - retention_secs],
+    )?;
+    let inserted = conn.execute(
+        "INSERT OR IGNORE INTO deliveries (delivery_id, received_at) VALUES (?1, ?2)",
+        params![delivery_id, received_at],
+    )?;
+    Ok(inserted > 0)
+}
+
+fn has_delivery(
+    conn: &Connection,
+    delivery_id: &str,
+    now: i64,
+    retention_secs: i64,
+) -> Result<bool, GitError> {
+    Ok(conn
+        .query_row(
+            "SELECT 1 FROM deliveries WHERE delivery_id = ?1 AND received_at >= ?2",
+            params![delivery_id, now - retention_secs],
+            |_| Ok(()),
+        )
+        .optional()?
+        .is_some())
+}
+
+/// Returns true if a webhook delivery ID was recorded within the retention
+pub fn delivery_recorded(delivery_id: &str) -> Result<bool, GitError> {
+    has_delivery(
+        &DB.lock().unwrap(),
+        delivery_id,
+        now(),
+        config::CONFIG.dedupe.delivery_retention_secs,
+    )
+}
+
+/// Records a webhook delivery ID, returning false if it was already seen
+pub fn record_delivery(delivery_id: &str) -> Result<bool, GitError> {
+    insert_delivery(
+        &DB.lock().unwrap(),
+        delivery_id,
+        now(),
+        config::CONFIG.dedupe.delivery_retention_secs,
+    )
+}
+
+pub fn engage_kill_switch(name: &str, engaged_by: &str) -> Result<(), GitError> {
+    let switch = EngagedSwitch {
+        name: name.to_string(),
+        engaged_by: engaged_by.to_string(),
+        engaged_at: now(),
+    };
+    if cluster::enabled() {
+        return cluster::shared_put(SHARED_KILL_SWITCHES, name, &switch);
+    }
+    insert_kill_switch(&DB.lock().unwrap(), &switch)
+}
+
+/// Releases a kill switch, returning false if it wasn't engaged
+pub fn release_kill_switch(name: &str) -> Result<bool, GitError> {
+    if cluster::enabled() {
+        return cluster::shared_remove(SHARED_KILL_SWITCHES, name);
+    }
+    delete_kill_switch(&DB.lock().unwrap(), name)
+}
+
+pub fn engaged_kill_switches() -> Result<Vec<EngagedSwitch>, GitError> {
+    if cluster::enabled() {
+        return cluster::shared_values(SHARED_KILL_SWITCHES);
+    }
+    select_kill_switches(&DB.lock().unwrap())
+}
+
+pub fn record_pr_action(
+    github_repo: &str,
+    pr_number: i64,
+    action: &str,
+    detail: &str,
+) -> Result<(), GitError> {
+    insert_pr_action(
+        &DB.lock().unwrap(),
+        github_repo,
+        pr_number,
+        &PrAction {
+            action: action.to_string(),
+            detail: detail.to_string(),
+            at: now(),
+        },
+    )
+}
+
+/// The actions taken on a PR, oldest first
+pub fn pr_actions(github_repo: &str, pr_number: i64) -> Result<Vec<PrAction>, GitError> {
+    select_pr_actions(&DB.lock().unwrap(), github_repo, pr_number)
+}
+
+/// Records that `approved_by` approved syncing a PR head
+pub fn record_approval(
+    github_repo: &str,
+    pr_number: i64,
+    head_sha: &str,
+    approved_by: &str,
+) -> Result<(), GitError> {
+    let approval = Approval {
+        github_repo: github_repo.to_string(),
+        pr_number,
+        head_sha: head_sha.to_string(),
+        approved_by: approved_by.to_string(),
+        approved_at: now(),
+    };
+    if cluster::enabled() {
+        return cluster::shared_put(
+            SHARED_APPROVALS,
+            &approval_field(github_repo, pr_number, head_sha),
+            &approval,
+        );
+    }
+    upsert_approval(&DB.lock().unwrap(), &approval)
+}
+
+pub fn approval(
+    github_repo: &str,
+    pr_number: i64,
+    head_sha: &str,
+) -> Result<Option<Approval>, GitError> {
+    if cluster::enabled() {
+        return cluster::shared_get(
+            SHARED_APPROVALS,
+            &approval_field(github_repo, pr_number, head_sha),
+        );
+    }
+    select_approval(&DB.lock().unwrap(), github_repo, pr_number, head_sha)
+}
+
+fn approval_field(github_repo: &str, pr_number: i64, head_sha: &str) -> String {
+    format!("{}#{}@{}", github_repo, pr_number, head_sha)
+}
+
+/// Records the GitLab merge request opened for a PR
+pub fn record_merge_request(
+    github_repo: &str,
+    pr_number: i64,
+    gitlab_project: &str,
+    iid: i64,
+) -> Result<(), GitError> {
+    upsert_merge_request(
+        &DB.lock().unwrap(),
+        &MergeRequest {
+            github_repo: github_repo.to_string(),
+            pr_number,
+            gitlab_project: gitlab_project.to_string(),
+            iid,
+        },
+    )
+}
+
+pub fn merge_request(github_repo: &str, pr_number: i64) -> Result<Option<MergeRequest>, GitError> {
+    select_merge_request(&DB.lock().unwrap(), github_repo, pr_number)
+}
+
+/// The PR a GitLab merge request was opened for
+pub fn merge_request_by_iid(
+    gitlab_project: &str,
+    iid: i64,
+) -> Result<Option<MergeRequest>, GitError> {
+    select_merge_request_by_iid(&DB.lock().unwrap(), gitlab_project, iid)
+}
+
+/// Forgets a PR's merge request, once it's closed
+pub fn forget_merge_request(github_repo: &str, pr_number: i64) -> Result<(), GitError> {
+    delete_merge_request(&DB.lock().unwrap(), github_repo, pr_number)
+}
+
+/// Records that a PR comment and a merge request note mirror each other
+pub fn record_mirrored_comment(comment: &MirroredComment) -> Result<(), GitError> {
+    insert_mirrored_comment(&DB.lock().unwrap(), comment)
+}
+
+/// The merge request note mirroring a PR comment, or mirrored by it
+pub fn mirrored_comment_by_github(
+    github_repo: &str,
+    github_comment_id: i64,
+) -> Result<Option<MirroredComment>, GitError> {
+    select_mirrored_comment(
+        &DB.lock().unwrap(),
+        "github_repo = ?1 AND github_comment_id = ?2",
+        params![github_repo, github_comment_id],
+    )
+}
+
+/// The PR comment mirroring a merge request note, or mirrored by it
+pub fn mirrored_comment_by_gitlab(
+    gitlab_project: &str,
+    gitlab_note_id: i64,
+) -> Result<Option<MirroredComment>, GitError> {
+    select_mirrored_comment(
+        &DB.lock().unwrap(),
+        "gitlab_project = ?1 AND gitlab_note_id = ?2",
+        params![gitlab_project, gitlab_note_id],
+    )
+}
+
+/// Records that a closed PR's branch is kept, from now on
+pub fn retain_branch(gitlab_project: &str, branch: &str) -> Result<(), GitError> {
+    upsert_retained_branch(
+        &DB.lock().unwrap(),
+        &RetainedBranch {
+            gitlab_project: gitlab_project.to_string(),
+            branch: branch.to_string(),
+            closed_at: now(),
+        },
+    )
+}
+
+pub fn retained_branch(
+    gitlab_project: &str,
+    branch: &str,
+) -> Result<Option<RetainedBranch>, GitError> {
+    select_retained_branch(&DB.lock().unwrap(), gitlab_project, branch)
+}
+
+/// Forgets a retained branch, once it's deleted
+pub fn forget_retained_branch(gitlab_project: &str, branch: &str) -> Result<(), GitError> {
+    delete_retained_branch(&DB.lock().unwrap(), gitlab_project, branch)
+}
+
+/// Appends an entry to the audit log, timestamped now
+pub fn record_audit_entry(entry: AuditEntry) -> Result<(), GitError> {
+    insert_audit_entry(&DB.lock().unwrap(), &AuditEntry { at: now(), ..entry })
+}
+
+/// Audit log entries matching `filter`, newest first
+pub fn audit_entries(filter: &AuditFilter) -> Result<Vec<AuditEntry>, GitError> {
+    select_audit_entries(&DB.lock().unwrap(), filter)
+}
+
+/// Stores a webhook payload for replaying it, returning its ID
+pub fn record_webhook_payload(
+    source: &str,
+    event_type: &str,
+    body: &str,
+    keep: usize,
+) -> Result<i64, GitError> {
+    insert_webhook_payload(
+        &DB.lock().unwrap(),
+        &WebhookPayload {
+            id: 0,
+            source: source.to_string(),
+            event_type: event_type.to_string(),
+            body: body.to_string(),
+            received_at: now(),
+            error_pointer: None,
+            error: None,
+        },
+        keep,
+    )
+}
+
+/// Records why a stored payload couldn't be parsed, which keeps it around
+/// for longer
+pub fn record_payload_error(payload_id: i64, pointer: &str, error: &str) -> Result<(), GitError> {
+    insert_payload_error(&DB.lock().unwrap(), payload_id, pointer, error)
+}
+
+pub fn webhook_payload(id: i64) -> Result<Option<WebhookPayload>, GitError> {
+    select_webhook_payload(&DB.lock().unwrap(), id)
+}
+
+pub fn webhook_payloads() -> Result<Vec<WebhookPayload>, GitError> {
+    select_webhook_payloads(&DB.lock().unwrap())
+}
+
+/// Stores events which weren't handled yet, e.g. on shutdown, where `kind`
+/// says what they were waiting for
+pub fn save_pending_events(kind: &str, events: &[String]) -> Result<(), GitError> {
+    insert_pending_events(&DB.lock().unwrap(), kind, events)
+}
+
+pub fn take_saved_events(kind: &str) -> Result<Vec<String>, GitError> {
+    take_pending_events(&DB.lock().unwrap(), kind)
+}
+
+/// Records that a PR head was pushed to its GitLab branch
+pub fn record_pr_sync(
+    github_repo: &str,
+    pr_number: i64,
+    head_sha: &str,
+    gitlab_project: &str,
+    gitlab_branch: &str,
+) -> Result<(), GitError> {
+    insert_pr_sync(
+        &DB.lock().unwrap(),
+        &PrSync {
+            github_repo: github_repo.to_string(),
+            pr_number,
+            head_sha: head_sha.to_string(),
+            gitlab_project: gitlab_project.to_string(),
+            gitlab_branch: gitlab_branch.to_string(),
+            synced_at: now(),
+        },
+    )
+}
+
+pub fn latest_pr_sync(github_repo: &str, pr_number: i64) -> Result<Option<PrSync>, GitError> {
+    select_latest_pr_sync(&DB.lock().unwrap(), github_repo, pr_number)
+}
+
+/// The latest PR syncs of a repo or PR, or of all repos, newest first
+pub fn recent_pr_syncs(
+    github_repo: Option<&str>,
+    pr_number: Option<i64>,
+    limit: i64,
+) -> Result<Vec<PrSync>, GitError> {
+    select_recent_pr_syncs(&DB.lock().unwrap(), github_repo, pr_number, limit)
+}
+
+/// Records the current status of a GitLab pipeline
+pub fn record_pipeline(
+    gitlab_project: &str,
+    pipeline_id: i64,
+    sha: &str,
+    status: &str,
+) -> Result<(), GitError> {
+    upsert_pipeline(
+        &DB.lock().unwrap(),
+        &Pipeline {
+            gitlab_project: gitlab_project.to_string(),
+            pipeline_id,
+            sha: sha.to_string(),
+            status: status.to_string(),
+            updated_at: now(),
+        },
+    )
+}
+
+/// Returns the most recent pipeline known for a commit
+pub fn latest_pipeline(gitlab_project: &str, sha: &str) -> Result<Option<Pipeline>, GitError> {
+    select_latest_pipeline(&DB.lock().unwrap(), gitlab_project, sha)
+}
+
+/// The most recently updated pipelines of a project or commit, or of all
+/// projects
+pub fn recent_pipelines(
+    gitlab_project: Option<&str>,
+    sha: Option<&str>,
+    limit: i64,
+) -> Result<Vec<Pipeline>, GitError> {
+    select_recent_pipelines(&DB.lock().unwrap(), gitlab_project, sha, limit)
+}
+
+/// Records how long a successful pipeline took
+pub fn record_pipeline_duration(
+    gitlab_project: &str,
+    pipeline_id: i64,
+    ref_name: &str,
+    duration_secs: i64,
+) -> Result<(), GitError> {
+    insert_pipeline_duration(
+        &DB.lock().unwrap(),
+        &PipelineDuration {
+            gitlab_project: gitlab_project.to_string(),
+            pipeline_id,
+            ref_name: ref_name.to_string(),
+            duration_secs,
+            finished_at: now(),
+        },
+    )
+}
+
+pub fn pipeline_duration(gitlab_project: &str, pipeline_id: i64) -> Result<Option<i64>, GitError> {
+    select_pipeline_duration(&DB.lock().unwrap(), gitlab_project, pipeline_id)
+}
+
+/// Returns the durations of the latest successful pipelines on a ref, most
+/// recent first
+pub fn recent_durations(
+    gitlab_project: &str,
+    ref_name: &str,
+    limit: usize,
+) -> Result<Vec<i64>, GitError> {
+    select_recent_durations(&DB.lock().unwrap(), gitlab_project, ref_name, limit)
+}
+
+/// Records the GitHub comment summarizing a pipeline, so re-runs of the
+/// pipeline update it
+pub fn record_pipeline_comment(
+    gitlab_project: &str,
+    pipeline_id: i64,
+    comment_id: i64,
+) -> Result<(), GitError> {
+    upsert_pipeline_comment(&DB.lock().unwrap(), gitlab_project, pipeline_id, comment_id)
+}
+
+/// Returns the GitHub comment summarizing a pipeline, if one was posted
+pub fn pipeline_comment(gitlab_project: &str, pipeline_id: i64) -> Result<Option<i64>, GitError> {
+    select_pipeline_comment(&DB.lock().unwrap(), gitlab_project, pipeline_id)
+}
+
+/// Records the current status of a GitLab job
+pub fn record_job(
+    gitlab_project: &str,
+    job_id: i64,
+    pipeline_id: i64,
+    job_name: &str,
+    status: &str,
+    auto_retried: bool,
+) -> Result<(), GitError> {
+    upsert_job(
+        &DB.lock().unwrap(),
+        &Job {
+            gitlab_project: gitlab_project.to_string(),
+            job_id,
+            pipeline_id,
+            job_name: job_name.to_string(),
+            status: status.to_string(),
+            auto_retried,
+            updated_at: now(),
+        },
+    )
+}
+
+/// How many pipelines a job failed in, then passed on a retry
+pub fn passes_on_retry(gitlab_project: &str, job_name: &str) -> Result<i64, GitError> {
+    select_passes_on_retry(&DB.lock().unwrap(), gitlab_project, job_name)
+}
+
+/// Whether LabHub already retried a job in a pipeline
+pub fn was_auto_retried(
+    gitlab_project: &str,
+    pipeline_id: i64,
+    job_name: &str,
+) -> Result<bool, GitError> {
+    select_auto_retried(&DB.lock().unwrap(), gitlab_project, pipeline_id, job_name)
+}
+
+/// Records the current status of a GitLab deployment
+pub fn record_deployment(
+    gitlab_project: &str,
+    deployment_id: i64,
+    gitlab_branch: &str,
+    environment: &str,
+    url: Option<&str>,
+    status: &str,
+) -> Result<(), GitError> {
+    upsert_deployment(
+        &DB.lock().unwrap(),
+        &Deployment {
+            gitlab_project: gitlab_project.to_string(),
+            deployment_id,
+            gitlab_branch: gitlab_branch.to_string(),
+            environment: environment.to_string(),
+            url: url.map(str::to_string),
+            status: status.to_string(),
+            github_deployment_id: None,
+            updated_at: now(),
+        },
+    )
+}
+
+pub fn deployment(
+    gitlab_project: &str,
+    deployment_id: i64,
+) -> Result<Option<Deployment>, GitError> {
+    select_deployment(&DB.lock().unwrap(), gitlab_project, deployment_id)
+}
+
+/// Records the GitHub deployment a GitLab deployment is bridged to
+pub fn set_github_deployment_id(
+    gitlab_project: &str,
+    deployment_id: i64,
+    github_deployment_id: i64,
+) -> Result<(), GitError> {
+    DB.lock().unwrap().execute(
+        "UPDATE deployments SET github_deployment_id = ?3
+         WHERE gitlab_project = ?1 AND deployment_id = ?2",
+        params![gitlab_project, deployment_id, github_deployment_id],
+    )?;
+    Ok(())
+}
+
+/// Returns the most recent deployment of a GitLab branch
+pub fn latest_deployment(
+    gitlab_project: &str,
+    gitlab_branch: &str,
+) -> Result<Option<Deployment>, GitError> {
+    select_latest_deployment(&DB.lock().unwrap(), gitlab_project, gitlab_branch)
+}
+
+/// Pauses mirroring of a repo, where `repo` is its lookup key
+pub fn pause_repo(repo: &str, paused_by: &str) -> Result<(), GitError> {
+    let paused = PausedRepo {
+        repo: repo.to_string(),
+        paused_by: paused_by.to_string(),
+        paused_at: now(),
+    };
+    if cluster::enabled() {
+        return cluster::shared_put(SHARED_PAUSED_REPOS, repo, &paused);
+    }
+    insert_paused_repo(&DB.lock().unwrap(), &paused)
+}
+
+/// Resumes mirroring of a repo, returning false if it wasn't paused
+pub fn resume_repo(repo: &str) -> Result<bool, GitError> {
+    if cluster::enabled() {
+        return cluster::shared_remove(SHARED_PAUSED_REPOS, repo);
+    }
+    delete_paused_repo(&DB.lock().unwrap(), repo)
+}
+
+pub fn paused_repos() -> Result<Vec<PausedRepo>, GitError> {
+    if cluster::enabled() {
+        return cluster::shared_values(SHARED_PAUSED_REPOS);
+    }
+    select_paused_repos(&DB.lock().unwrap())
+}
+
+pub fn is_repo_paused(repo: &str) -> Result<bool, GitError> {
+    Ok(paused_repos()?.iter().any(|paused| paused.repo == repo))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_pr_syncs() {
+        let conn = open(None).unwrap();
+        assert_eq!(select_latest_pr_sync(&conn, "org/repo", 1).unwrap(), None);
+        for (sha, synced_at) in [("aaa", 1), ("bbb", 2)] {
+            insert_pr_sync(
+                &conn,
+                &PrSync {
+                    github_repo: "org/repo".into(),
+                    pr_number: 1,
+                    head_sha: sha.into(),
+                    gitlab_project: "group/repo".into(),
+                    gitlab_branch: "pr-1/fork/repo/branch".into(),
+                    synced_at,
+                },
+            )
+            .unwrap();
+        }
+        let latest = select_latest_pr_sync(&conn, "org/repo", 1)
+            .unwrap()
+            .unwrap();
+        assert_eq!(latest.head_sha, "bbb");
+        assert_eq!(latest.gitlab_branch, "pr-1/fork/repo/branch");
+        let recent: Vec<String> = select_recent_pr_syncs(&conn, None, None, 10)
+            .unwrap()
+            .into_iter()
+            .map(|sync| sync.head_sha)
+            .collect();
+        assert_eq!(recent, ["bbb", "aaa"]);
+        assert_eq!(
+            select_recent_pr_syncs(&conn, None, None, 1).unwrap().len(),
+            1
+        );
+        assert_eq!(
+            select_recent_pr_syncs(&conn, Some("org/repo"), Some(1), 10)
+                .unwrap()
+                .len(),
+            2
+        );
+        assert!(select_recent_pr_syncs(&conn, Some("org/repo"), Some(2), 10)
+            .unwrap()
+            .is_empty());
+    }
+
+    #[test]
+    fn test_kill_switches() {
+        let conn = open(None).unwrap();
+        for name in ["comments", "branches"] {
+            let switch = EngagedSwitch {
+                name: name.into(),
+                engaged_by: "admin API".into(),
+                engaged_at: 1,
+            };
+            insert_kill_switch(&conn, &switch).unwrap();
+        }
+        assert!(delete_kill_switch(&conn, "branches").unwrap());
+        assert!(!delete_kill_switch(&conn, "branches").unwrap());
+        let names: Vec<String> = select_kill_switches(&conn)
+            .unwrap()
+            .into_iter()
+            .map(|switch| switch.name)
+            .collect();
+        assert_eq!(names, ["comments"]);
+    }
+
+    #[test]
+    fn test_pr_actions() {
+        let conn = open(None).unwrap();
+        for (number, action, at) in [(1, "mirrored", 10), (2, "mirrored", 11), (1, "comment", 5)] {
+            let action = PrAction {
+                action: action.into(),
+                detail: String::new(),
+                at,
+            };
+            insert_pr_action(&conn, "org/repo", number, &action).unwrap();
+        }
+        let actions: Vec<String> = select_pr_actions(&conn, "org/repo", 1)
+            .unwrap()
+            .into_iter()
+            .map(|action| action.action)
+            .collect();
+        // in the order they were recorded
+        assert_eq!(actions, ["mirrored", "comment"]);
+        assert!(select_pr_actions(&conn, "org/other", 1).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_approvals() {
+        let conn = open(None).unwrap();
+        let approval = Approval {
+            github_repo: "org/repo".into(),
+            pr_number: 1,
+            head_sha: "abc".into(),
+            approved_by: "maintainer".into(),
+            approved_at: 10,
+        };
+        upsert_approval(&conn, &approval).unwrap();
+        assert_eq!(
+            select_approval(&conn, "org/repo", 1, "abc").unwrap(),
+            Some(approval)
+        );
+        // approvals are for one head only
+        assert_eq!(select_approval(&conn, "org/repo", 1, "def").unwrap(), None);
+        assert_eq!(select_approval(&conn, "org/repo", 2, "abc").unwrap(), None);
+    }
+
+    #[test]
+    fn test_merge_requests() {
+        let conn = open(None).unwrap();
+        let merge_request = |iid| MergeRequest {
+            github_repo: "org/repo".into(),
+            pr_number: 1,
+            gitlab_project: "group/repo".into(),
+            iid,
+        };
+        upsert_merge_request(&conn, &merge_request(7)).unwrap();
+        // a PR has at most one merge request
+        upsert_merge_request(&conn, &merge_request(8)).unwrap();
+        assert_eq!(
+            select_merge_request(&conn, "org/repo", 1).unwrap(),
+            Some(merge_request(8))
+        );
+        assert_eq!(select_merge_request(&conn, "org/repo", 2).unwrap(), None);
+        assert_eq!(
+            select_merge_request_by_iid(&conn, "group/repo", 8).unwrap(),
+            Some(merge_request(8))
+        );
+        assert_eq!(
+            select_merge_request_by_iid(&conn, "group/repo", 7).unwrap(),
+            None
+        );
+        delete_merge_request(&conn, "org/repo", 1).unwrap();
+        assert_eq!(select_merge_request(&conn, "org/repo", 1).unwrap(), None);
+    }
+
+    #[test]
+    fn test_mirrored_comments() {
+        let conn = open(None).unwrap();
+        let comment = MirroredComment {
+            github_repo: "org/repo".into(),
+            pr_number: 1,
+            github_comment_id: 100,
+            gitlab_project: "group/repo".into(),
+            gitlab_note_id: 200,
+        };
+        insert_mirrored_comment(&conn, &comment).unwrap();
+        assert_eq!(
+            select_mirrored_comment(
+                &conn,
+                "github_repo = ?1 AND github_comment_id = ?2",
+                params!["org/repo", 100],
+            )
+            .unwrap(),
+            Some(comment)
+        );
+        assert_eq!(
+            select_mirrored_comment(
+                &conn,
+                "gitlab_project = ?1 AND gitlab_note_id = ?2",
+                params!["group/repo", 100],
+            )
+            .unwrap(),
+            None
+        );
+    }
+
+    #[test]
+    fn test_retained_branches() {
+        let conn = open(None).unwrap();
+        let branch = |closed_at| RetainedBranch {
+            gitlab_project: "group/repo".into(),
+            branch: "pr-1/fork/repo/main".into(),
+            closed_at,
+        };
+        upsert_retained_branch(&conn, &branch(10)).unwrap();
+        // closing the PR again restarts the retention period
+        upsert_retained_branch(&conn, &branch(20)).unwrap();
+        assert_eq!(
+            select_retained_branch(&conn, "group/repo", "pr-1/fork/repo/main").unwrap(),
+            Some(branch(20))
+        );
+        assert_eq!(
+            select_retained_branch(&conn, "group/other", "pr-1/fork/repo/main").unwrap(),
+            None
+        );
+        delete_retained_branch(&conn, "group/repo", "pr-1/fork/repo/main").unwrap();
+        assert_eq!(
+            select_retained_branch(&conn, "group/repo", "pr-1/fork/repo/main").unwrap(),
+            None
+        );
+    }
+
+    #[test]
+    fn test_audit_log() {
+        let conn = open(None).unwrap();
+        for (at, actor, pr_number, action, outcome) in [
+            (10, "alice", Some(1), "ref_push", "success"),
+            (20, "labhub", Some(1), "comment", "failure"),
+            (30, "labhub", None, "pipeline_retry", "success"),
+        ] {
+            let entry = AuditEntry {
+                id: 0,
+                at,
+                actor: actor.into(),
+                repo: "org/repo".into(),
+                pr_number,
+                action: action.into(),
+                target: String::new(),
+                outcome: outcome.into(),
+                error: None,
+            };
+            insert_audit_entry(&conn, &entry).unwrap();
+        }
+        let actions = |filter: AuditFilter| -> Vec<String> {
+            select_audit_entries(&conn, &filter)
+                .unwrap()
+                .into_iter()
+                .map(|entry| entry.action)
+                .collect()
+        };
+        // newest first
+        assert_eq!(
+            actions(AuditFilter::default()),
+            ["pipeline_retry", "comment", "ref_push"]
+        );
+        let filter = AuditFilter {
+            pr_number: Some(1),
+            actor: Some("labhub".into()),
+            ..Default::default()
+        };
+        assert_eq!(actions(filter), ["comment"]);
+        let filter = AuditFilter {
+            since: Some(15),
+            limit: Some(1),
+            ..Default::default()
+        };
+        assert_eq!(actions(filter), ["pipeline_retry"]);
+        let filter = AuditFilter {
+            outcome: Some("failure".into()),
+            ..Default::default()
+        };
+        assert_eq!(actions(filter), ["comment"]);
+        let filter = AuditFilter {
+            repo: Some("org/other".into()),
+            ..Default::default()
+        };
+        assert!(actions(filter).is_empty());
+
+        // entries can't be changed or removed
+        assert!(conn
+            .execute("UPDATE audit_log SET actor = 'mallory'", [])
+            .is_err());
+        assert!(conn.execute("DELETE FROM audit_log", []).is_err());
+        assert_eq!(actions(AuditFilter::default()).len(), 3);
+    }
+
+    #[test]
+    fn test_webhook_payloads() {
+        let conn = open(None).unwrap();
+        for body in ["1", "2", "3"] {
+            let payload = WebhookPayload {
+                id: 0,
+                source: "github".into(),
+                event_type: "pull_request".into(),
+                body: body.into(),
+                received_at: 1,
+                error_pointer: None,
+                error: None,
+            };
+            insert_webhook_payload(&conn, &payload, 2).unwrap();
+            if body == "2" {
+                insert_payload_error(&conn, 2, "/number", "invalid type").unwrap();
+            }
+        }
+        // only the last 2 are kept
+        let ids: Vec<i64> = select_webhook_payloads(&conn)
+            .unwrap()
+            .into_iter()
+            .map(|payload| payload.id)
+            .collect();
+        assert_eq!(ids, [3, 2]);
+        assert_eq!(select_webhook_payload(&conn, 3).unwrap().unwrap().body, "3");
+        assert_eq!(select_webhook_payload(&conn, 1).unwrap(), None);
+
+        // as well as the last 2 which couldn't be parsed
+        for body in ["4", "5"] {
+            let payload = WebhookPayload {
+                id: 0,
+                source: "github".into(),
+                event_type: "pull_request".into(),
+                body: body.into(),
+                received_at: 1,
+                error_pointer: None,
+                error: None,
+            };
+            insert_webhook_payload(&conn, &payload, 2).unwrap();
+        }
+        let payloads = select_webhook_payloads(&conn).unwrap();
+        let ids: Vec<i64> = payloads.iter().map(|payload| payload.id).collect();
+        assert_eq!(ids, [5, 4, 2]);
+        assert_eq!(payloads[2].error_pointer.as_deref(), Some("/number"));
+        let failed = select_webhook_payload(&conn, 2).unwrap().unwrap();
+        assert_eq!(failed.body, "2");
+        assert_eq!(failed.error.as_deref(), Some("invalid type"));
+    }
+
+    #[test]
+    fn test_pending_events() {
+        let conn = open(None).unwrap();
+        insert_pending_events(&conn, "queued", &["a".to_string(), "b".to_string()]).unwrap();
+        insert_pending_events(&conn, "held", &["c".to_string()]).unwrap();
+        assert_eq!(take_pending_events(&conn, "queued").unwrap(), ["a", "b"]);
+        assert!(take_pending_events(&conn, "queued").unwrap().is_empty());
+        assert_eq!(take_pending_events(&conn, "held").unwrap(), ["c"]);
+    }
+
+    #[test]
+    fn test_deliveries() {
+        let conn = open(None).unwrap();
+        assert!(insert_delivery(&conn, "abc", 100, 50).unwrap());
+        assert!(!insert_delivery(&conn, "abc", 120, 50).unwrap());
+        assert!(has_delivery(&conn, "abc", 120, 50).unwrap());
+        assert!(!has_delivery(&conn, "def", 120, 50).unwrap());
+        // expired deliveries are forgotten
+        assert!(!has_delivery(&conn, "abc", 200, 50).unwrap());
+        assert!(insert_delivery(&conn, "abc", 200, 50).unwrap());
+    }
+
+    #[test]
+    fn test_pipelines() {
+        let conn = open(None).unwrap();
+        assert_eq!(
+            select_latest_pipeline(&conn, "group/repo", "aaa").unwrap(),
+            None
+        );
+        for (id, status) in [(10, "running"), (11, "pending"), (10, "failed")] {
+            upsert_pipeline(
+                &conn,
+                &Pipeline {
+                    gitlab_project: "group/repo".into(),
+                    pipeline_id: id,
+                    sha: "aaa".into(),
+                    status: status.into(),
+                    updated_at: 0,
+                },
+            )
+            .unwrap();
+        }
+        let latest = select_latest_pipeline(&conn, "group/repo", "aaa")
+            .unwrap()
+            .unwrap();
+        assert_eq!(latest.pipeline_id, 11);
+        assert_eq!(latest.status, "pending");
+        let recent: Vec<(i64, String)> = select_recent_pipelines(&conn, None, None, 10)
+            .unwrap()
+            .into_iter()
+            .map(|pipeline| (pipeline.pipeline_id, pipeline.status))
+            .collect();
+        assert_eq!(recent, [(11, "pending".into()), (10, "failed".into())]);
+        assert!(
+            select_recent_pipelines(&conn, Some("group/repo"), Some("bbb"), 10)
+                .unwrap()
+                .is_empty()
+        );
+    }
+
+    #[test]
+    fn test_pipeline_durations() {
+        let conn = open(None).unwrap();
+        for (id, ref_name, duration_secs, finished_at) in [
+            (1, "master", 100, 1),
+            (2, "master", 120, 2),
+            (3, "pr-1/fork/repo/branch", 300, 3),
+            (4, "master", 110, 4),
+        ] {
+            insert_pipeline_duration(
+                &conn,
+                &PipelineDuration {
+                    gitlab_project: "group/repo".into(),
+                    pipeline_id: id,
+                    ref_name: ref_name.into(),
+                    duration_secs,
+                    finished_at,
+                },
+            )
+            .unwrap();
+        }
+        assert_eq!(
+            select_recent_durations(&conn, "group/repo", "master", 2).unwrap(),
+            [110, 120]
+        );
+        assert_eq!(
+            select_pipeline_duration(&conn, "group/repo", 3).unwrap(),
+            Some(300)
+        );
+        assert_eq!(
+            select_pipeline_duration(&conn, "group/repo", 5).unwrap(),
+            None
+        );
+    }
+
+    #[test]
+    fn test_pipeline_comments() {
+        let conn = open(None).unwrap();
+        assert_eq!(
+            select_pipeline_comment(&conn, "group/repo", 10).unwrap(),
+            None
+        );
+        upsert_pipeline_comment(&conn, "group/repo", 10, 100).unwrap();
+        upsert_pipeline_comment(&conn, "group/repo", 10, 101).unwrap();
+        upsert_pipeline_comment(&conn, "group/repo", 11, 102).unwrap();
+        assert_eq!(
+            select_pipeline_comment(&conn, "group/repo", 10).unwrap(),
+            Some(101)
+        );
+        assert_eq!(
+            select_pipeline_comment(&conn, "other/repo", 10).unwrap(),
+            None
+        );
+    }
+
+    #[test]
+    fn test_jobs() {
+        let conn = open(None).unwrap();
+        let job = |job_id, pipeline_id, status: &str, auto_retried| Job {
+            gitlab_project: "group/repo".into(),
+            job_id,
+            pipeline_id,
+            job_name: "test".into(),
+            status: status.into(),
+            auto_retried,
+            updated_at: 0,
+        };
+        upsert_job(&conn, &job(1, 10, "failed", true)).unwrap();
+        upsert_job(&conn, &job(1, 10, "failed", false)).unwrap();
+        upsert_job(&conn, &job(2, 10, "success", false)).unwrap();
+        upsert_job(&conn, &job(3, 11, "success", false)).unwrap();
+        upsert_job(&conn, &job(4, 12, "failed", false)).unwrap();
+        upsert_job(&conn, &job(5, 12, "failed", false)).unwrap();
+        assert_eq!(
+            select_passes_on_retry(&conn, "group/repo", "test").unwrap(),
+            1
+        );
+        assert_eq!(
+            select_passes_on_retry(&conn, "group/repo", "lint").unwrap(),
+            0
+        );
+        assert!(select_auto_retried(&conn, "group/repo", 10, "test").unwrap());
+        assert!(!select_auto_retried(&conn, "group/repo", 12, "test").unwrap());
+    }
+
+    #[test]
+    fn test_deployments() {
+        let conn = open(None).unwrap();
+        let deployment = |id, status: &str, github_deployment_id| Deployment {
+            gitlab_project: "group/repo".into(),
+            deployment_id: id,
+            gitlab_branch: "pr-1/fork/repo/branch".into(),
+            environment: "review/pr-1".into(),
+            url: Some("https://pr-1.example.com".into()),
+            status: status.into(),
+            github_deployment_id,
+            updated_at: 0,
+        };
+        upsert_deployment(&conn, &deployment(5, "running", None)).unwrap();
+        upsert_deployment(&conn, &deployment(5, "running", Some(99))).unwrap();
+        upsert_deployment(&conn, &deployment(5, "success", None)).unwrap();
+        let stored = select_deployment(&conn, "group/repo", 5).unwrap().unwrap();
+        assert_eq!(stored.status, "success");
+        assert_eq!(stored.github_deployment_id, Some(99));
+
+        upsert_deployment(&conn, &deployment(6, "failed", None)).unwrap();
+        let latest = select_latest_deployment(&conn, "group/repo", "pr-1/fork/repo/branch")
+            .unwrap()
+            .unwrap();
+        assert_eq!(latest.deployment_id, 6);
+        assert_eq!(select_deployment(&conn, "group/repo", 7).unwrap(), None);
+    }
+
+    #[test]
+    fn test_paused_repos() {
+        let conn = open(None).unwrap();
+        let paused = |repo: &str| PausedRepo {
+            repo: repo.into(),
+            paused_by: "octocat".into(),
+            paused_at: 0,
+        };
+        insert_paused_repo(&conn, &paused("org/b")).unwrap();
+        insert_paused_repo(&conn, &paused("org/a")).unwrap();
+        insert_paused_repo(&conn, &paused("org/a")).unwrap();
+        assert_eq!(
+            select_paused_repos(&conn).unwrap(),
+            vec![paused("org/a"), paused("org/b")]
+        );
+        assert!(delete_paused_repo(&conn, "org/a").unwrap());
+        assert!(!delete_paused_repo(&conn, "org/a").unwrap());
+        assert_eq!(select_paused_repos(&conn).unwrap(), vec![paused("org/b")]);
+    }
+}
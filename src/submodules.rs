@@ -0,0 +1,95 @@
+//! Rewriting of `.gitmodules` URLs, so GitLab CI fetches submodules from
+//! their GitLab mirrors. The rewrite is pushed as a generated commit on top
+//! of the PR's head.
+use crate::config::SubmoduleRewrite;
+
+const COMMIT_TITLE_PREFIX: &str = "Rewrite submodule URLs for ";
+
+/// Title of the commit rewriting the submodule URLs of `source_sha`
+pub fn commit_title(source_sha: &str) -> String {
+    format!("{}{}", COMMIT_TITLE_PREFIX, source_sha)
+}
+
+pub fn commit_message(source_sha: &str) -> String {
+    format!(
+        "{}\n\nGenerated by LabHub so CI fetches submodules from their GitLab mirrors.\n",
+        commit_title(source_sha)
+    )
+}
+
+/// Returns the PR head a generated commit was made for, from its title
+pub fn source_sha(commit_title: &str) -> Option<&str> {
+    commit_title.strip_prefix(COMMIT_TITLE_PREFIX)
+}
+
+/// Rewrites the submodule URLs in a `.gitmodules` file, returning `None` if
+/// none of them matched
+pub fn rewrite_gitmodules(contents: &str, rewrites: &[SubmoduleRewrite]) -> Option<String> {
+    let mut changed = false;
+    let lines: Vec<String> = contents
+        .lines()
+        .map(|line| {
+            let url = match line.split_once('=') {
+                Some((key, value)) if key.trim() == "url" => value.trim(),
+                _ => return line.to_string(),
+            };
+            match rewrites.iter().find(|r| url.starts_with(&r.from)) {
+                Some(rewrite) => {
+                    changed = true;
+                    let indent = &line[..line.len() - line.trim_start().len()];
+                    format!(
+                        "{}url = {}{}",
+                        indent,
+                        rewrite.to,
+                        &url[rewrite.from.len()..]
+                    )
+                }
+                None => line.to_string(),
+            }
+        })
+        .collect();
+    if !changed {
+        return None;
+    }
+    let mut rewritten = lines.join("\n");
+    if contents.ends_with('\n') {
+        rewritten.push('\n');
+    }
+    Some(rewritten)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_rewrite_gitmodules() {
+        let rewrites = vec![SubmoduleRewrite {
+            from: "https://github.com/octocat/".to_string(),
+            to: "https://gitlab.example.com/mirrors/".to_string(),
+        }];
+        let gitmodules = "[submodule \"vendor/lib\"]\n\
+                          \tpath = vendor/lib\n\
+                          \turl = https://github.com/octocat/lib.git\n\
+                          [submodule \"vendor/other\"]\n\
+                          \tpath = vendor/other\n\
+                          \turl = https://github.com/someone/other.git\n";
+        assert_eq!(
+            rewrite_gitmodules(gitmodules, &rewrites).unwrap(),
+            "[submodule \"vendor/lib\"]\n\
+             \tpath = vendor/lib\n\
+             \turl = https://gitlab.example.com/mirrors/lib.git\n\
+             [submodule \"vendor/other\"]\n\
+             \tpath = vendor/other\n\
+             \turl = https://github.com/someone/other.git\n"
+        );
+        assert_eq!(
+            rewrite_gitmodules("[submodule \"x\"]\n\turl = ../x.git\n", &rewrites),
+            None
+        );
+
+        let sha = "a91957a858320c0e17f3a0eca7cfacbff50ea29a";
+        assert_eq!(source_sha(&commit_title(sha)), Some(sha));
+        assert_eq!(source_sha("Fix typo"), None);
+    }
+}
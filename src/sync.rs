@@ -0,0 +1,2236 @@
+//! The forge-agnostic PR sync engine: pushes PR heads from any source forge
+//! to GitLab branches, given a [`ForgePullRequest`].
+use crate::api;
+use crate::api::gitlab_client;
+use crate::api::lfs_client;
+use crate::api::models::gitlab;
+use crate::audit;
+use crate::ci_config;
+use crate::config;
+use crate::disk;
+use crate::errors::GitError;
+use crate::event_webhooks::{self, Event};
+use crate::forge::{self, Forge, ForgePullRequest};
+use crate::git_cli;
+use crate::github;
+use crate::history;
+use crate::host_keys;
+use crate::killswitch::{self, KillSwitch};
+use crate::lfs;
+use crate::messages::{self, Message};
+use crate::pause;
+use crate::protected;
+use crate::provisioning;
+use crate::queue;
+use crate::repo_name;
+use crate::signing;
+use crate::state;
+use crate::submodules;
+use crate::trust;
+
+use git2::build::RepoBuilder;
+use git2::{FetchOptions, ProxyOptions, PushOptions, RemoteCallbacks, Repository};
+use log::{debug, error, info, warn};
+use ring::{digest, hmac};
+use std::collections::HashMap;
+use std::ops::{Deref, DerefMut};
+use std::path::Path;
+use std::sync::{Mutex, MutexGuard};
+use std::time::{Duration, Instant};
+use tempfile::TempDir;
+
+#[cfg(test)]
+use mockers_derive::mocked;
+
+struct RepoData {
+    repo: Repository,
+    dir: TempDir,
+    /// When the repo was last used, to evict the least recently used first
+    last_used: Instant,
+    /// Size of the clone, as of the last time the repos were unlocked after
+    /// using it
+    bytes: u64,
+}
+
+lazy_static! {
+    static ref REPOS: Mutex<HashMap<String, RepoData>> = {
+        #[allow(unused_mut)]
+        let mut m: HashMap<String, RepoData> = HashMap::new();
+        Mutex::new(m)
+    };
+}
+
+/// A failed clone of, or fetch from, `remote`
+fn fetch_error(remote: &str, err: GitError) -> GitError {
+    GitError::GitClone {
+        remote: remote.to_string(),
+        message: git_message(err),
+    }
+}
+
+/// A failed push of `refspec` to `remote`
+fn push_error(remote: &str, refspec: &str, err: GitError) -> GitError {
+    GitError::GitPush {
+        remote: remote.to_string(),
+        refspec: refspec.to_string(),
+        message: git_message(err),
+    }
+}
+
+/// The message of a libgit2 or `git` CLI error, without the `Git error`
+/// prefix
+fn git_message(err: GitError) -> String {
+    match err {
+        GitError::Git(err) => err.message().to_string(),
+        err => err.to_string(),
+    }
+}
+
+/// The locked cached repos. Unlocking them updates the clone cache gauges,
+/// so metrics scrapes never wait on a sync holding the lock.
+struct LockedRepos {
+    repos: MutexGuard<'static, HashMap<String, RepoData>>,
+    locked_at: Instant,
+}
+
+impl Deref for LockedRepos {
+    type Target = HashMap<String, RepoData>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.repos
+    }
+}
+
+impl DerefMut for LockedRepos {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.repos
+    }
+}
+
+impl Drop for LockedRepos {
+    fn drop(&mut self) {
+        // Only the repos used while locked may have changed size
+        for repo_data in self.repos.values_mut() {
+            if repo_data.last_used >= self.locked_at {
+                repo_data.bytes = disk::dir_size(repo_data.dir.path());
+            }
+        }
+        disk::record_cache(
+            self.repos.len(),
+            self.repos.values().map(|repo_data| repo_data.bytes).sum(),
+        );
+    }
+}
+
+/// Locks the cached repos, see [`lock_recovering`]
+fn lock_repos() -> LockedRepos {
+    LockedRepos {
+        repos: lock_recovering(&REPOS),
+        locked_at: Instant::now(),
+    }
+}
+
+/// Locks `repos`, even if a sync panicked while holding the lock. That sync
+/// may have left its repo half updated, so the repos are all evicted then,
+/// to be cloned again.
+fn lock_recovering(
+    repos: &Mutex<HashMap<String, RepoData>>,
+) -> MutexGuard<'_, HashMap<String, RepoData>> {
+    repos.lock().unwrap_or_else(|poisoned| {
+        let mut cached = poisoned.into_inner();
+        warn!(
+            "A sync panicked while using the cached repos, evicting all {} of them",
+            cached.len()
+        );
+        cached.clear();
+        repos.clear_poison();
+        cached
+    })
+}
+
+/// Proxy for libgit2, which only applies to HTTP(S) remotes
+fn get_proxy_options() -> ProxyOptions<'static> {
+    let mut proxy_options = ProxyOptions::new();
+    if let Some(url) = config::CONFIG.proxy.url.as_ref() {
+        proxy_options.url(url);
+    }
+    proxy_options
+}
+
+fn get_remote_callbacks(site: &config::Site) -> RemoteCallbacks<'_> {
+    let mut remote_callbacks = RemoteCallbacks::new();
+    // libgit2 calls back again when a credential is rejected, so the agent
+    // is only tried once before falling back to the key
+    let mut try_agent = site.ssh_agent;
+    remote_callbacks.credentials(move |_url, _user_from_url, cred| {
+        debug!("Entered Git credential callback, cred={:?}", cred);
+        if cred.contains(git2::CredentialType::USERNAME) {
+            git2::Cred::username("git")
+        } else if try_agent {
+            try_agent = false;
+            git2::Cred::ssh_key_from_agent("git")
+        } else if site.ssh_key.is_empty() {
+            Err(git2::Error::from_str(
+                "The ssh-agent has no key the remote accepts, and no ssh_key is configured",
+            ))
+        } else {
+            let path = site.ssh_key_path();
+            let passphrase = site.ssh_key_passphrase();
+            git2::Cred::ssh_key("git", None, Path::new(&path), passphrase.as_deref())
+        }
+    });
+    remote_callbacks.certificate_check(|cert, host| {
+        // TLS certificates are left to libgit2
+        let fingerprint = match cert.as_hostkey() {
+            Some(key) => key
+                .hash_sha256()
+                .map(|hash| host_keys::fingerprint_of_hash(hash)),
+            None => return Ok(git2::CertificateCheckStatus::CertificatePassthrough),
+        };
+        match host_keys::check(host, fingerprint.as_deref()) {
+            None => Ok(git2::CertificateCheckStatus::CertificatePassthrough),
+            Some(Ok(())) => Ok(git2::CertificateCheckStatus::CertificateOk),
+            Some(Err(message)) => {
+                error!("{}", message);
+                Err(git2::Error::from_str(&message))
+            }
+        }
+    });
+    remote_callbacks.push_update_reference(|reference, status_option| {
+        match status_option {
+            Some(status) => error!(
+                "Failed to update remote ref {} message={:?}",
+                reference, status
+            ),
+            _ => info!("Updated remote ref {}", reference),
+        };
+        Ok(())
+    });
+    remote_callbacks.update_tips(|reference, oid1, oid2| {
+        debug!(
+            "Updated tips, ref={} oid1={} oid2={}",
+            reference, oid1, oid2
+        );
+        true
+    });
+    remote_callbacks
+}
+
+/// Whether `fetched`, a full commit ID, is the head an event named, which
+/// Bitbucket abbreviates to at least 7 characters
+fn is_head(head_sha: &str, fetched: &str) -> bool {
+    head_sha.len() >= 7 && fetched.starts_with(&head_sha.to_lowercase())
+}
+
+#[cfg_attr(test, mocked)]
+trait RepositoryExt {
+    fn add_remotes(&mut self, pr_handle: &PrHandle) -> Result<(), GitError>;
+    fn fetch_source_remote(&self, pr_handle: &PrHandle) -> Result<(), GitError>;
+    /// Points the PR's GitLab branch at its fetched head, and returns the
+    /// head's full commit ID
+    fn create_ref_for_pr(&self, pr_handle: &PrHandle) -> Result<String, GitError>;
+    /// Pushes the PR's GitLab branch, and returns the commit it pushed
+    fn push_pr_ref(&self, pr_handle: &PrHandle) -> Result<String, GitError>;
+    fn delete_pr_refs(&self, pr_handles: &[PrHandle]) -> Result<(), GitError>;
+    fn lfs_pointers(&self, pr_handle: &PrHandle) -> Result<Vec<lfs_client::Object>, GitError>;
+    fn rewrite_submodules(&self, pr_handle: &PrHandle) -> Result<(), GitError>;
+    fn pin_ci_config(&self, pr_handle: &PrHandle, ci_config: &[u8]) -> Result<(), GitError>;
+}
+
+#[derive(Debug, Eq, PartialEq)]
+pub struct PrHandle {
+    forge: Forge,
+    base_full_name: String,
+    gitlab_project: String,
+    head_full_name: String,
+    source_remote: String,
+    gitlab_remote: String,
+    gitref: String,
+    head_sha: String,
+    source_clone_url: String,
+    pr_number: i64,
+    author: Option<String>,
+    labels: Vec<String>,
+    trusted: bool,
+    is_fork: bool,
+}
+
+impl PrHandle {
+    fn new(pr: &dyn ForgePullRequest) -> PrHandle {
+        let trusted = trust::is_trusted(pr);
+        PrHandle {
+            forge: pr.forge(),
+            gitref: pr.head_ref().to_owned(),
+            head_sha: pr.head_sha().to_owned(),
+            pr_number: pr.number(),
+            source_clone_url: pr.head_clone_url().to_owned(),
+            source_remote: format!("{}-{}", pr.forge().name(), pr.number()),
+            gitlab_remote: "gitlab".to_string(),
+            base_full_name: repo_name::lookup_key(pr.base_full_name()),
+            gitlab_project: config::gitlab_project_for(&pr.gitlab_project(), trusted),
+            // Only lookup keys are lowercased, branch names keep the head
+            // repo's case
+            head_full_name: pr.head_full_name().to_owned(),
+            author: pr.author().map(str::to_owned),
+            labels: pr.labels().into_iter().map(str::to_owned).collect(),
+            trusted,
+            is_fork: pr.is_fork(),
+        }
+    }
+
+    /// CI variables describing the PR, on top of the pipeline profile's
+    fn pipeline_variables(&self) -> HashMap<String, String> {
+        let mut variables = config::pipeline_profile(self.trusted).variables.clone();
+        variables.insert("LABHUB_PR_NUMBER".into(), self.pr_number.to_string());
+        variables.insert(
+            "LABHUB_PR_AUTHOR".into(),
+            self.author.clone().unwrap_or_default(),
+        );
+        variables.insert("LABHUB_PR_LABELS".into(), self.labels.join(","));
+        variables.insert("LABHUB_PR_TRUSTED".into(), self.trusted.to_string());
+        variables.insert("LABHUB_FORGE".into(), self.forge.name().into());
+        variables.insert("LABHUB_BASE_REPO".into(), self.base_full_name.clone());
+        variables.insert("LABHUB_HEAD_REPO".into(), self.head_full_name.clone());
+        variables.insert("LABHUB_HEAD_REF".into(), self.gitref.clone());
+        variables.insert("LABHUB_HEAD_SHA".into(), self.head_sha.clone());
+        variables
+    }
+
+    /// Git push options for the PR's branch: the pipeline profile's, plus
+    /// the PR variables with the `pr_variables` feature
+    fn push_options(&self) -> Vec<String> {
+        let mut options = config::pipeline_profile(self.trusted).push_options.clone();
+        if config::feature_enabled(&config::Feature::PrVariables) {
+            let mut variables: Vec<(String, String)> =
+                self.pipeline_variables().into_iter().collect();
+            variables.sort();
+            options.extend(
+                variables
+                    .into_iter()
+                    .map(|(key, value)| format!("ci.variable={}={}", key, value)),
+            );
+        }
+        options
+    }
+
+    /// Records a change to the PR's GitLab branch in the audit log, as
+    /// caused by the PR's author
+    fn audit<T>(&self, action: audit::Action, outcome: &Result<T, GitError>) {
+        audit::record(
+            self.author.as_deref().unwrap_or(audit::LABHUB),
+            &self.base_full_name,
+            Some(self.pr_number),
+            action,
+            &format!("{} on {}", self.gitlab_branch(), self.gitlab_project),
+            outcome,
+        );
+    }
+
+    /// Records a change to one of the PR's pipelines in the audit log
+    fn audit_pipeline<T>(
+        &self,
+        action: audit::Action,
+        pipeline: &state::Pipeline,
+        outcome: &Result<T, GitError>,
+    ) {
+        audit::record(
+            self.author.as_deref().unwrap_or(audit::LABHUB),
+            &self.base_full_name,
+            Some(self.pr_number),
+            action,
+            &format!(
+                "pipeline {} on {}",
+                pipeline.pipeline_id, pipeline.gitlab_project
+            ),
+            outcome,
+        );
+    }
+
+    /// Name of the branch pushed to GitLab for this PR
+    fn gitlab_branch(&self) -> String {
+        config::pipeline_profile(self.trusted).branch_name(
+            self.pr_number,
+            &self.head_full_name,
+            &self.gitref,
+        )
+    }
+}
+
+impl RepositoryExt for Repository {
+    fn add_remotes(&mut self, pr_handle: &PrHandle) -> Result<(), GitError> {
+        let source_refspec = format!("+refs/heads/*:refs/remotes/{}/*", pr_handle.source_remote);
+        self.remote_add_fetch(&pr_handle.source_remote, &source_refspec)?;
+        self.remote_set_url(&pr_handle.source_remote, &pr_handle.source_clone_url)?;
+        let gitlab = &config::gitlab_for_project(&pr_handle.gitlab_project).site;
+        let hostname = gitlab
+            .ssh_url
+            .clone()
+            .or(gitlab.hostname.clone())
+            .unwrap_or("gitlab.com".to_string());
+        let gitlab_url = format!("ssh://git@{}/{}.git", hostname, pr_handle.gitlab_project);
+        let gitlab_refspec = "refs/heads/master:refs/heads/master".to_string();
+        self.remote_add_push(&pr_handle.gitlab_remote, &gitlab_refspec)?;
+        self.remote_set_url(&pr_handle.gitlab_remote, &gitlab_url)?;
+        Ok(())
+    }
+
+    fn fetch_source_remote(&self, pr_handle: &PrHandle) -> Result<(), GitError> {
+        info!(
+            "Fetching remote={} ref={}",
+            pr_handle.source_remote, pr_handle.gitref
+        );
+        let site = pr_handle.forge.site(&pr_handle.base_full_name)?;
+        if git_cli::ssh_proxied() {
+            let (remote, refspec) =
+                match narrow_fetch_refspec(&config::CONFIG.clone.mode, pr_handle) {
+                    Some(refspec) => ("origin".to_string(), refspec),
+                    None => (
+                        pr_handle.source_remote.clone(),
+                        format!(
+                            "+refs/heads/{}:refs/remotes/{}/{}",
+                            pr_handle.gitref, pr_handle.source_remote, pr_handle.gitref
+                        ),
+                    ),
+                };
+            git_cli::run(
+                Some(self.path()),
+                site,
+                &["fetch".to_string(), remote.clone(), refspec],
+            )
+            .map_err(|err| fetch_error(&remote, err))?;
+            info!("Successfully fetched remote");
+            return Ok(());
+        }
+        let mut remote = self.find_remote(&pr_handle.source_remote)?;
+
+        let mut fetch_options = FetchOptions::new();
+        fetch_options.remote_callbacks(get_remote_callbacks(site));
+        fetch_options.proxy_options(get_proxy_options());
+
+        match narrow_fetch_refspec(&config::CONFIG.clone.mode, pr_handle) {
+            Some(refspec) => {
+                debug!("Fetching {} from origin", refspec);
+                let mut origin = self.find_remote("origin")?;
+                origin
+                    .fetch(&[&refspec], Some(&mut fetch_options), None)
+                    .map_err(|err| fetch_error("origin", err.into()))?;
+            }
+            None => remote
+                .fetch(&[&pr_handle.gitref], Some(&mut fetch_options), None)
+                .map_err(|err| fetch_error(&pr_handle.source_remote, err.into()))?,
+        }
+
+        info!("Successfully fetched remote");
+        Ok(())
+    }
+
+    fn create_ref_for_pr(&self, pr_handle: &PrHandle) -> Result<String, GitError> {
+        let github_ref = format!(
+            "refs/remotes/{}/{}",
+            pr_handle.source_remote, pr_handle.gitref
+        );
+        let gitlab_ref = format!("refs/heads/{}", pr_handle.gitlab_branch());
+        let id = self.refname_to_id(&github_ref)?;
+        // Only push the head the event was for, and which was checked for
+        // protected paths or approved, not whatever was pushed since
+        if !is_head(&pr_handle.head_sha, &id.to_string()) {
+            return Err(GitError::HeadMoved {
+                expected: pr_handle.head_sha.clone(),
+                fetched: id.to_string(),
+            });
+        }
+        debug!("Creating ref {} from {}, id={}", gitlab_ref, github_ref, id);
+        self.reference(&gitlab_ref, id, true, "new ref")?;
+        Ok(id.to_string())
+    }
+
+    fn rewrite_submodules(&self, pr_handle: &PrHandle) -> Result<(), GitError> {
+        let gitlab_ref = format!("refs/heads/{}", pr_handle.gitlab_branch());
+        let commit = self.find_commit(self.refname_to_id(&gitlab_ref)?)?;
+        let tree = commit.tree()?;
+        let gitmodules = match tree.get_name(".gitmodules") {
+            Some(entry) => self.find_blob(entry.id())?,
+            None => return Ok(()),
+        };
+        let rewritten = match std::str::from_utf8(gitmodules.content())
+            .ok()
+            .and_then(|contents| {
+                submodules::rewrite_gitmodules(contents, &config::CONFIG.submodules.rewrites)
+            }) {
+            Some(rewritten) => rewritten,
+            None => return Ok(()),
+        };
+
+        let mut builder = self.treebuilder(Some(&tree))?;
+        builder.insert(".gitmodules", self.blob(rewritten.as_bytes())?, 0o100644)?;
+        let new_tree = self.find_tree(builder.write()?)?;
+        let id = generated_commit(
+            self,
+            &commit,
+            &pr_handle.head_sha,
+            &submodules::commit_message(&pr_handle.head_sha),
+            &new_tree,
+        )?;
+        info!(
+            "Rewrote submodule URLs of {} in commit {}",
+            pr_handle.head_sha, id
+        );
+        self.reference(&gitlab_ref, id, true, "rewrite submodule URLs")?;
+        Ok(())
+    }
+
+    fn pin_ci_config(&self, pr_handle: &PrHandle, ci_config: &[u8]) -> Result<(), GitError> {
+        let gitlab_ref = format!("refs/heads/{}", pr_handle.gitlab_branch());
+        let commit = self.find_commit(self.refname_to_id(&gitlab_ref)?)?;
+        let tree = commit.tree()?;
+        let blob = self.blob(ci_config)?;
+        if tree
+            .get_name(ci_config::CI_CONFIG_PATH)
+            .map(|entry| entry.id())
+            == Some(blob)
+        {
+            return Ok(());
+        }
+
+        let mut builder = self.treebuilder(Some(&tree))?;
+        builder.insert(ci_config::CI_CONFIG_PATH, blob, 0o100644)?;
+        let new_tree = self.find_tree(builder.write()?)?;
+        let id = generated_commit(
+            self,
+            &commit,
+            &pr_handle.head_sha,
+            &ci_config::commit_message(&pr_handle.head_sha),
+            &new_tree,
+        )?;
+        info!(
+            "Pinned the CI config of {} in commit {}",
+            pr_handle.head_sha, id
+        );
+        self.reference(&gitlab_ref, id, true, "pin CI config")?;
+        Ok(())
+    }
+
+    fn lfs_pointers(&self, pr_handle: &PrHandle) -> Result<Vec<lfs_client::Object>, GitError> {
+        let id = self.refname_to_id(&format!("refs/heads/{}", pr_handle.gitlab_branch()))?;
+        let tree = self.find_commit(id)?.tree()?;
+        let odb = self.odb()?;
+        let mut pointers: Vec<lfs_client::Object> = vec![];
+        tree.walk(git2::TreeWalkMode::PreOrder, |_, entry| {
+            if entry.kind() != Some(git2::ObjectType::Blob) {
+                return git2::TreeWalkResult::Ok;
+            }
+            let is_small = matches!(
+                odb.read_header(entry.id()),
+                Ok((size, _)) if size <= lfs::MAX_POINTER_SIZE
+            );
+            if is_small {
+                if let Some(pointer) = self
+                    .find_blob(entry.id())
+                    .ok()
+                    .and_then(|blob| lfs::parse_pointer(blob.content()))
+                {
+                    if !pointers.contains(&pointer) {
+                        pointers.push(pointer);
+                    }
+                }
+            }
+            git2::TreeWalkResult::Ok
+        })?;
+        debug!(
+            "Found {} LFS pointers on {}",
+            pointers.len(),
+            pr_handle.gitlab_branch()
+        );
+        Ok(pointers)
+    }
+
+    fn push_pr_ref(&self, pr_handle: &PrHandle) -> Result<String, GitError> {
+        killswitch::check(KillSwitch::Branches)?;
+        info!(
+            "Pushing PR remote={} ref={} number={} base_full_name={}",
+            pr_handle.gitlab_remote,
+            pr_handle.gitref,
+            pr_handle.pr_number,
+            pr_handle.base_full_name
+        );
+        let site = &config::gitlab_for_project(&pr_handle.gitlab_project).site;
+        let gitlab_branch = pr_handle.gitlab_branch();
+        let id = self.refname_to_id(&format!("refs/heads/{}", gitlab_branch))?;
+        let refspec = format!("+refs/heads/{}:refs/heads/{}", gitlab_branch, gitlab_branch);
+        let options = pr_handle.push_options();
+        // libgit2 can't send push options
+        let pushed = if !options.is_empty() || git_cli::ssh_proxied() {
+            git_cli::run(
+                Some(self.path()),
+                site,
+                &git_cli::push_args(
+                    &pr_handle.gitlab_remote,
+                    std::slice::from_ref(&refspec),
+                    &options,
+                ),
+            )
+        } else {
+            let mut gitremote = self.find_remote(&pr_handle.gitlab_remote)?;
+            let mut push_options = PushOptions::new();
+            push_options.remote_callbacks(get_remote_callbacks(site));
+            push_options.proxy_options(get_proxy_options());
+            gitremote
+                .push(&[&refspec], Some(&mut push_options))
+                .map_err(GitError::from)
+        };
+        pushed.map_err(|err| push_error(&pr_handle.gitlab_remote, &refspec, err))?;
+
+        info!("Successfully pushed {}", id);
+        Ok(id.to_string())
+    }
+
+    fn delete_pr_refs(&self, pr_handles: &[PrHandle]) -> Result<(), GitError> {
+        killswitch::check(KillSwitch::Branches)?;
+        let first = match pr_handles.first() {
+            Some(pr_handle) => pr_handle,
+            None => return Ok(()),
+        };
+        info!(
+            "Deleting {} PR branches remote={} base_full_name={}",
+            pr_handles.len(),
+            first.gitlab_remote,
+            first.base_full_name
+        );
+        let site = &config::gitlab_for_project(&first.gitlab_project).site;
+        let refspecs: Vec<String> = pr_handles
+            .iter()
+            .map(|pr_handle| format!(":refs/heads/{}", pr_handle.gitlab_branch()))
+            .collect();
+        let pushed = if git_cli::ssh_proxied() {
+            git_cli::run(
+                Some(self.path()),
+                site,
+                &git_cli::push_args(&first.gitlab_remote, &refspecs, &[]),
+            )
+        } else {
+            let mut gitremote = self.find_remote(&first.gitlab_remote)?;
+            let mut push_options = PushOptions::new();
+            push_options.remote_callbacks(get_remote_callbacks(site));
+            push_options.proxy_options(get_proxy_options());
+            gitremote
+                .push(&refspecs, Some(&mut push_options))
+                .map_err(GitError::from)
+        };
+        pushed.map_err(|err| push_error(&first.gitlab_remote, &refspecs.join(" "), err))?;
+
+        info!("Successfully pushed");
+        Ok(())
+    }
+}
+
+/// Creates a commit LabHub generated on top of `parent` for the PR head
+/// `source_sha`, signed with the `[signing]` key if set. Unsigned commits
+/// reuse the head's committer, which keeps them the same across syncs of the
+/// same head.
+fn generated_commit(
+    repo: &Repository,
+    parent: &git2::Commit,
+    source_sha: &str,
+    message: &str,
+    tree: &git2::Tree,
+) -> Result<git2::Oid, GitError> {
+    let message = format!("{}\n{}", message, source_trailer(source_sha));
+    let message = message.as_str();
+    let head_committer = parent.committer();
+    let signing = match config::CONFIG.signing.as_ref() {
+        Some(signing) => signing,
+        None => {
+            return Ok(repo.commit(
+                None,
+                &head_committer,
+                &head_committer,
+                message,
+                tree,
+                &[parent],
+            )?)
+        }
+    };
+    let committer = git2::Signature::new(&signing.name, &signing.email, &head_committer.when())?;
+    let content =
+        repo.commit_create_buffer(&head_committer, &committer, message, tree, &[parent])?;
+    let content = content.as_str().ok_or(GitError::Other(
+        "Generated commit isn't valid UTF-8".to_string(),
+    ))?;
+    let signature = signing::sign(signing, content)?;
+    Ok(repo.commit_signed(content, &signature, None)?)
+}
+
+/// In narrow mode, fetches a PR's head straight from the base repo's pull
+/// ref, rather than the fork, for forges that have one
+fn narrow_fetch_refspec(mode: &config::CloneMode, pr_handle: &PrHandle) -> Option<String> {
+    if *mode != config::CloneMode::Narrow {
+        return None;
+    }
+    pr_handle
+        .forge
+        .pull_ref(pr_handle.pr_number)
+        .map(|pull_ref| {
+            format!(
+                "+{}:refs/remotes/{}/{}",
+                pull_ref, pr_handle.source_remote, pr_handle.gitref
+            )
+        })
+}
+
+/// Returns the cached repo for `url`, cloning it on first use
+fn cached_repo<'a>(
+    repos: &'a mut HashMap<String, RepoData>,
+    site: &config::Site,
+    url: &str,
+) -> Result<&'a mut RepoData, GitError> {
+    if !repos.contains_key(url) {
+        ensure_disk_space(repos)?;
+        let repo_data = clone_repo(site, url)?;
+        repos.insert(url.to_owned(), repo_data);
+    }
+    let repo_data = repos.get_mut(url).unwrap();
+    repo_data.last_used = Instant::now();
+    Ok(repo_data)
+}
+
+/// Makes room for a new clone by evicting the least recently used cached
+/// repos while free space is below the threshold, failing if there's
+/// nothing left to evict
+fn ensure_disk_space(repos: &mut HashMap<String, RepoData>) -> Result<(), GitError> {
+    let min_free = disk::min_free_bytes();
+    loop {
+        let free = match disk::free_bytes() {
+            Ok(free) => free,
+            Err(err) => {
+                warn!("Unable to check free disk space: {}", err);
+                return Ok(());
+            }
+        };
+        if free >= min_free {
+            return Ok(());
+        }
+        let lru = repos
+            .iter()
+            .min_by_key(|(_, repo_data)| repo_data.last_used)
+            .map(|(url, _)| url.clone());
+        match lru {
+            Some(url) => {
+                warn!(
+                    "Only {} MiB of disk space free, evicting cached repo {}",
+                    free / 1024 / 1024,
+                    url
+                );
+                repos.remove(&url);
+            }
+            None => {
+                let msg = format!(
+                    "Refusing to clone with only {} MiB of disk space free, below the {} MiB \
+                     threshold",
+                    free / 1024 / 1024,
+                    min_free / 1024 / 1024
+                );
+                error!("{}", msg);
+                return Err(GitError::Other(msg));
+            }
+        }
+    }
+}
+
+/// Removes the cached repos unused for at least `ttl` from `repos`, which
+/// deletes their clones, and returns their URLs
+fn evict_idle_repos_from(repos: &mut HashMap<String, RepoData>, ttl: Duration) -> Vec<String> {
+    let idle: Vec<String> = repos
+        .iter()
+        .filter(|(_, repo_data)| repo_data.last_used.elapsed() >= ttl)
+        .map(|(url, _)| url.clone())
+        .collect();
+    for url in idle.iter() {
+        info!("Evicting cached repo {}, unused for {:?}", url, ttl);
+        repos.remove(url);
+    }
+    idle
+}
+
+/// Evicts the cached repos unused for at least `ttl`, and returns their URLs
+pub(crate) fn evict_idle_repos(ttl: Duration) -> Vec<String> {
+    evict_idle_repos_from(&mut lock_repos(), ttl)
+}
+
+/// Evicts a cached repo right away, deleting its clone. Returns whether it
+/// was cached. A sync using the repo finishes first.
+pub(crate) fn evict_repo(url: &str) -> bool {
+    let evicted = lock_repos().remove(url).is_some();
+    if evicted {
+        info!("Evicted cached repo {}", url);
+    }
+    evicted
+}
+
+/// The URL, size and idle time of each cached repo
+pub(crate) fn cached_repo_sizes() -> Vec<(String, u64, Duration)> {
+    lock_repos()
+        .iter()
+        .map(|(url, repo_data)| (url.clone(), repo_data.bytes, repo_data.last_used.elapsed()))
+        .collect()
+}
+
+fn clone_repo(site: &config::Site, url: &str) -> Result<RepoData, GitError> {
+    if config::CONFIG.clone.mode == config::CloneMode::Narrow {
+        // Only the PR heads are fetched later on, GitLab already has the rest
+        let dir = disk::clone_dir()?;
+        let repo = Repository::init_bare(dir.as_ref())?;
+        repo.remote("origin", url)?;
+        info!(
+            "Initialized empty repo for {} in {}",
+            url,
+            dir.as_ref().to_str().unwrap()
+        );
+        return Ok(RepoData {
+            repo,
+            dir,
+            last_used: Instant::now(),
+            bytes: 0,
+        });
+    }
+
+    let dir = disk::clone_dir()?;
+    if git_cli::ssh_proxied() {
+        let path = dir.as_ref().to_string_lossy().to_string();
+        let args = [
+            "clone".to_string(),
+            "--no-checkout".to_string(),
+            url.to_string(),
+            path,
+        ];
+        git_cli::run(None, site, &args).map_err(|err| fetch_error(url, err))?;
+        let repo = Repository::open(dir.as_ref())?;
+        info!("Cloned new repo {} through the SSH proxy", url);
+        return Ok(RepoData {
+            repo,
+            dir,
+            last_used: Instant::now(),
+            bytes: 0,
+        });
+    }
+
+    // Setup fetch options
+    let mut fetch_options = FetchOptions::new();
+    fetch_options.remote_callbacks(get_remote_callbacks(site));
+    fetch_options.proxy_options(get_proxy_options());
+
+    // Initialize & clone repo
+    let mut builder = RepoBuilder::new();
+    builder.fetch_options(fetch_options);
+    match builder.clone(url, dir.as_ref()) {
+        Ok(repo) => {
+            info!(
+                "Cloned new repo {} into {}",
+                url,
+                dir.as_ref().to_str().unwrap()
+            );
+
+            Ok(RepoData {
+                repo,
+                dir,
+                last_used: Instant::now(),
+                bytes: 0,
+            })
+        }
+        Err(err) => {
+            let err = fetch_error(url, err.into());
+            error!("{}", err);
+            Err(err)
+        }
+    }
+}
+
+fn handle_prs_closed_with_repo(
+    repo: &mut dyn RepositoryExt,
+    prs: &[&dyn ForgePullRequest],
+) -> Result<(), GitError> {
+    let pr_handles: Vec<PrHandle> = prs.iter().map(|pr| PrHandle::new(*pr)).collect();
+
+    info!("pr_handles={:#?}", pr_handles);
+
+    // Trusted and untrusted PRs may be pushed to different GitLab projects,
+    // and the GitLab remote only points at one of them at a time
+    let mut by_project: Vec<Vec<PrHandle>> = vec![];
+    for pr_handle in pr_handles {
+        match by_project
+            .iter_mut()
+            .find(|handles| handles[0].gitlab_project == pr_handle.gitlab_project)
+        {
+            Some(handles) => handles.push(pr_handle),
+            None => by_project.push(vec![pr_handle]),
+        }
+    }
+    for pr_handles in by_project {
+        for pr_handle in pr_handles.iter() {
+            repo.add_remotes(pr_handle)?;
+        }
+        let result = repo.delete_pr_refs(&pr_handles);
+        for pr_handle in pr_handles.iter() {
+            pr_handle.audit(audit::Action::RefDeletion, &result);
+        }
+        result?;
+        for pr_handle in pr_handles.iter() {
+            history::record(
+                &pr_handle.base_full_name,
+                pr_handle.pr_number,
+                history::Action::BranchDeleted,
+                &format!(
+                    "{} on {}",
+                    pr_handle.gitlab_branch(),
+                    pr_handle.gitlab_project
+                ),
+            );
+        }
+    }
+    Ok(())
+}
+
+/// Deletes the GitLab branches of closed PRs, with a single push per repo
+/// however many of its PRs were closed. The queue worker batches up closes
+/// arriving together, e.g. from a stale bot sweep.
+pub(crate) async fn close_prs(prs: &[&dyn ForgePullRequest]) -> Result<String, GitError> {
+    info!("Handling {} closed PRs", prs.len());
+    for pr in prs {
+        // Deleting the branch would close it too, but not if it's kept. A
+        // failure fails the batch, so that it's retried like a failed push.
+        close_merge_request(*pr).await?;
+    }
+    let mut by_repo: Vec<(&str, Vec<&dyn ForgePullRequest>)> = vec![];
+    let mut kept = 0;
+    for pr in prs {
+        if keeps_branch(*pr)? {
+            kept += 1;
+            continue;
+        }
+        match by_repo
+            .iter_mut()
+            .find(|(url, _)| *url == pr.base_clone_url())
+        {
+            Some((_, repo_prs)) => repo_prs.push(*pr),
+            None => by_repo.push((pr.base_clone_url(), vec![*pr])),
+        }
+    }
+
+    for (url, repo_prs) in by_repo {
+        let mut repos = lock_repos();
+        let repo_data = cached_repo(
+            &mut repos,
+            repo_prs[0].forge().site(repo_prs[0].base_full_name())?,
+            url,
+        )?;
+
+        handle_prs_closed_with_repo(&mut repo_data.repo, &repo_prs)?;
+    }
+
+    Ok(format!("deleted {}, kept {} :D", prs.len() - kept, kept))
+}
+
+/// Adds or removes the label a `labeled` or `unlabeled` event changed on
+/// the PR's GitLab merge request
+async fn sync_merge_request_label(pr: &dyn ForgePullRequest) -> Result<String, GitError> {
+    let pr_handle = PrHandle::new(pr);
+    let label = match pr.changed_label() {
+        Some(label) => label,
+        None => return Ok(String::from("no label changed")),
+    };
+    let merge_request = match state::merge_request(&pr_handle.base_full_name, pr_handle.pr_number)?
+    {
+        Some(merge_request) => merge_request,
+        None => return Ok(String::from("no merge request to label")),
+    };
+    let (add, remove): (&[&str], &[&str]) = if pr.action() == "labeled" {
+        (&[label], &[])
+    } else {
+        (&[], &[label])
+    };
+    let client = api::new_client()?;
+    let result = gitlab_client::update_merge_request_labels(
+        &client,
+        &merge_request.gitlab_project,
+        merge_request.iid,
+        add,
+        remove,
+    )
+    .await;
+    pr_handle.audit(audit::Action::MergeRequest, &result);
+    result?;
+    Ok(format!(
+        "{} {} on merge request {}",
+        pr.action(),
+        label,
+        merge_request.iid
+    ))
+}
+
+/// Closes the GitLab merge request of a closed PR, with the repo's
+/// `merge_requests` set
+async fn close_merge_request(pr: &dyn ForgePullRequest) -> Result<(), GitError> {
+    if !config::merge_requests_for_repo(pr.base_full_name()) {
+        return Ok(());
+    }
+    let pr_handle = PrHandle::new(pr);
+    let merge_request = match state::merge_request(&pr_handle.base_full_name, pr_handle.pr_number)?
+    {
+        Some(merge_request) => merge_request,
+        None => return Ok(()),
+    };
+    info!(
+        "Closing merge request {} of PR {} on project={}",
+        merge_request.iid, pr_handle.pr_number, merge_request.gitlab_project
+    );
+    let client = api::new_client()?;
+    let result = gitlab_client::close_merge_request(
+        &client,
+        &merge_request.gitlab_project,
+        merge_request.iid,
+    )
+    .await;
+    pr_handle.audit(audit::Action::MergeRequest, &result);
+    result?;
+    state::forget_merge_request(&pr_handle.base_full_name, pr_handle.pr_number)?;
+    history::record(
+        &pr_handle.base_full_name,
+        pr_handle.pr_number,
+        history::Action::MergeRequest,
+        &format!(
+            "merge request {} closed on {}",
+            merge_request.iid, merge_request.gitlab_project
+        ),
+    );
+    Ok(())
+}
+
+/// Applies the repo's `branch_retention` to a closed PR, returning true if
+/// its branch is kept for now
+fn keeps_branch(pr: &dyn ForgePullRequest) -> Result<bool, GitError> {
+    match config::branch_retention_for_repo(pr.base_full_name()) {
+        config::BranchRetention::Delete => Ok(false),
+        config::BranchRetention::KeepMerged => {
+            if pr.is_merged() {
+                info!("Keeping the branch of merged PR {}", pr.number());
+            }
+            Ok(pr.is_merged())
+        }
+        config::BranchRetention::KeepDays { days } => {
+            let pr_handle = PrHandle::new(pr);
+            info!(
+                "Keeping branch {} of closed PR {} for {} days",
+                pr_handle.gitlab_branch(),
+                pr.number(),
+                days
+            );
+            state::retain_branch(&pr_handle.gitlab_project, &pr_handle.gitlab_branch())?;
+            Ok(true)
+        }
+    }
+}
+
+/// Pushes an open PR's head to GitLab, and returns the commit pushed
+async fn handle_pr_updated(
+    pr: &dyn ForgePullRequest,
+    pr_handle: &mut PrHandle,
+) -> Result<String, GitError> {
+    info!("Handling open PR");
+    let url = pr.base_clone_url();
+    info!("Handling open PR ssh: {}", url);
+    let site = pr.forge().site(pr.base_full_name())?;
+    if !config::feature_enabled(&config::Feature::Lfs) {
+        let mut repos = lock_repos();
+        let repo_data = cached_repo(&mut repos, site, url)?;
+        return handle_pr_updated_with_repo(&mut repo_data.repo, pr_handle);
+    }
+
+    // The LFS objects have to be on GitLab before the push, and the repo
+    // can't stay locked while they're copied
+    let pointers = {
+        let mut repos = lock_repos();
+        let repo_data = cached_repo(&mut repos, site, url)?;
+        fetch_pr_with_repo(&mut repo_data.repo, pr_handle)?;
+        repo_data.repo.lfs_pointers(pr_handle)?
+    };
+    forward_lfs_objects(pr, pr_handle, &pointers).await;
+    let mut repos = lock_repos();
+    let repo_data = cached_repo(&mut repos, site, url)?;
+    let result = repo_data.repo.push_pr_ref(pr_handle);
+    pr_handle.audit(audit::Action::RefPush, &result);
+    result
+}
+
+/// Copies a PR's LFS objects to GitLab, warning on the PR if that fails
+async fn forward_lfs_objects(
+    pr: &dyn ForgePullRequest,
+    pr_handle: &PrHandle,
+    pointers: &[lfs_client::Object],
+) {
+    let result = lfs::forward_objects(
+        pr_handle.forge,
+        pr.base_full_name(),
+        pr.head_full_name(),
+        &pr_handle.gitlab_project,
+        pointers,
+    )
+    .await;
+    let err = match result {
+        Ok(uploaded) => {
+            info!("Forwarded {} LFS objects", uploaded);
+            return;
+        }
+        Err(err) => err,
+    };
+    error!("Error forwarding LFS objects: {:?}", err);
+    if pr_handle.forge != Forge::GitHub {
+        return;
+    }
+    let warned = match api::new_client() {
+        Ok(client) => {
+            github::post_lfs_warning(
+                &client,
+                pr.base_full_name(),
+                pr_handle.pr_number,
+                &pr_handle.head_sha,
+                &err.to_string(),
+            )
+            .await
+        }
+        Err(err) => Err(err),
+    };
+    if let Err(err) = warned {
+        error!("Error posting LFS warning: {:?}", err);
+    }
+}
+
+/// GitLab may need a moment before a freshly pushed branch shows up in the
+/// API, so anything acting right after a push should look the branch up
+/// through here.
+async fn wait_for_gitlab_branch(pr_handle: &PrHandle) -> Result<gitlab::Branch, GitError> {
+    let client = api::new_client()?;
+    let project = &pr_handle.gitlab_project;
+    let branch = pr_handle.gitlab_branch();
+    api::retry::retry_until_found(&format!("Branch {} on project={}", branch, project), || {
+        gitlab_client::get_branch(&client, project, &branch)
+    })
+    .await
+}
+
+/// GitLab pipeline statuses of pipelines which haven't finished
+const ACTIVE_PIPELINE_STATUSES: &[&str] = &[
+    "created",
+    "waiting_for_resource",
+    "preparing",
+    "pending",
+    "running",
+    "scheduled",
+];
+
+/// Returns the pipeline of a PR's previous head if it's still running, and
+/// the PR's head moved on since
+fn superseded_pipeline(
+    previous: &state::PrSync,
+    head_sha: &str,
+    pipeline: Option<state::Pipeline>,
+) -> Option<state::Pipeline> {
+    pipeline.filter(|pipeline| {
+        previous.head_sha != head_sha
+            && pipeline.sha == previous.head_sha
+            && ACTIVE_PIPELINE_STATUSES.contains(&pipeline.status.as_str())
+    })
+}
+
+/// Cancels the pipeline still running for a PR's previous head, with the
+/// repo's `auto_cancel` set
+async fn cancel_superseded_pipeline(
+    pr_handle: &PrHandle,
+    previous: &state::PrSync,
+) -> Result<(), GitError> {
+    let pipeline = state::latest_pipeline(&previous.gitlab_project, &previous.head_sha)?;
+    let pipeline = match superseded_pipeline(previous, &pr_handle.head_sha, pipeline) {
+        Some(pipeline) => pipeline,
+        None => return Ok(()),
+    };
+    info!(
+        "Canceling pipeline {} of superseded head {} on project={}",
+        pipeline.pipeline_id, previous.head_sha, pipeline.gitlab_project
+    );
+    cancel_pipeline(
+        pr_handle,
+        &pipeline,
+        &format!("superseded by {}", pr_handle.head_sha),
+    )
+    .await
+}
+
+/// Cancels one of a PR's pipelines, noting why in the PR's history
+async fn cancel_pipeline(
+    pr_handle: &PrHandle,
+    pipeline: &state::Pipeline,
+    reason: &str,
+) -> Result<(), GitError> {
+    let client = api::new_client()?;
+    let result =
+        gitlab_client::cancel_pipeline(&client, &pipeline.gitlab_project, pipeline.pipeline_id)
+            .await;
+    pr_handle.audit_pipeline(audit::Action::PipelineCancel, pipeline, &result);
+    result?;
+    state::record_pipeline(
+        &pipeline.gitlab_project,
+        pipeline.pipeline_id,
+        &pipeline.sha,
+        "canceled",
+    )?;
+    history::record(
+        &pr_handle.base_full_name,
+        pr_handle.pr_number,
+        history::Action::Pipeline,
+        &format!("pipeline {} canceled, {}", pipeline.pipeline_id, reason),
+    );
+    Ok(())
+}
+
+/// The pipeline still running (or pending) for a PR's head, if any
+fn running_pipeline(pr_handle: &PrHandle) -> Result<Option<state::Pipeline>, GitError> {
+    Ok(
+        state::latest_pipeline(&pr_handle.gitlab_project, &pr_handle.head_sha)?
+            .filter(|pipeline| ACTIVE_PIPELINE_STATUSES.contains(&pipeline.status.as_str())),
+    )
+}
+
+/// Handles a PR converted to a draft as the `converted_to_draft` setting
+/// says
+async fn handle_pr_converted_to_draft(pr: &dyn ForgePullRequest) -> Result<String, GitError> {
+    match config::CONFIG.actions.converted_to_draft {
+        config::DraftPolicy::Ignore => Ok(String::from("draft, nothing to do")),
+        config::DraftPolicy::CancelPipeline => {
+            let pr_handle = PrHandle::new(pr);
+            match running_pipeline(&pr_handle)? {
+                Some(pipeline) => {
+                    info!(
+                        "Canceling pipeline {} of PR {} converted to a draft",
+                        pipeline.pipeline_id,
+                        pr.number()
+                    );
+                    cancel_pipeline(&pr_handle, &pipeline, "converted to a draft").await?;
+                    Ok(format!("canceled pipeline {}", pipeline.pipeline_id))
+                }
+                None => Ok(String::from("draft, no pipeline running")),
+            }
+        }
+        config::DraftPolicy::Close => close_prs(&[pr]).await,
+    }
+}
+
+/// Title of the GitLab merge request of a PR
+fn merge_request_title(pr: &dyn ForgePullRequest) -> String {
+    match pr.title() {
+        Some(title) => format!("{} (#{})", title, pr.number()),
+        None => format!("{}#{}", pr.base_full_name(), pr.number()),
+    }
+}
+
+/// Opens a GitLab merge request from a PR's branch to its base branch, or
+/// updates the open one if the PR's title or base changed, with the repo's
+/// `merge_requests` set
+async fn sync_merge_request(
+    pr: &dyn ForgePullRequest,
+    pr_handle: &PrHandle,
+) -> Result<(), GitError> {
+    let client = api::new_client()?;
+    let project = &pr_handle.gitlab_project;
+    let branch = pr_handle.gitlab_branch();
+    let title = merge_request_title(pr);
+    let description = messages::for_repo(
+        pr.base_full_name(),
+        Message::MergeRequestDescription {
+            repo: pr.base_full_name(),
+            number: pr.number(),
+            url: pr.html_url(),
+            author: pr.author(),
+        },
+    );
+    let open = gitlab_client::get_merge_requests(&client, project, &branch).await?;
+    let result = match open.first() {
+        Some(mr)
+            if mr.title.as_deref() == Some(title.as_str())
+                && mr.target_branch.as_deref() == Some(pr.base_ref())
+                && mr.description.as_deref() == Some(description.as_str()) =>
+        {
+            // Up to date, but the state store may not know it yet
+            if let Some(iid) = mr.iid {
+                state::record_merge_request(
+                    &pr_handle.base_full_name,
+                    pr_handle.pr_number,
+                    project,
+                    iid,
+                )?;
+            }
+            return Ok(());
+        }
+        Some(mr) => {
+            let iid = mr.iid.ok_or(GitError::UnexpectedResponse(format!(
+                "Merge request of {} has no iid",
+                branch
+            )))?;
+            gitlab_client::update_merge_request(
+                &client,
+                project,
+                iid,
+                pr.base_ref(),
+                &title,
+                &description,
+            )
+            .await
+        }
+        None => {
+            gitlab_client::create_merge_request(
+                &client,
+                project,
+                &branch,
+                pr.base_ref(),
+                &title,
+                &description,
+                &pr.labels(),
+            )
+            .await
+        }
+    };
+    pr_handle.audit(audit::Action::MergeRequest, &result);
+    let mr = result?;
+    let iid = mr.iid.ok_or(GitError::UnexpectedResponse(format!(
+        "Merge request of {} has no iid",
+        branch
+    )))?;
+    info!(
+        "Merge request {} of PR {} is at {}",
+        iid,
+        pr_handle.pr_number,
+        mr.web_url.unwrap_or_default()
+    );
+    if state::merge_request(&pr_handle.base_full_name, pr_handle.pr_number)?.is_none() {
+        history::record(
+            &pr_handle.base_full_name,
+            pr_handle.pr_number,
+            history::Action::MergeRequest,
+            &format!("merge request {} opened on {}", iid, project),
+        );
+    }
+    state::record_merge_request(&pr_handle.base_full_name, pr_handle.pr_number, project, iid)?;
+    Ok(())
+}
+
+/// Checks that a PR's GitLab branch is at the commit LabHub pushed
+fn check_branch_tip(
+    pr_handle: &PrHandle,
+    branch: &gitlab::Branch,
+    pushed: &str,
+) -> Result<(), GitError> {
+    let tip = branch
+        .commit
+        .as_ref()
+        .and_then(|commit| commit.id.as_deref());
+    if tip == Some(pushed) {
+        return Ok(());
+    }
+    Err(GitError::Other(format!(
+        "Branch {} on project={} is at {}, not at the pushed {}",
+        pr_handle.gitlab_branch(),
+        pr_handle.gitlab_project,
+        tip.unwrap_or("no commit"),
+        pushed
+    )))
+}
+
+async fn handle_pr_pushed(pr: &dyn ForgePullRequest) -> Result<String, GitError> {
+    sync_pr_head(pr, &mut PrHandle::new(pr)).await
+}
+
+/// Pushes a PR's head to its GitLab branch, completing `pr_handle`'s head
+/// SHA from the fetched commit
+async fn sync_pr_head(
+    pr: &dyn ForgePullRequest,
+    pr_handle: &mut PrHandle,
+) -> Result<String, GitError> {
+    let previous = state::latest_pr_sync(&pr_handle.base_full_name, pr_handle.pr_number)?;
+    provisioning::ensure_project(&pr_handle.gitlab_project).await?;
+    let pushed = handle_pr_updated(pr, pr_handle).await?;
+    // Another event for the PR, e.g. on another replica, may have pushed to
+    // the branch in between
+    let branch = wait_for_gitlab_branch(pr_handle).await?;
+    let pushed = match check_branch_tip(pr_handle, &branch, &pushed) {
+        Ok(()) => pushed,
+        Err(err) => {
+            let latest = state::latest_pr_sync(&pr_handle.base_full_name, pr_handle.pr_number)?;
+            let synced = |sync: &Option<state::PrSync>| {
+                sync.as_ref()
+                    .map(|sync| (sync.head_sha.clone(), sync.synced_at))
+            };
+            if synced(&latest) != synced(&previous) {
+                warn!("{}, after a concurrent sync of the PR", err);
+                return Ok(String::from("superseded by a concurrent sync"));
+            }
+            warn!("{}, pushing again", err);
+            let pushed = handle_pr_updated(pr, pr_handle).await?;
+            let branch = wait_for_gitlab_branch(pr_handle).await?;
+            check_branch_tip(pr_handle, &branch, &pushed)?;
+            pushed
+        }
+    };
+    state::record_pr_sync(
+        &pr_handle.base_full_name,
+        pr_handle.pr_number,
+        &pr_handle.head_sha,
+        &pr_handle.gitlab_project,
+        &pr_handle.gitlab_branch(),
+    )?;
+    history::record(
+        &pr_handle.base_full_name,
+        pr_handle.pr_number,
+        history::Action::Mirrored,
+        &format!(
+            "{} to {} on {}",
+            pr_handle.head_sha,
+            pr_handle.gitlab_branch(),
+            pr_handle.gitlab_project
+        ),
+    );
+    info!(
+        "Branch {} is on GitLab at commit {}",
+        pr_handle.gitlab_branch(),
+        pushed
+    );
+    event_webhooks::emit(Event::pr_synced(pr));
+    if let Some(previous) = previous.filter(|_| config::auto_cancel_for_repo(pr.base_full_name())) {
+        if let Err(err) = cancel_superseded_pipeline(pr_handle, &previous).await {
+            error!("Error canceling superseded pipeline: {:?}", err);
+        }
+    }
+    // The push went through, so a merge request error doesn't fail the sync,
+    // which would be retried and push again
+    if config::merge_requests_for_repo(pr.base_full_name()) {
+        if let Err(err) = sync_merge_request(pr, pr_handle).await {
+            error!("Error syncing the merge request: {:?}", err);
+        }
+    }
+    Ok(String::from(":)"))
+}
+
+fn handle_pr_updated_with_repo(
+    repo: &mut dyn RepositoryExt,
+    pr_handle: &mut PrHandle,
+) -> Result<String, GitError> {
+    info!("handle_pr_updated_with_repo");
+    info!("pr_handle={:#?}", pr_handle);
+
+    fetch_pr_with_repo(repo, pr_handle)?;
+    let result = repo.push_pr_ref(pr_handle);
+    pr_handle.audit(audit::Action::RefPush, &result);
+    result
+}
+
+/// Fetches a PR's head and points its GitLab branch at it, ready to push.
+/// The head's SHA is completed from the fetched commit, as it may be
+/// abbreviated.
+fn fetch_pr_with_repo(
+    repo: &mut dyn RepositoryExt,
+    pr_handle: &mut PrHandle,
+) -> Result<(), GitError> {
+    repo.add_remotes(pr_handle)?;
+    repo.fetch_source_remote(pr_handle)?;
+    pr_handle.head_sha = repo.create_ref_for_pr(pr_handle)?;
+    if !config::CONFIG.submodules.rewrites.is_empty() {
+        repo.rewrite_submodules(pr_handle)?;
+    }
+    let profile = config::pipeline_profile(pr_handle.trusted);
+    if let Some(ci_config) = ci_config::pinned(profile, pr_handle.is_fork)? {
+        repo.pin_ci_config(pr_handle, &ci_config)?;
+    }
+    Ok(())
+}
+
+/// Re-syncs a PR whose base branch changed, and starts a new pipeline on its
+/// branch since pushing an unchanged head won't trigger one. With the repo's
+/// `auto_cancel` set, the pipeline still running against the previous base
+/// is canceled first.
+async fn handle_pr_retargeted(pr: &dyn ForgePullRequest) -> Result<String, GitError> {
+    let previous_base = pr.previous_base_ref().unwrap_or_default();
+    info!(
+        "PR base changed from {} to {}",
+        previous_base,
+        pr.base_ref()
+    );
+    let mut pr_handle = PrHandle::new(pr);
+    let result = sync_pr_head(pr, &mut pr_handle).await?;
+
+    let client = api::new_client()?;
+    let project = &pr_handle.gitlab_project;
+    if config::auto_cancel_for_repo(pr.base_full_name()) {
+        if let Some(pipeline) = running_pipeline(&pr_handle)? {
+            info!(
+                "Canceling pipeline {} run against the previous base {}",
+                pipeline.pipeline_id, previous_base
+            );
+            let reason = format!("the base changed to {}", pr.base_ref());
+            if let Err(err) = cancel_pipeline(&pr_handle, &pipeline, &reason).await {
+                error!(
+                    "Unable to cancel pipeline {} of retargeted PR {}: {:?}",
+                    pipeline.pipeline_id, pr_handle.pr_number, err
+                );
+            }
+        }
+    }
+    let pipeline = gitlab_client::create_pipeline(
+        &client,
+        project,
+        &pr_handle.gitlab_branch(),
+        &pr_handle.pipeline_variables(),
+    )
+    .await?;
+    if let Some(id) = pipeline.id {
+        info!("Created pipeline id={} for retargeted PR", id);
+        history::record(
+            &pr_handle.base_full_name,
+            pr_handle.pr_number,
+            history::Action::Pipeline,
+            &format!(
+                "pipeline {} created after the base changed from {} to {}",
+                id,
+                previous_base,
+                pr.base_ref()
+            ),
+        );
+        state::record_pipeline(
+            project,
+            id,
+            &pr_handle.head_sha,
+            pipeline.status.as_deref().unwrap_or("created"),
+        )?;
+    }
+    Ok(result)
+}
+
+/// Syncs a PR event from any forge to GitLab. This is run by the queue
+/// worker, see [`handle_pr`].
+pub(crate) async fn sync_pr(pr: &dyn ForgePullRequest) -> Result<String, GitError> {
+    match pr.action() {
+        "closed" => return close_prs(&[pr]).await,
+        "converted_to_draft" => return handle_pr_converted_to_draft(pr).await,
+        "edited" if pr.previous_base_ref().is_none() => {
+            // The title may have changed, which the merge request follows
+            let repo = repo_name::lookup_key(pr.base_full_name());
+            if config::merge_requests_for_repo(pr.base_full_name())
+                && state::latest_pr_sync(&repo, pr.number())?.is_some()
+            {
+                sync_merge_request(pr, &PrHandle::new(pr)).await?;
+                return Ok(String::from("merge request updated"));
+            }
+            return Ok(String::from("base unchanged, nothing to do"));
+        }
+        // Labels reach pipelines through the merge request, or
+        // LABHUB_PR_LABELS once the head is pushed again, so the head isn't
+        // pushed for a label alone
+        "labeled" | "unlabeled" => {
+            if config::merge_requests_for_repo(pr.base_full_name()) {
+                return sync_merge_request_label(pr).await;
+            }
+            return Ok(String::from("no merge request to label, nothing to do"));
+        }
+        _ => {}
+    }
+    if pr.is_draft() && config::CONFIG.actions.skip_drafts {
+        info!(
+            "PR {} is a draft, waiting for it to be ready for review",
+            pr.number()
+        );
+        return Ok(String::from("draft, not synced until ready for review"));
+    }
+    if let Some(files) = protected::needs_approval(pr).await? {
+        warn!(
+            "Head {} of PR {} changes protected paths {:?}, holding it for approval",
+            pr.head_sha(),
+            pr.number(),
+            files
+        );
+        github::post_protected_paths_notice(
+            pr.base_full_name(),
+            pr.number(),
+            pr.head_sha(),
+            &files,
+        )
+        .await?;
+        return Ok(format!("held {} for approval", pr.head_sha()));
+    }
+    match pr.action() {
+        // The base branch changed: resync, and run a pipeline against the
+        // new base
+        "edited" => handle_pr_retargeted(pr).await,
+        // `opened`, `reopened`, `synchronize` and `ready_for_review`, and
+        // any other enabled action, (re)sync the PR's head
+        _ => handle_pr_pushed(pr).await,
+    }
+}
+
+/// Whether a PR is bridged to GitLab: fork PRs are, and with the
+/// `sync_internal_prs` feature, PRs from branches of the base repo too
+pub(crate) fn is_bridged(pr: &dyn ForgePullRequest) -> bool {
+    pr.is_fork() || config::feature_enabled(&config::Feature::SyncInternalPrs)
+}
+
+pub(crate) fn handle_pr(pr: Box<dyn ForgePullRequest>) -> Result<(), GitError> {
+    forge::validate(pr.as_ref())?;
+    if is_bridged(pr.as_ref()) {
+        if let Some(pr) = pause::intercept(pr) {
+            info!("Queueing sync of PR");
+            queue::enqueue(pr);
+        }
+    } else {
+        info!("Skipping PR because it's not a fork, cya 👋");
+    }
+    Ok(())
+}
+
+/// Trailer of the commits LabHub generates, with an HMAC of the PR head they
+/// were made for, so contributors can't pass off their own commits as
+/// generated ones by copying the title
+const SOURCE_TRAILER: &str = "LabHub-Source-Signature: ";
+
+/// The HMAC of a PR head, keyed with the first GitLab instance's webhook
+/// secret, which only LabHub and GitLab know
+fn source_signature(source_sha: &str) -> String {
+    let secret = config::CONFIG.gitlab[0].site.webhook_secret();
+    let key = hmac::SigningKey::new(&digest::SHA256, secret.as_bytes());
+    hex::encode(hmac::sign(&key, source_sha.as_bytes()).as_ref())
+}
+
+/// The trailer ending the message of a commit generated for `source_sha`
+fn source_trailer(source_sha: &str) -> String {
+    format!("{}{}\n", SOURCE_TRAILER, source_signature(source_sha))
+}
+
+/// Returns the PR head a commit generated by LabHub was made for, from its
+/// title, if its message also has the trailer signing that head
+pub(crate) fn generated_commit_source(commit_message: &str) -> Option<&str> {
+    let title = commit_message.lines().next()?;
+    let source_sha = ci_config::source_sha(title).or_else(|| submodules::source_sha(title))?;
+    let signed = commit_message
+        .lines()
+        .filter_map(|line| line.trim().strip_prefix(SOURCE_TRAILER))
+        .any(|signature| signature == source_signature(source_sha));
+    if signed {
+        Some(source_sha)
+    } else {
+        None
+    }
+}
+
+/// Returns true if the PR's head commit is already on its GitLab branch
+pub(crate) async fn is_pr_synced(
+    client: &reqwest::Client,
+    pr: &dyn ForgePullRequest,
+) -> Result<bool, GitError> {
+    let pr_handle = PrHandle::new(pr);
+    let branch = gitlab_client::get_branch(
+        client,
+        &pr_handle.gitlab_project,
+        &pr_handle.gitlab_branch(),
+    )
+    .await?;
+    let commit = match branch.and_then(|b| b.commit) {
+        Some(commit) => commit,
+        None => return Ok(false),
+    };
+    // With submodule rewrites or a pinned CI config, the branch is at a
+    // generated commit on top
+    let head = commit
+        .message
+        .as_deref()
+        .and_then(generated_commit_source)
+        .or(commit.id.as_deref());
+    Ok(head.is_some_and(|head| is_head(&pr_handle.head_sha, head)))
+}
+
+/// Entry points into the git layer for the benchmarks in `benches/`, which
+/// can only reach public items. Not a stable API.
+#[cfg(feature = "bench")]
+pub mod bench {
+    use super::*;
+
+    pub struct ClonedRepo(RepoData);
+
+    /// Clones a repo the way PR syncs do, with the first GitHub site's
+    /// credentials
+    pub fn clone(url: &str) -> Result<ClonedRepo, GitError> {
+        clone_repo(&config::CONFIG.github[0].site, url).map(ClonedRepo)
+    }
+
+    impl ClonedRepo {
+        /// Fetches a PR's head and points its GitLab branch at it
+        pub fn fetch_pr(&mut self, pr: &dyn ForgePullRequest) -> Result<(), GitError> {
+            fetch_pr_with_repo(&mut self.0.repo, &mut PrHandle::new(pr))
+        }
+
+        /// Pushes a fetched PR's GitLab branch to `gitlab_url` instead of
+        /// the configured GitLab instance
+        pub fn push_pr(&self, pr: &dyn ForgePullRequest, gitlab_url: &str) -> Result<(), GitError> {
+            let pr_handle = PrHandle::new(pr);
+            self.0
+                .repo
+                .remote_set_url(&pr_handle.gitlab_remote, gitlab_url)?;
+            self.0.repo.push_pr_ref(&pr_handle).map(|_| ())
+        }
+    }
+}
+
+#[cfg(test)]
+#[allow(clippy::bool_assert_comparison)]
+mod test {
+    use super::*;
+    use crate::api::models::github;
+    use crate::testing::{assert_within_budget, read_testdata_to_string, run_test};
+
+    /// A PR from a made-up forge, so the engine can be tested without any
+    /// real payloads
+    #[derive(Debug)]
+    struct FakePullRequest {
+        action: &'static str,
+        number: i64,
+    }
+
+    impl ForgePullRequest for FakePullRequest {
+        fn forge(&self) -> Forge {
+            Forge::Gitea
+        }
+
+        fn action(&self) -> &str {
+            self.action
+        }
+
+        fn number(&self) -> i64 {
+            self.number
+        }
+
+        fn is_fork(&self) -> bool {
+            true
+        }
+
+        fn base_full_name(&self) -> &str {
+            "Upstream/Project"
+        }
+
+        fn base_clone_url(&self) -> &str {
+            "git@forge.example.com:upstream/project.git"
+        }
+
+        fn base_ref(&self) -> &str {
+            "main"
+        }
+
+        fn previous_base_ref(&self) -> Option<&str> {
+            None
+        }
+
+        fn head_full_name(&self) -> &str {
+            "contributor/project"
+        }
+
+        fn head_clone_url(&self) -> &str {
+            "git@forge.example.com:contributor/project.git"
+        }
+
+        fn head_ref(&self) -> &str {
+            "feature"
+        }
+
+        fn head_sha(&self) -> &str {
+            "abc123"
+        }
+
+        fn gitlab_project(&self) -> String {
+            "mirror/project".to_string()
+        }
+    }
+
+    /// Records the git operations the engine asks for
+    #[derive(Default)]
+    struct FakeRepository {
+        calls: std::cell::RefCell<Vec<String>>,
+    }
+
+    impl FakeRepository {
+        fn record(&self, call: &str, pr_handle: &PrHandle) -> Result<(), GitError> {
+            self.calls
+                .borrow_mut()
+                .push(format!("{} {}", call, pr_handle.gitlab_branch()));
+            Ok(())
+        }
+    }
+
+    impl RepositoryExt for FakeRepository {
+        fn add_remotes(&mut self, pr_handle: &PrHandle) -> Result<(), GitError> {
+            self.record("add_remotes", pr_handle)
+        }
+
+        fn fetch_source_remote(&self, pr_handle: &PrHandle) -> Result<(), GitError> {
+            self.record("fetch", pr_handle)
+        }
+
+        fn create_ref_for_pr(&self, pr_handle: &PrHandle) -> Result<String, GitError> {
+            self.record("create_ref", pr_handle)?;
+            Ok(pr_handle.head_sha.clone())
+        }
+
+        fn push_pr_ref(&self, pr_handle: &PrHandle) -> Result<String, GitError> {
+            self.record("push", pr_handle)?;
+            Ok(pr_handle.head_sha.clone())
+        }
+
+        fn delete_pr_refs(&self, pr_handles: &[PrHandle]) -> Result<(), GitError> {
+            let branches: Vec<String> = pr_handles.iter().map(|h| h.gitlab_branch()).collect();
+            self.calls
+                .borrow_mut()
+                .push(format!("delete {}", branches.join(" ")));
+            Ok(())
+        }
+
+        fn rewrite_submodules(&self, pr_handle: &PrHandle) -> Result<(), GitError> {
+            self.record("rewrite_submodules", pr_handle)
+        }
+
+        fn pin_ci_config(&self, pr_handle: &PrHandle, _: &[u8]) -> Result<(), GitError> {
+            self.record("pin_ci_config", pr_handle)
+        }
+
+        fn lfs_pointers(&self, pr_handle: &PrHandle) -> Result<Vec<lfs_client::Object>, GitError> {
+            self.record("lfs_pointers", pr_handle)?;
+            Ok(vec![])
+        }
+    }
+
+    #[test]
+    fn fake_forge_pr_handle() {
+        let pr_handle = PrHandle::new(&FakePullRequest {
+            action: "opened",
+            number: 7,
+        });
+        assert_eq!(pr_handle.forge, Forge::Gitea);
+        assert_eq!(pr_handle.source_remote, "gitea-7");
+        assert_eq!(pr_handle.base_full_name, "upstream/project");
+        assert_eq!(pr_handle.gitlab_project, "mirror/project");
+        assert_eq!(
+            pr_handle.gitlab_branch(),
+            "pr-7/contributor/project/feature"
+        );
+    }
+
+    #[test]
+    fn idle_repo_eviction() {
+        let repo_data = |idle: Duration| {
+            let dir = disk::clone_dir().unwrap();
+            RepoData {
+                repo: Repository::init_bare(dir.path()).unwrap(),
+                dir,
+                last_used: Instant::now() - idle,
+                bytes: 0,
+            }
+        };
+        let mut repos = HashMap::new();
+        repos.insert("idle".to_string(), repo_data(Duration::from_secs(7200)));
+        repos.insert("busy".to_string(), repo_data(Duration::from_secs(60)));
+        let idle_dir = repos["idle"].dir.path().to_path_buf();
+
+        let evicted = evict_idle_repos_from(&mut repos, Duration::from_secs(3600));
+        assert_eq!(evicted, ["idle"]);
+        assert!(!idle_dir.exists());
+        assert!(repos.contains_key("busy"));
+        assert!(evict_idle_repos_from(&mut repos, Duration::from_secs(3600)).is_empty());
+    }
+
+    #[test]
+    fn poisoned_repos_recovery() {
+        let dir = disk::clone_dir().unwrap();
+        let path = dir.path().to_path_buf();
+        let mut cached = HashMap::new();
+        cached.insert(
+            "url".to_string(),
+            RepoData {
+                repo: Repository::init_bare(dir.path()).unwrap(),
+                dir,
+                last_used: Instant::now(),
+                bytes: 0,
+            },
+        );
+        let repos = Mutex::new(cached);
+        let _ = std::panic::catch_unwind(|| {
+            let _repos = repos.lock().unwrap();
+            panic!("sync failed");
+        });
+        assert!(repos.is_poisoned());
+
+        assert!(lock_recovering(&repos).is_empty());
+        assert!(!path.exists());
+        assert!(!repos.is_poisoned());
+    }
+
+    #[test]
+    fn branch_tip() {
+        let pr_handle = PrHandle::new(&FakePullRequest {
+            action: "opened",
+            number: 7,
+        });
+        let branch = |id: Option<&str>| gitlab::Branch {
+            name: Some(pr_handle.gitlab_branch()),
+            merged: None,
+            protected: None,
+            default: None,
+            web_url: None,
+            commit: id.map(|id| gitlab::BranchCommit {
+                id: Some(id.to_string()),
+                short_id: None,
+                title: None,
+                message: None,
+                committed_date: None,
+            }),
+        };
+        assert!(check_branch_tip(&pr_handle, &branch(Some("a91957a8")), "a91957a8").is_ok());
+        assert_eq!(
+            check_branch_tip(&pr_handle, &branch(Some("b3c4d5e6")), "a91957a8")
+                .unwrap_err()
+                .to_string(),
+            "Branch pr-7/contributor/project/feature on project=mirror/project is at \
+             b3c4d5e6, not at the pushed a91957a8"
+        );
+        assert!(check_branch_tip(&pr_handle, &branch(None), "a91957a8").is_err());
+    }
+
+    #[test]
+    fn generated_commit_sources() {
+        let sha = "a91957a8c5bd3b95ac0f41d0f4ec1a6ba1e40d52";
+        let message = format!(
+            "{}\n{}",
+            ci_config::commit_message(sha),
+            source_trailer(sha)
+        );
+        assert_eq!(generated_commit_source(&message), Some(sha));
+        let message = format!(
+            "{}\n{}",
+            submodules::commit_message(sha),
+            source_trailer(sha)
+        );
+        assert_eq!(generated_commit_source(&message), Some(sha));
+        // A contributor's commit copying the title isn't generated
+        assert_eq!(
+            generated_commit_source(&ci_config::commit_message(sha)),
+            None
+        );
+        let forged = format!(
+            "{}\n{}",
+            ci_config::commit_message(sha),
+            source_trailer("b3c4d5e6")
+        );
+        assert_eq!(generated_commit_source(&forged), None);
+    }
+
+    #[test]
+    fn merge_request_title_without_pr_title() {
+        let pr = FakePullRequest {
+            action: "opened",
+            number: 7,
+        };
+        assert_eq!(merge_request_title(&pr), "Upstream/Project#7");
+    }
+
+    #[test]
+    fn narrow_fetch() {
+        let pr_handle = PrHandle::new(&FakePullRequest {
+            action: "opened",
+            number: 7,
+        });
+        assert_eq!(
+            narrow_fetch_refspec(&config::CloneMode::Narrow, &pr_handle).as_deref(),
+            Some("+refs/pull/7/head:refs/remotes/gitea-7/feature")
+        );
+        assert_eq!(
+            narrow_fetch_refspec(&config::CloneMode::Full, &pr_handle),
+            None
+        );
+    }
+
+    #[test]
+    fn narrow_fetch_of_moved_head() {
+        let dir = disk::clone_dir().unwrap();
+        let repo = Repository::init_bare(dir.path()).unwrap();
+        let tree = repo
+            .find_tree(repo.treebuilder(None).unwrap().write().unwrap())
+            .unwrap();
+        let signature = git2::Signature::now("LabHub", "labhub@example.com").unwrap();
+        let id = repo
+            .commit(None, &signature, &signature, "Pushed since", &tree, &[])
+            .unwrap();
+        // Where the narrow refspec puts the pull ref
+        repo.reference("refs/remotes/gitea-7/feature", id, true, "fetch")
+            .unwrap();
+
+        let mut pr_handle = PrHandle::new(&FakePullRequest {
+            action: "opened",
+            number: 7,
+        });
+        assert!(matches!(
+            repo.create_ref_for_pr(&pr_handle),
+            Err(GitError::HeadMoved { .. })
+        ));
+        pr_handle.head_sha = id.to_string();
+        repo.create_ref_for_pr(&pr_handle).unwrap();
+        assert_eq!(
+            repo.refname_to_id("refs/heads/pr-7/contributor/project/feature")
+                .unwrap(),
+            id
+        );
+    }
+
+    #[test]
+    fn abbreviated_head_sha() {
+        let dir = disk::clone_dir().unwrap();
+        let repo = Repository::init_bare(dir.path()).unwrap();
+        let tree = repo
+            .find_tree(repo.treebuilder(None).unwrap().write().unwrap())
+            .unwrap();
+        let signature = git2::Signature::now("LabHub", "labhub@example.com").unwrap();
+        let id = repo
+            .commit(None, &signature, &signature, "Bitbucket head", &tree, &[])
+            .unwrap();
+        repo.reference("refs/remotes/gitea-7/feature", id, true, "fetch")
+            .unwrap();
+
+        // Bitbucket sends 12 characters of the head's SHA
+        let mut pr_handle = PrHandle::new(&FakePullRequest {
+            action: "opened",
+            number: 7,
+        });
+        pr_handle.head_sha = id.to_string()[..12].to_uppercase();
+        assert_eq!(repo.create_ref_for_pr(&pr_handle).unwrap(), id.to_string());
+        pr_handle.head_sha = id.to_string()[..6].to_string();
+        assert!(matches!(
+            repo.create_ref_for_pr(&pr_handle),
+            Err(GitError::HeadMoved { .. })
+        ));
+        pr_handle.head_sha = "9f8e7d6c5b4a".to_string();
+        assert!(matches!(
+            repo.create_ref_for_pr(&pr_handle),
+            Err(GitError::HeadMoved { .. })
+        ));
+    }
+
+    #[test]
+    fn pipeline_variables() {
+        let pr_handle = PrHandle::new(&FakePullRequest {
+            action: "opened",
+            number: 7,
+        });
+        let variables = pr_handle.pipeline_variables();
+        assert_eq!(variables["LABHUB_PR_NUMBER"], "7");
+        assert_eq!(variables["LABHUB_PR_AUTHOR"], "");
+        assert_eq!(variables["LABHUB_PR_LABELS"], "");
+        assert_eq!(variables["LABHUB_PR_TRUSTED"], "false");
+        assert_eq!(variables["LABHUB_FORGE"], "gitea");
+        assert_eq!(variables["LABHUB_HEAD_REPO"], "contributor/project");
+        assert_eq!(variables["LABHUB_HEAD_REF"], "feature");
+    }
+
+    #[test]
+    fn fake_forge_updated_and_closed() {
+        let mut repo = FakeRepository::default();
+        let pr = FakePullRequest {
+            action: "opened",
+            number: 7,
+        };
+        handle_pr_updated_with_repo(&mut repo, &mut PrHandle::new(&pr)).unwrap();
+        let pr = FakePullRequest {
+            action: "closed",
+            number: 7,
+        };
+        handle_prs_closed_with_repo(&mut repo, &[&pr]).unwrap();
+        let branch = "pr-7/contributor/project/feature";
+        assert_eq!(
+            *repo.calls.borrow(),
+            [
+                "add_remotes",
+                "fetch",
+                "create_ref",
+                "push",
+                "add_remotes",
+                "delete"
+            ]
+            .iter()
+            .map(|call| format!("{} {}", call, branch))
+            .collect::<Vec<String>>()
+        );
+    }
+
+    #[test]
+    fn fake_forge_closed_batch() {
+        let mut repo = FakeRepository::default();
+        let first = FakePullRequest {
+            action: "closed",
+            number: 7,
+        };
+        let second = FakePullRequest {
+            action: "closed",
+            number: 8,
+        };
+        handle_prs_closed_with_repo(&mut repo, &[&first, &second]).unwrap();
+        assert_eq!(
+            *repo.calls.borrow(),
+            vec![
+                "add_remotes pr-7/contributor/project/feature",
+                "add_remotes pr-8/contributor/project/feature",
+                "delete pr-7/contributor/project/feature pr-8/contributor/project/feature",
+            ]
+        );
+    }
+
+    #[test]
+    fn open_pr() {
+        run_test(|| {
+            info!("open_pr test");
+            let pr: github::PullRequest =
+                serde_json::from_str(&read_testdata_to_string("github_open_pull_request.json"))
+                    .unwrap();
+            assert_eq!(pr.is_fork(), false);
+            let _pr_handle = PrHandle::new(&pr);
+        });
+    }
+
+    #[test]
+    fn reopen_pr() {
+        run_test(|| {
+            info!("reopen_pr test");
+            let pr: github::PullRequest =
+                serde_json::from_str(&read_testdata_to_string("github_reopen_pull_request.json"))
+                    .unwrap();
+            assert_eq!(pr.is_fork(), false);
+            // the test config doesn't enable sync_internal_prs
+            assert!(!is_bridged(&pr));
+            let _pr_handle = PrHandle::new(&pr);
+        });
+    }
+
+    #[test]
+    fn open_pr_fork() {
+        run_test(|| {
+            info!("open_pr_fork test");
+            let pr: github::PullRequest =
+                serde_json::from_str(&read_testdata_to_string("github_open_pr_forked.json"))
+                    .unwrap();
+            assert_eq!(pr.is_fork(), true);
+            assert!(is_bridged(&pr));
+            let _pr_handle = PrHandle::new(&pr);
+        });
+    }
+
+    #[tokio::test]
+    async fn labeled_pr_without_merge_request() {
+        let pr: github::PullRequest =
+            serde_json::from_str(&read_testdata_to_string("github_pr_labeled_forked.json"))
+                .unwrap();
+        assert!(!config::merge_requests_for_repo(pr.base_full_name()));
+        // Nothing to push, so this returns without touching GitHub or GitLab
+        assert_eq!(
+            sync_pr(&pr).await.unwrap(),
+            "no merge request to label, nothing to do"
+        );
+    }
+
+    #[test]
+    fn edited_pr_base_fork() {
+        run_test(|| {
+            info!("edited_pr_base_fork test");
+            let pr: github::PullRequest = serde_json::from_str(&read_testdata_to_string(
+                "github_edited_pr_base_forked.json",
+            ))
+            .unwrap();
+            assert!(pr.is_fork());
+            assert_eq!(pr.previous_base_ref(), Some("develop"));
+
+            let pr: github::PullRequest =
+                serde_json::from_str(&read_testdata_to_string("github_open_pr_forked.json"))
+                    .unwrap();
+            assert_eq!(pr.previous_base_ref(), None);
+        });
+    }
+
+    #[test]
+    fn ref_naming_budget() {
+        let pr: github::PullRequest =
+            serde_json::from_str(&read_testdata_to_string("github_open_pr_forked.json")).unwrap();
+        assert_within_budget(
+            "PR handle and branch naming",
+            Duration::from_micros(200),
+            1000,
+            || PrHandle::new(&pr).gitlab_branch(),
+        );
+    }
+
+    #[test]
+    fn sanitized_branch() {
+        let mut event: serde_json::Value =
+            serde_json::from_str(&read_testdata_to_string("github_open_pr_forked.json")).unwrap();
+        event["pull_request"]["head"]["repo"]["full_name"] = "octocat/.github".into();
+        event["pull_request"]["head"]["ref"] = "fix: the ~config..lock".into();
+        let pr: github::PullRequest = serde_json::from_value(event.clone()).unwrap();
+        let pr_handle = PrHandle::new(&pr);
+        assert_eq!(
+            pr_handle.gitlab_branch(),
+            "pr-5/octocat/github/fix--the--config"
+        );
+        // the PR's own ref is still fetched as is
+        assert_eq!(pr_handle.gitref, "fix: the ~config..lock");
+
+        event["pull_request"]["head"]["ref"] = "a".repeat(300).into();
+        let pr: github::PullRequest = serde_json::from_value(event).unwrap();
+        let branch = PrHandle::new(&pr).gitlab_branch();
+        assert_eq!(branch.len(), crate::ref_name::MAX_LEN);
+        assert!(branch.starts_with("pr-5/octocat/github/aaa"));
+    }
+
+    #[test]
+    fn close_pr_fork() {
+        run_test(|| {
+            info!("close_pr_fork test");
+            let pr: github::PullRequest =
+                serde_json::from_str(&read_testdata_to_string("github_close_pr_forked.json"))
+                    .unwrap();
+            let _pr_handle = PrHandle::new(&pr);
+        });
+    }
+
+    #[test]
+    fn test_superseded_pipeline() {
+        let previous = state::PrSync {
+            github_repo: "org/repo".into(),
+            pr_number: 1,
+            head_sha: "old".into(),
+            gitlab_project: "group/repo".into(),
+            gitlab_branch: "pr-1/fork/repo/main".into(),
+            synced_at: 0,
+        };
+        let pipeline = |sha: &str, status: &str| {
+            Some(state::Pipeline {
+                gitlab_project: "group/repo".into(),
+                pipeline_id: 10,
+                sha: sha.into(),
+                status: status.into(),
+                updated_at: 0,
+            })
+        };
+        assert_eq!(
+            superseded_pipeline(&previous, "new", pipeline("old", "running")),
+            pipeline("old", "running")
+        );
+        assert!(superseded_pipeline(&previous, "new", pipeline("old", "pending")).is_some());
+        assert!(superseded_pipeline(&previous, "new", pipeline("old", "success")).is_none());
+        // the same head was synced again
+        assert!(superseded_pipeline(&previous, "old", pipeline("old", "running")).is_none());
+        assert!(superseded_pipeline(&previous, "new", None).is_none());
+    }
+}
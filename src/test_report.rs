@@ -0,0 +1,168 @@
+//! Test annotations: the failures of a pipeline's JUnit test report, as
+//! collected by GitLab, become a GitHub check run with an annotation on each
+//! failing test's file, so they show up inline on the PR diff.
+use crate::api::models::gitlab;
+use crate::config;
+use crate::messages::{self, Message};
+
+/// GitHub takes at most this many annotations per request
+const MAX_ANNOTATIONS: usize = 50;
+/// Stack traces are cut down to this many characters
+const MAX_MESSAGE_CHARS: usize = 4000;
+
+#[derive(Debug, PartialEq)]
+pub struct CheckRun {
+    pub conclusion: &'static str,
+    pub title: String,
+    pub summary: String,
+    pub annotations: Vec<serde_json::Value>,
+}
+
+fn is_failure(case: &gitlab::TestCase) -> bool {
+    matches!(case.status.as_deref(), Some("failed") | Some("error"))
+}
+
+/// Finds the line a test failed at, from the first `file:line` of its
+/// output which mentions the test's file
+fn failure_line(file: &str, case: &gitlab::TestCase) -> Option<i64> {
+    let name = file.rsplit('/').next().unwrap_or(file);
+    let prefix = format!("{}:", name);
+    [&case.stack_trace, &case.system_output]
+        .into_iter()
+        .flatten()
+        .find_map(|output| {
+            output.match_indices(&prefix).find_map(|(i, _)| {
+                let rest = &output[i + prefix.len()..];
+                let end = rest
+                    .find(|c: char| !c.is_ascii_digit())
+                    .unwrap_or(rest.len());
+                rest[..end].parse().ok()
+            })
+        })
+}
+
+fn test_name(case: &gitlab::TestCase) -> String {
+    match (case.classname.as_deref(), case.name.as_deref()) {
+        (Some(classname), Some(name)) => format!("{} {}", classname, name),
+        (None, Some(name)) => name.to_string(),
+        (Some(classname), None) => classname.to_string(),
+        (None, None) => "unnamed test".to_string(),
+    }
+}
+
+fn annotation(file: &str, case: &gitlab::TestCase) -> serde_json::Value {
+    let line = failure_line(file, case).unwrap_or(1);
+    let message: String = case
+        .stack_trace
+        .as_deref()
+        .or(case.system_output.as_deref())
+        .unwrap_or("Test failed")
+        .chars()
+        .take(MAX_MESSAGE_CHARS)
+        .collect();
+    serde_json::json!({
+        "path": file.trim_start_matches("./"),
+        "start_line": line,
+        "end_line": line,
+        "annotation_level": "failure",
+        "title": test_name(case),
+        "message": message,
+    })
+}
+
+/// Converts a test report into a check run, or `None` if it has no tests
+pub fn check_run(report: &gitlab::TestReport, language: config::Language) -> Option<CheckRun> {
+    let total = report.total_count.unwrap_or(0);
+    if total == 0 {
+        return None;
+    }
+    let failed = report.failed_count.unwrap_or(0) + report.error_count.unwrap_or(0);
+    let failures = report
+        .test_suites
+        .iter()
+        .flatten()
+        .flat_map(|suite| suite.test_cases.iter().flatten())
+        .filter(|case| is_failure(case));
+
+    let mut annotations = vec![];
+    let mut unannotated = vec![];
+    for case in failures {
+        match case.file.as_deref().filter(|file| !file.is_empty()) {
+            Some(file) if annotations.len() < MAX_ANNOTATIONS => {
+                annotations.push(annotation(file, case))
+            }
+            _ => unannotated.push(test_name(case)),
+        }
+    }
+
+    let mut summary = messages::text(
+        language,
+        Message::TestsSummary {
+            total,
+            passed: report.success_count.unwrap_or(0),
+            failed,
+            skipped: report.skipped_count.unwrap_or(0),
+        },
+    );
+    if !unannotated.is_empty() {
+        summary.push_str("\n\n");
+        summary.push_str(&messages::text(language, Message::UnannotatedFailures));
+        summary.push('\n');
+        for name in unannotated {
+            summary.push_str(&format!("\n- `{}`", name));
+        }
+    }
+    Some(CheckRun {
+        conclusion: if failed > 0 { "failure" } else { "success" },
+        title: if failed > 0 {
+            messages::text(language, Message::TestsFailed { failed, total })
+        } else {
+            messages::text(language, Message::TestsPassed { total })
+        },
+        summary,
+        annotations,
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::testing::read_testdata_to_string;
+
+    #[test]
+    fn test_check_run() {
+        let report: gitlab::TestReport =
+            serde_json::from_str(&read_testdata_to_string("gitlab_test_report.json")).unwrap();
+        let run = check_run(&report, config::Language::En).unwrap();
+        assert_eq!(run.conclusion, "failure");
+        assert_eq!(run.title, "2 of 5 tests failed");
+        assert_eq!(
+            run.summary,
+            "5 tests: 3 passed, 2 failed, 0 skipped.\n\n\
+             Failures which couldn't be annotated:\n\n\
+             - `Integration connects to the database`"
+        );
+        assert_eq!(
+            run.annotations,
+            [serde_json::json!({
+                "path": "spec/string_helper_spec.rb",
+                "start_line": 21,
+                "end_line": 21,
+                "annotation_level": "failure",
+                "title": "spec.test_string_helper strips whitespace",
+                "message": "Failure/Error: expect(strip(\" a \")).to eq(\"a\")\n\
+                            ./spec/string_helper_spec.rb:21:in `block (2 levels)'",
+            })]
+        );
+
+        let report = gitlab::TestReport {
+            total_count: Some(0),
+            success_count: None,
+            failed_count: None,
+            skipped_count: None,
+            error_count: None,
+            test_suites: None,
+        };
+        assert_eq!(check_run(&report, config::Language::En), None);
+    }
+}
@@ -28,7 +28,7 @@ fn setup() {
 
 pub fn run_test<T>(test: T)
 where
-    T: FnOnce() -> () + panic::UnwindSafe,
+    T: FnOnce() + panic::UnwindSafe,
 {
     setup();
 
@@ -36,3 +36,29 @@ where
 
     assert!(result.is_ok())
 }
+
+/// Fails if `f` takes longer than `budget` per call, on average over
+/// `iterations` calls. Budgets are for unoptimized test builds on a slow
+/// machine: generous enough not to be flaky, while catching the order of
+/// magnitude regressions which would show in webhook latency.
+pub fn assert_within_budget<T>(
+    name: &str,
+    budget: std::time::Duration,
+    iterations: u32,
+    mut f: impl FnMut() -> T,
+) {
+    // warm up lazy statics and caches
+    std::hint::black_box(f());
+    let start = std::time::Instant::now();
+    for _ in 0..iterations {
+        std::hint::black_box(f());
+    }
+    let per_call = start.elapsed() / iterations;
+    assert!(
+        per_call <= budget,
+        "{} took {:?} per call, over its budget of {:?}",
+        name,
+        per_call,
+        budget
+    );
+}
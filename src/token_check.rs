@@ -0,0 +1,252 @@
+use crate::api;
+use crate::api::github_client;
+use crate::config;
+use crate::errors::GitError;
+
+use log::{error, info, warn};
+
+/// A GitHub permission needed by some LabHub feature
+struct Requirement {
+    /// Name of the feature, as used in the config
+    feature: &'static str,
+    /// Fine-grained permission, ex: `issues:write`
+    permission: &'static str,
+    /// Any of these classic scopes grants the permission
+    classic_scopes: &'static [&'static str],
+}
+
+const REPO_SCOPES: &[&str] = &["repo", "public_repo"];
+
+fn enabled_requirements() -> Vec<Requirement> {
+    let mut requirements = vec![];
+    if config::feature_enabled(&config::Feature::Commands) {
+        requirements.push(Requirement {
+            feature: "commands",
+            permission: "pull_requests:read",
+            classic_scopes: REPO_SCOPES,
+        });
+        requirements.push(Requirement {
+            feature: "commands",
+            permission: "issues:write",
+            classic_scopes: REPO_SCOPES,
+        });
+    }
+    if config::feature_enabled(&config::Feature::StaleBranchCleanup) {
+        requirements.push(Requirement {
+            feature: "stale_branch_cleanup",
+            permission: "pull_requests:read",
+            classic_scopes: REPO_SCOPES,
+        });
+    }
+    if config::feature_enabled(&config::Feature::StartupReconciliation) {
+        requirements.push(Requirement {
+            feature: "startup_reconciliation",
+            permission: "pull_requests:read",
+            classic_scopes: REPO_SCOPES,
+        });
+    }
+    if config::feature_enabled(&config::Feature::Deployments) {
+        requirements.push(Requirement {
+            feature: "deployments",
+            permission: "deployments:write",
+            classic_scopes: &["repo", "public_repo", "repo_deployment"],
+        });
+    }
+    if config::feature_enabled(&config::Feature::ReviewAppComments) {
+        requirements.push(Requirement {
+            feature: "review_app_comments",
+            permission: "issues:write",
+            classic_scopes: REPO_SCOPES,
+        });
+    }
+    if config::feature_enabled(&config::Feature::ArtifactLinks) {
+        requirements.push(Requirement {
+            feature: "artifact_links",
+            permission: "issues:write",
+            classic_scopes: REPO_SCOPES,
+        });
+    }
+    if config::feature_enabled(&config::Feature::CoverageComments) {
+        requirements.push(Requirement {
+            feature: "coverage_comments",
+            permission: "pull_requests:read",
+            classic_scopes: REPO_SCOPES,
+        });
+        requirements.push(Requirement {
+            feature: "coverage_comments",
+            permission: "issues:write",
+            classic_scopes: REPO_SCOPES,
+        });
+    }
+    if config::feature_enabled(&config::Feature::PipelineSummary) {
+        requirements.push(Requirement {
+            feature: "pipeline_summary",
+            permission: "issues:write",
+            classic_scopes: REPO_SCOPES,
+        });
+    }
+    if config::feature_enabled(&config::Feature::ProtectedPaths) {
+        requirements.push(Requirement {
+            feature: "protected_paths",
+            permission: "pull_requests:read",
+            classic_scopes: REPO_SCOPES,
+        });
+        requirements.push(Requirement {
+            feature: "protected_paths",
+            permission: "issues:write",
+            classic_scopes: REPO_SCOPES,
+        });
+    }
+    if config::feature_enabled(&config::Feature::CommentMirroring) {
+        requirements.push(Requirement {
+            feature: "comment_mirroring",
+            permission: "issues:write",
+            classic_scopes: REPO_SCOPES,
+        });
+    }
+    if config::CONFIG.comments.stale_comment_policy != config::StaleCommentPolicy::Keep {
+        requirements.push(Requirement {
+            feature: "comments.stale_comment_policy",
+            permission: "issues:write",
+            classic_scopes: REPO_SCOPES,
+        });
+    }
+    requirements
+}
+
+/// Returns a warning for each requirement not covered by the token's scopes
+fn missing_classic_scopes(requirements: &[Requirement], scopes: &[String]) -> Vec<String> {
+    requirements
+        .iter()
+        .filter(|r| {
+            !r.classic_scopes
+                .iter()
+                .any(|s| scopes.iter().any(|t| t == s))
+        })
+        .map(|r| {
+            format!(
+                "{} enabled but GitHub token lacks {} (needs one of the scopes: {})",
+                r.feature,
+                r.permission,
+                r.classic_scopes.join(", ")
+            )
+        })
+        .collect()
+}
+
+async fn check_fine_grained_token(
+    client: &reqwest::Client,
+    instance: &config::GithubInstance,
+    requirements: &[Requirement],
+) -> Result<(), GitError> {
+    let needs_pulls = requirements
+        .iter()
+        .any(|r| r.permission == "pull_requests:read");
+    let mappings = config::CONFIG.mappings.iter().filter(|mapping| {
+        needs_pulls
+            && !mapping.is_wildcard()
+            && config::github_for_repo(&mapping.github_repo).name == instance.name
+    });
+    for mapping in mappings {
+        let parts: Vec<&str> = mapping.github_repo.split('/').collect();
+        if parts.len() != 2 {
+            continue;
+        }
+        let status = github_client::probe_pulls(client, parts[0], parts[1]).await?;
+        if !status.is_success() {
+            for r in requirements
+                .iter()
+                .filter(|r| r.permission == "pull_requests:read")
+            {
+                warn!(
+                    "{} enabled but GitHub token lacks pull_requests:read on {} (status={})",
+                    r.feature, mapping.github_repo, status
+                );
+            }
+        }
+    }
+    for r in requirements
+        .iter()
+        .filter(|r| r.permission.ends_with(":write"))
+    {
+        info!(
+            "{} requires {} on the mapped repos, which can't be verified for fine-grained tokens",
+            r.feature, r.permission
+        );
+    }
+    Ok(())
+}
+
+/// Checks each GitHub instance's token scopes against what the enabled
+/// features need, and logs a specific warning for anything missing rather
+/// than failing the first time the feature is used.
+pub async fn check_github_token() {
+    for instance in config::CONFIG.github.iter() {
+        check_instance_token(instance).await;
+    }
+}
+
+async fn check_instance_token(instance: &config::GithubInstance) {
+    if config::feature_enabled(&config::Feature::TestAnnotations) && instance.app.is_none() {
+        warn!(
+            "test_annotations enabled but GitHub {} has no [github.app], which check runs need",
+            instance.name
+        );
+    }
+    let requirements = enabled_requirements();
+    let result = async {
+        let client = api::new_client()?;
+        match github_client::get_token_scopes(&client, instance).await? {
+            Some(scopes) => {
+                info!("GitHub {} token scopes: {:?}", instance.name, scopes);
+                for warning in missing_classic_scopes(&requirements, &scopes) {
+                    warn!("{}", warning);
+                }
+                Ok(())
+            }
+            None => {
+                info!(
+                    "GitHub {} token is fine-grained, probing repo permissions",
+                    instance.name
+                );
+                check_fine_grained_token(&client, instance, &requirements).await
+            }
+        }
+    }
+    .await;
+    if let Err(err) = result {
+        error!(
+            "Unable to check GitHub {} token permissions: {:?}",
+            instance.name, err
+        );
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_missing_classic_scopes() {
+        let requirements = vec![
+            Requirement {
+                feature: "commands",
+                permission: "issues:write",
+                classic_scopes: REPO_SCOPES,
+            },
+            Requirement {
+                feature: "other",
+                permission: "checks:write",
+                classic_scopes: &["repo"],
+            },
+        ];
+        assert!(missing_classic_scopes(&requirements, &["repo".to_string()]).is_empty());
+        assert_eq!(
+            missing_classic_scopes(&requirements, &["public_repo".to_string()]),
+            vec![
+                "other enabled but GitHub token lacks checks:write (needs one of the scopes: repo)"
+            ]
+        );
+        assert_eq!(missing_classic_scopes(&requirements, &[]).len(), 2);
+    }
+}
@@ -0,0 +1,41 @@
+//! Whether a PR's author is trusted, which picks the pipeline profile and
+//! GitLab project its branch is pushed with.
+use crate::config;
+use crate::forge::ForgePullRequest;
+
+fn is_trusted_by(trust: &config::Trust, author: Option<&str>, association: Option<&str>) -> bool {
+    let trusted_user = author.is_some_and(|author| {
+        trust
+            .trusted_users
+            .iter()
+            .any(|user| user.eq_ignore_ascii_case(author))
+    });
+    let trusted_association = association.is_some_and(|association| {
+        trust
+            .trusted_associations
+            .iter()
+            .any(|trusted| trusted.eq_ignore_ascii_case(association))
+    });
+    trusted_user || trusted_association
+}
+
+pub fn is_trusted(pr: &dyn ForgePullRequest) -> bool {
+    is_trusted_by(&config::CONFIG.trust, pr.author(), pr.author_association())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_is_trusted_by() {
+        let trust = config::Trust {
+            trusted_users: vec!["Octocat".to_string()],
+            ..Default::default()
+        };
+        assert!(is_trusted_by(&trust, Some("octocat"), None));
+        assert!(is_trusted_by(&trust, Some("someone"), Some("MEMBER")));
+        assert!(!is_trusted_by(&trust, Some("someone"), Some("CONTRIBUTOR")));
+        assert!(!is_trusted_by(&trust, None, None));
+    }
+}
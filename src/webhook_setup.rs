@@ -0,0 +1,500 @@
+//! Sets up the webhooks of the mapped repos: the GitHub webhook of each
+//! repo, sending the events LabHub needs to `/github/events` signed with
+//! its secret, and the webhook of its GitLab projects, sending pipeline,
+//! job, deployment and note events to `/gitlab/events`. Orgs listed in
+//! `org_webhooks` get one org webhook instead of a webhook per repo, and the
+//! repo webhooks pointing at LabHub are deleted, as they'd send the same
+//! events. Webhooks already pointing at LabHub are updated rather than added
+//! again.
+use crate::api::{self, github_client, gitlab_client};
+use crate::config;
+use crate::errors::GitError;
+use crate::github;
+
+use log::{info, warn};
+use serde_json::json;
+
+/// Tokens to set the webhooks up with, which must be able to administer
+/// them. The configured API tokens are used for those left out.
+#[derive(Debug, Default, Deserialize)]
+pub struct Credentials {
+    pub github_token: Option<String>,
+    pub gitlab_token: Option<String>,
+}
+
+/// What was done to a webhook
+#[derive(Debug, Serialize, PartialEq)]
+#[serde(tag = "result", rename_all = "snake_case")]
+pub enum HookResult {
+    Created { id: Option<i64> },
+    Updated { id: Option<i64> },
+    Deleted { id: Option<i64> },
+    Failed { error: String },
+}
+
+/// The webhook of a GitHub repo or org, or of a GitLab project
+#[derive(Debug, Serialize, PartialEq)]
+pub struct HookSetup {
+    /// `github` or `gitlab`
+    pub forge: &'static str,
+    /// The repo, project or org
+    pub repo: String,
+    pub url: String,
+    #[serde(flatten)]
+    pub result: HookResult,
+}
+
+impl HookResult {
+    fn saved(existing: Option<i64>, id: Option<i64>) -> HookResult {
+        match existing {
+            Some(_) => HookResult::Updated { id },
+            None => HookResult::Created { id },
+        }
+    }
+}
+
+impl From<Result<HookResult, GitError>> for HookResult {
+    fn from(result: Result<HookResult, GitError>) -> Self {
+        result.unwrap_or_else(|err| HookResult::Failed {
+            error: err.to_string(),
+        })
+    }
+}
+
+/// The GitHub webhook of a repo, sending `events` to `url`
+fn github_hook(url: &str, secret: &str, events: &[&str]) -> serde_json::Value {
+    json!({
+        "name": "web",
+        "active": true,
+        "events": events,
+        "config": {
+            "url": url,
+            "content_type": "json",
+            "secret": secret,
+            "insecure_ssl": "0",
+        },
+    })
+}
+
+/// The GitLab webhook of a project, sending the events LabHub handles to
+/// `url`
+fn gitlab_hook(url: &str, token: &str) -> serde_json::Value {
+    json!({
+        "url": url,
+        "token": token,
+        "push_events": false,
+        "pipeline_events": true,
+        "job_events": true,
+        "deployment_events": true,
+        "note_events": config::feature_enabled(&config::Feature::CommentMirroring),
+        "enable_ssl_verification": true,
+    })
+}
+
+fn github_token(instance: &config::GithubInstance, credentials: &Credentials) -> String {
+    credentials
+        .github_token
+        .clone()
+        .unwrap_or_else(|| instance.site.api_token())
+}
+
+/// The ID of the GitHub webhook at `hooks_url` sending events to `url`, if
+/// there's one
+async fn find_github_hook(
+    client: &reqwest::Client,
+    hooks_url: &str,
+    token: &str,
+    url: &str,
+) -> Result<Option<i64>, GitError> {
+    Ok(github_client::get_hooks(client, hooks_url, token)
+        .await?
+        .into_iter()
+        .find(|hook| {
+            hook.config
+                .as_ref()
+                .and_then(|config| config.url.as_deref())
+                == Some(url)
+        })
+        .and_then(|hook| hook.id))
+}
+
+/// Creates or updates the GitHub webhook at `hooks_url`, of a repo or an
+/// org of `instance`, sending events to `url`
+async fn save_github_hook(
+    client: &reqwest::Client,
+    instance: &config::GithubInstance,
+    hooks_url: &str,
+    org: &str,
+    url: &str,
+    credentials: &Credentials,
+) -> Result<HookResult, GitError> {
+    let token = github_token(instance, credentials);
+    let mut events = github::required_events();
+    if events.is_empty() {
+        events.push("pull_request");
+    }
+    let hook = github_hook(url, &instance.webhook_secret(Some(org)), &events);
+
+    let existing = find_github_hook(client, hooks_url, &token, url).await?;
+    let saved = github_client::put_hook(client, hooks_url, &token, existing, &hook).await?;
+    Ok(HookResult::saved(existing, saved.id))
+}
+
+async fn setup_github_hook(
+    client: &reqwest::Client,
+    github_repo: &str,
+    url: &str,
+    credentials: &Credentials,
+) -> Result<HookResult, GitError> {
+    let (org, repo) = github_repo.split_once('/').ok_or(GitError::Config(format!(
+        "Invalid repo name {}",
+        github_repo
+    )))?;
+    let instance = config::github_for_repo(github_repo);
+    let hooks_url = github_client::repo_hooks_url(org, repo);
+    save_github_hook(client, instance, &hooks_url, org, url, credentials).await
+}
+
+/// Deletes the webhook of a repo sending events to `url`, if it has one, as
+/// the org webhook of its org sends them too
+async fn remove_github_hook(
+    client: &reqwest::Client,
+    github_repo: &str,
+    url: &str,
+    credentials: &Credentials,
+) -> Result<Option<HookResult>, GitError> {
+    let (org, repo) = github_repo.split_once('/').ok_or(GitError::Config(format!(
+        "Invalid repo name {}",
+        github_repo
+    )))?;
+    let token = github_token(config::github_for_repo(github_repo), credentials);
+    let hooks_url = github_client::repo_hooks_url(org, repo);
+    match find_github_hook(client, &hooks_url, &token, url).await? {
+        Some(id) => {
+            github_client::delete_hook(client, &hooks_url, &token, id).await?;
+            Ok(Some(HookResult::Deleted { id: Some(id) }))
+        }
+        None => Ok(None),
+    }
+}
+
+/// Sets up the org webhook of `org` on `instance`, sending the events of
+/// all its repos
+async fn setup_org_hook(
+    client: &reqwest::Client,
+    instance: &config::GithubInstance,
+    org: &str,
+    url: &str,
+    credentials: &Credentials,
+) -> Result<HookResult, GitError> {
+    let hooks_url = github_client::org_hooks_url(instance, org);
+    save_github_hook(client, instance, &hooks_url, org, url, credentials).await
+}
+
+async fn setup_gitlab_hook(
+    client: &reqwest::Client,
+    project: &str,
+    url: &str,
+    credentials: &Credentials,
+) -> Result<HookResult, GitError> {
+    let instance = config::gitlab_for_project(project);
+    let token = credentials
+        .gitlab_token
+        .clone()
+        .unwrap_or_else(|| instance.site.api_token());
+    let hook = gitlab_hook(url, &instance.site.webhook_secret());
+
+    let existing = gitlab_client::get_hooks(client, project, &token)
+        .await?
+        .into_iter()
+        .find(|hook| hook.url.as_deref() == Some(url))
+        .and_then(|hook| hook.id);
+    let saved = gitlab_client::put_hook(client, project, &token, existing, &hook).await?;
+    Ok(HookResult::saved(existing, saved.id))
+}
+
+/// Sets up the webhooks of a mapped repo and its GitLab projects, with
+/// LabHub at `public_url`. Repos of orgs with an org webhook don't get
+/// their own, and the one they had is deleted.
+async fn setup_mapping(
+    client: &reqwest::Client,
+    mapping: &config::Mapping,
+    public_url: &str,
+    credentials: &Credentials,
+) -> Vec<HookSetup> {
+    let mut setups = vec![];
+    let org_hook = mapping
+        .github_repo
+        .split_once('/')
+        .is_some_and(|(org, _)| config::github_for_repo(&mapping.github_repo).has_org_webhook(org));
+    let url = format!("{}/github/events", public_url);
+    let result = if org_hook {
+        remove_github_hook(client, &mapping.github_repo, &url, credentials)
+            .await
+            .transpose()
+    } else {
+        Some(setup_github_hook(client, &mapping.github_repo, &url, credentials).await)
+    };
+    if let Some(result) = result {
+        setups.push(HookSetup {
+            forge: "github",
+            repo: mapping.github_repo.clone(),
+            url,
+            result: result.into(),
+        });
+    }
+
+    let url = format!("{}/gitlab/events", public_url);
+    let projects = std::iter::once(&mapping.gitlab_repo).chain(&mapping.untrusted_gitlab_repo);
+    for project in projects {
+        let result = setup_gitlab_hook(client, project, &url, credentials).await;
+        setups.push(HookSetup {
+            forge: "gitlab",
+            repo: project.clone(),
+            url: url.clone(),
+            result: result.into(),
+        });
+    }
+    setups
+}
+
+/// Creates or updates the webhooks of every mapped repo, see the module
+/// docs. Failures are reported per webhook, without stopping the others.
+pub async fn setup_all(credentials: &Credentials) -> Result<Vec<HookSetup>, GitError> {
+    let public_url = config::CONFIG
+        .server
+        .public_url
+        .as_deref()
+        .ok_or(GitError::Config(
+            "Set public_url in [server] to set up webhooks".to_string(),
+        ))?
+        .trim_end_matches('/');
+    let client = api::new_client()?;
+    let mut setups = vec![];
+    let url = format!("{}/github/events", public_url);
+    for instance in config::CONFIG.github.iter() {
+        for org in instance.org_webhooks.iter() {
+            let result = setup_org_hook(&client, instance, org, &url, credentials).await;
+            setups.push(HookSetup {
+                forge: "github",
+                repo: org.clone(),
+                url: url.clone(),
+                result: result.into(),
+            });
+        }
+    }
+    for mapping in config::CONFIG.mappings.iter().filter(|m| !m.is_wildcard()) {
+        setups.extend(setup_mapping(&client, mapping, public_url, credentials).await);
+    }
+    for setup in setups.iter() {
+        match &setup.result {
+            HookResult::Failed { error } => warn!(
+                "Unable to set up the {} webhook of {}: {}",
+                setup.forge, setup.repo, error
+            ),
+            result => info!(
+                "Set up the {} webhook of {}: {:?}",
+                setup.forge, setup.repo, result
+            ),
+        }
+    }
+    Ok(setups)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use wiremock::matchers::{body_partial_json, method, path, query_param};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    #[tokio::test]
+    async fn test_setup_mapping() {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/repos/brndnmtthws/labhub/hooks"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!([
+                { "id": 1, "config": { "url": "https://elsewhere/hook" } },
+                { "id": 2, "config": { "url": "https://labhub.example.com/github/events" } },
+            ])))
+            .mount(&server)
+            .await;
+        Mock::given(method("PATCH"))
+            .and(path("/repos/brndnmtthws/labhub/hooks/2"))
+            .and(body_partial_json(json!({
+                "config": { "secret": "secret", "content_type": "json" },
+            })))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({ "id": 2 })))
+            .expect(1)
+            .mount(&server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path("/api/v4/projects/brndnmtthws-oss%2Flabhub/hooks"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!([])))
+            .mount(&server)
+            .await;
+        Mock::given(method("POST"))
+            .and(path("/api/v4/projects/brndnmtthws-oss%2Flabhub/hooks"))
+            .and(body_partial_json(json!({
+                "url": "https://labhub.example.com/gitlab/events",
+                "token": "secret",
+                "pipeline_events": true,
+            })))
+            .respond_with(ResponseTemplate::new(201).set_body_json(json!({ "id": 9 })))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let mapping = config::CONFIG
+            .mappings
+            .iter()
+            .find(|mapping| mapping.github_repo == "brndnmtthws/labhub")
+            .unwrap();
+        let client = api::new_client().unwrap();
+        let setups = api::with_base_url(
+            server.uri(),
+            setup_mapping(
+                &client,
+                mapping,
+                "https://labhub.example.com",
+                &Credentials::default(),
+            ),
+        )
+        .await;
+        assert_eq!(
+            setups,
+            vec![
+                HookSetup {
+                    forge: "github",
+                    repo: "brndnmtthws/labhub".to_string(),
+                    url: "https://labhub.example.com/github/events".to_string(),
+                    result: HookResult::Updated { id: Some(2) },
+                },
+                HookSetup {
+                    forge: "gitlab",
+                    repo: "brndnmtthws-oss/labhub".to_string(),
+                    url: "https://labhub.example.com/gitlab/events".to_string(),
+                    result: HookResult::Created { id: Some(9) },
+                },
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_setup_org_hook() {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/orgs/brndnmtthws/hooks"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!([])))
+            .mount(&server)
+            .await;
+        Mock::given(method("POST"))
+            .and(path("/orgs/brndnmtthws/hooks"))
+            .and(body_partial_json(json!({
+                "config": { "url": "https://labhub.example.com/github/events" },
+            })))
+            .respond_with(ResponseTemplate::new(201).set_body_json(json!({ "id": 3 })))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let client = api::new_client().unwrap();
+        let result = api::with_base_url(
+            server.uri(),
+            setup_org_hook(
+                &client,
+                &config::CONFIG.github[0],
+                "brndnmtthws",
+                "https://labhub.example.com/github/events",
+                &Credentials::default(),
+            ),
+        )
+        .await
+        .unwrap();
+        assert_eq!(result, HookResult::Created { id: Some(3) });
+    }
+
+    #[tokio::test]
+    async fn test_remove_github_hook() {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/repos/brndnmtthws/labhub/hooks"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!([
+                { "id": 1, "config": { "url": "https://elsewhere/hook" } },
+                { "id": 2, "config": { "url": "https://labhub.example.com/github/events" } },
+            ])))
+            .mount(&server)
+            .await;
+        Mock::given(method("DELETE"))
+            .and(path("/repos/brndnmtthws/labhub/hooks/2"))
+            .respond_with(ResponseTemplate::new(204))
+            .expect(1)
+            .mount(&server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path("/repos/brndnmtthws/conky/hooks"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!([])))
+            .mount(&server)
+            .await;
+
+        let client = api::new_client().unwrap();
+        let url = "https://labhub.example.com/github/events";
+        let removed = api::with_base_url(
+            server.uri(),
+            remove_github_hook(&client, "brndnmtthws/labhub", url, &Credentials::default()),
+        )
+        .await
+        .unwrap();
+        assert_eq!(removed, Some(HookResult::Deleted { id: Some(2) }));
+        let removed = api::with_base_url(
+            server.uri(),
+            remove_github_hook(&client, "brndnmtthws/conky", url, &Credentials::default()),
+        )
+        .await
+        .unwrap();
+        assert_eq!(removed, None);
+    }
+
+    #[tokio::test]
+    async fn test_remove_github_hook_on_later_page() {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/repos/brndnmtthws/labhub/hooks"))
+            .and(query_param("page", "1"))
+            .respond_with(
+                ResponseTemplate::new(200)
+                    .insert_header(
+                        "link",
+                        "<https://api.github.com/repositories/1/hooks?page=2>; rel=\"next\"",
+                    )
+                    .set_body_json(json!([
+                        { "id": 1, "config": { "url": "https://elsewhere/hook" } },
+                    ])),
+            )
+            .mount(&server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path("/repos/brndnmtthws/labhub/hooks"))
+            .and(query_param("page", "2"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!([
+                { "id": 2, "config": { "url": "https://labhub.example.com/github/events" } },
+            ])))
+            .mount(&server)
+            .await;
+        Mock::given(method("DELETE"))
+            .and(path("/repos/brndnmtthws/labhub/hooks/2"))
+            .respond_with(ResponseTemplate::new(204))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let client = api::new_client().unwrap();
+        let url = "https://labhub.example.com/github/events";
+        let removed = api::with_base_url(
+            server.uri(),
+            remove_github_hook(&client, "brndnmtthws/labhub", url, &Credentials::default()),
+        )
+        .await
+        .unwrap();
+        assert_eq!(removed, Some(HookResult::Deleted { id: Some(2) }));
+    }
+}